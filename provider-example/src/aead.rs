@@ -86,11 +86,21 @@ impl MessageEncrypter for Tls13Cipher {
         m: OutboundPlainMessage,
         seq: u64,
     ) -> Result<OutboundOpaqueMessage, rustls::Error> {
-        let total_len = self.encrypted_payload_len(m.payload.len());
+        self.encrypt_padded(m, seq, 0)
+    }
+
+    fn encrypt_padded(
+        &mut self,
+        m: OutboundPlainMessage,
+        seq: u64,
+        padding: usize,
+    ) -> Result<OutboundOpaqueMessage, rustls::Error> {
+        let total_len = self.encrypted_payload_len(m.payload.len() + padding);
         let mut payload = PrefixedPayload::with_capacity(total_len);
 
         payload.extend_from_chunks(&m.payload);
         payload.extend_from_slice(&m.typ.to_array());
+        payload.extend_with_zeros(padding);
         let nonce = chacha20poly1305::Nonce::from(Nonce::new(&self.1, seq).0);
         let aad = make_tls13_aad(total_len);
 
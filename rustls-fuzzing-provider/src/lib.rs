@@ -96,6 +96,7 @@ static TLS13_FUZZING_SUITE: SupportedCipherSuite = SupportedCipherSuite::Tls13(&
     hkdf_provider: &tls13::HkdfUsingHmac(&Hmac),
     aead_alg: &Aead,
     quic: None,
+    dtls: None,
 });
 
 static TLS_FUZZING_SUITE: SupportedCipherSuite = SupportedCipherSuite::Tls12(&Tls12CipherSuite {
@@ -10,6 +10,7 @@ use crate::client::{handy, ClientConfig, EchMode, ResolvesClientCert};
 use crate::error::Error;
 use crate::key_log::NoKeyLog;
 use crate::msgs::handshake::CertificateChain;
+use crate::record_padding::RecordPadding;
 use crate::versions::TLS13;
 use crate::webpki::{self, WebPkiServerVerifier};
 use crate::{compress, verify, versions, WantsVersions};
@@ -172,19 +173,27 @@ impl ConfigBuilder<ClientConfig, WantsClientCert> {
             alpn_protocols: Vec::new(),
             resumption: Resumption::default(),
             max_fragment_size: None,
+            record_size_limit: None,
+            record_padding: RecordPadding::None,
+            client_hello_padding: None,
+            custom_extensions: Vec::new(),
             client_auth_cert_resolver,
             versions: self.state.versions,
             enable_sni: true,
+            enable_grease: false,
             verifier: self.state.verifier,
             key_log: Arc::new(NoKeyLog {}),
+            observer: None,
             enable_secret_extraction: false,
             enable_early_data: false,
+            enable_post_handshake_auth: false,
             #[cfg(feature = "tls12")]
             require_ems: cfg!(feature = "fips"),
             time_provider: self.time_provider,
             cert_compressors: compress::default_cert_compressors().to_vec(),
             cert_compression_cache: Arc::new(compress::CompressionCache::default()),
             cert_decompressors: compress::default_cert_decompressors().to_vec(),
+            psk_resolver: None,
             ech_mode: self.state.client_ech_mode,
         }
     }
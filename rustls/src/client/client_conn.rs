@@ -4,7 +4,7 @@ use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
 use core::{fmt, mem};
 
-use pki_types::{ServerName, UnixTime};
+use pki_types::{EchConfigListBytes, ServerName, UnixTime};
 
 use super::handy::NoClientSessionStorage;
 use super::hs;
@@ -13,12 +13,17 @@ use crate::client::{EchMode, EchStatus};
 use crate::common_state::{CommonState, Protocol, Side};
 use crate::conn::{ConnectionCore, UnbufferedConnectionCommon};
 use crate::crypto::{CryptoProvider, SupportedKxGroup};
+use crate::custom_extension::CustomExtension;
 use crate::enums::{CipherSuite, ProtocolVersion, SignatureScheme};
 use crate::error::Error;
 use crate::log::trace;
 use crate::msgs::enums::NamedGroup;
+use crate::msgs::fragmenter::check_record_size_limit;
 use crate::msgs::handshake::ClientExtension;
 use crate::msgs::persist;
+use crate::observer::HandshakeObserver;
+use crate::psk::ExternalPsk;
+use crate::record_padding::RecordPadding;
 use crate::suites::SupportedCipherSuite;
 #[cfg(feature = "std")]
 use crate::time_provider::DefaultTimeProvider;
@@ -131,6 +136,19 @@ pub trait ResolvesClientCert: fmt::Debug + Send + Sync {
     fn has_certs(&self) -> bool;
 }
 
+/// A trait for the ability to choose the external pre-shared keys a client
+/// offers to a server.
+///
+/// See [`ExternalPsk`] for details.
+pub trait ResolvesClientPsk: fmt::Debug + Send + Sync {
+    /// Return the external PSKs to offer when connecting to `server_name`,
+    /// most preferred first.
+    ///
+    /// Return an empty list to offer none.  PSKs whose hash function is not
+    /// used by any enabled TLS1.3 cipher suite are not offered.
+    fn resolve(&self, server_name: &ServerName<'_>) -> Vec<Arc<ExternalPsk>>;
+}
+
 /// Common configuration for (typically) all connections made by a program.
 ///
 /// Making one of these is cheap, though one of the inputs may be expensive: gathering trust roots
@@ -150,13 +168,19 @@ pub trait ResolvesClientCert: fmt::Debug + Send + Sync {
 /// # Defaults
 ///
 /// * [`ClientConfig::max_fragment_size`]: the default is `None` (meaning 16kB).
+/// * [`ClientConfig::record_size_limit`]: the default is `None` -- no limit is advertised.
+/// * [`ClientConfig::record_padding`]: the default is [`RecordPadding::None`] -- records are not padded.
+/// * [`ClientConfig::client_hello_padding`]: the default is `None` -- the ClientHello is not padded.
+/// * [`ClientConfig::custom_extensions`]: the default is empty -- no custom extensions are sent.
 /// * [`ClientConfig::resumption`]: supports resumption with up to 256 server names, using session
 ///    ids or tickets, with a max of eight tickets per server.
 /// * [`ClientConfig::alpn_protocols`]: the default is empty -- no ALPN protocol is negotiated.
 /// * [`ClientConfig::key_log`]: key material is not logged.
+/// * [`ClientConfig::observer`]: the default is `None` -- handshakes are not observed.
 /// * [`ClientConfig::cert_decompressors`]: depends on the crate features, see [`compress::default_cert_decompressors()`].
 /// * [`ClientConfig::cert_compressors`]: depends on the crate features, see [`compress::default_cert_compressors()`].
 /// * [`ClientConfig::cert_compression_cache`]: caches the most recently used 4 compressions
+/// * [`ClientConfig::psk_resolver`]: the default is `None` -- no external PSKs are offered.
 ///
 /// [`RootCertStore`]: crate::RootCertStore
 #[derive(Clone, Debug)]
@@ -181,6 +205,53 @@ pub struct ClientConfig {
     /// [ClientConnection::new]: crate::client::ClientConnection::new
     pub max_fragment_size: Option<usize>,
 
+    /// The largest record the server may send us, advertised using the
+    /// [`record_size_limit` extension][RFC 8449].
+    ///
+    /// This is the largest record plaintext we are prepared to receive.  In TLS1.3 it
+    /// includes the content type byte, so the largest payload fragment the server can send
+    /// is one byte less.  The limit only takes effect if the server supports the extension,
+    /// and only applies to encrypted records.  Records exceeding the limit are rejected
+    /// with a `record_overflow` alert.
+    ///
+    /// A value of None means the extension is not sent.  Values must be between 64 and
+    /// 16385 inclusive: out of range values are reported as errors from [ClientConnection::new].
+    ///
+    /// The extension is not used with QUIC.
+    ///
+    /// [RFC 8449]: https://www.rfc-editor.org/rfc/rfc8449
+    /// [ClientConnection::new]: crate::client::ClientConnection::new
+    pub record_size_limit: Option<u16>,
+
+    /// How to pad the TLS1.3 records we send.
+    ///
+    /// Padding hides the exact length of handshake messages and application data
+    /// from observers of the connection, at the cost of extra bandwidth.  See
+    /// [`RecordPadding`] for the available policies.
+    pub record_padding: RecordPadding,
+
+    /// The smallest ClientHello to send, padding it using the
+    /// [`padding` extension][RFC 7685] if needed.
+    ///
+    /// This is the length of the ClientHello handshake message, including its
+    /// four-byte header but not the record header.  Some middleboxes fail on
+    /// ClientHellos between 256 and 511 bytes long, which a value of 512 avoids.
+    ///
+    /// When offering Encrypted Client Hello, this applies to the outer
+    /// ClientHello.  A value of `None` means the ClientHello is not padded.
+    ///
+    /// [RFC 7685]: https://www.rfc-editor.org/rfc/rfc7685
+    pub client_hello_padding: Option<u16>,
+
+    /// Application-defined extensions to send in the ClientHello.
+    ///
+    /// The server's replies to these are available from
+    /// [`ClientConnection::received_custom_extensions()`].  The server may only reply
+    /// with extension types listed here.
+    ///
+    /// [`ClientConnection::received_custom_extensions()`]: crate::client::ClientConnection::received_custom_extensions
+    pub custom_extensions: Vec<CustomExtension>,
+
     /// How to decide what client auth certificate/keys to use.
     pub client_auth_cert_resolver: Arc<dyn ResolvesClientCert>,
 
@@ -190,10 +261,28 @@ pub struct ClientConfig {
     /// The default is true.
     pub enable_sni: bool,
 
+    /// Whether to send [GREASE] values in the ClientHello.
+    ///
+    /// These are reserved cipher suites, extensions, named groups (with a key
+    /// share), signature schemes, protocol versions and ALPN protocols, which
+    /// servers must ignore.  Sending them keeps servers and middleboxes tolerant
+    /// of values they do not understand yet.
+    ///
+    /// The default is false.
+    ///
+    /// [GREASE]: https://www.rfc-editor.org/rfc/rfc8701
+    pub enable_grease: bool,
+
     /// How to output key material for debugging.  The default
     /// does nothing.
     pub key_log: Arc<dyn KeyLog>,
 
+    /// How to observe the handshakes of connections made with this config,
+    /// for example to collect metrics.  See [`HandshakeObserver`].
+    ///
+    /// The default is `None`.
+    pub observer: Option<Arc<dyn HandshakeObserver>>,
+
     /// Allows traffic secrets to be extracted after the handshake,
     /// e.g. for kTLS setup.
     pub enable_secret_extraction: bool,
@@ -204,6 +293,16 @@ pub struct ClientConfig {
     /// The default is false.
     pub enable_early_data: bool,
 
+    /// Whether to offer post-handshake client authentication in TLS1.3
+    /// handshakes.
+    ///
+    /// If the server then requests a certificate after the handshake,
+    /// one is chosen by [`ClientConfig::client_auth_cert_resolver`].
+    /// This is never offered for QUIC connections.
+    ///
+    /// The default is false.
+    pub enable_post_handshake_auth: bool,
+
     /// If set to `true`, requires the server to support the extended
     /// master secret extraction method defined in [RFC 7627].
     ///
@@ -262,6 +361,14 @@ pub struct ClientConfig {
     /// a cache that does no caching.
     pub cert_compression_cache: Arc<compress::CompressionCache>,
 
+    /// How to choose external pre-shared keys to offer in TLS1.3 handshakes.
+    ///
+    /// If the server accepts one of these, neither peer sends a certificate.
+    /// External PSKs are not offered when Encrypted Client Hello is enabled.
+    ///
+    /// The default is `None`.
+    pub psk_resolver: Option<Arc<dyn ResolvesClientPsk>>,
+
     /// How to offer Encrypted Client Hello (ECH). The default is to not offer ECH.
     pub(super) ech_mode: Option<EchMode>,
 }
@@ -400,6 +507,12 @@ impl ClientConfig {
             .any(|cs| cs.usable_for_protocol(proto))
     }
 
+    /// Whether encrypted client hello (or GREASE for it) is configured.
+    #[cfg(feature = "std")]
+    pub(crate) fn ech_configured(&self) -> bool {
+        self.ech_mode.is_some()
+    }
+
     pub(super) fn find_cipher_suite(&self, suite: CipherSuite) -> Option<SupportedCipherSuite> {
         self.provider
             .cipher_suites
@@ -617,12 +730,14 @@ mod connection {
     use core::ops::{Deref, DerefMut};
     use std::io;
 
-    use pki_types::ServerName;
+    use pki_types::{EchConfigListBytes, ServerName};
 
     use super::ClientConnectionData;
     use crate::client::EchStatus;
     use crate::common_state::Protocol;
+    use crate::conn::kernel::KernelConnection;
     use crate::conn::{ConnectionCommon, ConnectionCore};
+    use crate::custom_extension::CustomExtension;
     use crate::error::Error;
     use crate::suites::ExtractedSecrets;
     use crate::ClientConfig;
@@ -739,11 +854,73 @@ mod connection {
             self.inner.dangerous_extract_secrets()
         }
 
+        /// Extract secrets, and continue the connection as a [`KernelConnection`].
+        ///
+        /// See [`ConnectionCommon::dangerous_into_kernel_connection()`] for more information.
+        pub fn dangerous_into_kernel_connection(
+            self,
+        ) -> Result<(ExtractedSecrets, KernelConnection), Error> {
+            self.inner
+                .dangerous_into_kernel_connection()
+        }
+
         /// Return the connection's Encrypted Client Hello (ECH) status.
         pub fn ech_status(&self) -> EchStatus {
             self.inner.core.data.ech_status
         }
 
+        /// Return the ECH retry configurations sent by the server, if it rejected our ECH offer.
+        ///
+        /// These are only available once the server has authenticated itself using the
+        /// `public_name` of the rejected ECH config, which means the handshake will have failed
+        /// with [`PeerIncompatible::ServerRejectedEncryptedClientHello`].
+        ///
+        /// Returns `None` if ECH was not rejected, or the server did not provide retry
+        /// configurations. In the latter case the client may retry with ECH disabled.
+        ///
+        /// [`PeerIncompatible::ServerRejectedEncryptedClientHello`]: crate::PeerIncompatible::ServerRejectedEncryptedClientHello
+        pub fn ech_retry_configs(&self) -> Option<EchConfigListBytes<'static>> {
+            self.inner
+                .core
+                .data
+                .ech_retry_configs
+                .clone()
+        }
+
+        /// Return a `ClientConfig` to retry this connection with, if the server rejected our
+        /// ECH offer and provided usable [retry configurations][Self::ech_retry_configs].
+        ///
+        /// This is a copy of the connection's `ClientConfig`, using ECH with one of the retry
+        /// configurations, as described in [draft-ietf-tls-esni §6.1.6].  To retry, make a new
+        /// connection to the same server with it.
+        ///
+        /// Only retry configurations with the same `public_name` as the rejected ECH config
+        /// are used.  A connection made with a returned `ClientConfig` never returns one
+        /// itself, so a connection is retried at most once.
+        ///
+        /// [draft-ietf-tls-esni §6.1.6]: https://datatracker.ietf.org/doc/html/draft-ietf-tls-esni-18#section-6.1.6
+        pub fn ech_retry_client_config(&self) -> Option<Arc<ClientConfig>> {
+            self.inner
+                .core
+                .data
+                .ech_retry_client_config
+                .clone()
+        }
+
+        /// Return the custom extensions the server sent in reply to those in
+        /// [`ClientConfig::custom_extensions`], in the order they were received.
+        ///
+        /// These are taken from the ServerHello in TLS1.2, and from the EncryptedExtensions
+        /// and the end-entity certificate's entry in TLS1.3.  They are available once the
+        /// corresponding handshake message has been received.
+        pub fn received_custom_extensions(&self) -> &[CustomExtension] {
+            &self
+                .inner
+                .core
+                .data
+                .received_custom_extensions
+        }
+
         /// Return true if the connection was made with a `ClientConfig` that is FIPS compatible.
         ///
         /// This is different from [`crate::crypto::CryptoProvider::fips()`]:
@@ -811,9 +988,15 @@ impl ConnectionCore<ClientConnectionData> {
     ) -> Result<Self, Error> {
         let mut common_state = CommonState::new(Side::Client);
         common_state.set_max_fragment_size(config.max_fragment_size)?;
+        check_record_size_limit(config.record_size_limit)?;
+        common_state.record_padding = config.record_padding.clone();
         common_state.protocol = proto;
         common_state.enable_secret_extraction = config.enable_secret_extraction;
         common_state.fips = config.fips();
+        common_state.observer = config
+            .observer
+            .as_ref()
+            .map(|observer| observer.new_connection(Side::Client));
         let mut data = ClientConnectionData::new();
 
         let mut cx = hs::ClientContext {
@@ -848,6 +1031,47 @@ impl UnbufferedClientConnection {
             inner: ConnectionCore::for_client(config, name, Vec::new(), Protocol::Tcp)?.into(),
         })
     }
+
+    /// Return the custom extensions the server sent in reply to those in
+    /// [`ClientConfig::custom_extensions`].
+    ///
+    /// See [`ClientConnection::received_custom_extensions()`].
+    ///
+    /// [`ClientConnection::received_custom_extensions()`]: crate::client::ClientConnection::received_custom_extensions
+    pub fn received_custom_extensions(&self) -> &[CustomExtension] {
+        &self
+            .inner
+            .core
+            .data
+            .received_custom_extensions
+    }
+
+    /// Return the ECH retry configurations sent by the server, if it rejected our ECH offer.
+    ///
+    /// See [`ClientConnection::ech_retry_configs()`].
+    ///
+    /// [`ClientConnection::ech_retry_configs()`]: crate::client::ClientConnection::ech_retry_configs
+    pub fn ech_retry_configs(&self) -> Option<EchConfigListBytes<'static>> {
+        self.inner
+            .core
+            .data
+            .ech_retry_configs
+            .clone()
+    }
+
+    /// Return a `ClientConfig` to retry this connection with, if the server rejected our
+    /// ECH offer.
+    ///
+    /// See [`ClientConnection::ech_retry_client_config()`].
+    ///
+    /// [`ClientConnection::ech_retry_client_config()`]: crate::client::ClientConnection::ech_retry_client_config
+    pub fn ech_retry_client_config(&self) -> Option<Arc<ClientConfig>> {
+        self.inner
+            .core
+            .data
+            .ech_retry_client_config
+            .clone()
+    }
 }
 
 impl Deref for UnbufferedClientConnection {
@@ -950,6 +1174,9 @@ pub struct ClientConnectionData {
     pub(super) early_data: EarlyData,
     pub(super) resumption_ciphersuite: Option<SupportedCipherSuite>,
     pub(super) ech_status: EchStatus,
+    pub(super) ech_retry_configs: Option<EchConfigListBytes<'static>>,
+    pub(super) ech_retry_client_config: Option<Arc<ClientConfig>>,
+    pub(super) received_custom_extensions: Vec<CustomExtension>,
 }
 
 impl ClientConnectionData {
@@ -958,6 +1185,9 @@ impl ClientConnectionData {
             early_data: EarlyData::new(),
             resumption_ciphersuite: None,
             ech_status: EchStatus::NotOffered,
+            ech_retry_configs: None,
+            ech_retry_client_config: None,
+            received_custom_extensions: Vec::new(),
         }
     }
 }
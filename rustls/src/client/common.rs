@@ -3,6 +3,7 @@ use alloc::sync::Arc;
 use alloc::vec::Vec;
 
 use super::ResolvesClientCert;
+use crate::delegated_credential::DelegatedCredential;
 use crate::log::{debug, trace};
 use crate::msgs::enums::ExtensionType;
 use crate::msgs::handshake::{CertificateChain, DistinguishedName, ServerExtension};
@@ -12,24 +13,44 @@ use crate::{compress, sign, SignatureScheme};
 pub(super) struct ServerCertDetails<'a> {
     pub(super) cert_chain: CertificateChain<'a>,
     pub(super) ocsp_response: Vec<u8>,
+    pub(super) sct_list: Vec<u8>,
+    pub(super) delegated_credential: Option<DelegatedCredential>,
 }
 
 impl<'a> ServerCertDetails<'a> {
-    pub(super) fn new(cert_chain: CertificateChain<'a>, ocsp_response: Vec<u8>) -> Self {
+    pub(super) fn new(
+        cert_chain: CertificateChain<'a>,
+        ocsp_response: Vec<u8>,
+        sct_list: Vec<u8>,
+    ) -> Self {
         Self {
             cert_chain,
             ocsp_response,
+            sct_list,
+            delegated_credential: None,
         }
     }
 
+    pub(super) fn with_delegated_credential(
+        mut self,
+        delegated_credential: Option<DelegatedCredential>,
+    ) -> Self {
+        self.delegated_credential = delegated_credential;
+        self
+    }
+
     pub(super) fn into_owned(self) -> ServerCertDetails<'static> {
         let Self {
             cert_chain,
             ocsp_response,
+            sct_list,
+            delegated_credential,
         } = self;
         ServerCertDetails {
             cert_chain: cert_chain.into_owned(),
             ocsp_response,
+            sct_list,
+            delegated_credential,
         }
     }
 }
@@ -1,11 +1,13 @@
 use alloc::boxed::Box;
+use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 
 use pki_types::{DnsName, EchConfigListBytes, ServerName};
 use subtle::ConstantTimeEq;
 
-use crate::client::tls13;
+use crate::client::{tls13, ClientConfig};
+use crate::common_state::Protocol;
 use crate::crypto::hash::Hash;
 use crate::crypto::hpke::{EncapsulatedSecret, Hpke, HpkePublicKey, HpkeSealer, HpkeSuite};
 use crate::crypto::SecureRandom;
@@ -79,6 +81,13 @@ pub struct EchConfig {
     /// An HPKE instance corresponding to a suite from the `config` we have selected as
     /// a compatible choice.
     pub(crate) suite: &'static dyn Hpke,
+
+    /// The HPKE suites `config` was selected with, to select from a server's retry configs.
+    hpke_suites: Vec<&'static dyn Hpke>,
+
+    /// Whether `config` was selected from a server's retry configs, in which case
+    /// it is not retried again if rejected.
+    is_retry: bool,
 }
 
 impl EchConfig {
@@ -105,6 +114,45 @@ impl EchConfig {
                 Error::InvalidEncryptedClientHello(EncryptedClientHelloError::InvalidConfigList)
             })?;
 
+        Self::select(&ech_configs, hpke_suites, None)
+    }
+
+    /// Construct the EchConfig to retry with once `self` has been rejected by a server
+    /// which provided `retry_configs`, as described in [draft-ietf-tls-esni §6.1.6].
+    ///
+    /// Only configs with the same `public_name` as `self` are used, because that is the
+    /// name the server authenticated itself for when providing them.  Returns `None` if
+    /// there are none compatible with our HPKE suites, or if `self` was itself selected
+    /// from retry configs: we only retry once.
+    ///
+    /// [draft-ietf-tls-esni §6.1.6]: https://datatracker.ietf.org/doc/html/draft-ietf-tls-esni-18#section-6.1.6
+    pub(crate) fn for_retry(&self, retry_configs: &[EchConfigPayload]) -> Option<Self> {
+        if self.is_retry {
+            debug!("not retrying ECH again after a rejected retry");
+            return None;
+        }
+
+        let EchConfigPayload::V18(contents) = &self.config else {
+            return None;
+        };
+
+        let mut config = Self::select(
+            retry_configs,
+            &self.hpke_suites,
+            Some(&contents.public_name),
+        )
+        .ok()?;
+        config.is_retry = true;
+        Some(config)
+    }
+
+    /// Select a config from `ech_configs` that is compatible with one of `hpke_suites`,
+    /// and has `public_name` if that is given.
+    fn select(
+        ech_configs: &[EchConfigPayload],
+        hpke_suites: &[&'static dyn Hpke],
+        public_name: Option<&DnsName<'_>>,
+    ) -> Result<Self, Error> {
         // Note: we name the index var _i because if the log feature is disabled
         //       it is unused.
         #[cfg_attr(not(feature = "std"), allow(clippy::unused_enumerate_index))]
@@ -128,6 +176,14 @@ impl EchConfig {
                 continue; // Unsupported, or malformed extensions.
             }
 
+            if public_name.is_some_and(|name| name != &contents.public_name) {
+                warn!(
+                    "ECH retry config has a different public_name: {:?}",
+                    contents.public_name
+                );
+                continue; // Not authenticated by the server that sent it.
+            }
+
             let key_config = &contents.key_config;
             for cipher_suite in &key_config.symmetric_cipher_suites {
                 if cipher_suite.aead_id.tag_len().is_none() {
@@ -149,6 +205,8 @@ impl EchConfig {
                     return Ok(Self {
                         config: config.clone(),
                         suite: *hpke,
+                        hpke_suites: hpke_suites.to_vec(),
+                        is_retry: false,
                     });
                 }
             }
@@ -227,6 +285,8 @@ impl EchGreaseConfig {
                     extensions: Vec::default(),
                 }),
                 suite: self.suite,
+                hpke_suites: Vec::new(),
+                is_retry: false,
             },
             inner_name,
             false,
@@ -371,6 +431,7 @@ impl EchState {
         mut outer_hello: ClientHelloPayload,
         retry_req: Option<&HelloRetryRequest>,
         resuming: &Option<Retrieved<&persist::Tls13ClientSessionValue>>,
+        padding: Option<u16>,
     ) -> Result<ClientHelloPayload, Error> {
         trace!(
             "Preparing ECH offer {}",
@@ -422,6 +483,12 @@ impl EchState {
             .extensions
             .push(outer_hello_ext(self, enc.clone(), vec![0; payload_len]));
 
+        // The placeholder has the final length, so the outer hello can be padded now:
+        // padding is covered by the AAD.
+        if let Some(target) = padding {
+            outer_hello.pad_to(target);
+        }
+
         // Next we compute the proper extension payload.
         let payload = self
             .sender
@@ -559,6 +626,7 @@ impl EchState {
             // Some information is copied over as-is.
             client_version: outer_hello.client_version,
             session_id: outer_hello.session_id,
+            legacy_cookie: outer_hello.legacy_cookie.clone(),
             compression_methods: outer_hello.compression_methods.clone(),
 
             // We will build up the included extensions ourselves.
@@ -667,11 +735,13 @@ impl EchState {
                 payload: HandshakePayload::ClientHello(inner_hello),
             };
 
-            // Retain the early key schedule we get from processing the binder.
+            // Retain the early key schedule we get from processing the binder.  ECH is
+            // not offered in DTLS, so this uses the TLS1.3 key schedule labels.
             self.early_data_key_schedule = Some(tls13::fill_in_psk_binder(
                 resuming,
                 &self.inner_hello_transcript,
                 &mut chp,
+                Protocol::Tcp,
             ));
 
             // fill_in_psk_binder works on an owned HandshakeMessagePayload, so we need to
@@ -812,6 +882,34 @@ pub(crate) struct EchAccepted {
     pub(crate) sent_extensions: Vec<ExtensionType>,
 }
 
+/// Encode the server's retry configs as a config list.
+pub(crate) fn retry_config_list(
+    retry_configs: &Option<Vec<EchConfigPayload>>,
+) -> Option<EchConfigListBytes<'static>> {
+    retry_configs
+        .as_ref()
+        .map(|configs| EchConfigListBytes::from(configs.get_encoding()))
+}
+
+/// Make a copy of `config` to retry a connection with, after the server rejected its
+/// ECH offer and provided `retry_configs`.
+///
+/// See [`EchConfig::for_retry()`] for when this returns `None`.
+pub(crate) fn retry_client_config(
+    config: &ClientConfig,
+    retry_configs: &Option<Vec<EchConfigPayload>>,
+) -> Option<Arc<ClientConfig>> {
+    let Some(EchMode::Enable(rejected)) = &config.ech_mode else {
+        return None;
+    };
+    let ech_config = rejected.for_retry(retry_configs.as_deref()?)?;
+
+    Some(Arc::new(ClientConfig {
+        ech_mode: Some(EchMode::Enable(ech_config)),
+        ..config.clone()
+    }))
+}
+
 pub(crate) fn fatal_alert_required(
     retry_configs: Option<Vec<EchConfigPayload>>,
     common: &mut CommonState,
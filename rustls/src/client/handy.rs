@@ -195,6 +195,555 @@ mod cache {
 #[cfg(any(feature = "std", feature = "hashbrown"))]
 pub use cache::ClientSessionMemoryCache;
 
+#[cfg(feature = "std")]
+mod file_store {
+    use alloc::collections::VecDeque;
+    use alloc::vec::Vec;
+    use core::fmt;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use core::time::Duration;
+    use std::ffi::OsString;
+    use std::io::{self, Read, Write};
+    use std::path::{Path, PathBuf};
+    use std::time::SystemTime;
+    use std::{format, fs, process, thread};
+
+    use pki_types::{ServerName, UnixTime};
+    use zeroize::Zeroizing;
+
+    use crate::crypto::CryptoProvider;
+    use crate::lock::Mutex;
+    use crate::log::{debug, warn};
+    use crate::msgs::base::{Payload, PayloadU24, PayloadU8};
+    use crate::msgs::codec::{Codec, Reader};
+    use crate::msgs::persist;
+    use crate::NamedGroup;
+
+    const MAX_TLS13_TICKETS_PER_SERVER: usize = 8;
+
+    /// Files larger than this are not loaded.
+    const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+    /// The current version of the file format.
+    const FILE_FORMAT_V1: u8 = 1;
+
+    /// How long to wait for another process to finish changing the file.
+    const LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Lock files older than this are assumed to be left by a process that
+    /// exited while changing the file, and are removed.
+    const STALE_LOCK_AGE: Duration = Duration::from_secs(30);
+
+    /// An implementer of `ClientSessionStore` that keeps sessions in a file,
+    /// so that later processes can resume them.
+    ///
+    /// The file is read when the store is made, and rewritten whenever the
+    /// stored sessions change.  Rewriting is atomic: a temporary file is written
+    /// and then renamed over the original, so readers never see a partially
+    /// written file.  Expired sessions are discarded when reading and writing
+    /// the file.
+    ///
+    /// Several processes may share a file.  Each change is made while holding
+    /// a lock file next to it (`<file>.lock`), to the sessions freshly read
+    /// from the file, so changes made by other processes are kept.  In
+    /// particular, a TLS1.3 ticket is removed from the file before it is used,
+    /// so no other process uses it again.  If the file cannot be locked or
+    /// written, TLS1.3 tickets are not used.
+    ///
+    /// The file contains session secrets, so should be protected like a private
+    /// key.  On Unix-like systems it is created readable only by its owner.
+    ///
+    /// As with [`ClientSessionMemoryCache`], the number of stored sessions is
+    /// bounded; the servers least recently connected to are forgotten first.
+    ///
+    /// [`ClientSessionMemoryCache`]: super::ClientSessionMemoryCache
+    pub struct ClientSessionFileStore {
+        path: PathBuf,
+        provider: CryptoProvider,
+        max_servers: usize,
+        /// Least recently changed first.
+        servers: Mutex<VecDeque<ServerData>>,
+    }
+
+    impl ClientSessionFileStore {
+        /// Make a new `ClientSessionFileStore`, keeping sessions in the file at `path`.
+        ///
+        /// Sessions already stored in the file are loaded, if their cipher suite is
+        /// one of `provider`'s.  If the file does not exist, or cannot be read, the
+        /// store starts empty.  `size` is the maximum number of stored sessions.
+        pub fn new(path: impl Into<PathBuf>, provider: &CryptoProvider, size: usize) -> Self {
+            let path = path.into();
+            let max_servers = size.saturating_add(MAX_TLS13_TICKETS_PER_SERVER - 1)
+                / MAX_TLS13_TICKETS_PER_SERVER;
+
+            let store = Self {
+                path,
+                provider: provider.clone(),
+                max_servers,
+                servers: Mutex::new(VecDeque::new()),
+            };
+            if let Some(servers) = store.read() {
+                *store.servers.lock().unwrap() = servers;
+            }
+            store
+        }
+
+        /// Read the sessions from the file.
+        ///
+        /// Returns `None` if the file does not exist or cannot be read.
+        fn read(&self) -> Option<VecDeque<ServerData>> {
+            let mut servers = match read_file(&self.path) {
+                Ok(Some(bytes)) => decode(&bytes, &self.provider, UnixTime::now()),
+                Ok(None) => return None,
+                Err(_err) => {
+                    warn!("cannot read client sessions from {:?}: {_err}", self.path);
+                    return None;
+                }
+            };
+            while servers.len() > self.max_servers {
+                servers.pop_front();
+            }
+            Some(servers)
+        }
+
+        /// Change the data for `server_name` with `edit`, then save the file if
+        /// `edit` returns true.
+        ///
+        /// The sessions are first re-read from the file, while holding its lock.
+        /// Returns whether the change was saved.
+        fn edit(
+            &self,
+            server_name: &ServerName<'_>,
+            edit: impl FnOnce(&mut ServerData) -> bool,
+        ) -> bool {
+            let mut servers = self.servers.lock().unwrap();
+            let lock = FileLock::acquire(&self.path);
+            if lock.is_ok() {
+                if let Some(current) = self.read() {
+                    *servers = current;
+                }
+            }
+
+            let mut data = match servers
+                .iter()
+                .position(|data| &data.name == server_name)
+            {
+                Some(i) => servers.remove(i).unwrap(),
+                None => ServerData::new(server_name.to_owned()),
+            };
+
+            let changed = edit(&mut data);
+            servers.push_back(data);
+            if servers.len() > self.max_servers {
+                servers.pop_front();
+            }
+
+            if !changed {
+                return true;
+            }
+
+            let result = lock
+                .and_then(|_lock| write_atomically(&self.path, &encode(&servers, UnixTime::now())));
+            match result {
+                Ok(()) => true,
+                Err(_err) => {
+                    warn!("cannot save client sessions to {:?}: {_err}", self.path);
+                    false
+                }
+            }
+        }
+
+        fn get<T>(
+            &self,
+            server_name: &ServerName<'_>,
+            f: impl FnOnce(&ServerData) -> Option<T>,
+        ) -> Option<T> {
+            self.servers
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|data| &data.name == server_name)
+                .and_then(f)
+        }
+    }
+
+    impl super::client::ClientSessionStore for ClientSessionFileStore {
+        fn set_kx_hint(&self, server_name: ServerName<'static>, group: NamedGroup) {
+            self.edit(&server_name, |data| {
+                data.kx_hint.replace(group) != Some(group)
+            });
+        }
+
+        fn kx_hint(&self, server_name: &ServerName<'_>) -> Option<NamedGroup> {
+            self.get(server_name, |data| data.kx_hint)
+        }
+
+        fn set_tls12_session(
+            &self,
+            server_name: ServerName<'static>,
+            value: persist::Tls12ClientSessionValue,
+        ) {
+            self.edit(&server_name, |data| {
+                data.tls12 = Some(value);
+                true
+            });
+        }
+
+        fn tls12_session(
+            &self,
+            server_name: &ServerName<'_>,
+        ) -> Option<persist::Tls12ClientSessionValue> {
+            self.get(server_name, |data| data.tls12.clone())
+        }
+
+        fn remove_tls12_session(&self, server_name: &ServerName<'static>) {
+            self.edit(server_name, |data| data.tls12.take().is_some());
+        }
+
+        fn insert_tls13_ticket(
+            &self,
+            server_name: ServerName<'static>,
+            value: persist::Tls13ClientSessionValue,
+        ) {
+            self.edit(&server_name, |data| {
+                if data.tls13.len() == MAX_TLS13_TICKETS_PER_SERVER {
+                    data.tls13.pop_front();
+                }
+                data.tls13.push_back(value);
+                true
+            });
+        }
+
+        fn take_tls13_ticket(
+            &self,
+            server_name: &ServerName<'static>,
+        ) -> Option<persist::Tls13ClientSessionValue> {
+            // Only used once it has been removed from the file, so that no other
+            // process uses it again.
+            let mut taken = None;
+            let saved = self.edit(server_name, |data| {
+                taken = data.tls13.pop_back();
+                taken.is_some()
+            });
+            taken.filter(|_| saved)
+        }
+    }
+
+    impl fmt::Debug for ClientSessionFileStore {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            // Note: we omit self.servers as it may contain sensitive data.
+            f.debug_struct("ClientSessionFileStore")
+                .field("path", &self.path)
+                .finish_non_exhaustive()
+        }
+    }
+
+    struct ServerData {
+        name: ServerName<'static>,
+        kx_hint: Option<NamedGroup>,
+        tls12: Option<persist::Tls12ClientSessionValue>,
+        // Oldest first.
+        tls13: VecDeque<persist::Tls13ClientSessionValue>,
+    }
+
+    impl ServerData {
+        fn new(name: ServerName<'static>) -> Self {
+            Self {
+                name,
+                kx_hint: None,
+                tls12: None,
+                tls13: VecDeque::with_capacity(MAX_TLS13_TICKETS_PER_SERVER),
+            }
+        }
+    }
+
+    /// Encode `servers` for the file, omitting sessions expired at `now`.
+    ///
+    /// The file is a format version, followed by each server's name,
+    /// kx hint, TLS1.2 session and TLS1.3 tickets.
+    fn encode(servers: &VecDeque<ServerData>, now: UnixTime) -> Zeroizing<Vec<u8>> {
+        let mut bytes = Zeroizing::new(Vec::new());
+        FILE_FORMAT_V1.encode(&mut bytes);
+
+        for data in servers {
+            PayloadU8::new(data.name.to_str().as_bytes().to_vec()).encode(&mut bytes);
+
+            match data.kx_hint {
+                Some(group) => {
+                    1u8.encode(&mut bytes);
+                    group.encode(&mut bytes);
+                }
+                None => 0u8.encode(&mut bytes),
+            }
+
+            #[cfg(feature = "tls12")]
+            let tls12 = data
+                .tls12
+                .as_ref()
+                .filter(|value| !value.has_expired_at(now))
+                .map(|value| value.to_bytes());
+            #[cfg(not(feature = "tls12"))]
+            let tls12 = None::<Zeroizing<Vec<u8>>>;
+            match tls12 {
+                Some(value) => {
+                    1u8.encode(&mut bytes);
+                    PayloadU24(Payload::Borrowed(&value)).encode(&mut bytes);
+                }
+                None => 0u8.encode(&mut bytes),
+            }
+
+            let tls13 = data
+                .tls13
+                .iter()
+                .filter(|value| !value.has_expired_at(now))
+                .collect::<Vec<_>>();
+            (tls13.len() as u8).encode(&mut bytes);
+            for value in tls13 {
+                PayloadU24(Payload::Borrowed(&value.to_bytes())).encode(&mut bytes);
+            }
+        }
+
+        bytes
+    }
+
+    /// Decode a file written by `encode()`.
+    ///
+    /// A file with a different format version, or which is corrupt, gives
+    /// no sessions.  Individual sessions that are expired at `now`, or that
+    /// `provider` does not support, are skipped.
+    fn decode(bytes: &[u8], provider: &CryptoProvider, now: UnixTime) -> VecDeque<ServerData> {
+        let mut r = Reader::init(bytes);
+        let mut servers = VecDeque::new();
+        match u8::read(&mut r) {
+            Ok(FILE_FORMAT_V1) => {}
+            _ => {
+                debug!("ignoring client session file with unknown format");
+                return servers;
+            }
+        }
+
+        while r.any_left() {
+            match decode_server(&mut r, provider, now) {
+                Some(data) => servers.push_back(data),
+                None => {
+                    debug!("ignoring corrupt client session file");
+                    return VecDeque::new();
+                }
+            }
+        }
+
+        servers
+    }
+
+    fn decode_server(
+        r: &mut Reader<'_>,
+        provider: &CryptoProvider,
+        now: UnixTime,
+    ) -> Option<ServerData> {
+        let name = PayloadU8::read(r).ok()?;
+        let name = core::str::from_utf8(&name.0).ok()?;
+        let mut data = ServerData::new(
+            ServerName::try_from(name)
+                .ok()?
+                .to_owned(),
+        );
+
+        if u8::read(r).ok()? == 1 {
+            data.kx_hint = Some(NamedGroup::read(r).ok()?);
+        }
+
+        if u8::read(r).ok()? == 1 {
+            let _value = Zeroizing::new(PayloadU24::read(r).ok()?.0.into_vec());
+            #[cfg(feature = "tls12")]
+            {
+                data.tls12 = persist::Tls12ClientSessionValue::from_bytes(&_value, provider)
+                    .ok()
+                    .filter(|value| !value.has_expired_at(now));
+            }
+        }
+
+        for _ in 0..u8::read(r).ok()? {
+            let value = Zeroizing::new(PayloadU24::read(r).ok()?.0.into_vec());
+            let Ok(value) = persist::Tls13ClientSessionValue::from_bytes(&value, provider) else {
+                continue;
+            };
+            if !value.has_expired_at(now) && data.tls13.len() < MAX_TLS13_TICKETS_PER_SERVER {
+                data.tls13.push_back(value);
+            }
+        }
+
+        Some(data)
+    }
+
+    /// Read `path`, or return `None` if it does not exist.
+    fn read_file(path: &Path) -> io::Result<Option<Zeroizing<Vec<u8>>>> {
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        // Allocate enough up front that the secrets are not left behind by
+        // reallocation.
+        let len = file
+            .metadata()?
+            .len()
+            .min(MAX_FILE_SIZE) as usize;
+        let mut bytes = Zeroizing::new(Vec::with_capacity(len + 1));
+        file.take(MAX_FILE_SIZE + 1)
+            .read_to_end(&mut bytes)?;
+        match bytes.len() as u64 > MAX_FILE_SIZE {
+            true => Err(io::Error::new(io::ErrorKind::InvalidData, "file too large")),
+            false => Ok(Some(bytes)),
+        }
+    }
+
+    /// An exclusive lock on changing a session file, held by creating a lock
+    /// file next to it.  The lock file is removed on drop.
+    struct FileLock {
+        path: PathBuf,
+    }
+
+    impl FileLock {
+        /// Take the lock for `path`, waiting up to `LOCK_TIMEOUT` for any other
+        /// holder to release it.
+        fn acquire(path: &Path) -> io::Result<Self> {
+            let path = sibling_path(path, ".lock");
+            let mut waited = Duration::ZERO;
+            loop {
+                match new_file_options().open(&path) {
+                    Ok(_) => return Ok(Self { path }),
+                    Err(err) if err.kind() != io::ErrorKind::AlreadyExists => return Err(err),
+                    Err(err) if waited >= LOCK_TIMEOUT => return Err(err),
+                    Err(_) => {}
+                }
+
+                if let Some(modified) = stale_lock_time(&path) {
+                    debug!("removing stale client session lock file {path:?}");
+                    let _ = Self::remove_stale(&path, modified);
+                    continue;
+                }
+
+                thread::sleep(Duration::from_millis(5));
+                waited += Duration::from_millis(5);
+            }
+        }
+
+        /// Remove the lock file at `path`, which was found to be stale with
+        /// modification time `modified`.
+        ///
+        /// Another process may also find it stale, remove it, and take the lock
+        /// afresh in the meantime.  So the file is first renamed to a unique name
+        /// (which only one process can do), and only removed if it is still the
+        /// stale one.  A live lock file is put back.
+        fn remove_stale(path: &Path, modified: SystemTime) -> io::Result<()> {
+            static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+            let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+            let moved = sibling_path(path, &format!(".{}.{id}.stale", process::id()));
+            fs::rename(path, &moved)?;
+
+            if stale_lock_time(&moved) == Some(modified) {
+                return fs::remove_file(&moved);
+            }
+
+            // Putting it back fails if another lock file is in its place: never
+            // overwrite that.
+            let result = fs::hard_link(&moved, path);
+            let _ = fs::remove_file(&moved);
+            result
+        }
+    }
+
+    /// The modification time of the lock file at `path`, if it is older than
+    /// `STALE_LOCK_AGE`.
+    fn stale_lock_time(path: &Path) -> Option<SystemTime> {
+        let modified = fs::symlink_metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()?;
+        SystemTime::now()
+            .duration_since(modified)
+            .is_ok_and(|age| age > STALE_LOCK_AGE)
+            .then_some(modified)
+    }
+
+    impl Drop for FileLock {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    /// Replace the contents of `path` with `contents`, via a temporary file.
+    ///
+    /// The caller must hold the `FileLock` for `path`.
+    fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+        let temp_path = sibling_path(path, &format!(".{}.tmp", process::id()));
+
+        // Never write through an existing file (or symlink): any left here was
+        // abandoned by an earlier process with the same id.
+        let file = match new_file_options().open(&temp_path) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                fs::remove_file(&temp_path)?;
+                new_file_options().open(&temp_path)
+            }
+            result => result,
+        };
+
+        let result = file
+            .and_then(|mut file| {
+                file.write_all(contents)?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&temp_path, path));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    /// Options for creating a new file, readable only by its owner.
+    fn new_file_options() -> fs::OpenOptions {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options
+    }
+
+    /// `path`, with `suffix` appended to its file name.
+    fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+        let mut name = path
+            .file_name()
+            .map(OsString::from)
+            .unwrap_or_default();
+        name.push(suffix);
+        path.with_file_name(name)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::env;
+
+        use super::*;
+
+        #[test]
+        fn live_lock_is_not_removed_as_stale() {
+            let path = env::temp_dir().join(format!("rustls-{}-live.lock", process::id()));
+            fs::write(&path, b"").unwrap();
+
+            // As if another process took the lock after we found an older lock file stale.
+            FileLock::remove_stale(&path, SystemTime::UNIX_EPOCH).unwrap();
+            assert!(path.exists());
+            fs::remove_file(&path).unwrap();
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+pub use file_store::ClientSessionFileStore;
+
 #[derive(Debug)]
 pub(super) struct FailResolveClientCert {}
 
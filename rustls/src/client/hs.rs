@@ -18,25 +18,30 @@ use crate::client::common::ClientHelloDetails;
 use crate::client::ech::EchState;
 use crate::client::{tls13, ClientConfig, EchMode, EchStatus};
 use crate::common_state::{
-    CommonState, HandshakeKind, KxState, RawKeyNegotationResult, RawKeyNegotiationParams, State,
+    CommonState, HandshakeKind, KxState, Protocol, RawKeyNegotationResult, RawKeyNegotiationParams,
+    State,
 };
 use crate::conn::ConnectionRandoms;
 use crate::crypto::{ActiveKeyExchange, KeyExchangeAlgorithm};
+use crate::custom_extension::CustomExtension;
 use crate::enums::{AlertDescription, CipherSuite, ContentType, HandshakeType, ProtocolVersion};
 use crate::error::{Error, PeerIncompatible, PeerMisbehaved};
+use crate::grease::{is_grease, Grease};
 use crate::hash_hs::HandshakeHashBuffer;
 use crate::log::{debug, trace};
-use crate::msgs::base::Payload;
+use crate::msgs::base::{Payload, PayloadU8};
 use crate::msgs::enums::{
     CertificateType, Compression, ECPointFormat, ExtensionType, PSKKeyExchangeMode,
 };
 use crate::msgs::handshake::{
     CertificateStatusRequest, ClientExtension, ClientHelloPayload, ClientSessionTicket,
     ConvertProtocolNameList, HandshakeMessagePayload, HandshakePayload, HasServerExtensions,
-    HelloRetryRequest, KeyShareEntry, Random, SessionId,
+    HelloRetryRequest, KeyShareEntry, Random, SessionId, UnknownExtension,
 };
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
+use crate::observer::HandshakeEvent;
+use crate::psk::ExternalPsk;
 use crate::tls13::key_schedule::KeyScheduleEarly;
 use crate::SupportedCipherSuite;
 
@@ -123,6 +128,8 @@ pub(super) fn start_handshake(
 
     let session_id = if let Some(_resuming) = &mut resuming {
         debug!("Resuming session");
+        cx.common
+            .observe(HandshakeEvent::ResumptionOffered);
 
         match &mut _resuming.value {
             #[cfg(feature = "tls12")]
@@ -144,14 +151,19 @@ pub(super) fn start_handshake(
 
     // https://tools.ietf.org/html/rfc8446#appendix-D.4
     // https://tools.ietf.org/html/draft-ietf-quic-tls-34#section-8.4
+    // https://www.rfc-editor.org/rfc/rfc9147#section-5.3
     let session_id = match session_id {
         Some(session_id) => session_id,
-        None if cx.common.is_quic() => SessionId::empty(),
+        None if cx.common.is_quic() || cx.common.is_dtls() => SessionId::empty(),
         None if !config.supports_version(ProtocolVersion::TLSv1_3) => SessionId::empty(),
         None => SessionId::random(config.provider.secure_random)?,
     };
 
     let random = Random::new(config.provider.secure_random)?;
+    let grease = match config.enable_grease {
+        true => Some(Grease::new(config.provider.secure_random)?),
+        false => None,
+    };
     let extension_order_seed = crate::rand::random_u16(config.provider.secure_random)?;
 
     let ech_state = match config.ech_mode.as_ref() {
@@ -167,6 +179,14 @@ pub(super) fn start_handshake(
         _ => None,
     };
 
+    // External PSKs are not offered alongside ECH.
+    let external_psks = match (&config.psk_resolver, &config.ech_mode) {
+        (Some(resolver), None) if config.supports_version(ProtocolVersion::TLSv1_3) => {
+            resolver.resolve(&server_name)
+        }
+        _ => Vec::new(),
+    };
+
     emit_client_hello_for_retry(
         transcript_buffer,
         None,
@@ -176,6 +196,7 @@ pub(super) fn start_handshake(
         ClientHelloInput {
             config,
             resuming,
+            external_psks,
             random,
             #[cfg(feature = "tls12")]
             using_ems: false,
@@ -184,6 +205,7 @@ pub(super) fn start_handshake(
             session_id,
             server_name,
             prev_ech_ext: None,
+            grease,
         },
         cx,
         ech_state,
@@ -194,6 +216,7 @@ struct ExpectServerHello {
     input: ClientHelloInput,
     transcript_buffer: HandshakeHashBuffer,
     early_key_schedule: Option<KeyScheduleEarly>,
+    offered_psks: Vec<tls13::OfferedPsk>,
     offered_key_share: Option<Box<dyn ActiveKeyExchange>>,
     suite: Option<SupportedCipherSuite>,
     ech_state: Option<EchState>,
@@ -207,6 +230,7 @@ struct ExpectServerHelloOrHelloRetryRequest {
 struct ClientHelloInput {
     config: Arc<ClientConfig>,
     resuming: Option<persist::Retrieved<ClientSessionValue>>,
+    external_psks: Vec<Arc<ExternalPsk>>,
     random: Random,
     #[cfg(feature = "tls12")]
     using_ems: bool,
@@ -215,6 +239,7 @@ struct ClientHelloInput {
     session_id: SessionId,
     server_name: ServerName<'static>,
     prev_ech_ext: Option<ClientExtension>,
+    grease: Option<Grease>,
 }
 
 fn emit_client_hello_for_retry(
@@ -228,9 +253,10 @@ fn emit_client_hello_for_retry(
     mut ech_state: Option<EchState>,
 ) -> NextStateOrError<'static> {
     let config = &input.config;
+    let grease = input.grease;
     // Defense in depth: the ECH state should be None if ECH is disabled based on config
     // builder semantics.
-    let forbids_tls12 = cx.common.is_quic() || ech_state.is_some();
+    let forbids_tls12 = cx.common.is_quic() || cx.common.is_dtls() || ech_state.is_some();
     let support_tls12 = config.supports_version(ProtocolVersion::TLSv1_2) && !forbids_tls12;
     let support_tls13 = config.supports_version(ProtocolVersion::TLSv1_3);
 
@@ -260,13 +286,26 @@ fn emit_client_hello_for_retry(
         .collect();
 
     let mut exts = vec![
-        ClientExtension::SupportedVersions(supported_versions),
-        ClientExtension::NamedGroups(offered_groups),
-        ClientExtension::SignatureAlgorithms(
+        ClientExtension::SupportedVersions(grease_first(
+            grease.map(|g| g.version()),
+            supported_versions
+                .iter()
+                .map(|version| match version {
+                    ProtocolVersion::TLSv1_3 => cx.common.protocol.tls13_version(),
+                    version => *version,
+                })
+                .collect(),
+        )),
+        ClientExtension::NamedGroups(grease_first(
+            grease.map(|g| g.named_group()),
+            offered_groups,
+        )),
+        ClientExtension::SignatureAlgorithms(grease_first(
+            grease.map(|g| g.signature_scheme()),
             config
                 .verifier
                 .supported_verify_schemes(),
-        ),
+        )),
         ClientExtension::ExtendedMasterSecretRequest,
         ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp()),
     ];
@@ -277,6 +316,13 @@ fn emit_client_hello_for_retry(
         }
     }
 
+    if config
+        .verifier
+        .requests_signed_certificate_timestamps()
+    {
+        exts.push(ClientExtension::SignedCertificateTimestampRequest);
+    }
+
     // Send the ECPointFormat extension only if we are proposing ECDHE
     if config
         .provider
@@ -327,6 +373,12 @@ fn emit_client_hello_for_retry(
             {
                 shares.push(KeyShareEntry::new(component_group, component_share));
             }
+
+            // The GREASE key share is only sent in the initial client hello: the
+            // server cannot have asked for it in a `HelloRetryRequest`.
+            if let Some(grease) = grease {
+                shares.insert(0, KeyShareEntry::new(grease.named_group(), &[0][..]));
+            }
         }
 
         exts.push(ClientExtension::KeyShare(shares));
@@ -336,21 +388,46 @@ fn emit_client_hello_for_retry(
         exts.push(ClientExtension::Cookie(cookie.clone()));
     }
 
+    let external_psks = match support_tls13 {
+        true => {
+            tls13::usable_external_psks(config, cx.common.protocol, &input.external_psks, suite)
+        }
+        false => Vec::new(),
+    };
+
+    // We only offer PSK_KE for external PSKs which allow it.  Such connections
+    // don't have forward secrecy, and are similar to TLS1.2 resumption.  We
+    // don't offer PSK_DHE_KE (so also don't resume) if none of them allow it.
+    let offer_psk_dhe_ke = external_psks.is_empty()
+        || external_psks
+            .iter()
+            .any(|(psk, _)| psk.key_exchange_modes().allows_dhe());
     if support_tls13 {
-        // We could support PSK_KE here too. Such connections don't
-        // have forward secrecy, and are similar to TLS1.2 resumption.
-        let psk_modes = vec![PSKKeyExchangeMode::PSK_DHE_KE];
+        let mut psk_modes = Vec::new();
+        if offer_psk_dhe_ke {
+            psk_modes.push(PSKKeyExchangeMode::PSK_DHE_KE);
+        }
+        if external_psks.iter().any(|(psk, _)| {
+            psk.key_exchange_modes()
+                .allows_psk_only()
+        }) {
+            psk_modes.push(PSKKeyExchangeMode::PSK_KE);
+        }
         exts.push(ClientExtension::PresharedKeyModes(psk_modes));
     }
 
     if !config.alpn_protocols.is_empty() {
-        exts.push(ClientExtension::Protocols(Vec::from_slices(
-            &config
+        let grease_protocol = grease.map(|g| g.alpn_protocol());
+        exts.push(ClientExtension::Protocols(Vec::from_slices(&grease_first(
+            grease_protocol
+                .as_ref()
+                .map(|proto| &proto[..]),
+            config
                 .alpn_protocols
                 .iter()
                 .map(|proto| &proto[..])
-                .collect::<Vec<_>>(),
-        )));
+                .collect(),
+        ))));
     }
 
     input.hello.offered_cert_compression = if support_tls13 && !config.cert_decompressors.is_empty()
@@ -385,6 +462,55 @@ fn emit_client_hello_for_retry(
         ]));
     }
 
+    if support_tls13 {
+        let schemes = config
+            .verifier
+            .delegated_credential_schemes();
+        if !schemes.is_empty() {
+            exts.push(ClientExtension::DelegatedCredential(schemes));
+        }
+    }
+
+    // RFC 9001 section 4.4: clients MUST NOT offer post-handshake authentication in QUIC.
+    // Our DTLS connections do not support it either.
+    if support_tls13
+        && config.enable_post_handshake_auth
+        && !cx.common.is_quic()
+        && !cx.common.is_dtls()
+    {
+        exts.push(ClientExtension::PostHandshakeAuth);
+    }
+
+    // QUIC does not use TLS records, so there is no record size to limit.  DTLS
+    // records are sized to fit datagrams instead.
+    if let (Some(limit), false, false) = (
+        config.record_size_limit,
+        cx.common.is_quic(),
+        cx.common.is_dtls(),
+    ) {
+        exts.push(ClientExtension::RecordSizeLimit(limit));
+    }
+
+    exts.extend(
+        config
+            .custom_extensions
+            .iter()
+            .map(|ext| ClientExtension::Unknown(ext.to_unknown_extension())),
+    );
+
+    if let Some(grease) = grease {
+        // One empty, and one non-empty, as recommended by RFC 8701.
+        let [empty, non_empty] = grease.extensions();
+        exts.push(ClientExtension::Unknown(UnknownExtension {
+            typ: empty,
+            payload: Payload::new(Vec::new()),
+        }));
+        exts.push(ClientExtension::Unknown(UnknownExtension {
+            typ: non_empty,
+            payload: Payload::new(vec![0]),
+        }));
+    }
+
     // Extra extensions must be placed before the PSK extension
     exts.extend(extra_exts.iter().cloned());
 
@@ -398,7 +524,13 @@ fn emit_client_hello_for_retry(
     }
 
     // Do we have a SessionID or ticket cached for this host?
-    let tls13_session = prepare_resumption(&input.resuming, &mut exts, suite, cx, config);
+    let no_resumption = None;
+    let resuming = match offer_psk_dhe_ke {
+        true => &input.resuming,
+        false => &no_resumption,
+    };
+    let tls13_session = prepare_resumption(resuming, &mut exts, suite, cx, config);
+    tls13::prepare_external_psks(&external_psks, &mut exts);
 
     // Extensions MAY be randomized
     // but they also need to keep the same order as the previous ClientHello
@@ -432,11 +564,17 @@ fn emit_client_hello_for_retry(
         .collect();
     // We don't do renegotiation at all, in fact.
     cipher_suites.push(CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV);
+    let cipher_suites = grease_first(grease.map(|g| g.cipher_suite()), cipher_suites);
 
     let mut chp_payload = ClientHelloPayload {
-        client_version: ProtocolVersion::TLSv1_2,
+        client_version: cx.common.protocol.legacy_version(),
         random: input.random,
         session_id: input.session_id,
+        // DTLS 1.3 does not use this: see RFC 9147 section 5.3.
+        legacy_cookie: cx
+            .common
+            .is_dtls()
+            .then(PayloadU8::empty),
         cipher_suites,
         compression_methods: vec![Compression::Null],
         extensions: exts,
@@ -454,12 +592,20 @@ fn emit_client_hello_for_retry(
             _ => None,
         });
 
+    let prev_ech_status = cx.data.ech_status;
+    let mut padded = false;
     match (cx.data.ech_status, &mut ech_state) {
         // If we haven't offered ECH, or have offered ECH but got a non-rejecting HRR, then
         // we need to replace the client hello payload with an ECH client hello payload.
         (EchStatus::NotOffered | EchStatus::Offered, Some(ech_state)) => {
             // Replace the client hello payload with an ECH client hello payload.
-            chp_payload = ech_state.ech_hello(chp_payload, retryreq, &tls13_session)?;
+            chp_payload = ech_state.ech_hello(
+                chp_payload,
+                retryreq,
+                &tls13_session,
+                config.client_hello_padding,
+            )?;
+            padded = true;
             cx.data.ech_status = EchStatus::Offered;
             // Store the ECH extension in case we need to carry it forward in a subsequent hello.
             input.prev_ech_ext = chp_payload.extensions.last().cloned();
@@ -482,11 +628,23 @@ fn emit_client_hello_for_retry(
         _ => {}
     }
 
-    // Note what extensions we sent.
+    // An ECH outer hello is padded as it is made.  Otherwise, pad now: PSK binders
+    // are computed over the padded hello.
+    if let (Some(target), false) = (config.client_hello_padding, padded) {
+        chp_payload.pad_to(target);
+    }
+
+    if cx.data.ech_status != prev_ech_status {
+        cx.common
+            .observe(HandshakeEvent::Ech(cx.data.ech_status));
+    }
+
+    // Note what extensions we sent.  The server may not reply to GREASE extensions.
     input.hello.sent_extensions = chp_payload
         .extensions
         .iter()
         .map(ClientExtension::ext_type)
+        .filter(|typ| !is_grease(u16::from(*typ)))
         .collect();
 
     let mut chp = HandshakeMessagePayload {
@@ -494,6 +652,15 @@ fn emit_client_hello_for_retry(
         payload: HandshakePayload::ClientHello(chp_payload),
     };
 
+    // External PSKs follow any resumption ticket.
+    let offered_psks = tls13::fill_in_external_psk_binders(
+        external_psks,
+        usize::from(tls13_session.is_some()),
+        &transcript_buffer,
+        &mut chp,
+        cx.common.protocol,
+    );
+
     let early_key_schedule = match (ech_state.as_mut(), tls13_session) {
         // If we're performing ECH and resuming, then the PSK binder will have been dealt with
         // separately, and we need to take the early_data_key_schedule computed for the inner hello.
@@ -506,7 +673,12 @@ fn emit_client_hello_for_retry(
         // normal.
         (_, Some(tls13_session)) => Some((
             tls13_session.suite(),
-            tls13::fill_in_psk_binder(&tls13_session, &transcript_buffer, &mut chp),
+            tls13::fill_in_psk_binder(
+                &tls13_session,
+                &transcript_buffer,
+                &mut chp,
+                cx.common.protocol,
+            ),
         )),
 
         // No early key schedule in other cases.
@@ -572,6 +744,7 @@ fn emit_client_hello_for_retry(
         input,
         transcript_buffer,
         early_key_schedule,
+        offered_psks,
         offered_key_share: key_share,
         suite,
         ech_state,
@@ -584,6 +757,14 @@ fn emit_client_hello_for_retry(
     })
 }
 
+/// Put `grease` (if any) before `values`.
+fn grease_first<T>(grease: Option<T>, values: Vec<T>) -> Vec<T> {
+    grease
+        .into_iter()
+        .chain(values)
+        .collect()
+}
+
 /// Prepare resumption with the session state retrieved from storage.
 ///
 /// This function will push onto `exts` to
@@ -651,6 +832,17 @@ fn prepare_resumption<'a>(
     Some(tls13)
 }
 
+/// Record the server's replies to our custom extensions.
+///
+/// These were already checked to be solicited.
+pub(super) fn process_custom_extensions<'a>(
+    data: &mut ClientConnectionData,
+    exts: impl Iterator<Item = &'a UnknownExtension>,
+) {
+    data.received_custom_extensions
+        .extend(exts.map(CustomExtension::from_unknown_extension));
+}
+
 pub(super) fn process_alpn_protocol(
     common: &mut CommonState,
     config: &ClientConfig,
@@ -693,6 +885,20 @@ pub(super) fn process_alpn_protocol(
     Ok(())
 }
 
+pub(super) fn process_record_size_limit(
+    common: &mut CommonState,
+    config: &ClientConfig,
+    limit: Option<u16>,
+) -> Result<(), Error> {
+    if let Some(limit) = limit {
+        common.set_peer_record_size_limit(limit)?;
+        if let Some(ours) = config.record_size_limit {
+            common.enforce_record_size_limit(ours);
+        }
+    }
+    Ok(())
+}
+
 pub(super) fn process_server_cert_type_extension(
     common: &mut CommonState,
     config: &ClientConfig,
@@ -756,7 +962,7 @@ impl State<ClientConnectionData> for ExpectServerHello {
         let config = &self.input.config;
         let tls13_supported = config.supports_version(TLSv1_3);
 
-        let server_version = if server_hello.legacy_version == TLSv1_2 {
+        let server_version = if server_hello.legacy_version == cx.common.protocol.legacy_version() {
             server_hello
                 .supported_versions()
                 .unwrap_or(server_hello.legacy_version)
@@ -764,6 +970,18 @@ impl State<ClientConnectionData> for ExpectServerHello {
             server_hello.legacy_version
         };
 
+        // DTLS 1.3 is handled as TLS1.3 from here on.
+        let server_version = match (cx.common.protocol, server_version) {
+            (Protocol::Dtls, ProtocolVersion::DTLSv1_3) => TLSv1_3,
+            (Protocol::Dtls, _) => {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::ProtocolVersion,
+                    PeerIncompatible::ServerDoesNotSupportDtls13,
+                ));
+            }
+            (_, version) => version,
+        };
+
         let version = match server_version {
             TLSv1_3 if tls13_supported => TLSv1_3,
             TLSv1_2 if config.supports_version(TLSv1_2) => {
@@ -831,6 +1049,8 @@ impl State<ClientConnectionData> for ExpectServerHello {
         // Extract ALPN protocol
         if !cx.common.is_tls13() {
             process_alpn_protocol(cx.common, config, server_hello.alpn_protocol())?;
+            process_record_size_limit(cx.common, config, server_hello.record_size_limit())?;
+            process_custom_extensions(cx.data, server_hello.unknown_extensions());
         }
 
         // If ECPointFormats extension is supplied by the server, it must contain
@@ -846,6 +1066,7 @@ impl State<ClientConnectionData> for ExpectServerHello {
 
         let suite = config
             .find_cipher_suite(server_hello.cipher_suite)
+            .filter(|cs| cs.usable_for_protocol(cx.common.protocol))
             .ok_or_else(|| {
                 cx.common.send_fatal_alert(
                     AlertDescription::HandshakeFailure,
@@ -909,6 +1130,7 @@ impl State<ClientConnectionData> for ExpectServerHello {
                     suite,
                     transcript,
                     self.early_key_schedule,
+                    self.offered_psks,
                     self.input.hello,
                     // We always send a key share when TLS 1.3 is enabled.
                     self.offered_key_share.unwrap(),
@@ -1045,7 +1267,7 @@ impl ExpectServerHelloOrHelloRetryRequest {
 
         // Or asks us to talk a protocol we didn't offer, or doesn't support HRR at all.
         match hrr.supported_versions() {
-            Some(ProtocolVersion::TLSv1_3) => {
+            Some(version) if version == cx.common.protocol.tls13_version() => {
                 cx.common.negotiated_version = Some(ProtocolVersion::TLSv1_3);
             }
             _ => {
@@ -1060,7 +1282,10 @@ impl ExpectServerHelloOrHelloRetryRequest {
 
         // Or asks us to use a ciphersuite we didn't offer.
         let config = &self.next.input.config;
-        let Some(cs) = config.find_cipher_suite(hrr.cipher_suite) else {
+        let Some(cs) = config
+            .find_cipher_suite(hrr.cipher_suite)
+            .filter(|cs| cs.usable_for_protocol(cx.common.protocol))
+        else {
             return Err({
                 cx.common.send_fatal_alert(
                     AlertDescription::IllegalParameter,
@@ -1091,6 +1316,8 @@ impl ExpectServerHelloOrHelloRetryRequest {
                     // continue the handshake. We will abort with an ECH required error
                     // at the end.
                     cx.data.ech_status = EchStatus::Rejected;
+                    cx.common
+                        .observe(HandshakeEvent::Ech(EchStatus::Rejected));
                 }
             }
             (Some(_), None) => {
@@ -1116,6 +1343,7 @@ impl ExpectServerHelloOrHelloRetryRequest {
         // Early data is not allowed after HelloRetryrequest
         if cx.data.early_data.is_enabled() {
             cx.data.early_data.rejected();
+            cx.common.early_exporter = None;
         }
 
         let key_share = match req_group {
@@ -14,6 +14,7 @@ use crate::check::{inappropriate_handshake_message, inappropriate_message};
 use crate::client::common::{ClientAuthDetails, ServerCertDetails};
 use crate::client::{hs, ClientConfig};
 use crate::common_state::{CommonState, HandshakeKind, KxState, Side, State};
+use crate::conn::kernel::{KernelState, Tls12KernelState};
 use crate::conn::ConnectionRandoms;
 use crate::crypto::KeyExchangeAlgorithm;
 use crate::enums::{AlertDescription, ContentType, HandshakeType, ProtocolVersion};
@@ -22,6 +23,7 @@ use crate::hash_hs::HandshakeHash;
 use crate::log::{debug, trace, warn};
 use crate::msgs::base::{Payload, PayloadU16, PayloadU8};
 use crate::msgs::ccs::ChangeCipherSpecPayload;
+use crate::msgs::codec::Codec;
 use crate::msgs::handshake::{
     CertificateChain, ClientDhParams, ClientEcdhParams, ClientKeyExchangeParams,
     HandshakeMessagePayload, HandshakePayload, NewSessionTicketPayload, ServerKeyExchangeParams,
@@ -104,6 +106,12 @@ mod server_hello {
                 debug!("Server may staple OCSP response");
             }
 
+            // Did the server send any signed certificate timestamps?
+            let sct_list = server_hello
+                .sct_list()
+                .map(|scts| scts.get_encoding())
+                .unwrap_or_default();
+
             // See if we're successfully resuming.
             if let Some(resuming) = self.resuming_session {
                 if resuming.session_id == server_hello.session_id {
@@ -183,6 +191,7 @@ mod server_hello {
                 transcript: self.transcript,
                 suite,
                 may_send_cert_status,
+                sct_list,
                 must_issue_new_ticket,
             }))
         }
@@ -199,6 +208,7 @@ struct ExpectCertificate {
     transcript: HandshakeHash,
     pub(super) suite: &'static Tls12CipherSuite,
     may_send_cert_status: bool,
+    sct_list: Vec<u8>,
     must_issue_new_ticket: bool,
 }
 
@@ -229,10 +239,11 @@ impl State<ClientConnectionData> for ExpectCertificate {
                 transcript: self.transcript,
                 suite: self.suite,
                 server_cert_chain,
+                sct_list: self.sct_list,
                 must_issue_new_ticket: self.must_issue_new_ticket,
             }))
         } else {
-            let server_cert = ServerCertDetails::new(server_cert_chain, vec![]);
+            let server_cert = ServerCertDetails::new(server_cert_chain, vec![], self.sct_list);
 
             Ok(Box::new(ExpectServerKx {
                 config: self.config,
@@ -264,6 +275,7 @@ struct ExpectCertificateStatusOrServerKx<'m> {
     transcript: HandshakeHash,
     suite: &'static Tls12CipherSuite,
     server_cert_chain: CertificateChain<'m>,
+    sct_list: Vec<u8>,
     must_issue_new_ticket: bool,
 }
 
@@ -293,7 +305,7 @@ impl State<ClientConnectionData> for ExpectCertificateStatusOrServerKx<'_> {
                 using_ems: self.using_ems,
                 transcript: self.transcript,
                 suite: self.suite,
-                server_cert: ServerCertDetails::new(self.server_cert_chain, vec![]),
+                server_cert: ServerCertDetails::new(self.server_cert_chain, vec![], self.sct_list),
                 must_issue_new_ticket: self.must_issue_new_ticket,
             })
             .handle(cx, m),
@@ -314,6 +326,7 @@ impl State<ClientConnectionData> for ExpectCertificateStatusOrServerKx<'_> {
                 transcript: self.transcript,
                 suite: self.suite,
                 server_cert_chain: self.server_cert_chain,
+                sct_list: self.sct_list,
                 must_issue_new_ticket: self.must_issue_new_ticket,
             })
             .handle(cx, m),
@@ -339,6 +352,7 @@ impl State<ClientConnectionData> for ExpectCertificateStatusOrServerKx<'_> {
             transcript: self.transcript,
             suite: self.suite,
             server_cert_chain: self.server_cert_chain.into_owned(),
+            sct_list: self.sct_list,
             must_issue_new_ticket: self.must_issue_new_ticket,
         })
     }
@@ -354,6 +368,7 @@ struct ExpectCertificateStatus<'a> {
     transcript: HandshakeHash,
     suite: &'static Tls12CipherSuite,
     server_cert_chain: CertificateChain<'a>,
+    sct_list: Vec<u8>,
     must_issue_new_ticket: bool,
 }
 
@@ -379,7 +394,11 @@ impl State<ClientConnectionData> for ExpectCertificateStatus<'_> {
             &server_cert_ocsp_response
         );
 
-        let server_cert = ServerCertDetails::new(self.server_cert_chain, server_cert_ocsp_response);
+        let server_cert = ServerCertDetails::new(
+            self.server_cert_chain,
+            server_cert_ocsp_response,
+            self.sct_list,
+        );
 
         Ok(Box::new(ExpectServerKx {
             config: self.config,
@@ -406,6 +425,7 @@ impl State<ClientConnectionData> for ExpectCertificateStatus<'_> {
             transcript: self.transcript,
             suite: self.suite,
             server_cert_chain: self.server_cert_chain.into_owned(),
+            sct_list: self.sct_list,
             must_issue_new_ticket: self.must_issue_new_ticket,
         })
     }
@@ -860,11 +880,12 @@ impl State<ClientConnectionData> for ExpectServerDone<'_> {
         let cert_verified = st
             .config
             .verifier
-            .verify_server_cert(
+            .verify_server_cert_with_scts(
                 end_entity,
                 intermediates,
                 &st.server_name,
                 &st.server_cert.ocsp_response,
+                &st.server_cert.sct_list,
                 now,
             )
             .map_err(|err| {
@@ -1329,6 +1350,10 @@ impl State<ClientConnectionData> for ExpectTraffic {
             .extract_secrets(Side::Client)
     }
 
+    fn into_kernel_state(self: Box<Self>) -> Result<Box<dyn KernelState + 'static>, Error> {
+        Ok(Box::new(Tls12KernelState))
+    }
+
     fn into_owned(self: Box<Self>) -> hs::NextState<'static> {
         self
     }
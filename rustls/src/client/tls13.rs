@@ -15,8 +15,10 @@ use crate::client::{hs, ClientConfig, ClientSessionStore};
 use crate::common_state::{
     CommonState, HandshakeFlightTls13, HandshakeKind, KxState, Protocol, Side, State,
 };
+use crate::conn::kernel::{Direction, KernelContext, KernelState};
 use crate::conn::ConnectionRandoms;
 use crate::crypto::{ActiveKeyExchange, SharedSecret};
+use crate::custom_extension::CustomExtension;
 use crate::enums::{
     AlertDescription, ContentType, HandshakeType, ProtocolVersion, SignatureScheme,
 };
@@ -28,15 +30,17 @@ use crate::msgs::ccs::ChangeCipherSpecPayload;
 use crate::msgs::codec::{Codec, Reader};
 use crate::msgs::enums::{ExtensionType, KeyUpdateRequest};
 use crate::msgs::handshake::{
-    CertificatePayloadTls13, ClientExtension, EchConfigPayload, HandshakeMessagePayload,
-    HandshakePayload, HasServerExtensions, KeyShareEntry, NewSessionTicketPayloadTls13,
-    PresharedKeyIdentity, PresharedKeyOffer, ServerExtension, ServerHelloPayload,
-    CERTIFICATE_MAX_SIZE_LIMIT,
+    CertificateChain, CertificatePayloadTls13, CertificateRequestPayloadTls13, ClientExtension,
+    EchConfigPayload, HandshakeMessagePayload, HandshakePayload, HasServerExtensions,
+    KeyShareEntry, NewSessionTicketPayloadTls13, PresharedKeyIdentity, PresharedKeyOffer,
+    ServerExtension, ServerHelloPayload, CERTIFICATE_MAX_SIZE_LIMIT,
 };
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
+use crate::observer::HandshakeEvent;
+use crate::psk::ExternalPsk;
 use crate::sign::{CertifiedKey, Signer};
-use crate::suites::PartiallyExtractedSecrets;
+use crate::suites::{ConnectionTrafficSecrets, PartiallyExtractedSecrets, SupportedCipherSuite};
 use crate::tls13::key_schedule::{
     KeyScheduleEarly, KeyScheduleHandshake, KeySchedulePreHandshake, KeyScheduleTraffic,
     ResumptionSecret,
@@ -73,6 +77,7 @@ pub(super) fn handle_server_hello(
     suite: &'static Tls13CipherSuite,
     mut transcript: HandshakeHash,
     early_key_schedule: Option<KeyScheduleEarly>,
+    offered_psks: Vec<OfferedPsk>,
     mut hello: ClientHelloDetails,
     our_key_share: Box<dyn ActiveKeyExchange>,
     mut sent_tls13_fake_ccs: bool,
@@ -81,27 +86,15 @@ pub(super) fn handle_server_hello(
 ) -> hs::NextStateOrError<'static> {
     validate_server_hello(cx.common, server_hello)?;
 
-    let their_key_share = server_hello
-        .key_share()
-        .ok_or_else(|| {
-            cx.common.send_fatal_alert(
-                AlertDescription::MissingExtension,
-                PeerMisbehaved::MissingKeyShare,
-            )
-        })?;
-
-    let our_key_share = KeyExchangeChoice::new(&config, cx, our_key_share, their_key_share)
-        .map_err(|_| {
-            cx.common.send_fatal_alert(
-                AlertDescription::IllegalParameter,
-                PeerMisbehaved::WrongGroupForKeyShare,
-            )
-        })?;
+    // A resumption ticket, if offered, comes first in our `PresharedKey` extension.
+    let ticket_offered = early_key_schedule.is_some();
+    let mut external_psk = None;
+    let key_schedule_pre_handshake = match (server_hello.psk_index(), early_key_schedule) {
+        (Some(0), Some(early_key_schedule)) => {
+            let Some(ref resuming) = resuming_session else {
+                return Err(PeerMisbehaved::SelectedUnofferedPsk.into());
+            };
 
-    let key_schedule_pre_handshake = if let (Some(selected_psk), Some(early_key_schedule)) =
-        (server_hello.psk_index(), early_key_schedule)
-    {
-        if let Some(ref resuming) = resuming_session {
             let Some(resuming_suite) = suite.can_resume_from(resuming.suite()) else {
                 return Err({
                     cx.common.send_fatal_alert(
@@ -122,42 +115,101 @@ pub(super) fn handle_server_hello(
                 });
             }
 
-            if selected_psk != 0 {
-                return Err({
-                    cx.common.send_fatal_alert(
+            debug!("Resuming using PSK");
+            // The key schedule has been initialized and set in fill_in_psk_binder()
+            KeySchedulePreHandshake::from(early_key_schedule)
+        }
+        (Some(selected_psk), _) => {
+            let Some(offered) = offered_psks
+                .into_iter()
+                .nth((selected_psk as usize).wrapping_sub(usize::from(ticket_offered)))
+            else {
+                return Err(match ticket_offered {
+                    true => cx.common.send_fatal_alert(
                         AlertDescription::IllegalParameter,
                         PeerMisbehaved::SelectedInvalidPsk,
-                    )
+                    ),
+                    false => PeerMisbehaved::SelectedUnofferedPsk.into(),
                 });
+            };
+
+            if !offered.psk.usable_with(suite) {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::IllegalParameter,
+                    PeerMisbehaved::SelectedPskWithIncompatibleCipherSuite,
+                ));
             }
 
-            debug!("Resuming using PSK");
-            // The key schedule has been initialized and set in fill_in_psk_binder()
-        } else {
-            return Err(PeerMisbehaved::SelectedUnofferedPsk.into());
+            debug!("Using external PSK {:?}", offered.psk);
+            // Early data is only sent with a resumption ticket.
+            cx.data.early_data.rejected();
+            cx.common.early_traffic = false;
+            cx.common.early_exporter = None;
+            resuming_session.take();
+            cx.common.external_psk_identity = Some(offered.psk.identity().to_vec());
+            external_psk = Some(Arc::clone(&offered.psk));
+            KeySchedulePreHandshake::from(offered.key_schedule)
+        }
+        (None, _) => {
+            debug!("Not resuming");
+            // Discard the early data key schedule.
+            cx.data.early_data.rejected();
+            cx.common.early_traffic = false;
+            cx.common.early_exporter = None;
+            resuming_session.take();
+            KeySchedulePreHandshake::new(suite, cx.common.protocol)
         }
-        KeySchedulePreHandshake::from(early_key_schedule)
-    } else {
-        debug!("Not resuming");
-        // Discard the early data key schedule.
-        cx.data.early_data.rejected();
-        cx.common.early_traffic = false;
-        resuming_session.take();
-        KeySchedulePreHandshake::new(suite)
     };
 
-    cx.common.kx_state.complete();
-    let shared_secret = our_key_share
-        .complete(&their_key_share.payload.0)
-        .map_err(|err| {
-            cx.common
-                .send_fatal_alert(AlertDescription::IllegalParameter, err)
-        })?;
+    let mut key_schedule = match server_hello.key_share() {
+        Some(their_key_share) => {
+            let our_key_share = KeyExchangeChoice::new(&config, cx, our_key_share, their_key_share)
+                .map_err(|_| {
+                    cx.common.send_fatal_alert(
+                        AlertDescription::IllegalParameter,
+                        PeerMisbehaved::WrongGroupForKeyShare,
+                    )
+                })?;
+
+            cx.common.kx_state.complete();
+            let shared_secret = our_key_share
+                .complete(&their_key_share.payload.0)
+                .map_err(|err| {
+                    cx.common
+                        .send_fatal_alert(AlertDescription::IllegalParameter, err)
+                })?;
 
-    let mut key_schedule = key_schedule_pre_handshake.into_handshake(shared_secret);
+            // Remember what KX group the server liked for next time.
+            config
+                .resumption
+                .store
+                .set_kx_hint(server_name.clone(), their_key_share.group);
+
+            key_schedule_pre_handshake.into_handshake(shared_secret)
+        }
+        // The server chose the "psk_ke" key exchange mode, which we only offer
+        // for external PSKs allowing it.
+        None if external_psk
+            .as_ref()
+            .is_some_and(|psk| {
+                psk.key_exchange_modes()
+                    .allows_psk_only()
+            }) =>
+        {
+            cx.common.kx_state = KxState::None;
+            key_schedule_pre_handshake.into_handshake_without_dhe()
+        }
+        None => {
+            return Err(cx.common.send_fatal_alert(
+                AlertDescription::MissingExtension,
+                PeerMisbehaved::MissingKeyShare,
+            ));
+        }
+    };
 
     // If we have ECH state, check that the server accepted our offer.
     if let Some(ech_state) = ech_state {
+        let prev_ech_status = cx.data.ech_status;
         cx.data.ech_status = match ech_state.confirm_acceptance(
             &mut key_schedule,
             server_hello,
@@ -178,14 +230,12 @@ pub(super) fn handle_server_hello(
             // The server rejected our ECH offer.
             None => EchStatus::Rejected,
         };
+        if cx.data.ech_status != prev_ech_status {
+            cx.common
+                .observe(HandshakeEvent::Ech(cx.data.ech_status));
+        }
     }
 
-    // Remember what KX group the server liked for next time.
-    config
-        .resumption
-        .store
-        .set_kx_hint(server_name.clone(), their_key_share.group);
-
     // If we change keying when a subsequent handshake message is being joined,
     // the two halves will have different record layer protections.  Disallow this.
     cx.common.check_aligned_handshake()?;
@@ -205,6 +255,7 @@ pub(super) fn handle_server_hello(
     Ok(Box::new(ExpectEncryptedExtensions {
         config,
         resuming_session,
+        using_external_psk: external_psk.is_some(),
         server_name,
         randoms,
         suite,
@@ -304,6 +355,7 @@ pub(super) fn fill_in_psk_binder(
     resuming: &persist::Tls13ClientSessionValue,
     transcript: &HandshakeHashBuffer,
     hmp: &mut HandshakeMessagePayload<'_>,
+    protocol: Protocol,
 ) -> KeyScheduleEarly {
     // We need to know the hash function of the suite we're trying to resume into.
     let suite = resuming.suite();
@@ -316,16 +368,103 @@ pub(super) fn fill_in_psk_binder(
 
     // Run a fake key_schedule to simulate what the server will do if it chooses
     // to resume.
-    let key_schedule = KeyScheduleEarly::new(suite, resuming.secret());
+    let key_schedule = KeyScheduleEarly::new(suite, resuming.secret(), protocol);
     let real_binder = key_schedule.resumption_psk_binder_key_and_sign_verify_data(&handshake_hash);
 
     if let HandshakePayload::ClientHello(ref mut ch) = hmp.payload {
-        ch.set_psk_binder(real_binder.as_ref());
+        ch.set_psk_binder(0, real_binder.as_ref());
     };
 
     key_schedule
 }
 
+/// An external PSK offered in our `ClientHello`, with the key schedule used
+/// to compute its binder.
+pub(super) struct OfferedPsk {
+    psk: Arc<ExternalPsk>,
+    key_schedule: KeyScheduleEarly,
+}
+
+/// Choose which of `psks` to offer, and the cipher suite used to compute each binder.
+///
+/// `suite` is the suite selected by a `HelloRetryRequest`, if any.
+pub(super) fn usable_external_psks(
+    config: &ClientConfig,
+    protocol: Protocol,
+    psks: &[Arc<ExternalPsk>],
+    suite: Option<SupportedCipherSuite>,
+) -> Vec<(Arc<ExternalPsk>, &'static Tls13CipherSuite)> {
+    psks.iter()
+        .filter_map(|psk| {
+            let suite = match suite {
+                Some(suite) => suite
+                    .tls13()
+                    .filter(|suite| psk.usable_with(suite)),
+                None => config
+                    .provider
+                    .cipher_suites
+                    .iter()
+                    .filter(|suite| suite.usable_for_protocol(protocol))
+                    .filter_map(SupportedCipherSuite::tls13)
+                    .find(|suite| psk.usable_with(suite)),
+            }?;
+            Some((Arc::clone(psk), suite))
+        })
+        .collect()
+}
+
+/// Add identities for `psks` to our `PresharedKey` extension, following any
+/// resumption ticket.
+///
+/// Like [`prepare_resumption()`], this includes empty binders that are filled
+/// in later by [`fill_in_external_psk_binders()`].
+pub(super) fn prepare_external_psks(
+    psks: &[(Arc<ExternalPsk>, &'static Tls13CipherSuite)],
+    exts: &mut Vec<ClientExtension>,
+) {
+    for (psk, suite) in psks {
+        let psk_identity = PresharedKeyIdentity::new(psk.identity().to_vec(), 0);
+        let binder = vec![0u8; suite.common.hash_provider.output_len()];
+
+        match exts.last_mut() {
+            Some(ClientExtension::PresharedKey(offer)) => offer.push(psk_identity, binder),
+            _ => exts.push(ClientExtension::PresharedKey(PresharedKeyOffer::new(
+                psk_identity,
+                binder,
+            ))),
+        }
+    }
+}
+
+/// Compute the binders for `psks`, the first of which is at `first_index` in our
+/// `PresharedKey` extension.
+pub(super) fn fill_in_external_psk_binders(
+    psks: Vec<(Arc<ExternalPsk>, &'static Tls13CipherSuite)>,
+    first_index: usize,
+    transcript: &HandshakeHashBuffer,
+    hmp: &mut HandshakeMessagePayload<'_>,
+    protocol: Protocol,
+) -> Vec<OfferedPsk> {
+    let binder_plaintext = hmp.encoding_for_binder_signing();
+
+    psks.into_iter()
+        .enumerate()
+        .map(|(i, (psk, suite))| {
+            let handshake_hash =
+                transcript.hash_given(suite.common.hash_provider, &binder_plaintext);
+            let key_schedule = KeyScheduleEarly::new(suite, psk.secret(), protocol);
+            let binder = key_schedule
+                .external_psk_binder_key_and_sign_verify_data(psk.is_imported(), &handshake_hash);
+
+            if let HandshakePayload::ClientHello(ref mut ch) = hmp.payload {
+                ch.set_psk_binder(first_index + i, binder.as_ref());
+            };
+
+            OfferedPsk { psk, key_schedule }
+        })
+        .collect()
+}
+
 pub(super) fn prepare_resumption(
     config: &ClientConfig,
     cx: &mut ClientContext<'_>,
@@ -338,8 +477,10 @@ pub(super) fn prepare_resumption(
     cx.data.resumption_ciphersuite = Some(resuming_suite.into());
     // The EarlyData extension MUST be supplied together with the
     // PreSharedKey extension.
+    //
+    // Our DTLS connections do not send early data.
     let max_early_data_size = resuming_session.max_early_data_size();
-    if config.enable_early_data && max_early_data_size > 0 && !doing_retry {
+    if config.enable_early_data && max_early_data_size > 0 && !doing_retry && !cx.common.is_dtls() {
         cx.data
             .early_data
             .enable(max_early_data_size as usize);
@@ -384,6 +525,8 @@ pub(super) fn derive_early_traffic_secret(
         client_random,
         cx.common,
     );
+    cx.common.early_exporter =
+        Some(early_key_schedule.early_exporter(&client_hello_hash, key_log, client_random));
 
     // Now the client can send encrypted early data
     cx.common.early_traffic = true;
@@ -391,7 +534,8 @@ pub(super) fn derive_early_traffic_secret(
 }
 
 pub(super) fn emit_fake_ccs(sent_tls13_fake_ccs: &mut bool, common: &mut CommonState) {
-    if common.is_quic() {
+    // DTLS 1.3 has no middlebox compatibility mode: RFC 9147 section 5.
+    if common.is_quic() || common.is_dtls() {
         return;
     }
 
@@ -442,6 +586,7 @@ fn validate_encrypted_extensions(
 struct ExpectEncryptedExtensions {
     config: Arc<ClientConfig>,
     resuming_session: Option<persist::Tls13ClientSessionValue>,
+    using_external_psk: bool,
     server_name: ServerName<'static>,
     randoms: ConnectionRandoms,
     suite: &'static Tls13CipherSuite,
@@ -469,8 +614,10 @@ impl State<ClientConnectionData> for ExpectEncryptedExtensions {
 
         validate_encrypted_extensions(cx.common, &self.hello, exts)?;
         hs::process_alpn_protocol(cx.common, &self.config, exts.alpn_protocol())?;
+        hs::process_record_size_limit(cx.common, &self.config, exts.record_size_limit())?;
         hs::process_client_cert_type_extension(cx.common, &self.config, exts.client_cert_type())?;
         hs::process_server_cert_type_extension(cx.common, &self.config, exts.server_cert_type())?;
+        hs::process_custom_extensions(cx.data, exts.unknown_extensions());
 
         let ech_retry_configs = match (cx.data.ech_status, exts.server_ech_extension()) {
             // If we didn't offer ECH, or ECH was accepted, but the server sent an ECH encrypted
@@ -508,6 +655,7 @@ impl State<ClientConnectionData> for ExpectEncryptedExtensions {
                 } else {
                     cx.data.early_data.rejected();
                     cx.common.early_traffic = false;
+                    cx.common.early_exporter = None;
                 }
             }
 
@@ -548,6 +696,25 @@ impl State<ClientConnectionData> for ExpectEncryptedExtensions {
                 .handshake_kind
                 .get_or_insert(HandshakeKind::Full);
 
+            if self.using_external_psk {
+                // The server authenticated itself by knowledge of the PSK, so
+                // sends no certificate, and may not request one from us.
+                let cert_verified = verify::ServerCertVerified::assertion();
+                let sig_verified = verify::HandshakeSignatureValid::assertion();
+                return Ok(Box::new(ExpectFinished {
+                    config: self.config,
+                    server_name: self.server_name,
+                    randoms: self.randoms,
+                    suite: self.suite,
+                    transcript: self.transcript,
+                    key_schedule: self.key_schedule,
+                    client_auth: None,
+                    cert_verified,
+                    sig_verified,
+                    ech_retry_configs,
+                }));
+            }
+
             Ok(if self.hello.offered_cert_compression {
                 Box::new(ExpectCertificateOrCompressedCertificateOrCertReq {
                     config: self.config,
@@ -859,41 +1026,7 @@ impl State<ClientConnectionData> for ExpectCertificateRequest {
             ));
         }
 
-        let no_sigschemes = Vec::new();
-        let compat_sigschemes = certreq
-            .sigalgs_extension()
-            .unwrap_or(&no_sigschemes)
-            .iter()
-            .cloned()
-            .filter(SignatureScheme::supported_in_tls13)
-            .collect::<Vec<SignatureScheme>>();
-
-        if compat_sigschemes.is_empty() {
-            return Err(cx.common.send_fatal_alert(
-                AlertDescription::HandshakeFailure,
-                PeerIncompatible::NoCertificateRequestSignatureSchemesInCommon,
-            ));
-        }
-
-        let compat_compressor = certreq
-            .certificate_compression_extension()
-            .and_then(|offered| {
-                self.config
-                    .cert_compressors
-                    .iter()
-                    .find(|compressor| offered.contains(&compressor.algorithm()))
-            })
-            .cloned();
-
-        let client_auth = ClientAuthDetails::resolve(
-            self.config
-                .client_auth_cert_resolver
-                .as_ref(),
-            certreq.authorities_extension(),
-            &compat_sigschemes,
-            Some(certreq.context.0.clone()),
-            compat_compressor,
-        );
+        let client_auth = resolve_client_auth(&self.config, certreq, cx.common)?;
 
         Ok(if self.offered_cert_compression {
             Box::new(ExpectCertificateOrCompressedCertificate {
@@ -926,6 +1059,49 @@ impl State<ClientConnectionData> for ExpectCertificateRequest {
     }
 }
 
+/// Choose how to respond to `certreq`, during or after the handshake.
+fn resolve_client_auth(
+    config: &ClientConfig,
+    certreq: &CertificateRequestPayloadTls13,
+    common: &mut CommonState,
+) -> Result<ClientAuthDetails, Error> {
+    let no_sigschemes = Vec::new();
+    let compat_sigschemes = certreq
+        .sigalgs_extension()
+        .unwrap_or(&no_sigschemes)
+        .iter()
+        .cloned()
+        .filter(SignatureScheme::supported_in_tls13)
+        .collect::<Vec<SignatureScheme>>();
+
+    if compat_sigschemes.is_empty() {
+        return Err(common.send_fatal_alert(
+            AlertDescription::HandshakeFailure,
+            PeerIncompatible::NoCertificateRequestSignatureSchemesInCommon,
+        ));
+    }
+
+    let compat_compressor = certreq
+        .certificate_compression_extension()
+        .and_then(|offered| {
+            config
+                .cert_compressors
+                .iter()
+                .find(|compressor| offered.contains(&compressor.algorithm()))
+        })
+        .cloned();
+
+    Ok(ClientAuthDetails::resolve(
+        config
+            .client_auth_cert_resolver
+            .as_ref(),
+        certreq.authorities_extension(),
+        &compat_sigschemes,
+        Some(certreq.context.0.clone()),
+        compat_compressor,
+    ))
+}
+
 struct ExpectCompressedCertificate {
     config: Arc<ClientConfig>,
     server_name: ServerName<'static>,
@@ -1068,21 +1244,61 @@ impl State<ClientConnectionData> for ExpectCertificate {
             ));
         }
 
+        let custom_types = self
+            .config
+            .custom_extensions
+            .iter()
+            .map(CustomExtension::typ)
+            .collect::<Vec<_>>();
         if cert_chain.any_entry_has_duplicate_extension()
-            || cert_chain.any_entry_has_unknown_extension()
+            || cert_chain.any_entry_has_unknown_extension(&custom_types)
         {
             return Err(cx.common.send_fatal_alert(
                 AlertDescription::UnsupportedExtension,
                 PeerMisbehaved::BadCertChainExtensions,
             ));
         }
+        hs::process_custom_extensions(cx.data, cert_chain.end_entity_unknown_extensions());
         let end_entity_ocsp = cert_chain.end_entity_ocsp();
+        let end_entity_sct_list = cert_chain.end_entity_sct_list();
+        if !end_entity_sct_list.is_empty()
+            && !self
+                .config
+                .verifier
+                .requests_signed_certificate_timestamps()
+        {
+            return Err(cx.common.send_fatal_alert(
+                AlertDescription::UnsupportedExtension,
+                PeerMisbehaved::BadCertChainExtensions,
+            ));
+        }
+
+        let delegated_credential = cert_chain
+            .end_entity_delegated_credential()
+            .cloned();
+        if let Some(dc) = &delegated_credential {
+            // RFC 9345 section 4.1.1: the credential's scheme must be one we offered
+            if !self
+                .config
+                .verifier
+                .delegated_credential_schemes()
+                .contains(&dc.dc_cert_verify_algorithm)
+            {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::IllegalParameter,
+                    PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme,
+                ));
+            }
+        }
+
         let server_cert = ServerCertDetails::new(
             cert_chain
                 .into_certificate_chain()
                 .into_owned(),
             end_entity_ocsp,
-        );
+            end_entity_sct_list,
+        )
+        .with_delegated_credential(delegated_credential);
 
         Ok(Box::new(ExpectCertificateVerify {
             config: self.config,
@@ -1144,11 +1360,12 @@ impl State<ClientConnectionData> for ExpectCertificateVerify<'_> {
         let cert_verified = self
             .config
             .verifier
-            .verify_server_cert(
+            .verify_server_cert_with_scts(
                 end_entity,
                 intermediates,
                 &self.server_name,
                 &self.server_cert.ocsp_response,
+                &self.server_cert.sct_list,
                 now,
             )
             .map_err(|err| {
@@ -1158,18 +1375,27 @@ impl State<ClientConnectionData> for ExpectCertificateVerify<'_> {
 
         // 2. Verify their signature on the handshake.
         let handshake_hash = self.transcript.current_hash();
-        let sig_verified = self
-            .config
-            .verifier
-            .verify_tls13_signature(
-                construct_server_verify_message(&handshake_hash).as_ref(),
-                end_entity,
-                cert_verify,
-            )
-            .map_err(|err| {
-                cx.common
-                    .send_cert_verify_error_alert(err)
-            })?;
+        let message = construct_server_verify_message(&handshake_hash);
+        let sig_verified = match &self.server_cert.delegated_credential {
+            Some(credential) => self
+                .config
+                .verifier
+                .verify_tls13_signature_with_delegated_credential(
+                    message.as_ref(),
+                    end_entity,
+                    credential,
+                    cert_verify,
+                    now,
+                ),
+            None => self
+                .config
+                .verifier
+                .verify_tls13_signature(message.as_ref(), end_entity, cert_verify),
+        }
+        .map_err(|err| {
+            cx.common
+                .send_cert_verify_error_alert(err)
+        })?;
 
         cx.common.peer_certificates = Some(self.server_cert.cert_chain.into_owned());
         self.transcript.add_message(&m);
@@ -1260,6 +1486,40 @@ fn emit_certverify_tls13(
     Ok(())
 }
 
+fn emit_client_auth_tls13(
+    flight: &mut HandshakeFlightTls13<'_>,
+    client_auth: ClientAuthDetails,
+    config: &ClientConfig,
+) -> Result<(), Error> {
+    match client_auth {
+        ClientAuthDetails::Empty {
+            auth_context_tls13: auth_context,
+        } => {
+            emit_certificate_tls13(flight, None, auth_context);
+        }
+        ClientAuthDetails::Verify {
+            certkey,
+            signer,
+            auth_context_tls13: auth_context,
+            compressor,
+        } => {
+            if let Some(compressor) = compressor {
+                emit_compressed_certificate_tls13(
+                    flight,
+                    &certkey,
+                    auth_context,
+                    compressor,
+                    config,
+                );
+            } else {
+                emit_certificate_tls13(flight, Some(&certkey), auth_context);
+            }
+            emit_certverify_tls13(flight, signer.as_ref())?;
+        }
+    }
+    Ok(())
+}
+
 fn emit_finished_tls13(flight: &mut HandshakeFlightTls13<'_>, verify_data: &crypto::hmac::Tag) {
     let verify_data_payload = Payload::new(verify_data.as_ref());
 
@@ -1346,11 +1606,6 @@ impl State<ClientConnectionData> for ExpectFinished {
          * with our handshake keys. */
         if let Some(client_auth) = st.client_auth {
             match client_auth {
-                ClientAuthDetails::Empty {
-                    auth_context_tls13: auth_context,
-                } => {
-                    emit_certificate_tls13(&mut flight, None, auth_context);
-                }
                 ClientAuthDetails::Verify {
                     auth_context_tls13: auth_context,
                     ..
@@ -1359,25 +1614,7 @@ impl State<ClientConnectionData> for ExpectFinished {
                     // an empty certificate message.
                     emit_certificate_tls13(&mut flight, None, auth_context);
                 }
-                ClientAuthDetails::Verify {
-                    certkey,
-                    signer,
-                    auth_context_tls13: auth_context,
-                    compressor,
-                } => {
-                    if let Some(compressor) = compressor {
-                        emit_compressed_certificate_tls13(
-                            &mut flight,
-                            &certkey,
-                            auth_context,
-                            compressor,
-                            &st.config,
-                        );
-                    } else {
-                        emit_certificate_tls13(&mut flight, Some(&certkey), auth_context);
-                    }
-                    emit_certverify_tls13(&mut flight, signer.as_ref())?;
-                }
+                client_auth => emit_client_auth_tls13(&mut flight, client_auth, &st.config)?,
             }
         }
 
@@ -1410,6 +1647,9 @@ impl State<ClientConnectionData> for ExpectFinished {
         // sending an alert and returning an error (potentially with retry configs) if the server
         // did not accept our ECH offer.
         if cx.data.ech_status == EchStatus::Rejected {
+            cx.data.ech_retry_configs = ech::retry_config_list(&st.ech_retry_configs);
+            cx.data.ech_retry_client_config =
+                ech::retry_client_config(&st.config, &st.ech_retry_configs);
             return Err(ech::fatal_alert_required(st.ech_retry_configs, cx.common));
         }
 
@@ -1464,20 +1704,44 @@ impl ExpectTraffic {
             ));
         }
 
+        let mut quic_params = None;
+        if cx.common.is_quic() {
+            if let Some(sz) = nst.max_early_data_size() {
+                if sz != 0 && sz != 0xffff_ffff {
+                    return Err(PeerMisbehaved::InvalidMaxEarlyDataSize.into());
+                }
+            }
+
+            quic_params = cx.common.quic.params.as_deref();
+        }
+
+        self.store_ticket_tls13(
+            nst,
+            cx.common.peer_certificates.as_ref(),
+            cx.common.alpn_protocol.as_deref(),
+            quic_params,
+        )
+    }
+
+    fn store_ticket_tls13(
+        &mut self,
+        nst: &NewSessionTicketPayloadTls13,
+        peer_certificates: Option<&CertificateChain<'static>>,
+        alpn_protocol: Option<&[u8]>,
+        quic_params: Option<&[u8]>,
+    ) -> Result<(), Error> {
         let handshake_hash = self.transcript.current_hash();
         let secret = ResumptionSecret::new(&self.key_schedule, &handshake_hash)
             .derive_ticket_psk(&nst.nonce.0);
 
         let now = self.config.current_time()?;
 
-        #[allow(unused_mut)]
         let mut value = persist::Tls13ClientSessionValue::new(
             self.suite,
             Arc::clone(&nst.ticket),
             secret.as_ref(),
-            cx.common
-                .peer_certificates
-                .clone()
+            peer_certificates
+                .cloned()
                 .unwrap_or_default(),
             now,
             nst.lifetime,
@@ -1486,16 +1750,9 @@ impl ExpectTraffic {
                 .unwrap_or_default(),
         );
 
-        if cx.common.is_quic() {
-            if let Some(sz) = nst.max_early_data_size() {
-                if sz != 0 && sz != 0xffff_ffff {
-                    return Err(PeerMisbehaved::InvalidMaxEarlyDataSize.into());
-                }
-            }
-
-            if let Some(ref quic_params) = &cx.common.quic.params {
-                value.set_quic_params(quic_params);
-            }
+        value.set_alpn_protocol(alpn_protocol);
+        if let Some(quic_params) = quic_params {
+            value.set_quic_params(quic_params);
         }
 
         self.session_storage
@@ -1503,6 +1760,32 @@ impl ExpectTraffic {
         Ok(())
     }
 
+    fn handle_post_handshake_certificate_request(
+        &mut self,
+        cx: &mut ClientContext<'_>,
+        m: &Message<'_>,
+        certreq: &CertificateRequestPayloadTls13,
+    ) -> Result<(), Error> {
+        debug!("Got post-handshake CertificateRequest {:?}", certreq);
+        cx.common.check_aligned_handshake()?;
+
+        let client_auth = resolve_client_auth(&self.config, certreq, cx.common)?;
+
+        // The transcript for this exchange starts with the handshake, and then
+        // this CertificateRequest: it does not include any other post-handshake messages.
+        let mut transcript = self.transcript.clone();
+        transcript.add_message(m);
+
+        let mut flight = HandshakeFlightTls13::new(&mut transcript);
+        emit_client_auth_tls13(&mut flight, client_auth, &self.config)?;
+        let verify_data = self
+            .key_schedule
+            .sign_post_handshake_client_finish(&flight.transcript.current_hash());
+        emit_finished_tls13(&mut flight, &verify_data);
+        flight.finish(cx.common);
+        Ok(())
+    }
+
     fn handle_key_update(
         &mut self,
         common: &mut CommonState,
@@ -1559,6 +1842,16 @@ impl State<ClientConnectionData> for ExpectTraffic {
                     },
                 ..
             } => self.handle_key_update(cx.common, key_update)?,
+            MessagePayload::Handshake {
+                parsed:
+                    HandshakeMessagePayload {
+                        payload: HandshakePayload::CertificateRequestTls13(ref certreq),
+                        ..
+                    },
+                ..
+            } if self.config.enable_post_handshake_auth => {
+                self.handle_post_handshake_certificate_request(cx, &m, certreq)?
+            }
             payload => {
                 return Err(inappropriate_handshake_message(
                     &payload,
@@ -1591,11 +1884,34 @@ impl State<ClientConnectionData> for ExpectTraffic {
             .extract_secrets(Side::Client)
     }
 
+    fn into_kernel_state(self: Box<Self>) -> Result<Box<dyn KernelState + 'static>, Error> {
+        Ok(self)
+    }
+
     fn into_owned(self: Box<Self>) -> hs::NextState<'static> {
         self
     }
 }
 
+impl KernelState for ExpectTraffic {
+    fn update_secrets(&mut self, direction: Direction) -> Result<ConnectionTrafficSecrets, Error> {
+        self.key_schedule
+            .refresh_traffic_secrets(direction.secret_side(Side::Client))
+    }
+
+    fn handle_new_session_ticket(
+        &mut self,
+        cx: &KernelContext<'_>,
+        ticket: &NewSessionTicketPayloadTls13,
+    ) -> Result<(), Error> {
+        if ticket.has_duplicate_extension() {
+            return Err(PeerMisbehaved::DuplicateNewSessionTicketExtensions.into());
+        }
+
+        self.store_ticket_tls13(ticket, cx.peer_certificates, cx.alpn_protocol, None)
+    }
+}
+
 struct ExpectQuicTraffic(ExpectTraffic);
 
 impl State<ClientConnectionData> for ExpectQuicTraffic {
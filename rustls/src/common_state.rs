@@ -3,6 +3,7 @@ use alloc::vec::Vec;
 
 use pki_types::CertificateDer;
 
+use crate::conn::kernel::KernelState;
 use crate::crypto::SupportedKxGroup;
 use crate::enums::{AlertDescription, ContentType, HandshakeType, ProtocolVersion};
 use crate::error::{Error, InvalidMessage, PeerMisbehaved};
@@ -12,19 +13,25 @@ use crate::msgs::alert::AlertMessagePayload;
 use crate::msgs::base::Payload;
 use crate::msgs::codec::Codec;
 use crate::msgs::enums::{AlertLevel, ExtensionType, KeyUpdateRequest};
-use crate::msgs::fragmenter::MessageFragmenter;
+use crate::msgs::fragmenter::{
+    max_fragment_len_for_record_size_limit, MessageFragmenter, MIN_RECORD_SIZE_LIMIT,
+};
 use crate::msgs::handshake::{CertificateChain, HandshakeMessagePayload};
 use crate::msgs::message::{
     Message, MessagePayload, OutboundChunks, OutboundOpaqueMessage, OutboundPlainMessage,
     PlainMessage,
 };
+use crate::observer::{ConnectionObserver, HandshakeEvent};
 use crate::record_layer::PreEncryptAction;
+use crate::record_padding::RecordPadding;
+use crate::server::{OperationResult, PendingOperation};
 use crate::suites::{PartiallyExtractedSecrets, SupportedCipherSuite};
 #[cfg(feature = "tls12")]
 use crate::tls12::ConnectionSecrets;
+use crate::tls13::key_schedule::KeyScheduleEarlyExporter;
 use crate::unbuffered::{EncryptError, InsufficientSizeError};
 use crate::vecbuf::ChunkVecBuffer;
-use crate::{quic, record_layer, PeerIncompatible};
+use crate::{dtls, quic, record_layer, PeerIncompatible};
 
 /// Connection state common to both client and server connections.
 pub struct CommonState {
@@ -39,13 +46,15 @@ pub struct CommonState {
     pub(crate) may_send_application_data: bool,
     pub(crate) may_receive_application_data: bool,
     pub(crate) early_traffic: bool,
-    sent_fatal_alert: bool,
+    pub(crate) sent_fatal_alert: bool,
     /// If the peer has signaled end of stream.
     pub(crate) has_received_close_notify: bool,
     #[cfg(feature = "std")]
     pub(crate) has_seen_eof: bool,
     pub(crate) peer_certificates: Option<CertificateChain<'static>>,
+    pub(crate) external_psk_identity: Option<Vec<u8>>,
     message_fragmenter: MessageFragmenter,
+    pub(crate) record_padding: RecordPadding,
     pub(crate) received_plaintext: ChunkVecBuffer,
     pub(crate) sendable_tls: ChunkVecBuffer,
     queued_key_update_message: Option<Vec<u8>>,
@@ -53,10 +62,14 @@ pub struct CommonState {
     /// Protocol whose key schedule should be used. Unused for TLS < 1.3.
     pub(crate) protocol: Protocol,
     pub(crate) quic: quic::Quic,
+    pub(crate) dtls: dtls::Dtls,
+    /// The TLS1.3 early exporter, if early data was sent or accepted.
+    pub(crate) early_exporter: Option<KeyScheduleEarlyExporter>,
     pub(crate) enable_secret_extraction: bool,
     temper_counters: TemperCounters,
     pub(crate) refresh_traffic_keys_pending: bool,
     pub(crate) fips: bool,
+    pub(crate) observer: Option<Box<dyn ConnectionObserver>>,
 }
 
 impl CommonState {
@@ -78,16 +91,21 @@ impl CommonState {
             #[cfg(feature = "std")]
             has_seen_eof: false,
             peer_certificates: None,
+            external_psk_identity: None,
             message_fragmenter: MessageFragmenter::default(),
+            record_padding: RecordPadding::None,
             received_plaintext: ChunkVecBuffer::new(Some(DEFAULT_RECEIVED_PLAINTEXT_LIMIT)),
             sendable_tls: ChunkVecBuffer::new(Some(DEFAULT_BUFFER_LIMIT)),
             queued_key_update_message: None,
             protocol: Protocol::Tcp,
             quic: quic::Quic::default(),
+            dtls: dtls::Dtls::default(),
+            early_exporter: None,
             enable_secret_extraction: false,
             temper_counters: TemperCounters::default(),
             refresh_traffic_keys_pending: false,
             fips: false,
+            observer: None,
         }
     }
 
@@ -134,6 +152,17 @@ impl CommonState {
         self.peer_certificates.as_deref()
     }
 
+    /// Retrieves the identity of the external pre-shared key used to authenticate
+    /// the handshake, if any.
+    ///
+    /// This is `None` for handshakes authenticated with certificates, and for
+    /// resumed handshakes.
+    ///
+    /// See [`ExternalPsk`](crate::psk::ExternalPsk).
+    pub fn external_psk_identity(&self) -> Option<&[u8]> {
+        self.external_psk_identity.as_deref()
+    }
+
     /// Retrieves the protocol agreed with the peer via ALPN.
     ///
     /// A return value of `None` after handshake completion
@@ -170,7 +199,10 @@ impl CommonState {
     ///
     /// This returns `None` until the version is agreed.
     pub fn protocol_version(&self) -> Option<ProtocolVersion> {
-        self.negotiated_version
+        match self.negotiated_version {
+            Some(ProtocolVersion::TLSv1_3) => Some(self.protocol.tls13_version()),
+            version => version,
+        }
     }
 
     /// Which kind of handshake was performed.
@@ -194,6 +226,8 @@ impl CommonState {
         data: &mut Data,
         sendable_plaintext: Option<&mut ChunkVecBuffer>,
     ) -> Result<Box<dyn State<Data>>, Error> {
+        self.observe_received(&msg);
+
         // For TLS1.2, outside of the handshake, send rejection alerts for
         // renegotiation requests.  These can occur any time.
         if self.may_receive_application_data && !self.is_tls13() {
@@ -339,7 +373,7 @@ impl CommonState {
     fn send_single_fragment(&mut self, m: OutboundPlainMessage<'_>) {
         if m.typ == ContentType::Alert {
             // Alerts are always sendable -- never quashed by a PreEncryptAction.
-            let em = self.record_layer.encrypt_outgoing(m);
+            let em = self.encrypt_outgoing(m);
             self.queue_tls_message(em);
             return;
         }
@@ -373,10 +407,30 @@ impl CommonState {
             }
         };
 
-        let em = self.record_layer.encrypt_outgoing(m);
+        let em = self.encrypt_outgoing(m);
         self.queue_tls_message(em);
     }
 
+    /// Encrypt `m`, padding it according to our `record_padding` policy.
+    fn encrypt_outgoing(&mut self, m: OutboundPlainMessage<'_>) -> OutboundOpaqueMessage {
+        let padding = match self.record_layer.padding_allowed() {
+            true => {
+                self.record_padding
+                    .padding_len(m.typ, m.payload.len(), self.max_padding_len(&m))
+            }
+            false => 0,
+        };
+        self.record_layer
+            .encrypt_outgoing(m, padding)
+    }
+
+    /// Return the most padding we may add to `m` without exceeding the fragment size limit.
+    fn max_padding_len(&self, m: &OutboundPlainMessage<'_>) -> usize {
+        self.message_fragmenter
+            .max_fragment_len()
+            .saturating_sub(m.payload.len())
+    }
+
     fn send_plain_non_buffering(&mut self, payload: OutboundChunks<'_>, limit: Limit) -> usize {
         debug_assert!(self.may_send_application_data);
         debug_assert!(self.record_layer.is_encrypting());
@@ -408,6 +462,9 @@ impl CommonState {
     pub(crate) fn start_traffic(&mut self, sendable_plaintext: &mut Option<&mut ChunkVecBuffer>) {
         self.may_receive_application_data = true;
         self.start_outgoing_traffic(sendable_plaintext);
+        if let Some(kind) = self.handshake_kind {
+            self.observe(HandshakeEvent::HandshakeComplete { kind });
+        }
     }
 
     /// Send any buffered plaintext.  Plaintext is buffered if
@@ -436,6 +493,7 @@ impl CommonState {
 
     /// Send a raw TLS message, fragmenting it if needed.
     pub(crate) fn send_msg(&mut self, m: Message<'_>, must_encrypt: bool) {
+        self.observe_sent(&m);
         {
             if let Protocol::Quic = self.protocol {
                 if let MessagePayload::Alert(alert) = m.payload {
@@ -456,6 +514,28 @@ impl CommonState {
                 }
                 return;
             }
+
+            if let Protocol::Dtls = self.protocol {
+                // DTLS records are made by the `dtls` layer, which fragments
+                // handshake messages itself and chooses the epoch for each.
+                match m.payload {
+                    MessagePayload::Alert(alert) => self.dtls.alerts.push_back(alert),
+                    payload => {
+                        debug_assert!(
+                            matches!(
+                                payload,
+                                MessagePayload::Handshake { .. }
+                                    | MessagePayload::HandshakeFlight(_)
+                            ),
+                            "DTLS application data is sent by the DTLS connection"
+                        );
+                        let mut bytes = Vec::new();
+                        payload.encode(&mut bytes);
+                        self.dtls.hs_queue.push_back(bytes);
+                    }
+                }
+                return;
+            }
         }
         if !must_encrypt {
             let msg = &m.into();
@@ -470,6 +550,61 @@ impl CommonState {
         }
     }
 
+    /// Tell our observer (if any) about `event`.
+    pub(crate) fn observe(&mut self, event: HandshakeEvent) {
+        if let Some(observer) = &mut self.observer {
+            observer.observe(&event);
+        }
+    }
+
+    /// Tell our observer (if any) about the handshake messages or alert in `m`,
+    /// which we are about to send.
+    pub(crate) fn observe_sent(&mut self, m: &Message<'_>) {
+        let Some(observer) = &mut self.observer else {
+            return;
+        };
+
+        match &m.payload {
+            MessagePayload::Handshake { parsed, encoded } => {
+                observer.observe(&HandshakeEvent::MessageSent {
+                    typ: parsed.typ,
+                    len: encoded.bytes().len(),
+                });
+            }
+            MessagePayload::HandshakeFlight(flight) => {
+                // A flight is a sequence of encoded messages, each with a
+                // one-byte type and three-byte length header.
+                let mut rest = flight.bytes();
+                while let [typ, a, b, c, ..] = *rest {
+                    let len = 4 + u32::from_be_bytes([0, a, b, c]) as usize;
+                    observer.observe(&HandshakeEvent::MessageSent {
+                        typ: HandshakeType::from(typ),
+                        len,
+                    });
+                    rest = rest.get(len..).unwrap_or_default();
+                }
+            }
+            MessagePayload::Alert(alert) => {
+                observer.observe(&HandshakeEvent::AlertSent {
+                    description: alert.description,
+                    fatal: alert.level == AlertLevel::Fatal,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn observe_received(&mut self, m: &Message<'_>) {
+        if let (Some(observer), MessagePayload::Handshake { parsed, encoded }) =
+            (&mut self.observer, &m.payload)
+        {
+            observer.observe(&HandshakeEvent::MessageReceived {
+                typ: parsed.typ,
+                len: encoded.bytes().len(),
+            });
+        }
+    }
+
     pub(crate) fn take_received_plaintext(&mut self, bytes: Payload<'_>) {
         self.received_plaintext
             .append(bytes.into_vec());
@@ -500,6 +635,11 @@ impl CommonState {
     }
 
     pub(crate) fn process_alert(&mut self, alert: &AlertMessagePayload) -> Result<(), Error> {
+        self.observe(HandshakeEvent::AlertReceived {
+            description: alert.description,
+            fatal: alert.level == AlertLevel::Fatal,
+        });
+
         // Reject unknown AlertLevels.
         if let AlertLevel::Unknown(_) = alert.level {
             return Err(self.send_fatal_alert(
@@ -598,7 +738,15 @@ impl CommonState {
         let mut required_size = self.sendable_tls.len();
 
         for m in fragments {
-            required_size += m.encoded_len(&self.record_layer);
+            let padding = match self.record_layer.padding_allowed() {
+                true => self.record_padding.max_padding_len(
+                    m.typ,
+                    m.payload.len(),
+                    self.max_padding_len(&m),
+                ),
+                false => 0,
+            };
+            required_size += m.encoded_len(&self.record_layer, padding);
         }
 
         if required_size > outgoing_tls.len() {
@@ -626,10 +774,7 @@ impl CommonState {
         }
 
         for m in fragments {
-            let em = self
-                .record_layer
-                .encrypt_outgoing(m)
-                .encode();
+            let em = self.encrypt_outgoing(m).encode();
 
             let len = em.len();
             outgoing_tls[written..written + len].copy_from_slice(&em);
@@ -644,6 +789,36 @@ impl CommonState {
             .set_max_fragment_size(new)
     }
 
+    /// Restrict the records we send according to the peer's `record_size_limit`.
+    ///
+    /// The protocol version must have been negotiated.
+    pub(crate) fn set_peer_record_size_limit(&mut self, limit: u16) -> Result<(), Error> {
+        if limit < MIN_RECORD_SIZE_LIMIT {
+            return Err(self.send_fatal_alert(
+                AlertDescription::IllegalParameter,
+                PeerMisbehaved::InvalidRecordSizeLimit,
+            ));
+        }
+
+        let version = self
+            .negotiated_version
+            .unwrap_or(ProtocolVersion::TLSv1_2);
+        self.message_fragmenter
+            .limit_fragment_len(max_fragment_len_for_record_size_limit(limit, version));
+        Ok(())
+    }
+
+    /// Start rejecting received records larger than our own `record_size_limit`.
+    ///
+    /// The protocol version must have been negotiated.
+    pub(crate) fn enforce_record_size_limit(&mut self, limit: u16) {
+        let version = self
+            .negotiated_version
+            .unwrap_or(ProtocolVersion::TLSv1_2);
+        self.record_layer
+            .set_max_plaintext_len(max_fragment_len_for_record_size_limit(limit, version));
+    }
+
     pub(crate) fn get_alpn_protocol(&self) -> Option<&[u8]> {
         self.alpn_protocol
             .as_ref()
@@ -683,6 +858,10 @@ impl CommonState {
         self.protocol == Protocol::Quic
     }
 
+    pub(crate) fn is_dtls(&self) -> bool {
+        self.protocol == Protocol::Dtls
+    }
+
     pub(crate) fn should_update_key(
         &mut self,
         key_update_request: &KeyUpdateRequest,
@@ -701,10 +880,11 @@ impl CommonState {
     }
 
     pub(crate) fn enqueue_key_update_notification(&mut self) {
-        let message = PlainMessage::from(Message::build_key_update_notify());
+        let message = Message::build_key_update_notify();
+        self.observe_sent(&message);
+        let message = PlainMessage::from(message);
         self.queued_key_update_message = Some(
-            self.record_layer
-                .encrypt_outgoing(message.borrow_outbound())
+            self.encrypt_outgoing(message.borrow_outbound())
                 .encode(),
         );
     }
@@ -853,12 +1033,45 @@ pub(crate) trait State<Data>: Send + Sync {
         Err(Error::HandshakeNotComplete)
     }
 
+    /// Keep the parts of this state needed by a `KernelConnection`.
+    fn into_kernel_state(self: Box<Self>) -> Result<Box<dyn KernelState + 'static>, Error> {
+        Err(Error::HandshakeNotComplete)
+    }
+
     fn send_key_update_request(&mut self, _common: &mut CommonState) -> Result<(), Error> {
         Err(Error::HandshakeNotComplete)
     }
 
+    /// Send a post-handshake `CertificateRequest`.
+    fn request_client_certificate(&mut self, _common: &mut CommonState) -> Result<(), Error> {
+        Err(Error::HandshakeNotComplete)
+    }
+
+    /// Whether a post-handshake `CertificateRequest` awaits a complete response.
+    fn client_certificate_requested(&self) -> bool {
+        false
+    }
+
     fn handle_decrypt_error(&self) {}
 
+    /// The operation this state is waiting on the application to complete, if any.
+    ///
+    /// No messages are passed to a state which returns `Some` here.
+    fn pending_operation(&self) -> Option<PendingOperation<'_>> {
+        None
+    }
+
+    /// Continue the handshake with the outcome of `pending_operation()`.
+    ///
+    /// Only called if `pending_operation()` returned an operation that `result` completes.
+    fn complete_operation(
+        self: Box<Self>,
+        _cx: &mut Context<'_, Data>,
+        _result: OperationResult,
+    ) -> Result<Box<dyn State<Data>>, Error> {
+        Err(Error::General("unreachable state".into()))
+    }
+
     fn into_owned(self: Box<Self>) -> Box<dyn State<Data> + 'static>;
 }
 
@@ -892,6 +1105,25 @@ impl Side {
 pub(crate) enum Protocol {
     Tcp,
     Quic,
+    Dtls,
+}
+
+impl Protocol {
+    /// The version number that means TLS1.3 in this protocol.
+    pub(crate) fn tls13_version(self) -> ProtocolVersion {
+        match self {
+            Self::Tcp | Self::Quic => ProtocolVersion::TLSv1_3,
+            Self::Dtls => ProtocolVersion::DTLSv1_3,
+        }
+    }
+
+    /// The `legacy_version` of TLS1.3 `ClientHello` and `ServerHello` messages.
+    pub(crate) fn legacy_version(self) -> ProtocolVersion {
+        match self {
+            Self::Tcp | Self::Quic => ProtocolVersion::TLSv1_2,
+            Self::Dtls => ProtocolVersion::DTLSv1_2,
+        }
+    }
 }
 
 enum Limit {
@@ -1034,6 +1266,18 @@ impl<'a, const TLS13: bool> HandshakeFlight<'a, TLS13> {
         }
     }
 
+    /// Continues a flight previously suspended with [`Self::into_body()`].
+    pub(crate) fn resume(transcript: &'a mut HandshakeHash, body: Vec<u8>) -> Self {
+        Self { transcript, body }
+    }
+
+    /// Suspends this flight, returning the encoding of the messages added so far.
+    ///
+    /// These are already included in the transcript.
+    pub(crate) fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+
     pub(crate) fn add(&mut self, hs: HandshakeMessagePayload<'_>) {
         let start_len = self.body.len();
         hs.encode(&mut self.body);
@@ -7,11 +7,18 @@
 //!
 //! # Getting started
 //!
-//! Build this crate with the `brotli` and/or `zlib` crate features.  This
+//! Build this crate with the `brotli`, `zlib` and/or `zstd` crate features.  This
 //! adds dependencies on these crates.  They are used by default if enabled.
+//! Unlike the others, the `zstd` crate is a binding to the C implementation
+//! of Zstandard, so needs a C compiler to build.
 //!
 //! We especially recommend `brotli` as it has the widest deployment so far.
 //!
+//! With the `zstd` feature, `ZstdDictionary` allows compression using a
+//! dictionary agreed in advance with peers, such as one trained on (or made
+//! from) commonly-used intermediate certificates.  rustls does not ship such
+//! a dictionary.
+//!
 //! # Custom compression/decompression implementations
 //!
 //! 1. Implement the [`CertCompressor`] and/or [`CertDecompressor`] traits
@@ -53,6 +60,8 @@ pub fn default_cert_decompressors() -> &'static [&'static dyn CertDecompressor]
         BROTLI_DECOMPRESSOR,
         #[cfg(feature = "zlib")]
         ZLIB_DECOMPRESSOR,
+        #[cfg(feature = "zstd")]
+        ZSTD_DECOMPRESSOR,
     ]
 }
 
@@ -78,6 +87,8 @@ pub fn default_cert_compressors() -> &'static [&'static dyn CertCompressor] {
         BROTLI_COMPRESSOR,
         #[cfg(feature = "zlib")]
         ZLIB_COMPRESSOR,
+        #[cfg(feature = "zstd")]
+        ZSTD_COMPRESSOR,
     ]
 }
 
@@ -266,6 +277,207 @@ mod feat_brotli {
 #[cfg(feature = "brotli")]
 pub use feat_brotli::{BROTLI_COMPRESSOR, BROTLI_DECOMPRESSOR};
 
+#[cfg(feature = "zstd")]
+mod feat_zstd {
+    use pki_types::CertificateDer;
+    use zstd::bulk::{Compressor, Decompressor};
+    use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+    use super::*;
+    use crate::error::Error;
+
+    /// A certificate decompressor for the Zstandard algorithm using the `zstd` crate.
+    pub const ZSTD_DECOMPRESSOR: &dyn CertDecompressor = &ZstdDecompressor;
+
+    #[derive(Debug)]
+    struct ZstdDecompressor;
+
+    impl CertDecompressor for ZstdDecompressor {
+        fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressionFailed> {
+            let decompressor = Decompressor::new().map_err(|_| DecompressionFailed)?;
+            decompress_exactly(decompressor, input, output)
+        }
+
+        fn algorithm(&self) -> CertificateCompressionAlgorithm {
+            CertificateCompressionAlgorithm::Zstd
+        }
+    }
+
+    /// A certificate compressor for the Zstandard algorithm using the `zstd` crate.
+    pub const ZSTD_COMPRESSOR: &dyn CertCompressor = &ZstdCompressor;
+
+    #[derive(Debug)]
+    struct ZstdCompressor;
+
+    impl CertCompressor for ZstdCompressor {
+        fn compress(
+            &self,
+            input: Vec<u8>,
+            level: CompressionLevel,
+        ) -> Result<Vec<u8>, CompressionFailed> {
+            let level = match level {
+                CompressionLevel::Interactive => LEVEL_FAST,
+                CompressionLevel::Amortized => LEVEL_SLOW,
+            };
+            zstd::bulk::compress(&input, level).map_err(|_| CompressionFailed)
+        }
+
+        fn algorithm(&self) -> CertificateCompressionAlgorithm {
+            CertificateCompressionAlgorithm::Zstd
+        }
+    }
+
+    /// Zstandard certificate compression and decompression, using a dictionary.
+    ///
+    /// A dictionary improves compression of small inputs like certificate chains,
+    /// by providing content they are likely to share -- for example, the common
+    /// intermediate certificates of the WebPKI.  rustls does not provide a
+    /// dictionary: train one, or make one from the intermediates your peers use
+    /// with [`Self::from_certificates()`].
+    ///
+    /// Both peers must use the same dictionary.  RFC8879 has no way to negotiate
+    /// one, so each dictionary is given its own algorithm codepoint from the range
+    /// reserved for experimental use (16384 to 65535), agreed in advance with
+    /// peers.  Peers without the dictionary do not offer its codepoint, so
+    /// never receive certificates compressed with it.
+    ///
+    /// This type is both a [`CertCompressor`] and a [`CertDecompressor`].  To use it
+    /// in a config, it must have a `'static` lifetime: put it in a `static`, or use
+    /// [`Box::leak()`](alloc::boxed::Box::leak).
+    ///
+    /// ```no_run
+    /// # use rustls::compress::ZstdDictionary;
+    /// # use rustls::CertificateCompressionAlgorithm;
+    /// # let mut config: rustls::ServerConfig = unimplemented!();
+    /// let trained = std::fs::read("certificates.dict").unwrap();
+    /// let algorithm = CertificateCompressionAlgorithm::from(0xff01);
+    /// let dictionary = ZstdDictionary::new(algorithm, &trained).unwrap();
+    /// let dictionary: &'static ZstdDictionary = Box::leak(Box::new(dictionary));
+    /// config.cert_compressors.insert(0, dictionary);
+    /// config.cert_decompressors.insert(0, dictionary);
+    /// ```
+    pub struct ZstdDictionary {
+        algorithm: CertificateCompressionAlgorithm,
+        fast: EncoderDictionary<'static>,
+        slow: EncoderDictionary<'static>,
+        decoder: DecoderDictionary<'static>,
+    }
+
+    impl ZstdDictionary {
+        /// Make a dictionary from `dictionary`, identified by `algorithm`.
+        ///
+        /// This can be a dictionary trained using `zstd --train` on a corpus of
+        /// certificate messages, or any content the certificates are likely to share
+        /// (a "raw content" dictionary).
+        ///
+        /// This fails if `algorithm` is not in the range reserved for experimental use.
+        pub fn new(
+            algorithm: CertificateCompressionAlgorithm,
+            dictionary: &[u8],
+        ) -> Result<Self, Error> {
+            if u16::from(algorithm) < FIRST_EXPERIMENTAL_ALGORITHM {
+                return Err(Error::General(
+                    "zstd dictionaries need an experimental-use algorithm codepoint".into(),
+                ));
+            }
+
+            Ok(Self {
+                algorithm,
+                fast: EncoderDictionary::copy(dictionary, LEVEL_FAST),
+                slow: EncoderDictionary::copy(dictionary, LEVEL_SLOW),
+                decoder: DecoderDictionary::copy(dictionary),
+            })
+        }
+
+        /// Make a raw content dictionary from `certificates`, identified by `algorithm`.
+        ///
+        /// This is useful when peers' certificate chains are likely to include
+        /// some of `certificates`, such as commonly-used intermediates.  Certificates
+        /// which are more likely to be used should come last.
+        pub fn from_certificates<'a>(
+            algorithm: CertificateCompressionAlgorithm,
+            certificates: impl IntoIterator<Item = &'a CertificateDer<'a>>,
+        ) -> Result<Self, Error> {
+            let content = certificates
+                .into_iter()
+                .flat_map(|cert| cert.as_ref())
+                .copied()
+                .collect::<Vec<u8>>();
+            Self::new(algorithm, &content)
+        }
+    }
+
+    impl CertCompressor for ZstdDictionary {
+        fn compress(
+            &self,
+            input: Vec<u8>,
+            level: CompressionLevel,
+        ) -> Result<Vec<u8>, CompressionFailed> {
+            let dictionary = match level {
+                CompressionLevel::Interactive => &self.fast,
+                CompressionLevel::Amortized => &self.slow,
+            };
+            Compressor::with_prepared_dictionary(dictionary)
+                .and_then(|mut compressor| compressor.compress(&input))
+                .map_err(|_| CompressionFailed)
+        }
+
+        fn algorithm(&self) -> CertificateCompressionAlgorithm {
+            self.algorithm
+        }
+    }
+
+    impl CertDecompressor for ZstdDictionary {
+        fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressionFailed> {
+            let decompressor = Decompressor::with_prepared_dictionary(&self.decoder)
+                .map_err(|_| DecompressionFailed)?;
+            decompress_exactly(decompressor, input, output)
+        }
+
+        fn algorithm(&self) -> CertificateCompressionAlgorithm {
+            self.algorithm
+        }
+    }
+
+    impl Debug for ZstdDictionary {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            f.debug_struct("ZstdDictionary")
+                .field("algorithm", &self.algorithm)
+                .finish_non_exhaustive()
+        }
+    }
+
+    /// Decompress `input` into `output`, which must be filled exactly.
+    ///
+    /// `output` bounds the decompression: input which would decompress to more
+    /// than this is rejected without further work.
+    fn decompress_exactly(
+        mut decompressor: Decompressor<'_>,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(), DecompressionFailed> {
+        match decompressor.decompress_to_buffer(input, output) {
+            Ok(used) if used == output.len() => Ok(()),
+            _ => Err(DecompressionFailed),
+        }
+    }
+
+    /// The start of the range of algorithm codepoints reserved for experimental use.
+    ///
+    /// <https://www.rfc-editor.org/rfc/rfc8879.html#section-7.3>
+    const FIRST_EXPERIMENTAL_ALGORITHM: u16 = 16384;
+
+    /// Compression level we use for interactive compressions (the zstd default).
+    const LEVEL_FAST: i32 = 3;
+
+    /// Compression level we use for offline compressions (the maximum which
+    /// does not need extra memory to decompress).
+    const LEVEL_SLOW: i32 = 19;
+}
+
+#[cfg(feature = "zstd")]
+pub use feat_zstd::{ZstdDictionary, ZSTD_COMPRESSOR, ZSTD_DECOMPRESSOR};
+
 /// An LRU cache for compressions.
 ///
 /// The prospect of being able to reuse a given compression for many connections
@@ -444,7 +656,7 @@ impl CompressionCacheEntry {
     }
 }
 
-#[cfg(all(test, any(feature = "brotli", feature = "zlib")))]
+#[cfg(all(test, any(feature = "brotli", feature = "zlib", feature = "zstd")))]
 mod tests {
     use std::{println, vec};
 
@@ -462,6 +674,76 @@ mod tests {
         test_compressor(BROTLI_COMPRESSOR, BROTLI_DECOMPRESSOR);
     }
 
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_zstd() {
+        test_compressor(ZSTD_COMPRESSOR, ZSTD_DECOMPRESSOR);
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_zstd_dictionary() {
+        use pki_types::CertificateDer;
+
+        let intermediate = CertificateDer::from(
+            (0..=255u8)
+                .cycle()
+                .take(1500)
+                .collect::<Vec<_>>(),
+        );
+        let algorithm = CertificateCompressionAlgorithm::from(0xff01);
+        let dictionary = ZstdDictionary::from_certificates(algorithm, [&intermediate]).unwrap();
+        assert_eq!(CertCompressor::algorithm(&dictionary), algorithm);
+        test_compressor(&dictionary, &dictionary);
+
+        // standard codepoints are not allowed
+        for algorithm in [
+            CertificateCompressionAlgorithm::Zstd,
+            CertificateCompressionAlgorithm::from(16383),
+        ] {
+            assert!(ZstdDictionary::new(algorithm, b"dictionary").is_err());
+        }
+
+        // a chain sharing the dictionary's content compresses better with it
+        let chain = [&[0x30, 0x82, 0x05, 0xdc][..], intermediate.as_ref()].concat();
+        let with = dictionary
+            .compress(chain.clone(), CompressionLevel::Interactive)
+            .unwrap();
+        let without = ZSTD_COMPRESSOR
+            .compress(chain.clone(), CompressionLevel::Interactive)
+            .unwrap();
+        assert!(with.len() < without.len());
+
+        let mut recovered = vec![0u8; chain.len()];
+        dictionary
+            .decompress(&with, &mut recovered)
+            .unwrap();
+        assert_eq!(chain, recovered);
+
+        // peers must agree on the dictionary
+        ZSTD_DECOMPRESSOR
+            .decompress(&with, &mut recovered)
+            .unwrap_err();
+        let other = ZstdDictionary::new(algorithm, b"some other dictionary content").unwrap();
+        other
+            .decompress(&with, &mut recovered)
+            .unwrap_err();
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_zstd_decompression_is_bounded() {
+        let compressed = ZSTD_COMPRESSOR
+            .compress(vec![0u8; 1 << 20], CompressionLevel::Interactive)
+            .unwrap();
+        assert!(compressed.len() < 1024);
+
+        let mut recovered = vec![0u8; 1024];
+        ZSTD_DECOMPRESSOR
+            .decompress(&compressed, &mut recovered)
+            .unwrap_err();
+    }
+
     fn test_compressor(comp: &dyn CertCompressor, decomp: &dyn CertDecompressor) {
         assert_eq!(comp.algorithm(), decomp.algorithm());
         for sz in [16, 64, 512, 2048, 8192, 16384] {
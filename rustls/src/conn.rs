@@ -6,8 +6,9 @@ use core::ops::{Deref, DerefMut, Range};
 use std::io;
 
 use crate::common_state::{CommonState, Context, IoState, State, DEFAULT_BUFFER_LIMIT};
+use crate::conn::kernel::KernelConnection;
 use crate::enums::{AlertDescription, ContentType, ProtocolVersion};
-use crate::error::{Error, PeerMisbehaved};
+use crate::error::{Error, PeerIncompatible, PeerMisbehaved};
 use crate::log::trace;
 use crate::msgs::deframer::buffers::{BufferProgress, DeframerVecBuffer, Delocator, Locator};
 use crate::msgs::deframer::handshake::HandshakeDeframer;
@@ -15,9 +16,11 @@ use crate::msgs::deframer::DeframerIter;
 use crate::msgs::handshake::Random;
 use crate::msgs::message::{InboundPlainMessage, Message, MessagePayload};
 use crate::record_layer::Decrypted;
+use crate::server::{OperationResult, PendingOperation};
 use crate::suites::{ExtractedSecrets, PartiallyExtractedSecrets};
 use crate::vecbuf::ChunkVecBuffer;
 
+pub(crate) mod kernel;
 pub(crate) mod unbuffered;
 
 #[cfg(feature = "std")]
@@ -28,6 +31,7 @@ mod connection {
     use std::io;
 
     use crate::common_state::{CommonState, IoState};
+    use crate::conn::kernel::KernelConnection;
     use crate::error::Error;
     use crate::msgs::message::OutboundChunks;
     use crate::suites::ExtractedSecrets;
@@ -102,6 +106,21 @@ mod connection {
             }
         }
 
+        /// Derives key material from the TLS1.3 early exporter secret.
+        ///
+        /// See [`ConnectionCommon::export_early_keying_material()`] for more information.
+        pub fn export_early_keying_material<T: AsMut<[u8]>>(
+            &self,
+            output: T,
+            label: &[u8],
+            context: Option<&[u8]>,
+        ) -> Result<T, Error> {
+            match self {
+                Self::Client(conn) => conn.export_early_keying_material(output, label, context),
+                Self::Server(conn) => conn.export_early_keying_material(output, label, context),
+            }
+        }
+
         /// This function uses `io` to complete any outstanding IO for this connection.
         ///
         /// See [`ConnectionCommon::complete_io()`] for more information.
@@ -125,6 +144,18 @@ mod connection {
             }
         }
 
+        /// Extract secrets, and continue the connection as a [`KernelConnection`].
+        ///
+        /// See [`ConnectionCommon::dangerous_into_kernel_connection()`] for more information.
+        pub fn dangerous_into_kernel_connection(
+            self,
+        ) -> Result<(ExtractedSecrets, KernelConnection), Error> {
+            match self {
+                Self::Client(client) => client.dangerous_into_kernel_connection(),
+                Self::Server(server) => server.dangerous_into_kernel_connection(),
+            }
+        }
+
         /// Sets a limit on the internal buffers
         ///
         /// See [`ConnectionCommon::set_buffer_limit()`] for more information.
@@ -409,7 +440,7 @@ impl<Data> ConnectionCommon<Data> {
     /// See RFC5705 for more details on what this does and is for.
     ///
     /// For TLS1.3 connections, this function does not use the
-    /// "early" exporter at any point.
+    /// "early" exporter at any point: see [`Self::export_early_keying_material()`].
     ///
     /// This function fails if called prior to the handshake completing;
     /// check with [`CommonState::is_handshaking`] first.
@@ -426,6 +457,35 @@ impl<Data> ConnectionCommon<Data> {
             .export_keying_material(output, label, context)
     }
 
+    /// Derives key material from the TLS1.3 early exporter secret.
+    ///
+    /// This works like [`Self::export_keying_material()`], but uses the
+    /// `early_exporter_master_secret` described in [RFC 8446 section 7.5].  This
+    /// allows early data to be bound to properties of the connection.
+    ///
+    /// The early exporter is available on a client once it has started sending
+    /// early data, and on a server once it has accepted early data.  It is
+    /// discarded if the server rejects early data.  It remains available after
+    /// the handshake completes.
+    ///
+    /// Note that the early exporter offers weaker guarantees than the main
+    /// exporter: its output is not forward secret, and early data may be replayed.
+    ///
+    /// This function fails if the early exporter is not available, or if
+    /// `output.len()` is zero.
+    ///
+    /// [RFC 8446 section 7.5]: https://www.rfc-editor.org/rfc/rfc8446#section-7.5
+    #[inline]
+    pub fn export_early_keying_material<T: AsMut<[u8]>>(
+        &self,
+        output: T,
+        label: &[u8],
+        context: Option<&[u8]>,
+    ) -> Result<T, Error> {
+        self.core
+            .export_early_keying_material(output, label, context)
+    }
+
     /// Extract secrets, so they can be used when configuring kTLS, for example.
     /// Should be used with care as it exposes secret key material.
     pub fn dangerous_extract_secrets(self) -> Result<ExtractedSecrets, Error> {
@@ -443,6 +503,61 @@ impl<Data> ConnectionCommon<Data> {
         })
     }
 
+    /// Extract secrets, and continue the connection as a [`KernelConnection`].
+    ///
+    /// This is like [`Self::dangerous_extract_secrets()`], but the returned
+    /// [`KernelConnection`] can then process the `KeyUpdate`, `NewSessionTicket`
+    /// and alert records received by the new record layer (for example, kTLS).
+    ///
+    /// This fails if there are TLS records waiting to be written: call
+    /// [`Self::write_tls()`] until [`CommonState::wants_write()`] is false first.
+    ///
+    /// It also fails if any received data has not been consumed, as that data
+    /// would otherwise be lost.  Call [`Self::process_new_packets()`] after each
+    /// [`Self::read_tls()`], and read all the received plaintext first.  If only
+    /// part of a record has been received, read the rest before calling this.
+    ///
+    /// Should be used with care as it exposes secret key material.
+    ///
+    /// [`KernelConnection`]: crate::kernel::KernelConnection
+    /// [`CommonState::wants_write()`]: crate::CommonState::wants_write
+    pub fn dangerous_into_kernel_connection(
+        self,
+    ) -> Result<(ExtractedSecrets, KernelConnection), Error> {
+        if !self.enable_secret_extraction {
+            return Err(Error::General("Secret extraction is disabled".into()));
+        }
+
+        if !self.sendable_tls.is_empty() {
+            return Err(Error::General(
+                "TLS records are waiting to be written".into(),
+            ));
+        }
+
+        if !self.deframer_buffer.filled().is_empty() || self.core.hs_deframer.is_active() {
+            return Err(Error::General(
+                "received TLS records have not been processed".into(),
+            ));
+        }
+
+        if !self.received_plaintext.is_empty() {
+            return Err(Error::General(
+                "received plaintext has not been read".into(),
+            ));
+        }
+
+        let st = self.core.state?;
+
+        let common = self.core.common_state;
+        let PartiallyExtractedSecrets { tx, rx } = st.extract_secrets()?;
+        let secrets = ExtractedSecrets {
+            tx: (common.record_layer.write_seq(), tx),
+            rx: (common.record_layer.read_seq(), rx),
+        };
+        let kernel = KernelConnection::new(st.into_kernel_state()?, &common)?;
+        Ok((secrets, kernel))
+    }
+
     /// Sets a limit on the internal buffers used to buffer
     /// unsent plaintext (prior to completing the TLS handshake)
     /// and unsent TLS records.  This limit acts only on application
@@ -556,6 +671,9 @@ impl<Data> ConnectionCommon<Data> {
     /// - Otherwise, if [`wants_read`] is true, [`read_tls`] is invoked
     ///   once.
     ///
+    /// In all cases, this returns early if the handshake is waiting on the
+    /// application to complete an operation: see [`ServerConnection::pending_operation()`].
+    ///
     /// The return value is the number of bytes read from and written
     /// to `io`, respectively.
     ///
@@ -570,6 +688,7 @@ impl<Data> ConnectionCommon<Data> {
     /// [`write_tls`]: ConnectionCommon::write_tls
     /// [`read_tls`]: ConnectionCommon::read_tls
     /// [`process_new_packets`]: ConnectionCommon::process_new_packets
+    /// [`ServerConnection::pending_operation()`]: crate::server::ServerConnection::pending_operation
     pub fn complete_io<T>(&mut self, io: &mut T) -> Result<(usize, usize), io::Error>
     where
         Self: Sized,
@@ -598,6 +717,11 @@ impl<Data> ConnectionCommon<Data> {
             }
             io.flush()?;
 
+            if self.core.pending_operation().is_some() {
+                // No progress is possible until the application completes this.
+                return Ok((rdlen, wrlen));
+            }
+
             if !until_handshaked && wrlen > 0 {
                 return Ok((rdlen, wrlen));
             }
@@ -676,6 +800,14 @@ impl<Data> ConnectionCommon<Data> {
         }
     }
 
+    pub(crate) fn complete_pending_operation(
+        &mut self,
+        result: OperationResult,
+    ) -> Result<(), Error> {
+        self.core
+            .complete_pending_operation(result, Some(&mut self.sendable_plaintext))
+    }
+
     pub(crate) fn replace_state(&mut self, new: Box<dyn State<Data>>) {
         self.core.state = Ok(new);
     }
@@ -830,6 +962,11 @@ impl<Data> ConnectionCore<Data> {
         let mut buffer_progress = self.hs_deframer.progress();
 
         loop {
+            if state.pending_operation().is_some() {
+                // Received data is left in place until the operation is completed.
+                break;
+            }
+
             let res = self.deframe(
                 Some(&*state),
                 deframer_buffer.filled_mut(),
@@ -878,7 +1015,7 @@ impl<Data> ConnectionCore<Data> {
     }
 
     /// Pull a message out of the deframer and send any messages that need to be sent as a result.
-    fn deframe<'b>(
+    pub(crate) fn deframe<'b>(
         &mut self,
         state: Option<&dyn State<Data>>,
         buffer: &'b mut [u8],
@@ -1141,6 +1278,75 @@ impl<Data> ConnectionCore<Data> {
         }
     }
 
+    pub(crate) fn export_early_keying_material<T: AsMut<[u8]>>(
+        &self,
+        mut output: T,
+        label: &[u8],
+        context: Option<&[u8]>,
+    ) -> Result<T, Error> {
+        if output.as_mut().is_empty() {
+            return Err(Error::General(
+                "export_early_keying_material with zero-length output".into(),
+            ));
+        }
+
+        if let Err(e) = self.state.as_ref() {
+            return Err(e.clone());
+        }
+
+        match self
+            .common_state
+            .early_exporter
+            .as_ref()
+        {
+            Some(exporter) => exporter
+                .export_keying_material(output.as_mut(), label, context)
+                .map(|_| output),
+            None => Err(Error::General("early exporter not available".into())),
+        }
+    }
+
+    pub(crate) fn pending_operation(&self) -> Option<PendingOperation<'_>> {
+        self.state
+            .as_ref()
+            .ok()?
+            .pending_operation()
+    }
+
+    pub(crate) fn complete_pending_operation(
+        &mut self,
+        result: OperationResult,
+        sendable_plaintext: Option<&mut ChunkVecBuffer>,
+    ) -> Result<(), Error> {
+        match self.pending_operation() {
+            Some(operation) if result.completes(&operation) => {}
+            Some(_) => {
+                return Err(Error::General(
+                    "result does not match pending operation".into(),
+                ))
+            }
+            None => return Err(Error::General("no operation pending".into())),
+        }
+
+        let state = mem::replace(&mut self.state, Err(Error::HandshakeNotComplete))?;
+        let mut cx = Context {
+            common: &mut self.common_state,
+            data: &mut self.data,
+            sendable_plaintext,
+        };
+
+        match state.complete_operation(&mut cx, result) {
+            Ok(new) => {
+                self.state = Ok(new);
+                Ok(())
+            }
+            Err(e) => {
+                self.state = Err(e.clone());
+                Err(e)
+            }
+        }
+    }
+
     /// Trigger a `refresh_traffic_keys` if required by `CommonState`.
     fn maybe_refresh_traffic_keys(&mut self) {
         if mem::take(
@@ -1158,6 +1364,26 @@ impl<Data> ConnectionCore<Data> {
             Err(e) => Err(e.clone()),
         }
     }
+
+    pub(crate) fn request_client_certificate(&mut self) -> Result<(), Error> {
+        if self.common_state.is_handshaking() {
+            return Err(Error::HandshakeNotComplete);
+        }
+        if self.common_state.negotiated_version != Some(ProtocolVersion::TLSv1_3) {
+            return Err(PeerIncompatible::PostHandshakeAuthNotOffered.into());
+        }
+
+        match &mut self.state {
+            Ok(st) => st.request_client_certificate(&mut self.common_state),
+            Err(e) => Err(e.clone()),
+        }
+    }
+
+    pub(crate) fn client_certificate_requested(&self) -> bool {
+        self.state
+            .as_ref()
+            .is_ok_and(|st| st.client_certificate_requested())
+    }
 }
 
 /// Data specific to the peer's side (client or server).
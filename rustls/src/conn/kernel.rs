@@ -0,0 +1,345 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::common_state::{CommonState, Side};
+use crate::enums::{AlertDescription, ContentType, HandshakeType, ProtocolVersion};
+use crate::error::{Error, InvalidMessage, PeerMisbehaved};
+use crate::msgs::alert::AlertMessagePayload;
+use crate::msgs::codec::{Codec, Reader};
+use crate::msgs::enums::{AlertLevel, KeyUpdateRequest};
+use crate::msgs::handshake::{
+    CertificateChain, HandshakeMessagePayload, HandshakePayload, NewSessionTicketPayloadTls13,
+};
+use crate::suites::{ConnectionTrafficSecrets, SupportedCipherSuite};
+
+/// A connection whose record layer is handled elsewhere, most likely by the
+/// operating system kernel (for example, Linux's kTLS).
+///
+/// This is obtained from `dangerous_into_kernel_connection()` on a connection
+/// whose handshake is complete, along with the [`ExtractedSecrets`] for the
+/// record layer.  It processes the non-application-data records the record layer
+/// receives, and tells the application when the traffic secrets change.
+///
+/// For TLS1.3 this handles `KeyUpdate` and (for clients) `NewSessionTicket`
+/// messages; for both versions it handles alerts.
+///
+/// [`ExtractedSecrets`]: crate::ExtractedSecrets
+pub struct KernelConnection {
+    state: Box<dyn KernelState>,
+    side: Side,
+    version: ProtocolVersion,
+    suite: SupportedCipherSuite,
+    peer_certificates: Option<CertificateChain<'static>>,
+    alpn_protocol: Option<Vec<u8>>,
+    /// Received handshake data not yet making up a whole message.
+    handshake: Vec<u8>,
+    peer_closed: bool,
+}
+
+impl KernelConnection {
+    pub(crate) fn new(state: Box<dyn KernelState>, common: &CommonState) -> Result<Self, Error> {
+        let (Some(version), Some(suite)) = (common.negotiated_version, common.suite) else {
+            return Err(Error::HandshakeNotComplete);
+        };
+
+        Ok(Self {
+            state,
+            side: common.side,
+            version,
+            suite,
+            peer_certificates: common.peer_certificates.clone(),
+            alpn_protocol: common.alpn_protocol.clone(),
+            handshake: Vec::new(),
+            peer_closed: false,
+        })
+    }
+
+    /// The negotiated protocol version.
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /// The negotiated cipher suite.
+    pub fn negotiated_cipher_suite(&self) -> SupportedCipherSuite {
+        self.suite
+    }
+
+    /// Whether the peer has sent a `close_notify` alert.
+    pub fn peer_has_closed(&self) -> bool {
+        self.peer_closed
+    }
+
+    /// Start updating our transmit keys, optionally asking the peer to update
+    /// theirs too.
+    ///
+    /// This is only possible for TLS1.3.  The returned
+    /// [`KernelAction::SendKeyUpdate`] must be carried out before sending any
+    /// more data.  If `request_peer_update` is true, the peer's `KeyUpdate` later
+    /// arrives through [`Self::handle_record()`].
+    pub fn update_keys(&mut self, request_peer_update: bool) -> Result<KernelAction, Error> {
+        if self.version != ProtocolVersion::TLSv1_3 {
+            return Err(Error::General(
+                "key updates are only possible in TLS1.3".into(),
+            ));
+        }
+
+        let request = match request_peer_update {
+            true => KeyUpdateRequest::UpdateRequested,
+            false => KeyUpdateRequest::UpdateNotRequested,
+        };
+        self.key_update(request)
+    }
+
+    /// Process a record of type `typ`, with decrypted contents `payload`.
+    ///
+    /// This must be given every record received, in order, other than
+    /// those of type [`ContentType::ApplicationData`].
+    ///
+    /// The returned actions must be carried out in order.  Errors are fatal
+    /// to the connection: the record layer should send a fatal alert and close.
+    pub fn handle_record(
+        &mut self,
+        typ: ContentType,
+        payload: &[u8],
+    ) -> Result<Vec<KernelAction>, Error> {
+        match typ {
+            ContentType::Alert if self.handshake.is_empty() => self.handle_alert(payload),
+            ContentType::Handshake => self.handle_handshake(payload),
+            // Alerts must not be interleaved with handshake messages.
+            ContentType::Alert => {
+                Err(PeerMisbehaved::MessageInterleavedWithHandshakeMessage.into())
+            }
+            _ => Err(Error::InappropriateMessage {
+                expect_types: vec![ContentType::Alert, ContentType::Handshake],
+                got_type: typ,
+            }),
+        }
+    }
+
+    fn handle_alert(&mut self, payload: &[u8]) -> Result<Vec<KernelAction>, Error> {
+        let alert = AlertMessagePayload::read_bytes(payload)?;
+        if let AlertLevel::Unknown(_) = alert.level {
+            return Err(Error::AlertReceived(alert.description));
+        }
+
+        if alert.description == AlertDescription::CloseNotify {
+            self.peer_closed = true;
+            return Ok(vec![KernelAction::PeerClosed]);
+        }
+
+        // As in `CommonState::process_alert()`: warnings are nonfatal for TLS1.2,
+        // but outlawed in TLS1.3 (except user_canceled).
+        let ignore = alert.level == AlertLevel::Warning
+            && (self.version != ProtocolVersion::TLSv1_3
+                || alert.description == AlertDescription::UserCanceled);
+        match ignore {
+            true => Ok(Vec::new()),
+            false => Err(Error::AlertReceived(alert.description)),
+        }
+    }
+
+    fn handle_handshake(&mut self, payload: &[u8]) -> Result<Vec<KernelAction>, Error> {
+        if self.version != ProtocolVersion::TLSv1_3 {
+            // No renegotiation, so nothing is expected after a TLS1.2 handshake.
+            return Err(Error::InappropriateMessage {
+                expect_types: vec![ContentType::Alert],
+                got_type: ContentType::Handshake,
+            });
+        }
+
+        self.handshake
+            .extend_from_slice(payload);
+        let mut actions = Vec::new();
+        while let Some(header) = self.handshake.get(..4) {
+            let len =
+                usize::from(header[1]) << 16 | usize::from(header[2]) << 8 | usize::from(header[3]);
+            if len > MAX_HANDSHAKE_SIZE {
+                return Err(InvalidMessage::HandshakePayloadTooLarge.into());
+            }
+            if self.handshake.len() < 4 + len {
+                break;
+            }
+
+            let message = self
+                .handshake
+                .drain(..4 + len)
+                .collect::<Vec<u8>>();
+            let parsed = HandshakeMessagePayload::read_version(
+                &mut Reader::init(&message),
+                ProtocolVersion::TLSv1_3,
+            )?;
+            actions.extend(self.handle_message(parsed)?);
+        }
+
+        Ok(actions)
+    }
+
+    fn handle_message(
+        &mut self,
+        message: HandshakeMessagePayload<'_>,
+    ) -> Result<Vec<KernelAction>, Error> {
+        match message.payload {
+            HandshakePayload::KeyUpdate(request) => {
+                // Mustn't be interleaved with other handshake messages.
+                if !self.handshake.is_empty() {
+                    return Err(PeerMisbehaved::KeyEpochWithPendingFragment.into());
+                }
+
+                let reply = match request {
+                    KeyUpdateRequest::UpdateNotRequested => false,
+                    KeyUpdateRequest::UpdateRequested => true,
+                    _ => return Err(InvalidMessage::InvalidKeyUpdate.into()),
+                };
+
+                let mut actions = vec![KernelAction::UpdateRx(
+                    self.state
+                        .update_secrets(Direction::Receive)?,
+                )];
+                if reply {
+                    actions.push(self.key_update(KeyUpdateRequest::UpdateNotRequested)?);
+                }
+                Ok(actions)
+            }
+            HandshakePayload::NewSessionTicketTls13(ref ticket) if self.side == Side::Client => {
+                let cx = KernelContext {
+                    peer_certificates: self.peer_certificates.as_ref(),
+                    alpn_protocol: self.alpn_protocol.as_deref(),
+                };
+                self.state
+                    .handle_new_session_ticket(&cx, ticket)?;
+                Ok(Vec::new())
+            }
+            _ => Err(Error::InappropriateHandshakeMessage {
+                expect_types: match self.side {
+                    Side::Client => vec![HandshakeType::NewSessionTicket, HandshakeType::KeyUpdate],
+                    Side::Server => vec![HandshakeType::KeyUpdate],
+                },
+                got_type: message.typ,
+            }),
+        }
+    }
+
+    fn key_update(&mut self, request: KeyUpdateRequest) -> Result<KernelAction, Error> {
+        let message = HandshakeMessagePayload {
+            typ: HandshakeType::KeyUpdate,
+            payload: HandshakePayload::KeyUpdate(request),
+        }
+        .get_encoding();
+
+        Ok(KernelAction::SendKeyUpdate {
+            message,
+            secrets: self
+                .state
+                .update_secrets(Direction::Transmit)?,
+        })
+    }
+}
+
+impl fmt::Debug for KernelConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KernelConnection")
+            .field("side", &self.side)
+            .field("version", &self.version)
+            .field("suite", &self.suite)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Something the application must do for a [`KernelConnection`].
+#[non_exhaustive]
+pub enum KernelAction {
+    /// The peer updated its keys: decrypt following records using these
+    /// secrets, starting from sequence number zero.
+    UpdateRx(ConnectionTrafficSecrets),
+
+    /// Send `message` as a handshake record, using the current transmit keys.
+    ///
+    /// Then encrypt following records using `secrets`, starting from
+    /// sequence number zero.
+    SendKeyUpdate {
+        /// The encoded `KeyUpdate` handshake message.
+        message: Vec<u8>,
+        /// The new transmit secrets.
+        secrets: ConnectionTrafficSecrets,
+    },
+
+    /// The peer sent a `close_notify` alert: it will send no more data.
+    PeerClosed,
+}
+
+impl fmt::Debug for KernelAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UpdateRx(_) => f.write_str("UpdateRx(..)"),
+            Self::SendKeyUpdate { message, .. } => f
+                .debug_struct("SendKeyUpdate")
+                .field("message", message)
+                .finish_non_exhaustive(),
+            Self::PeerClosed => f.write_str("PeerClosed"),
+        }
+    }
+}
+
+/// The parts of a connection's state needed once it is a [`KernelConnection`].
+pub(crate) trait KernelState: Send + Sync {
+    /// Move to the next traffic secrets for `direction`.
+    fn update_secrets(&mut self, direction: Direction) -> Result<ConnectionTrafficSecrets, Error>;
+
+    /// Process a TLS1.3 `NewSessionTicket` received by a client.
+    fn handle_new_session_ticket(
+        &mut self,
+        cx: &KernelContext<'_>,
+        ticket: &NewSessionTicketPayloadTls13,
+    ) -> Result<(), Error>;
+}
+
+/// The connection-wide state available to a [`KernelState`].
+pub(crate) struct KernelContext<'a> {
+    pub(crate) peer_certificates: Option<&'a CertificateChain<'static>>,
+    pub(crate) alpn_protocol: Option<&'a [u8]>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Direction {
+    Transmit,
+    Receive,
+}
+
+impl Direction {
+    /// Whose traffic secrets `self` uses, on `side`.
+    pub(crate) fn secret_side(self, side: Side) -> Side {
+        match self {
+            Self::Transmit => side,
+            Self::Receive => side.peer(),
+        }
+    }
+}
+
+/// TLS1.2 has no post-handshake messages, so never uses its `KernelState`.
+#[cfg(feature = "tls12")]
+pub(crate) struct Tls12KernelState;
+
+#[cfg(feature = "tls12")]
+impl KernelState for Tls12KernelState {
+    fn update_secrets(&mut self, _direction: Direction) -> Result<ConnectionTrafficSecrets, Error> {
+        Err(Error::General(
+            "key updates are only possible in TLS1.3".into(),
+        ))
+    }
+
+    fn handle_new_session_ticket(
+        &mut self,
+        _cx: &KernelContext<'_>,
+        _ticket: &NewSessionTicketPayloadTls13,
+    ) -> Result<(), Error> {
+        Err(Error::InappropriateHandshakeMessage {
+            expect_types: Vec::new(),
+            got_type: HandshakeType::NewSessionTicket,
+        })
+    }
+}
+
+/// The largest handshake message we accept, as for the `HandshakeDeframer`.
+const MAX_HANDSHAKE_SIZE: usize = 0xffff;
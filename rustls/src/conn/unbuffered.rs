@@ -9,7 +9,7 @@ use std::error::Error as StdError;
 use super::UnbufferedConnectionCommon;
 use crate::client::ClientConnectionData;
 use crate::msgs::deframer::buffers::DeframerSliceBuffer;
-use crate::server::ServerConnectionData;
+use crate::server::{OperationResult, PendingOperation, ServerConnectionData};
 use crate::Error;
 
 impl UnbufferedConnectionCommon<ClientConnectionData> {
@@ -36,6 +36,12 @@ impl UnbufferedConnectionCommon<ServerConnectionData> {
             |conn, incoming_tls, chunk| ReadEarlyData::new(conn, incoming_tls, chunk).into(),
         )
     }
+
+    /// Returns true while a request made with [`WriteTraffic::request_client_certificate()`]
+    /// awaits the client's response.
+    pub fn client_certificate_requested(&self) -> bool {
+        self.core.client_certificate_requested()
+    }
 }
 
 impl<Data> UnbufferedConnectionCommon<Data> {
@@ -77,6 +83,15 @@ impl<Data> UnbufferedConnectionCommon<Data> {
                 );
             }
 
+            if self.core.pending_operation().is_some() {
+                // Received data is left in place until the operation is completed.
+                let state = match self.wants_write {
+                    true => TransmitTlsData { conn: self }.into(),
+                    false => PerformOperation { conn: self }.into(),
+                };
+                break (buffer.pending_discard(), state);
+            }
+
             let deframer_output =
                 match self
                     .core
@@ -231,6 +246,14 @@ pub enum ConnectionState<'c, 'i, Data> {
     /// appended to `incoming_tls`, [`UnbufferedConnectionCommon::process_tls_records`] will yield
     /// the [`ConnectionState::ReadTraffic`] state.
     WriteTraffic(WriteTraffic<'c, Data>),
+
+    /// The handshake is waiting on the application to complete an operation.
+    ///
+    /// Call [`PerformOperation::operation`] on the enclosed object to learn what is needed,
+    /// and complete it with [`PerformOperation::complete`].  This may happen at a later time:
+    /// until then, [`UnbufferedConnectionCommon::process_tls_records`] will keep yielding
+    /// this state.
+    PerformOperation(PerformOperation<'c, Data>),
 }
 
 impl<'c, 'i, Data> From<ReadTraffic<'c, 'i, Data>> for ConnectionState<'c, 'i, Data> {
@@ -257,6 +280,12 @@ impl<'c, Data> From<TransmitTlsData<'c, Data>> for ConnectionState<'c, '_, Data>
     }
 }
 
+impl<'c, Data> From<PerformOperation<'c, Data>> for ConnectionState<'c, '_, Data> {
+    fn from(v: PerformOperation<'c, Data>) -> Self {
+        Self::PerformOperation(v)
+    }
+}
+
 impl<Data> fmt::Debug for ConnectionState<'_, '_, Data> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -277,6 +306,10 @@ impl<Data> fmt::Debug for ConnectionState<'_, '_, Data> {
                 .finish(),
 
             Self::WriteTraffic(..) => f.debug_tuple("WriteTraffic").finish(),
+
+            Self::PerformOperation(..) => f
+                .debug_tuple("PerformOperation")
+                .finish(),
         }
     }
 }
@@ -444,6 +477,25 @@ impl<Data> WriteTraffic<'_, Data> {
     }
 }
 
+impl WriteTraffic<'_, ServerConnectionData> {
+    /// Arranges for a TLS1.3 post-handshake `CertificateRequest` to be sent.
+    ///
+    /// This consumes the `WriteTraffic` state:  to actually send the message,
+    /// call [`UnbufferedConnectionCommon::process_tls_records`] again which will
+    /// return a `ConnectionState::EncodeTlsData` that emits the `CertificateRequest`
+    /// message.  The client's response is processed as it is received.
+    ///
+    /// See [`ServerConnection::request_client_certificate()`] for full documentation,
+    /// including in what circumstances it will fail.
+    ///
+    /// [`ServerConnection::request_client_certificate()`]: crate::server::ServerConnection::request_client_certificate
+    pub fn request_client_certificate(self) -> Result<(), Error> {
+        self.conn
+            .core
+            .request_client_certificate()
+    }
+}
+
 /// A handshake record must be encoded
 pub struct EncodeTlsData<'c, Data> {
     conn: &'c mut UnbufferedConnectionCommon<Data>,
@@ -511,6 +563,32 @@ impl<Data> TransmitTlsData<'_, Data> {
     }
 }
 
+/// An operation must be completed by the application before the handshake can continue
+pub struct PerformOperation<'c, Data> {
+    conn: &'c mut UnbufferedConnectionCommon<Data>,
+}
+
+impl<Data> PerformOperation<'_, Data> {
+    /// Returns the operation to perform
+    pub fn operation(&self) -> PendingOperation<'_> {
+        self.conn
+            .core
+            .pending_operation()
+            .expect("operation must be pending")
+    }
+
+    /// Continues the handshake with the outcome of the operation
+    ///
+    /// Errors are fatal to the connection, as for
+    /// [`UnbufferedConnectionCommon::process_tls_records`].  Call that again to
+    /// encode any handshake messages produced.
+    pub fn complete(self, result: OperationResult) -> Result<(), Error> {
+        self.conn
+            .core
+            .complete_pending_operation(result, None)
+    }
+}
+
 /// Errors that may arise when encoding a handshake record
 #[derive(Debug)]
 pub enum EncodeError {
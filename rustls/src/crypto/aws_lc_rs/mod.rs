@@ -22,6 +22,8 @@ pub mod hpke;
 /// Using software keys for authentication.
 pub mod sign;
 
+#[path = "../ring/dtls.rs"]
+pub(crate) mod dtls;
 #[path = "../ring/hash.rs"]
 pub(crate) mod hash;
 #[path = "../ring/hmac.rs"]
@@ -30,6 +32,9 @@ pub(crate) mod hmac;
 pub(crate) mod kx;
 #[path = "../ring/quic.rs"]
 pub(crate) mod quic;
+#[cfg(feature = "std")]
+#[path = "../ring/shared_ticketer.rs"]
+pub(crate) mod shared_ticketer;
 #[cfg(any(feature = "std", feature = "hashbrown"))]
 pub(crate) mod ticketer;
 #[cfg(feature = "tls12")]
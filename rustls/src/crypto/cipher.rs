@@ -154,6 +154,25 @@ pub trait MessageEncrypter: Send + Sync {
         seq: u64,
     ) -> Result<OutboundOpaqueMessage, Error>;
 
+    /// Encrypt the given TLS1.3 message `msg` like [`Self::encrypt()`], but with
+    /// `padding` zero bytes appended to its `TLSInnerPlaintext`.
+    ///
+    /// This is used to apply a connection's record padding policy.  The default
+    /// implementation only supports a `padding` of zero, and fails with
+    /// [`Error::EncryptError`] otherwise: implementations for TLS1.3 must
+    /// override it to be usable with a padding policy.
+    fn encrypt_padded(
+        &mut self,
+        msg: OutboundPlainMessage<'_>,
+        seq: u64,
+        padding: usize,
+    ) -> Result<OutboundOpaqueMessage, Error> {
+        match padding {
+            0 => self.encrypt(msg, seq),
+            _ => Err(Error::EncryptError),
+        }
+    }
+
     /// Return the length of the ciphertext that results from encrypting plaintext of
     /// length `payload_len`
     fn encrypted_payload_len(&self, payload_len: usize) -> usize;
@@ -0,0 +1,54 @@
+#![allow(clippy::duplicate_mod)]
+
+use alloc::boxed::Box;
+
+use super::quic::{KeyBuilder, PacketKey};
+use super::ring_like::aead;
+use crate::crypto::cipher::{AeadKey, Iv};
+use crate::error::Error;
+use crate::{dtls, quic};
+
+pub(crate) struct RecordNumberKey(aead::quic::HeaderProtectionKey);
+
+impl dtls::RecordNumberKey for RecordNumberKey {
+    fn mask(&self, sample: &[u8]) -> Result<[u8; 2], Error> {
+        // RFC 9147 record number encryption is the QUIC header protection
+        // mask function, applied to the sequence number only.
+        let mask = self
+            .0
+            .new_mask(sample)
+            .map_err(|_| Error::General("sample of invalid length".into()))?;
+        Ok([mask[0], mask[1]])
+    }
+
+    #[inline]
+    fn sample_len(&self) -> usize {
+        self.0.algorithm().sample_len()
+    }
+}
+
+impl dtls::Algorithm for KeyBuilder {
+    fn record_key(&self, key: AeadKey, iv: Iv) -> Box<dyn quic::PacketKey> {
+        Box::new(PacketKey::new(
+            key,
+            iv,
+            self.confidentiality_limit,
+            self.integrity_limit,
+            self.packet_alg,
+        ))
+    }
+
+    fn record_number_key(&self, key: AeadKey) -> Box<dyn dtls::RecordNumberKey> {
+        Box::new(RecordNumberKey(
+            aead::quic::HeaderProtectionKey::new(self.header_alg, key.as_ref()).unwrap(),
+        ))
+    }
+
+    fn aead_key_len(&self) -> usize {
+        self.packet_alg.key_len()
+    }
+
+    fn fips(&self) -> bool {
+        super::fips()
+    }
+}
@@ -15,11 +15,14 @@ use crate::Error;
 /// Using software keys for authentication.
 pub mod sign;
 
+pub(crate) mod dtls;
 pub(crate) mod hash;
 #[cfg(any(test, feature = "tls12"))]
 pub(crate) mod hmac;
 pub(crate) mod kx;
 pub(crate) mod quic;
+#[cfg(feature = "std")]
+pub(crate) mod shared_ticketer;
 #[cfg(any(feature = "std", feature = "hashbrown"))]
 pub(crate) mod ticketer;
 #[cfg(feature = "tls12")]
@@ -0,0 +1,133 @@
+#![allow(clippy::duplicate_mod)]
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use subtle::ConstantTimeEq;
+
+use super::ring_like::rand::{SecureRandom, SystemRandom};
+use super::ring_like::{aead, hkdf};
+use crate::error::Error;
+#[cfg(debug_assertions)]
+use crate::log::debug;
+use crate::polyfill::try_split_at;
+use crate::server::ProducesTickets;
+use crate::ticketer::{TicketKey, SHARED_TICKET_KEY_LABEL};
+
+/// Make a `ProducesTickets` implementation for one [`TicketKey`], using the
+/// construction described in the [`SharedKeyTicketer`] docs.
+///
+/// This is shared between the *ring* and aws-lc-rs providers, so that servers
+/// using either can decrypt each other's tickets.
+///
+/// [`SharedKeyTicketer`]: crate::ticketer::SharedKeyTicketer
+pub(crate) fn make_shared_key_ticketer(key: &TicketKey) -> Result<Box<dyn ProducesTickets>, Error> {
+    let key_name = key.id();
+    // This won't fail since the output length is that of the AEAD key.
+    let key: aead::UnboundKey = hkdf::Prk::new_less_safe(hkdf::HKDF_SHA256, key.secret())
+        .expand(&[SHARED_TICKET_KEY_LABEL], TICKETER_AEAD)
+        .unwrap()
+        .into();
+
+    Ok(Box::new(SharedKeyAeadTicketer {
+        key: aead::LessSafeKey::new(key),
+        key_name,
+    }))
+}
+
+/// Encrypts tickets for a single shared [`TicketKey`].
+///
+/// Unlike the providers' default ticketers, this does not limit the length of
+/// tickets it decrypts to the longest it has produced: tickets encrypted by
+/// other servers may be longer than any it has seen.
+struct SharedKeyAeadTicketer {
+    key: aead::LessSafeKey,
+    key_name: [u8; 16],
+}
+
+impl ProducesTickets for SharedKeyAeadTicketer {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn lifetime(&self) -> u32 {
+        // Only the `SharedKeyTicketer` lifetime is given to clients.
+        0
+    }
+
+    /// Encrypt `message` and return the ciphertext.
+    fn encrypt(&self, message: &[u8]) -> Option<Vec<u8>> {
+        // Random nonce, because a counter is a privacy leak.
+        let mut nonce_buf = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce_buf)
+            .ok()?;
+        let nonce = aead::Nonce::assume_unique_for_key(nonce_buf);
+        let aad = aead::Aad::from(self.key_name);
+
+        // ciphertext structure is:
+        // key_name: [u8; 16]
+        // nonce: [u8; 12]
+        // message: [u8, _]
+        // tag: [u8; 16]
+
+        let mut ciphertext = Vec::with_capacity(
+            self.key_name.len() + nonce_buf.len() + message.len() + TICKETER_AEAD.tag_len(),
+        );
+        ciphertext.extend(self.key_name);
+        ciphertext.extend(nonce_buf);
+        ciphertext.extend(message);
+        self.key
+            .seal_in_place_separate_tag(
+                nonce,
+                aad,
+                &mut ciphertext[self.key_name.len() + nonce_buf.len()..],
+            )
+            .map(|tag| {
+                ciphertext.extend(tag.as_ref());
+                ciphertext
+            })
+            .ok()
+    }
+
+    /// Decrypt `ciphertext` and recover the original message.
+    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        let (alleged_key_name, ciphertext) = try_split_at(ciphertext, self.key_name.len())?;
+        let (nonce, ciphertext) = try_split_at(ciphertext, NONCE_LEN)?;
+
+        // See `AeadTicketer::decrypt()` in the *ring* provider for why the key
+        // name is both checked and authenticated.
+        if ConstantTimeEq::ct_ne(&self.key_name[..], alleged_key_name).into() {
+            #[cfg(debug_assertions)]
+            debug!("rejected ticket with wrong ticket_name");
+            return None;
+        }
+
+        // This won't fail since `nonce` has the required length.
+        let nonce = aead::Nonce::try_assume_unique_for_key(nonce).ok()?;
+
+        let mut out = Vec::from(ciphertext);
+
+        let plain_len = self
+            .key
+            .open_in_place(nonce, aead::Aad::from(alleged_key_name), &mut out)
+            .ok()?
+            .len();
+        out.truncate(plain_len);
+
+        Some(out)
+    }
+}
+
+impl core::fmt::Debug for SharedKeyAeadTicketer {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // Note: we deliberately omit the key from the debug output.
+        f.debug_struct("SharedKeyAeadTicketer")
+            .field("key_name", &self.key_name)
+            .finish_non_exhaustive()
+    }
+}
+
+static TICKETER_AEAD: &aead::Algorithm = &aead::AES_256_GCM;
+
+const NONCE_LEN: usize = 12;
@@ -15,6 +15,8 @@ use crate::log::debug;
 use crate::polyfill::try_split_at;
 use crate::rand::GetRandomFailed;
 use crate::server::ProducesTickets;
+#[cfg(feature = "std")]
+use crate::ticketer::{SharedKeyTicketer, TicketKeys};
 
 /// A concrete, safe ticket creation mechanism.
 pub struct Ticketer {}
@@ -46,6 +48,26 @@ impl Ticketer {
             time_provider,
         )?))
     }
+
+    /// Make a `Ticketer` which uses externally supplied `keys`, so that
+    /// servers given the same keys can decrypt each other's tickets.
+    ///
+    /// `lifetime` is the ticket lifetime hint given to clients, in seconds.
+    /// The keys in use can be changed later using [`SharedKeyTicketer::set_keys()`].
+    ///
+    /// Tickets are encrypted using the construction described in the
+    /// [`SharedKeyTicketer`] docs, which all providers in this crate share.
+    #[cfg(feature = "std")]
+    pub fn with_shared_keys(
+        lifetime: u32,
+        keys: TicketKeys,
+    ) -> Result<Arc<SharedKeyTicketer>, Error> {
+        Ok(Arc::new(SharedKeyTicketer::new(
+            lifetime,
+            keys,
+            super::shared_ticketer::make_shared_key_ticketer,
+        )?))
+    }
 }
 
 fn make_ticket_generator() -> Result<Box<dyn ProducesTickets>, GetRandomFailed> {
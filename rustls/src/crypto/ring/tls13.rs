@@ -37,6 +37,13 @@ pub(crate) static TLS13_CHACHA20_POLY1305_SHA256_INTERNAL: &Tls13CipherSuite = &
         // ref: <https://datatracker.ietf.org/doc/html/rfc9001#section-6.6>
         integrity_limit: 1 << 36,
     }),
+    dtls: Some(&super::quic::KeyBuilder {
+        packet_alg: &aead::CHACHA20_POLY1305,
+        header_alg: &aead::quic::CHACHA20,
+        confidentiality_limit: u64::MAX,
+        // ref: <https://www.rfc-editor.org/rfc/rfc9147#section-4.5.3>
+        integrity_limit: 1 << 36,
+    }),
 };
 
 /// The TLS1.3 ciphersuite TLS_AES_256_GCM_SHA384
@@ -57,6 +64,13 @@ pub static TLS13_AES_256_GCM_SHA384: SupportedCipherSuite =
             // ref: <https://datatracker.ietf.org/doc/html/rfc9001#section-b.1.2>
             integrity_limit: 1 << 52,
         }),
+        dtls: Some(&super::quic::KeyBuilder {
+            packet_alg: &aead::AES_256_GCM,
+            header_alg: &aead::quic::AES_256,
+            confidentiality_limit: 1 << 24,
+            // ref: <https://www.rfc-editor.org/rfc/rfc9147#section-4.5.3>
+            integrity_limit: 1 << 36,
+        }),
     });
 
 /// The TLS1.3 ciphersuite TLS_AES_128_GCM_SHA256
@@ -79,6 +93,13 @@ pub(crate) static TLS13_AES_128_GCM_SHA256_INTERNAL: &Tls13CipherSuite = &Tls13C
         // ref: <https://datatracker.ietf.org/doc/html/rfc9001#section-b.1.2>
         integrity_limit: 1 << 52,
     }),
+    dtls: Some(&super::quic::KeyBuilder {
+        packet_alg: &aead::AES_128_GCM,
+        header_alg: &aead::quic::AES_128,
+        confidentiality_limit: 1 << 24,
+        // ref: <https://www.rfc-editor.org/rfc/rfc9147#section-4.5.3>
+        integrity_limit: 1 << 36,
+    }),
 };
 
 struct Chacha20Poly1305Aead(AeadAlgorithm);
@@ -206,13 +227,23 @@ impl MessageEncrypter for Tls13MessageEncrypter {
         msg: OutboundPlainMessage<'_>,
         seq: u64,
     ) -> Result<OutboundOpaqueMessage, Error> {
-        let total_len = self.encrypted_payload_len(msg.payload.len());
+        self.encrypt_padded(msg, seq, 0)
+    }
+
+    fn encrypt_padded(
+        &mut self,
+        msg: OutboundPlainMessage<'_>,
+        seq: u64,
+        padding: usize,
+    ) -> Result<OutboundOpaqueMessage, Error> {
+        let total_len = self.encrypted_payload_len(msg.payload.len() + padding);
         let mut payload = PrefixedPayload::with_capacity(total_len);
 
         let nonce = aead::Nonce::assume_unique_for_key(Nonce::new(&self.iv, seq).0);
         let aad = aead::Aad::from(make_tls13_aad(total_len));
         payload.extend_from_chunks(&msg.payload);
         payload.extend_from_slice(&msg.typ.to_array());
+        payload.extend_with_zeros(padding);
 
         self.enc_key
             .seal_in_place_append_tag(nonce, aad, &mut payload)
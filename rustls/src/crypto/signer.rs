@@ -5,6 +5,7 @@ use core::fmt::Debug;
 
 use pki_types::{AlgorithmIdentifier, CertificateDer, SubjectPublicKeyInfoDer};
 
+use crate::delegated_credential::DelegatedKey;
 use crate::enums::{SignatureAlgorithm, SignatureScheme};
 use crate::error::{Error, InconsistentKeys};
 use crate::server::ParsedCertificate;
@@ -83,6 +84,20 @@ pub trait Signer: Debug + Send + Sync {
 
     /// Reveals which scheme will be used when you call [`Self::sign()`].
     fn scheme(&self) -> SignatureScheme;
+
+    /// Return true to defer signing to the application.
+    ///
+    /// In that case a server does not call [`Self::sign()`].  Instead the handshake
+    /// pauses with a [`PendingOperation::Sign`], and continues once the application
+    /// supplies an [`OperationResult::Signature`].
+    ///
+    /// This is not supported by clients, which always call [`Self::sign()`].
+    ///
+    /// [`PendingOperation::Sign`]: crate::server::PendingOperation::Sign
+    /// [`OperationResult::Signature`]: crate::server::OperationResult::Signature
+    fn defers_signing(&self) -> bool {
+        false
+    }
 }
 
 /// A packaged-together certificate chain, matching `SigningKey` and
@@ -104,6 +119,21 @@ pub struct CertifiedKey {
     /// An optional OCSP response from the certificate issuer,
     /// attesting to its continued validity.
     pub ocsp: Option<Vec<u8>>,
+
+    /// An optional collection of signed certificate timestamps (SCTs) from
+    /// Certificate Transparency logs, proving the certificate was submitted
+    /// to those logs.
+    ///
+    /// This must be a `SignedCertificateTimestampList` encoding; see
+    /// [RFC 6962 section 3.3](https://www.rfc-editor.org/rfc/rfc6962#section-3.3).
+    /// It is sent to clients that request it.
+    pub sct_list: Option<Vec<u8>>,
+
+    /// An optional delegated credential, used in place of `key` for TLS1.3
+    /// clients that support it.
+    ///
+    /// See [`crate::delegated_credential`].
+    pub delegated_credential: Option<DelegatedKey>,
 }
 
 impl CertifiedKey {
@@ -116,6 +146,8 @@ impl CertifiedKey {
             cert,
             key,
             ocsp: None,
+            sct_list: None,
+            delegated_credential: None,
         }
     }
 
@@ -0,0 +1,98 @@
+use alloc::vec::Vec;
+
+use crate::error::Error;
+use crate::grease::is_grease;
+use crate::msgs::base::Payload;
+use crate::msgs::enums::ExtensionType;
+use crate::msgs::handshake::UnknownExtension;
+
+/// An application-defined TLS extension.
+///
+/// Clients send these in their ClientHello (see `ClientConfig::custom_extensions`),
+/// and servers reply with them (see `ServerConfig::custom_extensions`).  rustls does
+/// not interpret their contents.
+///
+/// The extension type must not be one rustls implements or knows about, nor a
+/// [GREASE] value, so custom extensions can never be confused with rustls's own.
+///
+/// [GREASE]: https://www.rfc-editor.org/rfc/rfc8701
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CustomExtension {
+    typ: ExtensionType,
+    payload: Vec<u8>,
+}
+
+impl CustomExtension {
+    /// Make a new custom extension of type `typ`, carrying `payload`.
+    ///
+    /// Returns [`Error::BadCustomExtension`] if `typ` is reserved by rustls, or if
+    /// `payload` is too long to be encoded.
+    pub fn new(typ: ExtensionType, payload: impl Into<Vec<u8>>) -> Result<Self, Error> {
+        // Normalise, so `ExtensionType::Unknown` cannot smuggle in a known type.
+        let typ = ExtensionType::from(u16::from(typ));
+        let payload = payload.into();
+        if !matches!(typ, ExtensionType::Unknown(_))
+            || is_grease(u16::from(typ))
+            || payload.len() > usize::from(u16::MAX)
+        {
+            return Err(Error::BadCustomExtension);
+        }
+
+        Ok(Self { typ, payload })
+    }
+
+    /// The extension type.
+    pub fn typ(&self) -> ExtensionType {
+        self.typ
+    }
+
+    /// The extension's contents.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    pub(crate) fn to_unknown_extension(&self) -> UnknownExtension {
+        UnknownExtension {
+            typ: self.typ,
+            payload: Payload::new(self.payload.clone()),
+        }
+    }
+
+    pub(crate) fn from_unknown_extension(ext: &UnknownExtension) -> Self {
+        Self {
+            typ: ext.typ,
+            payload: ext.payload.bytes().to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec;
+
+    use super::*;
+
+    #[test]
+    fn reserved_types_are_rejected() {
+        assert_eq!(
+            CustomExtension::new(ExtensionType::ALProtocolNegotiation, []),
+            Err(Error::BadCustomExtension)
+        );
+        assert_eq!(
+            CustomExtension::new(ExtensionType::Unknown(0x0010), []),
+            Err(Error::BadCustomExtension)
+        );
+        assert_eq!(
+            CustomExtension::new(ExtensionType::Unknown(0x3a3a), []),
+            Err(Error::BadCustomExtension)
+        );
+        assert_eq!(
+            CustomExtension::new(ExtensionType::Unknown(0xfe01), vec![0; 0x10000]),
+            Err(Error::BadCustomExtension)
+        );
+
+        let ext = CustomExtension::new(ExtensionType::Unknown(0xfe01), [1, 2, 3]).unwrap();
+        assert_eq!(ext.typ(), ExtensionType::Unknown(0xfe01));
+        assert_eq!(ext.payload(), &[1, 2, 3]);
+    }
+}
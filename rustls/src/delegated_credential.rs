@@ -0,0 +1,417 @@
+//! Delegated credentials for TLS 1.3, as defined in [RFC 9345].
+//!
+//! A delegated credential lets a server sign its handshakes with a short-lived key,
+//! vouched for by a signature from its certificate's key rather than by a CA.  The
+//! certificate must carry the `DelegationUsage` extension.
+//!
+//! Servers attach a [`DelegatedKey`] to a [`CertifiedKey`].  It is used instead of the
+//! certificate's key with clients that offer the `delegated_credential` extension with
+//! a matching signature scheme.
+//!
+//! Clients offer the extension if their [`ServerCertVerifier`] returns any
+//! [`ServerCertVerifier::delegated_credential_schemes()`].  [`WebPkiServerVerifier`]
+//! does so if built with [`ServerCertVerifierBuilder::allow_delegated_credentials()`].
+//!
+//! [RFC 9345]: https://www.rfc-editor.org/rfc/rfc9345
+//! [`ServerCertVerifier`]: crate::client::danger::ServerCertVerifier
+//! [`ServerCertVerifier::delegated_credential_schemes()`]: crate::client::danger::ServerCertVerifier::delegated_credential_schemes
+//! [`WebPkiServerVerifier`]: crate::client::WebPkiServerVerifier
+//! [`ServerCertVerifierBuilder::allow_delegated_credentials()`]: crate::client::ServerCertVerifierBuilder::allow_delegated_credentials
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use pki_types::{CertificateDer, SubjectPublicKeyInfoDer, UnixTime};
+
+use crate::common_state::Side;
+use crate::enums::SignatureScheme;
+use crate::error::{CertificateError, Error, InvalidMessage};
+use crate::msgs::base::{Payload, PayloadU16, PayloadU24};
+use crate::msgs::codec::{Codec, Reader};
+use crate::sign::{CertifiedKey, SigningKey};
+use crate::x509::DerReader;
+
+/// A delegated credential, as sent by a server alongside its certificate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DelegatedCredential {
+    /// The credential's validity, in seconds from the `notBefore` time of the
+    /// delegating certificate.
+    pub valid_time: u32,
+
+    /// The scheme the credential's key uses to sign `CertificateVerify` messages.
+    pub dc_cert_verify_algorithm: SignatureScheme,
+
+    /// The credential's public key.
+    pub subject_public_key_info: SubjectPublicKeyInfoDer<'static>,
+
+    /// The scheme the certificate's key used for `signature`.
+    pub algorithm: SignatureScheme,
+
+    /// The certificate key's signature over the credential.
+    pub signature: Vec<u8>,
+}
+
+impl DelegatedCredential {
+    /// The longest validity a peer may give a delegated credential.
+    pub const MAX_VALIDITY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+    /// Decode a `DelegatedCredential` structure.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, InvalidMessage> {
+        Self::read_bytes(bytes)
+    }
+
+    /// Encode this as a `DelegatedCredential` structure.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.get_encoding()
+    }
+
+    /// The message signed by the key of `end_entity`, the delegating certificate.
+    ///
+    /// `side` is the side of the connection presenting the credential.
+    pub fn signed_message(&self, end_entity: &CertificateDer<'_>, side: Side) -> Vec<u8> {
+        let context: &[u8] = match side {
+            Side::Client => b"TLS, client delegated credentials",
+            Side::Server => b"TLS, server delegated credentials",
+        };
+
+        let mut message = Vec::new();
+        message.extend_from_slice(&[0x20; 64]);
+        message.extend_from_slice(context);
+        message.push(0);
+        message.extend_from_slice(end_entity.as_ref());
+        self.encode_credential(&mut message);
+        self.algorithm.encode(&mut message);
+        message
+    }
+
+    /// The time this credential expires, given its delegating certificate `end_entity`.
+    pub fn not_after(&self, end_entity: &CertificateDer<'_>) -> Result<UnixTime, Error> {
+        let not_before = DelegatingCertificate::parse(end_entity)?.not_before;
+        Ok(UnixTime::since_unix_epoch(Duration::from_secs(
+            not_before + u64::from(self.valid_time),
+        )))
+    }
+
+    /// Check this credential is usable at `now`, and was delegated by `end_entity`
+    /// with the `DelegationUsage` extension.
+    ///
+    /// This does not check the credential's signature.
+    pub fn check_validity(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        now: UnixTime,
+    ) -> Result<(), Error> {
+        let cert = DelegatingCertificate::parse(end_entity)?;
+        if !cert.delegation_usage || !cert.digital_signature {
+            return Err(CertificateError::DelegationNotPermitted.into());
+        }
+
+        let not_after = cert.not_before + u64::from(self.valid_time);
+        let now = now.as_secs();
+        if now >= not_after {
+            return Err(CertificateError::DelegatedCredentialExpired.into());
+        }
+
+        if not_after - now > Self::MAX_VALIDITY.as_secs() {
+            return Err(CertificateError::DelegatedCredentialValidityTooLong.into());
+        }
+
+        Ok(())
+    }
+
+    fn encode_credential(&self, bytes: &mut Vec<u8>) {
+        self.valid_time.encode(bytes);
+        self.dc_cert_verify_algorithm
+            .encode(bytes);
+        PayloadU24(Payload::Borrowed(self.subject_public_key_info.as_ref())).encode(bytes);
+    }
+}
+
+impl Codec<'_> for DelegatedCredential {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.encode_credential(bytes);
+        self.algorithm.encode(bytes);
+        PayloadU16::encode_slice(&self.signature, bytes);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, InvalidMessage> {
+        let valid_time = u32::read(r)?;
+        let dc_cert_verify_algorithm = SignatureScheme::read(r)?;
+        let spki = PayloadU24::read(r)?.0.into_vec();
+        let algorithm = SignatureScheme::read(r)?;
+        let signature = PayloadU16::read(r)?.0;
+
+        Ok(Self {
+            valid_time,
+            dc_cert_verify_algorithm,
+            subject_public_key_info: SubjectPublicKeyInfoDer::from(spki),
+            algorithm,
+            signature,
+        })
+    }
+}
+
+/// A delegated credential together with its private key.
+#[derive(Clone, Debug)]
+pub struct DelegatedKey {
+    credential: DelegatedCredential,
+    key: Arc<dyn SigningKey>,
+}
+
+impl DelegatedKey {
+    /// Pair an existing `credential` with its private `key`.
+    pub fn new(credential: DelegatedCredential, key: Arc<dyn SigningKey>) -> Self {
+        Self { credential, key }
+    }
+
+    /// Delegate to `key` using the certificate and key in `certified`.
+    ///
+    /// The new credential signs handshakes using `scheme`, and is valid for
+    /// `valid_for` from `now`.  That must not exceed
+    /// [`DelegatedCredential::MAX_VALIDITY`].
+    ///
+    /// `key` must be able to produce its public key.  The certificate must carry
+    /// the `DelegationUsage` extension.
+    pub fn delegate(
+        certified: &CertifiedKey,
+        key: Arc<dyn SigningKey>,
+        scheme: SignatureScheme,
+        valid_for: Duration,
+        now: UnixTime,
+    ) -> Result<Self, Error> {
+        if valid_for > DelegatedCredential::MAX_VALIDITY {
+            return Err(CertificateError::DelegatedCredentialValidityTooLong.into());
+        }
+
+        let end_entity = certified.end_entity_cert()?;
+        let cert = DelegatingCertificate::parse(end_entity)?;
+        if !cert.delegation_usage || !cert.digital_signature {
+            return Err(CertificateError::DelegationNotPermitted.into());
+        }
+
+        let valid_time = (now.as_secs() + valid_for.as_secs())
+            .checked_sub(cert.not_before)
+            .and_then(|secs| u32::try_from(secs).ok())
+            .ok_or(CertificateError::DelegatedCredentialExpired)?;
+
+        let subject_public_key_info = key
+            .public_key()
+            .ok_or_else(|| Error::General("delegated key has no public key".into()))?
+            .into_owned();
+
+        let signer = certified
+            .key
+            .choose_scheme(TLS13_SIGNATURE_SCHEMES)
+            .ok_or(Error::General(
+                "certificate key cannot sign delegated credentials".into(),
+            ))?;
+
+        let mut credential = DelegatedCredential {
+            valid_time,
+            dc_cert_verify_algorithm: scheme,
+            subject_public_key_info,
+            algorithm: signer.scheme(),
+            signature: Vec::new(),
+        };
+        credential.signature = signer.sign(&credential.signed_message(end_entity, Side::Server))?;
+
+        Ok(Self { credential, key })
+    }
+
+    /// The delegated credential.
+    pub fn credential(&self) -> &DelegatedCredential {
+        &self.credential
+    }
+
+    /// The credential's private key.
+    pub fn key(&self) -> &Arc<dyn SigningKey> {
+        &self.key
+    }
+}
+
+/// What a delegated credential needs to know about its delegating certificate.
+struct DelegatingCertificate {
+    /// `notBefore`, in seconds since the Unix epoch.
+    not_before: u64,
+    delegation_usage: bool,
+    /// True if there is no `KeyUsage` extension, or it asserts `digitalSignature`.
+    digital_signature: bool,
+}
+
+impl DelegatingCertificate {
+    fn parse(end_entity: &CertificateDer<'_>) -> Result<Self, Error> {
+        Self::parse_inner(end_entity.as_ref()).ok_or_else(|| CertificateError::BadEncoding.into())
+    }
+
+    fn parse_inner(der: &[u8]) -> Option<Self> {
+        let mut outer = DerReader::new(der);
+        let mut cert = DerReader::new(outer.expect(SEQUENCE)?);
+        let mut tbs = DerReader::new(cert.expect(SEQUENCE)?);
+
+        tbs.optional(CONTEXT_0)?; // version
+        tbs.read()?; // serialNumber
+        tbs.expect(SEQUENCE)?; // signature
+        tbs.expect(SEQUENCE)?; // issuer
+        let mut validity = DerReader::new(tbs.expect(SEQUENCE)?);
+        let not_before = match validity.read()? {
+            (UTC_TIME, time) => parse_time(time, 2)?,
+            (GENERALIZED_TIME, time) => parse_time(time, 4)?,
+            _ => return None,
+        };
+        tbs.expect(SEQUENCE)?; // subject
+        tbs.expect(SEQUENCE)?; // subjectPublicKeyInfo
+
+        let mut result = Self {
+            not_before,
+            delegation_usage: false,
+            digital_signature: true,
+        };
+
+        while !tbs.is_empty() {
+            let (tag, contents) = tbs.read()?;
+            if tag != CONTEXT_3 {
+                continue;
+            }
+
+            let mut explicit = DerReader::new(contents);
+            let mut extensions = DerReader::new(explicit.expect(SEQUENCE)?);
+            while !extensions.is_empty() {
+                let mut extension = DerReader::new(extensions.expect(SEQUENCE)?);
+                let oid = extension.expect(OID)?;
+                extension.optional(BOOLEAN)?;
+                let value = extension.expect(OCTET_STRING)?;
+
+                match oid {
+                    DELEGATION_USAGE => result.delegation_usage = true,
+                    KEY_USAGE => {
+                        // KeyUsage ::= BIT STRING; digitalSignature is bit 0
+                        let bits = DerReader::new(value).expect(BIT_STRING)?;
+                        result.digital_signature = bits
+                            .get(1)
+                            .is_some_and(|byte| byte & 0x80 != 0);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Some(result)
+    }
+}
+
+/// Parse a `UTCTime` (with a `year_len` of 2) or `GeneralizedTime` (4) in the
+/// DER form `YYMMDDHHMMSSZ`, returning seconds since the Unix epoch.
+fn parse_time(time: &[u8], year_len: usize) -> Option<u64> {
+    if time.len() != year_len + 11 || time.last() != Some(&b'Z') {
+        return None;
+    }
+
+    let digits = |range: core::ops::Range<usize>| -> Option<u64> {
+        time[range]
+            .iter()
+            .try_fold(0u64, |acc, d| {
+                d.is_ascii_digit()
+                    .then(|| acc * 10 + u64::from(d - b'0'))
+            })
+    };
+
+    let year = match year_len {
+        2 => match digits(0..2)? {
+            yy @ 0..=49 => 2000 + yy,
+            yy => 1900 + yy,
+        },
+        _ => digits(0..4)?,
+    };
+    let month = digits(year_len..year_len + 2)?;
+    let day = digits(year_len + 2..year_len + 4)?;
+    let hours = digits(year_len + 4..year_len + 6)?;
+    let minutes = digits(year_len + 6..year_len + 8)?;
+    let seconds = digits(year_len + 8..year_len + 10)?;
+
+    if year < 1970
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hours > 23
+        || minutes > 59
+        || seconds > 59
+    {
+        return None;
+    }
+
+    // Days since the epoch of a proleptic Gregorian date; see
+    // <https://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+    let (year, month) = match month {
+        1 | 2 => (year - 1, month + 9),
+        _ => (year, month - 3),
+    };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era).checked_sub(719_468)?;
+
+    Some(days * 86_400 + hours * 3_600 + minutes * 60 + seconds)
+}
+
+/// The schemes a certificate key may use to sign a delegated credential.
+static TLS13_SIGNATURE_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::ECDSA_NISTP521_SHA512,
+    SignatureScheme::ED25519,
+    SignatureScheme::ED448,
+    SignatureScheme::RSA_PSS_SHA512,
+    SignatureScheme::RSA_PSS_SHA384,
+    SignatureScheme::RSA_PSS_SHA256,
+];
+
+const BOOLEAN: u8 = 0x01;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const OID: u8 = 0x06;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const SEQUENCE: u8 = 0x30;
+const CONTEXT_0: u8 = 0xa0;
+const CONTEXT_3: u8 = 0xa3;
+
+/// DelegationUsage extension: 1.3.6.1.4.1.44363.44
+const DELEGATION_USAGE: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xda, 0x4b, 0x2c];
+
+/// KeyUsage extension: 2.5.29.15
+const KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x0f];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time(b"700101000000Z", 2), Some(0));
+        assert_eq!(parse_time(b"19700101000000Z", 4), Some(0));
+        assert_eq!(parse_time(b"000229120000Z", 2), Some(951_825_600));
+        assert_eq!(parse_time(b"20380119031408Z", 4), Some(2_147_483_648));
+        assert_eq!(parse_time(b"491231235959Z", 2), Some(2_524_607_999));
+        assert_eq!(parse_time(b"691231235959Z", 2), None);
+        assert_eq!(parse_time(b"700101000000", 2), None);
+        assert_eq!(parse_time(b"701301000000Z", 2), None);
+        assert_eq!(parse_time(b"7001010000a0Z", 2), None);
+    }
+
+    #[test]
+    fn credential_round_trip() {
+        let credential = DelegatedCredential {
+            valid_time: 86_400,
+            dc_cert_verify_algorithm: SignatureScheme::ED25519,
+            subject_public_key_info: SubjectPublicKeyInfoDer::from(alloc::vec![0x30, 0x00]),
+            algorithm: SignatureScheme::ECDSA_NISTP256_SHA256,
+            signature: alloc::vec![1, 2, 3],
+        };
+
+        let bytes = credential.to_bytes();
+        assert_eq!(bytes.len(), 4 + 2 + 3 + 2 + 2 + 2 + 3);
+        assert_eq!(DelegatedCredential::from_bytes(&bytes), Ok(credential));
+        assert!(DelegatedCredential::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,1544 @@
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug};
+use core::ops::{Deref, DerefMut};
+use core::time::Duration;
+use std::time::Instant;
+
+use pki_types::ServerName;
+
+use super::{
+    encode_plaintext, epoch_bits, next_traffic_secret, read_plaintext, split_record, Ack,
+    CookieKey, CookieState, EpochKeys, Record, RecordDecrypter, RecordEncrypter, RecordNumber,
+    RetransmitTimer,
+};
+use crate::client::{ClientConfig, ClientConnectionData};
+use crate::common_state::{CommonState, Protocol, Side, DEFAULT_BUFFER_LIMIT};
+use crate::conn::{ConnectionCore, SideData};
+use crate::crypto::tls13::OkmBlock;
+use crate::enums::{AlertDescription, ContentType, HandshakeType, ProtocolVersion};
+use crate::error::{Error, InvalidMessage, PeerMisbehaved};
+use crate::log::{debug, trace};
+use crate::msgs::alert::AlertMessagePayload;
+use crate::msgs::codec::{u24, Codec, Reader};
+use crate::msgs::deframer::buffers::{DeframerVecBuffer, Locator};
+use crate::msgs::enums::KeyUpdateRequest;
+use crate::msgs::handshake::{HandshakeMessagePayload, HandshakePayload};
+use crate::msgs::message::InboundPlainMessage;
+use crate::server::{ServerConfig, ServerConnectionData};
+use crate::tls13::Tls13CipherSuite;
+use crate::vecbuf::ChunkVecBuffer;
+
+/// A DTLS 1.3 client connection.
+pub struct DtlsClientConnection {
+    inner: DtlsConnectionCommon<ClientConnectionData>,
+}
+
+impl DtlsClientConnection {
+    /// Make a new DTLS client connection, connecting to `name`.
+    ///
+    /// The first flight is ready for [`DtlsConnectionCommon::poll_transmit()`]
+    /// once this returns.
+    pub fn new(config: Arc<ClientConfig>, name: ServerName<'static>) -> Result<Self, Error> {
+        if !config.supports_version(ProtocolVersion::TLSv1_3) {
+            return Err(Error::General(
+                "TLS 1.3 support is required for DTLS".into(),
+            ));
+        }
+
+        if !config.supports_protocol(Protocol::Dtls) {
+            return Err(Error::General(
+                "at least one ciphersuite must support DTLS".into(),
+            ));
+        }
+
+        if config.ech_configured() {
+            return Err(Error::General(
+                "encrypted client hello is not supported for DTLS".into(),
+            ));
+        }
+
+        let core = ConnectionCore::for_client(config, name, Vec::new(), Protocol::Dtls)?;
+        let mut inner = DtlsConnectionCommon::new(core, None);
+        inner.collect_output()?;
+        Ok(Self { inner })
+    }
+}
+
+impl Deref for DtlsClientConnection {
+    type Target = DtlsConnectionCommon<ClientConnectionData>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl DerefMut for DtlsClientConnection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl Debug for DtlsClientConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("dtls::DtlsClientConnection")
+            .finish()
+    }
+}
+
+/// A DTLS 1.3 server connection.
+pub struct DtlsServerConnection {
+    inner: DtlsConnectionCommon<ServerConnectionData>,
+}
+
+impl DtlsServerConnection {
+    /// Make a new DTLS server connection.
+    ///
+    /// The server keeps handshake state from the first `ClientHello` it
+    /// receives.  See [`Self::with_cookie()`] for a server that does not.
+    pub fn new(config: Arc<ServerConfig>) -> Result<Self, Error> {
+        Self::build(config, None)
+    }
+
+    /// Make a new DTLS server connection that requires the client to return
+    /// a cookie before it keeps any handshake state.
+    ///
+    /// A `ClientHello` without a valid cookie is answered with a
+    /// `HelloRetryRequest` carrying one.  This proves that the client can
+    /// receive datagrams at its source address, which limits the use of the
+    /// server to amplify traffic towards a forged address.
+    ///
+    /// `key` authenticates the cookies, and must be shared by all servers that
+    /// may receive the client's second `ClientHello`.  `client_address`
+    /// identifies the client's transport address, in any encoding, so that a
+    /// cookie is only accepted from the address it was issued to.  It may be
+    /// at most 65535 bytes long.
+    ///
+    /// Once [`Self::awaiting_cookie()`] returns true, this connection holds no
+    /// state the caller needs: it may be discarded after its datagrams are
+    /// sent, and a new one made for the client's next datagram.
+    pub fn with_cookie(
+        config: Arc<ServerConfig>,
+        key: Arc<CookieKey>,
+        client_address: Vec<u8>,
+    ) -> Result<Self, Error> {
+        if client_address.len() > usize::from(u16::MAX) {
+            return Err(Error::General("client address is too long".into()));
+        }
+
+        Self::build(
+            config,
+            Some(CookieState {
+                key,
+                client_address,
+                sent_retry: false,
+                accepted: None,
+            }),
+        )
+    }
+
+    fn build(config: Arc<ServerConfig>, cookie: Option<CookieState>) -> Result<Self, Error> {
+        if !config.supports_version(ProtocolVersion::TLSv1_3) {
+            return Err(Error::General(
+                "TLS 1.3 support is required for DTLS".into(),
+            ));
+        }
+
+        if !config.supports_protocol(Protocol::Dtls) {
+            return Err(Error::General(
+                "at least one ciphersuite must support DTLS".into(),
+            ));
+        }
+
+        let new_core = move || -> Result<ConnectionCore<ServerConnectionData>, Error> {
+            let mut core = ConnectionCore::for_server(Arc::clone(&config), Vec::new())?;
+            core.common_state.protocol = Protocol::Dtls;
+            Ok(core)
+        };
+
+        let mut core = new_core()?;
+        core.common_state.dtls.cookie = cookie;
+        Ok(Self {
+            inner: DtlsConnectionCommon::new(core, Some(Box::new(new_core))),
+        })
+    }
+
+    /// Returns true if the last `ClientHello` was answered with a stateless
+    /// `HelloRetryRequest`, and nothing was received since.
+    ///
+    /// See [`Self::with_cookie()`].
+    pub fn awaiting_cookie(&self) -> bool {
+        self.inner.awaiting_cookie
+    }
+
+    /// Retrieves the server name, if any, used to select the certificate and
+    /// private key.
+    ///
+    /// See [`crate::ServerConnection::server_name()`] for more details.
+    pub fn server_name(&self) -> Option<&str> {
+        self.inner.core.get_sni_str()
+    }
+}
+
+impl Deref for DtlsServerConnection {
+    type Target = DtlsConnectionCommon<ServerConnectionData>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl DerefMut for DtlsServerConnection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl Debug for DtlsServerConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("dtls::DtlsServerConnection")
+            .finish()
+    }
+}
+
+/// Makes a fresh server `ConnectionCore`, after a stateless `HelloRetryRequest`.
+type NewCore<Data> = Box<dyn Fn() -> Result<ConnectionCore<Data>, Error> + Send + Sync>;
+
+/// A shared interface for DTLS connections.
+///
+/// This reassembles, acknowledges and retransmits handshake messages, and
+/// protects records with the keys for each epoch.  The handshake itself is
+/// the same as for TLS 1.3.
+pub struct DtlsConnectionCommon<Data> {
+    core: ConnectionCore<Data>,
+    deframer_buffer: DeframerVecBuffer,
+    sendable_plaintext: ChunkVecBuffer,
+    new_core: Option<NewCore<Data>>,
+    awaiting_cookie: bool,
+    error: Option<Error>,
+
+    suite: Option<&'static Tls13CipherSuite>,
+    decrypters: Vec<Decrypter>,
+    /// Protected records for epochs we do not have keys for yet.
+    future_records: VecDeque<Vec<u8>>,
+    next_receive_seq: u16,
+    received_first_message: bool,
+    reassembly: BTreeMap<u16, Reassembly>,
+    /// Records carrying handshake messages, to acknowledge.
+    records_to_ack: Vec<RecordNumber>,
+    received_app_data: VecDeque<Vec<u8>>,
+    /// Application data received before the handshake completed.
+    held_app_data: VecDeque<Vec<u8>>,
+    read_secret: Option<OkmBlock>,
+    /// Whether a protected record was received, so the peer has handshake keys.
+    peer_sent_protected: bool,
+
+    next_plaintext_sequence_number: u64,
+    handshake_encrypter: Option<RecordEncrypter>,
+    traffic_encrypter: Option<RecordEncrypter>,
+    write_secret: Option<OkmBlock>,
+    next_send_seq: u16,
+    flights: VecDeque<Flight>,
+    next_flight_id: u64,
+    /// The fragment of the current flight carried by each record sent.
+    sent_records: BTreeMap<RecordNumber, (usize, usize)>,
+    transmit: VecDeque<Outgoing>,
+    timer: RetransmitTimer,
+    deadline: Option<Instant>,
+    max_datagram_size: usize,
+}
+
+#[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+impl<Data: SideData> DtlsConnectionCommon<Data> {
+    fn new(core: ConnectionCore<Data>, new_core: Option<NewCore<Data>>) -> Self {
+        Self {
+            core,
+            deframer_buffer: DeframerVecBuffer::default(),
+            sendable_plaintext: ChunkVecBuffer::new(Some(DEFAULT_BUFFER_LIMIT)),
+            new_core,
+            awaiting_cookie: false,
+            error: None,
+            suite: None,
+            decrypters: Vec::new(),
+            future_records: VecDeque::new(),
+            next_receive_seq: 0,
+            received_first_message: false,
+            reassembly: BTreeMap::new(),
+            records_to_ack: Vec::new(),
+            received_app_data: VecDeque::new(),
+            held_app_data: VecDeque::new(),
+            read_secret: None,
+            peer_sent_protected: false,
+            next_plaintext_sequence_number: 0,
+            handshake_encrypter: None,
+            traffic_encrypter: None,
+            write_secret: None,
+            next_send_seq: 0,
+            flights: VecDeque::new(),
+            next_flight_id: 0,
+            sent_records: BTreeMap::new(),
+            transmit: VecDeque::new(),
+            timer: RetransmitTimer::default(),
+            deadline: None,
+            max_datagram_size: DEFAULT_MAX_DATAGRAM_SIZE,
+        }
+    }
+
+    /// Process a datagram received from the peer.
+    ///
+    /// `datagram` is decrypted in place.  Records that cannot be decrypted or
+    /// parsed are discarded, as RFC 9147 requires.  Returns an error if the
+    /// connection failed: any alert describing the failure is then available
+    /// from [`Self::poll_transmit()`].
+    pub fn process_datagram(&mut self, datagram: &mut [u8]) -> Result<(), Error> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+
+        let result = self.process_records(datagram);
+        if let Err(error) = &result {
+            self.error = Some(error.clone());
+            // Send any alert the handshake produced, and nothing else.
+            self.flights.clear();
+            self.transmit
+                .retain(|outgoing| outgoing.typ == ContentType::Alert);
+            self.deadline = None;
+            self.collect_alerts();
+        }
+        result
+    }
+
+    /// Return the next datagram to send to the peer, if any.
+    ///
+    /// Call this until it returns `None` after constructing the connection,
+    /// and after each call to [`Self::process_datagram()`],
+    /// [`Self::handle_timeout()`] or a method that sends data.  `now` is used
+    /// to start the retransmission timer.
+    pub fn poll_transmit(&mut self, now: Instant) -> Option<Vec<u8>> {
+        self.collect_alerts();
+
+        let mut datagram = Vec::new();
+        while let Some(outgoing) = self.transmit.front() {
+            let len = outgoing.payload.len() + self.overhead(outgoing.epoch);
+            if !datagram.is_empty() && datagram.len() + len > self.max_datagram_size {
+                break;
+            }
+
+            let outgoing = self.transmit.pop_front().unwrap();
+            let result = match outgoing.epoch {
+                Epoch::Initial => {
+                    let sequence_number = self.next_plaintext_sequence_number;
+                    self.next_plaintext_sequence_number += 1;
+                    encode_plaintext(
+                        outgoing.typ,
+                        sequence_number,
+                        &outgoing.payload,
+                        &mut datagram,
+                    )
+                    .map(|()| RecordNumber {
+                        epoch: 0,
+                        sequence_number,
+                    })
+                }
+                Epoch::Handshake => match &mut self.handshake_encrypter {
+                    Some(encrypter) => {
+                        encrypter.encrypt(outgoing.typ, &outgoing.payload, &mut datagram)
+                    }
+                    None => continue,
+                },
+                Epoch::Traffic => match &mut self.traffic_encrypter {
+                    Some(encrypter) => {
+                        encrypter.encrypt(outgoing.typ, &outgoing.payload, &mut datagram)
+                    }
+                    None => continue,
+                },
+            };
+
+            match (result, outgoing.fragment) {
+                (Ok(record_number), Some((flight_id, message, fragment))) => {
+                    if self
+                        .flights
+                        .front()
+                        .is_some_and(|flight| flight.id == flight_id)
+                    {
+                        self.sent_records
+                            .insert(record_number, (message, fragment));
+                    }
+                }
+                (Ok(_), None) => {}
+                (Err(err), _) => debug!("Dropping DTLS record that cannot be sent: {err:?}"),
+            }
+        }
+
+        if self.deadline.is_none()
+            && self
+                .flights
+                .front()
+                .is_some_and(|f| f.started)
+        {
+            self.deadline = Some(now + self.timer.timeout());
+        }
+
+        (!datagram.is_empty()).then_some(datagram)
+    }
+
+    /// The time at which [`Self::handle_timeout()`] should next be called, if any.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Retransmit the unacknowledged part of the current flight, if the
+    /// retransmission timer expired at or before `now`.
+    pub fn handle_timeout(&mut self, now: Instant) {
+        if self
+            .deadline
+            .is_some_and(|deadline| deadline <= now)
+        {
+            trace!("DTLS retransmission timer expired");
+            self.deadline = None;
+            self.timer.back_off();
+            self.queue_flight();
+        }
+    }
+
+    /// Send `data` to the peer as one application data record.
+    ///
+    /// The peer receives `data` as one message from its
+    /// [`Self::recv_application_data()`].  Fails if the handshake has not
+    /// progressed far enough to send application data, or `data` does not fit
+    /// in one datagram.
+    pub fn send_application_data(&mut self, data: &[u8]) -> Result<(), Error> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+
+        let Some(encrypter) = &self.traffic_encrypter else {
+            return Err(Error::HandshakeNotComplete);
+        };
+        if !self
+            .core
+            .common_state
+            .may_send_application_data
+            || self.core.common_state.sent_fatal_alert
+        {
+            return Err(Error::HandshakeNotComplete);
+        }
+
+        if data.len() > self.max_application_data_size() {
+            return Err(Error::General(
+                "application data does not fit in one datagram".into(),
+            ));
+        }
+
+        // Update our keys well before the encrypter's sequence numbers or its
+        // confidentiality limit run out: the new keys are only used once the
+        // peer acknowledges the `KeyUpdate`.
+        let next = encrypter.next_sequence_number();
+        let limit = encrypter.confidentiality_limit();
+        if next >= limit {
+            return Err(Error::EncryptError);
+        } else if next >= limit / 2 {
+            self.refresh_traffic_keys()?;
+        }
+
+        self.transmit.push_back(Outgoing {
+            epoch: Epoch::Traffic,
+            typ: ContentType::ApplicationData,
+            payload: data.to_vec(),
+            fragment: None,
+        });
+        Ok(())
+    }
+
+    /// Return the next application data message received from the peer, if any.
+    ///
+    /// Up to 64 messages are held until they are read, and later ones are
+    /// discarded like any other lost datagram.
+    pub fn recv_application_data(&mut self) -> Option<Vec<u8>> {
+        self.received_app_data.pop_front()
+    }
+
+    /// Returns true once the peer sent a `close_notify` alert.
+    ///
+    /// No application data is received after this.
+    pub fn has_received_close_notify(&self) -> bool {
+        self.core
+            .common_state
+            .has_received_close_notify
+    }
+
+    /// Update our traffic keys, by sending a `KeyUpdate` message.
+    ///
+    /// The new keys are used once the peer acknowledges the message.  Does
+    /// nothing if an update is already in progress.
+    pub fn refresh_traffic_keys(&mut self) -> Result<(), Error> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+
+        if self.traffic_encrypter.is_none()
+            || !self
+                .core
+                .common_state
+                .may_send_application_data
+        {
+            return Err(Error::HandshakeNotComplete);
+        }
+
+        self.send_key_update(KeyUpdateRequest::UpdateNotRequested);
+        Ok(())
+    }
+
+    /// Set the largest datagram [`Self::poll_transmit()`] returns.
+    ///
+    /// The default is 1200 bytes, and values below 256 bytes are treated as
+    /// 256 bytes.  This applies to flights made after it is called.
+    pub fn set_max_datagram_size(&mut self, size: usize) {
+        self.max_datagram_size = size.max(MIN_DATAGRAM_SIZE);
+    }
+
+    /// The largest message [`Self::send_application_data()`] accepts, given
+    /// the current maximum datagram size and keys.
+    pub fn max_application_data_size(&self) -> usize {
+        self.max_datagram_size
+            .saturating_sub(self.overhead(Epoch::Traffic))
+    }
+
+    /// Set the initial retransmission timeout.
+    ///
+    /// The default is [`RetransmitTimer::DEFAULT_INITIAL_TIMEOUT`].
+    pub fn set_initial_timeout(&mut self, timeout: Duration) {
+        self.timer = RetransmitTimer::new(timeout);
+    }
+
+    /// Derives key material from the agreed connection secrets.
+    ///
+    /// See [`crate::ConnectionCommon::export_keying_material()`] for more information.
+    pub fn export_keying_material<T: AsMut<[u8]>>(
+        &self,
+        output: T,
+        label: &[u8],
+        context: Option<&[u8]>,
+    ) -> Result<T, Error> {
+        self.core
+            .export_keying_material(output, label, context)
+    }
+
+    fn process_records(&mut self, datagram: &mut [u8]) -> Result<(), Error> {
+        let mut started_flight = false;
+        let mut received_duplicate = false;
+
+        let mut rest = datagram;
+        while !rest.is_empty() {
+            let (record, next) = match split_record(rest, 0) {
+                Ok(split) => split,
+                Err(err) => {
+                    debug!("Discarding malformed DTLS record: {err:?}");
+                    break;
+                }
+            };
+            rest = next;
+            self.process_record(record, &mut started_flight, &mut received_duplicate)?;
+        }
+
+        // Keys installed while processing may allow earlier records to be read.
+        while self.retry_future_records(&mut started_flight, &mut received_duplicate)? {}
+
+        if !self.records_to_ack.is_empty() && !started_flight {
+            let retransmit = received_duplicate
+                && self
+                    .flights
+                    .front()
+                    .is_some_and(|flight| !flight.post_handshake);
+            match retransmit {
+                // The peer did not receive our flight: the duplicate it sent
+                // says so more quickly than our timer would.
+                true => {
+                    self.records_to_ack.clear();
+                    self.queue_flight();
+                }
+                false => self.send_ack(),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn retry_future_records(
+        &mut self,
+        started_flight: &mut bool,
+        received_duplicate: &mut bool,
+    ) -> Result<bool, Error> {
+        let ready = self
+            .future_records
+            .iter()
+            .position(|record| {
+                epoch_bits(record).is_some_and(|bits| self.decrypter_index(bits).is_some())
+            });
+
+        match ready {
+            Some(index) => {
+                let mut record = self
+                    .future_records
+                    .remove(index)
+                    .unwrap();
+                self.process_record(&mut record, started_flight, received_duplicate)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn process_record(
+        &mut self,
+        record: &mut [u8],
+        started_flight: &mut bool,
+        received_duplicate: &mut bool,
+    ) -> Result<(), Error> {
+        let Some(bits) = epoch_bits(record) else {
+            let record = match read_plaintext(record) {
+                Ok(record) => record,
+                Err(err) => {
+                    debug!("Discarding malformed DTLS record: {err:?}");
+                    return Ok(());
+                }
+            };
+
+            // Only hellos and alerts are sent in plaintext, and only during the handshake.
+            if !self.core.common_state.is_handshaking()
+                || !matches!(record.typ, ContentType::Handshake | ContentType::Alert)
+            {
+                return Ok(());
+            }
+
+            let Record {
+                typ,
+                record_number,
+                payload,
+            } = record;
+            let payload = payload.to_vec();
+            return self.process_content(
+                typ,
+                record_number,
+                &payload,
+                started_flight,
+                received_duplicate,
+            );
+        };
+
+        let Some(index) = self.decrypter_index(bits) else {
+            if self.future_records.len() < MAX_FUTURE_RECORDS {
+                self.future_records
+                    .push_back(record.to_vec());
+            }
+            return Ok(());
+        };
+
+        let decrypter = &mut self.decrypters[index];
+        let (typ, record_number, payload) = match decrypter.decrypter.decrypt(record) {
+            Ok(Some(record)) => {
+                self.peer_sent_protected = true;
+                (record.typ, record.record_number, record.payload.to_vec())
+            }
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                debug!("Discarding DTLS record that failed to decrypt: {err:?}");
+                decrypter.failures += 1;
+                if decrypter.failures >= decrypter.decrypter.integrity_limit() {
+                    return Err(Error::DecryptError);
+                }
+                return Ok(());
+            }
+        };
+
+        self.process_content(
+            typ,
+            record_number,
+            &payload,
+            started_flight,
+            received_duplicate,
+        )
+    }
+
+    fn process_content(
+        &mut self,
+        typ: ContentType,
+        record_number: RecordNumber,
+        payload: &[u8],
+        started_flight: &mut bool,
+        received_duplicate: &mut bool,
+    ) -> Result<(), Error> {
+        match typ {
+            ContentType::Handshake => {
+                self.process_handshake(record_number, payload, started_flight, received_duplicate)
+            }
+            ContentType::Alert => self.process_alert(payload),
+            ContentType::Ack if record_number.epoch != 0 => {
+                match Ack::read(&mut Reader::init(payload)) {
+                    Ok(ack) => self.process_ack(&ack),
+                    Err(err) => debug!("Discarding malformed ACK: {err:?}"),
+                }
+                Ok(())
+            }
+            ContentType::ApplicationData if record_number.epoch >= TRAFFIC_EPOCH => {
+                let queue = match self
+                    .core
+                    .common_state
+                    .may_receive_application_data
+                {
+                    true => &mut self.received_app_data,
+                    false => &mut self.held_app_data,
+                };
+                if queue.len() < MAX_HELD_APP_DATA
+                    && !self
+                        .core
+                        .common_state
+                        .has_received_close_notify
+                {
+                    queue.push_back(payload.to_vec());
+                }
+                Ok(())
+            }
+            _ => {
+                debug!("Discarding unexpected DTLS {typ:?} record in epoch {record_number:?}");
+                Ok(())
+            }
+        }
+    }
+
+    fn process_alert(&mut self, payload: &[u8]) -> Result<(), Error> {
+        let alert = match AlertMessagePayload::read(&mut Reader::init(payload)) {
+            Ok(alert) => alert,
+            Err(err) => {
+                debug!("Discarding malformed alert: {err:?}");
+                return Ok(());
+            }
+        };
+
+        self.core
+            .common_state
+            .process_alert(&alert)
+    }
+
+    fn process_handshake(
+        &mut self,
+        record_number: RecordNumber,
+        mut payload: &[u8],
+        started_flight: &mut bool,
+        received_duplicate: &mut bool,
+    ) -> Result<(), Error> {
+        while !payload.is_empty() {
+            let mut r = Reader::init(payload);
+            let Ok(header) = FragmentHeader::read(&mut r) else {
+                debug!("Discarding malformed handshake fragment");
+                return Ok(());
+            };
+            let Some(body) = r.take(header.fragment_length) else {
+                debug!("Discarding truncated handshake fragment");
+                return Ok(());
+            };
+            payload = r.rest();
+
+            if header.fragment_offset + header.fragment_length > header.length
+                || header.length > MAX_HANDSHAKE_SIZE
+            {
+                debug!("Discarding handshake fragment with invalid length");
+                return Ok(());
+            }
+
+            if !self.allowed_in_epoch(header.typ, record_number.epoch) {
+                match record_number.epoch {
+                    // Unauthenticated, so may not have been sent by the peer.
+                    0 => {
+                        debug!("Discarding plaintext {:?}", header.typ);
+                        return Ok(());
+                    }
+                    _ => {
+                        return Err(self.core.common_state.send_fatal_alert(
+                            AlertDescription::UnexpectedMessage,
+                            PeerMisbehaved::HandshakeMessageInWrongEpoch,
+                        ));
+                    }
+                }
+            }
+
+            // A server's first `ClientHello` may follow a stateless `HelloRetryRequest`,
+            // whose `message_seq` and record sequence number the server takes from it.
+            if !self.received_first_message && self.core.common_state.side == Side::Server {
+                if header.typ != HandshakeType::ClientHello {
+                    return Ok(());
+                }
+                self.received_first_message = true;
+                self.next_receive_seq = header.message_seq;
+                self.next_send_seq = header.message_seq;
+            }
+            if header.typ == HandshakeType::ClientHello
+                && self.core.common_state.side == Side::Server
+            {
+                self.next_plaintext_sequence_number = self
+                    .next_plaintext_sequence_number
+                    .max(record_number.sequence_number);
+            }
+
+            self.records_to_ack.push(record_number);
+
+            if header.message_seq < self.next_receive_seq {
+                *received_duplicate = true;
+                continue;
+            }
+
+            if header.message_seq - self.next_receive_seq >= MAX_FUTURE_MESSAGES {
+                continue;
+            }
+
+            // Any part of the peer's next flight acknowledges our last one.
+            if self
+                .flights
+                .front()
+                .is_some_and(|flight| !flight.post_handshake)
+            {
+                self.flight_acknowledged();
+            }
+            self.awaiting_cookie = false;
+
+            let entry = self
+                .reassembly
+                .entry(header.message_seq)
+                .or_insert_with(|| Reassembly::new(&header, record_number.epoch));
+            if entry.typ != header.typ
+                || entry.body.len() != header.length
+                || entry.epoch != record_number.epoch
+            {
+                debug!("Discarding inconsistent handshake fragment");
+                continue;
+            }
+            entry.insert(header.fragment_offset, body);
+
+            while let Some(message) = self
+                .reassembly
+                .get(&self.next_receive_seq)
+                .filter(|message| message.is_complete())
+            {
+                let typ = message.typ;
+                let message = self
+                    .reassembly
+                    .remove(&self.next_receive_seq)
+                    .unwrap();
+                self.next_receive_seq = self.next_receive_seq.wrapping_add(1);
+
+                let flights = self.flights.len();
+                // `KeyUpdate` changes our epochs, so is handled here once the
+                // handshake is done.  Before then the handshake rejects it.
+                match typ {
+                    HandshakeType::KeyUpdate
+                        if self
+                            .core
+                            .common_state
+                            .may_receive_application_data =>
+                    {
+                        self.process_key_update(&message.body)?
+                    }
+                    _ => self.process_message(typ, &message.body)?,
+                }
+
+                if self
+                    .flights
+                    .iter()
+                    .skip(flights)
+                    .any(|flight| !flight.post_handshake)
+                {
+                    *started_flight = true;
+                }
+
+                if self.restart()? {
+                    *started_flight = true;
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn allowed_in_epoch(&self, typ: HandshakeType, epoch: u64) -> bool {
+        match (Epoch::for_message(typ), epoch) {
+            (Epoch::Initial, 0) => true,
+            (Epoch::Handshake, HANDSHAKE_EPOCH) => true,
+            (Epoch::Traffic, epoch) => epoch >= TRAFFIC_EPOCH,
+            _ => false,
+        }
+    }
+
+    /// Pass a complete handshake message to the TLS 1.3 handshake, in the
+    /// form its transcript uses.  See RFC 9147 section 5.2.
+    fn process_message(&mut self, typ: HandshakeType, body: &[u8]) -> Result<(), Error> {
+        let mut message = Vec::with_capacity(4 + body.len());
+        typ.encode(&mut message);
+        u24(body.len() as u32).encode(&mut message);
+        message.extend_from_slice(body);
+
+        let range = self.deframer_buffer.extend(&message);
+        self.core.hs_deframer.input_message(
+            InboundPlainMessage {
+                typ: ContentType::Handshake,
+                version: ProtocolVersion::TLSv1_3,
+                payload: &self.deframer_buffer.filled()[range.clone()],
+            },
+            &Locator::new(self.deframer_buffer.filled()),
+            range.end,
+        );
+
+        self.core
+            .hs_deframer
+            .coalesce(self.deframer_buffer.filled_mut())?;
+
+        let result = self
+            .core
+            .process_new_packets(&mut self.deframer_buffer, &mut self.sendable_plaintext);
+        self.collect_output()?;
+        result.map(|_| ())
+    }
+
+    fn process_key_update(&mut self, body: &[u8]) -> Result<(), Error> {
+        let (Some(suite), Some(read_secret)) = (self.suite, &self.read_secret) else {
+            return Err(Error::HandshakeNotComplete);
+        };
+
+        let mut r = Reader::init(body);
+        let request = KeyUpdateRequest::read(&mut r);
+        if request.is_err() || r.any_left() {
+            return Err(self.core.common_state.send_fatal_alert(
+                AlertDescription::DecodeError,
+                InvalidMessage::InvalidKeyUpdate,
+            ));
+        }
+
+        let epoch = self
+            .decrypters
+            .iter()
+            .map(|d| d.decrypter.epoch())
+            .max()
+            .unwrap_or(TRAFFIC_EPOCH)
+            + 1;
+        let read_secret = next_traffic_secret(suite, read_secret);
+        self.install_decrypter(EpochKeys::new(suite, epoch, &read_secret)?);
+        self.read_secret = Some(read_secret);
+
+        if let Ok(KeyUpdateRequest::UpdateRequested) = request {
+            self.send_key_update(KeyUpdateRequest::UpdateNotRequested);
+        }
+        Ok(())
+    }
+
+    fn process_ack(&mut self, ack: &Ack) {
+        let Some(flight) = self.flights.front_mut() else {
+            return;
+        };
+
+        for record_number in &ack.record_numbers {
+            if let Some((message, fragment)) = self.sent_records.get(record_number) {
+                flight.messages[*message].fragments[*fragment].acked = true;
+            }
+        }
+
+        if flight.messages.iter().all(|message| {
+            message
+                .fragments
+                .iter()
+                .all(|f| f.acked)
+        }) {
+            self.flight_acknowledged();
+        }
+    }
+
+    /// Forget the current flight, and start the next one if any.
+    fn flight_acknowledged(&mut self) {
+        let Some(flight) = self.flights.pop_front() else {
+            return;
+        };
+        trace!("DTLS flight {} acknowledged", flight.id);
+
+        let id = flight.id;
+        self.transmit
+            .retain(|outgoing| !matches!(outgoing.fragment, Some((flight, _, _)) if flight == id));
+        self.sent_records.clear();
+        self.timer.reset();
+        self.deadline = None;
+
+        if flight.key_update {
+            self.switch_write_epoch();
+        }
+
+        self.queue_flight();
+    }
+
+    /// Use our next traffic keys, once the peer acknowledged our `KeyUpdate`.
+    fn switch_write_epoch(&mut self) {
+        let (Some(suite), Some(secret), Some(encrypter)) =
+            (self.suite, &self.write_secret, &self.traffic_encrypter)
+        else {
+            return;
+        };
+
+        let epoch = encrypter.epoch() + 1;
+        let secret = next_traffic_secret(suite, secret);
+        match EpochKeys::new(suite, epoch, &secret) {
+            Ok(keys) => {
+                self.traffic_encrypter = Some(RecordEncrypter::new(keys));
+                self.write_secret = Some(secret);
+            }
+            Err(err) => debug!("Cannot update DTLS traffic keys: {err:?}"),
+        }
+    }
+
+    fn send_key_update(&mut self, request: KeyUpdateRequest) {
+        if self
+            .flights
+            .iter()
+            .any(|flight| flight.key_update)
+        {
+            return;
+        }
+
+        let message = HandshakeMessagePayload {
+            typ: HandshakeType::KeyUpdate,
+            payload: HandshakePayload::KeyUpdate(request),
+        };
+        self.core
+            .common_state
+            .dtls
+            .hs_queue
+            .push_back(message.get_encoding());
+        if let Err(err) = self.collect_output() {
+            debug!("Cannot send KeyUpdate: {err:?}");
+        }
+    }
+
+    /// Take keys and handshake messages produced by the TLS 1.3 handshake.
+    fn collect_output(&mut self) -> Result<(), Error> {
+        let common = &mut self.core.common_state;
+        if let Some(suite) = common
+            .suite
+            .and_then(|suite| suite.tls13())
+        {
+            self.suite = Some(suite);
+        }
+
+        let side = common.side;
+        let handshake_secrets = common.dtls.handshake_secrets.take();
+        let traffic_secrets = common.dtls.traffic_secrets.take();
+        let messages = common
+            .dtls
+            .hs_queue
+            .drain(..)
+            .collect::<Vec<_>>();
+
+        if let (Some(secrets), Some(suite)) = (handshake_secrets, self.suite) {
+            let (write, read) = (*secrets).split(side);
+            self.handshake_encrypter = Some(RecordEncrypter::new(EpochKeys::new(
+                suite,
+                HANDSHAKE_EPOCH,
+                &write,
+            )?));
+            self.install_decrypter(EpochKeys::new(suite, HANDSHAKE_EPOCH, &read)?);
+        }
+
+        if let (Some(secrets), Some(suite)) = (traffic_secrets, self.suite) {
+            let (write, read) = (*secrets).split(side);
+            self.traffic_encrypter = Some(RecordEncrypter::new(EpochKeys::new(
+                suite,
+                TRAFFIC_EPOCH,
+                &write,
+            )?));
+            self.install_decrypter(EpochKeys::new(suite, TRAFFIC_EPOCH, &read)?);
+            self.write_secret = Some(write);
+            self.read_secret = Some(read);
+        }
+
+        if self
+            .core
+            .common_state
+            .may_receive_application_data
+        {
+            self.received_app_data
+                .extend(self.held_app_data.drain(..));
+        }
+
+        let mut flight = Flight {
+            id: self.next_flight_id,
+            messages: Vec::new(),
+            post_handshake: true,
+            key_update: false,
+            started: false,
+        };
+        for encoded in messages {
+            let mut r = Reader::init(&encoded);
+            while let (Ok(typ), Ok(len)) = (HandshakeType::read(&mut r), u24::read(&mut r)) {
+                let Some(body) = r.take(usize::from(len)) else {
+                    break;
+                };
+                let epoch = Epoch::for_message(typ);
+                flight.post_handshake &= epoch == Epoch::Traffic;
+                flight.key_update |= typ == HandshakeType::KeyUpdate;
+                flight.messages.push(FlightMessage {
+                    epoch,
+                    typ,
+                    message_seq: self.next_send_seq,
+                    body: body.to_vec(),
+                    fragments: Vec::new(),
+                });
+                self.next_send_seq = self.next_send_seq.wrapping_add(1);
+            }
+        }
+
+        if !flight.messages.is_empty() {
+            self.next_flight_id += 1;
+            self.flights.push_back(flight);
+            if self.flights.len() == 1 {
+                self.queue_flight();
+            }
+        }
+
+        self.collect_alerts();
+        Ok(())
+    }
+
+    fn collect_alerts(&mut self) {
+        // Use keys the peer is known to have, so that it can read the alert.
+        let epoch = match (&self.traffic_encrypter, &self.handshake_encrypter) {
+            (Some(_), _) if !self.core.common_state.is_handshaking() => Epoch::Traffic,
+            (_, Some(_)) if self.peer_sent_protected => Epoch::Handshake,
+            _ => Epoch::Initial,
+        };
+
+        while let Some(alert) = self
+            .core
+            .common_state
+            .dtls
+            .alerts
+            .pop_front()
+        {
+            let mut payload = Vec::new();
+            alert.encode(&mut payload);
+            self.transmit.push_back(Outgoing {
+                epoch,
+                typ: ContentType::Alert,
+                payload,
+                fragment: None,
+            });
+        }
+    }
+
+    /// Queue the unacknowledged fragments of the current flight for transmission.
+    fn queue_flight(&mut self) {
+        let max_datagram_size = self.max_datagram_size;
+        let overheads = [
+            self.overhead(Epoch::Initial),
+            self.overhead(Epoch::Handshake),
+            self.overhead(Epoch::Traffic),
+        ];
+        let Some(flight) = self.flights.front_mut() else {
+            return;
+        };
+
+        if !flight.started {
+            for message in &mut flight.messages {
+                let overhead = overheads[message.epoch as usize];
+                let max_fragment = max_datagram_size
+                    .saturating_sub(overhead + FRAGMENT_HEADER_LEN)
+                    .max(1);
+                message.fragments = fragment_ranges(message.body.len(), max_fragment)
+                    .map(|(offset, len)| Fragment {
+                        offset,
+                        len,
+                        acked: false,
+                    })
+                    .collect();
+            }
+            flight.started = true;
+        }
+
+        let id = flight.id;
+        self.transmit
+            .retain(|outgoing| !matches!(outgoing.fragment, Some((flight, _, _)) if flight == id));
+
+        for (m, message) in flight.messages.iter().enumerate() {
+            for (f, fragment) in message.fragments.iter().enumerate() {
+                if fragment.acked {
+                    continue;
+                }
+
+                let mut payload = Vec::with_capacity(FRAGMENT_HEADER_LEN + fragment.len);
+                FragmentHeader {
+                    typ: message.typ,
+                    length: message.body.len(),
+                    message_seq: message.message_seq,
+                    fragment_offset: fragment.offset,
+                    fragment_length: fragment.len,
+                }
+                .encode(&mut payload);
+                payload.extend_from_slice(
+                    &message.body[fragment.offset..fragment.offset + fragment.len],
+                );
+
+                self.transmit.push_back(Outgoing {
+                    epoch: message.epoch,
+                    typ: ContentType::Handshake,
+                    payload,
+                    fragment: Some((id, m, f)),
+                });
+            }
+        }
+    }
+
+    fn send_ack(&mut self) {
+        let epoch = match (&self.traffic_encrypter, &self.handshake_encrypter) {
+            (Some(_), _) if !self.core.common_state.is_handshaking() => Epoch::Traffic,
+            (_, Some(_)) => Epoch::Handshake,
+            _ => {
+                self.records_to_ack.clear();
+                return;
+            }
+        };
+
+        // Acknowledge the most recent records, as many as fit in one record.
+        let max = (self
+            .max_datagram_size
+            .saturating_sub(self.overhead(epoch) + 2))
+            / 16;
+        let skip = self
+            .records_to_ack
+            .len()
+            .saturating_sub(max);
+        let ack = Ack {
+            record_numbers: self
+                .records_to_ack
+                .drain(..)
+                .skip(skip)
+                .collect(),
+        };
+
+        self.transmit.push_back(Outgoing {
+            epoch,
+            typ: ContentType::Ack,
+            payload: ack.get_encoding(),
+            fragment: None,
+        });
+    }
+
+    /// Start afresh after a stateless `HelloRetryRequest`.
+    ///
+    /// Returns true if this happened.
+    fn restart(&mut self) -> Result<bool, Error> {
+        let sent_retry = self
+            .core
+            .common_state
+            .dtls
+            .cookie
+            .as_ref()
+            .is_some_and(|cookie| cookie.sent_retry);
+        let (true, Some(new_core)) = (sent_retry, &self.new_core) else {
+            return Ok(false);
+        };
+
+        debug!("Sent stateless HelloRetryRequest");
+        let mut core = new_core()?;
+        core.common_state.dtls.cookie = self
+            .core
+            .common_state
+            .dtls
+            .cookie
+            .take()
+            .map(|cookie| CookieState {
+                sent_retry: false,
+                ..cookie
+            });
+        self.core = core;
+        self.deframer_buffer = DeframerVecBuffer::default();
+
+        // The `HelloRetryRequest` is in `transmit`, and is not retransmitted.
+        self.flights.clear();
+        self.sent_records.clear();
+        self.deadline = None;
+        self.suite = None;
+        self.received_first_message = false;
+        self.reassembly.clear();
+        self.records_to_ack.clear();
+        self.awaiting_cookie = true;
+        Ok(true)
+    }
+
+    fn install_decrypter(&mut self, keys: EpochKeys) {
+        let bits = (keys.epoch() & 3) as u8;
+        self.decrypters
+            .retain(|d| (d.decrypter.epoch() & 3) as u8 != bits);
+        self.decrypters.push(Decrypter {
+            decrypter: RecordDecrypter::new(keys),
+            failures: 0,
+        });
+    }
+
+    fn decrypter_index(&self, bits: u8) -> Option<usize> {
+        self.decrypters
+            .iter()
+            .position(|d| (d.decrypter.epoch() & 3) as u8 == bits)
+    }
+
+    /// The number of bytes a record adds to its payload in `epoch`.
+    fn overhead(&self, epoch: Epoch) -> usize {
+        let encrypter = match epoch {
+            Epoch::Initial => return PLAINTEXT_OVERHEAD,
+            Epoch::Handshake => &self.handshake_encrypter,
+            Epoch::Traffic => &self.traffic_encrypter,
+        };
+        encrypter
+            .as_ref()
+            .map_or(MAX_PROTECTED_OVERHEAD, |encrypter| encrypter.overhead())
+    }
+}
+
+impl<Data> Deref for DtlsConnectionCommon<Data> {
+    type Target = CommonState;
+
+    fn deref(&self) -> &Self::Target {
+        &self.core.common_state
+    }
+}
+
+impl<Data> DerefMut for DtlsConnectionCommon<Data> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.core.common_state
+    }
+}
+
+impl super::Secrets {
+    /// Split into our write and read secrets.
+    fn split(self, side: Side) -> (OkmBlock, OkmBlock) {
+        match side {
+            Side::Client => (self.client, self.server),
+            Side::Server => (self.server, self.client),
+        }
+    }
+}
+
+/// The epochs records are sent in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Epoch {
+    /// Epoch 0, for hellos.
+    Initial = 0,
+    /// Epoch 2, for the rest of the handshake.
+    Handshake = 1,
+    /// Epoch 3 or later.
+    Traffic = 2,
+}
+
+impl Epoch {
+    fn for_message(typ: HandshakeType) -> Self {
+        match typ {
+            HandshakeType::ClientHello | HandshakeType::ServerHello => Self::Initial,
+            HandshakeType::NewSessionTicket | HandshakeType::KeyUpdate => Self::Traffic,
+            _ => Self::Handshake,
+        }
+    }
+}
+
+struct Decrypter {
+    decrypter: RecordDecrypter,
+    failures: u64,
+}
+
+/// A record waiting to be sent.
+struct Outgoing {
+    epoch: Epoch,
+    typ: ContentType,
+    payload: Vec<u8>,
+    /// The flight, message and fragment carried, for handshake records.
+    fragment: Option<(u64, usize, usize)>,
+}
+
+/// A flight of handshake messages, retransmitted until acknowledged.
+struct Flight {
+    id: u64,
+    messages: Vec<FlightMessage>,
+    /// Post-handshake flights are only acknowledged by `ACK`s.  Others are
+    /// also acknowledged by the peer's next flight.
+    post_handshake: bool,
+    key_update: bool,
+    /// Whether the flight was fragmented and queued for transmission.
+    started: bool,
+}
+
+struct FlightMessage {
+    epoch: Epoch,
+    typ: HandshakeType,
+    message_seq: u16,
+    body: Vec<u8>,
+    fragments: Vec<Fragment>,
+}
+
+struct Fragment {
+    offset: usize,
+    len: usize,
+    acked: bool,
+}
+
+fn fragment_ranges(len: usize, max_fragment: usize) -> impl Iterator<Item = (usize, usize)> {
+    let count = ((len + max_fragment - 1) / max_fragment).max(1);
+    (0..count).map(move |i| {
+        let offset = i * max_fragment;
+        (offset, (len - offset).min(max_fragment))
+    })
+}
+
+/// A handshake message being reassembled from its fragments.
+struct Reassembly {
+    epoch: u64,
+    typ: HandshakeType,
+    body: Vec<u8>,
+    /// The received ranges of `body`: sorted, and not overlapping or adjacent.
+    received: Vec<(usize, usize)>,
+}
+
+impl Reassembly {
+    fn new(header: &FragmentHeader, epoch: u64) -> Self {
+        Self {
+            epoch,
+            typ: header.typ,
+            body: vec![0; header.length],
+            received: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, offset: usize, data: &[u8]) {
+        let end = offset + data.len();
+        self.body[offset..end].copy_from_slice(data);
+
+        let (mut start, mut end) = (offset, end);
+        self.received.retain(|&(s, e)| {
+            let overlaps = s <= end && start <= e;
+            if overlaps {
+                start = start.min(s);
+                end = end.max(e);
+            }
+            !overlaps
+        });
+        let index = self
+            .received
+            .partition_point(|&(s, _)| s < start);
+        self.received
+            .insert(index, (start, end));
+    }
+
+    fn is_complete(&self) -> bool {
+        self.body.is_empty() || self.received[..] == [(0, self.body.len())]
+    }
+}
+
+/// The DTLS handshake message header, describing one fragment.
+///
+/// See [RFC 9147 section 5.2].
+///
+/// [RFC 9147 section 5.2]: https://www.rfc-editor.org/rfc/rfc9147#section-5.2
+#[derive(Debug)]
+struct FragmentHeader {
+    typ: HandshakeType,
+    length: usize,
+    message_seq: u16,
+    fragment_offset: usize,
+    fragment_length: usize,
+}
+
+impl Codec<'_> for FragmentHeader {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.typ.encode(bytes);
+        u24(self.length as u32).encode(bytes);
+        self.message_seq.encode(bytes);
+        u24(self.fragment_offset as u32).encode(bytes);
+        u24(self.fragment_length as u32).encode(bytes);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, InvalidMessage> {
+        Ok(Self {
+            typ: HandshakeType::read(r)?,
+            length: u24::read(r)?.into(),
+            message_seq: u16::read(r)?,
+            fragment_offset: u24::read(r)?.into(),
+            fragment_length: u24::read(r)?.into(),
+        })
+    }
+}
+
+const HANDSHAKE_EPOCH: u64 = 2;
+const TRAFFIC_EPOCH: u64 = 3;
+
+const DEFAULT_MAX_DATAGRAM_SIZE: usize = 1200;
+const MIN_DATAGRAM_SIZE: usize = 256;
+const FRAGMENT_HEADER_LEN: usize = 12;
+
+/// Type, version, epoch, sequence number and length.
+const PLAINTEXT_OVERHEAD: usize = 13;
+/// Unified header, content type and a 16 byte tag.
+const MAX_PROTECTED_OVERHEAD: usize = 5 + 1 + 16;
+
+const MAX_HANDSHAKE_SIZE: usize = 0xffff;
+const MAX_FUTURE_MESSAGES: u16 = 16;
+const MAX_FUTURE_RECORDS: usize = 16;
+const MAX_HELD_APP_DATA: usize = 64;
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    use super::{fragment_ranges, FragmentHeader, Reassembly};
+    use crate::enums::HandshakeType;
+
+    fn reassembly(length: usize) -> Reassembly {
+        Reassembly::new(
+            &FragmentHeader {
+                typ: HandshakeType::Certificate,
+                length,
+                message_seq: 2,
+                fragment_offset: 0,
+                fragment_length: 0,
+            },
+            2,
+        )
+    }
+
+    #[test]
+    fn reassembles_out_of_order_and_overlapping_fragments() {
+        let message = (0..100u8).collect::<Vec<_>>();
+        let mut r = reassembly(message.len());
+
+        r.insert(60, &message[60..]);
+        assert!(!r.is_complete());
+        r.insert(10, &message[10..30]);
+        r.insert(25, &message[25..61]);
+        assert_eq!(r.received, vec![(10, 100)]);
+        assert!(!r.is_complete());
+        r.insert(0, &message[0..10]);
+        assert!(r.is_complete());
+        assert_eq!(r.body, message);
+    }
+
+    #[test]
+    fn empty_message_is_complete() {
+        assert!(reassembly(0).is_complete());
+    }
+
+    #[test]
+    fn fragments_cover_message() {
+        assert_eq!(
+            fragment_ranges(10, 4).collect::<Vec<_>>(),
+            vec![(0, 4), (4, 4), (8, 2)]
+        );
+        assert_eq!(
+            fragment_ranges(8, 4).collect::<Vec<_>>(),
+            vec![(0, 4), (4, 4)]
+        );
+        assert_eq!(fragment_ranges(0, 4).collect::<Vec<_>>(), vec![(0, 0)]);
+    }
+}
@@ -0,0 +1,274 @@
+//! Stateless `HelloRetryRequest` cookies.
+//!
+//! A server using these answers a `ClientHello` without a cookie with a
+//! `HelloRetryRequest` carrying one, and keeps no state for the client.  The
+//! cookie holds what the server needs to continue the handshake from the
+//! second `ClientHello`: the hash of the first one, and the cipher suite and
+//! key exchange group chosen for it.  It is authenticated, together with the
+//! client's address, using a key known only to the server.
+//!
+//! See [RFC 9147 section 5.1].
+//!
+//! [RFC 9147 section 5.1]: https://www.rfc-editor.org/rfc/rfc9147#section-5.1
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+
+use pki_types::UnixTime;
+use subtle::ConstantTimeEq;
+
+use crate::crypto::tls13::OkmBlock;
+use crate::crypto::SecureRandom;
+use crate::enums::CipherSuite;
+use crate::msgs::base::{PayloadU16, PayloadU8};
+use crate::msgs::codec::{Codec, Reader};
+use crate::msgs::enums::NamedGroup;
+use crate::rand::GetRandomFailed;
+use crate::tls13::Tls13CipherSuite;
+
+/// The key a DTLS server uses to authenticate its `HelloRetryRequest` cookies.
+///
+/// All the servers that may receive a client's second `ClientHello` must
+/// share the key.  It should be replaced from time to time; cookies are only
+/// accepted for [`CookieKey::LIFETIME`] in any case.
+pub struct CookieKey {
+    secret: OkmBlock,
+}
+
+impl CookieKey {
+    /// How long after it is issued a cookie is accepted.
+    pub const LIFETIME: u64 = 60;
+
+    /// Make a new key from `secure_random`.
+    pub fn new(secure_random: &dyn SecureRandom) -> Result<Self, GetRandomFailed> {
+        let mut secret = [0u8; 32];
+        secure_random.fill(&mut secret)?;
+        Ok(Self::from_secret(&secret))
+    }
+
+    /// Make a key from an existing `secret`, shared between servers.
+    pub fn from_secret(secret: &[u8; 32]) -> Self {
+        Self {
+            secret: OkmBlock::new(secret),
+        }
+    }
+}
+
+impl fmt::Debug for CookieKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CookieKey")
+            .finish_non_exhaustive()
+    }
+}
+
+/// How a server issues and checks cookies for one client.
+pub(crate) struct CookieState {
+    pub(crate) key: Arc<CookieKey>,
+    /// The client's transport address, in any form that identifies it.
+    pub(crate) client_address: Vec<u8>,
+    /// Whether the handshake answered a `ClientHello` with a stateless
+    /// `HelloRetryRequest`, so the connection should start afresh.
+    pub(crate) sent_retry: bool,
+    /// A valid cookie received from the client, and its sealed encoding.
+    pub(crate) accepted: Option<(Cookie, Vec<u8>)>,
+}
+
+impl CookieState {
+    /// Encode and authenticate `cookie`, using the HMAC of `suite`.
+    pub(crate) fn seal(&self, cookie: &Cookie, suite: &'static Tls13CipherSuite) -> Vec<u8> {
+        let mut sealed = cookie.get_encoding();
+        let tag = self.tag(&sealed, suite);
+        sealed.extend_from_slice(tag.as_ref());
+        sealed
+    }
+
+    /// Check and decode a cookie made by [`Self::seal()`].
+    ///
+    /// Returns `None` if the cookie was not made for `suite` and this client,
+    /// or has expired.
+    pub(crate) fn open(
+        &self,
+        sealed: &[u8],
+        suite: &'static Tls13CipherSuite,
+        now: UnixTime,
+    ) -> Option<Cookie> {
+        let mut r = Reader::init(sealed);
+        let cookie = Cookie::read(&mut r).ok()?;
+        if cookie.suite != suite.common.suite {
+            return None;
+        }
+        let (contents, tag) = sealed.split_at(r.used());
+
+        let expected = self.tag(contents, suite);
+        if !bool::from(ConstantTimeEq::ct_eq(expected.as_ref(), tag)) {
+            return None;
+        }
+
+        let age = now
+            .as_secs()
+            .checked_sub(cookie.issued.as_secs())?;
+        (age <= CookieKey::LIFETIME).then_some(cookie)
+    }
+
+    fn tag(&self, contents: &[u8], suite: &'static Tls13CipherSuite) -> impl AsRef<[u8]> {
+        let mut message = Vec::with_capacity(2 + self.client_address.len() + contents.len());
+        PayloadU16::encode_slice(&self.client_address, &mut message);
+        message.extend_from_slice(contents);
+        suite
+            .hkdf_provider
+            .hmac_sign(&self.key.secret, &message)
+    }
+}
+
+/// The contents of a stateless `HelloRetryRequest` cookie.
+#[derive(Debug)]
+pub(crate) struct Cookie {
+    pub(crate) issued: UnixTime,
+    pub(crate) suite: CipherSuite,
+    /// The group the `HelloRetryRequest` asked for a key share for, if any.
+    pub(crate) group: Option<NamedGroup>,
+    /// The transcript hash of the first `ClientHello`.
+    pub(crate) ch1_hash: Vec<u8>,
+}
+
+impl Codec<'_> for Cookie {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.issued.as_secs().encode(bytes);
+        self.suite.encode(bytes);
+        match self.group {
+            Some(group) => {
+                1u8.encode(bytes);
+                group.encode(bytes);
+            }
+            None => 0u8.encode(bytes),
+        }
+        PayloadU8::encode_slice(&self.ch1_hash, bytes);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, crate::error::InvalidMessage> {
+        let issued = UnixTime::since_unix_epoch(core::time::Duration::from_secs(u64::read(r)?));
+        let suite = CipherSuite::read(r)?;
+        let group = match u8::read(r)? {
+            0 => None,
+            _ => Some(NamedGroup::read(r)?),
+        };
+        let ch1_hash = PayloadU8::read(r)?.0;
+        Ok(Self {
+            issued,
+            suite,
+            group,
+            ch1_hash,
+        })
+    }
+}
+
+#[cfg(test)]
+#[macro_rules_attribute::apply(test_for_each_provider)]
+mod tests {
+    use core::time::Duration;
+    use std::prelude::v1::*;
+    use std::sync::Arc;
+
+    use pki_types::UnixTime;
+
+    use super::provider::tls13::{
+        TLS13_AES_128_GCM_SHA256_INTERNAL, TLS13_CHACHA20_POLY1305_SHA256_INTERNAL,
+    };
+    use super::{Cookie, CookieKey, CookieState};
+    use crate::enums::CipherSuite;
+    use crate::msgs::enums::NamedGroup;
+
+    fn state(address: &[u8]) -> CookieState {
+        CookieState {
+            key: Arc::new(CookieKey::from_secret(&[7; 32])),
+            client_address: address.to_vec(),
+            sent_retry: false,
+            accepted: None,
+        }
+    }
+
+    fn cookie(issued: UnixTime) -> Cookie {
+        Cookie {
+            issued,
+            suite: CipherSuite::TLS13_AES_128_GCM_SHA256,
+            group: Some(NamedGroup::X25519),
+            ch1_hash: vec![1; 32],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let now = UnixTime::since_unix_epoch(Duration::from_secs(1_000_000));
+        let state = state(b"192.0.2.1:4433");
+        let sealed = state.seal(&cookie(now), TLS13_AES_128_GCM_SHA256_INTERNAL);
+
+        let opened = state
+            .open(&sealed, TLS13_AES_128_GCM_SHA256_INTERNAL, now)
+            .unwrap();
+        assert_eq!(opened.issued, now);
+        assert_eq!(opened.suite, CipherSuite::TLS13_AES_128_GCM_SHA256);
+        assert_eq!(opened.group, Some(NamedGroup::X25519));
+        assert_eq!(opened.ch1_hash, vec![1; 32]);
+    }
+
+    #[test]
+    fn rejects_bad_cookies() {
+        let now = UnixTime::since_unix_epoch(Duration::from_secs(1_000_000));
+        let state = state(b"192.0.2.1:4433");
+        let sealed = state.seal(&cookie(now), TLS13_AES_128_GCM_SHA256_INTERNAL);
+
+        // another client
+        assert!(self::state(b"192.0.2.2:4433")
+            .open(&sealed, TLS13_AES_128_GCM_SHA256_INTERNAL, now)
+            .is_none());
+
+        // another key
+        let other_key = CookieState {
+            key: Arc::new(CookieKey::from_secret(&[8; 32])),
+            ..self::state(b"192.0.2.1:4433")
+        };
+        assert!(other_key
+            .open(&sealed, TLS13_AES_128_GCM_SHA256_INTERNAL, now)
+            .is_none());
+
+        // another suite
+        assert!(state
+            .open(&sealed, TLS13_CHACHA20_POLY1305_SHA256_INTERNAL, now)
+            .is_none());
+
+        // tampered
+        let mut tampered = sealed.clone();
+        tampered[0] ^= 1;
+        assert!(state
+            .open(&tampered, TLS13_AES_128_GCM_SHA256_INTERNAL, now)
+            .is_none());
+        assert!(state
+            .open(&sealed[..10], TLS13_AES_128_GCM_SHA256_INTERNAL, now)
+            .is_none());
+
+        // expired
+        let later =
+            UnixTime::since_unix_epoch(Duration::from_secs(1_000_000 + CookieKey::LIFETIME + 1));
+        assert!(state
+            .open(&sealed, TLS13_AES_128_GCM_SHA256_INTERNAL, later)
+            .is_none());
+    }
+
+    #[test]
+    fn long_client_addresses() {
+        let now = UnixTime::since_unix_epoch(Duration::from_secs(1_000_000));
+        let state = state(&[1; 300]);
+        let sealed = state.seal(&cookie(now), TLS13_AES_128_GCM_SHA256_INTERNAL);
+
+        assert!(state
+            .open(&sealed, TLS13_AES_128_GCM_SHA256_INTERNAL, now)
+            .is_some());
+        assert!(self::state(&[1; 44])
+            .open(&sealed, TLS13_AES_128_GCM_SHA256_INTERNAL, now)
+            .is_none());
+        assert!(self::state(&[1; 301])
+            .open(&sealed, TLS13_AES_128_GCM_SHA256_INTERNAL, now)
+            .is_none());
+    }
+}
@@ -0,0 +1,146 @@
+//! DTLS 1.3, as described in [RFC 9147].
+//!
+//! [`DtlsClientConnection`] and [`DtlsServerConnection`] run the TLS 1.3
+//! handshake over an unreliable datagram transport.  They are made from the
+//! same [`ClientConfig`] and [`ServerConfig`] as TLS connections, and like
+//! [`crate::quic`] they leave I/O to the caller:
+//!
+//! - pass each datagram received from the peer to `process_datagram()`,
+//! - send each datagram returned by `poll_transmit()` to the peer,
+//! - call `handle_timeout()` once the time returned by `poll_timeout()` has
+//!   passed.  This drives retransmission of handshake flights that the peer
+//!   has not acknowledged.
+//!
+//! Each record carries a whole handshake message fragment, alert, `ACK` or
+//! application data message, and application data keeps its datagram
+//! boundaries: each message passed to `send_application_data()` is returned
+//! as one message by the peer's `recv_application_data()`.
+//!
+//! A server can avoid keeping state for unverified clients by answering each
+//! `ClientHello` that lacks a valid cookie with a `HelloRetryRequest` carrying
+//! one; see [`DtlsServerConnection::with_cookie()`].
+//!
+//! Cipher suites need a [`Tls13CipherSuite::dtls`] [`Algorithm`] to be used
+//! for DTLS.  Early data, post-handshake client authentication and encrypted
+//! client hello are not supported, nor is TLS 1.2 or DTLS 1.2.  Connection
+//! IDs ([RFC 9146]) are supported by the record layer, but not negotiated by
+//! the connection types.
+//!
+//! Resumption tickets are stored in the client's session store in the same
+//! way as for TLS, so a [`ClientConfig`] used for DTLS should not share its
+//! session store with one used for TLS.
+//!
+//! The record layer types ([`RecordEncrypter`], [`RecordDecrypter`] and the
+//! functions for plaintext records) are also public, for callers that manage
+//! epochs themselves.
+//!
+//! [RFC 9147]: https://www.rfc-editor.org/rfc/rfc9147
+//! [RFC 9146]: https://www.rfc-editor.org/rfc/rfc9146
+//! [`ClientConfig`]: crate::ClientConfig
+//! [`ServerConfig`]: crate::ServerConfig
+//! [`Tls13CipherSuite::dtls`]: crate::Tls13CipherSuite::dtls
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use crate::crypto::cipher::{AeadKey, Iv};
+use crate::crypto::tls13::OkmBlock;
+use crate::error::Error;
+use crate::msgs::alert::AlertMessagePayload;
+use crate::quic::PacketKey;
+use crate::tls13::key_schedule::dtls13_expand_label_block;
+use crate::tls13::Tls13CipherSuite;
+
+#[cfg(feature = "std")]
+mod connection;
+mod cookie;
+mod record;
+
+#[cfg(feature = "std")]
+pub use connection::{DtlsClientConnection, DtlsConnectionCommon, DtlsServerConnection};
+pub use cookie::CookieKey;
+pub(crate) use cookie::{Cookie, CookieState};
+pub use record::{
+    encode_plaintext, epoch_bits, read_plaintext, split_record, Ack, EpochKeys, Record,
+    RecordDecrypter, RecordEncrypter, RecordNumber, RetransmitTimer,
+};
+
+/// How a `Tls13CipherSuite` generates DTLS record protection and record
+/// number encryption keys.
+pub trait Algorithm: Send + Sync {
+    /// Produce a record protection key for this suite.
+    ///
+    /// `key` and `iv` is the key material to use.  The nonce for each record
+    /// is formed from `iv` and the record's sequence number in the same way
+    /// as for QUIC packets, so this reuses [`PacketKey`].
+    fn record_key(&self, key: AeadKey, iv: Iv) -> Box<dyn PacketKey>;
+
+    /// Produce a record number encryption key for this suite.
+    ///
+    /// `key` is the key material, which is `aead_key_len()` bytes in length.
+    fn record_number_key(&self, key: AeadKey) -> Box<dyn RecordNumberKey>;
+
+    /// The length in bytes of keys for this Algorithm.
+    fn aead_key_len(&self) -> usize;
+
+    /// Whether this algorithm is FIPS-approved.
+    fn fips(&self) -> bool {
+        false
+    }
+}
+
+/// A DTLS 1.3 record number encryption key.
+///
+/// See [RFC 9147 section 4.2.3].
+///
+/// [RFC 9147 section 4.2.3]: https://www.rfc-editor.org/rfc/rfc9147#section-4.2.3
+pub trait RecordNumberKey: Send + Sync {
+    /// Compute the mask XORed with the (at most two byte) sequence number
+    /// field of a record's header.
+    ///
+    /// `sample` is the start of the record's ciphertext, and is
+    /// [`Self::sample_len()`] bytes long.
+    fn mask(&self, sample: &[u8]) -> Result<[u8; 2], Error>;
+
+    /// Number of ciphertext bytes sampled to compute the mask.
+    fn sample_len(&self) -> usize;
+}
+
+/// DTLS state held in `CommonState`, shared by the handshake and the
+/// connection types.
+#[derive(Default)]
+pub(crate) struct Dtls {
+    /// Encoded handshake messages sent by the handshake, not yet framed.
+    pub(crate) hs_queue: VecDeque<Vec<u8>>,
+    /// Alerts sent by the handshake, not yet framed.
+    pub(crate) alerts: VecDeque<AlertMessagePayload>,
+    pub(crate) handshake_secrets: Option<Box<Secrets>>,
+    pub(crate) traffic_secrets: Option<Box<Secrets>>,
+    /// How a server issues and checks `HelloRetryRequest` cookies, if it does.
+    pub(crate) cookie: Option<CookieState>,
+}
+
+/// The client and server traffic secrets for one epoch.
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+pub(crate) struct Secrets {
+    pub(crate) client: OkmBlock,
+    pub(crate) server: OkmBlock,
+}
+
+/// The traffic secret for the next epoch, after a `KeyUpdate`.
+///
+/// See [RFC 8446 section 7.2].
+///
+/// [RFC 8446 section 7.2]: https://www.rfc-editor.org/rfc/rfc8446#section-7.2
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+pub(crate) fn next_traffic_secret(suite: &'static Tls13CipherSuite, secret: &OkmBlock) -> OkmBlock {
+    dtls13_expand_label_block(
+        suite
+            .hkdf_provider
+            .expander_for_okm(secret)
+            .as_ref(),
+        b"traffic upd",
+        &[],
+    )
+}
@@ -0,0 +1,900 @@
+//! The DTLS 1.3 record layer.
+//!
+//! This protects and deprotects records for a single epoch at a time, given
+//! the traffic secret for that epoch.  It handles the unified record header,
+//! record number encryption, reconstruction of the full sequence number from
+//! its truncated form, and replay detection.  It also provides the `ACK`
+//! message and a retransmission timer, which the caller drives from its own
+//! clock.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::crypto::cipher::Iv;
+use crate::crypto::tls13::OkmBlock;
+use crate::enums::{ContentType, ProtocolVersion};
+use crate::error::{Error, InvalidMessage, PeerMisbehaved};
+use crate::msgs::codec::{Codec, ListLength, Reader, TlsListElement};
+use crate::msgs::fragmenter::MAX_FRAGMENT_LEN;
+use crate::quic::{self, PacketKey};
+use crate::tls13::key_schedule::{dtls13_expand_label, dtls13_expand_label_aead_key};
+use crate::tls13::Tls13CipherSuite;
+
+use super::RecordNumberKey;
+
+/// Record protection keys for one epoch, in one direction.
+pub struct EpochKeys {
+    epoch: u64,
+    record: Box<dyn PacketKey>,
+    record_number: Box<dyn RecordNumberKey>,
+}
+
+impl EpochKeys {
+    /// Derive the keys for `epoch` from `traffic_secret`.
+    ///
+    /// Fails if `suite` has no [`super::Algorithm`].
+    pub fn new(
+        suite: &'static Tls13CipherSuite,
+        epoch: u64,
+        traffic_secret: &OkmBlock,
+    ) -> Result<Self, Error> {
+        let alg = suite.dtls.ok_or_else(|| {
+            Error::General("cipher suite does not support DTLS record protection".into())
+        })?;
+
+        let expander = suite
+            .hkdf_provider
+            .expander_for_okm(traffic_secret);
+        let key = dtls13_expand_label_aead_key(expander.as_ref(), alg.aead_key_len(), b"key", &[]);
+        let iv: Iv = dtls13_expand_label(expander.as_ref(), b"iv", &[]);
+        let sn_key =
+            dtls13_expand_label_aead_key(expander.as_ref(), alg.aead_key_len(), b"sn", &[]);
+
+        Ok(Self {
+            epoch,
+            record: alg.record_key(key, iv),
+            record_number: alg.record_number_key(sn_key),
+        })
+    }
+
+    /// The epoch these keys protect.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// The largest number of records these keys may protect.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn confidentiality_limit(&self) -> u64 {
+        self.record.confidentiality_limit()
+    }
+
+    /// The largest number of records that may fail authentication with these keys.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn integrity_limit(&self) -> u64 {
+        self.record.integrity_limit()
+    }
+
+    fn record_number_mask(&self, ciphertext: &[u8]) -> Result<[u8; 2], Error> {
+        let sample_len = self.record_number.sample_len();
+        match ciphertext.get(..sample_len) {
+            Some(sample) => self.record_number.mask(sample),
+            None => Err(InvalidMessage::MessageTooShort.into()),
+        }
+    }
+}
+
+/// Protects outgoing records for one epoch.
+pub struct RecordEncrypter {
+    keys: EpochKeys,
+    connection_id: Vec<u8>,
+    next_sequence_number: u64,
+}
+
+impl RecordEncrypter {
+    /// Make a new `RecordEncrypter`, starting at sequence number zero.
+    pub fn new(keys: EpochKeys) -> Self {
+        Self::with_connection_id(keys, Vec::new())
+    }
+
+    /// Make a new `RecordEncrypter` whose records carry `connection_id`.
+    ///
+    /// This is the connection ID the peer asked to receive.  An empty
+    /// `connection_id` means records carry none.
+    pub fn with_connection_id(keys: EpochKeys, connection_id: Vec<u8>) -> Self {
+        Self {
+            keys,
+            connection_id,
+            next_sequence_number: 0,
+        }
+    }
+
+    /// Protect `payload` as a record of type `typ`, appending it to `out`.
+    ///
+    /// The record uses the unified header with a 16-bit sequence number and
+    /// an explicit length, so several records may share one datagram.
+    ///
+    /// Returns the record number used, so the caller can match it against
+    /// later `ACK`s.  Fails if `payload` is too large, or the epoch's
+    /// sequence numbers are exhausted: the caller must then move to a new
+    /// epoch.
+    pub fn encrypt(
+        &mut self,
+        typ: ContentType,
+        payload: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<RecordNumber, Error> {
+        let sequence_number = self.next_sequence_number;
+        if sequence_number > MAX_SEQUENCE_NUMBER || payload.len() > MAX_FRAGMENT_LEN {
+            return Err(Error::EncryptError);
+        }
+
+        let length = payload.len() + 1 + self.keys.record.tag_len();
+        let start = out.len();
+        let mut first = UNIFIED_HEADER | SEQUENCE_NUMBER_16 | LENGTH_PRESENT | self.epoch_bits();
+        if !self.connection_id.is_empty() {
+            first |= CONNECTION_ID_PRESENT;
+        }
+        out.push(first);
+        out.extend_from_slice(&self.connection_id);
+        out.extend_from_slice(&(sequence_number as u16).to_be_bytes());
+        out.extend_from_slice(&(length as u16).to_be_bytes());
+        out.extend_from_slice(payload);
+        out.push(u8::from(typ));
+
+        let result = self.protect(sequence_number, &mut out[start..]);
+        match result {
+            Ok(tag) => out.extend_from_slice(tag.as_ref()),
+            Err(err) => {
+                out.truncate(start);
+                return Err(err);
+            }
+        }
+
+        // Record number encryption covers the header's sequence number, and
+        // is keyed from the start of the ciphertext (including the tag).
+        let (header, ciphertext) = out[start..].split_at_mut(self.header_len());
+        let mask = match self.keys.record_number_mask(ciphertext) {
+            Ok(mask) => mask,
+            Err(err) => {
+                out.truncate(start);
+                return Err(err);
+            }
+        };
+        let sequence_number_offset = 1 + self.connection_id.len();
+        header[sequence_number_offset] ^= mask[0];
+        header[sequence_number_offset + 1] ^= mask[1];
+
+        self.next_sequence_number += 1;
+        Ok(RecordNumber {
+            epoch: self.keys.epoch,
+            sequence_number,
+        })
+    }
+
+    /// The epoch this `RecordEncrypter` writes.
+    pub fn epoch(&self) -> u64 {
+        self.keys.epoch
+    }
+
+    /// The number of bytes [`Self::encrypt()`] adds to a payload.
+    pub fn overhead(&self) -> usize {
+        self.header_len() + 1 + self.keys.record.tag_len()
+    }
+
+    /// The sequence number the next record will use.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn next_sequence_number(&self) -> u64 {
+        self.next_sequence_number
+    }
+
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn confidentiality_limit(&self) -> u64 {
+        self.keys.confidentiality_limit()
+    }
+
+    fn protect(&self, sequence_number: u64, record: &mut [u8]) -> Result<quic::Tag, Error> {
+        let (header, inner_plaintext) = record.split_at_mut(self.header_len());
+        self.keys
+            .record
+            .encrypt_in_place(sequence_number, header, inner_plaintext)
+    }
+
+    /// Flags, connection ID, 16-bit sequence number and length.
+    fn header_len(&self) -> usize {
+        1 + self.connection_id.len() + 2 + 2
+    }
+
+    fn epoch_bits(&self) -> u8 {
+        (self.keys.epoch & u64::from(EPOCH_BITS)) as u8
+    }
+}
+
+/// Deprotects incoming records for one epoch.
+pub struct RecordDecrypter {
+    keys: EpochKeys,
+    connection_id: Vec<u8>,
+    window: ReplayWindow,
+}
+
+impl RecordDecrypter {
+    /// Make a new `RecordDecrypter`, expecting sequence numbers from zero.
+    pub fn new(keys: EpochKeys) -> Self {
+        Self::with_connection_id(keys, Vec::new())
+    }
+
+    /// Make a new `RecordDecrypter` for records carrying `connection_id`.
+    ///
+    /// This is the connection ID we asked the peer to use.  Records carrying
+    /// another connection ID are discarded.
+    pub fn with_connection_id(keys: EpochKeys, connection_id: Vec<u8>) -> Self {
+        Self {
+            keys,
+            connection_id,
+            window: ReplayWindow::default(),
+        }
+    }
+
+    /// Deprotect the single protected record in `record`.
+    ///
+    /// `record` must be exactly one record, as returned by [`split_record()`].
+    /// It is decrypted in place.
+    ///
+    /// Returns `Ok(None)` if the record should be silently discarded: because
+    /// it belongs to another epoch or connection ID, or is a replay of a record
+    /// already received.  Other errors also mean the record should be discarded;
+    /// RFC 9147 does not require closing the connection for them.
+    pub fn decrypt<'a>(&mut self, record: &'a mut [u8]) -> Result<Option<Record<'a>>, Error> {
+        let header = UnifiedHeader::read(record, self.connection_id.len())?;
+        if header
+            .length
+            .is_some_and(|len| header.len + len != record.len())
+        {
+            return Err(InvalidMessage::TrailingData("DTLSCiphertext").into());
+        }
+
+        if header.epoch_bits != (self.keys.epoch & u64::from(EPOCH_BITS)) as u8
+            || record[1..1 + self.connection_id.len()] != self.connection_id[..]
+        {
+            return Ok(None);
+        }
+
+        let (header_bytes, ciphertext) = record.split_at_mut(header.len);
+        let mask = self
+            .keys
+            .record_number_mask(ciphertext)?;
+        let sequence_number_offset = 1 + self.connection_id.len();
+        let truncated = &mut header_bytes
+            [sequence_number_offset..sequence_number_offset + header.sequence_number_len];
+        truncated
+            .iter_mut()
+            .zip(mask)
+            .for_each(|(byte, mask)| *byte ^= mask);
+        let truncated = truncated
+            .iter()
+            .fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte));
+
+        let sequence_number = reconstruct_sequence_number(
+            self.window.next_expected(),
+            truncated,
+            header.sequence_number_len as u32 * 8,
+        );
+        if self.window.contains(sequence_number) {
+            return Ok(None);
+        }
+
+        let inner_plaintext =
+            self.keys
+                .record
+                .decrypt_in_place(sequence_number, header_bytes, ciphertext)?;
+
+        let Some(type_offset) = inner_plaintext
+            .iter()
+            .rposition(|byte| *byte != 0)
+        else {
+            return Err(PeerMisbehaved::IllegalTlsInnerPlaintext.into());
+        };
+
+        let payload = &inner_plaintext[..type_offset];
+        if payload.len() > MAX_FRAGMENT_LEN {
+            return Err(Error::PeerSentOversizedRecord);
+        }
+
+        self.window.insert(sequence_number);
+        Ok(Some(Record {
+            typ: ContentType::from(inner_plaintext[type_offset]),
+            record_number: RecordNumber {
+                epoch: self.keys.epoch,
+                sequence_number,
+            },
+            payload,
+        }))
+    }
+
+    /// The epoch this `RecordDecrypter` reads.
+    pub fn epoch(&self) -> u64 {
+        self.keys.epoch
+    }
+
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn integrity_limit(&self) -> u64 {
+        self.keys.integrity_limit()
+    }
+}
+
+/// A record received from the peer.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Record<'a> {
+    /// The record's content type.
+    pub typ: ContentType,
+    /// The record's full record number.
+    pub record_number: RecordNumber,
+    /// The record's payload.
+    pub payload: &'a [u8],
+}
+
+/// Split the first record from `datagram`.
+///
+/// `connection_id_len` is the length of the connection ID expected in
+/// protected records, or zero if none is expected.
+///
+/// Returns the first record and the rest of the datagram, which may contain
+/// further records.  Protected records without an explicit length extend to
+/// the end of the datagram.
+pub fn split_record(
+    datagram: &mut [u8],
+    connection_id_len: usize,
+) -> Result<(&mut [u8], &mut [u8]), Error> {
+    let len = match is_plaintext(datagram) {
+        true => {
+            let mut r = Reader::init(datagram);
+            r.take(PLAINTEXT_HEADER_LEN - 2)
+                .ok_or(InvalidMessage::MessageTooShort)?;
+            PLAINTEXT_HEADER_LEN + usize::from(u16::read(&mut r)?)
+        }
+        false => {
+            let header = UnifiedHeader::read(datagram, connection_id_len)?;
+            match header.length {
+                Some(length) => header.len + length,
+                None => datagram.len(),
+            }
+        }
+    };
+
+    if len > datagram.len() {
+        return Err(InvalidMessage::MessageTooShort.into());
+    }
+
+    Ok(datagram.split_at_mut(len))
+}
+
+/// Return the low bits of the epoch of the protected record in `record`.
+///
+/// This identifies the [`RecordDecrypter`] to use for it.  Returns `None` for
+/// plaintext records.
+pub fn epoch_bits(record: &[u8]) -> Option<u8> {
+    match is_plaintext(record) {
+        true => None,
+        false => record
+            .first()
+            .map(|first| first & EPOCH_BITS),
+    }
+}
+
+/// Append an unprotected epoch 0 record to `out`.
+pub fn encode_plaintext(
+    typ: ContentType,
+    sequence_number: u64,
+    payload: &[u8],
+    out: &mut Vec<u8>,
+) -> Result<(), Error> {
+    if sequence_number > MAX_SEQUENCE_NUMBER || payload.len() > MAX_FRAGMENT_LEN {
+        return Err(Error::EncryptError);
+    }
+
+    typ.encode(out);
+    LEGACY_RECORD_VERSION.encode(out);
+    0u16.encode(out);
+    out.extend_from_slice(&sequence_number.to_be_bytes()[2..]);
+    (payload.len() as u16).encode(out);
+    out.extend_from_slice(payload);
+    Ok(())
+}
+
+/// Read the unprotected epoch 0 record in `record`.
+///
+/// `record` must be exactly one record, as returned by [`split_record()`].
+pub fn read_plaintext(record: &[u8]) -> Result<Record<'_>, Error> {
+    let mut r = Reader::init(record);
+    let typ = ContentType::read(&mut r)?;
+    ProtocolVersion::read(&mut r)?;
+    let epoch = u16::read(&mut r)?;
+    let sequence_number = r
+        .take(6)
+        .ok_or(InvalidMessage::MessageTooShort)?
+        .iter()
+        .fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte));
+    let len = usize::from(u16::read(&mut r)?);
+    let payload = r
+        .take(len)
+        .ok_or(InvalidMessage::MessageTooShort)?;
+    r.expect_empty("DTLSPlaintext")?;
+
+    if epoch != 0 || !is_plaintext(record) {
+        return Err(InvalidMessage::InvalidContentType.into());
+    }
+
+    if payload.len() > MAX_FRAGMENT_LEN {
+        return Err(Error::PeerSentOversizedRecord);
+    }
+
+    Ok(Record {
+        typ,
+        record_number: RecordNumber {
+            epoch: 0,
+            sequence_number,
+        },
+        payload,
+    })
+}
+
+/// Identifies a record: its epoch and sequence number within that epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RecordNumber {
+    /// The epoch.
+    pub epoch: u64,
+    /// The sequence number within `epoch`.
+    pub sequence_number: u64,
+}
+
+impl Codec<'_> for RecordNumber {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.epoch.encode(bytes);
+        self.sequence_number.encode(bytes);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, InvalidMessage> {
+        Ok(Self {
+            epoch: u64::read(r)?,
+            sequence_number: u64::read(r)?,
+        })
+    }
+}
+
+impl TlsListElement for RecordNumber {
+    const SIZE_LEN: ListLength = ListLength::U16;
+}
+
+/// The DTLS 1.3 `ACK` message, sent with content type [`ContentType::Ack`].
+///
+/// See [RFC 9147 section 7].
+///
+/// [RFC 9147 section 7]: https://www.rfc-editor.org/rfc/rfc9147#section-7
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Ack {
+    /// The records being acknowledged.
+    pub record_numbers: Vec<RecordNumber>,
+}
+
+impl Codec<'_> for Ack {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.record_numbers.encode(bytes);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, InvalidMessage> {
+        Ok(Self {
+            record_numbers: Vec::read(r)?,
+        })
+    }
+}
+
+/// The timer controlling retransmission of a handshake flight.
+///
+/// The caller starts the timer when it sends a flight, retransmits the flight
+/// and calls [`RetransmitTimer::back_off()`] each time it expires, and calls
+/// [`RetransmitTimer::reset()`] once the flight is acknowledged.
+///
+/// See [RFC 9147 section 5.8].
+///
+/// [RFC 9147 section 5.8]: https://www.rfc-editor.org/rfc/rfc9147#section-5.8
+#[derive(Clone, Debug)]
+pub struct RetransmitTimer {
+    initial: Duration,
+    timeout: Duration,
+}
+
+impl RetransmitTimer {
+    /// The recommended initial timeout.
+    pub const DEFAULT_INITIAL_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// The largest timeout reached by backing off.
+    pub const MAX_TIMEOUT: Duration = Duration::from_secs(60);
+
+    /// Make a timer starting from `initial`.
+    ///
+    /// Smaller values suit deployments that know their round trip time.
+    pub fn new(initial: Duration) -> Self {
+        let initial = initial.min(Self::MAX_TIMEOUT);
+        Self {
+            initial,
+            timeout: initial,
+        }
+    }
+
+    /// How long to wait before retransmitting the current flight.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Double the timeout after it expired, up to [`Self::MAX_TIMEOUT`].
+    pub fn back_off(&mut self) {
+        self.timeout = (self.timeout * 2).min(Self::MAX_TIMEOUT);
+    }
+
+    /// Return to the initial timeout, after a flight was acknowledged.
+    pub fn reset(&mut self) {
+        self.timeout = self.initial;
+    }
+}
+
+impl Default for RetransmitTimer {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_INITIAL_TIMEOUT)
+    }
+}
+
+/// The fields of a unified header that precede the ciphertext.
+struct UnifiedHeader {
+    epoch_bits: u8,
+    sequence_number_len: usize,
+    length: Option<usize>,
+    /// Total length of the header.
+    len: usize,
+}
+
+impl UnifiedHeader {
+    fn read(record: &[u8], connection_id_len: usize) -> Result<Self, Error> {
+        let mut r = Reader::init(record);
+        let first = u8::read(&mut r)?;
+        if first & UNIFIED_HEADER_MASK != UNIFIED_HEADER {
+            return Err(InvalidMessage::InvalidContentType.into());
+        }
+
+        // The connection ID's length is not on the wire: it is the length we
+        // asked the peer to use, so records with and without one cannot mix.
+        match (first & CONNECTION_ID_PRESENT != 0, connection_id_len) {
+            (true, 0) => return Err(InvalidMessage::UnexpectedConnectionId.into()),
+            (false, 0) => {}
+            (true, len) => {
+                r.take(len)
+                    .ok_or(InvalidMessage::MessageTooShort)?;
+            }
+            (false, _) => return Err(InvalidMessage::MissingData("connection_id").into()),
+        }
+
+        let sequence_number_len = match first & SEQUENCE_NUMBER_16 {
+            0 => 1,
+            _ => 2,
+        };
+        r.take(sequence_number_len)
+            .ok_or(InvalidMessage::MessageTooShort)?;
+
+        let length = match first & LENGTH_PRESENT {
+            0 => None,
+            _ => Some(usize::from(u16::read(&mut r)?)),
+        };
+
+        Ok(Self {
+            epoch_bits: first & EPOCH_BITS,
+            sequence_number_len,
+            length,
+            len: r.used(),
+        })
+    }
+}
+
+/// Tracks which recent sequence numbers have been received in an epoch.
+///
+/// See [RFC 9147 section 4.5.1].
+///
+/// [RFC 9147 section 4.5.1]: https://www.rfc-editor.org/rfc/rfc9147#section-4.5.1
+#[derive(Default)]
+struct ReplayWindow {
+    highest: Option<u64>,
+    /// Bit `n` is set if `highest - n` was received.
+    received: u64,
+}
+
+impl ReplayWindow {
+    fn next_expected(&self) -> u64 {
+        self.highest
+            .map_or(0, |highest| highest + 1)
+    }
+
+    /// Returns true for duplicates, and for records too old to tell.
+    fn contains(&self, sequence_number: u64) -> bool {
+        match self.highest {
+            Some(highest) if sequence_number <= highest => {
+                let age = highest - sequence_number;
+                age >= u64::BITS as u64 || self.received & (1 << age) != 0
+            }
+            _ => false,
+        }
+    }
+
+    fn insert(&mut self, sequence_number: u64) {
+        match self.highest {
+            Some(highest) if sequence_number <= highest => {
+                self.received |= 1 << (highest - sequence_number);
+            }
+            Some(highest) => {
+                self.received = self
+                    .received
+                    .checked_shl((sequence_number - highest) as u32)
+                    .unwrap_or_default()
+                    | 1;
+                self.highest = Some(sequence_number);
+            }
+            None => {
+                self.received = 1;
+                self.highest = Some(sequence_number);
+            }
+        }
+    }
+}
+
+/// Choose the sequence number closest to `expected` whose low `bits` bits
+/// are `truncated`.
+///
+/// See [RFC 9147 section 4.2.2].
+///
+/// [RFC 9147 section 4.2.2]: https://www.rfc-editor.org/rfc/rfc9147#section-4.2.2
+fn reconstruct_sequence_number(expected: u64, truncated: u64, bits: u32) -> u64 {
+    let window = 1u64 << bits;
+    let half_window = window / 2;
+    let candidate = (expected & !(window - 1)) | truncated;
+
+    if candidate + half_window <= expected && candidate + window <= MAX_SEQUENCE_NUMBER {
+        candidate + window
+    } else if candidate > expected + half_window && candidate >= window {
+        candidate - window
+    } else {
+        candidate
+    }
+}
+
+fn is_plaintext(record: &[u8]) -> bool {
+    record
+        .first()
+        .is_some_and(|first| first & UNIFIED_HEADER_MASK != UNIFIED_HEADER)
+}
+
+/// `legacy_record_version` in epoch 0 records.
+const LEGACY_RECORD_VERSION: ProtocolVersion = ProtocolVersion::DTLSv1_2;
+
+/// Sequence numbers are 48 bits long.
+const MAX_SEQUENCE_NUMBER: u64 = (1 << 48) - 1;
+
+/// Type, version, epoch, sequence number and length.
+const PLAINTEXT_HEADER_LEN: usize = 1 + 2 + 2 + 6 + 2;
+
+// Bits of the unified header's first byte: `001CSLEE`.
+const UNIFIED_HEADER_MASK: u8 = 0b1110_0000;
+const UNIFIED_HEADER: u8 = 0b0010_0000;
+const CONNECTION_ID_PRESENT: u8 = 0b0001_0000;
+const SEQUENCE_NUMBER_16: u8 = 0b0000_1000;
+const LENGTH_PRESENT: u8 = 0b0000_0100;
+const EPOCH_BITS: u8 = 0b0000_0011;
+
+#[cfg(test)]
+#[macro_rules_attribute::apply(test_for_each_provider)]
+mod tests {
+    use std::prelude::v1::*;
+    use std::vec;
+
+    use super::provider::tls13::{
+        TLS13_AES_128_GCM_SHA256_INTERNAL, TLS13_CHACHA20_POLY1305_SHA256_INTERNAL,
+    };
+    use super::*;
+
+    fn keys(suite: &'static Tls13CipherSuite, epoch: u64) -> EpochKeys {
+        EpochKeys::new(suite, epoch, &OkmBlock::new(&[0x5a; 32])).unwrap()
+    }
+
+    #[test]
+    fn records_round_trip() {
+        for suite in [
+            TLS13_AES_128_GCM_SHA256_INTERNAL,
+            TLS13_CHACHA20_POLY1305_SHA256_INTERNAL,
+        ] {
+            let mut encrypter = RecordEncrypter::new(keys(suite, 3));
+            let mut decrypter = RecordDecrypter::new(keys(suite, 3));
+
+            let mut datagram = vec![];
+            for i in 0..3u8 {
+                let rn = encrypter
+                    .encrypt(ContentType::ApplicationData, &[i; 100], &mut datagram)
+                    .unwrap();
+                assert_eq!(rn.sequence_number, u64::from(i));
+            }
+
+            let mut rest = &mut datagram[..];
+            for i in 0..3u8 {
+                assert_eq!(epoch_bits(rest), Some(3));
+                let (record, next) = split_record(rest, 0).unwrap();
+                // the sequence number is not sent in the clear
+                assert_ne!(&record[1..3], &[0, i]);
+                let record = decrypter
+                    .decrypt(record)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(record.typ, ContentType::ApplicationData);
+                assert_eq!(record.payload, &[i; 100]);
+                assert_eq!(
+                    record.record_number,
+                    RecordNumber {
+                        epoch: 3,
+                        sequence_number: u64::from(i)
+                    }
+                );
+                rest = next;
+            }
+            assert!(rest.is_empty());
+        }
+    }
+
+    #[test]
+    fn replays_and_other_epochs_are_discarded() {
+        let suite = TLS13_AES_128_GCM_SHA256_INTERNAL;
+        let mut encrypter = RecordEncrypter::new(keys(suite, 2));
+        let mut decrypter = RecordDecrypter::new(keys(suite, 2));
+        let mut other_epoch = RecordDecrypter::new(keys(suite, 3));
+
+        let mut record = vec![];
+        encrypter
+            .encrypt(ContentType::Handshake, b"hello", &mut record)
+            .unwrap();
+
+        assert_eq!(other_epoch.decrypt(&mut record.clone()), Ok(None));
+        assert!(decrypter
+            .decrypt(&mut record.clone())
+            .unwrap()
+            .is_some());
+        assert_eq!(decrypter.decrypt(&mut record.clone()), Ok(None));
+    }
+
+    #[test]
+    fn tampered_records_are_rejected() {
+        let suite = TLS13_AES_128_GCM_SHA256_INTERNAL;
+        let mut encrypter = RecordEncrypter::new(keys(suite, 2));
+        let mut decrypter = RecordDecrypter::new(keys(suite, 2));
+
+        let mut record = vec![];
+        encrypter
+            .encrypt(ContentType::Handshake, b"hello", &mut record)
+            .unwrap();
+        *record.last_mut().unwrap() ^= 1;
+        assert_eq!(decrypter.decrypt(&mut record), Err(Error::DecryptError));
+    }
+
+    #[test]
+    fn connection_ids() {
+        let suite = TLS13_AES_128_GCM_SHA256_INTERNAL;
+        let mut encrypter = RecordEncrypter::with_connection_id(keys(suite, 2), vec![1, 2, 3]);
+        let mut decrypter = RecordDecrypter::with_connection_id(keys(suite, 2), vec![1, 2, 3]);
+        let mut other_id = RecordDecrypter::with_connection_id(keys(suite, 2), vec![1, 2, 4]);
+
+        let mut datagram = vec![];
+        encrypter
+            .encrypt(ContentType::Handshake, b"hello", &mut datagram)
+            .unwrap();
+        assert_eq!(datagram.len(), encrypter.overhead() + 5);
+        assert_eq!(&datagram[1..4], &[1, 2, 3]);
+
+        assert_eq!(
+            split_record(&mut datagram.clone(), 0).unwrap_err(),
+            Error::InvalidMessage(InvalidMessage::UnexpectedConnectionId)
+        );
+        assert_eq!(other_id.decrypt(&mut datagram.clone()), Ok(None));
+
+        let (record, rest) = split_record(&mut datagram, 3).unwrap();
+        assert!(rest.is_empty());
+        let record = decrypter
+            .decrypt(record)
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.payload, b"hello");
+    }
+
+    #[test]
+    fn plaintext_records_round_trip() {
+        let mut datagram = vec![];
+        encode_plaintext(ContentType::Handshake, 7, b"client hello", &mut datagram).unwrap();
+        encode_plaintext(ContentType::Alert, 8, &[2, 40], &mut datagram).unwrap();
+
+        let (first, rest) = split_record(&mut datagram, 0).unwrap();
+        assert_eq!(epoch_bits(first), None);
+        assert_eq!(
+            read_plaintext(first).unwrap(),
+            Record {
+                typ: ContentType::Handshake,
+                record_number: RecordNumber {
+                    epoch: 0,
+                    sequence_number: 7
+                },
+                payload: b"client hello",
+            }
+        );
+
+        let (second, rest) = split_record(rest, 0).unwrap();
+        assert_eq!(read_plaintext(second).unwrap().payload, &[2, 40]);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn truncated_records_are_rejected() {
+        let mut datagram = vec![];
+        encode_plaintext(ContentType::Handshake, 0, b"client hello", &mut datagram).unwrap();
+        datagram.pop();
+        assert!(split_record(&mut datagram, 0).is_err());
+    }
+
+    #[test]
+    fn sequence_number_reconstruction() {
+        assert_eq!(reconstruct_sequence_number(0, 0, 8), 0);
+        assert_eq!(reconstruct_sequence_number(0, 0xff, 8), 0xff);
+        assert_eq!(reconstruct_sequence_number(0xff, 0x01, 8), 0x101);
+        assert_eq!(reconstruct_sequence_number(0x101, 0xfe, 8), 0xfe);
+        assert_eq!(reconstruct_sequence_number(0x1_0000, 0xffff, 16), 0xffff);
+        assert_eq!(reconstruct_sequence_number(0x1_fff0, 0x0002, 16), 0x2_0002);
+    }
+
+    #[test]
+    fn replay_window() {
+        let mut window = ReplayWindow::default();
+        assert!(!window.contains(0));
+        window.insert(0);
+        assert!(window.contains(0));
+        window.insert(10);
+        assert!(!window.contains(5));
+        window.insert(5);
+        assert!(window.contains(5));
+        window.insert(100);
+        assert!(window.contains(10));
+        assert!(!window.contains(99));
+        assert_eq!(window.next_expected(), 101);
+    }
+
+    #[test]
+    fn ack_round_trip() {
+        let ack = Ack {
+            record_numbers: vec![
+                RecordNumber {
+                    epoch: 2,
+                    sequence_number: 0,
+                },
+                RecordNumber {
+                    epoch: 2,
+                    sequence_number: 1,
+                },
+            ],
+        };
+        let encoded = ack.get_encoding();
+        assert_eq!(encoded.len(), 2 + 2 * 16);
+        assert_eq!(Ack::read_bytes(&encoded).unwrap(), ack);
+    }
+
+    #[test]
+    fn retransmit_timer_backs_off() {
+        let mut timer = RetransmitTimer::default();
+        assert_eq!(timer.timeout(), Duration::from_secs(1));
+        timer.back_off();
+        assert_eq!(timer.timeout(), Duration::from_secs(2));
+        for _ in 0..10 {
+            timer.back_off();
+        }
+        assert_eq!(timer.timeout(), RetransmitTimer::MAX_TIMEOUT);
+        timer.reset();
+        assert_eq!(timer.timeout(), Duration::from_secs(1));
+    }
+}
@@ -86,6 +86,7 @@ enum_builder! {
         Handshake => 0x16,
         ApplicationData => 0x17,
         Heartbeat => 0x18,
+        Ack => 0x1a,
     }
 }
 
@@ -107,6 +108,16 @@ enum_builder! {
     }
 }
 
+impl ProtocolVersion {
+    /// Whether this is a DTLS version.
+    ///
+    /// DTLS versions are the ones' complement of the TLS version they are based
+    /// on, so all have a first byte of `0xFE`.
+    pub(crate) fn is_dtls(self) -> bool {
+        u16::from(self) >> 8 == 0xfe
+    }
+}
+
 enum_builder! {
     /// The `CipherSuite` TLS protocol enum.  Values in this enum are taken
     /// from the various RFCs covering TLS, and are listed by IANA.
@@ -606,7 +617,7 @@ mod tests {
     #[test]
     fn test_enums() {
         test_enum8::<SignatureAlgorithm>(SignatureAlgorithm::Anonymous, SignatureAlgorithm::ECDSA);
-        test_enum8::<ContentType>(ContentType::ChangeCipherSpec, ContentType::Heartbeat);
+        test_enum8::<ContentType>(ContentType::ChangeCipherSpec, ContentType::Ack);
         test_enum8::<HandshakeType>(HandshakeType::HelloRequest, HandshakeType::MessageHash);
         test_enum8::<AlertDescription>(
             AlertDescription::CloseNotify,
@@ -97,6 +97,15 @@ pub enum Error {
     /// or too large.
     BadMaxFragmentSize,
 
+    /// The `record_size_limit` value supplied in configuration was too small,
+    /// or too large.
+    BadRecordSizeLimit,
+
+    /// A [`CustomExtension`] used a reserved extension type, or was too large.
+    ///
+    /// [`CustomExtension`]: crate::CustomExtension
+    BadCustomExtension,
+
     /// Specific failure cases from [`keys_match`] or a [`crate::crypto::signer::SigningKey`] that cannot produce a corresponding public key.
     ///
     /// [`keys_match`]: crate::crypto::signer::CertifiedKey::keys_match
@@ -173,6 +182,8 @@ pub enum InvalidMessage {
     NoSignatureSchemes,
     /// Trailing data found for the named handshake payload value
     TrailingData(&'static str),
+    /// A DTLS record carried a connection ID when none was expected.
+    UnexpectedConnectionId,
     /// A peer sent an unexpected message type.
     UnexpectedMessage(&'static str),
     /// An unknown TLS protocol was encountered during message decoding.
@@ -219,6 +230,8 @@ pub enum PeerMisbehaved {
     EarlyDataExtensionWithoutResumption,
     EarlyDataOfferedWithVariedCipherSuite,
     HandshakeHashVariedAfterRetry,
+    HandshakeMessageInWrongEpoch,
+    IllegalClientHelloLegacyCookie,
     IllegalHelloRetryRequestWithEmptyCookie,
     IllegalHelloRetryRequestWithNoChanges,
     IllegalHelloRetryRequestWithOfferedGroup,
@@ -230,8 +243,13 @@ pub enum PeerMisbehaved {
     IllegalMiddleboxChangeCipherSpec,
     IllegalTlsInnerPlaintext,
     IncorrectBinder,
+    IncorrectCertificateRequestContext,
     InvalidCertCompression,
+    InvalidCookie,
+    InvalidEchExtensionAfterRetry,
+    InvalidEchInnerClientHello,
     InvalidMaxEarlyDataSize,
+    InvalidRecordSizeLimit,
     InvalidKeyShare,
     KeyEpochWithPendingFragment,
     KeyUpdateReceivedInQuicConnection,
@@ -255,6 +273,7 @@ pub enum PeerMisbehaved {
     ResumptionOfferedWithIncompatibleCipherSuite,
     SelectedDifferentCipherSuiteAfterRetry,
     SelectedInvalidPsk,
+    SelectedPskWithIncompatibleCipherSuite,
     SelectedTls12UsingTls13VersionExtension,
     SelectedUnofferedApplicationProtocol,
     SelectedUnofferedCertCompression,
@@ -273,6 +292,7 @@ pub enum PeerMisbehaved {
     TooManyRenegotiationRequests,
     TooManyWarningAlertsReceived,
     TooMuchEarlyDataReceived,
+    UndecryptableEchExtensionAfterRetry,
     UnexpectedCleartextExtension,
     UnsolicitedCertExtension,
     UnsolicitedEncryptedExtension,
@@ -298,6 +318,7 @@ impl From<PeerMisbehaved> for Error {
 /// This is `non_exhaustive`: we might add or stop using items here in minor
 /// versions.
 pub enum PeerIncompatible {
+    Dtls13NotOffered,
     EcPointsExtensionRequired,
     ExtendedMasterSecretExtensionRequired,
     IncorrectCertificateTypeExtension,
@@ -309,6 +330,8 @@ pub enum PeerIncompatible {
     NoKxGroupsInCommon,
     NoSignatureSchemesInCommon,
     NullCompressionRequired,
+    PostHandshakeAuthNotOffered,
+    ServerDoesNotSupportDtls13,
     ServerDoesNotSupportTls12Or13,
     ServerSentHelloRetryRequestWithUnknownExtension,
     ServerTlsVersionIsDisabledByOurConfig,
@@ -364,6 +387,35 @@ pub enum CertificateError {
     /// The certificate's revocation status could not be determined, because the CRL is expired.
     ExpiredRevocationList,
 
+    /// The certificate has been revoked, according to a stapled OCSP response.
+    RevokedByOcspResponse,
+
+    /// The stapled OCSP response is too old, or is past its `nextUpdate` time.
+    ExpiredOcspResponse,
+
+    /// The stapled OCSP response could not be parsed, was not correctly signed by
+    /// the certificate's issuer or its delegated responder, or does not cover the
+    /// certificate.
+    InvalidOcspResponse,
+
+    /// A stapled OCSP response was required, but the server did not provide one.
+    MissingOcspResponse,
+
+    /// The certificate was not accompanied by enough valid signed certificate
+    /// timestamps (SCTs) to satisfy the configured Certificate Transparency policy.
+    InsufficientSignedCertificateTimestamps,
+
+    /// The delegated credential presented with the certificate has expired.
+    DelegatedCredentialExpired,
+
+    /// The delegated credential presented with the certificate remains valid
+    /// for longer than the seven days allowed.
+    DelegatedCredentialValidityTooLong,
+
+    /// A delegated credential was presented with a certificate that lacks the
+    /// `DelegationUsage` extension, or that may not be used for signatures.
+    DelegationNotPermitted,
+
     /// A certificate is not correctly signed by the key of its alleged
     /// issuer.
     BadSignature,
@@ -403,11 +455,22 @@ impl PartialEq<Self> for CertificateError {
             (Revoked, Revoked) => true,
             (UnhandledCriticalExtension, UnhandledCriticalExtension) => true,
             (UnknownIssuer, UnknownIssuer) => true,
+            (UnknownRevocationStatus, UnknownRevocationStatus) => true,
             (BadSignature, BadSignature) => true,
             (NotValidForName, NotValidForName) => true,
             (InvalidPurpose, InvalidPurpose) => true,
             (ApplicationVerificationFailure, ApplicationVerificationFailure) => true,
             (ExpiredRevocationList, ExpiredRevocationList) => true,
+            (RevokedByOcspResponse, RevokedByOcspResponse) => true,
+            (ExpiredOcspResponse, ExpiredOcspResponse) => true,
+            (InvalidOcspResponse, InvalidOcspResponse) => true,
+            (MissingOcspResponse, MissingOcspResponse) => true,
+            (InsufficientSignedCertificateTimestamps, InsufficientSignedCertificateTimestamps) => {
+                true
+            }
+            (DelegatedCredentialExpired, DelegatedCredentialExpired) => true,
+            (DelegatedCredentialValidityTooLong, DelegatedCredentialValidityTooLong) => true,
+            (DelegationNotPermitted, DelegationNotPermitted) => true,
             _ => false,
         }
     }
@@ -420,16 +483,29 @@ impl From<CertificateError> for AlertDescription {
     fn from(e: CertificateError) -> Self {
         use CertificateError::*;
         match e {
-            BadEncoding | UnhandledCriticalExtension | NotValidForName => Self::BadCertificate,
+            BadEncoding
+            | UnhandledCriticalExtension
+            | NotValidForName
+            | InsufficientSignedCertificateTimestamps => Self::BadCertificate,
             // RFC 5246/RFC 8446
             // certificate_expired
             //  A certificate has expired or **is not currently valid**.
             Expired | NotValidYet => Self::CertificateExpired,
-            Revoked => Self::CertificateRevoked,
+            Revoked | RevokedByOcspResponse => Self::CertificateRevoked,
+            // RFC 6066
+            // bad_certificate_status_response
+            //  Sent by clients that receive an invalid certificate status response.
+            ExpiredOcspResponse | InvalidOcspResponse | MissingOcspResponse => {
+                Self::BadCertificateStatusResponse
+            }
             // OpenSSL, BoringSSL and AWS-LC all generate an Unknown CA alert for
             // the case where revocation status can not be determined, so we do the same here.
             UnknownIssuer | UnknownRevocationStatus | ExpiredRevocationList => Self::UnknownCA,
             BadSignature => Self::DecryptError,
+            // RFC 9345 section 4.1.1
+            DelegatedCredentialExpired
+            | DelegatedCredentialValidityTooLong
+            | DelegationNotPermitted => Self::IllegalParameter,
             InvalidPurpose => Self::UnsupportedCertificate,
             ApplicationVerificationFailure => Self::AccessDenied,
             // RFC 5246/RFC 8446
@@ -594,6 +670,12 @@ impl fmt::Display for Error {
             Self::BadMaxFragmentSize => {
                 write!(f, "the supplied max_fragment_size was too small or large")
             }
+            Self::BadRecordSizeLimit => {
+                write!(f, "the supplied record_size_limit was too small or large")
+            }
+            Self::BadCustomExtension => {
+                write!(f, "the supplied custom extension was reserved or too large")
+            }
             Self::InconsistentKeys(ref why) => {
                 write!(f, "keys may not be consistent: {:?}", why)
             }
@@ -689,6 +771,7 @@ mod tests {
         assert_eq!(Revoked, Revoked);
         assert_eq!(UnhandledCriticalExtension, UnhandledCriticalExtension);
         assert_eq!(UnknownIssuer, UnknownIssuer);
+        assert_eq!(UnknownRevocationStatus, UnknownRevocationStatus);
         assert_eq!(BadSignature, BadSignature);
         assert_eq!(NotValidForName, NotValidForName);
         assert_eq!(InvalidPurpose, InvalidPurpose);
@@ -696,6 +779,20 @@ mod tests {
             ApplicationVerificationFailure,
             ApplicationVerificationFailure
         );
+        assert_eq!(RevokedByOcspResponse, RevokedByOcspResponse);
+        assert_eq!(ExpiredOcspResponse, ExpiredOcspResponse);
+        assert_eq!(InvalidOcspResponse, InvalidOcspResponse);
+        assert_eq!(MissingOcspResponse, MissingOcspResponse);
+        assert_eq!(
+            InsufficientSignedCertificateTimestamps,
+            InsufficientSignedCertificateTimestamps
+        );
+        assert_eq!(DelegatedCredentialExpired, DelegatedCredentialExpired);
+        assert_eq!(
+            DelegatedCredentialValidityTooLong,
+            DelegatedCredentialValidityTooLong
+        );
+        assert_eq!(DelegationNotPermitted, DelegationNotPermitted);
         let other = Other(OtherError(
             #[cfg(feature = "std")]
             alloc::sync::Arc::from(Box::from("")),
@@ -764,6 +861,8 @@ mod tests {
             Error::PeerSentOversizedRecord,
             Error::NoApplicationProtocol,
             Error::BadMaxFragmentSize,
+            Error::BadRecordSizeLimit,
+            Error::BadCustomExtension,
             Error::InconsistentKeys(InconsistentKeys::KeyMismatch),
             Error::InconsistentKeys(InconsistentKeys::Unknown),
             Error::InvalidCertRevocationList(CertRevocationListError::BadSignature),
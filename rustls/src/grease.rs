@@ -0,0 +1,92 @@
+use crate::crypto::SecureRandom;
+use crate::enums::{CipherSuite, ProtocolVersion, SignatureScheme};
+use crate::msgs::enums::{ExtensionType, NamedGroup};
+use crate::rand::GetRandomFailed;
+
+/// The [GREASE] values used in one handshake message.
+///
+/// GREASE reserves values of the form `0x?a?a` in each of the two-byte
+/// registries.  Peers must ignore them, so sending them occasionally keeps
+/// peers (and middleboxes) tolerant of values they do not understand yet.
+///
+/// Each kind of value is chosen independently at random, and stays the same
+/// for the lifetime of a `Grease`, so a ClientHello sent in response to a
+/// `HelloRetryRequest` can repeat the values of the first.
+///
+/// [GREASE]: https://www.rfc-editor.org/rfc/rfc8701
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Grease([u8; 7]);
+
+impl Grease {
+    pub(crate) fn new(secure_random: &dyn SecureRandom) -> Result<Self, GetRandomFailed> {
+        let mut seed = [0u8; 7];
+        secure_random.fill(&mut seed)?;
+        Ok(Self(seed))
+    }
+
+    pub(crate) fn cipher_suite(&self) -> CipherSuite {
+        CipherSuite::from(self.value(0))
+    }
+
+    /// Two distinct extension types.
+    pub(crate) fn extensions(&self) -> [ExtensionType; 2] {
+        let first = self.value(1);
+        let mut second = self.value(2);
+        if second == first {
+            // Stay within the GREASE values, but avoid a duplicate extension.
+            second ^= 0x1010;
+        }
+        [ExtensionType::from(first), ExtensionType::from(second)]
+    }
+
+    pub(crate) fn named_group(&self) -> NamedGroup {
+        NamedGroup::from(self.value(3))
+    }
+
+    pub(crate) fn signature_scheme(&self) -> SignatureScheme {
+        SignatureScheme::from(self.value(4))
+    }
+
+    pub(crate) fn version(&self) -> ProtocolVersion {
+        ProtocolVersion::from(self.value(5))
+    }
+
+    pub(crate) fn alpn_protocol(&self) -> [u8; 2] {
+        self.value(6).to_be_bytes()
+    }
+
+    fn value(&self, index: usize) -> u16 {
+        let byte = (self.0[index] & 0xf0) | 0x0a;
+        u16::from_be_bytes([byte, byte])
+    }
+}
+
+/// GREASE values have the form `0x?a?a`, with both bytes equal.
+pub(crate) fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_grease() {
+        for byte in 0..=u8::MAX {
+            let grease = Grease([byte; 7]);
+            assert!(is_grease(u16::from(grease.cipher_suite())));
+            let [first, second] = grease.extensions();
+            assert!(is_grease(u16::from(first)));
+            assert!(is_grease(u16::from(second)));
+            assert_ne!(first, second);
+            assert!(is_grease(u16::from(grease.named_group())));
+            assert!(is_grease(u16::from(grease.signature_scheme())));
+            assert!(is_grease(u16::from(grease.version())));
+            assert!(is_grease(u16::from_be_bytes(grease.alpn_protocol())));
+        }
+
+        assert!(!is_grease(0x0a1a));
+        assert!(!is_grease(0x1a0a));
+        assert!(!is_grease(0x0b0b));
+    }
+}
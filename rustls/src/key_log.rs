@@ -33,6 +33,8 @@ pub trait KeyLog: Debug + Send + Sync {
     ///   from the client in a TLSv1.3 session.
     /// - `EXPORTER_SECRET`: `secret` is the post-handshake exporter secret
     ///   in a TLSv1.3 session.
+    /// - `EARLY_EXPORTER_SECRET`: `secret` is the early exporter secret
+    ///   in a TLSv1.3 session that used early data.
     ///
     /// These strings are selected to match the NSS key log format:
     /// <https://nss-crypto.org/reference/security/nss/legacy/key_log_format/index.html>
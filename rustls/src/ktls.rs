@@ -0,0 +1,400 @@
+//! Linux kernel TLS (kTLS) support.
+//!
+//! Once a handshake is complete, the record layer can be moved into the
+//! kernel: encryption and decryption then happen inside `read()`/`write()`
+//! (and `sendfile()`) on the socket.  This is done as follows:
+//!
+//! 1. Set [`ClientConfig::enable_secret_extraction`] or
+//!    [`ServerConfig::enable_secret_extraction`], and complete the handshake
+//!    as usual.  The connection must not have any buffered data
+//!    left to write or read, or step 3 fails.
+//! 2. Call [`enable()`] on the socket.
+//! 3. Call `dangerous_into_kernel_connection()` on the connection, and give
+//!    the resulting [`ExtractedSecrets`] to [`install()`].
+//! 4. Read and write application data directly on the socket.  Reads fail
+//!    with `EIO` when the next record is not application data: use
+//!    [`recv_record()`] to receive it, and pass it to
+//!    [`KernelConnection::handle_record()`].  Carry out the returned
+//!    [`KernelAction`]s with [`apply()`].
+//!
+//! This module is only available on Linux, with the `ktls` crate feature.
+//! It is the only part of rustls that uses `unsafe` code, which is
+//! confined to the calls to `setsockopt()`, `sendmsg()` and `recvmsg()`.
+//!
+//! Support for each suite and protocol version depends on the kernel version
+//! and configuration; in particular, updating the keys of a TLS1.3
+//! connection needs Linux 6.14 or later.  An unsupported suite or version
+//! results in an error from [`install()`], after which the socket cannot
+//! be used.
+//!
+//! [`ClientConfig::enable_secret_extraction`]: crate::ClientConfig::enable_secret_extraction
+//! [`ServerConfig::enable_secret_extraction`]: crate::ServerConfig::enable_secret_extraction
+//! [`KernelConnection::handle_record()`]: crate::kernel::KernelConnection::handle_record
+
+#![allow(unsafe_code)]
+
+use core::ffi::c_void;
+use core::mem::{self, size_of_val};
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use crate::enums::{ContentType, ProtocolVersion};
+use crate::kernel::KernelAction;
+use crate::suites::{ConnectionTrafficSecrets, ExtractedSecrets};
+
+/// Attach the kernel's TLS "upper layer protocol" to `socket`.
+///
+/// This must be done once, before [`install()`] or [`set_tx()`]/[`set_rx()`].
+pub fn enable(socket: &impl AsRawFd) -> io::Result<()> {
+    const NAME: &[u8] = b"tls\0";
+    setsockopt(socket, libc::SOL_TCP, libc::TCP_ULP, NAME)
+}
+
+/// Install both directions of `secrets` on `socket`.
+///
+/// `version` is the negotiated protocol version.
+pub fn install(
+    socket: &impl AsRawFd,
+    version: ProtocolVersion,
+    secrets: &ExtractedSecrets,
+) -> io::Result<()> {
+    let (tx_seq, tx) = &secrets.tx;
+    set_tx(socket, version, *tx_seq, tx)?;
+    let (rx_seq, rx) = &secrets.rx;
+    set_rx(socket, version, *rx_seq, rx)
+}
+
+/// Encrypt records sent on `socket` using `secrets`, starting at sequence number `seq`.
+pub fn set_tx(
+    socket: &impl AsRawFd,
+    version: ProtocolVersion,
+    seq: u64,
+    secrets: &ConnectionTrafficSecrets,
+) -> io::Result<()> {
+    CryptoInfo::new(version, seq, secrets)?.set(socket, libc::TLS_TX)
+}
+
+/// Decrypt records received on `socket` using `secrets`, starting at sequence number `seq`.
+pub fn set_rx(
+    socket: &impl AsRawFd,
+    version: ProtocolVersion,
+    seq: u64,
+    secrets: &ConnectionTrafficSecrets,
+) -> io::Result<()> {
+    CryptoInfo::new(version, seq, secrets)?.set(socket, libc::TLS_RX)
+}
+
+/// Carry out `action` on `socket`.
+///
+/// `version` is the negotiated protocol version.
+pub fn apply(
+    socket: &impl AsRawFd,
+    version: ProtocolVersion,
+    action: &KernelAction,
+) -> io::Result<()> {
+    match action {
+        KernelAction::UpdateRx(secrets) => set_rx(socket, version, 0, secrets),
+        KernelAction::SendKeyUpdate { message, secrets } => {
+            send_record(socket, ContentType::Handshake, message)?;
+            set_tx(socket, version, 0, secrets)
+        }
+        KernelAction::PeerClosed => Ok(()),
+    }
+}
+
+/// Send `payload` as a single record of type `typ`.
+///
+/// This is needed for anything other than application data, which can be
+/// written to the socket directly.
+pub fn send_record(socket: &impl AsRawFd, typ: ContentType, payload: &[u8]) -> io::Result<()> {
+    let mut control = ControlBuffer::new();
+    let mut iov = libc::iovec {
+        iov_base: payload.as_ptr() as *mut c_void,
+        iov_len: payload.len(),
+    };
+
+    // SAFETY: all-zeroes is a valid `msghdr`.
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    control.attach(&mut msg);
+
+    // SAFETY: `msg_control` points to `control`, which has room for a header
+    // and one byte of data.
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_TLS;
+        (*cmsg).cmsg_type = libc::TLS_SET_RECORD_TYPE;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(1) as _;
+        *libc::CMSG_DATA(cmsg) = u8::from(typ);
+    }
+
+    // SAFETY: `msg` and everything it points to outlives the call.
+    let sent = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) };
+    match sent {
+        -1 => Err(io::Error::last_os_error()),
+        n if n as usize == payload.len() => Ok(()),
+        _ => Err(io::ErrorKind::WriteZero.into()),
+    }
+}
+
+/// Receive a record from `socket` into `buf`.
+///
+/// Returns the record's type and the length of its (decrypted) payload.
+/// `buf` should be large enough for a whole record: 16KiB.
+///
+/// This fails if the socket has reached end of stream, or if the kernel did
+/// not say what the record's type is.
+pub fn recv_record(socket: &impl AsRawFd, buf: &mut [u8]) -> io::Result<(ContentType, usize)> {
+    let mut control = ControlBuffer::new();
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+
+    // SAFETY: all-zeroes is a valid `msghdr`.
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    control.attach(&mut msg);
+
+    // SAFETY: `msg` and everything it points to outlives the call.
+    let received = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if received == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if received == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "record type control message was truncated",
+        ));
+    }
+
+    // SAFETY: the kernel has filled in `msg_controllen` bytes of `control`,
+    // which `CMSG_FIRSTHDR` and `CMSG_NXTHDR` do not read beyond.
+    let mut typ = None;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_TLS && (*cmsg).cmsg_type == libc::TLS_GET_RECORD_TYPE
+            {
+                typ = Some(ContentType::from(*libc::CMSG_DATA(cmsg)));
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    match typ {
+        Some(typ) => Ok((typ, received as usize)),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no record type control message received",
+        )),
+    }
+}
+
+/// The `tls12_crypto_info_*` structure for a suite (also used for TLS1.3).
+enum CryptoInfo {
+    Aes128Gcm(libc::tls12_crypto_info_aes_gcm_128),
+    Aes256Gcm(libc::tls12_crypto_info_aes_gcm_256),
+    Chacha20Poly1305(libc::tls12_crypto_info_chacha20_poly1305),
+}
+
+impl CryptoInfo {
+    fn new(
+        version: ProtocolVersion,
+        seq: u64,
+        secrets: &ConnectionTrafficSecrets,
+    ) -> io::Result<Self> {
+        let version = match version {
+            ProtocolVersion::TLSv1_2 => libc::TLS_1_2_VERSION,
+            ProtocolVersion::TLSv1_3 => libc::TLS_1_3_VERSION,
+            _ => return Err(unsupported()),
+        };
+        let rec_seq = seq.to_be_bytes();
+
+        // For the AES-GCM suites the kernel wants the 12-byte IV split into
+        // its implicit "salt" and the remainder.
+        Ok(match secrets {
+            ConnectionTrafficSecrets::Aes128Gcm { key, iv } => {
+                Self::Aes128Gcm(libc::tls12_crypto_info_aes_gcm_128 {
+                    info: libc::tls_crypto_info {
+                        version,
+                        cipher_type: libc::TLS_CIPHER_AES_GCM_128,
+                    },
+                    iv: copied(&iv.as_ref()[4..])?,
+                    key: copied(key.as_ref())?,
+                    salt: copied(&iv.as_ref()[..4])?,
+                    rec_seq,
+                })
+            }
+            ConnectionTrafficSecrets::Aes256Gcm { key, iv } => {
+                Self::Aes256Gcm(libc::tls12_crypto_info_aes_gcm_256 {
+                    info: libc::tls_crypto_info {
+                        version,
+                        cipher_type: libc::TLS_CIPHER_AES_GCM_256,
+                    },
+                    iv: copied(&iv.as_ref()[4..])?,
+                    key: copied(key.as_ref())?,
+                    salt: copied(&iv.as_ref()[..4])?,
+                    rec_seq,
+                })
+            }
+            ConnectionTrafficSecrets::Chacha20Poly1305 { key, iv } => {
+                Self::Chacha20Poly1305(libc::tls12_crypto_info_chacha20_poly1305 {
+                    info: libc::tls_crypto_info {
+                        version,
+                        cipher_type: libc::TLS_CIPHER_CHACHA20_POLY1305,
+                    },
+                    iv: copied(iv.as_ref())?,
+                    key: copied(key.as_ref())?,
+                    salt: [],
+                    rec_seq,
+                })
+            }
+        })
+    }
+
+    fn set(&self, socket: &impl AsRawFd, direction: libc::c_int) -> io::Result<()> {
+        match self {
+            Self::Aes128Gcm(info) => setsockopt(socket, libc::SOL_TLS, direction, info),
+            Self::Aes256Gcm(info) => setsockopt(socket, libc::SOL_TLS, direction, info),
+            Self::Chacha20Poly1305(info) => setsockopt(socket, libc::SOL_TLS, direction, info),
+        }
+    }
+}
+
+impl Drop for CryptoInfo {
+    fn drop(&mut self) {
+        use zeroize::Zeroize;
+        match self {
+            Self::Aes128Gcm(info) => {
+                info.key.zeroize();
+                info.iv.zeroize();
+                info.salt.zeroize();
+            }
+            Self::Aes256Gcm(info) => {
+                info.key.zeroize();
+                info.iv.zeroize();
+                info.salt.zeroize();
+            }
+            Self::Chacha20Poly1305(info) => {
+                info.key.zeroize();
+                info.iv.zeroize();
+            }
+        }
+    }
+}
+
+fn setsockopt<T: ?Sized>(
+    socket: &impl AsRawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: &T,
+) -> io::Result<()> {
+    let ptr: *const T = value;
+    // SAFETY: `value` is valid for reads of its size for the duration of the call.
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            ptr.cast(),
+            size_of_val(value) as libc::socklen_t,
+        )
+    };
+    match ret {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+fn copied<const N: usize>(bytes: &[u8]) -> io::Result<[u8; N]> {
+    bytes
+        .try_into()
+        .map_err(|_| unsupported())
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "protocol version or cipher suite not supported by kernel TLS",
+    )
+}
+
+/// Room for one control message carrying a record type, suitably aligned.
+struct ControlBuffer([libc::cmsghdr; 2]);
+
+impl ControlBuffer {
+    fn new() -> Self {
+        // SAFETY: all-zeroes is a valid `cmsghdr`.
+        Self(unsafe { mem::zeroed() })
+    }
+
+    fn attach(&mut self, msg: &mut libc::msghdr) {
+        msg.msg_control = self.0.as_mut_ptr().cast();
+        // `msg_controllen` is a `size_t` on glibc but a `socklen_t` on musl.
+        #[allow(trivial_numeric_casts)]
+        {
+            // SAFETY: `CMSG_SPACE` only does arithmetic.
+            msg.msg_controllen = unsafe { libc::CMSG_SPACE(1) } as _;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::cipher::{AeadKey, Iv};
+
+    #[test]
+    fn aes_gcm_iv_is_split_into_salt() {
+        let secrets = ConnectionTrafficSecrets::Aes128Gcm {
+            key: AeadKey::from([0x11; 32]).with_length(16),
+            iv: Iv::new([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]),
+        };
+        let CryptoInfo::Aes128Gcm(info) =
+            CryptoInfo::new(ProtocolVersion::TLSv1_3, 0x0102, &secrets).unwrap()
+        else {
+            panic!("wrong suite");
+        };
+        assert_eq!(info.info.version, libc::TLS_1_3_VERSION);
+        assert_eq!(info.info.cipher_type, libc::TLS_CIPHER_AES_GCM_128);
+        assert_eq!(info.salt, [1, 2, 3, 4]);
+        assert_eq!(info.iv, [5, 6, 7, 8, 9, 10, 11, 12]);
+        assert_eq!(info.key, [0x11; 16]);
+        assert_eq!(info.rec_seq, [0, 0, 0, 0, 0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn chacha20_poly1305_uses_whole_iv() {
+        let secrets = ConnectionTrafficSecrets::Chacha20Poly1305 {
+            key: AeadKey::from([0x22; 32]),
+            iv: Iv::new([7; 12]),
+        };
+        let CryptoInfo::Chacha20Poly1305(info) =
+            CryptoInfo::new(ProtocolVersion::TLSv1_2, 0, &secrets).unwrap()
+        else {
+            panic!("wrong suite");
+        };
+        assert_eq!(info.info.version, libc::TLS_1_2_VERSION);
+        assert_eq!(info.iv, [7; 12]);
+        assert_eq!(info.key, [0x22; 32]);
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let secrets = ConnectionTrafficSecrets::Aes256Gcm {
+            key: AeadKey::from([0; 32]),
+            iv: Iv::new([0; 12]),
+        };
+        let err = CryptoInfo::new(ProtocolVersion::TLSv1_1, 0, &secrets)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+}
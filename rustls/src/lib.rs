@@ -307,6 +307,10 @@
 //!   and protocol-level errors at `warn!` and `error!` level.  The log messages do not
 //!   contain secret key data, and so are safe to archive without affecting session security.
 //!
+//! - `tracing`: make the rustls crate depend on the `tracing` crate, and provide
+//!   `TracingObserver`, which reports the handshake events of each connection as
+//!   `tracing` events.  See [`HandshakeObserver`].
+//!
 //! - `read_buf`: when building with Rust Nightly, adds support for the unstable
 //!   `std::io::ReadBuf` and related APIs. This reduces costs from initializing
 //!   buffers. Will do nothing on non-Nightly releases.
@@ -315,9 +319,19 @@
 //!
 //! - `zlib`: uses the `zlib-rs` crate for RFC8879 certificate compression support.
 //!
+//! - `zstd`: uses the `zstd` crate for RFC8879 certificate compression support.
+//!   Note that `zstd` wraps the C implementation of Zstandard, so this needs a
+//!   C compiler.
+//!
+//! - `ktls`: on Linux, adds the `ktls` module for handing the record layer
+//!   of a connection to the kernel.  This is the only feature that brings
+//!   `unsafe` code into rustls.
+//!
 
 // Require docs for public APIs, deny unsafe code, etc.
-#![forbid(unsafe_code, unused_must_use)]
+#![forbid(unused_must_use)]
+#![cfg_attr(not(feature = "ktls"), forbid(unsafe_code))]
+#![cfg_attr(feature = "ktls", deny(unsafe_code))]
 #![cfg_attr(not(any(read_buf, bench)), forbid(unstable_features))]
 #![warn(
     clippy::alloc_instead_of_core,
@@ -408,12 +422,15 @@ pub mod compress;
 mod conn;
 /// Crypto provider interface.
 pub mod crypto;
+mod custom_extension;
 mod error;
+mod grease;
 mod hash_hs;
 #[cfg(any(feature = "std", feature = "hashbrown"))]
 mod limited_cache;
 mod rand;
 mod record_layer;
+mod record_padding;
 #[cfg(feature = "std")]
 mod stream;
 #[cfg(feature = "tls12")]
@@ -433,6 +450,7 @@ mod enums;
 mod key_log;
 #[cfg(feature = "std")]
 mod key_log_file;
+mod observer;
 mod suites;
 mod versions;
 mod webpki;
@@ -513,8 +531,8 @@ pub mod internal {
 pub mod unbuffered {
     pub use crate::conn::unbuffered::{
         AppDataRecord, ConnectionState, EncodeError, EncodeTlsData, EncryptError,
-        InsufficientSizeError, ReadEarlyData, ReadTraffic, TransmitTlsData, UnbufferedStatus,
-        WriteTraffic,
+        InsufficientSizeError, PerformOperation, ReadEarlyData, ReadTraffic, TransmitTlsData,
+        UnbufferedStatus, WriteTraffic,
     };
     pub use crate::conn::UnbufferedConnectionCommon;
 }
@@ -525,6 +543,7 @@ pub use crate::common_state::{CommonState, HandshakeKind, IoState, Side};
 #[cfg(feature = "std")]
 pub use crate::conn::{Connection, Reader, Writer};
 pub use crate::conn::{ConnectionCommon, SideData};
+pub use crate::custom_extension::CustomExtension;
 pub use crate::enums::{
     AlertDescription, CertificateCompressionAlgorithm, CipherSuite, ContentType, HandshakeType,
     ProtocolVersion, SignatureAlgorithm, SignatureScheme,
@@ -536,18 +555,24 @@ pub use crate::error::{
 pub use crate::key_log::{KeyLog, NoKeyLog};
 #[cfg(feature = "std")]
 pub use crate::key_log_file::KeyLogFile;
-pub use crate::msgs::enums::NamedGroup;
+pub use crate::msgs::enums::{
+    Compression, ECPointFormat, ExtensionType, NamedGroup, PSKKeyExchangeMode,
+};
 pub use crate::msgs::ffdhe_groups;
 pub use crate::msgs::handshake::DistinguishedName;
+#[cfg(feature = "tracing")]
+pub use crate::observer::TracingObserver;
+pub use crate::observer::{ConnectionObserver, HandshakeEvent, HandshakeObserver};
+pub use crate::record_padding::{PadsRecords, RecordPadding};
 #[cfg(feature = "std")]
 pub use crate::stream::{Stream, StreamOwned};
 pub use crate::suites::{
     CipherSuiteCommon, ConnectionTrafficSecrets, ExtractedSecrets, SupportedCipherSuite,
 };
-#[cfg(feature = "std")]
-pub use crate::ticketer::TicketRotator;
 #[cfg(any(feature = "std", feature = "hashbrown"))] // < XXX: incorrect feature gate
 pub use crate::ticketer::TicketSwitcher;
+#[cfg(feature = "std")]
+pub use crate::ticketer::{SharedKeyTicketer, TicketKey, TicketKeys, TicketRotator};
 #[cfg(feature = "tls12")]
 pub use crate::tls12::Tls12CipherSuite;
 pub use crate::tls13::Tls13CipherSuite;
@@ -570,12 +595,14 @@ pub mod client {
     pub use builder::WantsClientCert;
     pub use client_conn::{
         ClientConfig, ClientConnectionData, ClientSessionStore, EarlyDataError, ResolvesClientCert,
-        Resumption, Tls12Resumption, UnbufferedClientConnection,
+        ResolvesClientPsk, Resumption, Tls12Resumption, UnbufferedClientConnection,
     };
     #[cfg(feature = "std")]
     pub use client_conn::{ClientConnection, WriteEarlyData};
     pub use ech::{EchConfig, EchGreaseConfig, EchMode, EchStatus};
     pub use handy::AlwaysResolvesClientRawPublicKeys;
+    #[cfg(feature = "std")]
+    pub use handy::ClientSessionFileStore;
     #[cfg(any(feature = "std", feature = "hashbrown"))]
     pub use handy::ClientSessionMemoryCache;
 
@@ -586,10 +613,17 @@ pub mod client {
         pub use crate::verify::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
     }
 
+    /// Certificate Transparency support for [`WebPkiServerVerifier`].
+    ///
+    /// See [`ServerCertVerifierBuilder::with_certificate_transparency`].
+    pub mod ct {
+        pub use crate::webpki::{CtLog, CtPolicy, DistinctOperatorsPolicy, SctSource, VerifiedSct};
+    }
+
     pub use crate::msgs::persist::{Tls12ClientSessionValue, Tls13ClientSessionValue};
     pub use crate::webpki::{
-        verify_server_cert_signed_by_trust_anchor, verify_server_name, ServerCertVerifierBuilder,
-        VerifierBuilderError, WebPkiServerVerifier,
+        verify_server_cert_signed_by_trust_anchor, verify_server_name, OcspStaplingPolicy,
+        ServerCertVerifierBuilder, VerifierBuilderError, WebPkiServerVerifier,
     };
 }
 
@@ -601,6 +635,7 @@ pub use client::ClientConnection;
 pub mod server {
     pub(crate) mod builder;
     mod common;
+    mod ech;
     pub(crate) mod handy;
     mod hs;
     mod server_conn;
@@ -609,17 +644,22 @@ pub mod server {
     mod tls13;
 
     pub use builder::WantsServerCert;
+    pub use ech::EchServerKey;
     #[cfg(any(feature = "std", feature = "hashbrown"))]
     pub use handy::ResolvesServerCertUsingSni;
-    #[cfg(any(feature = "std", feature = "hashbrown"))]
-    pub use handy::ServerSessionMemoryCache;
     pub use handy::{AlwaysResolvesServerRawPublicKeys, NoServerSessionStorage};
+    #[cfg(feature = "std")]
+    pub use handy::{ReloadOutcome, ReportsCertReloads, ResolvesServerCertFromFiles};
+    #[cfg(any(feature = "std", feature = "hashbrown"))]
+    pub use handy::{ServerSessionMemoryCache, StrikeRegister};
     pub use server_conn::{
-        Accepted, ClientHello, ProducesTickets, ResolvesServerCert, ServerConfig,
-        ServerConnectionData, StoresServerSessions, UnbufferedServerConnection,
+        Accepted, AcceptedAlert, ClientHello, HandlesCustomExtensions, OperationResult,
+        PendingOperation, ProducesTickets, RecordsClientHellos, ResolvesServerCert,
+        ResolvesServerPsk, ServerConfig, ServerConnectionData, StoresServerSessions,
+        UnbufferedAcceptStatus, UnbufferedAccepted, UnbufferedAcceptor, UnbufferedServerConnection,
     };
     #[cfg(feature = "std")]
-    pub use server_conn::{AcceptedAlert, Acceptor, ReadEarlyData, ServerConnection};
+    pub use server_conn::{Acceptor, ReadEarlyData, ServerConnection};
 
     pub use crate::verify::NoClientAuth;
     pub use crate::webpki::{
@@ -645,6 +685,14 @@ pub mod version {
     pub use crate::versions::TLS13;
 }
 
+/// Continuing connections after their record layer has been handed elsewhere,
+/// such as to the operating system kernel.
+///
+/// See [`ConnectionCommon::dangerous_into_kernel_connection()`].
+pub mod kernel {
+    pub use crate::conn::kernel::{KernelAction, KernelConnection};
+}
+
 /// Re-exports the contents of the [rustls-pki-types](https://docs.rs/rustls-pki-types) crate for easy access
 pub mod pki_types {
     #[doc(no_inline)]
@@ -659,6 +707,16 @@ pub mod sign {
 /// APIs for implementing QUIC TLS
 pub mod quic;
 
+pub mod dtls;
+
+pub mod delegated_credential;
+
+#[cfg(all(feature = "ktls", target_os = "linux"))]
+pub mod ktls;
+
+/// External pre-shared keys for TLS1.3.
+pub mod psk;
+
 #[cfg(any(feature = "std", feature = "hashbrown"))] // < XXX: incorrect feature gate
 /// APIs for implementing TLS tickets
 pub mod ticketer;
@@ -680,9 +738,13 @@ mod hash_map {
     pub(crate) use std::collections::hash_map::Entry;
     #[cfg(feature = "std")]
     pub(crate) use std::collections::HashMap;
+    #[cfg(feature = "std")]
+    pub(crate) use std::collections::HashSet;
 
     #[cfg(all(not(feature = "std"), feature = "hashbrown"))]
     pub(crate) use hashbrown::hash_map::Entry;
     #[cfg(all(not(feature = "std"), feature = "hashbrown"))]
     pub(crate) use hashbrown::HashMap;
+    #[cfg(all(not(feature = "std"), feature = "hashbrown"))]
+    pub(crate) use hashbrown::HashSet;
 }
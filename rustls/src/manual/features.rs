@@ -29,7 +29,7 @@ APIs ([`CryptoProvider`] for example).
 * [RFC7250](https://tools.ietf.org/html/rfc7250) raw public keys for TLS1.3
 * [RFC8879](https://tools.ietf.org/html/rfc8879) certificate compression by clients
   and servers `*`
-* Client-side Encrypted client hello (ECH)
+* Encrypted client hello (ECH) by clients and servers
    ([draft-ietf-tls-esni](https://datatracker.ietf.org/doc/draft-ietf-tls-esni/)).
 
 [^1]: Note that, at the time of writing, Ed25519 does not have wide support
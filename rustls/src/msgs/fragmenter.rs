@@ -68,6 +68,49 @@ impl MessageFragmenter {
         };
         Ok(())
     }
+
+    /// Return the largest payload, in bytes, of the fragments produced.
+    pub(crate) fn max_fragment_len(&self) -> usize {
+        self.max_frag
+    }
+
+    /// Further restrict the fragments produced to at most `max_fragment_len` bytes
+    /// of payload, as required by the peer's `record_size_limit` extension.
+    pub(crate) fn limit_fragment_len(&mut self, max_fragment_len: usize) {
+        self.max_frag = self.max_frag.min(max_fragment_len);
+    }
+}
+
+/// The smallest `record_size_limit` a peer may advertise.
+pub(crate) const MIN_RECORD_SIZE_LIMIT: u16 = 64;
+
+/// The largest `record_size_limit` meaningful in TLS1.3.
+pub(crate) const MAX_RECORD_SIZE_LIMIT: u16 = MAX_FRAGMENT_LEN as u16 + 1;
+
+/// Check a `record_size_limit` supplied in configuration is in range.
+pub(crate) fn check_record_size_limit(limit: Option<u16>) -> Result<(), Error> {
+    match limit {
+        Some(MIN_RECORD_SIZE_LIMIT..=MAX_RECORD_SIZE_LIMIT) | None => Ok(()),
+        Some(_) => Err(Error::BadRecordSizeLimit),
+    }
+}
+
+/// Return the largest plaintext fragment allowed by a `record_size_limit` of `limit`.
+///
+/// In TLS1.3 the limit covers the whole `TLSInnerPlaintext`, so includes the content
+/// type byte (and any padding).  See [RFC 8449 section 4].
+///
+/// [RFC 8449 section 4]: https://www.rfc-editor.org/rfc/rfc8449#section-4
+pub(crate) fn max_fragment_len_for_record_size_limit(
+    limit: u16,
+    version: ProtocolVersion,
+) -> usize {
+    let limit = usize::from(limit);
+    let len = match version {
+        ProtocolVersion::TLSv1_3 => limit - 1,
+        _ => limit,
+    };
+    len.min(MAX_FRAGMENT_LEN)
 }
 
 /// An iterator over borrowed fragments of a payload
@@ -12,6 +12,7 @@ use pki_types::{CertificateDer, DnsName};
 #[cfg(feature = "tls12")]
 use crate::crypto::ActiveKeyExchange;
 use crate::crypto::SecureRandom;
+use crate::delegated_credential::DelegatedCredential;
 use crate::enums::{
     CertificateCompressionAlgorithm, CipherSuite, EchClientHelloType, HandshakeType,
     ProtocolVersion, SignatureScheme,
@@ -177,7 +178,6 @@ impl SessionId {
         }
     }
 
-    #[cfg(feature = "tls12")]
     pub(crate) fn is_empty(&self) -> bool {
         self.len == 0
     }
@@ -439,6 +439,13 @@ impl PresharedKeyOffer {
             binders: vec![PresharedKeyBinder::from(binder)],
         }
     }
+
+    /// Add another entry.
+    pub(crate) fn push(&mut self, id: PresharedKeyIdentity, binder: Vec<u8>) {
+        self.identities.push(id);
+        self.binders
+            .push(PresharedKeyBinder::from(binder));
+    }
 }
 
 impl Codec<'_> for PresharedKeyOffer {
@@ -563,12 +570,16 @@ pub enum ClientExtension {
     Cookie(PayloadU16),
     ExtendedMasterSecretRequest,
     CertificateStatusRequest(CertificateStatusRequest),
+    SignedCertificateTimestampRequest,
     ServerCertTypes(Vec<CertificateType>),
     ClientCertTypes(Vec<CertificateType>),
     TransportParameters(Vec<u8>),
     TransportParametersDraft(Vec<u8>),
     EarlyData,
+    PostHandshakeAuth,
     CertificateCompressionAlgorithms(Vec<CertificateCompressionAlgorithm>),
+    RecordSizeLimit(u16),
+    DelegatedCredential(Vec<SignatureScheme>),
     EncryptedClientHello(EncryptedClientHello),
     EncryptedClientHelloOuterExtensions(Vec<ExtensionType>),
     AuthorityNames(Vec<DistinguishedName>),
@@ -591,12 +602,16 @@ impl ClientExtension {
             Self::Cookie(_) => ExtensionType::Cookie,
             Self::ExtendedMasterSecretRequest => ExtensionType::ExtendedMasterSecret,
             Self::CertificateStatusRequest(_) => ExtensionType::StatusRequest,
+            Self::SignedCertificateTimestampRequest => ExtensionType::SCT,
             Self::ClientCertTypes(_) => ExtensionType::ClientCertificateType,
             Self::ServerCertTypes(_) => ExtensionType::ServerCertificateType,
             Self::TransportParameters(_) => ExtensionType::TransportParameters,
             Self::TransportParametersDraft(_) => ExtensionType::TransportParametersDraft,
             Self::EarlyData => ExtensionType::EarlyData,
+            Self::PostHandshakeAuth => ExtensionType::PostHandshakeAuth,
             Self::CertificateCompressionAlgorithms(_) => ExtensionType::CompressCertificate,
+            Self::RecordSizeLimit(_) => ExtensionType::RecordSizeLimit,
+            Self::DelegatedCredential(_) => ExtensionType::DelegatedCredential,
             Self::EncryptedClientHello(_) => ExtensionType::EncryptedClientHello,
             Self::EncryptedClientHelloOuterExtensions(_) => {
                 ExtensionType::EncryptedClientHelloOuterExtensions
@@ -619,7 +634,9 @@ impl Codec<'_> for ClientExtension {
             Self::ServerName(ref r) => r.encode(nested.buf),
             Self::SessionTicket(ClientSessionTicket::Request)
             | Self::ExtendedMasterSecretRequest
-            | Self::EarlyData => {}
+            | Self::SignedCertificateTimestampRequest
+            | Self::EarlyData
+            | Self::PostHandshakeAuth => {}
             Self::SessionTicket(ClientSessionTicket::Offer(ref r)) => r.encode(nested.buf),
             Self::Protocols(ref r) => r.encode(nested.buf),
             Self::SupportedVersions(ref r) => r.encode(nested.buf),
@@ -634,6 +651,8 @@ impl Codec<'_> for ClientExtension {
                 nested.buf.extend_from_slice(r);
             }
             Self::CertificateCompressionAlgorithms(ref r) => r.encode(nested.buf),
+            Self::RecordSizeLimit(ref r) => r.encode(nested.buf),
+            Self::DelegatedCredential(ref r) => r.encode(nested.buf),
             Self::EncryptedClientHello(ref r) => r.encode(nested.buf),
             Self::EncryptedClientHelloOuterExtensions(ref r) => r.encode(nested.buf),
             Self::AuthorityNames(ref r) => r.encode(nested.buf),
@@ -674,14 +693,21 @@ impl Codec<'_> for ClientExtension {
                 let csr = CertificateStatusRequest::read(&mut sub)?;
                 Self::CertificateStatusRequest(csr)
             }
+            ExtensionType::SCT if !sub.any_left() => Self::SignedCertificateTimestampRequest,
             ExtensionType::TransportParameters => Self::TransportParameters(sub.rest().to_vec()),
             ExtensionType::TransportParametersDraft => {
                 Self::TransportParametersDraft(sub.rest().to_vec())
             }
             ExtensionType::EarlyData if !sub.any_left() => Self::EarlyData,
+            ExtensionType::PostHandshakeAuth if !sub.any_left() => Self::PostHandshakeAuth,
             ExtensionType::CompressCertificate => {
                 Self::CertificateCompressionAlgorithms(Vec::read(&mut sub)?)
             }
+            ExtensionType::RecordSizeLimit => Self::RecordSizeLimit(u16::read(&mut sub)?),
+            ExtensionType::DelegatedCredential => Self::DelegatedCredential(Vec::read(&mut sub)?),
+            ExtensionType::EncryptedClientHello => {
+                Self::EncryptedClientHello(EncryptedClientHello::read(&mut sub)?)
+            }
             ExtensionType::EncryptedClientHelloOuterExtensions => {
                 Self::EncryptedClientHelloOuterExtensions(Vec::read(&mut sub)?)
             }
@@ -738,12 +764,14 @@ pub enum ServerExtension {
     PresharedKey(u16),
     ExtendedMasterSecretAck,
     CertificateStatusAck,
+    SignedCertificateTimestamp(Vec<PayloadU16>),
     ServerCertType(CertificateType),
     ClientCertType(CertificateType),
     SupportedVersions(ProtocolVersion),
     TransportParameters(Vec<u8>),
     TransportParametersDraft(Vec<u8>),
     EarlyData,
+    RecordSizeLimit(u16),
     EncryptedClientHello(ServerEncryptedClientHello),
     Unknown(UnknownExtension),
 }
@@ -762,10 +790,12 @@ impl ServerExtension {
             Self::ServerCertType(_) => ExtensionType::ServerCertificateType,
             Self::ExtendedMasterSecretAck => ExtensionType::ExtendedMasterSecret,
             Self::CertificateStatusAck => ExtensionType::StatusRequest,
+            Self::SignedCertificateTimestamp(_) => ExtensionType::SCT,
             Self::SupportedVersions(_) => ExtensionType::SupportedVersions,
             Self::TransportParameters(_) => ExtensionType::TransportParameters,
             Self::TransportParametersDraft(_) => ExtensionType::TransportParametersDraft,
             Self::EarlyData => ExtensionType::EarlyData,
+            Self::RecordSizeLimit(_) => ExtensionType::RecordSizeLimit,
             Self::EncryptedClientHello(_) => ExtensionType::EncryptedClientHello,
             Self::Unknown(ref r) => r.typ,
         }
@@ -787,7 +817,8 @@ impl Codec<'_> for ServerExtension {
             Self::RenegotiationInfo(ref r) => r.encode(nested.buf),
             Self::Protocols(ref r) => r.encode(nested.buf),
             Self::KeyShare(ref r) => r.encode(nested.buf),
-            Self::PresharedKey(r) => r.encode(nested.buf),
+            Self::PresharedKey(r) | Self::RecordSizeLimit(r) => r.encode(nested.buf),
+            Self::SignedCertificateTimestamp(ref r) => r.encode(nested.buf),
             Self::ClientCertType(r) => r.encode(nested.buf),
             Self::ServerCertType(r) => r.encode(nested.buf),
             Self::SupportedVersions(ref r) => r.encode(nested.buf),
@@ -809,6 +840,7 @@ impl Codec<'_> for ServerExtension {
             ExtensionType::ServerName => Self::ServerNameAck,
             ExtensionType::SessionTicket => Self::SessionTicketAck,
             ExtensionType::StatusRequest => Self::CertificateStatusAck,
+            ExtensionType::SCT => Self::SignedCertificateTimestamp(Vec::read(&mut sub)?),
             ExtensionType::RenegotiationInfo => Self::RenegotiationInfo(PayloadU8::read(&mut sub)?),
             ExtensionType::ALProtocolNegotiation => Self::Protocols(Vec::read(&mut sub)?),
             ExtensionType::ClientCertificateType => {
@@ -828,6 +860,7 @@ impl Codec<'_> for ServerExtension {
                 Self::TransportParametersDraft(sub.rest().to_vec())
             }
             ExtensionType::EarlyData => Self::EarlyData,
+            ExtensionType::RecordSizeLimit => Self::RecordSizeLimit(u16::read(&mut sub)?),
             ExtensionType::EncryptedClientHello => {
                 Self::EncryptedClientHello(ServerEncryptedClientHello::read(&mut sub)?)
             }
@@ -849,6 +882,28 @@ impl ServerExtension {
         let empty = Vec::new();
         Self::RenegotiationInfo(PayloadU8::new(empty))
     }
+
+    #[cfg(feature = "tls12")]
+    pub(crate) fn make_sct(sct_list: &[u8]) -> Option<Self> {
+        read_sct_list(sct_list).map(Self::SignedCertificateTimestamp)
+    }
+}
+
+/// Parse a `SignedCertificateTimestampList`, as defined in RFC 6962 section 3.3.
+///
+/// The individual SCTs are not examined.  Returns `None` if the list is
+/// malformed or empty.
+pub(crate) fn read_sct_list(sct_list: &[u8]) -> Option<Vec<PayloadU16>> {
+    let scts = Vec::<PayloadU16>::read_bytes(sct_list).ok()?;
+    match scts.is_empty() || scts.iter().any(|sct| sct.0.is_empty()) {
+        true => None,
+        false => Some(scts),
+    }
+}
+
+/// A `SerializedSCT` in a `SignedCertificateTimestampList`.
+impl TlsListElement for PayloadU16 {
+    const SIZE_LEN: ListLength = ListLength::U16;
 }
 
 #[derive(Clone, Debug)]
@@ -856,6 +911,8 @@ pub struct ClientHelloPayload {
     pub client_version: ProtocolVersion,
     pub random: Random,
     pub session_id: SessionId,
+    /// DTLS only: present exactly when `client_version` is a DTLS version.
+    pub legacy_cookie: Option<PayloadU8>,
     pub cipher_suites: Vec<CipherSuite>,
     pub compression_methods: Vec<Compression>,
     pub extensions: Vec<ClientExtension>,
@@ -867,10 +924,15 @@ impl Codec<'_> for ClientHelloPayload {
     }
 
     fn read(r: &mut Reader<'_>) -> Result<Self, InvalidMessage> {
+        let client_version = ProtocolVersion::read(r)?;
         let mut ret = Self {
-            client_version: ProtocolVersion::read(r)?,
+            client_version,
             random: Random::read(r)?,
             session_id: SessionId::read(r)?,
+            legacy_cookie: match client_version.is_dtls() {
+                true => Some(PayloadU8::read(r)?),
+                false => None,
+            },
             cipher_suites: Vec::read(r)?,
             compression_methods: Vec::read(r)?,
             extensions: Vec::new(),
@@ -921,6 +983,10 @@ impl ClientHelloPayload {
             _ => self.session_id.encode(bytes),
         }
 
+        if let Some(cookie) = &self.legacy_cookie {
+            cookie.encode(bytes);
+        }
+
         self.cipher_suites.encode(bytes);
         self.compression_methods.encode(bytes);
 
@@ -1023,7 +1089,6 @@ impl ClientHelloPayload {
         }
     }
 
-    #[cfg(feature = "tls12")]
     pub(crate) fn ecpoints_extension(&self) -> Option<&[ECPointFormat]> {
         let ext = self.find_extension(ExtensionType::ECPointFormats)?;
         match *ext {
@@ -1128,24 +1193,90 @@ impl ClientHelloPayload {
             .unwrap_or(false)
     }
 
-    pub(crate) fn set_psk_binder(&mut self, binder: impl Into<Vec<u8>>) {
+    pub(crate) fn set_psk_binder(&mut self, index: usize, binder: impl Into<Vec<u8>>) {
         let last_extension = self.extensions.last_mut();
         if let Some(ClientExtension::PresharedKey(ref mut offer)) = last_extension {
-            offer.binders[0] = PresharedKeyBinder::from(binder.into());
+            offer.binders[index] = PresharedKeyBinder::from(binder.into());
         }
     }
 
+    /// Add a [padding extension][RFC 7685], if needed to make the encoding of this
+    /// ClientHello (as a handshake message, including its header) at least `target`
+    /// bytes long.
+    ///
+    /// The extension goes before any PSK and ECH extensions, which must come last.
+    /// Their contents may change afterwards, but not their length.
+    ///
+    /// [RFC 7685]: https://www.rfc-editor.org/rfc/rfc7685
+    pub(crate) fn pad_to(&mut self, target: u16) {
+        const HANDSHAKE_HEADER_LEN: usize = 4;
+        const EXTENSION_HEADER_LEN: usize = 4;
+
+        let len = HANDSHAKE_HEADER_LEN + self.get_encoding().len();
+        if len >= usize::from(target) {
+            return;
+        }
+
+        let padding_len = usize::from(target).saturating_sub(len + EXTENSION_HEADER_LEN);
+        let position = self
+            .extensions
+            .iter()
+            .rposition(|ext| {
+                !matches!(
+                    ext,
+                    ClientExtension::PresharedKey(_) | ClientExtension::EncryptedClientHello(_)
+                )
+            })
+            .map_or(0, |index| index + 1);
+        self.extensions.insert(
+            position,
+            ClientExtension::Unknown(UnknownExtension {
+                typ: ExtensionType::Padding,
+                payload: Payload::new(vec![0; padding_len]),
+            }),
+        );
+    }
+
     #[cfg(feature = "tls12")]
     pub(crate) fn ems_support_offered(&self) -> bool {
         self.find_extension(ExtensionType::ExtendedMasterSecret)
             .is_some()
     }
 
+    pub(crate) fn cookie(&self) -> Option<&PayloadU16> {
+        let ext = self.find_extension(ExtensionType::Cookie)?;
+        match *ext {
+            ClientExtension::Cookie(ref ck) => Some(ck),
+            _ => None,
+        }
+    }
+
     pub(crate) fn early_data_extension_offered(&self) -> bool {
         self.find_extension(ExtensionType::EarlyData)
             .is_some()
     }
 
+    pub(crate) fn post_handshake_auth_offered(&self) -> bool {
+        self.find_extension(ExtensionType::PostHandshakeAuth)
+            .is_some()
+    }
+
+    pub(crate) fn record_size_limit(&self) -> Option<u16> {
+        let ext = self.find_extension(ExtensionType::RecordSizeLimit)?;
+        match *ext {
+            ClientExtension::RecordSizeLimit(limit) => Some(limit),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn delegated_credential_schemes(&self) -> Option<&[SignatureScheme]> {
+        let ext = self.find_extension(ExtensionType::DelegatedCredential)?;
+        match *ext {
+            ClientExtension::DelegatedCredential(ref schemes) => Some(schemes),
+            _ => None,
+        }
+    }
+
     pub(crate) fn certificate_compression_extension(
         &self,
     ) -> Option<&[CertificateCompressionAlgorithm]> {
@@ -1170,6 +1301,14 @@ impl ClientHelloPayload {
             _ => unreachable!("extension type checked"),
         }
     }
+
+    pub(crate) fn ech_extension(&self) -> Option<&EncryptedClientHello> {
+        let ext = self.find_extension(ExtensionType::EncryptedClientHello)?;
+        match *ext {
+            ClientExtension::EncryptedClientHello(ref ech) => Some(ech),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1434,6 +1573,15 @@ impl ServerHelloPayload {
             .is_some()
     }
 
+    #[cfg(feature = "tls12")]
+    pub(crate) fn sct_list(&self) -> Option<&Vec<PayloadU16>> {
+        let ext = self.find_extension(ExtensionType::SCT)?;
+        match *ext {
+            ServerExtension::SignedCertificateTimestamp(ref scts) => Some(scts),
+            _ => None,
+        }
+    }
+
     pub(crate) fn supported_versions(&self) -> Option<ProtocolVersion> {
         let ext = self.find_extension(ExtensionType::SupportedVersions)?;
         match *ext {
@@ -1516,6 +1664,8 @@ pub(crate) const CERTIFICATE_MAX_SIZE_LIMIT: usize = 0x1_0000;
 #[derive(Debug)]
 pub(crate) enum CertificateExtension<'a> {
     CertificateStatus(CertificateStatus<'a>),
+    SignedCertificateTimestamp(Vec<PayloadU16>),
+    DelegatedCredential(DelegatedCredential),
     Unknown(UnknownExtension),
 }
 
@@ -1523,6 +1673,8 @@ impl CertificateExtension<'_> {
     pub(crate) fn ext_type(&self) -> ExtensionType {
         match *self {
             Self::CertificateStatus(_) => ExtensionType::StatusRequest,
+            Self::SignedCertificateTimestamp(_) => ExtensionType::SCT,
+            Self::DelegatedCredential(_) => ExtensionType::DelegatedCredential,
             Self::Unknown(ref r) => r.typ,
         }
     }
@@ -1534,9 +1686,27 @@ impl CertificateExtension<'_> {
         }
     }
 
+    pub(crate) fn sct_list(&self) -> Option<&Vec<PayloadU16>> {
+        match *self {
+            Self::SignedCertificateTimestamp(ref scts) => Some(scts),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn delegated_credential(&self) -> Option<&DelegatedCredential> {
+        match *self {
+            Self::DelegatedCredential(ref dc) => Some(dc),
+            _ => None,
+        }
+    }
+
     pub(crate) fn into_owned(self) -> CertificateExtension<'static> {
         match self {
             Self::CertificateStatus(st) => CertificateExtension::CertificateStatus(st.into_owned()),
+            Self::SignedCertificateTimestamp(scts) => {
+                CertificateExtension::SignedCertificateTimestamp(scts)
+            }
+            Self::DelegatedCredential(dc) => CertificateExtension::DelegatedCredential(dc),
             Self::Unknown(unk) => CertificateExtension::Unknown(unk),
         }
     }
@@ -1549,6 +1719,8 @@ impl<'a> Codec<'a> for CertificateExtension<'a> {
         let nested = LengthPrefixedBuffer::new(ListLength::U16, bytes);
         match *self {
             Self::CertificateStatus(ref r) => r.encode(nested.buf),
+            Self::SignedCertificateTimestamp(ref r) => r.encode(nested.buf),
+            Self::DelegatedCredential(ref r) => r.encode(nested.buf),
             Self::Unknown(ref r) => r.encode(nested.buf),
         }
     }
@@ -1563,6 +1735,10 @@ impl<'a> Codec<'a> for CertificateExtension<'a> {
                 let st = CertificateStatus::read(&mut sub)?;
                 Self::CertificateStatus(st)
             }
+            ExtensionType::SCT => Self::SignedCertificateTimestamp(Vec::read(&mut sub)?),
+            ExtensionType::DelegatedCredential => {
+                Self::DelegatedCredential(DelegatedCredential::read(&mut sub)?)
+            }
             _ => Self::Unknown(UnknownExtension::read(typ, &mut sub)),
         };
 
@@ -1622,17 +1798,25 @@ impl<'a> CertificateEntry<'a> {
         )
     }
 
-    pub(crate) fn has_unknown_extension(&self) -> bool {
+    pub(crate) fn ocsp_response(&self) -> Option<&[u8]> {
         self.exts
             .iter()
-            .any(|ext| ext.ext_type() != ExtensionType::StatusRequest)
+            .find(|ext| ext.ext_type() == ExtensionType::StatusRequest)
+            .and_then(CertificateExtension::cert_status)
     }
 
-    pub(crate) fn ocsp_response(&self) -> Option<&[u8]> {
+    pub(crate) fn sct_list(&self) -> Option<&Vec<PayloadU16>> {
         self.exts
             .iter()
-            .find(|ext| ext.ext_type() == ExtensionType::StatusRequest)
-            .and_then(CertificateExtension::cert_status)
+            .find(|ext| ext.ext_type() == ExtensionType::SCT)
+            .and_then(CertificateExtension::sct_list)
+    }
+
+    pub(crate) fn delegated_credential(&self) -> Option<&DelegatedCredential> {
+        self.exts
+            .iter()
+            .find(|ext| ext.ext_type() == ExtensionType::DelegatedCredential)
+            .and_then(CertificateExtension::delegated_credential)
     }
 }
 
@@ -1714,14 +1898,22 @@ impl<'a> CertificatePayloadTls13<'a> {
         false
     }
 
-    pub(crate) fn any_entry_has_unknown_extension(&self) -> bool {
-        for entry in &self.entries {
-            if entry.has_unknown_extension() {
-                return true;
-            }
-        }
-
-        false
+    /// `custom` lists the custom extension types the end-entity certificate may carry.
+    pub(crate) fn any_entry_has_unknown_extension(&self, custom: &[ExtensionType]) -> bool {
+        self.entries
+            .iter()
+            .enumerate()
+            .any(|(i, entry)| {
+                entry
+                    .exts
+                    .iter()
+                    .any(|ext| match ext.ext_type() {
+                        ExtensionType::StatusRequest | ExtensionType::SCT => false,
+                        // only the end-entity certificate may carry a delegated credential
+                        ExtensionType::DelegatedCredential => i != 0,
+                        typ => i != 0 || !custom.contains(&typ),
+                    })
+            })
     }
 
     pub(crate) fn any_entry_has_extension(&self) -> bool {
@@ -1742,6 +1934,68 @@ impl<'a> CertificatePayloadTls13<'a> {
             .unwrap_or_default()
     }
 
+    /// The end-entity's `SignedCertificateTimestampList`, encoded, or empty if there isn't one.
+    pub(crate) fn end_entity_sct_list(&self) -> Vec<u8> {
+        self.entries
+            .first()
+            .and_then(CertificateEntry::sct_list)
+            .map(|scts| scts.get_encoding())
+            .unwrap_or_default()
+    }
+
+    /// Attach `sct_list` to the end-entity certificate's entry.
+    pub(crate) fn with_end_entity_sct_list(mut self, sct_list: Option<Vec<PayloadU16>>) -> Self {
+        if let (Some(entry), Some(scts)) = (self.entries.first_mut(), sct_list) {
+            entry
+                .exts
+                .push(CertificateExtension::SignedCertificateTimestamp(scts));
+        }
+        self
+    }
+
+    /// The delegated credential attached to the end-entity certificate, if any.
+    pub(crate) fn end_entity_delegated_credential(&self) -> Option<&DelegatedCredential> {
+        self.entries
+            .first()
+            .and_then(CertificateEntry::delegated_credential)
+    }
+
+    /// Attach `credential` to the end-entity certificate's entry.
+    pub(crate) fn with_end_entity_delegated_credential(
+        mut self,
+        credential: Option<DelegatedCredential>,
+    ) -> Self {
+        if let (Some(entry), Some(dc)) = (self.entries.first_mut(), credential) {
+            entry
+                .exts
+                .push(CertificateExtension::DelegatedCredential(dc));
+        }
+        self
+    }
+
+    pub(crate) fn end_entity_unknown_extensions(&self) -> impl Iterator<Item = &UnknownExtension> {
+        self.entries
+            .first()
+            .into_iter()
+            .flat_map(|entry| entry.exts.iter())
+            .filter_map(|ext| match ext {
+                CertificateExtension::Unknown(unknown) => Some(unknown),
+                _ => None,
+            })
+    }
+
+    /// Attach `exts` to the end-entity certificate's entry.
+    pub(crate) fn with_end_entity_unknown_extensions(mut self, exts: &[UnknownExtension]) -> Self {
+        if let Some(entry) = self.entries.first_mut() {
+            entry.exts.extend(
+                exts.iter()
+                    .cloned()
+                    .map(CertificateExtension::Unknown),
+            );
+        }
+        self
+    }
+
     pub(crate) fn into_certificate_chain(self) -> CertificateChain<'a> {
         CertificateChain(
             self.entries
@@ -2083,6 +2337,15 @@ pub(crate) trait HasServerExtensions {
             .find(|x| x.ext_type() == ext)
     }
 
+    fn unknown_extensions(&self) -> impl Iterator<Item = &UnknownExtension> {
+        self.extensions()
+            .iter()
+            .filter_map(|ext| match ext {
+                ServerExtension::Unknown(unknown) => Some(unknown),
+                _ => None,
+            })
+    }
+
     fn alpn_protocol(&self) -> Option<&[u8]> {
         let ext = self.find_extension(ExtensionType::ALProtocolNegotiation)?;
         match *ext {
@@ -2130,6 +2393,14 @@ pub(crate) trait HasServerExtensions {
         self.find_extension(ExtensionType::EarlyData)
             .is_some()
     }
+
+    fn record_size_limit(&self) -> Option<u16> {
+        let ext = self.find_extension(ExtensionType::RecordSizeLimit)?;
+        match *ext {
+            ServerExtension::RecordSizeLimit(limit) => Some(limit),
+            _ => None,
+        }
+    }
 }
 
 impl HasServerExtensions for Vec<ServerExtension> {
@@ -22,6 +22,7 @@ use super::handshake::{
     ServerDhParams, ServerEcdhParams, ServerExtension, ServerHelloPayload, ServerKeyExchange,
     ServerKeyExchangeParams, ServerKeyExchangePayload, SessionId, UnknownExtension,
 };
+use crate::delegated_credential::DelegatedCredential;
 use crate::enums::{
     CertificateCompressionAlgorithm, CipherSuite, HandshakeType, ProtocolVersion, SignatureScheme,
 };
@@ -957,6 +958,7 @@ fn sample_client_hello_payload() -> ClientHelloPayload {
         client_version: ProtocolVersion::TLSv1_2,
         random: Random::from([0; 32]),
         session_id: SessionId::empty(),
+        legacy_cookie: None,
         cipher_suites: vec![CipherSuite::TLS_NULL_WITH_NULL_NULL],
         compression_methods: vec![Compression::Null],
         extensions: vec![
@@ -987,6 +989,10 @@ fn sample_client_hello_payload() -> ClientHelloPayload {
             ClientExtension::ClientCertTypes(vec![CertificateType::RawPublicKey]),
             ClientExtension::TransportParameters(vec![1, 2, 3]),
             ClientExtension::EarlyData,
+            ClientExtension::PostHandshakeAuth,
+            ClientExtension::RecordSizeLimit(4096),
+            ClientExtension::DelegatedCredential(vec![SignatureScheme::ED25519]),
+            ClientExtension::SignedCertificateTimestampRequest,
             ClientExtension::CertificateCompressionAlgorithms(vec![
                 CertificateCompressionAlgorithm::Brotli,
                 CertificateCompressionAlgorithm::Zlib,
@@ -1018,12 +1024,14 @@ fn sample_server_hello_payload() -> ServerHelloPayload {
             ServerExtension::CertificateStatusAck,
             ServerExtension::SupportedVersions(ProtocolVersion::TLSv1_2),
             ServerExtension::TransportParameters(vec![1, 2, 3]),
+            ServerExtension::SignedCertificateTimestamp(vec![PayloadU16(vec![1, 2, 3])]),
             ServerExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload::Borrowed(&[1, 2, 3]),
             }),
             ServerExtension::ClientCertType(CertificateType::RawPublicKey),
             ServerExtension::ServerCertType(CertificateType::RawPublicKey),
+            ServerExtension::RecordSizeLimit(16385),
         ],
     }
 }
@@ -1212,6 +1220,14 @@ fn sample_certificate_payload_tls13() -> CertificatePayloadTls13<'static> {
                 CertificateExtension::CertificateStatus(CertificateStatus {
                     ocsp_response: PayloadU24(Payload::new(vec![1, 2, 3])),
                 }),
+                CertificateExtension::SignedCertificateTimestamp(vec![PayloadU16(vec![1, 2, 3])]),
+                CertificateExtension::DelegatedCredential(DelegatedCredential {
+                    valid_time: 3600,
+                    dc_cert_verify_algorithm: SignatureScheme::ED25519,
+                    subject_public_key_info: vec![1, 2, 3].into(),
+                    algorithm: SignatureScheme::ECDSA_NISTP256_SHA256,
+                    signature: vec![4, 5, 6],
+                }),
                 CertificateExtension::Unknown(UnknownExtension {
                     typ: ExtensionType::Unknown(12345),
                     payload: Payload::Borrowed(&[1, 2, 3]),
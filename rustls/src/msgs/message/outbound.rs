@@ -18,8 +18,9 @@ pub struct OutboundPlainMessage<'a> {
 }
 
 impl OutboundPlainMessage<'_> {
-    pub(crate) fn encoded_len(&self, record_layer: &RecordLayer) -> usize {
-        HEADER_SIZE + record_layer.encrypted_len(self.payload.len())
+    /// Return the encoded length of this message once encrypted with `padding` bytes of padding.
+    pub(crate) fn encoded_len(&self, record_layer: &RecordLayer, padding: usize) -> usize {
+        HEADER_SIZE + record_layer.encrypted_len(self.payload.len() + padding)
     }
 
     pub(crate) fn to_unencrypted_opaque(&self) -> OutboundOpaqueMessage {
@@ -229,6 +230,10 @@ impl PrefixedPayload {
         chunks.copy_to_vec(&mut self.0)
     }
 
+    pub fn extend_with_zeros(&mut self, len: usize) {
+        self.0.resize(self.0.len() + len, 0);
+    }
+
     pub fn truncate(&mut self, len: usize) {
         self.0.truncate(len + HEADER_SIZE)
     }
@@ -1,17 +1,20 @@
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::cmp;
+use core::time::Duration;
 
 use pki_types::{DnsName, UnixTime};
 use zeroize::Zeroizing;
 
+use crate::crypto::CryptoProvider;
 use crate::enums::{CipherSuite, ProtocolVersion};
-use crate::error::InvalidMessage;
+use crate::error::{Error, InvalidMessage};
 use crate::msgs::base::{PayloadU16, PayloadU8};
 use crate::msgs::codec::{Codec, Reader};
 use crate::msgs::handshake::CertificateChain;
 #[cfg(feature = "tls12")]
 use crate::msgs::handshake::SessionId;
+use crate::suites::SupportedCipherSuite;
 #[cfg(feature = "tls12")]
 use crate::tls12::Tls12CipherSuite;
 use crate::tls13::Tls13CipherSuite;
@@ -74,6 +77,7 @@ pub struct Tls13ClientSessionValue {
     max_early_data_size: u32,
     pub(crate) common: ClientSessionCommon,
     quic_params: PayloadU16,
+    alpn: Option<PayloadU8>,
 }
 
 impl Tls13ClientSessionValue {
@@ -99,9 +103,60 @@ impl Tls13ClientSessionValue {
                 server_cert_chain,
             ),
             quic_params: PayloadU16(Vec::new()),
+            alpn: None,
         }
     }
 
+    /// Encode this session for storage, for example by a `ClientSessionStore`
+    /// that persists sessions between processes.
+    ///
+    /// The encoding starts with a format version, so that it can still be
+    /// decoded by later versions of rustls.  It includes the session's
+    /// resumption secret, so must be stored as securely as a private key.
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut bytes = Zeroizing::new(Vec::new());
+        CLIENT_SESSION_FORMAT_V1.encode(&mut bytes);
+        ProtocolVersion::TLSv1_3.encode(&mut bytes);
+        self.suite
+            .common
+            .suite
+            .encode(&mut bytes);
+        self.age_add.encode(&mut bytes);
+        self.max_early_data_size
+            .encode(&mut bytes);
+        self.common.encode(&mut bytes);
+        self.quic_params.encode(&mut bytes);
+        encode_optional(self.alpn.as_ref(), &mut bytes);
+        bytes
+    }
+
+    /// Decode a session encoded by [`Self::to_bytes()`].
+    ///
+    /// The session's cipher suite must be one of `provider`'s.
+    pub fn from_bytes(bytes: &[u8], provider: &CryptoProvider) -> Result<Self, Error> {
+        let mut r = Reader::init(bytes);
+        let suite = read_header(&mut r, ProtocolVersion::TLSv1_3)?;
+        let suite = provider
+            .cipher_suites
+            .iter()
+            .find_map(|scs| match scs {
+                SupportedCipherSuite::Tls13(tls13) if tls13.common.suite == suite => Some(*tls13),
+                _ => None,
+            })
+            .ok_or_else(unsupported_suite)?;
+
+        let value = Self {
+            suite,
+            age_add: u32::read(&mut r)?,
+            max_early_data_size: u32::read(&mut r)?,
+            common: ClientSessionCommon::read(&mut r)?,
+            quic_params: PayloadU16::read(&mut r)?,
+            alpn: read_optional(&mut r)?,
+        };
+        r.expect_empty("Tls13ClientSessionValue")?;
+        Ok(value)
+    }
+
     pub fn max_early_data_size(&self) -> u32 {
         self.max_early_data_size
     }
@@ -129,6 +184,17 @@ impl Tls13ClientSessionValue {
     pub fn quic_params(&self) -> Vec<u8> {
         self.quic_params.0.clone()
     }
+
+    pub(crate) fn set_alpn_protocol(&mut self, alpn: Option<&[u8]>) {
+        self.alpn = alpn.map(|alpn| PayloadU8::new(alpn.to_vec()));
+    }
+
+    /// The ALPN protocol negotiated by the connection that received this ticket.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn
+            .as_ref()
+            .map(|alpn| alpn.0.as_slice())
+    }
 }
 
 impl core::ops::Deref for Tls13ClientSessionValue {
@@ -178,6 +244,51 @@ impl Tls12ClientSessionValue {
         }
     }
 
+    /// Encode this session for storage, for example by a `ClientSessionStore`
+    /// that persists sessions between processes.
+    ///
+    /// The encoding starts with a format version, so that it can still be
+    /// decoded by later versions of rustls.  It includes the session's
+    /// master secret, so must be stored as securely as a private key.
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut bytes = Zeroizing::new(Vec::new());
+        CLIENT_SESSION_FORMAT_V1.encode(&mut bytes);
+        ProtocolVersion::TLSv1_2.encode(&mut bytes);
+        self.suite
+            .common
+            .suite
+            .encode(&mut bytes);
+        self.session_id.encode(&mut bytes);
+        u8::from(self.extended_ms).encode(&mut bytes);
+        self.common.encode(&mut bytes);
+        bytes
+    }
+
+    /// Decode a session encoded by [`Self::to_bytes()`].
+    ///
+    /// The session's cipher suite must be one of `provider`'s.
+    pub fn from_bytes(bytes: &[u8], provider: &CryptoProvider) -> Result<Self, Error> {
+        let mut r = Reader::init(bytes);
+        let suite = read_header(&mut r, ProtocolVersion::TLSv1_2)?;
+        let suite = provider
+            .cipher_suites
+            .iter()
+            .find_map(|scs| match scs {
+                SupportedCipherSuite::Tls12(tls12) if tls12.common.suite == suite => Some(*tls12),
+                _ => None,
+            })
+            .ok_or_else(unsupported_suite)?;
+
+        let value = Self {
+            suite,
+            session_id: SessionId::read(&mut r)?,
+            extended_ms: u8::read(&mut r)? == 1,
+            common: ClientSessionCommon::read(&mut r)?,
+        };
+        r.expect_empty("Tls12ClientSessionValue")?;
+        Ok(value)
+    }
+
     pub(crate) fn ticket(&mut self) -> Arc<PayloadU16> {
         Arc::clone(&self.common.ticket)
     }
@@ -243,6 +354,63 @@ impl ClientSessionCommon {
     pub(crate) fn ticket(&self) -> &[u8] {
         self.ticket.0.as_ref()
     }
+
+    /// Whether this session's lifetime has passed at `now`.
+    pub fn has_expired_at(&self, now: UnixTime) -> bool {
+        Retrieved::new(self, now).has_expired()
+    }
+}
+
+impl Codec<'_> for ClientSessionCommon {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.ticket.encode(bytes);
+        self.secret.encode(bytes);
+        self.epoch.encode(bytes);
+        self.lifetime_secs.encode(bytes);
+        self.server_cert_chain.encode(bytes);
+    }
+
+    fn read(r: &mut Reader<'_>) -> Result<Self, InvalidMessage> {
+        Ok(Self {
+            ticket: Arc::new(PayloadU16::read(r)?),
+            secret: Zeroizing::new(PayloadU8::read(r)?),
+            epoch: u64::read(r)?,
+            lifetime_secs: cmp::min(u32::read(r)?, MAX_TICKET_LIFETIME),
+            server_cert_chain: Arc::new(CertificateChain::read(r)?.into_owned()),
+        })
+    }
+}
+
+/// The current version of the `to_bytes()` encoding of client sessions.
+const CLIENT_SESSION_FORMAT_V1: u8 = 1;
+
+/// Read the common start of an encoded client session, returning its cipher suite.
+fn read_header(r: &mut Reader<'_>, expect_version: ProtocolVersion) -> Result<CipherSuite, Error> {
+    if u8::read(r)? != CLIENT_SESSION_FORMAT_V1 || ProtocolVersion::read(r)? != expect_version {
+        return Err(Error::General("unsupported client session encoding".into()));
+    }
+    Ok(CipherSuite::read(r)?)
+}
+
+fn unsupported_suite() -> Error {
+    Error::General("client session uses an unsupported cipher suite".into())
+}
+
+fn encode_optional(value: Option<&PayloadU8>, bytes: &mut Vec<u8>) {
+    match value {
+        Some(value) => {
+            1u8.encode(bytes);
+            value.encode(bytes);
+        }
+        None => 0u8.encode(bytes),
+    }
+}
+
+fn read_optional(r: &mut Reader<'_>) -> Result<Option<PayloadU8>, InvalidMessage> {
+    match u8::read(r)? {
+        0 => Ok(None),
+        _ => Ok(Some(PayloadU8::read(r)?)),
+    }
 }
 
 static MAX_TICKET_LIFETIME: u32 = 7 * 24 * 60 * 60;
@@ -266,7 +434,7 @@ pub struct ServerSessionValue {
     pub(crate) application_data: PayloadU16,
     pub creation_time_sec: u64,
     pub(crate) age_obfuscation_offset: u32,
-    freshness: Option<bool>,
+    age_difference_ms: Option<u32>,
 }
 
 impl Codec<'_> for ServerSessionValue {
@@ -345,7 +513,7 @@ impl Codec<'_> for ServerSessionValue {
             application_data,
             creation_time_sec,
             age_obfuscation_offset,
-            freshness: None,
+            age_difference_ms: None,
         })
     }
 }
@@ -373,7 +541,7 @@ impl ServerSessionValue {
             application_data: PayloadU16::new(application_data),
             creation_time_sec: creation_time.as_secs(),
             age_obfuscation_offset,
-            freshness: None,
+            age_difference_ms: None,
         }
     }
 
@@ -399,12 +567,20 @@ impl ServerSessionValue {
             client_age_ms - server_age_ms
         };
 
-        self.freshness = Some(age_difference <= MAX_FRESHNESS_SKEW_MS);
+        self.age_difference_ms = Some(age_difference);
         self
     }
 
     pub(crate) fn is_fresh(&self) -> bool {
-        self.freshness.unwrap_or_default()
+        self.age_difference_ms
+            .is_some_and(|diff| diff <= MAX_FRESHNESS_SKEW_MS)
+    }
+
+    /// Like `is_fresh()`, but requires the client's view of the ticket age to be
+    /// within `window` of ours, instead of `MAX_FRESHNESS_SKEW_MS`.
+    pub(crate) fn is_fresh_within(&self, window: Duration) -> bool {
+        self.age_difference_ms
+            .is_some_and(|diff| u128::from(diff) <= window.as_millis())
     }
 }
 
@@ -430,6 +606,26 @@ mod tests {
         println!("{:?}", ssv);
     }
 
+    #[test]
+    fn serversessionvalue_freshness_window() {
+        let ssv = ServerSessionValue::new(
+            None,
+            ProtocolVersion::TLSv1_3,
+            CipherSuite::TLS13_AES_128_GCM_SHA256,
+            &[1, 2, 3],
+            None,
+            None,
+            Vec::new(),
+            UnixTime::since_unix_epoch(Duration::from_secs(1000)),
+            0,
+        )
+        .set_freshness(0, UnixTime::since_unix_epoch(Duration::from_secs(1090)));
+
+        assert!(!ssv.is_fresh());
+        assert!(ssv.is_fresh_within(Duration::from_secs(120)));
+        assert!(!ssv.is_fresh_within(Duration::from_secs(80)));
+    }
+
     #[test]
     fn serversessionvalue_no_sni() {
         let bytes = [
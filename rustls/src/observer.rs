@@ -0,0 +1,142 @@
+use alloc::boxed::Box;
+use core::fmt::Debug;
+
+use crate::client::EchStatus;
+use crate::common_state::{HandshakeKind, Side};
+use crate::enums::{AlertDescription, HandshakeType};
+
+/// This trait represents the ability to follow the handshakes of
+/// connections, for example to collect per-connection metrics or
+/// structured traces.
+///
+/// Set it as `ClientConfig::observer` or `ServerConfig::observer`.
+/// Unlike logging, this can be enabled for a subset of connections,
+/// and delivers events in a form suitable for processing.
+///
+/// With the `tracing` crate feature, `TracingObserver` is an implementation
+/// which emits events using the `tracing` crate.
+pub trait HandshakeObserver: Debug + Send + Sync {
+    /// Start observing a new connection on the given `side`.
+    ///
+    /// The returned value receives all the events for this connection.
+    fn new_connection(&self, side: Side) -> Box<dyn ConnectionObserver>;
+}
+
+/// Receives the [`HandshakeEvent`]s for a single connection.
+///
+/// Events are delivered synchronously, as the connection processes them,
+/// so implementations can timestamp them to measure the duration of each
+/// step of the handshake.
+pub trait ConnectionObserver: Send + Sync {
+    /// Called for each `event` on this connection, in order.
+    fn observe(&mut self, event: &HandshakeEvent);
+}
+
+/// Something that happened during the lifetime of a connection.
+///
+/// A `HelloRetryRequest` appears as a message of type
+/// [`HandshakeType::HelloRetryRequest`], and key updates as messages of type
+/// [`HandshakeType::KeyUpdate`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HandshakeEvent {
+    /// We sent a handshake message.
+    ///
+    /// This includes messages sent after the handshake, such as TLS1.3
+    /// `NewSessionTicket` and `KeyUpdate`.
+    MessageSent {
+        /// The type of the message.
+        typ: HandshakeType,
+        /// The length of the message encoding, including its four-byte header.
+        len: usize,
+    },
+
+    /// We received a handshake message.
+    ///
+    /// This is reported before the message is processed, so it includes
+    /// messages which are later rejected.
+    MessageReceived {
+        /// The type of the message.
+        typ: HandshakeType,
+        /// The length of the message encoding, including its four-byte header.
+        len: usize,
+    },
+
+    /// The client found a stored session for the server, and offered to resume it.
+    ///
+    /// Whether the server accepted is reported by [`HandshakeEvent::HandshakeComplete`].
+    ResumptionOffered,
+
+    /// The client's Encrypted Client Hello status changed.
+    Ech(EchStatus),
+
+    /// We sent an alert.
+    AlertSent {
+        /// What the alert says.
+        description: AlertDescription,
+        /// Whether the alert was fatal (rather than a warning).
+        fatal: bool,
+    },
+
+    /// We received an alert.
+    AlertReceived {
+        /// What the alert says.
+        description: AlertDescription,
+        /// Whether the alert was fatal (rather than a warning).
+        fatal: bool,
+    },
+
+    /// The handshake completed, and application data can flow in both directions.
+    HandshakeComplete {
+        /// Which sort of handshake happened.
+        kind: HandshakeKind,
+    },
+}
+
+/// A [`HandshakeObserver`] which emits events using the `tracing` crate.
+///
+/// Each connection gets a `tls_connection` span at `DEBUG` level, and each
+/// [`HandshakeEvent`] becomes a `DEBUG` event within that span.
+#[cfg(feature = "tracing")]
+#[derive(Debug, Default)]
+pub struct TracingObserver;
+
+#[cfg(feature = "tracing")]
+impl HandshakeObserver for TracingObserver {
+    fn new_connection(&self, side: Side) -> Box<dyn ConnectionObserver> {
+        Box::new(TracingConnectionObserver {
+            span: tracing::debug_span!("tls_connection", ?side),
+        })
+    }
+}
+
+#[cfg(feature = "tracing")]
+struct TracingConnectionObserver {
+    span: tracing::Span,
+}
+
+#[cfg(feature = "tracing")]
+impl ConnectionObserver for TracingConnectionObserver {
+    fn observe(&mut self, event: &HandshakeEvent) {
+        let _entered = self.span.enter();
+        match *event {
+            HandshakeEvent::MessageSent { typ, len } => {
+                tracing::debug!(?typ, len, "handshake message sent")
+            }
+            HandshakeEvent::MessageReceived { typ, len } => {
+                tracing::debug!(?typ, len, "handshake message received")
+            }
+            HandshakeEvent::ResumptionOffered => tracing::debug!("resumption offered"),
+            HandshakeEvent::Ech(status) => tracing::debug!(?status, "ECH status changed"),
+            HandshakeEvent::AlertSent { description, fatal } => {
+                tracing::debug!(?description, fatal, "alert sent")
+            }
+            HandshakeEvent::AlertReceived { description, fatal } => {
+                tracing::debug!(?description, fatal, "alert received")
+            }
+            HandshakeEvent::HandshakeComplete { kind } => {
+                tracing::debug!(?kind, "handshake complete")
+            }
+        }
+    }
+}
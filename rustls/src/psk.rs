@@ -0,0 +1,329 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use zeroize::Zeroizing;
+
+use crate::crypto::hash::HashAlgorithm;
+use crate::crypto::CryptoProvider;
+use crate::error::Error;
+use crate::msgs::base::PayloadU16;
+use crate::msgs::codec::Codec;
+use crate::tls13::key_schedule::hkdf_expand_label_slice;
+use crate::tls13::Tls13CipherSuite;
+
+/// An external pre-shared key (PSK) for TLS1.3.
+///
+/// This is a symmetric secret shared between a client and server by some
+/// out-of-band mechanism, identified by an opaque `identity`.  Peers which
+/// agree on an external PSK authenticate each other by their knowledge of it:
+/// no certificates are sent.  See [RFC 8446 section 2.2].
+///
+/// Each external PSK is associated with a hash function, and can only be used with
+/// TLS1.3 cipher suites using that hash function.  The default is SHA-256.
+///
+/// External PSKs are not used for TLS1.2, nor for early data.  Clients do not
+/// offer them alongside Encrypted Client Hello.
+///
+/// Clients configure these with [`ClientConfig::psk_resolver`], and servers
+/// with [`ServerConfig::psk_resolver`].
+///
+/// [RFC 8446 section 2.2]: https://www.rfc-editor.org/rfc/rfc8446#section-2.2
+/// [`ClientConfig::psk_resolver`]: crate::ClientConfig::psk_resolver
+/// [`ServerConfig::psk_resolver`]: crate::ServerConfig::psk_resolver
+#[derive(Clone)]
+pub struct ExternalPsk {
+    identity: Vec<u8>,
+    secret: Zeroizing<Vec<u8>>,
+    hash: HashAlgorithm,
+    key_exchange_modes: PskKeyExchangeModes,
+    imported: bool,
+}
+
+impl ExternalPsk {
+    /// Make a new external PSK, with the given `identity` and `secret`.
+    ///
+    /// `identity` is sent in the clear.  This returns an error if it is
+    /// empty or longer than 65535 bytes, so cannot be sent.
+    pub fn new(identity: Vec<u8>, secret: Vec<u8>) -> Result<Self, Error> {
+        if identity.is_empty() || identity.len() > usize::from(u16::MAX) {
+            return Err(Error::General(
+                "external PSK identity must be between 1 and 65535 bytes".into(),
+            ));
+        }
+
+        Ok(Self {
+            identity,
+            secret: Zeroizing::new(secret),
+            hash: HashAlgorithm::SHA256,
+            key_exchange_modes: PskKeyExchangeModes::default(),
+            imported: false,
+        })
+    }
+
+    /// Associate this PSK with the hash function `hash`, rather than SHA-256.
+    pub fn with_hash_algorithm(mut self, hash: HashAlgorithm) -> Self {
+        self.hash = hash;
+        self
+    }
+
+    /// Set which key exchange modes this PSK may be used with.
+    pub fn with_key_exchange_modes(mut self, modes: PskKeyExchangeModes) -> Self {
+        self.key_exchange_modes = modes;
+        self
+    }
+
+    /// Derive an imported PSK from this one, according to [RFC 9258].
+    ///
+    /// The result can be used with TLS1.3 cipher suites using the hash
+    /// function `target`.  Its identity is the encoding of the `ImportedIdentity`
+    /// structure, containing this PSK's identity and `context`.
+    ///
+    /// Both `target` and this PSK's hash function must be used by a TLS1.3
+    /// cipher suite in `provider`.  The key exchange modes of this PSK are
+    /// retained.
+    ///
+    /// [RFC 9258]: https://www.rfc-editor.org/rfc/rfc9258
+    pub fn import(
+        &self,
+        context: &[u8],
+        target: HashAlgorithm,
+        provider: &CryptoProvider,
+    ) -> Result<Self, Error> {
+        let target_kdf: u16 = match target {
+            HashAlgorithm::SHA256 => 0x0001,
+            HashAlgorithm::SHA384 => 0x0002,
+            _ => {
+                return Err(Error::General(
+                    "unsupported target KDF for imported PSK".into(),
+                ))
+            }
+        };
+
+        let (Some(epsk_suite), Some(target_suite)) = (
+            find_suite(provider, self.hash),
+            find_suite(provider, target),
+        ) else {
+            return Err(Error::General(
+                "no TLS1.3 cipher suite for imported PSK hash".into(),
+            ));
+        };
+
+        // struct {
+        //    opaque external_identity<1...2^16-1>;
+        //    opaque context<0..2^16-1>;
+        //    uint16 target_protocol;
+        //    uint16 target_kdf;
+        // } ImportedIdentity;
+        let mut identity = Vec::new();
+        PayloadU16::encode_slice(&self.identity, &mut identity);
+        PayloadU16::encode_slice(context, &mut identity);
+        0x0304u16.encode(&mut identity);
+        target_kdf.encode(&mut identity);
+
+        // epskx = HKDF-Extract(0, epsk)
+        // ipskx = HKDF-Expand-Label(epskx, "derived psk", Hash(ImportedIdentity), L)
+        let epskx = epsk_suite
+            .hkdf_provider
+            .extract_from_secret(None, &self.secret);
+        let identity_hash = epsk_suite
+            .common
+            .hash_provider
+            .hash(&identity);
+        let mut secret = Zeroizing::new(vec![
+            0u8;
+            target_suite
+                .common
+                .hash_provider
+                .output_len()
+        ]);
+        hkdf_expand_label_slice(
+            epskx.as_ref(),
+            b"derived psk",
+            identity_hash.as_ref(),
+            &mut secret,
+        )
+        .map_err(|_| Error::General("imported PSK too long".into()))?;
+
+        Ok(Self {
+            identity,
+            secret,
+            hash: target,
+            key_exchange_modes: self.key_exchange_modes,
+            imported: true,
+        })
+    }
+
+    /// The identity of this PSK.
+    pub fn identity(&self) -> &[u8] {
+        &self.identity
+    }
+
+    /// The hash function associated with this PSK.
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash
+    }
+
+    /// The key exchange modes this PSK may be used with.
+    pub fn key_exchange_modes(&self) -> PskKeyExchangeModes {
+        self.key_exchange_modes
+    }
+
+    /// Whether this PSK was made by [`ExternalPsk::import()`].
+    pub fn is_imported(&self) -> bool {
+        self.imported
+    }
+
+    pub(crate) fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    /// Whether this PSK can be used with `suite`.
+    pub(crate) fn usable_with(&self, suite: &Tls13CipherSuite) -> bool {
+        suite.common.hash_provider.algorithm() == self.hash
+    }
+}
+
+impl fmt::Debug for ExternalPsk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExternalPsk")
+            .field("identity", &self.identity)
+            .field("hash", &self.hash)
+            .field("key_exchange_modes", &self.key_exchange_modes)
+            .field("imported", &self.imported)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The TLS1.3 key exchange modes an [`ExternalPsk`] may be used with.
+///
+/// See [RFC 8446 section 4.2.9].
+///
+/// [RFC 8446 section 4.2.9]: https://www.rfc-editor.org/rfc/rfc8446#section-4.2.9
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PskKeyExchangeModes {
+    /// Only PSK with (EC)DHE key establishment (`psk_dhe_ke`).
+    ///
+    /// This provides forward secrecy, and is the default.
+    #[default]
+    Dhe,
+
+    /// Either PSK with (EC)DHE key establishment (`psk_dhe_ke`), or PSK-only
+    /// key establishment (`psk_ke`).
+    ///
+    /// Servers choose `psk_dhe_ke` if the client offers it.
+    DheOrPskOnly,
+
+    /// Either PSK-only key establishment (`psk_ke`), or PSK with (EC)DHE
+    /// key establishment (`psk_dhe_ke`).
+    ///
+    /// Servers choose `psk_ke` if the client offers it.  This avoids the cost
+    /// of a key exchange, at the expense of forward secrecy.
+    PskOnlyOrDhe,
+
+    /// Only PSK-only key establishment (`psk_ke`).
+    ///
+    /// Such connections do not have forward secrecy: compromise of the PSK
+    /// compromises all traffic protected by it.
+    PskOnly,
+}
+
+impl PskKeyExchangeModes {
+    pub(crate) fn allows_dhe(self) -> bool {
+        matches!(self, Self::Dhe | Self::DheOrPskOnly | Self::PskOnlyOrDhe)
+    }
+
+    pub(crate) fn allows_psk_only(self) -> bool {
+        matches!(
+            self,
+            Self::DheOrPskOnly | Self::PskOnlyOrDhe | Self::PskOnly
+        )
+    }
+
+    /// Whether a server should choose `psk_ke` over `psk_dhe_ke`, if both are offered.
+    pub(crate) fn prefers_psk_only(self) -> bool {
+        matches!(self, Self::PskOnlyOrDhe | Self::PskOnly)
+    }
+}
+
+fn find_suite(provider: &CryptoProvider, hash: HashAlgorithm) -> Option<&'static Tls13CipherSuite> {
+    provider
+        .cipher_suites
+        .iter()
+        .filter_map(|suite| suite.tls13())
+        .find(|suite| suite.common.hash_provider.algorithm() == hash)
+}
+
+#[cfg(all(test, feature = "ring"))]
+mod tests {
+    use super::*;
+    use crate::crypto::tls13::OkmBlock;
+
+    #[test]
+    fn imported_identity_encoding() {
+        let provider = crate::crypto::ring::default_provider();
+        let epsk = ExternalPsk::new(b"device-1".to_vec(), vec![0x42; 32]).unwrap();
+        let imported = epsk
+            .import(b"ctx", HashAlgorithm::SHA384, &provider)
+            .unwrap();
+
+        assert_eq!(
+            imported.identity(),
+            &[
+                0x00, 0x08, b'd', b'e', b'v', b'i', b'c', b'e', b'-', b'1', 0x00, 0x03, b'c', b't',
+                b'x', 0x03, 0x04, 0x00, 0x02,
+            ]
+        );
+        assert_eq!(imported.hash_algorithm(), HashAlgorithm::SHA384);
+        assert_eq!(imported.secret().len(), 48);
+        assert!(imported.is_imported());
+    }
+
+    #[test]
+    fn imported_secret_depends_on_context_and_target() {
+        let provider = crate::crypto::ring::default_provider();
+        let epsk = ExternalPsk::new(b"device-1".to_vec(), vec![0x42; 32]).unwrap();
+
+        let a = epsk
+            .import(b"a", HashAlgorithm::SHA256, &provider)
+            .unwrap();
+        let b = epsk
+            .import(b"b", HashAlgorithm::SHA256, &provider)
+            .unwrap();
+        assert_ne!(a.secret(), b.secret());
+        assert_ne!(a.secret(), epsk.secret());
+
+        // this is HKDF-Expand-Label(HKDF-Extract(0, epsk), "derived psk", Hash(identity), 32)
+        let suite = find_suite(&provider, HashAlgorithm::SHA256).unwrap();
+        let epskx = suite
+            .hkdf_provider
+            .extract_from_secret(None, epsk.secret());
+        let hash = suite
+            .common
+            .hash_provider
+            .hash(a.identity());
+        let expected: OkmBlock = crate::tls13::key_schedule::hkdf_expand_label_block(
+            epskx.as_ref(),
+            b"derived psk",
+            hash.as_ref(),
+        );
+        assert_eq!(a.secret(), expected.as_ref());
+    }
+
+    #[test]
+    fn new_rejects_unsendable_identity() {
+        assert!(ExternalPsk::new(Vec::new(), vec![0x42; 32]).is_err());
+        assert!(ExternalPsk::new(vec![0; 0x10000], vec![0x42; 32]).is_err());
+        assert!(ExternalPsk::new(vec![0; 0xffff], vec![0x42; 32]).is_ok());
+    }
+
+    #[test]
+    fn import_rejects_unsupported_target() {
+        let provider = crate::crypto::ring::default_provider();
+        let epsk = ExternalPsk::new(b"device-1".to_vec(), vec![0x42; 32]).unwrap();
+        assert!(epsk
+            .import(b"", HashAlgorithm::SHA512, &provider)
+            .is_err());
+    }
+}
@@ -126,6 +126,26 @@ mod connection {
                     .export_keying_material(output, label, context),
             }
         }
+
+        /// Derives key material from the TLS1.3 early exporter secret.
+        ///
+        /// See [`ConnectionCommon::export_early_keying_material()`] for more information.
+        #[inline]
+        pub fn export_early_keying_material<T: AsMut<[u8]>>(
+            &self,
+            output: T,
+            label: &[u8],
+            context: Option<&[u8]>,
+        ) -> Result<T, Error> {
+            match self {
+                Self::Client(conn) => conn
+                    .core
+                    .export_early_keying_material(output, label, context),
+                Self::Server(conn) => conn
+                    .core
+                    .export_early_keying_material(output, label, context),
+            }
+        }
     }
 
     impl Deref for Connection {
@@ -368,6 +388,22 @@ mod connection {
             ))
         }
 
+        /// Derives key material from the TLS1.3 early exporter secret.
+        ///
+        /// This is available once 0-RTT keys have been produced by a client, or
+        /// a server has accepted 0-RTT.
+        ///
+        /// See [`crate::ConnectionCommon::export_early_keying_material()`] for more information.
+        pub fn export_early_keying_material<T: AsMut<[u8]>>(
+            &self,
+            output: T,
+            label: &[u8],
+            context: Option<&[u8]>,
+        ) -> Result<T, Error> {
+            self.core
+                .export_early_keying_material(output, label, context)
+        }
+
         /// Consume unencrypted TLS handshake data.
         ///
         /// Handshake data obtained from separate encryption levels should be supplied in separate calls.
@@ -4,6 +4,7 @@ use core::cmp::min;
 use crate::crypto::cipher::{InboundOpaqueMessage, MessageDecrypter, MessageEncrypter};
 use crate::error::Error;
 use crate::log::trace;
+use crate::msgs::fragmenter::MAX_FRAGMENT_LEN;
 use crate::msgs::message::{InboundPlainMessage, OutboundOpaqueMessage, OutboundPlainMessage};
 
 #[derive(PartialEq)]
@@ -29,10 +30,18 @@ pub(crate) struct RecordLayer {
     encrypt_state: DirectionState,
     decrypt_state: DirectionState,
 
+    // Whether the current `message_encrypter` produces TLS1.3 records, whose
+    // plaintext may be padded.
+    padding_allowed: bool,
+
     // Message encrypted with other keys may be encountered, so failures
     // should be swallowed by the caller.  This struct tracks the amount
     // of message size this is allowed for.
     trial_decryption_len: Option<usize>,
+
+    // The largest plaintext we accept in a decrypted record: smaller than
+    // the protocol maximum if we advertised a `record_size_limit`.
+    max_plaintext_len: usize,
 }
 
 impl RecordLayer {
@@ -47,7 +56,9 @@ impl RecordLayer {
             has_decrypted: false,
             encrypt_state: DirectionState::Invalid,
             decrypt_state: DirectionState::Invalid,
+            padding_allowed: false,
             trial_decryption_len: None,
+            max_plaintext_len: MAX_FRAGMENT_LEN,
         }
     }
 
@@ -82,6 +93,9 @@ impl RecordLayer {
             .message_decrypter
             .decrypt(encr, self.read_seq)
         {
+            Ok(plaintext) if plaintext.payload.len() > self.max_plaintext_len => {
+                Err(Error::PeerSentOversizedRecord)
+            }
             Ok(plaintext) => {
                 self.read_seq += 1;
                 if !self.has_decrypted {
@@ -102,19 +116,30 @@ impl RecordLayer {
 
     /// Encrypt a TLS message.
     ///
-    /// `plain` is a TLS message we'd like to send.  This function
-    /// panics if the requisite keying material hasn't been established yet.
+    /// `plain` is a TLS message we'd like to send, and `padding` the number of
+    /// zero bytes to append to its plaintext.  `padding` must be zero unless
+    /// [`Self::padding_allowed()`].  This function panics if the requisite keying
+    /// material hasn't been established yet.
     pub(crate) fn encrypt_outgoing(
         &mut self,
         plain: OutboundPlainMessage<'_>,
+        padding: usize,
     ) -> OutboundOpaqueMessage {
         debug_assert!(self.encrypt_state == DirectionState::Active);
+        debug_assert!(padding == 0 || self.padding_allowed);
         assert!(self.next_pre_encrypt_action() != PreEncryptAction::Refuse);
         let seq = self.write_seq;
         self.write_seq += 1;
-        self.message_encrypter
-            .encrypt(plain, seq)
-            .unwrap()
+
+        match padding {
+            0 => self
+                .message_encrypter
+                .encrypt(plain, seq),
+            _ => self
+                .message_encrypter
+                .encrypt_padded(plain, seq, padding),
+        }
+        .unwrap()
     }
 
     /// Prepare to use the given `MessageEncrypter` for future message encryption.
@@ -128,6 +153,7 @@ impl RecordLayer {
         self.write_seq = 0;
         self.write_seq_max = min(SEQ_SOFT_LIMIT, max_messages);
         self.encrypt_state = DirectionState::Prepared;
+        self.padding_allowed = false;
     }
 
     /// Prepare to use the given `MessageDecrypter` for future message decryption.
@@ -184,6 +210,11 @@ impl RecordLayer {
         self.trial_decryption_len = Some(max_length);
     }
 
+    /// Reject future decrypted records carrying more than `len` bytes of plaintext.
+    pub(crate) fn set_max_plaintext_len(&mut self, len: usize) {
+        self.max_plaintext_len = len;
+    }
+
     pub(crate) fn finish_trial_decryption(&mut self) {
         self.trial_decryption_len = None;
     }
@@ -208,6 +239,18 @@ impl RecordLayer {
         self.encrypt_state == DirectionState::Active
     }
 
+    /// Allow padding of records produced by the current `MessageEncrypter`.
+    ///
+    /// Only valid for TLS1.3 encrypters.
+    pub(crate) fn allow_padding(&mut self) {
+        self.padding_allowed = true;
+    }
+
+    /// Return true if outgoing records can currently be padded.
+    pub(crate) fn padding_allowed(&self) -> bool {
+        self.padding_allowed && self.is_encrypting()
+    }
+
     /// Return true if we have ever decrypted a message. This is used in place
     /// of checking the read_seq since that will be reset on key updates.
     pub(crate) fn has_decrypted(&self) -> bool {
@@ -333,4 +376,44 @@ mod tests {
         assert_eq!(record_layer.read_seq, 0);
         assert!(record_layer.has_decrypted());
     }
+
+    #[test]
+    fn test_max_plaintext_len() {
+        use crate::{ContentType, ProtocolVersion};
+
+        struct PassThroughDecrypter;
+        impl MessageDecrypter for PassThroughDecrypter {
+            fn decrypt<'a>(
+                &mut self,
+                m: InboundOpaqueMessage<'a>,
+                _: u64,
+            ) -> Result<InboundPlainMessage<'a>, Error> {
+                Ok(m.into_plain_message())
+            }
+        }
+
+        let mut record_layer = RecordLayer::new();
+        record_layer.prepare_message_decrypter(Box::new(PassThroughDecrypter));
+        record_layer.start_decrypting();
+        record_layer.set_max_plaintext_len(4);
+
+        record_layer
+            .decrypt_incoming(InboundOpaqueMessage::new(
+                ContentType::ApplicationData,
+                ProtocolVersion::TLSv1_2,
+                &mut [0u8; 4],
+            ))
+            .unwrap();
+        assert_eq!(
+            record_layer
+                .decrypt_incoming(InboundOpaqueMessage::new(
+                    ContentType::ApplicationData,
+                    ProtocolVersion::TLSv1_2,
+                    &mut [0u8; 5],
+                ))
+                .err(),
+            Some(Error::PeerSentOversizedRecord)
+        );
+        assert_eq!(record_layer.read_seq, 1);
+    }
 }
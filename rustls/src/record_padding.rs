@@ -0,0 +1,150 @@
+use alloc::sync::Arc;
+use core::fmt::Debug;
+
+use crate::enums::ContentType;
+
+/// How outgoing TLS1.3 records are padded.
+///
+/// TLS1.3 allows the sender of a protected record to append zero bytes to its
+/// plaintext, hiding the exact length of the content from observers.  See
+/// [RFC 8446 section 5.4].  rustls always removes padding from received records;
+/// this controls what it adds to the records it sends.
+///
+/// Padding is applied to every record protected with TLS1.3 keys: encrypted handshake
+/// messages, early data, application data, and alerts.  It is never applied to TLS1.2
+/// records, nor to the unprotected records at the start of a handshake.
+///
+/// Padding never makes a record exceed the maximum fragment size in force for the
+/// connection, including any `record_size_limit` advertised by the peer.
+///
+/// Padding requires the TLS1.3 [`MessageEncrypter`]s of the connection's crypto
+/// provider to implement [`MessageEncrypter::encrypt_padded()`].
+///
+/// [`MessageEncrypter`]: crate::crypto::cipher::MessageEncrypter
+/// [`MessageEncrypter::encrypt_padded()`]: crate::crypto::cipher::MessageEncrypter::encrypt_padded
+/// [RFC 8446 section 5.4]: https://www.rfc-editor.org/rfc/rfc8446#section-5.4
+#[derive(Clone, Debug, Default)]
+pub enum RecordPadding {
+    /// Records are not padded.
+    #[default]
+    None,
+
+    /// Each record's plaintext (including the content type byte) is padded up to a
+    /// multiple of the given number of bytes.
+    ///
+    /// A value of zero or one is equivalent to [`RecordPadding::None`].
+    BlockSize(u16),
+
+    /// Every record is padded up to the maximum fragment size.
+    ///
+    /// This hides message lengths completely, at the cost of sending a full-size
+    /// record for every write.
+    MaxFragment,
+
+    /// Padding for each record is chosen by the given [`PadsRecords`] implementation.
+    Custom(Arc<dyn PadsRecords>),
+}
+
+impl RecordPadding {
+    /// Return the number of padding bytes to add to a record carrying `len` bytes of
+    /// content of type `typ`, where at most `max_padding` bytes may be added.
+    pub(crate) fn padding_len(&self, typ: ContentType, len: usize, max_padding: usize) -> usize {
+        let padding = match self {
+            Self::None => 0,
+            Self::BlockSize(block_size) => {
+                let block_size = usize::from(*block_size).max(1);
+                // The content type byte is part of the padded plaintext.
+                (block_size - (len + 1) % block_size) % block_size
+            }
+            Self::MaxFragment => max_padding,
+            Self::Custom(padder) => padder.padding_len(typ, len, max_padding),
+        };
+        padding.min(max_padding)
+    }
+
+    /// Return the most padding [`Self::padding_len()`] could produce for the same arguments.
+    ///
+    /// Used to size output buffers ahead of encryption, because a [`PadsRecords`]
+    /// implementation need not be deterministic.
+    pub(crate) fn max_padding_len(
+        &self,
+        typ: ContentType,
+        len: usize,
+        max_padding: usize,
+    ) -> usize {
+        match self {
+            Self::Custom(_) => max_padding,
+            _ => self.padding_len(typ, len, max_padding),
+        }
+    }
+}
+
+/// Decides how much padding to add to each outgoing TLS1.3 record.
+///
+/// Used with [`RecordPadding::Custom`].
+pub trait PadsRecords: Debug + Send + Sync {
+    /// Return the number of zero bytes to append to a record carrying `len` bytes of
+    /// content of type `typ`.
+    ///
+    /// At most `max_padding` bytes can be added without exceeding the connection's
+    /// maximum fragment size; larger return values are reduced to `max_padding`.
+    fn padding_len(&self, typ: ContentType, len: usize, max_padding: usize) -> usize;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_size_includes_content_type() {
+        let padding = RecordPadding::BlockSize(16);
+        assert_eq!(
+            padding.padding_len(ContentType::ApplicationData, 15, 100),
+            0
+        );
+        assert_eq!(
+            padding.padding_len(ContentType::ApplicationData, 0, 100),
+            15
+        );
+        assert_eq!(
+            padding.padding_len(ContentType::ApplicationData, 16, 100),
+            15
+        );
+        assert_eq!(
+            padding.padding_len(ContentType::ApplicationData, 16, 10),
+            10
+        );
+        assert_eq!(
+            RecordPadding::BlockSize(0).padding_len(ContentType::Alert, 5, 100),
+            0
+        );
+        assert_eq!(
+            RecordPadding::BlockSize(1).padding_len(ContentType::Alert, 5, 100),
+            0
+        );
+    }
+
+    #[test]
+    fn custom_padding_is_limited() {
+        #[derive(Debug)]
+        struct TooMuch;
+
+        impl PadsRecords for TooMuch {
+            fn padding_len(&self, _: ContentType, _: usize, max_padding: usize) -> usize {
+                max_padding + 1
+            }
+        }
+
+        let padding = RecordPadding::Custom(Arc::new(TooMuch));
+        assert_eq!(padding.padding_len(ContentType::Handshake, 10, 20), 20);
+        assert_eq!(padding.max_padding_len(ContentType::Handshake, 10, 20), 20);
+        assert_eq!(
+            RecordPadding::None.max_padding_len(ContentType::Handshake, 10, 20),
+            0
+        );
+        assert_eq!(
+            RecordPadding::MaxFragment.padding_len(ContentType::Handshake, 10, 20),
+            20
+        );
+    }
+}
@@ -6,6 +6,7 @@ use pki_types::{CertificateDer, PrivateKeyDer};
 
 use crate::builder::{ConfigBuilder, WantsVerifier};
 use crate::error::Error;
+use crate::record_padding::RecordPadding;
 use crate::server::{handy, ResolvesServerCert, ServerConfig};
 use crate::sign::CertifiedKey;
 use crate::verify::{ClientCertVerifier, NoClientAuth};
@@ -126,15 +127,21 @@ impl ConfigBuilder<ServerConfig, WantsServerCert> {
             cert_resolver,
             ignore_client_order: false,
             max_fragment_size: None,
+            record_size_limit: None,
+            record_padding: RecordPadding::None,
             #[cfg(feature = "std")]
             session_storage: handy::ServerSessionMemoryCache::new(256),
             #[cfg(not(feature = "std"))]
             session_storage: Arc::new(handy::NoServerSessionStorage {}),
             ticketer: Arc::new(handy::NeverProducesTickets {}),
+            anti_replay: None,
+            custom_extensions: None,
             alpn_protocols: Vec::new(),
             versions: self.state.versions,
             key_log: Arc::new(NoKeyLog {}),
+            observer: None,
             enable_secret_extraction: false,
+            enable_grease: false,
             max_early_data_size: 0,
             send_half_rtt_data: false,
             send_tls13_tickets: 2,
@@ -144,6 +151,8 @@ impl ConfigBuilder<ServerConfig, WantsServerCert> {
             cert_compressors: compress::default_cert_compressors().to_vec(),
             cert_compression_cache: Arc::new(compress::CompressionCache::default()),
             cert_decompressors: compress::default_cert_decompressors().to_vec(),
+            psk_resolver: None,
+            ech_keys: Vec::new(),
         }
     }
 }
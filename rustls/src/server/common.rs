@@ -1,11 +1,13 @@
 use pki_types::CertificateDer;
 
+use crate::delegated_credential::DelegatedKey;
 use crate::sign;
 
-/// ActiveCertifiedKey wraps [`sign::CertifiedKey`] and tracks OSCP state in a single handshake.
+/// ActiveCertifiedKey wraps [`sign::CertifiedKey`] and tracks OSCP and SCT state in a single handshake.
 pub(super) struct ActiveCertifiedKey<'a> {
     key: &'a sign::CertifiedKey,
     ocsp: Option<&'a [u8]>,
+    sct_list: Option<&'a [u8]>,
 }
 
 impl ActiveCertifiedKey<'_> {
@@ -13,6 +15,7 @@ impl ActiveCertifiedKey<'_> {
         ActiveCertifiedKey {
             key,
             ocsp: key.ocsp.as_deref(),
+            sct_list: key.sct_list.as_deref(),
         }
     }
 
@@ -32,4 +35,15 @@ impl ActiveCertifiedKey<'_> {
     pub(super) fn get_ocsp(&self) -> Option<&[u8]> {
         self.ocsp
     }
+
+    #[inline]
+    pub(super) fn get_sct_list(&self) -> Option<&[u8]> {
+        self.sct_list
+    }
+
+    /// Get the delegated credential and its key, if any
+    #[inline]
+    pub(super) fn get_delegated_key(&self) -> Option<&DelegatedKey> {
+        self.key.delegated_credential.as_ref()
+    }
 }
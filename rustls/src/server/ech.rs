@@ -0,0 +1,557 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+
+use pki_types::{DnsName, EchConfigListBytes};
+
+use super::ServerConfig;
+use crate::common_state::CommonState;
+use crate::crypto::hpke::{
+    EncapsulatedSecret, Hpke, HpkeKeyPair, HpkeOpener, HpkePrivateKey, HpkeSuite,
+};
+use crate::enums::{AlertDescription, CipherSuite, HandshakeType, ProtocolVersion};
+use crate::error::{EncryptedClientHelloError, Error, InvalidMessage, PeerMisbehaved};
+use crate::log::{debug, trace};
+use crate::msgs::base::{Payload, PayloadU16};
+use crate::msgs::codec::{self, Codec, LengthPrefixedBuffer, ListLength, Reader};
+use crate::msgs::enums::{Compression, ExtensionType};
+use crate::msgs::handshake::{
+    ClientHelloPayload, EchConfigContents, EchConfigPayload, EncryptedClientHello,
+    EncryptedClientHelloOuter, HandshakeMessagePayload, HandshakePayload, HpkeKeyConfig,
+    HpkeSymmetricCipherSuite, Random, SessionId,
+};
+use crate::msgs::message::{Message, MessagePayload};
+
+/// An Encrypted Client Hello (ECH) configuration, together with the private key
+/// needed to accept it.
+///
+/// The configuration is what clients learn about out-of-band, typically from the `ech`
+/// parameter of a DNS `HTTPS` resource record. See [`EchServerKey::config_list()`].
+///
+/// Provide these to a server using [`ServerConfig::ech_keys`].
+pub struct EchServerKey {
+    config: EchConfigPayload,
+    private_key: HpkePrivateKey,
+    hpke_suites: Vec<&'static dyn Hpke>,
+}
+
+impl EchServerKey {
+    /// Construct an `EchServerKey` from an encoded ECH configuration list and its key pair.
+    ///
+    /// `ech_config_list` must contain exactly one supported ECH configuration, and its
+    /// public key must be `key_pair.public_key`. `hpke_suites` are the HPKE instances that
+    /// may be used to decrypt client hellos: those not matching the configuration are ignored,
+    /// and an error is returned if none match.
+    pub fn new(
+        ech_config_list: EchConfigListBytes<'_>,
+        key_pair: HpkeKeyPair,
+        hpke_suites: &[&'static dyn Hpke],
+    ) -> Result<Self, Error> {
+        let invalid = || Error::from(EncryptedClientHelloError::InvalidConfigList);
+
+        let mut configs = Vec::<EchConfigPayload>::read(&mut Reader::init(&ech_config_list))
+            .map_err(|_| invalid())?;
+        let (Some(config), true) = (configs.pop(), configs.is_empty()) else {
+            return Err(invalid());
+        };
+
+        let EchConfigPayload::V18(contents) = &config else {
+            return Err(invalid());
+        };
+
+        if contents.has_unknown_mandatory_extension()
+            || contents.has_duplicate_extension()
+            || contents.key_config.public_key.0 != key_pair.public_key.0
+        {
+            return Err(invalid());
+        }
+
+        let hpke_suites = Self::compatible_suites(&contents.key_config, hpke_suites);
+        if hpke_suites.is_empty() {
+            return Err(EncryptedClientHelloError::NoCompatibleConfig.into());
+        }
+
+        Ok(Self {
+            config,
+            private_key: key_pair.private_key,
+            hpke_suites,
+        })
+    }
+
+    /// Generate a new key pair using `hpke`, and a matching ECH configuration.
+    ///
+    /// `config_id` identifies the configuration to clients, and should be unique among the
+    /// server's current keys. `public_name` is the name clients use in the unencrypted
+    /// outer `ClientHello`, and that this server must be able to authenticate as in case
+    /// ECH is rejected.
+    pub fn generate(
+        hpke: &'static dyn Hpke,
+        config_id: u8,
+        public_name: DnsName<'static>,
+    ) -> Result<Self, Error> {
+        let (public_key, private_key) = hpke.generate_key_pair()?;
+        let suite = hpke.suite();
+
+        Ok(Self {
+            config: EchConfigPayload::V18(EchConfigContents {
+                key_config: HpkeKeyConfig {
+                    config_id,
+                    kem_id: suite.kem,
+                    public_key: PayloadU16::new(public_key.0),
+                    symmetric_cipher_suites: vec![suite.sym],
+                },
+                maximum_name_length: 0,
+                public_name,
+                extensions: Vec::new(),
+            }),
+            private_key,
+            hpke_suites: vec![hpke],
+        })
+    }
+
+    /// Return the encoded ECH configuration list for this key, to be published to clients.
+    ///
+    /// This is the value given to [`crate::client::EchConfig::new()`].
+    pub fn config_list(&self) -> EchConfigListBytes<'static> {
+        vec![self.config.clone()]
+            .get_encoding()
+            .into()
+    }
+
+    /// Returns true if all of the HPKE suites used with this key are FIPS approved.
+    pub fn fips(&self) -> bool {
+        self.hpke_suites
+            .iter()
+            .all(|suite| suite.fips())
+    }
+
+    pub(crate) fn config(&self) -> &EchConfigPayload {
+        &self.config
+    }
+
+    fn contents(&self) -> &EchConfigContents {
+        match &self.config {
+            EchConfigPayload::V18(contents) => contents,
+            // Unknown versions are refused on construction.
+            EchConfigPayload::Unknown { .. } => unreachable!(),
+        }
+    }
+
+    fn compatible_suites(
+        key_config: &HpkeKeyConfig,
+        hpke_suites: &[&'static dyn Hpke],
+    ) -> Vec<&'static dyn Hpke> {
+        hpke_suites
+            .iter()
+            .filter(|hpke| {
+                let HpkeSuite { kem, sym } = hpke.suite();
+                kem == key_config.kem_id
+                    && key_config
+                        .symmetric_cipher_suites
+                        .contains(&sym)
+            })
+            .copied()
+            .collect()
+    }
+
+    fn suite_for(&self, cipher_suite: HpkeSymmetricCipherSuite) -> Option<&'static dyn Hpke> {
+        self.hpke_suites
+            .iter()
+            .find(|hpke| hpke.suite().sym == cipher_suite)
+            .copied()
+    }
+
+    /// Compute the HPKE `SetupBaseR` `info` parameter for this ECH configuration.
+    ///
+    /// See <https://datatracker.ietf.org/doc/html/draft-ietf-tls-esni-18#section-7.1>.
+    fn hpke_info(&self) -> Vec<u8> {
+        let mut info = Vec::with_capacity(128);
+        // "tls ech" || 0x00 || ECHConfig
+        info.extend_from_slice(b"tls ech\0");
+        self.config.encode(&mut info);
+        info
+    }
+}
+
+impl fmt::Debug for EchServerKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EchServerKey")
+            .field("config", &self.config)
+            .field("hpke_suites", &self.hpke_suites)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Tracks a server's decision on a client's Encrypted Client Hello (ECH) offer.
+pub(super) enum EchState {
+    /// ECH was not offered, or the server has no ECH keys.
+    NotOffered,
+    /// ECH was offered but could not be decrypted: the handshake proceeds using the
+    /// outer hello, and retry configurations are sent to the client.
+    Rejected,
+    /// ECH was accepted: the handshake proceeds using the inner hello.
+    Accepted(EchAccepted),
+}
+
+impl EchState {
+    /// Process the ECH offer in a `ClientHello`, if any.
+    ///
+    /// If the offer is accepted, returns the reconstructed inner `ClientHello` message that
+    /// the handshake must continue with.
+    pub(super) fn process_client_hello(
+        &mut self,
+        config: &ServerConfig,
+        done_retry: bool,
+        m: &Message<'_>,
+        common: &mut CommonState,
+    ) -> Result<Option<Message<'static>>, Error> {
+        // ECH is not offered in DTLS: its outer hello would need a DTLS-specific
+        // reconstruction of the inner one.
+        if config.ech_keys.is_empty()
+            || !config.supports_version(ProtocolVersion::TLSv1_3)
+            || common.is_dtls()
+        {
+            return Ok(None);
+        }
+
+        let MessagePayload::Handshake {
+            parsed:
+                HandshakeMessagePayload {
+                    payload: HandshakePayload::ClientHello(outer_hello),
+                    ..
+                },
+            encoded,
+        } = &m.payload
+        else {
+            // Left for `process_client_hello` to complain about.
+            return Ok(None);
+        };
+
+        let offer = match outer_hello.ech_extension() {
+            Some(EncryptedClientHello::Outer(offer)) => Some(offer),
+            _ => None,
+        };
+        let outer = OuterHello::new(encoded.bytes(), &outer_hello.session_id);
+
+        match (self, done_retry) {
+            (Self::Accepted(accepted), true) => {
+                // draft-ietf-tls-esni-18 section 7.1.1: the second ClientHelloOuter must
+                // use the same configuration and suite, and carry an empty `enc`.
+                let offer = match offer {
+                    Some(offer)
+                        if offer.config_id == accepted.config_id
+                            && offer.cipher_suite == accepted.cipher_suite
+                            && offer.enc.0.is_empty() =>
+                    {
+                        offer
+                    }
+                    _ => {
+                        return Err(common.send_fatal_alert(
+                            AlertDescription::IllegalParameter,
+                            PeerMisbehaved::InvalidEchExtensionAfterRetry,
+                        ))
+                    }
+                };
+
+                let encoded_inner = outer
+                    .and_then(|outer| {
+                        accepted
+                            .opener
+                            .open(&outer.aad()?, &offer.payload.0)
+                            .ok()
+                    })
+                    .ok_or_else(|| {
+                        common.send_fatal_alert(
+                            AlertDescription::DecryptError,
+                            PeerMisbehaved::UndecryptableEchExtensionAfterRetry,
+                        )
+                    })?;
+
+                let inner = outer
+                    .and_then(|outer| outer.decode_inner(&encoded_inner))
+                    .ok_or_else(|| invalid_inner_hello(common))?;
+                accepted.check_inner(&inner, common)?;
+                Ok(Some(inner.message(m.version)))
+            }
+            (state, false) => {
+                let Some(offer) = offer else {
+                    return Ok(None);
+                };
+
+                *state = Self::Rejected;
+                let Some(outer) = outer else {
+                    return Ok(None);
+                };
+                let Some(aad) = outer.aad() else {
+                    return Ok(None);
+                };
+
+                for key in config
+                    .ech_keys
+                    .iter()
+                    .filter(|key| key.contents().key_config.config_id == offer.config_id)
+                {
+                    let Some(mut opener) = open_offer(key, offer) else {
+                        continue;
+                    };
+                    let Ok(encoded_inner) = opener.open(&aad, &offer.payload.0) else {
+                        continue;
+                    };
+
+                    trace!("ECH offer decrypted with config ID {}", offer.config_id);
+                    let inner = outer
+                        .decode_inner(&encoded_inner)
+                        .ok_or_else(|| invalid_inner_hello(common))?;
+
+                    let accepted = EchAccepted {
+                        opener,
+                        config_id: offer.config_id,
+                        cipher_suite: offer.cipher_suite,
+                        inner_random: inner.hello().random,
+                    };
+                    accepted.check_inner(&inner, common)?;
+
+                    *state = Self::Accepted(accepted);
+                    return Ok(Some(inner.message(m.version)));
+                }
+
+                debug!("ECH offer could not be decrypted: continuing with outer hello");
+                Ok(None)
+            }
+            (_, true) => Ok(None),
+        }
+    }
+
+    pub(super) fn accepted(&self) -> Option<&EchAccepted> {
+        match self {
+            Self::Accepted(accepted) => Some(accepted),
+            _ => None,
+        }
+    }
+}
+
+/// State retained once a server has accepted a client's ECH offer.
+pub(super) struct EchAccepted {
+    /// HPKE context: a second `ClientHello` after a retry is decrypted with this.
+    opener: Box<dyn HpkeOpener>,
+    config_id: u8,
+    cipher_suite: HpkeSymmetricCipherSuite,
+    pub(super) inner_random: Random,
+}
+
+impl EchAccepted {
+    /// Checks the requirements draft-ietf-tls-esni-18 section 7.1 places on a decrypted
+    /// `ClientHelloInner`.
+    fn check_inner(&self, inner: &InnerHello, common: &mut CommonState) -> Result<(), Error> {
+        let hello = inner.hello();
+        let offers_inner_ech = matches!(hello.ech_extension(), Some(EncryptedClientHello::Inner));
+
+        // "If ClientHelloInner offers TLS 1.2 or below, the server MUST abort"
+        let offers_tls13_only = hello
+            .versions_extension()
+            .is_some_and(|versions| {
+                !versions.is_empty()
+                    && versions
+                        .iter()
+                        .all(|v| u16::from(*v) >= u16::from(ProtocolVersion::TLSv1_3))
+            });
+
+        match offers_inner_ech && offers_tls13_only {
+            true => Ok(()),
+            false => Err(invalid_inner_hello(common)),
+        }
+    }
+}
+
+fn open_offer(
+    key: &EchServerKey,
+    offer: &EncryptedClientHelloOuter,
+) -> Option<Box<dyn HpkeOpener>> {
+    let hpke = key.suite_for(offer.cipher_suite)?;
+    hpke.setup_opener(
+        &EncapsulatedSecret(offer.enc.0.clone()),
+        &key.hpke_info(),
+        &key.private_key,
+    )
+    .ok()
+}
+
+fn invalid_inner_hello(common: &mut CommonState) -> Error {
+    common.send_fatal_alert(
+        AlertDescription::IllegalParameter,
+        PeerMisbehaved::InvalidEchInnerClientHello,
+    )
+}
+
+/// The encoding of a received `ClientHelloOuter`, with the location of its extensions.
+#[derive(Clone, Copy)]
+struct OuterHello<'a> {
+    /// `ClientHello` encoding, excluding the handshake message header.
+    body: &'a [u8],
+    session_id: &'a SessionId,
+}
+
+impl<'a> OuterHello<'a> {
+    fn new(encoded: &'a [u8], session_id: &'a SessionId) -> Option<Self> {
+        Some(Self {
+            body: encoded.get(HANDSHAKE_HEADER_LEN..)?,
+            session_id,
+        })
+    }
+
+    /// Returns the type and encoded range (including the type and length) of each extension.
+    fn extensions(&self) -> Result<Vec<(ExtensionType, Range<usize>)>, InvalidMessage> {
+        let mut r = Reader::init(self.body);
+        ProtocolVersion::read(&mut r)?;
+        Random::read(&mut r)?;
+        SessionId::read(&mut r)?;
+        Vec::<CipherSuite>::read(&mut r)?;
+        Vec::<Compression>::read(&mut r)?;
+
+        let len = u16::read(&mut r)? as usize;
+        let start = r.used();
+        let mut exts = r.sub(len)?;
+        let mut ret = Vec::new();
+        while exts.any_left() {
+            let from = start + exts.used();
+            let typ = ExtensionType::read(&mut exts)?;
+            let len = u16::read(&mut exts)? as usize;
+            exts.take(len)
+                .ok_or(InvalidMessage::MessageTooShort)?;
+            ret.push((typ, from..start + exts.used()));
+        }
+
+        Ok(ret)
+    }
+
+    /// Compute the `ClientHelloOuterAAD`: the outer hello with the ECH payload zeroed.
+    ///
+    /// See <https://datatracker.ietf.org/doc/html/draft-ietf-tls-esni-18#section-5.2>.
+    fn aad(&self) -> Option<Vec<u8>> {
+        let extensions = self.extensions().ok()?;
+        let (_, range) = extensions
+            .iter()
+            .find(|(typ, _)| *typ == ExtensionType::EncryptedClientHello)?;
+
+        // The payload is the final, length-prefixed, field of the extension.
+        let mut r = Reader::init(&self.body[range.clone()]);
+        ExtensionType::read(&mut r).ok()?;
+        u16::read(&mut r).ok()?;
+        let EncryptedClientHello::Outer(offer) = EncryptedClientHello::read(&mut r).ok()? else {
+            return None;
+        };
+        let payload_len = offer.payload.0.len();
+
+        let mut aad = self.body.to_vec();
+        aad[range.end - payload_len..range.end].fill(0);
+        Some(aad)
+    }
+
+    /// Decode an `EncodedClientHelloInner`, recovering the complete `ClientHelloInner`.
+    ///
+    /// See <https://datatracker.ietf.org/doc/html/draft-ietf-tls-esni-18#section-5.1>.
+    fn decode_inner(&self, encoded_inner: &[u8]) -> Option<InnerHello> {
+        let outer_extensions = self.extensions().ok()?;
+        let mut r = Reader::init(encoded_inner);
+
+        let mut body = Vec::new();
+        ProtocolVersion::read(&mut r)
+            .ok()?
+            .encode(&mut body);
+        Random::read(&mut r)
+            .ok()?
+            .encode(&mut body);
+        // The session ID is elided from the inner hello, and copied from the outer.
+        if !SessionId::read(&mut r).ok()?.is_empty() {
+            return None;
+        }
+        self.session_id.encode(&mut body);
+        Vec::<CipherSuite>::read(&mut r)
+            .ok()?
+            .encode(&mut body);
+        Vec::<Compression>::read(&mut r)
+            .ok()?
+            .encode(&mut body);
+
+        let len = u16::read(&mut r).ok()? as usize;
+        let mut exts = r.sub(len).ok()?;
+        let mut next_outer = 0;
+        let nested = LengthPrefixedBuffer::new(ListLength::U16, &mut body);
+        while exts.any_left() {
+            let typ = ExtensionType::read(&mut exts).ok()?;
+            let len = u16::read(&mut exts).ok()? as usize;
+            let data = exts.take(len)?;
+
+            if typ != ExtensionType::EncryptedClientHelloOuterExtensions {
+                typ.encode(nested.buf);
+                (len as u16).encode(nested.buf);
+                nested.buf.extend_from_slice(data);
+                continue;
+            }
+
+            // Referenced extensions are copied verbatim from the outer hello, and
+            // must appear there in the same order.
+            for referenced in Vec::<ExtensionType>::read_bytes(data).ok()? {
+                if referenced == ExtensionType::EncryptedClientHello {
+                    return None;
+                }
+
+                let offset = outer_extensions[next_outer..]
+                    .iter()
+                    .position(|(typ, _)| *typ == referenced)?;
+                let (_, range) = &outer_extensions[next_outer + offset];
+                nested
+                    .buf
+                    .extend_from_slice(&self.body[range.clone()]);
+                next_outer += offset + 1;
+            }
+        }
+        drop(nested);
+
+        // The remainder is padding, which must be zeroes.
+        if r.rest().iter().any(|b| *b != 0) {
+            return None;
+        }
+
+        let mut encoded = Vec::with_capacity(HANDSHAKE_HEADER_LEN + body.len());
+        HandshakeType::ClientHello.encode(&mut encoded);
+        codec::u24(body.len() as u32).encode(&mut encoded);
+        encoded.extend_from_slice(&body);
+
+        let parsed = HandshakeMessagePayload::read_bytes(&encoded)
+            .ok()?
+            .into_owned();
+
+        Some(InnerHello { parsed, encoded })
+    }
+}
+
+/// A decrypted and reconstructed `ClientHelloInner`.
+struct InnerHello {
+    parsed: HandshakeMessagePayload<'static>,
+    encoded: Vec<u8>,
+}
+
+impl InnerHello {
+    fn hello(&self) -> &ClientHelloPayload {
+        match &self.parsed.payload {
+            HandshakePayload::ClientHello(hello) => hello,
+            // `decode_inner` only yields a `ClientHello`.
+            _ => unreachable!(),
+        }
+    }
+
+    fn message(self, version: ProtocolVersion) -> Message<'static> {
+        Message {
+            version,
+            payload: MessagePayload::Handshake {
+                parsed: self.parsed,
+                encoded: Payload::new(self.encoded),
+            },
+        }
+    }
+}
+
+const HANDSHAKE_HEADER_LEN: usize = 4;
@@ -26,10 +26,16 @@ impl server::StoresServerSessions for NoServerSessionStorage {
 
 #[cfg(any(feature = "std", feature = "hashbrown"))]
 mod cache {
+    use alloc::collections::VecDeque;
     use alloc::sync::Arc;
     use alloc::vec::Vec;
     use core::fmt::{Debug, Formatter};
+    use core::hash::BuildHasher;
+    use core::time::Duration;
 
+    use pki_types::UnixTime;
+
+    use crate::hash_map::HashSet;
     use crate::lock::Mutex;
     use crate::{limited_cache, server};
 
@@ -95,12 +101,97 @@ mod cache {
         }
     }
 
+    /// An implementer of `RecordsClientHellos` that records ClientHellos in memory.
+    ///
+    /// It records at most a fixed number of ClientHellos, forgetting them after twice
+    /// its window.  When full, early data is rejected until space is freed.
+    pub struct StrikeRegister {
+        window: Duration,
+        capacity: usize,
+        seen: Mutex<Strikes>,
+    }
+
+    impl StrikeRegister {
+        /// Make a new StrikeRegister.  `window` is the tolerance allowed in ticket
+        /// ages, and `capacity` the maximum number of ClientHellos recorded at once.
+        #[cfg(feature = "std")]
+        pub fn new(window: Duration, capacity: usize) -> Arc<Self> {
+            Arc::new(Self {
+                window,
+                capacity,
+                seen: Mutex::new(Strikes::default()),
+            })
+        }
+
+        /// Make a new StrikeRegister.  `window` is the tolerance allowed in ticket
+        /// ages, and `capacity` the maximum number of ClientHellos recorded at once.
+        #[cfg(not(feature = "std"))]
+        pub fn new<M: crate::lock::MakeMutex>(window: Duration, capacity: usize) -> Arc<Self> {
+            Arc::new(Self {
+                window,
+                capacity,
+                seen: Mutex::new::<M>(Strikes::default()),
+            })
+        }
+    }
+
+    impl server::RecordsClientHellos for StrikeRegister {
+        fn record(&self, key: &[u8], now: UnixTime) -> bool {
+            let now = now.as_secs();
+            let mut seen = self.seen.lock().unwrap();
+            let hash = seen.hashes.hasher().hash_one(key);
+
+            // A ClientHello accepted at `t` could be replayed with an acceptable
+            // ticket age until `t + 2 * window`.
+            let retention = self.window.as_secs() * 2 + 1;
+            while let Some((recorded, _)) = seen.order.front() {
+                if recorded.saturating_add(retention) > now {
+                    break;
+                }
+                if let Some((_, old)) = seen.order.pop_front() {
+                    seen.hashes.remove(&old);
+                }
+            }
+
+            if seen.hashes.contains(&hash) || seen.hashes.len() >= self.capacity {
+                return false;
+            }
+
+            seen.hashes.insert(hash);
+            seen.order.push_back((now, hash));
+            true
+        }
+
+        fn window(&self) -> Duration {
+            self.window
+        }
+    }
+
+    impl Debug for StrikeRegister {
+        fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+            f.debug_struct("StrikeRegister")
+                .field("window", &self.window)
+                .field("capacity", &self.capacity)
+                .finish()
+        }
+    }
+
+    /// Hashes of recorded keys, and the order (with times, in seconds) they were
+    /// recorded in.
+    ///
+    /// Only hashes are kept: a collision merely causes early data to be rejected.
+    #[derive(Default)]
+    struct Strikes {
+        hashes: HashSet<u64>,
+        order: VecDeque<(u64, u64)>,
+    }
+
     #[cfg(test)]
     mod tests {
         use std::vec;
 
         use super::*;
-        use crate::server::StoresServerSessions;
+        use crate::server::{RecordsClientHellos, StoresServerSessions};
 
         #[test]
         fn test_serversessionmemorycache_accepts_put() {
@@ -141,11 +232,51 @@ mod cache {
 
             assert!(count < 5);
         }
+
+        #[test]
+        fn test_strikeregister_rejects_replays() {
+            let r = StrikeRegister::new(Duration::from_secs(10), 4);
+            let now = UnixTime::since_unix_epoch(Duration::from_secs(1000));
+            assert!(r.record(&[0x01], now));
+            assert!(r.record(&[0x02], now));
+            assert!(!r.record(&[0x01], now));
+            assert!(!r.record(&[0x02], now));
+        }
+
+        #[test]
+        fn test_strikeregister_forgets_after_twice_window() {
+            let r = StrikeRegister::new(Duration::from_secs(10), 4);
+            assert!(r.record(
+                &[0x01],
+                UnixTime::since_unix_epoch(Duration::from_secs(1000))
+            ));
+            assert!(!r.record(
+                &[0x01],
+                UnixTime::since_unix_epoch(Duration::from_secs(1020))
+            ));
+            assert!(r.record(
+                &[0x01],
+                UnixTime::since_unix_epoch(Duration::from_secs(1021))
+            ));
+        }
+
+        #[test]
+        fn test_strikeregister_rejects_when_full() {
+            let r = StrikeRegister::new(Duration::from_secs(10), 2);
+            let now = UnixTime::since_unix_epoch(Duration::from_secs(1000));
+            assert!(r.record(&[0x01], now));
+            assert!(r.record(&[0x02], now));
+            assert!(!r.record(&[0x03], now));
+            assert!(r.record(
+                &[0x03],
+                UnixTime::since_unix_epoch(Duration::from_secs(1021))
+            ));
+        }
     }
 }
 
 #[cfg(any(feature = "std", feature = "hashbrown"))]
-pub use cache::ServerSessionMemoryCache;
+pub use cache::{ServerSessionMemoryCache, StrikeRegister};
 
 /// Something which never produces tickets.
 #[derive(Debug)]
@@ -227,10 +358,14 @@ impl server::ResolvesServerCert for AlwaysResolvesServerRawPublicKeys {
 mod sni_resolver {
     use alloc::string::{String, ToString};
     use alloc::sync::Arc;
+    use alloc::vec::Vec;
     use core::fmt::Debug;
+    #[cfg(feature = "std")]
+    use std::sync::RwLock;
 
     use pki_types::{DnsName, ServerName};
 
+    use crate::enums::SignatureScheme;
     use crate::error::Error;
     use crate::hash_map::HashMap;
     use crate::server::ClientHello;
@@ -239,67 +374,277 @@ mod sni_resolver {
 
     /// Something that resolves do different cert chains/keys based
     /// on client-supplied server name (via SNI).
+    ///
+    /// Names may be exact (`www.example.com`) or wildcards (`*.example.com`).
+    /// A wildcard matches names with exactly one more label, and is only used
+    /// if there is no exact match.  A default certificate may be added for
+    /// clients which do not send SNI, or send a name that matches nothing else.
+    ///
+    /// Each name may have several certificates, with keys of different
+    /// algorithms (say, ECDSA and RSA).  The first one usable with the
+    /// client's offered signature schemes is chosen.
+    ///
+    /// With the `std` feature, certificates can be added and removed at any time,
+    /// including while this is in use by a `ServerConfig`.  Handshakes only take
+    /// a read lock, so they do not wait for each other.  Without it, the methods
+    /// that change the certificates take `&mut self`.
     #[derive(Debug)]
     pub struct ResolvesServerCertUsingSni {
-        by_name: HashMap<String, Arc<sign::CertifiedKey>>,
+        #[cfg(feature = "std")]
+        state: RwLock<SniState>,
+        #[cfg(not(feature = "std"))]
+        state: SniState,
     }
 
     impl ResolvesServerCertUsingSni {
         /// Create a new and empty (i.e., knows no certificates) resolver.
         pub fn new() -> Self {
             Self {
-                by_name: HashMap::new(),
+                state: Default::default(),
             }
         }
 
         /// Add a new `sign::CertifiedKey` to be used for the given SNI `name`.
         ///
+        /// `name` may be a wildcard name like `*.example.com`.
+        ///
+        /// This replaces any certificate previously added for `name` with a key
+        /// of the same algorithm.  Certificates with keys of other algorithms are
+        /// kept, and earlier ones are preferred.
+        ///
         /// This function fails if `name` is not a valid DNS name, or if
         /// it's not valid for the supplied certificate, or if the certificate
         /// chain is syntactically faulty.
+        #[cfg(feature = "std")]
+        pub fn add(&self, name: &str, ck: sign::CertifiedKey) -> Result<(), Error> {
+            let (wildcard, name, ck) = check_key(name, ck)?;
+            self.state
+                .write()
+                .unwrap()
+                .add(wildcard, name, ck);
+            Ok(())
+        }
+
+        /// Add a new `sign::CertifiedKey` to be used for the given SNI `name`.
+        ///
+        /// `name` may be a wildcard name like `*.example.com`.
+        ///
+        /// This replaces any certificate previously added for `name` with a key
+        /// of the same algorithm.  Certificates with keys of other algorithms are
+        /// kept, and earlier ones are preferred.
+        ///
+        /// This function fails if `name` is not a valid DNS name, or if
+        /// it's not valid for the supplied certificate, or if the certificate
+        /// chain is syntactically faulty.
+        #[cfg(not(feature = "std"))]
         pub fn add(&mut self, name: &str, ck: sign::CertifiedKey) -> Result<(), Error> {
-            let server_name = {
-                let checked_name = DnsName::try_from(name)
-                    .map_err(|_| Error::General("Bad DNS name".into()))
-                    .map(|name| name.to_lowercase_owned())?;
-                ServerName::DnsName(checked_name)
-            };
+            let (wildcard, name, ck) = check_key(name, ck)?;
+            self.state.add(wildcard, name, ck);
+            Ok(())
+        }
 
-            // Check the certificate chain for validity:
-            // - it should be non-empty list
-            // - the first certificate should be parsable as a x509v3,
-            // - the first certificate should quote the given server name
-            //   (if provided)
-            //
-            // These checks are not security-sensitive.  They are the
-            // *server* attempting to detect accidental misconfiguration.
+        /// Remove all the certificates for the given SNI `name`.
+        ///
+        /// `name` may be a wildcard name like `*.example.com`; this only removes
+        /// certificates added for that wildcard, not those for names it covers.
+        ///
+        /// Returns `true` if there were any.
+        #[cfg(feature = "std")]
+        pub fn remove(&self, name: &str) -> bool {
+            self.state.write().unwrap().remove(name)
+        }
 
+        /// Remove all the certificates for the given SNI `name`.
+        ///
+        /// `name` may be a wildcard name like `*.example.com`; this only removes
+        /// certificates added for that wildcard, not those for names it covers.
+        ///
+        /// Returns `true` if there were any.
+        #[cfg(not(feature = "std"))]
+        pub fn remove(&mut self, name: &str) -> bool {
+            self.state.remove(name)
+        }
+
+        /// Add a `sign::CertifiedKey` to be used if no other certificate matches.
+        ///
+        /// This is used for clients which do not send SNI, or send a name with
+        /// no certificate.  As with [`Self::add()`], this replaces any default
+        /// certificate with a key of the same algorithm.
+        ///
+        /// This function fails if the certificate chain is syntactically faulty.
+        #[cfg(feature = "std")]
+        pub fn add_default(&self, ck: sign::CertifiedKey) -> Result<(), Error> {
             ck.end_entity_cert()
-                .and_then(ParsedCertificate::try_from)
-                .and_then(|cert| verify_server_name(&cert, &server_name))?;
+                .and_then(ParsedCertificate::try_from)?;
+            insert_key(&mut self.state.write().unwrap().default, ck);
+            Ok(())
+        }
 
-            if let ServerName::DnsName(name) = server_name {
-                self.by_name
-                    .insert(name.as_ref().to_string(), Arc::new(ck));
-            }
+        /// Add a `sign::CertifiedKey` to be used if no other certificate matches.
+        ///
+        /// This is used for clients which do not send SNI, or send a name with
+        /// no certificate.  As with [`Self::add()`], this replaces any default
+        /// certificate with a key of the same algorithm.
+        ///
+        /// This function fails if the certificate chain is syntactically faulty.
+        #[cfg(not(feature = "std"))]
+        pub fn add_default(&mut self, ck: sign::CertifiedKey) -> Result<(), Error> {
+            ck.end_entity_cert()
+                .and_then(ParsedCertificate::try_from)?;
+            insert_key(&mut self.state.default, ck);
             Ok(())
         }
+
+        /// Remove all the default certificates.
+        ///
+        /// Returns `true` if there were any.
+        #[cfg(feature = "std")]
+        pub fn remove_default(&self) -> bool {
+            self.state
+                .write()
+                .unwrap()
+                .remove_default()
+        }
+
+        /// Remove all the default certificates.
+        ///
+        /// Returns `true` if there were any.
+        #[cfg(not(feature = "std"))]
+        pub fn remove_default(&mut self) -> bool {
+            self.state.remove_default()
+        }
     }
 
     impl server::ResolvesServerCert for ResolvesServerCertUsingSni {
         fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<sign::CertifiedKey>> {
-            if let Some(name) = client_hello.server_name() {
-                self.by_name.get(name).cloned()
-            } else {
-                // This kind of resolver requires SNI
-                None
-            }
+            #[cfg(feature = "std")]
+            let state = self.state.read().unwrap();
+            #[cfg(not(feature = "std"))]
+            let state = &self.state;
+
+            let keys = client_hello
+                .server_name()
+                .and_then(|name| state.lookup(name))
+                .unwrap_or(&state.default);
+            choose_key(keys, client_hello.signature_schemes())
         }
     }
 
+    /// Check `ck` is usable for `name`, which may be a wildcard name.
+    ///
+    /// Returns whether `name` is a wildcard, and the (lowercased) name without
+    /// any leading `*.`.
+    fn check_key(
+        name: &str,
+        ck: sign::CertifiedKey,
+    ) -> Result<(bool, String, sign::CertifiedKey), Error> {
+        let (wildcard, name) = match name.strip_prefix("*.") {
+            Some(suffix) => (true, suffix),
+            None => (false, name),
+        };
+
+        let checked_name = DnsName::try_from(name)
+            .map_err(|_| Error::General("Bad DNS name".into()))
+            .map(|name| name.to_lowercase_owned())?;
+
+        // Check the certificate chain for validity:
+        // - it should be non-empty list
+        // - the first certificate should be parsable as a x509v3,
+        // - the first certificate should quote the given server name
+        //   (if provided)
+        //
+        // These checks are not security-sensitive.  They are the
+        // *server* attempting to detect accidental misconfiguration.
+        // For a wildcard name, we check one name it covers.
+
+        let example_name = match wildcard {
+            true => DnsName::try_from(["wildcard.", checked_name.as_ref()].concat())
+                .map_err(|_| Error::General("Bad DNS name".into()))?,
+            false => checked_name.clone(),
+        };
+
+        ck.end_entity_cert()
+            .and_then(ParsedCertificate::try_from)
+            .and_then(|cert| verify_server_name(&cert, &ServerName::DnsName(example_name)))?;
+
+        Ok((wildcard, checked_name.as_ref().to_string(), ck))
+    }
+
+    #[derive(Debug, Default)]
+    struct SniState {
+        by_name: HashMap<String, Vec<Arc<sign::CertifiedKey>>>,
+        /// Keyed by the name without the leading `*.`
+        by_wildcard: HashMap<String, Vec<Arc<sign::CertifiedKey>>>,
+        default: Vec<Arc<sign::CertifiedKey>>,
+    }
+
+    impl SniState {
+        fn lookup(&self, name: &str) -> Option<&Vec<Arc<sign::CertifiedKey>>> {
+            self.by_name.get(name).or_else(|| {
+                let (_, parent) = name.split_once('.')?;
+                self.by_wildcard.get(parent)
+            })
+        }
+
+        fn add(&mut self, wildcard: bool, name: String, ck: sign::CertifiedKey) {
+            let by_name = match wildcard {
+                true => &mut self.by_wildcard,
+                false => &mut self.by_name,
+            };
+            insert_key(by_name.entry(name).or_default(), ck);
+        }
+
+        fn remove(&mut self, name: &str) -> bool {
+            let (wildcard, name) = match name.strip_prefix("*.") {
+                Some(suffix) => (true, suffix),
+                None => (false, name),
+            };
+            let name = name.to_ascii_lowercase();
+
+            let by_name = match wildcard {
+                true => &mut self.by_wildcard,
+                false => &mut self.by_name,
+            };
+            by_name.remove(&name).is_some()
+        }
+
+        fn remove_default(&mut self) -> bool {
+            let removed = !self.default.is_empty();
+            self.default.clear();
+            removed
+        }
+    }
+
+    /// Add `ck` to `keys`, replacing any with the same key algorithm.
+    fn insert_key(keys: &mut Vec<Arc<sign::CertifiedKey>>, ck: sign::CertifiedKey) {
+        let ck = Arc::new(ck);
+        match keys
+            .iter_mut()
+            .find(|existing| existing.key.algorithm() == ck.key.algorithm())
+        {
+            Some(existing) => *existing = ck,
+            None => keys.push(ck),
+        }
+    }
+
+    /// Choose the first of `keys` usable with `schemes`.
+    ///
+    /// If none are, the first is returned anyway, so the handshake fails in
+    /// the usual way.
+    fn choose_key(
+        keys: &[Arc<sign::CertifiedKey>],
+        schemes: &[SignatureScheme],
+    ) -> Option<Arc<sign::CertifiedKey>> {
+        keys.iter()
+            .find(|ck| ck.key.choose_scheme(schemes).is_some())
+            .or_else(|| keys.first())
+            .cloned()
+    }
+
     #[cfg(test)]
     mod tests {
         use super::*;
+        use crate::enums::ProtocolVersion;
         use crate::server::ResolvesServerCert;
 
         #[test]
@@ -314,6 +659,15 @@ mod sni_resolver {
                     client_cert_types: None,
                     cipher_suites: &[],
                     certificate_authorities: None,
+                    client_version: ProtocolVersion::TLSv1_2,
+                    extensions: &[],
+                    named_groups: None,
+                    key_shares: None,
+                    supported_versions: None,
+                    psk_modes: None,
+                    ec_point_formats: None,
+                    compression_methods: &[],
+                    encoded: &[],
                 })
                 .is_none());
         }
@@ -333,6 +687,15 @@ mod sni_resolver {
                     client_cert_types: None,
                     cipher_suites: &[],
                     certificate_authorities: None,
+                    client_version: ProtocolVersion::TLSv1_2,
+                    extensions: &[],
+                    named_groups: None,
+                    key_shares: None,
+                    supported_versions: None,
+                    psk_modes: None,
+                    ec_point_formats: None,
+                    compression_methods: &[],
+                    encoded: &[],
                 })
                 .is_none());
         }
@@ -342,6 +705,212 @@ mod sni_resolver {
 #[cfg(any(feature = "std", feature = "hashbrown"))]
 pub use sni_resolver::ResolvesServerCertUsingSni;
 
+#[cfg(feature = "std")]
+mod file_resolver {
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use core::fmt::{Debug, Formatter};
+    use std::path::{Path, PathBuf};
+    use std::sync::RwLock;
+    use std::{format, fs};
+
+    use pki_types::pem::PemObject;
+    use pki_types::{CertificateDer, PrivateKeyDer};
+    use zeroize::Zeroizing;
+
+    use crate::crypto::{CryptoProvider, KeyProvider};
+    use crate::error::{Error, InconsistentKeys};
+    use crate::log::{debug, warn};
+    use crate::server::ClientHello;
+    use crate::{server, sign};
+
+    /// Something that resolves to a cert chain/key loaded from PEM files,
+    /// and reloads them when they change.
+    ///
+    /// The files are checked when the application calls [`Self::reload()`], for
+    /// example on a timer or a signal.  If the new files cannot be loaded (for
+    /// example, because they are being rewritten, or the certificate does not
+    /// match the key), the previously-loaded cert chain/key continues to be used.
+    pub struct ResolvesServerCertFromFiles {
+        cert_path: PathBuf,
+        key_path: PathBuf,
+        key_provider: &'static dyn KeyProvider,
+        reporter: Option<Arc<dyn ReportsCertReloads>>,
+        state: RwLock<LoadedFiles>,
+    }
+
+    impl ResolvesServerCertFromFiles {
+        /// Load a cert chain from `cert_path` and a private key from `key_path`,
+        /// using `provider` to load the key.
+        ///
+        /// The cert chain file must contain one or more PEM `CERTIFICATE`
+        /// sections, starting with the end-entity certificate.  The key file
+        /// must contain a PEM private key.
+        ///
+        /// This function fails if either file cannot be read or parsed, or if
+        /// the certificate does not match the key.
+        pub fn new(
+            cert_path: impl Into<PathBuf>,
+            key_path: impl Into<PathBuf>,
+            provider: &CryptoProvider,
+        ) -> Result<Self, Error> {
+            let cert_path = cert_path.into();
+            let key_path = key_path.into();
+            let key_provider = provider.key_provider;
+            let files = read_files(&cert_path, &key_path)?;
+            let certified_key = files.load(key_provider)?;
+
+            Ok(Self {
+                cert_path,
+                key_path,
+                key_provider,
+                reporter: None,
+                state: RwLock::new(LoadedFiles {
+                    files,
+                    certified_key,
+                }),
+            })
+        }
+
+        /// Report the outcome of each reload to `reporter`.
+        pub fn with_reporter(mut self, reporter: Arc<dyn ReportsCertReloads>) -> Self {
+            self.reporter = Some(reporter);
+            self
+        }
+
+        /// Check the files, and load them if they have changed.
+        ///
+        /// The outcome is also given to any reporter set with
+        /// [`Self::with_reporter()`].
+        pub fn reload(&self) -> ReloadOutcome {
+            let outcome = self.try_reload();
+            match &outcome {
+                ReloadOutcome::Unchanged => {}
+                ReloadOutcome::Reloaded => debug!("reloaded certificate from {:?}", self.cert_path),
+                ReloadOutcome::Failed(_err) => {
+                    warn!(
+                        "failed to reload certificate from {:?}: {_err}",
+                        self.cert_path
+                    )
+                }
+            }
+            if let Some(reporter) = &self.reporter {
+                reporter.report(&outcome);
+            }
+            outcome
+        }
+
+        fn try_reload(&self) -> ReloadOutcome {
+            let files = match read_files(&self.cert_path, &self.key_path) {
+                Ok(files) => files,
+                Err(err) => return ReloadOutcome::Failed(err),
+            };
+
+            if self.state.read().unwrap().files == files {
+                return ReloadOutcome::Unchanged;
+            }
+
+            let loaded = files.load(self.key_provider);
+            let mut state = self.state.write().unwrap();
+            // Don't report a failure again until the files change.
+            state.files = files;
+            match loaded {
+                Ok(certified_key) => {
+                    state.certified_key = certified_key;
+                    ReloadOutcome::Reloaded
+                }
+                Err(err) => ReloadOutcome::Failed(err),
+            }
+        }
+    }
+
+    impl server::ResolvesServerCert for ResolvesServerCertFromFiles {
+        fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<sign::CertifiedKey>> {
+            Some(Arc::clone(&self.state.read().unwrap().certified_key))
+        }
+    }
+
+    impl Debug for ResolvesServerCertFromFiles {
+        fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+            f.debug_struct("ResolvesServerCertFromFiles")
+                .field("cert_path", &self.cert_path)
+                .field("key_path", &self.key_path)
+                .finish_non_exhaustive()
+        }
+    }
+
+    /// The outcome of [`ResolvesServerCertFromFiles::reload()`].
+    #[non_exhaustive]
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum ReloadOutcome {
+        /// The files have not changed since they were last checked.
+        Unchanged,
+        /// The files changed, and the new cert chain/key are now in use.
+        Reloaded,
+        /// The files could not be loaded, so the previous cert chain/key are
+        /// still in use.
+        Failed(Error),
+    }
+
+    /// This trait represents the ability to be told the outcome of each
+    /// reload of a [`ResolvesServerCertFromFiles`], for example to alert
+    /// when a new certificate cannot be loaded.
+    pub trait ReportsCertReloads: Debug + Send + Sync {
+        /// Called after each reload, with its `outcome`.
+        fn report(&self, outcome: &ReloadOutcome);
+    }
+
+    /// The contents of the files, kept for detecting changes.
+    #[derive(PartialEq)]
+    struct FileContents {
+        cert: Vec<u8>,
+        key: Zeroizing<Vec<u8>>,
+    }
+
+    impl FileContents {
+        fn load(&self, key_provider: &dyn KeyProvider) -> Result<Arc<sign::CertifiedKey>, Error> {
+            let cert_chain = CertificateDer::pem_slice_iter(&self.cert)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| Error::General(format!("cannot parse certificate: {err:?}")))?;
+            if cert_chain.is_empty() {
+                return Err(Error::NoCertificatesPresented);
+            }
+
+            let key = PrivateKeyDer::from_pem_slice(&self.key)
+                .map_err(|err| Error::General(format!("cannot parse private key: {err:?}")))?;
+
+            let certified_key =
+                sign::CertifiedKey::new(cert_chain, key_provider.load_private_key(key)?);
+            match certified_key.keys_match() {
+                // Don't treat unknown consistency as an error
+                Ok(()) | Err(Error::InconsistentKeys(InconsistentKeys::Unknown)) => (),
+                Err(err) => return Err(err),
+            }
+
+            Ok(Arc::new(certified_key))
+        }
+    }
+
+    fn read_files(cert_path: &Path, key_path: &Path) -> Result<FileContents, Error> {
+        let read = |path: &Path| {
+            fs::read(path).map_err(|err| Error::General(format!("cannot read {path:?}: {err}")))
+        };
+        Ok(FileContents {
+            cert: read(cert_path)?,
+            key: Zeroizing::new(read(key_path)?),
+        })
+    }
+
+    struct LoadedFiles {
+        /// The contents most recently read, even if they could not be loaded.
+        files: FileContents,
+        certified_key: Arc<sign::CertifiedKey>,
+    }
+}
+
+#[cfg(feature = "std")]
+pub use file_resolver::{ReloadOutcome, ReportsCertReloads, ResolvesServerCertFromFiles};
+
 #[cfg(test)]
 mod tests {
     use std::vec;
@@ -5,38 +5,51 @@ use alloc::vec::Vec;
 
 use pki_types::DnsName;
 
+use super::ech::EchState;
 use super::server_conn::ServerConnectionData;
 #[cfg(feature = "tls12")]
 use super::tls12;
+use crate::check::inappropriate_message;
 use crate::common_state::{
-    KxState, Protocol, RawKeyNegotationResult, RawKeyNegotiationParams, State,
+    CommonState, KxState, Protocol, RawKeyNegotationResult, RawKeyNegotiationParams, State,
 };
 use crate::conn::ConnectionRandoms;
+use crate::crypto::hash::HashAlgorithm;
 use crate::crypto::SupportedKxGroup;
+use crate::custom_extension::CustomExtension;
 use crate::enums::{
-    AlertDescription, CipherSuite, HandshakeType, ProtocolVersion, SignatureAlgorithm,
+    AlertDescription, CipherSuite, ContentType, HandshakeType, ProtocolVersion, SignatureAlgorithm,
     SignatureScheme,
 };
 use crate::error::{Error, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::{HandshakeHash, HandshakeHashBuffer};
 use crate::log::{debug, trace};
 use crate::msgs::enums::{CertificateType, Compression, ExtensionType, NamedGroup};
+use crate::msgs::fragmenter::MAX_RECORD_SIZE_LIMIT;
 #[cfg(feature = "tls12")]
 use crate::msgs::handshake::SessionId;
 use crate::msgs::handshake::{
     ClientHelloPayload, ConvertProtocolNameList, ConvertServerNameList, HandshakePayload,
-    KeyExchangeAlgorithm, Random, ServerExtension,
+    KeyExchangeAlgorithm, Random, ServerExtension, UnknownExtension,
 };
-use crate::msgs::message::{Message, MessagePayload};
+use crate::msgs::message::{Message, MessagePayload, PlainMessage};
 use crate::msgs::persist;
 use crate::server::common::ActiveCertifiedKey;
-use crate::server::{tls13, ClientHello, ServerConfig};
+use crate::server::{tls13, ClientHello, OperationResult, PendingOperation, ServerConfig};
+use crate::sign::CertifiedKey;
 use crate::{suites, SupportedCipherSuite};
 
 pub(super) type NextState<'a> = Box<dyn State<ServerConnectionData> + 'a>;
 pub(super) type NextStateOrError<'a> = Result<NextState<'a>, Error>;
 pub(super) type ServerContext<'a> = crate::common_state::Context<'a, ServerConnectionData>;
 
+pub(super) fn no_certificate_resolved(common: &mut CommonState) -> Error {
+    common.send_fatal_alert(
+        AlertDescription::AccessDenied,
+        Error::General("no server certificate chain resolved".to_owned()),
+    )
+}
+
 pub(super) fn can_resume(
     suite: SupportedCipherSuite,
     sni: &Option<DnsName<'_>>,
@@ -61,6 +74,9 @@ pub(super) struct ExtensionProcessing {
     pub(super) exts: Vec<ServerExtension>,
     #[cfg(feature = "tls12")]
     pub(super) send_ticket: bool,
+    // the `record_size_limit` we advertised, to be enforced once
+    // the client is sending records under the keys it applies to
+    pub(super) record_size_limit: Option<u16>,
 }
 
 impl ExtensionProcessing {
@@ -73,6 +89,7 @@ impl ExtensionProcessing {
         config: &ServerConfig,
         cx: &mut ServerContext<'_>,
         ocsp_response: &mut Option<&[u8]>,
+        sct_list: &mut Option<&[u8]>,
         hello: &ClientHelloPayload,
         resumedata: Option<&persist::ServerSessionValue>,
         extra_exts: Vec<ServerExtension>,
@@ -158,6 +175,48 @@ impl ExtensionProcessing {
             ocsp_response.take();
         }
 
+        // Likewise for the signed certificate timestamp list.  In TLS1.2 it is
+        // sent in the ServerHello; in TLS1.3 with the end-entity certificate.
+        if !for_resume
+            && hello
+                .find_extension(ExtensionType::SCT)
+                .is_some()
+        {
+            #[cfg(feature = "tls12")]
+            if !cx.common.is_tls13() {
+                if let Some(ext) = sct_list
+                    .take()
+                    .and_then(ServerExtension::make_sct)
+                {
+                    self.exts.push(ext);
+                }
+            }
+        } else {
+            // Throw away any SCT list so we don't try to send it later.
+            sct_list.take();
+        }
+
+        // QUIC does not use TLS records, so there is no record size to limit.
+        // Nor do we limit DTLS records: they are sized to fit the datagram.
+        if let (Some(limit), false) = (
+            hello.record_size_limit(),
+            cx.common.is_quic() || cx.common.is_dtls(),
+        ) {
+            cx.common
+                .set_peer_record_size_limit(limit)?;
+
+            let ours = match cx.common.is_tls13() {
+                true => MAX_RECORD_SIZE_LIMIT,
+                false => MAX_RECORD_SIZE_LIMIT - 1,
+            };
+            let ours = config
+                .record_size_limit
+                .map_or(ours, |limit| limit.min(ours));
+            self.exts
+                .push(ServerExtension::RecordSizeLimit(ours));
+            self.record_size_limit = Some(ours);
+        }
+
         self.validate_server_cert_type_extension(hello, config, cx)?;
         self.validate_client_cert_type_extension(hello, config, cx)?;
 
@@ -300,13 +359,14 @@ pub(super) struct ExpectClientHello {
     pub(super) using_ems: bool,
     pub(super) done_retry: bool,
     pub(super) send_tickets: usize,
+    pub(super) ech: EchState,
 }
 
 impl ExpectClientHello {
     pub(super) fn new(config: Arc<ServerConfig>, extra_exts: Vec<ServerExtension>) -> Self {
         let mut transcript_buffer = HandshakeHashBuffer::new();
 
-        if config.verifier.offer_client_auth() {
+        if config.verifier.offer_client_auth() && !config.verifier.defers_client_auth() {
             transcript_buffer.set_client_auth_enabled();
         }
 
@@ -320,9 +380,39 @@ impl ExpectClientHello {
             using_ems: false,
             done_retry: false,
             send_tickets: 0,
+            ech: EchState::NotOffered,
         }
     }
 
+    /// Continues handling of a `ClientHello` message already processed by an [`Acceptor`].
+    ///
+    /// If the message offers ECH and this can be accepted, the handshake continues using
+    /// the inner hello.  `cx.data.sni` is then replaced by its server name.
+    ///
+    /// [`Acceptor`]: crate::server::Acceptor
+    pub(super) fn with_accepted_client_hello(
+        mut self,
+        sig_schemes: Vec<SignatureScheme>,
+        m: &Message<'_>,
+        cx: &mut ServerContext<'_>,
+    ) -> NextStateOrError<'static> {
+        let Some(inner) =
+            self.ech
+                .process_client_hello(&self.config, self.done_retry, m, cx.common)?
+        else {
+            let client_hello = require_handshake_msg!(
+                m,
+                HandshakeType::ClientHello,
+                HandshakePayload::ClientHello
+            )?;
+            return self.with_certified_key(sig_schemes, client_hello, m, cx);
+        };
+
+        cx.data.sni = None;
+        let (client_hello, sig_schemes) = process_client_hello(&inner, self.done_retry, cx)?;
+        self.with_certified_key(sig_schemes, client_hello, &inner, cx)
+    }
+
     /// Continues handling of a `ClientHello` message once config and certificate are available.
     pub(super) fn with_certified_key(
         self,
@@ -340,7 +430,22 @@ impl ExpectClientHello {
 
         // Are we doing TLS1.3?
         let maybe_versions_ext = client_hello.versions_extension();
-        let version = if let Some(versions) = maybe_versions_ext {
+        let version = if cx.common.is_dtls() {
+            // We do not support DTLS 1.2, so there is nothing to fall back to.
+            match maybe_versions_ext {
+                Some(versions)
+                    if versions.contains(&ProtocolVersion::DTLSv1_3) && tls13_enabled =>
+                {
+                    ProtocolVersion::TLSv1_3
+                }
+                _ => {
+                    return Err(cx.common.send_fatal_alert(
+                        AlertDescription::ProtocolVersion,
+                        PeerIncompatible::Dtls13NotOffered,
+                    ));
+                }
+            }
+        } else if let Some(versions) = maybe_versions_ext {
             if versions.contains(&ProtocolVersion::TLSv1_3) && tls13_enabled {
                 ProtocolVersion::TLSv1_3
             } else if !versions.contains(&ProtocolVersion::TLSv1_2) || !tls12_enabled {
@@ -398,47 +503,75 @@ impl ExpectClientHello {
         sig_schemes
             .retain(|scheme| suites::compatible_sigscheme_for_suites(*scheme, &client_suites));
 
-        // We adhere to the TLS 1.2 RFC by not exposing this to the cert resolver if TLS version is 1.2
-        let certificate_authorities = match version {
-            ProtocolVersion::TLSv1_2 => None,
-            _ => client_hello.certificate_authorities_extension(),
-        };
+        if self
+            .config
+            .cert_resolver
+            .defers_resolution()
+        {
+            trace!("Deferring server certificate resolution");
+            return Ok(Box::new(ExpectCertificateResolution {
+                message: owned_client_hello(m)?,
+                sig_schemes,
+                version,
+                sni: cx.data.sni.clone(),
+                hello: self,
+            }));
+        }
+
         // Choose a certificate.
         let certkey = {
-            let client_hello = ClientHello {
-                server_name: &cx.data.sni,
-                signature_schemes: &sig_schemes,
-                alpn: client_hello.alpn_extension(),
-                client_cert_types: client_hello.server_certificate_extension(),
-                server_cert_types: client_hello.client_certificate_extension(),
-                cipher_suites: &client_hello.cipher_suites,
-                certificate_authorities,
-            };
+            let client_hello = client_hello_for_resolver(&cx.data.sni, &sig_schemes, m, version);
             trace!("Resolving server certificate: {client_hello:#?}");
 
-            let certkey = self
-                .config
+            self.config
                 .cert_resolver
-                .resolve(client_hello);
+                .resolve(client_hello)
+        };
 
-            certkey.ok_or_else(|| {
-                cx.common.send_fatal_alert(
-                    AlertDescription::AccessDenied,
-                    Error::General("no server certificate chain resolved".to_owned()),
-                )
-            })?
+        self.with_resolved_certificate(certkey, sig_schemes, version, client_hello, m, cx)
+    }
+
+    /// Continues handling of a `ClientHello` message once its certificate is resolved.
+    fn with_resolved_certificate(
+        self,
+        certkey: Option<Arc<CertifiedKey>>,
+        sig_schemes: Vec<SignatureScheme>,
+        version: ProtocolVersion,
+        client_hello: &ClientHelloPayload,
+        m: &Message<'_>,
+        cx: &mut ServerContext<'_>,
+    ) -> NextStateOrError<'static> {
+        // A TLS1.3 handshake can go ahead without a certificate if the client
+        // might authenticate with an external PSK instead.
+        let psk_possible = version == ProtocolVersion::TLSv1_3
+            && self.config.psk_resolver.is_some()
+            && client_hello.psk().is_some();
+        if certkey.is_none() && !psk_possible {
+            return Err(no_certificate_resolved(cx.common));
+        }
+        let certkey = certkey
+            .as_deref()
+            .map(ActiveCertifiedKey::from_certified_key);
+
+        // Prefer a cipher suite which can be used with an offered external PSK.
+        let psk_hash = match psk_possible {
+            true => self.external_psk_hash(client_hello),
+            false => None,
         };
-        let certkey = ActiveCertifiedKey::from_certified_key(&certkey);
 
         let (suite, skxg) = self
             .choose_suite_and_kx_group(
                 version,
-                certkey.get_key().algorithm(),
+                certkey
+                    .as_ref()
+                    .map(|certkey| certkey.get_key().algorithm())
+                    .unwrap_or(SignatureAlgorithm::Anonymous),
                 cx.common.protocol,
                 client_hello
                     .namedgroups_extension()
                     .unwrap_or(&[]),
                 &client_hello.cipher_suites,
+                psk_hash,
             )
             .map_err(|incompat| {
                 cx.common
@@ -471,6 +604,11 @@ impl ExpectClientHello {
             client_hello.random,
             Random::new(self.config.provider.secure_random)?,
         );
+        #[cfg(feature = "tls12")]
+        let tls13_enabled = self
+            .config
+            .supports_version(ProtocolVersion::TLSv1_3);
+
         match suite {
             SupportedCipherSuite::Tls13(suite) => tls13::CompleteClientHelloHandling {
                 config: self.config,
@@ -480,31 +618,132 @@ impl ExpectClientHello {
                 done_retry: self.done_retry,
                 send_tickets: self.send_tickets,
                 extra_exts: self.extra_exts,
+                ech: self.ech,
             }
             .handle_client_hello(cx, certkey, m, client_hello, skxg, sig_schemes),
             #[cfg(feature = "tls12")]
-            SupportedCipherSuite::Tls12(suite) => tls12::CompleteClientHelloHandling {
-                config: self.config,
-                transcript,
-                session_id: self.session_id,
-                suite,
-                using_ems: self.using_ems,
-                randoms,
-                send_ticket: self.send_tickets > 0,
-                extra_exts: self.extra_exts,
+            SupportedCipherSuite::Tls12(suite) => {
+                let certkey = certkey.ok_or_else(|| no_certificate_resolved(cx.common))?;
+                tls12::CompleteClientHelloHandling {
+                    config: self.config,
+                    transcript,
+                    session_id: self.session_id,
+                    suite,
+                    using_ems: self.using_ems,
+                    randoms,
+                    send_ticket: self.send_tickets > 0,
+                    extra_exts: self.extra_exts,
+                }
+                .handle_client_hello(
+                    cx,
+                    certkey,
+                    m,
+                    client_hello,
+                    skxg,
+                    sig_schemes,
+                    tls13_enabled,
+                )
             }
-            .handle_client_hello(
-                cx,
-                certkey,
-                m,
-                client_hello,
-                skxg,
-                sig_schemes,
-                tls13_enabled,
-            ),
         }
     }
 
+    /// The hash algorithm of the first external PSK offered in `client_hello` that
+    /// we know, if any.
+    fn external_psk_hash(&self, client_hello: &ClientHelloPayload) -> Option<HashAlgorithm> {
+        let resolver = self.config.psk_resolver.as_ref()?;
+        client_hello
+            .psk()?
+            .identities
+            .iter()
+            .find_map(|id| resolver.resolve(id.identity.0.as_slice()))
+            .map(|psk| psk.hash_algorithm())
+    }
+
+    /// Handles the cookie of a DTLS `ClientHello`, before it is decrypted with
+    /// ECH or a certificate is resolved for it.
+    ///
+    /// A `ClientHello` without a cookie is answered with a stateless
+    /// `HelloRetryRequest` carrying one, and the state for the client's next
+    /// `ClientHello` is returned.  A valid cookie is kept for
+    /// [`tls13::CompleteClientHelloHandling`] to continue the handshake from.
+    ///
+    /// This only applies to DTLS servers made with
+    /// [`DtlsServerConnection::with_cookie()`].  See RFC 9147 section 5.1.
+    ///
+    /// [`DtlsServerConnection::with_cookie()`]: crate::dtls::DtlsServerConnection::with_cookie
+    fn process_dtls_cookie(
+        &mut self,
+        m: &Message<'_>,
+        cx: &mut ServerContext<'_>,
+    ) -> Result<Option<NextState<'static>>, Error> {
+        let Some(cookie_state) = &cx.common.dtls.cookie else {
+            return Ok(None);
+        };
+        let client_hello =
+            require_handshake_msg!(m, HandshakeType::ClientHello, HandshakePayload::ClientHello)?;
+        let now = self.config.current_time()?;
+
+        if let Some(sealed) = client_hello.cookie() {
+            let cookie = self
+                .config
+                .provider
+                .cipher_suites
+                .iter()
+                .filter_map(|suite| suite.tls13())
+                .find_map(|suite| cookie_state.open(&sealed.0, suite, now));
+            let Some(cookie) = cookie else {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::IllegalParameter,
+                    PeerMisbehaved::InvalidCookie,
+                ));
+            };
+
+            if let Some(cookie_state) = &mut cx.common.dtls.cookie {
+                cookie_state.accepted = Some((cookie, sealed.0.clone()));
+            }
+            return Ok(None);
+        }
+
+        if !client_hello
+            .versions_extension()
+            .is_some_and(|versions| versions.contains(&ProtocolVersion::DTLSv1_3))
+        {
+            return Err(cx.common.send_fatal_alert(
+                AlertDescription::ProtocolVersion,
+                PeerIncompatible::Dtls13NotOffered,
+            ));
+        }
+
+        let (suite, skxg) = self
+            .choose_suite_and_kx_group(
+                ProtocolVersion::TLSv1_3,
+                SignatureAlgorithm::Anonymous,
+                cx.common.protocol,
+                client_hello
+                    .namedgroups_extension()
+                    .unwrap_or(&[]),
+                &client_hello.cipher_suites,
+                self.external_psk_hash(client_hello),
+            )
+            .map_err(|incompat| {
+                cx.common
+                    .send_fatal_alert(AlertDescription::HandshakeFailure, incompat)
+            })?;
+        let Some(suite) = suite.tls13() else {
+            return Err(Error::General("chose a TLS1.2 suite for DTLS".into()));
+        };
+
+        debug!("sending stateless HelloRetryRequest for suite {:?}", suite);
+        tls13::emit_stateless_hello_retry_request(suite, skxg, m, client_hello, now, cx.common);
+
+        // The connection discards this state, and handles the second
+        // `ClientHello` as a new connection.
+        Ok(Some(Box::new(Self::new(
+            Arc::clone(&self.config),
+            core::mem::take(&mut self.extra_exts),
+        ))))
+    }
+
     fn choose_suite_and_kx_group(
         &self,
         selected_version: ProtocolVersion,
@@ -512,6 +751,7 @@ impl ExpectClientHello {
         protocol: Protocol,
         client_groups: &[NamedGroup],
         client_suites: &[CipherSuite],
+        psk_hash: Option<HashAlgorithm>,
     ) -> Result<(SupportedCipherSuite, &'static dyn SupportedKxGroup), PeerIncompatible> {
         // Determine which `KeyExchangeAlgorithm`s are theoretically possible, based
         // on the offered and supported groups.
@@ -562,7 +802,7 @@ impl ExpectClientHello {
             return Err(PeerIncompatible::NoKxGroupsInCommon);
         }
 
-        let mut suitable_suites_iter = self
+        let suitable_suites = self
             .config
             .provider
             .cipher_suites
@@ -577,7 +817,8 @@ impl ExpectClientHello {
                 // And support one of key exchange groups
                 && (ecdhe_possible && suite.usable_for_kx_algorithm(KeyExchangeAlgorithm::ECDHE)
                 || ffdhe_possible && suite.usable_for_kx_algorithm(KeyExchangeAlgorithm::DHE))
-            });
+            })
+            .collect::<Vec<_>>();
 
         // RFC 7919 (https://datatracker.ietf.org/doc/html/rfc7919#section-4) requires us to send
         // the InsufficientSecurity alert in case we don't recognize client's FFDHE groups (i.e.,
@@ -585,20 +826,31 @@ impl ExpectClientHello {
         // proposes FFDHE4096 and we only support FFDHE2048), so we ignore that requirement here,
         // and continue to send HandshakeFailure.
 
-        let suite = if self.config.ignore_client_order {
-            suitable_suites_iter.find(|suite| client_suites.contains(&suite.suite()))
-        } else {
-            let suitable_suites = suitable_suites_iter.collect::<Vec<_>>();
-            client_suites
+        // Choose from `suitable_suites` those usable with `hash`, if given.
+        let choose = |hash: Option<HashAlgorithm>| {
+            let mut candidates = suitable_suites
                 .iter()
-                .find_map(|client_suite| {
-                    suitable_suites
-                        .iter()
-                        .find(|x| *client_suite == x.suite())
-                })
-                .copied()
-        }
-        .ok_or(PeerIncompatible::NoCipherSuitesInCommon)?;
+                .filter(|suite| hash.map_or(true, |h| suite.hash_provider().algorithm() == h));
+            if self.config.ignore_client_order {
+                candidates.find(|suite| client_suites.contains(&suite.suite()))
+            } else {
+                let candidates = candidates.collect::<Vec<_>>();
+                client_suites
+                    .iter()
+                    .find_map(|client_suite| {
+                        candidates
+                            .iter()
+                            .find(|x| *client_suite == x.suite())
+                    })
+                    .copied()
+            }
+        };
+
+        let suite = psk_hash
+            .and_then(|hash| choose(Some(hash)))
+            .or_else(|| choose(None))
+            .copied()
+            .ok_or(PeerIncompatible::NoCipherSuitesInCommon)?;
 
         // Finally, choose a key exchange group that is compatible with the selected cipher
         // suite.
@@ -636,13 +888,25 @@ impl ExpectClientHello {
 
 impl State<ServerConnectionData> for ExpectClientHello {
     fn handle<'m>(
-        self: Box<Self>,
+        mut self: Box<Self>,
         cx: &mut ServerContext<'_>,
         m: Message<'m>,
     ) -> NextStateOrError<'m>
     where
         Self: 'm,
     {
+        if let Some(next) = self.process_dtls_cookie(&m, cx)? {
+            return Ok(next);
+        }
+
+        let m = match self
+            .ech
+            .process_client_hello(&self.config, self.done_retry, &m, cx.common)?
+        {
+            Some(inner) => inner,
+            None => m,
+        };
+
         let (client_hello, sig_schemes) = process_client_hello(&m, self.done_retry, cx)?;
         self.with_certified_key(sig_schemes, client_hello, &m, cx)
     }
@@ -652,6 +916,163 @@ impl State<ServerConnectionData> for ExpectClientHello {
     }
 }
 
+/// Waiting for the application to resolve the certificate for a `ClientHello`.
+///
+/// See [`ResolvesServerCert::defers_resolution()`].
+///
+/// [`ResolvesServerCert::defers_resolution()`]: crate::server::ResolvesServerCert::defers_resolution
+struct ExpectCertificateResolution {
+    hello: ExpectClientHello,
+    message: Message<'static>,
+    sig_schemes: Vec<SignatureScheme>,
+    version: ProtocolVersion,
+    sni: Option<DnsName<'static>>,
+}
+
+impl State<ServerConnectionData> for ExpectCertificateResolution {
+    fn handle<'m>(
+        self: Box<Self>,
+        _cx: &mut ServerContext<'_>,
+        m: Message<'m>,
+    ) -> NextStateOrError<'m>
+    where
+        Self: 'm,
+    {
+        Err(inappropriate_message(&m.payload, &[]))
+    }
+
+    fn pending_operation(&self) -> Option<PendingOperation<'_>> {
+        Some(PendingOperation::ResolveCertificate(
+            client_hello_for_resolver(&self.sni, &self.sig_schemes, &self.message, self.version),
+        ))
+    }
+
+    fn complete_operation(
+        self: Box<Self>,
+        cx: &mut ServerContext<'_>,
+        result: OperationResult,
+    ) -> NextStateOrError<'static> {
+        let OperationResult::Certificate(certkey) = result else {
+            return Err(Error::General("unexpected operation result".into()));
+        };
+
+        let Self {
+            hello,
+            message,
+            sig_schemes,
+            version,
+            ..
+        } = *self;
+        hello.with_resolved_certificate(
+            certkey,
+            sig_schemes,
+            version,
+            client_hello_payload(&message),
+            &message,
+            cx,
+        )
+    }
+
+    fn into_owned(self: Box<Self>) -> NextState<'static> {
+        self
+    }
+}
+
+fn client_hello_for_resolver<'a>(
+    sni: &'a Option<DnsName<'static>>,
+    sig_schemes: &'a [SignatureScheme],
+    message: &'a Message<'_>,
+    version: ProtocolVersion,
+) -> ClientHello<'a> {
+    let client_hello = client_hello_payload(message);
+    ClientHello {
+        client_cert_types: client_hello.server_certificate_extension(),
+        server_cert_types: client_hello.client_certificate_extension(),
+        // We adhere to the TLS 1.2 RFC by not exposing this to the cert resolver if TLS version is 1.2
+        certificate_authorities: match version {
+            ProtocolVersion::TLSv1_2 => None,
+            _ => client_hello.certificate_authorities_extension(),
+        },
+        ..ClientHello::new(sni, sig_schemes, message)
+    }
+}
+
+/// The server's replies to the custom extensions offered in a ClientHello.
+#[derive(Default)]
+pub(super) struct CustomExtensionReplies {
+    /// For the ServerHello in TLS1.2, or the EncryptedExtensions in TLS1.3.
+    pub(super) hello: Vec<ServerExtension>,
+    /// For the end-entity certificate's entry, in TLS1.3.
+    pub(super) certificate: Vec<UnknownExtension>,
+}
+
+impl CustomExtensionReplies {
+    /// Ask `config.custom_extensions` for its replies to the ClientHello in `m`.
+    ///
+    /// Replies to extensions the client did not offer are dropped, as the server must
+    /// not send those.
+    pub(super) fn new(
+        config: &ServerConfig,
+        sni: &Option<DnsName<'static>>,
+        sig_schemes: &[SignatureScheme],
+        m: &Message<'_>,
+    ) -> Self {
+        let Some(handler) = &config.custom_extensions else {
+            return Self::default();
+        };
+
+        let client_hello = ClientHello::new(sni, sig_schemes, m);
+        let solicited = |exts: Vec<CustomExtension>| {
+            exts.into_iter()
+                .filter(|ext| {
+                    let offered = client_hello
+                        .custom_extension(ext.typ())
+                        .is_some();
+                    if !offered {
+                        debug!(
+                            "Dropping reply to unoffered custom extension {:?}",
+                            ext.typ()
+                        );
+                    }
+                    offered
+                })
+                .map(|ext| ext.to_unknown_extension())
+                .collect::<Vec<_>>()
+        };
+
+        Self {
+            hello: solicited(handler.respond(&client_hello))
+                .into_iter()
+                .map(ServerExtension::Unknown)
+                .collect(),
+            certificate: solicited(handler.respond_in_certificate(&client_hello)),
+        }
+    }
+}
+
+/// Makes an owned copy of a `ClientHello` message, so it can outlive the received data.
+fn owned_client_hello(m: &Message<'_>) -> Result<Message<'static>, Error> {
+    let MessagePayload::Handshake { encoded, .. } = &m.payload else {
+        unreachable!();
+    };
+
+    Message::try_from(PlainMessage {
+        typ: ContentType::Handshake,
+        version: m.version,
+        payload: encoded.clone().into_owned(),
+    })
+}
+
+pub(super) fn client_hello_payload<'a>(message: &'a Message<'_>) -> &'a ClientHelloPayload {
+    match &message.payload {
+        MessagePayload::Handshake { parsed, .. } => match &parsed.payload {
+            HandshakePayload::ClientHello(ch) => ch,
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    }
+}
+
 /// Configuration-independent validation of a `ClientHello` message.
 ///
 /// This represents the first part of the `ClientHello` handling, where we do all validation that
@@ -687,6 +1108,20 @@ pub(super) fn process_client_hello<'m>(
         ));
     }
 
+    // "A DTLS 1.3-only server MUST abort the handshake with an
+    //  illegal_parameter alert if the legacy_cookie field is non-empty"
+    //  - RFC 9147 section 5.3
+    if client_hello
+        .legacy_cookie
+        .as_ref()
+        .is_some_and(|cookie| !cookie.0.is_empty())
+    {
+        return Err(cx.common.send_fatal_alert(
+            AlertDescription::IllegalParameter,
+            PeerMisbehaved::IllegalClientHelloLegacyCookie,
+        ));
+    }
+
     // No handshake messages should follow this one in this flight.
     cx.common.check_aligned_handshake()?;
 
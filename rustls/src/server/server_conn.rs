@@ -5,30 +5,41 @@ use core::fmt;
 use core::fmt::{Debug, Formatter};
 use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
+use core::time::Duration;
 #[cfg(feature = "std")]
 use std::io;
 
 use pki_types::{DnsName, UnixTime};
 
+use super::ech::EchServerKey;
 use super::hs;
 use crate::builder::ConfigBuilder;
-use crate::common_state::{CommonState, Side};
 #[cfg(feature = "std")]
-use crate::common_state::{Protocol, State};
+use crate::common_state::Protocol;
+use crate::common_state::{CommonState, Context, Side, State};
 use crate::conn::{ConnectionCommon, ConnectionCore, UnbufferedConnectionCommon};
 #[cfg(doc)]
 use crate::crypto;
 use crate::crypto::CryptoProvider;
-use crate::enums::{CipherSuite, ProtocolVersion, SignatureScheme};
+use crate::custom_extension::CustomExtension;
+use crate::enums::{AlertDescription, CipherSuite, ProtocolVersion, SignatureScheme};
 use crate::error::Error;
 use crate::log::trace;
 use crate::msgs::base::Payload;
-use crate::msgs::enums::CertificateType;
-use crate::msgs::handshake::{ClientHelloPayload, ProtocolName, ServerExtension};
-use crate::msgs::message::Message;
+use crate::msgs::deframer::buffers::DeframerSliceBuffer;
+use crate::msgs::enums::{
+    CertificateType, Compression, ECPointFormat, ExtensionType, NamedGroup, PSKKeyExchangeMode,
+};
+use crate::msgs::fragmenter::check_record_size_limit;
+use crate::msgs::handshake::{ClientExtension, KeyShareEntry, ProtocolName, ServerExtension};
+use crate::msgs::message::{Message, MessagePayload};
+use crate::observer::HandshakeObserver;
+use crate::psk::ExternalPsk;
+use crate::record_padding::RecordPadding;
 #[cfg(feature = "std")]
 use crate::time_provider::DefaultTimeProvider;
 use crate::time_provider::TimeProvider;
+use crate::unbuffered::InsufficientSizeError;
 use crate::vecbuf::ChunkVecBuffer;
 #[cfg(feature = "std")]
 use crate::WantsVerifier;
@@ -76,6 +87,37 @@ pub trait StoresServerSessions: Debug + Send + Sync {
     fn can_cache(&self) -> bool;
 }
 
+/// A record of ClientHellos that offered early data, used to detect replays.
+///
+/// This is the "ClientHello recording" defence described in [RFC 8446 section 8.2].
+/// It lets a server accept early data in sessions resumed using stateless tickets,
+/// which are otherwise open to replay.  See [`ServerConfig::anti_replay`].
+///
+/// Each ClientHello is identified by the binder of the PSK it resumes with.
+///
+/// Implementations shared by several servers must record ClientHellos in a store
+/// shared between them, or else each must use different ticket keys.
+///
+/// [RFC 8446 section 8.2]: https://www.rfc-editor.org/rfc/rfc8446#section-8.2
+pub trait RecordsClientHellos: Debug + Send + Sync {
+    /// Record the ClientHello identified by `key`, received at `now`.
+    ///
+    /// Returns `true` if `key` is new, so the early data may be accepted.  Returns
+    /// `false` if `key` was already recorded, or if it could not be recorded (for
+    /// example, because storage is full): the server then rejects the early data, but
+    /// continues with the handshake.
+    ///
+    /// Keys must be remembered for at least twice [`RecordsClientHellos::window()`].
+    fn record(&self, key: &[u8], now: UnixTime) -> bool;
+
+    /// How far the client's view of its ticket's age may differ from the server's.
+    ///
+    /// Early data is only accepted from ClientHellos that were sent within this
+    /// window, according to their `obfuscated_ticket_age`.  Replays of them older
+    /// than the window are therefore rejected without being recorded.
+    fn window(&self) -> Duration;
+}
+
 /// A trait for the ability to encrypt and decrypt tickets.
 pub trait ProducesTickets: Debug + Send + Sync {
     /// Returns true if this implementation will encrypt/decrypt
@@ -107,6 +149,17 @@ pub trait ProducesTickets: Debug + Send + Sync {
     /// panic-proof, and otherwise bullet-proof.  If the decryption
     /// fails, return None.
     fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>>;
+
+    /// Decrypt `cipher` like [`Self::decrypt()`], also returning the
+    /// identifier of the key which decrypted it, if there is one.
+    ///
+    /// This is used for tickets which resume a connection, and the identifier
+    /// is then available from `ServerConnection::resumption_ticket_key_id()`.
+    /// The default implementation returns no identifier.
+    fn decrypt_with_key_id(&self, cipher: &[u8]) -> Option<(Vec<u8>, Option<[u8; 16]>)> {
+        self.decrypt(cipher)
+            .map(|plain| (plain, None))
+    }
 }
 
 /// How to choose a certificate chain and signing key for use
@@ -117,7 +170,8 @@ pub trait ProducesTickets: Debug + Send + Sync {
 ///
 /// For applications that use async I/O and need to do I/O to choose
 /// a certificate (for instance, fetching a certificate from a data store),
-/// the [`Acceptor`] interface is more suitable.
+/// the [`Acceptor`] interface is more suitable.  Alternatively, resolution
+/// can be deferred to the application: see [`ResolvesServerCert::defers_resolution()`].
 pub trait ResolvesServerCert: Debug + Send + Sync {
     /// Choose a certificate chain and matching key given simplified
     /// ClientHello information.
@@ -129,6 +183,55 @@ pub trait ResolvesServerCert: Debug + Send + Sync {
     fn only_raw_public_keys(&self) -> bool {
         false
     }
+
+    /// Return true to defer certificate resolution to the application.
+    ///
+    /// In that case [`ResolvesServerCert::resolve()`] is not called.  Instead the
+    /// handshake pauses with a [`PendingOperation::ResolveCertificate`], and continues
+    /// once the application supplies an [`OperationResult::Certificate`].
+    fn defers_resolution(&self) -> bool {
+        false
+    }
+}
+
+/// A trait for the ability to find an external pre-shared key given
+/// its identity.
+///
+/// See [`ExternalPsk`] for details.
+pub trait ResolvesServerPsk: Debug + Send + Sync {
+    /// Find the external PSK with the given `identity`, offered by a client.
+    ///
+    /// Return `None` if there is no such PSK.  The handshake then continues
+    /// with the client's next offered PSK, if any, or with certificate
+    /// authentication.
+    ///
+    /// This may be called more than once for each identity during a handshake:
+    /// the server also uses the result to prefer a cipher suite compatible
+    /// with the PSK.
+    fn resolve(&self, identity: &[u8]) -> Option<Arc<ExternalPsk>>;
+}
+
+/// Replies to the application-defined extensions in a client's ClientHello.
+///
+/// The client's extensions are available from [`ClientHello::custom_extension()`].
+/// Replies are only sent for extension types the client offered; any others are
+/// dropped.  See [`ServerConfig::custom_extensions`].
+pub trait HandlesCustomExtensions: Debug + Send + Sync {
+    /// Return the custom extensions to send in reply to `client_hello`.
+    ///
+    /// These are sent in the ServerHello in TLS1.2, and in the EncryptedExtensions
+    /// in TLS1.3.
+    fn respond(&self, client_hello: &ClientHello<'_>) -> Vec<CustomExtension>;
+
+    /// Return the custom extensions to send in the entry for the end-entity
+    /// certificate, in reply to `client_hello`.
+    ///
+    /// This is only called for TLS1.3 handshakes which send a certificate.  The
+    /// default implementation returns no extensions.
+    fn respond_in_certificate(&self, client_hello: &ClientHello<'_>) -> Vec<CustomExtension> {
+        let _ = client_hello;
+        Vec::new()
+    }
 }
 
 /// A struct representing the received Client Hello
@@ -144,9 +247,49 @@ pub struct ClientHello<'a> {
     ///
     /// [certificate_authorities]: https://datatracker.ietf.org/doc/html/rfc8446#section-4.2.4
     pub(super) certificate_authorities: Option<&'a [DistinguishedName]>,
+    pub(super) client_version: ProtocolVersion,
+    pub(super) extensions: &'a [ClientExtension],
+    pub(super) named_groups: Option<&'a [NamedGroup]>,
+    pub(super) key_shares: Option<&'a [KeyShareEntry]>,
+    pub(super) supported_versions: Option<&'a [ProtocolVersion]>,
+    pub(super) psk_modes: Option<&'a [PSKKeyExchangeMode]>,
+    pub(super) ec_point_formats: Option<&'a [ECPointFormat]>,
+    pub(super) compression_methods: &'a [Compression],
+    pub(super) encoded: &'a [u8],
 }
 
 impl<'a> ClientHello<'a> {
+    /// Describe the `ClientHello` handshake `message`, exposing every extension as sent.
+    pub(super) fn new(
+        server_name: &'a Option<DnsName<'a>>,
+        signature_schemes: &'a [SignatureScheme],
+        message: &'a Message<'_>,
+    ) -> Self {
+        let MessagePayload::Handshake { encoded, .. } = &message.payload else {
+            unreachable!();
+        };
+        let payload = hs::client_hello_payload(message);
+
+        Self {
+            server_name,
+            signature_schemes,
+            alpn: payload.alpn_extension(),
+            server_cert_types: payload.server_certificate_extension(),
+            client_cert_types: payload.client_certificate_extension(),
+            cipher_suites: &payload.cipher_suites,
+            certificate_authorities: payload.certificate_authorities_extension(),
+            client_version: payload.client_version,
+            extensions: &payload.extensions,
+            named_groups: payload.namedgroups_extension(),
+            key_shares: payload.keyshare_extension(),
+            supported_versions: payload.versions_extension(),
+            psk_modes: payload.psk_modes(),
+            ec_point_formats: payload.ecpoints_extension(),
+            compression_methods: &payload.compression_methods,
+            encoded: encoded.bytes(),
+        }
+    }
+
     /// Get the server name indicator.
     ///
     /// Returns `None` if the client did not supply a SNI.
@@ -215,6 +358,164 @@ impl<'a> ClientHello<'a> {
     pub fn certificate_authorities(&self) -> Option<&'a [DistinguishedName]> {
         self.certificate_authorities
     }
+
+    /// Get the `legacy_version` field of the ClientHello.
+    ///
+    /// This is always TLS1.2 for clients that support TLS1.3; see
+    /// [`ClientHello::supported_versions()`] for the versions actually offered.
+    pub fn client_version(&self) -> ProtocolVersion {
+        self.client_version
+    }
+
+    /// Get the types of the extensions in the ClientHello, in the order the client sent them.
+    ///
+    /// This includes extensions that rustls does not otherwise understand, and any GREASE
+    /// values sent by the client.
+    pub fn extensions(&self) -> impl Iterator<Item = ExtensionType> + 'a {
+        self.extensions
+            .iter()
+            .map(|ext| ext.ext_type())
+    }
+
+    /// Get the [supported_groups] extension sent by the client.
+    ///
+    /// Returns `None` if the client did not send this extension.
+    ///
+    /// [supported_groups]: https://datatracker.ietf.org/doc/html/rfc8446#section-4.2.7
+    pub fn named_groups(&self) -> Option<&'a [NamedGroup]> {
+        self.named_groups
+    }
+
+    /// Get the groups of the key shares in the [key_share] extension sent by the client,
+    /// in the order the client sent them.
+    ///
+    /// Returns `None` if the client did not send this extension.
+    ///
+    /// [key_share]: https://datatracker.ietf.org/doc/html/rfc8446#section-4.2.8
+    pub fn key_share_groups(&self) -> Option<impl Iterator<Item = NamedGroup> + 'a> {
+        self.key_shares
+            .map(|shares| shares.iter().map(|share| share.group))
+    }
+
+    /// Get the [supported_versions] extension sent by the client.
+    ///
+    /// Returns `None` if the client did not send this extension.
+    ///
+    /// [supported_versions]: https://datatracker.ietf.org/doc/html/rfc8446#section-4.2.1
+    pub fn supported_versions(&self) -> Option<&'a [ProtocolVersion]> {
+        self.supported_versions
+    }
+
+    /// Get the [psk_key_exchange_modes] extension sent by the client.
+    ///
+    /// Returns `None` if the client did not send this extension.
+    ///
+    /// [psk_key_exchange_modes]: https://datatracker.ietf.org/doc/html/rfc8446#section-4.2.9
+    pub fn psk_key_exchange_modes(&self) -> Option<&'a [PSKKeyExchangeMode]> {
+        self.psk_modes
+    }
+
+    /// Get the [ec_point_formats] extension sent by the client.
+    ///
+    /// Returns `None` if the client did not send this extension.
+    ///
+    /// [ec_point_formats]: https://datatracker.ietf.org/doc/html/rfc8422#section-5.1.2
+    pub fn ec_point_formats(&self) -> Option<&'a [ECPointFormat]> {
+        self.ec_point_formats
+    }
+
+    /// Get the compression methods offered by the client.
+    pub fn compression_methods(&self) -> &'a [Compression] {
+        self.compression_methods
+    }
+
+    /// Return true if the client sent an `encrypted_client_hello` extension.
+    ///
+    /// When the server accepts ECH, this describes the inner ClientHello, which
+    /// carries an extension of this type too.
+    pub fn encrypted_client_hello_offered(&self) -> bool {
+        self.extensions
+            .iter()
+            .any(|ext| ext.ext_type() == ExtensionType::EncryptedClientHello)
+    }
+
+    /// Get the payload of the extension of type `typ`, if the client sent one that
+    /// rustls does not implement.
+    ///
+    /// This is how the client's [`CustomExtension`]s are received.
+    pub fn custom_extension(&self, typ: ExtensionType) -> Option<&'a [u8]> {
+        self.extensions
+            .iter()
+            .find_map(|ext| match ext {
+                ClientExtension::Unknown(unknown) if unknown.typ == typ => {
+                    Some(unknown.payload.bytes())
+                }
+                _ => None,
+            })
+    }
+
+    /// Get the encoded ClientHello handshake message, including its four-byte header.
+    ///
+    /// This is the message as reassembled from its records, or the decrypted inner
+    /// ClientHello if the server accepted ECH.
+    pub fn encoded(&self) -> &'a [u8] {
+        self.encoded
+    }
+}
+
+/// An operation the server handshake is waiting on the application to complete.
+///
+/// These arise when the configured [`ResolvesServerCert`] or [`sign::Signer`] defers its
+/// work (see [`ResolvesServerCert::defers_resolution()`] and
+/// [`sign::Signer::defers_signing()`]), which lets that work be done asynchronously,
+/// for example by a remote key management service.
+///
+/// The handshake makes no further progress until the operation is completed with
+/// the matching [`OperationResult`].
+#[non_exhaustive]
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)] // `ClientHello` only borrows; boxing it would allocate per call
+pub enum PendingOperation<'a> {
+    /// A certificate chain and signing key must be chosen for this `ClientHello`,
+    /// as [`ResolvesServerCert::resolve()`] would.
+    ///
+    /// Complete this with [`OperationResult::Certificate`].
+    ResolveCertificate(ClientHello<'a>),
+
+    /// `message` must be signed using `scheme`, as [`sign::Signer::sign()`] would.
+    ///
+    /// Complete this with [`OperationResult::Signature`].
+    Sign {
+        /// The signature scheme to use.
+        scheme: SignatureScheme,
+        /// The message to sign.
+        message: &'a [u8],
+    },
+}
+
+/// The outcome of a [`PendingOperation`], as supplied by the application.
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum OperationResult {
+    /// The chosen certificate chain and signing key.
+    ///
+    /// `None` aborts the handshake.
+    Certificate(Option<Arc<sign::CertifiedKey>>),
+
+    /// The signature, or an error which aborts the handshake.
+    Signature(Result<Vec<u8>, Error>),
+}
+
+impl OperationResult {
+    pub(crate) fn completes(&self, operation: &PendingOperation<'_>) -> bool {
+        matches!(
+            (self, operation),
+            (
+                Self::Certificate(_),
+                PendingOperation::ResolveCertificate(_)
+            ) | (Self::Signature(_), PendingOperation::Sign { .. })
+        )
+    }
 }
 
 /// Common configuration for a set of server sessions.
@@ -229,6 +530,9 @@ impl<'a> ClientHello<'a> {
 /// # Defaults
 ///
 /// * [`ServerConfig::max_fragment_size`]: the default is `None` (meaning 16kB).
+/// * [`ServerConfig::record_size_limit`]: the default is `None` -- the protocol maximum is
+///   advertised to clients which support the extension.
+/// * [`ServerConfig::record_padding`]: the default is [`RecordPadding::None`] -- records are not padded.
 /// * [`ServerConfig::session_storage`]: if the `std` feature is enabled, the default stores 256
 ///   sessions in memory. If the `std` feature is not enabled, the default is to not store any
 ///   sessions. In a no-std context, by enabling the `hashbrown` feature you may provide your
@@ -236,10 +540,13 @@ impl<'a> ClientHello<'a> {
 ///   implementation.
 /// * [`ServerConfig::alpn_protocols`]: the default is empty -- no ALPN protocol is negotiated.
 /// * [`ServerConfig::key_log`]: key material is not logged.
+/// * [`ServerConfig::observer`]: the default is `None` -- handshakes are not observed.
 /// * [`ServerConfig::send_tls13_tickets`]: 2 tickets are sent.
 /// * [`ServerConfig::cert_compressors`]: depends on the crate features, see [`compress::default_cert_compressors()`].
 /// * [`ServerConfig::cert_compression_cache`]: caches the most recently used 4 compressions
 /// * [`ServerConfig::cert_decompressors`]: depends on the crate features, see [`compress::default_cert_decompressors()`].
+/// * [`ServerConfig::psk_resolver`]: the default is `None` -- external PSKs are not accepted.
+/// * [`ServerConfig::ech_keys`]: the default is empty -- Encrypted Client Hello is not accepted.
 ///
 /// [`RootCertStore`]: crate::RootCertStore
 /// [`ServerSessionMemoryCache`]: crate::server::handy::ServerSessionMemoryCache
@@ -266,12 +573,57 @@ pub struct ServerConfig {
     /// [ServerConnection::new]: crate::server::ServerConnection::new
     pub max_fragment_size: Option<usize>,
 
+    /// The largest record the client may send us, advertised using the
+    /// [`record_size_limit` extension][RFC 8449].
+    ///
+    /// This is the largest record plaintext we are prepared to receive.  In TLS1.3 it
+    /// includes the content type byte, so the largest payload fragment the client can send
+    /// is one byte less.  The extension is only sent to clients which offer it, and the
+    /// limit only applies to encrypted records sent by the client after the handshake
+    /// has started (not to early data).  Records exceeding the limit are rejected with a
+    /// `record_overflow` alert.
+    ///
+    /// A value of None means the protocol maximum is advertised to clients which support
+    /// the extension, so they learn we support it too.  Values must be between 64 and
+    /// 16385 inclusive: out of range values are reported as errors from [ServerConnection::new].
+    ///
+    /// The extension is not used with QUIC.
+    ///
+    /// [RFC 8449]: https://www.rfc-editor.org/rfc/rfc8449
+    /// [ServerConnection::new]: crate::server::ServerConnection::new
+    pub record_size_limit: Option<u16>,
+
+    /// How to pad the TLS1.3 records we send.
+    ///
+    /// Padding hides the exact length of handshake messages and application data
+    /// from observers of the connection, at the cost of extra bandwidth.  See
+    /// [`RecordPadding`] for the available policies.
+    pub record_padding: RecordPadding,
+
     /// How to store client sessions.
     pub session_storage: Arc<dyn StoresServerSessions>,
 
     /// How to produce tickets.
     pub ticketer: Arc<dyn ProducesTickets>,
 
+    /// How to detect replayed early data, when resuming using tickets from `ticketer`.
+    ///
+    /// Early data in sessions resumed using stateless tickets can be replayed by an
+    /// attacker, so by default it is only accepted with stateful resumption (where
+    /// each session can only be resumed once).  Setting this allows early data with
+    /// stateless tickets too, rejecting that of any ClientHello seen before.
+    ///
+    /// The default is `None`.  See [`RecordsClientHellos`] and [`StrikeRegister`].
+    ///
+    /// [`StrikeRegister`]: crate::server::StrikeRegister
+    pub anti_replay: Option<Arc<dyn RecordsClientHellos>>,
+
+    /// How to reply to application-defined extensions sent by clients.
+    ///
+    /// The default is `None`, meaning custom extensions are ignored.
+    /// See [`HandlesCustomExtensions`].
+    pub custom_extensions: Option<Arc<dyn HandlesCustomExtensions>>,
+
     /// How to choose a server cert and key. This is usually set by
     /// [ConfigBuilder::with_single_cert] or [ConfigBuilder::with_cert_resolver].
     /// For async applications, see also [Acceptor].
@@ -292,16 +644,37 @@ pub struct ServerConfig {
     /// does nothing.
     pub key_log: Arc<dyn KeyLog>,
 
+    /// How to observe the handshakes of connections made with this config,
+    /// for example to collect metrics.  See [`HandshakeObserver`].
+    ///
+    /// The default is `None`.
+    pub observer: Option<Arc<dyn HandshakeObserver>>,
+
     /// Allows traffic secrets to be extracted after the handshake,
     /// e.g. for kTLS setup.
     pub enable_secret_extraction: bool,
 
+    /// Whether to send [GREASE] values in TLS1.3 `CertificateRequest` and
+    /// `NewSessionTicket` messages.
+    ///
+    /// These are reserved extensions and signature schemes, which clients must
+    /// ignore.  Sending them keeps clients and middleboxes tolerant of values they
+    /// do not understand yet.
+    ///
+    /// The default is false.
+    ///
+    /// [GREASE]: https://www.rfc-editor.org/rfc/rfc8701
+    pub enable_grease: bool,
+
     /// Amount of early data to accept for sessions created by
     /// this config.  Specify 0 to disable early data.  The
     /// default is 0.
     ///
     /// Read the early data via [`ServerConnection::early_data`].
     ///
+    /// Early data is only accepted in sessions resumed using `session_storage`,
+    /// unless [`ServerConfig::anti_replay`] is set.
+    ///
     /// The units for this are _both_ plaintext bytes, _and_ ciphertext
     /// bytes, depending on whether the server accepts a client's early_data
     /// or not.  It is therefore recommended to include some slop in
@@ -390,6 +763,34 @@ pub struct ServerConfig {
     ///
     /// [RFC8779]: https://datatracker.ietf.org/doc/rfc8879/
     pub cert_decompressors: Vec<&'static dyn compress::CertDecompressor>,
+
+    /// How to find external pre-shared keys offered by clients in TLS1.3 handshakes.
+    ///
+    /// If the client offers a PSK this finds, neither peer sends a certificate and
+    /// client authentication is not requested.  In that case [`ServerConfig::cert_resolver`]
+    /// may return `None`.  The identity of the PSK used is available from
+    /// [`CommonState::external_psk_identity()`].
+    ///
+    /// The default is `None`.
+    pub psk_resolver: Option<Arc<dyn ResolvesServerPsk>>,
+
+    /// Keys for accepting Encrypted Client Hello (ECH).
+    ///
+    /// If this is non-empty, a TLS1.3 `ClientHello` offering ECH with one of these
+    /// configurations is decrypted, and the handshake continues using the inner
+    /// `ClientHello`: the server name, ALPN protocols and so on seen by the
+    /// certificate resolver are those from the inner hello.
+    ///
+    /// If an offer can't be decrypted, the handshake continues using the outer
+    /// `ClientHello` and the configurations of all these keys are sent to the
+    /// client as retry configurations.  The server must then be able to
+    /// authenticate as the configuration's public name.
+    ///
+    /// When using an [`Acceptor`], note that [`Accepted::client_hello()`] describes
+    /// the outer `ClientHello`: decryption happens in [`Accepted::into_connection()`].
+    ///
+    /// The default is empty.
+    pub ech_keys: Vec<Arc<EchServerKey>>,
 }
 
 impl ServerConfig {
@@ -480,17 +881,22 @@ impl ServerConfig {
     ///
     /// This is different from [`CryptoProvider::fips()`]: [`CryptoProvider::fips()`]
     /// is concerned only with cryptography, whereas this _also_ covers TLS-level
-    /// configuration that NIST recommends.
+    /// configuration that NIST recommends, as well as ECH HPKE suites if applicable.
     pub fn fips(&self) -> bool {
+        let mut is_fips = self.provider.fips();
+
         #[cfg(feature = "tls12")]
         {
-            self.provider.fips() && self.require_ems
+            is_fips = is_fips && self.require_ems
         }
 
-        #[cfg(not(feature = "tls12"))]
-        {
-            self.provider.fips()
-        }
+        is_fips = is_fips
+            && self
+                .ech_keys
+                .iter()
+                .all(|key| key.fips());
+
+        is_fips
     }
 
     /// Return the crypto provider used to construct this client configuration.
@@ -535,13 +941,16 @@ mod connection {
     use core::ops::{Deref, DerefMut};
     use std::io;
 
-    use super::{Accepted, Accepting, EarlyDataState, ServerConfig, ServerConnectionData};
+    use super::{
+        Accepted, AcceptedAlert, Accepting, EarlyDataState, OperationResult, PendingOperation,
+        ServerConfig, ServerConnectionData,
+    };
     use crate::common_state::{CommonState, Context, Side};
+    use crate::conn::kernel::KernelConnection;
     use crate::conn::{ConnectionCommon, ConnectionCore};
     use crate::error::Error;
     use crate::server::hs;
     use crate::suites::ExtractedSecrets;
-    use crate::vecbuf::ChunkVecBuffer;
 
     /// Allows reading of early data in resumed TLS1.3 connections.
     ///
@@ -619,6 +1028,19 @@ mod connection {
                 .map(|x| &x[..])
         }
 
+        /// The identifier of the key which decrypted the client's resumption ticket.
+        ///
+        /// Returns `Some` if the connection was resumed using a ticket, and the
+        /// [`ServerConfig::ticketer`] reports key identifiers, like [`SharedKeyTicketer`] does.
+        ///
+        /// [`SharedKeyTicketer`]: crate::SharedKeyTicketer
+        pub fn resumption_ticket_key_id(&self) -> Option<[u8; 16]> {
+            self.inner
+                .core
+                .data
+                .resumption_ticket_key_id
+        }
+
         /// Set the resumption data to embed in future resumption tickets supplied to the client.
         ///
         /// Defaults to the empty byte string. Must be less than 2^15 bytes to allow room for other
@@ -669,11 +1091,79 @@ mod connection {
             self.inner.core.common_state.fips
         }
 
+        /// Returns the operation the handshake is waiting on the application to complete, if any.
+        ///
+        /// While this returns `Some`, no further received data is processed.  Complete
+        /// the operation with [`ServerConnection::complete_pending_operation()`].
+        pub fn pending_operation(&self) -> Option<PendingOperation<'_>> {
+            self.inner.core.pending_operation()
+        }
+
+        /// Completes the operation returned by [`ServerConnection::pending_operation()`].
+        ///
+        /// This fails if no operation is pending, or if `result` does not match it; in
+        /// these cases the connection is unaffected.  Otherwise errors are fatal to the
+        /// connection, as for [`ConnectionCommon::process_new_packets()`].
+        ///
+        /// Afterwards any handshake messages produced should be written out, and
+        /// [`ConnectionCommon::process_new_packets()`] called to process any data
+        /// received in the meantime.
+        pub fn complete_pending_operation(&mut self, result: OperationResult) -> Result<(), Error> {
+            self.inner
+                .complete_pending_operation(result)
+        }
+
+        /// Requests a certificate from the client, after the handshake.
+        ///
+        /// This sends a TLS1.3 `CertificateRequest` message, which should then be
+        /// written out.  The client's response is verified by the configured
+        /// [`ClientCertVerifier`] as data is received.  Once it has been processed,
+        /// [`ServerConnection::client_certificate_requested()`] returns false, and
+        /// the client's certificate chain is available from
+        /// [`CommonState::peer_certificates()`].  If the verifier does not
+        /// mandate client authentication the client may decline, leaving
+        /// `peer_certificates()` unchanged.
+        ///
+        /// This fails if the handshake is not complete, if the client did not offer
+        /// post-handshake authentication (which is never the case for TLS1.2), if the
+        /// verifier does not offer client authentication, or if a request is
+        /// already outstanding.  In these cases the connection is unaffected.
+        ///
+        /// See [`ClientCertVerifier::defers_client_auth()`] to only authenticate
+        /// clients this way.
+        ///
+        /// [`ClientCertVerifier`]: crate::server::danger::ClientCertVerifier
+        /// [`ClientCertVerifier::defers_client_auth()`]: crate::server::danger::ClientCertVerifier::defers_client_auth
+        /// [`CommonState::peer_certificates()`]: crate::CommonState::peer_certificates
+        pub fn request_client_certificate(&mut self) -> Result<(), Error> {
+            self.inner
+                .core
+                .request_client_certificate()
+        }
+
+        /// Returns true while a request made with
+        /// [`ServerConnection::request_client_certificate()`] awaits the client's response.
+        pub fn client_certificate_requested(&self) -> bool {
+            self.inner
+                .core
+                .client_certificate_requested()
+        }
+
         /// Extract secrets, so they can be used when configuring kTLS, for example.
         /// Should be used with care as it exposes secret key material.
         pub fn dangerous_extract_secrets(self) -> Result<ExtractedSecrets, Error> {
             self.inner.dangerous_extract_secrets()
         }
+
+        /// Extract secrets, and continue the connection as a [`KernelConnection`].
+        ///
+        /// See [`ConnectionCommon::dangerous_into_kernel_connection()`] for more information.
+        pub fn dangerous_into_kernel_connection(
+            self,
+        ) -> Result<(ExtractedSecrets, KernelConnection), Error> {
+            self.inner
+                .dangerous_into_kernel_connection()
+        }
     }
 
     impl Debug for ServerConnection {
@@ -830,17 +1320,7 @@ mod connection {
         }
     }
 
-    /// Represents a TLS alert resulting from handling the client's `ClientHello` message.
-    ///
-    /// When [`Acceptor::accept()`] returns an error, it yields an `AcceptedAlert` such that the
-    /// application can communicate failure to the client via [`AcceptedAlert::write()`].
-    pub struct AcceptedAlert(ChunkVecBuffer);
-
     impl AcceptedAlert {
-        pub(super) fn empty() -> Self {
-            Self(ChunkVecBuffer::new(None))
-        }
-
         /// Send the alert to the client.
         ///
         /// To account for short writes this function should be called repeatedly until it
@@ -860,19 +1340,62 @@ mod connection {
 
     impl From<ConnectionCommon<ServerConnectionData>> for AcceptedAlert {
         fn from(conn: ConnectionCommon<ServerConnectionData>) -> Self {
-            Self(conn.core.common_state.sendable_tls)
+            Self::from(conn.core)
         }
     }
+}
 
-    impl Debug for AcceptedAlert {
-        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-            f.debug_struct("AcceptedAlert").finish()
+#[cfg(feature = "std")]
+pub use connection::{Acceptor, ReadEarlyData, ServerConnection};
+
+/// Represents a TLS alert resulting from handling the client's `ClientHello` message.
+///
+/// When [`Acceptor::accept()`] returns an error, it yields an `AcceptedAlert` such that the
+/// application can communicate failure to the client via [`AcceptedAlert::write()`].
+///
+/// When [`UnbufferedAcceptor::accept()`] returns an error, use [`AcceptedAlert::encode()`]
+/// instead.
+///
+/// [`Acceptor::accept()`]: crate::server::Acceptor::accept
+/// [`AcceptedAlert::write()`]: crate::server::AcceptedAlert::write
+pub struct AcceptedAlert(ChunkVecBuffer);
+
+impl AcceptedAlert {
+    pub(super) fn empty() -> Self {
+        Self(ChunkVecBuffer::new(None))
+    }
+
+    /// Encode the alert into `outgoing_tls`, for sending to the client.
+    ///
+    /// Returns the number of bytes written, which is zero if there is no alert to send.
+    /// If `outgoing_tls` is too small, nothing is written and an error reporting the
+    /// required size is returned.
+    pub fn encode(&mut self, outgoing_tls: &mut [u8]) -> Result<usize, InsufficientSizeError> {
+        let required_size = self.0.len();
+        if required_size > outgoing_tls.len() {
+            return Err(InsufficientSizeError { required_size });
+        }
+
+        let mut written = 0;
+        while let Some(chunk) = self.0.pop() {
+            outgoing_tls[written..written + chunk.len()].copy_from_slice(&chunk);
+            written += chunk.len();
         }
+        Ok(written)
     }
 }
 
-#[cfg(feature = "std")]
-pub use connection::{AcceptedAlert, Acceptor, ReadEarlyData, ServerConnection};
+impl From<ConnectionCore<ServerConnectionData>> for AcceptedAlert {
+    fn from(core: ConnectionCore<ServerConnectionData>) -> Self {
+        Self(core.common_state.sendable_tls)
+    }
+}
+
+impl Debug for AcceptedAlert {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AcceptedAlert").finish()
+    }
+}
 
 /// Unbuffered version of `ServerConnection`
 ///
@@ -891,6 +1414,18 @@ impl UnbufferedServerConnection {
             )?),
         })
     }
+
+    /// The identifier of the key which decrypted the client's resumption ticket.
+    ///
+    /// See [`ServerConnection::resumption_ticket_key_id()`].
+    ///
+    /// [`ServerConnection::resumption_ticket_key_id()`]: crate::server::ServerConnection::resumption_ticket_key_id
+    pub fn resumption_ticket_key_id(&self) -> Option<[u8; 16]> {
+        self.inner
+            .core
+            .data
+            .resumption_ticket_key_id
+    }
 }
 
 impl Deref for UnbufferedServerConnection {
@@ -913,6 +1448,179 @@ impl UnbufferedConnectionCommon<ServerConnectionData> {
     }
 }
 
+/// Handle a server-side connection before configuration is available, using the
+/// unbuffered API.
+///
+/// This is the unbuffered equivalent of [`Acceptor`]: it reads the client's `ClientHello`
+/// directly from a caller-owned buffer, and then lets the application choose a
+/// [`ServerConfig`] based on its contents.  Any records following the `ClientHello` are
+/// left in the caller's buffer, to be processed by the resulting
+/// [`UnbufferedServerConnection`].
+///
+/// [`Acceptor`]: crate::server::Acceptor
+pub struct UnbufferedAcceptor {
+    inner: Option<ConnectionCore<ServerConnectionData>>,
+}
+
+impl Default for UnbufferedAcceptor {
+    /// Return an empty UnbufferedAcceptor, ready to receive bytes from a new client connection.
+    fn default() -> Self {
+        Self {
+            inner: Some(ConnectionCore::new(
+                Box::new(Accepting),
+                ServerConnectionData::default(),
+                CommonState::new(Side::Server),
+            )),
+        }
+    }
+}
+
+impl UnbufferedAcceptor {
+    /// Process the TLS records in `incoming_tls`, looking for a `ClientHello` message.
+    ///
+    /// The returned [`UnbufferedAcceptStatus::discard`] bytes must be removed from the front
+    /// of `incoming_tls` before it is passed to this function, or to the resulting
+    /// [`UnbufferedServerConnection`], again.
+    ///
+    /// [`UnbufferedAcceptStatus::accepted`] is `Ok(None)` if the complete `ClientHello` has
+    /// not yet been received: receive more data into `incoming_tls` and call this function
+    /// again.  It is `Ok(Some(accepted))` once the `ClientHello` has been received: call
+    /// `accepted.into_connection()` to continue.  Do not call this function again.
+    ///
+    /// If an error occurred, the application should use [`AcceptedAlert::encode()`] to
+    /// send the alert to the client.  It should not call `accept()` again.
+    pub fn accept(&mut self, incoming_tls: &mut [u8]) -> UnbufferedAcceptStatus {
+        let Some(mut core) = self.inner.take() else {
+            return UnbufferedAcceptStatus {
+                discard: 0,
+                accepted: Err((
+                    Error::General("Acceptor polled after completion".into()),
+                    AcceptedAlert::empty(),
+                )),
+            };
+        };
+
+        let mut buffer = DeframerSliceBuffer::new(incoming_tls);
+        let mut buffer_progress = core.hs_deframer.progress();
+
+        let message = match core.deframe(None, buffer.filled_mut(), &mut buffer_progress) {
+            Ok(Some(msg)) => Message::try_from(msg)
+                .map(|m| m.into_owned())
+                .map_err(|err| {
+                    core.common_state
+                        .send_fatal_alert(AlertDescription::DecodeError, err)
+                }),
+            Ok(None) => {
+                self.inner = Some(core);
+                return UnbufferedAcceptStatus {
+                    discard: buffer.pending_discard(),
+                    accepted: Ok(None),
+                };
+            }
+            Err(err) => Err(err),
+        };
+        buffer.queue_discard(buffer_progress.take_discard());
+        let discard = buffer.pending_discard();
+
+        let message = match message {
+            Ok(message) => message,
+            Err(err) => {
+                return UnbufferedAcceptStatus {
+                    discard,
+                    accepted: Err((err, AcceptedAlert::from(core))),
+                };
+            }
+        };
+
+        let mut cx = Context {
+            common: &mut core.common_state,
+            data: &mut core.data,
+            sendable_plaintext: None,
+        };
+        let accepted = match hs::process_client_hello(&message, false, &mut cx) {
+            Ok((_, sig_schemes)) => Ok(Some(UnbufferedAccepted {
+                core,
+                message,
+                sig_schemes,
+            })),
+            Err(err) => Err((err, AcceptedAlert::from(core))),
+        };
+
+        UnbufferedAcceptStatus { discard, accepted }
+    }
+}
+
+impl Debug for UnbufferedAcceptor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnbufferedAcceptor")
+            .finish()
+    }
+}
+
+/// The result of [`UnbufferedAcceptor::accept()`].
+#[must_use]
+#[derive(Debug)]
+pub struct UnbufferedAcceptStatus {
+    /// Number of bytes to discard
+    ///
+    /// After the `accepted` field of this object has been handled, `discard` bytes must be
+    /// removed from the *front* of the `incoming_tls` buffer that was passed to
+    /// [`UnbufferedAcceptor::accept()`].
+    pub discard: usize,
+
+    /// Whether a `ClientHello` has been received
+    pub accepted: Result<Option<UnbufferedAccepted>, (Error, AcceptedAlert)>,
+}
+
+/// Represents a `ClientHello` message received through the [`UnbufferedAcceptor`].
+///
+/// Contains the state required to resume the connection through
+/// [`UnbufferedAccepted::into_connection()`].
+pub struct UnbufferedAccepted {
+    core: ConnectionCore<ServerConnectionData>,
+    message: Message<'static>,
+    sig_schemes: Vec<SignatureScheme>,
+}
+
+impl UnbufferedAccepted {
+    /// Get the [`ClientHello`] for this connection.
+    pub fn client_hello(&self) -> ClientHello<'_> {
+        accepted_client_hello(&self.core.data, &self.sig_schemes, &self.message)
+    }
+
+    /// Convert the [`UnbufferedAccepted`] into an [`UnbufferedServerConnection`].
+    ///
+    /// Returns an error if configuration-dependent validation of the received `ClientHello`
+    /// message fails.  The connection's first flight is then available from
+    /// [`UnbufferedConnectionCommon::process_tls_records()`].
+    pub fn into_connection(
+        mut self,
+        config: Arc<ServerConfig>,
+    ) -> Result<UnbufferedServerConnection, (Error, AcceptedAlert)> {
+        let mut cx = Context {
+            common: &mut self.core.common_state,
+            data: &mut self.core.data,
+            sendable_plaintext: None,
+        };
+
+        match accept_with_config(config, self.sig_schemes, &self.message, &mut cx) {
+            Ok(new) => self.core.state = Ok(new),
+            Err(err) => return Err((err, AcceptedAlert::from(self.core))),
+        }
+
+        Ok(UnbufferedServerConnection {
+            inner: UnbufferedConnectionCommon::from(self.core),
+        })
+    }
+}
+
+impl Debug for UnbufferedAccepted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnbufferedAccepted")
+            .finish()
+    }
+}
+
 /// Represents a `ClientHello` message received through the [`Acceptor`].
 ///
 /// Contains the state required to resume the connection through [`Accepted::into_connection()`].
@@ -925,19 +1633,7 @@ pub struct Accepted {
 impl Accepted {
     /// Get the [`ClientHello`] for this connection.
     pub fn client_hello(&self) -> ClientHello<'_> {
-        let payload = Self::client_hello_payload(&self.message);
-        let ch = ClientHello {
-            server_name: &self.connection.core.data.sni,
-            signature_schemes: &self.sig_schemes,
-            alpn: payload.alpn_extension(),
-            server_cert_types: payload.server_certificate_extension(),
-            client_cert_types: payload.client_certificate_extension(),
-            cipher_suites: &payload.cipher_suites,
-            certificate_authorities: payload.certificate_authorities_extension(),
-        };
-
-        trace!("Accepted::client_hello(): {ch:#?}");
-        ch
+        accepted_client_hello(&self.connection.core.data, &self.sig_schemes, &self.message)
     }
 
     /// Convert the [`Accepted`] into a [`ServerConnection`].
@@ -950,22 +1646,9 @@ impl Accepted {
         mut self,
         config: Arc<ServerConfig>,
     ) -> Result<ServerConnection, (Error, AcceptedAlert)> {
-        if let Err(err) = self
-            .connection
-            .set_max_fragment_size(config.max_fragment_size)
-        {
-            // We have a connection here, but it won't contain an alert since the error
-            // is with the fragment size configured in the `ServerConfig`.
-            return Err((err, AcceptedAlert::empty()));
-        }
-
-        self.connection.enable_secret_extraction = config.enable_secret_extraction;
-
-        let state = hs::ExpectClientHello::new(config, Vec::new());
         let mut cx = hs::ServerContext::from(&mut self.connection);
 
-        let ch = Self::client_hello_payload(&self.message);
-        let new = match state.with_certified_key(self.sig_schemes, ch, &self.message, &mut cx) {
+        let new = match accept_with_config(config, self.sig_schemes, &self.message, &mut cx) {
             Ok(new) => new,
             Err(err) => return Err((err, AcceptedAlert::from(self.connection))),
         };
@@ -975,17 +1658,45 @@ impl Accepted {
             inner: self.connection,
         })
     }
+}
 
-    fn client_hello_payload<'a>(message: &'a Message<'_>) -> &'a ClientHelloPayload {
-        match &message.payload {
-            crate::msgs::message::MessagePayload::Handshake { parsed, .. } => match &parsed.payload
-            {
-                crate::msgs::handshake::HandshakePayload::ClientHello(ch) => ch,
-                _ => unreachable!(),
-            },
-            _ => unreachable!(),
-        }
-    }
+/// Continue the handshake for a `ClientHello` received by an acceptor, using `config`.
+///
+/// If this fails because of the configuration, no alert is queued.
+fn accept_with_config(
+    config: Arc<ServerConfig>,
+    sig_schemes: Vec<SignatureScheme>,
+    message: &Message<'_>,
+    cx: &mut hs::ServerContext<'_>,
+) -> Result<Box<dyn State<ServerConnectionData>>, Error> {
+    cx.common
+        .set_max_fragment_size(config.max_fragment_size)?;
+    check_record_size_limit(config.record_size_limit)?;
+
+    cx.common.enable_secret_extraction = config.enable_secret_extraction;
+    cx.common.record_padding = config.record_padding.clone();
+    cx.common.observer = config
+        .observer
+        .as_ref()
+        .map(|observer| observer.new_connection(Side::Server));
+    // The acceptor received the ClientHello before our observer existed.
+    cx.common.observe_received(message);
+
+    hs::ExpectClientHello::new(config, Vec::new()).with_accepted_client_hello(
+        sig_schemes,
+        message,
+        cx,
+    )
+}
+
+fn accepted_client_hello<'a>(
+    data: &'a ServerConnectionData,
+    sig_schemes: &'a [SignatureScheme],
+    message: &'a Message<'_>,
+) -> ClientHello<'a> {
+    let ch = ClientHello::new(&data.sni, sig_schemes, message);
+    trace!("Accepted::client_hello(): {ch:#?}");
+    ch
 }
 
 impl Debug for Accepted {
@@ -994,10 +1705,8 @@ impl Debug for Accepted {
     }
 }
 
-#[cfg(feature = "std")]
 struct Accepting;
 
-#[cfg(feature = "std")]
 impl State<ServerConnectionData> for Accepting {
     fn handle<'m>(
         self: Box<Self>,
@@ -1118,8 +1827,14 @@ impl ConnectionCore<ServerConnectionData> {
     ) -> Result<Self, Error> {
         let mut common = CommonState::new(Side::Server);
         common.set_max_fragment_size(config.max_fragment_size)?;
+        check_record_size_limit(config.record_size_limit)?;
+        common.record_padding = config.record_padding.clone();
         common.enable_secret_extraction = config.enable_secret_extraction;
         common.fips = config.fips();
+        common.observer = config
+            .observer
+            .as_ref()
+            .map(|observer| observer.new_connection(Side::Server));
         Ok(Self::new(
             Box::new(hs::ExpectClientHello::new(config, extra_exts)),
             ServerConnectionData::default(),
@@ -1147,6 +1862,7 @@ impl ConnectionCore<ServerConnectionData> {
 pub struct ServerConnectionData {
     pub(super) sni: Option<DnsName<'static>>,
     pub(super) received_resumption_data: Option<Vec<u8>>,
+    pub(super) resumption_ticket_key_id: Option<[u8; 16]>,
     pub(super) resumption_data: Vec<u8>,
     pub(super) early_data: EarlyDataState,
 }
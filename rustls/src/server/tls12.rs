@@ -13,6 +13,7 @@ use super::hs::{self, ServerContext};
 use super::server_conn::{ProducesTickets, ServerConfig, ServerConnectionData};
 use crate::check::inappropriate_message;
 use crate::common_state::{CommonState, HandshakeFlightTls12, HandshakeKind, Side, State};
+use crate::conn::kernel::{KernelState, Tls12KernelState};
 use crate::conn::ConnectionRandoms;
 use crate::crypto::ActiveKeyExchange;
 use crate::enums::{AlertDescription, ContentType, HandshakeType, ProtocolVersion};
@@ -45,6 +46,7 @@ mod client_hello {
         ClientSessionTicket, Random, ServerExtension, ServerHelloPayload, ServerKeyExchange,
         ServerKeyExchangeParams, ServerKeyExchangePayload,
     };
+    use crate::server::{OperationResult, PendingOperation};
     use crate::sign;
     use crate::verify::DigitallySignedStruct;
 
@@ -73,6 +75,12 @@ mod client_hello {
             // -- TLS1.2 only from hereon in --
             self.transcript.add_message(chm);
 
+            // Certificate entries cannot carry extensions in TLS1.2.
+            let custom_replies =
+                hs::CustomExtensionReplies::new(&self.config, &cx.data.sni, &sigschemes_ext, chm);
+            self.extra_exts
+                .extend(custom_replies.hello);
+
             if client_hello.ems_support_offered() {
                 self.using_ems = true;
             } else if self.config.require_ems {
@@ -117,6 +125,7 @@ mod client_hello {
             // our handling of the ClientHello.
             //
             let mut ticket_received = false;
+            let mut ticket_key_id = None;
             let resume_data = client_hello
                 .ticket_extension()
                 .and_then(|ticket_ext| match ticket_ext {
@@ -128,14 +137,16 @@ mod client_hello {
                 .and_then(|ticket| {
                     ticket_received = true;
                     debug!("Ticket received");
-                    let data = self
+                    let Some((data, key_id)) = self
                         .config
                         .ticketer
-                        .decrypt(ticket.bytes());
-                    if data.is_none() {
+                        .decrypt_with_key_id(ticket.bytes())
+                    else {
                         debug!("Ticket didn't decrypt");
-                    }
-                    data
+                        return None;
+                    };
+                    ticket_key_id = key_id;
+                    Some(data)
                 })
                 .or_else(|| {
                     // Perhaps resume?  If we received a ticket, the sessionid
@@ -154,6 +165,7 @@ mod client_hello {
                 });
 
             if let Some(data) = resume_data {
+                cx.data.resumption_ticket_key_id = ticket_key_id;
                 return self.start_resumption(cx, client_hello, &client_hello.session_id, data);
             }
 
@@ -183,6 +195,7 @@ mod client_hello {
             debug_assert_eq!(ecpoint, ECPointFormat::Uncompressed);
 
             let mut ocsp_response = server_key.get_ocsp();
+            let mut sct_list = server_key.get_sct_list();
 
             // If we're not offered a ticket or a potential session ID, allocate a session ID.
             if !self.config.session_storage.can_cache() {
@@ -204,6 +217,7 @@ mod client_hello {
                 self.suite,
                 self.using_ems,
                 &mut ocsp_response,
+                &mut sct_list,
                 client_hello,
                 None,
                 &self.randoms,
@@ -213,41 +227,34 @@ mod client_hello {
             if let Some(ocsp_response) = ocsp_response {
                 emit_cert_status(&mut flight, ocsp_response);
             }
-            let server_kx = emit_server_kx(
+            let server_kx = selected_kxg.start()?;
+            let deferred_signature = emit_server_kx(
                 &mut flight,
                 sigschemes,
-                selected_kxg,
+                &*server_kx,
                 server_key.get_key(),
                 &self.randoms,
             )?;
-            let doing_client_auth = emit_certificate_req(&mut flight, &self.config)?;
-            emit_server_hello_done(&mut flight);
 
-            flight.finish(cx.common);
+            let rest = CompleteServerFlight {
+                flight: flight.into_body(),
+                config: self.config,
+                transcript: self.transcript,
+                randoms: self.randoms,
+                session_id: self.session_id,
+                suite: self.suite,
+                using_ems: self.using_ems,
+                server_kx,
+                send_ticket: self.send_ticket,
+            };
 
-            if doing_client_auth {
-                Ok(Box::new(ExpectCertificate {
-                    config: self.config,
-                    transcript: self.transcript,
-                    randoms: self.randoms,
-                    session_id: self.session_id,
-                    suite: self.suite,
-                    using_ems: self.using_ems,
-                    server_kx,
-                    send_ticket: self.send_ticket,
-                }))
-            } else {
-                Ok(Box::new(ExpectClientKx {
-                    config: self.config,
-                    transcript: self.transcript,
-                    randoms: self.randoms,
-                    session_id: self.session_id,
-                    suite: self.suite,
-                    using_ems: self.using_ems,
-                    server_kx,
-                    client_cert: None,
-                    send_ticket: self.send_ticket,
-                }))
+            match deferred_signature {
+                Some((scheme, message)) => Ok(Box::new(ExpectSignature {
+                    scheme,
+                    message,
+                    rest,
+                })),
+                None => rest.handle(None, cx),
             }
         }
 
@@ -277,6 +284,7 @@ mod client_hello {
                 self.suite,
                 self.using_ems,
                 &mut None,
+                &mut None,
                 client_hello,
                 Some(&resumedata),
                 &self.randoms,
@@ -337,14 +345,27 @@ mod client_hello {
         suite: &'static Tls12CipherSuite,
         using_ems: bool,
         ocsp_response: &mut Option<&[u8]>,
+        sct_list: &mut Option<&[u8]>,
         hello: &ClientHelloPayload,
         resumedata: Option<&persist::ServerSessionValue>,
         randoms: &ConnectionRandoms,
         extra_exts: Vec<ServerExtension>,
     ) -> Result<bool, Error> {
         let mut ep = hs::ExtensionProcessing::new();
-        ep.process_common(config, cx, ocsp_response, hello, resumedata, extra_exts)?;
+        ep.process_common(
+            config,
+            cx,
+            ocsp_response,
+            sct_list,
+            hello,
+            resumedata,
+            extra_exts,
+        )?;
         ep.process_tls12(config, hello, using_ems);
+        if let Some(limit) = ep.record_size_limit {
+            cx.common
+                .enforce_record_size_limit(limit);
+        }
 
         let sh = HandshakeMessagePayload {
             typ: HandshakeType::ServerHello,
@@ -380,15 +401,17 @@ mod client_hello {
         });
     }
 
+    /// Emits our `ServerKeyExchange` message, unless signing is deferred to the application.
+    ///
+    /// In that case, the chosen scheme and message to sign are returned.
     fn emit_server_kx(
         flight: &mut HandshakeFlightTls12<'_>,
         sigschemes: Vec<SignatureScheme>,
-        selected_group: &'static dyn SupportedKxGroup,
+        kx: &dyn ActiveKeyExchange,
         signing_key: &dyn sign::SigningKey,
         randoms: &ConnectionRandoms,
-    ) -> Result<Box<dyn ActiveKeyExchange>, Error> {
-        let kx = selected_group.start()?;
-        let kx_params = ServerKeyExchangeParams::new(&*kx);
+    ) -> Result<Option<(SignatureScheme, Vec<u8>)>, Error> {
+        let kx_params = ServerKeyExchangeParams::new(kx);
 
         let mut msg = Vec::new();
         msg.extend(randoms.client);
@@ -399,18 +422,133 @@ mod client_hello {
             .choose_scheme(&sigschemes)
             .ok_or_else(|| Error::General("incompatible signing key".to_string()))?;
         let sigscheme = signer.scheme();
+        if signer.defers_signing() {
+            trace!("Deferring server signature");
+            return Ok(Some((sigscheme, msg)));
+        }
+
         let sig = signer.sign(&msg)?;
+        emit_signed_server_kx(flight, kx, DigitallySignedStruct::new(sigscheme, sig));
+        Ok(None)
+    }
 
+    fn emit_signed_server_kx(
+        flight: &mut HandshakeFlightTls12<'_>,
+        kx: &dyn ActiveKeyExchange,
+        dss: DigitallySignedStruct,
+    ) {
         let skx = ServerKeyExchangePayload::from(ServerKeyExchange {
-            params: kx_params,
-            dss: DigitallySignedStruct::new(sigscheme, sig),
+            params: ServerKeyExchangeParams::new(kx),
+            dss,
         });
 
         flight.add(HandshakeMessagePayload {
             typ: HandshakeType::ServerKeyExchange,
             payload: HandshakePayload::ServerKeyExchange(skx),
         });
-        Ok(kx)
+    }
+
+    /// Waiting for the application to sign our `ServerKeyExchange` message.
+    ///
+    /// See [`Signer::defers_signing()`].
+    ///
+    /// [`Signer::defers_signing()`]: crate::sign::Signer::defers_signing
+    struct ExpectSignature {
+        scheme: SignatureScheme,
+        message: Vec<u8>,
+        rest: CompleteServerFlight,
+    }
+
+    impl State<ServerConnectionData> for ExpectSignature {
+        fn handle<'m>(
+            self: Box<Self>,
+            _cx: &mut ServerContext<'_>,
+            m: Message<'m>,
+        ) -> hs::NextStateOrError<'m>
+        where
+            Self: 'm,
+        {
+            Err(inappropriate_message(&m.payload, &[]))
+        }
+
+        fn pending_operation(&self) -> Option<PendingOperation<'_>> {
+            Some(PendingOperation::Sign {
+                scheme: self.scheme,
+                message: &self.message,
+            })
+        }
+
+        fn complete_operation(
+            self: Box<Self>,
+            cx: &mut ServerContext<'_>,
+            result: OperationResult,
+        ) -> hs::NextStateOrError<'static> {
+            let OperationResult::Signature(sig) = result else {
+                return Err(Error::General("unexpected operation result".into()));
+            };
+
+            self.rest
+                .handle(Some(DigitallySignedStruct::new(self.scheme, sig?)), cx)
+        }
+
+        fn into_owned(self: Box<Self>) -> hs::NextState<'static> {
+            self
+        }
+    }
+
+    /// The remainder of our first flight, following any `ServerKeyExchange` message.
+    struct CompleteServerFlight {
+        flight: Vec<u8>,
+        config: Arc<ServerConfig>,
+        transcript: HandshakeHash,
+        randoms: ConnectionRandoms,
+        session_id: SessionId,
+        suite: &'static Tls12CipherSuite,
+        using_ems: bool,
+        server_kx: Box<dyn ActiveKeyExchange>,
+        send_ticket: bool,
+    }
+
+    impl CompleteServerFlight {
+        fn handle(
+            mut self,
+            server_kx_signature: Option<DigitallySignedStruct>,
+            cx: &mut ServerContext<'_>,
+        ) -> hs::NextStateOrError<'static> {
+            let mut flight = HandshakeFlightTls12::resume(&mut self.transcript, self.flight);
+            if let Some(dss) = server_kx_signature {
+                emit_signed_server_kx(&mut flight, &*self.server_kx, dss);
+            }
+            let doing_client_auth = emit_certificate_req(&mut flight, &self.config)?;
+            emit_server_hello_done(&mut flight);
+
+            flight.finish(cx.common);
+
+            if doing_client_auth {
+                Ok(Box::new(ExpectCertificate {
+                    config: self.config,
+                    transcript: self.transcript,
+                    randoms: self.randoms,
+                    session_id: self.session_id,
+                    suite: self.suite,
+                    using_ems: self.using_ems,
+                    server_kx: self.server_kx,
+                    send_ticket: self.send_ticket,
+                }))
+            } else {
+                Ok(Box::new(ExpectClientKx {
+                    config: self.config,
+                    transcript: self.transcript,
+                    randoms: self.randoms,
+                    session_id: self.session_id,
+                    suite: self.suite,
+                    using_ems: self.using_ems,
+                    server_kx: self.server_kx,
+                    client_cert: None,
+                    send_ticket: self.send_ticket,
+                }))
+            }
+        }
     }
 
     fn emit_certificate_req(
@@ -419,7 +557,7 @@ mod client_hello {
     ) -> Result<bool, Error> {
         let client_auth = &config.verifier;
 
-        if !client_auth.offer_client_auth() {
+        if !client_auth.offer_client_auth() || client_auth.defers_client_auth() {
             return Ok(false);
         }
 
@@ -999,6 +1137,10 @@ impl State<ServerConnectionData> for ExpectTraffic {
             .extract_secrets(Side::Server)
     }
 
+    fn into_kernel_state(self: Box<Self>) -> Result<Box<dyn KernelState + 'static>, Error> {
+        Ok(Box::new(Tls12KernelState))
+    }
+
     fn into_owned(self: Box<Self>) -> hs::NextState<'static> {
         self
     }
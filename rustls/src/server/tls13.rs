@@ -2,8 +2,9 @@ use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::mem;
 
-pub(super) use client_hello::CompleteClientHelloHandling;
+pub(super) use client_hello::{emit_stateless_hello_retry_request, CompleteClientHelloHandling};
 use pki_types::{CertificateDer, UnixTime};
 use subtle::ConstantTimeEq;
 
@@ -11,23 +12,28 @@ use super::hs::{self, HandshakeHashOrBuffer, ServerContext};
 use super::server_conn::ServerConnectionData;
 use crate::check::{inappropriate_handshake_message, inappropriate_message};
 use crate::common_state::{
-    CommonState, HandshakeFlightTls13, HandshakeKind, Protocol, Side, State,
+    CommonState, HandshakeFlightTls13, HandshakeKind, KxState, Protocol, Side, State,
 };
+use crate::conn::kernel::{Direction, KernelContext, KernelState};
 use crate::conn::ConnectionRandoms;
 use crate::enums::{AlertDescription, ContentType, HandshakeType, ProtocolVersion};
 use crate::error::{Error, InvalidMessage, PeerIncompatible, PeerMisbehaved};
+use crate::grease::Grease;
 use crate::hash_hs::HandshakeHash;
 use crate::log::{debug, trace, warn};
+use crate::msgs::base::{Payload, PayloadU16, PayloadU8};
 use crate::msgs::codec::{Codec, Reader};
 use crate::msgs::enums::KeyUpdateRequest;
 use crate::msgs::handshake::{
-    CertificateChain, CertificatePayloadTls13, HandshakeMessagePayload, HandshakePayload,
-    NewSessionTicketExtension, NewSessionTicketPayloadTls13, CERTIFICATE_MAX_SIZE_LIMIT,
+    read_sct_list, CertReqExtension, CertificateChain, CertificatePayloadTls13,
+    CertificateRequestPayloadTls13, HandshakeMessagePayload, HandshakePayload,
+    NewSessionTicketExtension, NewSessionTicketPayloadTls13, UnknownExtension,
+    CERTIFICATE_MAX_SIZE_LIMIT,
 };
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
 use crate::server::ServerConfig;
-use crate::suites::PartiallyExtractedSecrets;
+use crate::suites::{ConnectionTrafficSecrets, PartiallyExtractedSecrets};
 use crate::tls13::key_schedule::{
     KeyScheduleTraffic, KeyScheduleTrafficWithClientFinishedPending, ResumptionSecret,
 };
@@ -37,23 +43,33 @@ use crate::tls13::{
 use crate::{compress, rand, verify};
 
 mod client_hello {
+    use core::slice;
+
     use super::*;
     use crate::compress::CertCompressor;
     use crate::crypto::SupportedKxGroup;
+    use crate::delegated_credential::{DelegatedCredential, DelegatedKey};
+    use crate::dtls::Cookie;
     use crate::enums::SignatureScheme;
-    use crate::msgs::base::{Payload, PayloadU8};
+    use crate::hash_hs::HandshakeHashBuffer;
+    use crate::msgs::base::Payload;
     use crate::msgs::ccs::ChangeCipherSpecPayload;
     use crate::msgs::enums::{Compression, NamedGroup, PSKKeyExchangeMode};
     use crate::msgs::handshake::{
-        CertReqExtension, CertificatePayloadTls13, CertificateRequestPayloadTls13,
-        ClientHelloPayload, HelloRetryExtension, HelloRetryRequest, KeyShareEntry, Random,
-        ServerExtension, ServerHelloPayload, SessionId,
+        CertificatePayloadTls13, ClientHelloPayload, Encoding, HelloRetryExtension,
+        HelloRetryRequest, KeyShareEntry, Random, ServerEncryptedClientHello, ServerExtension,
+        ServerHelloPayload, SessionId, UnknownExtension,
     };
+    use crate::psk::ExternalPsk;
     use crate::server::common::ActiveCertifiedKey;
+    use crate::server::ech::{EchAccepted, EchState};
+    use crate::server::{OperationResult, PendingOperation};
     use crate::sign;
     use crate::tls13::key_schedule::{
-        KeyScheduleEarly, KeyScheduleHandshake, KeySchedulePreHandshake,
+        server_ech_hrr_confirmation_secret, KeyScheduleEarly, KeyScheduleHandshake,
+        KeySchedulePreHandshake,
     };
+    use crate::tls13::VerifyMessage;
     use crate::verify::DigitallySignedStruct;
 
     #[derive(PartialEq)]
@@ -63,6 +79,22 @@ mod client_hello {
         Accepted,
     }
 
+    /// The PSK chosen from those offered by the client, with its index in the offer.
+    enum ChosenPsk {
+        None,
+        Resumption(usize, persist::ServerSessionValue),
+        External(usize, Arc<ExternalPsk>),
+    }
+
+    impl ChosenPsk {
+        fn resumption(&self) -> Option<&persist::ServerSessionValue> {
+            match self {
+                Self::Resumption(_, resume) => Some(resume),
+                _ => None,
+            }
+        }
+    }
+
     pub(in crate::server) struct CompleteClientHelloHandling {
         pub(in crate::server) config: Arc<ServerConfig>,
         pub(in crate::server) transcript: HandshakeHash,
@@ -71,6 +103,7 @@ mod client_hello {
         pub(in crate::server) done_retry: bool,
         pub(in crate::server) send_tickets: usize,
         pub(in crate::server) extra_exts: Vec<ServerExtension>,
+        pub(in crate::server) ech: EchState,
     }
 
     fn max_early_data_size(configured: u32) -> usize {
@@ -94,7 +127,9 @@ mod client_hello {
             suite: &'static Tls13CipherSuite,
             client_hello: &Message<'_>,
             psk: &[u8],
+            external: Option<&ExternalPsk>,
             binder: &[u8],
+            protocol: Protocol,
         ) -> bool {
             let binder_plaintext = match &client_hello.payload {
                 MessagePayload::Handshake { parsed, .. } => parsed.encoding_for_binder_signing(),
@@ -105,34 +140,231 @@ mod client_hello {
                 .transcript
                 .hash_given(&binder_plaintext);
 
-            let key_schedule = KeyScheduleEarly::new(suite, psk);
-            let real_binder =
-                key_schedule.resumption_psk_binder_key_and_sign_verify_data(&handshake_hash);
+            let key_schedule = KeyScheduleEarly::new(suite, psk, protocol);
+            let real_binder = match external {
+                Some(external) => key_schedule.external_psk_binder_key_and_sign_verify_data(
+                    external.is_imported(),
+                    &handshake_hash,
+                ),
+                None => {
+                    key_schedule.resumption_psk_binder_key_and_sign_verify_data(&handshake_hash)
+                }
+            };
 
             ConstantTimeEq::ct_eq(real_binder.as_ref(), binder).into()
         }
 
+        /// Choose a PSK offered by the client for use with the key exchange `mode`.
+        ///
+        /// Resumption PSKs are only chosen for `psk_dhe_ke`.
+        fn choose_psk(
+            &mut self,
+            cx: &mut ServerContext<'_>,
+            chm: &Message<'_>,
+            client_hello: &ClientHelloPayload,
+            mode: PSKKeyExchangeMode,
+        ) -> Result<ChosenPsk, Error> {
+            let Some(psk_offer) = client_hello.psk() else {
+                return Ok(ChosenPsk::None);
+            };
+
+            if !client_hello.check_psk_ext_is_last() {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::IllegalParameter,
+                    PeerMisbehaved::PskExtensionMustBeLast,
+                ));
+            }
+
+            // "A client MUST provide a "psk_key_exchange_modes" extension if it
+            //  offers a "pre_shared_key" extension. If clients offer
+            //  "pre_shared_key" without a "psk_key_exchange_modes" extension,
+            //  servers MUST abort the handshake." - RFC8446 4.2.9
+            if client_hello.psk_modes().is_none() {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::MissingExtension,
+                    PeerMisbehaved::MissingPskModesExtension,
+                ));
+            }
+
+            if psk_offer.binders.is_empty() {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::DecodeError,
+                    PeerMisbehaved::MissingBinderInPskExtension,
+                ));
+            }
+
+            if psk_offer.binders.len() != psk_offer.identities.len() {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::IllegalParameter,
+                    PeerMisbehaved::PskExtensionWithMismatchedIdsAndBinders,
+                ));
+            }
+
+            if !client_hello.psk_mode_offered(mode) {
+                debug!("Client unwilling to use a PSK, {:?} not offered", mode);
+                return Ok(ChosenPsk::None);
+            }
+
+            let now = self.config.current_time()?;
+
+            for (i, psk_id) in psk_offer.identities.iter().enumerate() {
+                let maybe_resume_data = match mode {
+                    PSKKeyExchangeMode::PSK_DHE_KE => self
+                        .attempt_tls13_ticket_decryption(&psk_id.identity.0)
+                        .map(|(resumedata, key_id)| {
+                            (
+                                resumedata.set_freshness(psk_id.obfuscated_ticket_age, now),
+                                key_id,
+                            )
+                        })
+                        .filter(|(resumedata, _)| {
+                            hs::can_resume(self.suite.into(), &cx.data.sni, false, resumedata)
+                        }),
+                    _ => None,
+                };
+
+                if let Some((resume, key_id)) = maybe_resume_data {
+                    if !self.check_binder(
+                        self.suite,
+                        chm,
+                        &resume.master_secret.0,
+                        None,
+                        psk_offer.binders[i].as_ref(),
+                        cx.common.protocol,
+                    ) {
+                        return Err(cx.common.send_fatal_alert(
+                            AlertDescription::DecryptError,
+                            PeerMisbehaved::IncorrectBinder,
+                        ));
+                    }
+
+                    cx.data.resumption_ticket_key_id = key_id;
+                    return Ok(ChosenPsk::Resumption(i, resume));
+                }
+
+                let Some(external) = self
+                    .config
+                    .psk_resolver
+                    .as_ref()
+                    .and_then(|resolver| resolver.resolve(&psk_id.identity.0))
+                else {
+                    continue;
+                };
+
+                // "psk_ke" is tried first, so is only chosen over "psk_dhe_ke"
+                // if the PSK prefers it.
+                let modes = external.key_exchange_modes();
+                let mode_allowed = match mode {
+                    PSKKeyExchangeMode::PSK_DHE_KE => modes.allows_dhe(),
+                    _ if client_hello.psk_mode_offered(PSKKeyExchangeMode::PSK_DHE_KE) => {
+                        modes.prefers_psk_only()
+                    }
+                    _ => modes.allows_psk_only(),
+                };
+
+                if !mode_allowed || !external.usable_with(self.suite) {
+                    continue;
+                }
+
+                if !self.check_binder(
+                    self.suite,
+                    chm,
+                    external.secret(),
+                    Some(&external),
+                    psk_offer.binders[i].as_ref(),
+                    cx.common.protocol,
+                ) {
+                    return Err(cx.common.send_fatal_alert(
+                        AlertDescription::DecryptError,
+                        PeerMisbehaved::IncorrectBinder,
+                    ));
+                }
+
+                return Ok(ChosenPsk::External(i, external));
+            }
+
+            Ok(ChosenPsk::None)
+        }
+
         fn attempt_tls13_ticket_decryption(
             &mut self,
             ticket: &[u8],
-        ) -> Option<persist::ServerSessionValue> {
+        ) -> Option<(persist::ServerSessionValue, Option<[u8; 16]>)> {
             if self.config.ticketer.enabled() {
-                self.config
+                let (plain, key_id) = self
+                    .config
                     .ticketer
-                    .decrypt(ticket)
-                    .and_then(|plain| persist::ServerSessionValue::read_bytes(&plain).ok())
+                    .decrypt_with_key_id(ticket)?;
+                persist::ServerSessionValue::read_bytes(&plain)
+                    .ok()
+                    .map(|resumedata| (resumedata, key_id))
             } else {
                 self.config
                     .session_storage
                     .take(ticket)
                     .and_then(|plain| persist::ServerSessionValue::read_bytes(&plain).ok())
+                    .map(|resumedata| (resumedata, None))
             }
         }
 
+        /// Restore the transcript of a stateless `HelloRetryRequest` from the
+        /// cookie that [`hs::ExpectClientHello`] accepted, if any.
+        ///
+        /// This only applies to DTLS servers made with
+        /// [`DtlsServerConnection::with_cookie()`].  See RFC 9147 section 5.1.
+        ///
+        /// [`DtlsServerConnection::with_cookie()`]: crate::dtls::DtlsServerConnection::with_cookie
+        fn restore_dtls_retry(
+            &mut self,
+            cx: &mut ServerContext<'_>,
+            client_hello: &ClientHelloPayload,
+        ) -> Result<(), Error> {
+            let Some((cookie, sealed)) = cx
+                .common
+                .dtls
+                .cookie
+                .as_mut()
+                .and_then(|cookie_state| cookie_state.accepted.take())
+            else {
+                return Ok(());
+            };
+
+            if cookie.suite != self.suite.common.suite {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::IllegalParameter,
+                    PeerMisbehaved::InvalidCookie,
+                ));
+            }
+
+            // Rebuild the transcript of the first `ClientHello` and our
+            // `HelloRetryRequest`, as RFC 8446 section 4.4.1 describes.
+            self.transcript.add(
+                &HandshakeMessagePayload::build_handshake_hash(&cookie.ch1_hash).get_encoding(),
+            );
+            let hrr = Message {
+                version: ProtocolVersion::TLSv1_2,
+                payload: MessagePayload::handshake(HandshakeMessagePayload {
+                    typ: HandshakeType::HelloRetryRequest,
+                    payload: HandshakePayload::HelloRetryRequest(hello_retry_request(
+                        self.suite,
+                        client_hello.session_id,
+                        cx.common.protocol,
+                        cookie.group,
+                        Some(&sealed),
+                    )),
+                }),
+            };
+            self.transcript.add_message(&hrr);
+
+            self.done_retry = true;
+            cx.common.handshake_kind = Some(HandshakeKind::FullWithHelloRetryRequest);
+            Ok(())
+        }
+
         pub(in crate::server) fn handle_client_hello(
             mut self,
             cx: &mut ServerContext<'_>,
-            server_key: ActiveCertifiedKey<'_>,
+            server_key: Option<ActiveCertifiedKey<'_>>,
             chm: &Message<'_>,
             client_hello: &ClientHelloPayload,
             selected_kxg: &'static dyn SupportedKxGroup,
@@ -147,15 +379,6 @@ mod client_hello {
 
             sigschemes_ext.retain(SignatureScheme::supported_in_tls13);
 
-            let shares_ext = client_hello
-                .keyshare_extension()
-                .ok_or_else(|| {
-                    cx.common.send_fatal_alert(
-                        AlertDescription::HandshakeFailure,
-                        PeerIncompatible::KeyShareExtensionRequired,
-                    )
-                })?;
-
             if client_hello.has_keyshare_extension_with_duplicates() {
                 return Err(cx.common.send_fatal_alert(
                     AlertDescription::IllegalParameter,
@@ -170,6 +393,8 @@ mod client_hello {
                 ));
             }
 
+            self.restore_dtls_retry(cx, client_hello)?;
+
             let cert_compressor = client_hello
                 .certificate_compression_extension()
                 .and_then(|offered|
@@ -193,202 +418,228 @@ mod client_hello {
                 });
             }
 
-            // See if there is a KeyShare for the selected kx group.
-            let chosen_share_and_kxg = shares_ext.iter().find_map(|share| {
-                (share.group == selected_kxg.name()).then_some((share, selected_kxg))
-            });
-
-            let Some(chosen_share_and_kxg) = chosen_share_and_kxg else {
-                // We don't have a suitable key share.  Send a HelloRetryRequest
-                // for the mutually_preferred_group.
-                self.transcript.add_message(chm);
-
-                if self.done_retry {
-                    return Err(cx.common.send_fatal_alert(
-                        AlertDescription::IllegalParameter,
-                        PeerMisbehaved::RefusedToFollowHelloRetryRequest,
-                    ));
-                }
-
-                emit_hello_retry_request(
-                    &mut self.transcript,
-                    self.suite,
-                    client_hello.session_id,
-                    cx.common,
-                    selected_kxg.name(),
-                );
-                emit_fake_ccs(cx.common);
-
-                let skip_early_data = max_early_data_size(self.config.max_early_data_size);
-
-                let next = Box::new(hs::ExpectClientHello {
-                    config: self.config,
-                    transcript: HandshakeHashOrBuffer::Hash(self.transcript),
-                    #[cfg(feature = "tls12")]
-                    session_id: SessionId::empty(),
-                    #[cfg(feature = "tls12")]
-                    using_ems: false,
-                    done_retry: true,
-                    send_tickets: self.send_tickets,
-                    extra_exts: self.extra_exts,
-                });
-
-                return if early_data_requested {
-                    Ok(Box::new(ExpectAndSkipRejectedEarlyData {
-                        skip_data_left: skip_early_data,
-                        next,
-                    }))
-                } else {
-                    Ok(next)
-                };
+            // We only use "psk_ke" (which needs no key share) with an external PSK,
+            // and if the client also offers "psk_dhe_ke", only where the PSK's
+            // key exchange modes prefer "psk_ke".
+            let mut chosen_psk = match client_hello.psk_mode_offered(PSKKeyExchangeMode::PSK_KE) {
+                true => self.choose_psk(cx, chm, client_hello, PSKKeyExchangeMode::PSK_KE)?,
+                false => ChosenPsk::None,
             };
-
-            let mut chosen_psk_index = None;
-            let mut resumedata = None;
-
-            if let Some(psk_offer) = client_hello.psk() {
-                if !client_hello.check_psk_ext_is_last() {
-                    return Err(cx.common.send_fatal_alert(
-                        AlertDescription::IllegalParameter,
-                        PeerMisbehaved::PskExtensionMustBeLast,
-                    ));
-                }
-
-                // "A client MUST provide a "psk_key_exchange_modes" extension if it
-                //  offers a "pre_shared_key" extension. If clients offer
-                //  "pre_shared_key" without a "psk_key_exchange_modes" extension,
-                //  servers MUST abort the handshake." - RFC8446 4.2.9
-                if client_hello.psk_modes().is_none() {
-                    return Err(cx.common.send_fatal_alert(
-                        AlertDescription::MissingExtension,
-                        PeerMisbehaved::MissingPskModesExtension,
-                    ));
-                }
-
-                if psk_offer.binders.is_empty() {
-                    return Err(cx.common.send_fatal_alert(
-                        AlertDescription::DecodeError,
-                        PeerMisbehaved::MissingBinderInPskExtension,
-                    ));
-                }
-
-                if psk_offer.binders.len() != psk_offer.identities.len() {
-                    return Err(cx.common.send_fatal_alert(
-                        AlertDescription::IllegalParameter,
-                        PeerMisbehaved::PskExtensionWithMismatchedIdsAndBinders,
-                    ));
-                }
-
-                let now = self.config.current_time()?;
-
-                for (i, psk_id) in psk_offer.identities.iter().enumerate() {
-                    let maybe_resume_data = self
-                        .attempt_tls13_ticket_decryption(&psk_id.identity.0)
-                        .map(|resumedata| {
-                            resumedata.set_freshness(psk_id.obfuscated_ticket_age, now)
-                        })
-                        .filter(|resumedata| {
-                            hs::can_resume(self.suite.into(), &cx.data.sni, false, resumedata)
+            let psk_only = matches!(chosen_psk, ChosenPsk::External(..));
+
+            let chosen_share_and_kxg = match chosen_psk {
+                ChosenPsk::External(..) => None,
+                _ => {
+                    let shares_ext = client_hello
+                        .keyshare_extension()
+                        .ok_or_else(|| {
+                            cx.common.send_fatal_alert(
+                                AlertDescription::HandshakeFailure,
+                                PeerIncompatible::KeyShareExtensionRequired,
+                            )
+                        })?;
+
+                    // See if there is a KeyShare for the selected kx group.
+                    let chosen_share_and_kxg = shares_ext.iter().find_map(|share| {
+                        (share.group == selected_kxg.name()).then_some((share, selected_kxg))
+                    });
+
+                    let Some(chosen_share_and_kxg) = chosen_share_and_kxg else {
+                        // We don't have a suitable key share.  Send a HelloRetryRequest
+                        // for the mutually_preferred_group.
+                        self.transcript.add_message(chm);
+
+                        if self.done_retry {
+                            return Err(cx.common.send_fatal_alert(
+                                AlertDescription::IllegalParameter,
+                                PeerMisbehaved::RefusedToFollowHelloRetryRequest,
+                            ));
+                        }
+
+                        emit_hello_retry_request(
+                            &mut self.transcript,
+                            self.suite,
+                            client_hello.session_id,
+                            cx.common,
+                            Some(selected_kxg.name()),
+                            None,
+                            self.ech.accepted(),
+                        );
+                        emit_fake_ccs(cx.common);
+
+                        let skip_early_data = max_early_data_size(self.config.max_early_data_size);
+
+                        let next = Box::new(hs::ExpectClientHello {
+                            config: self.config,
+                            transcript: HandshakeHashOrBuffer::Hash(self.transcript),
+                            #[cfg(feature = "tls12")]
+                            session_id: SessionId::empty(),
+                            #[cfg(feature = "tls12")]
+                            using_ems: false,
+                            done_retry: true,
+                            send_tickets: self.send_tickets,
+                            extra_exts: self.extra_exts,
+                            ech: self.ech,
                         });
 
-                    let Some(resume) = maybe_resume_data else {
-                        continue;
+                        return if early_data_requested {
+                            Ok(Box::new(ExpectAndSkipRejectedEarlyData {
+                                skip_data_left: skip_early_data,
+                                next,
+                            }))
+                        } else {
+                            Ok(next)
+                        };
                     };
 
-                    if !self.check_binder(
-                        self.suite,
-                        chm,
-                        &resume.master_secret.0,
-                        psk_offer.binders[i].as_ref(),
-                    ) {
-                        return Err(cx.common.send_fatal_alert(
-                            AlertDescription::DecryptError,
-                            PeerMisbehaved::IncorrectBinder,
-                        ));
-                    }
-
-                    chosen_psk_index = Some(i);
-                    resumedata = Some(resume);
-                    break;
+                    Some(chosen_share_and_kxg)
                 }
+            };
+
+            if !psk_only {
+                chosen_psk =
+                    self.choose_psk(cx, chm, client_hello, PSKKeyExchangeMode::PSK_DHE_KE)?;
             }
 
-            if !client_hello.psk_mode_offered(PSKKeyExchangeMode::PSK_DHE_KE) {
+            if client_hello.psk_mode_offered(PSKKeyExchangeMode::PSK_DHE_KE) {
+                self.send_tickets = self.config.send_tls13_tickets;
+            } else {
                 debug!("Client unwilling to resume, DHE_KE not offered");
                 self.send_tickets = 0;
-                chosen_psk_index = None;
-                resumedata = None;
-            } else {
-                self.send_tickets = self.config.send_tls13_tickets;
             }
 
-            if let Some(ref resume) = resumedata {
-                cx.data.received_resumption_data = Some(resume.application_data.0.clone());
-                cx.common
-                    .peer_certificates
-                    .clone_from(&resume.client_cert_chain);
-            }
+            let (chosen_psk_index, psk_secret) = match &chosen_psk {
+                ChosenPsk::None => (None, None),
+                ChosenPsk::Resumption(index, resume) => {
+                    cx.data.received_resumption_data = Some(resume.application_data.0.clone());
+                    cx.common
+                        .peer_certificates
+                        .clone_from(&resume.client_cert_chain);
+                    (Some(*index), Some(&resume.master_secret.0[..]))
+                }
+                ChosenPsk::External(index, external) => {
+                    debug!("Using external PSK {:?}", external);
+                    cx.common.external_psk_identity = Some(external.identity().to_vec());
+                    (Some(*index), Some(external.secret()))
+                }
+            };
+
+            // Certificates are only used for a full handshake.
+            let server_key = match chosen_psk {
+                ChosenPsk::None => {
+                    Some(server_key.ok_or_else(|| hs::no_certificate_resolved(cx.common))?)
+                }
+                _ => None,
+            };
+
+            let custom_replies =
+                hs::CustomExtensionReplies::new(&self.config, &cx.data.sni, &sigschemes_ext, chm);
+            self.extra_exts
+                .extend(custom_replies.hello);
 
-            let full_handshake = resumedata.is_none();
             self.transcript.add_message(chm);
             let key_schedule = emit_server_hello(
                 &mut self.transcript,
-                &self.randoms,
+                &mut self.randoms,
                 self.suite,
                 cx,
-                &client_hello.session_id,
+                client_hello,
                 chosen_share_and_kxg,
                 chosen_psk_index,
-                resumedata
-                    .as_ref()
-                    .map(|x| &x.master_secret.0[..]),
+                psk_secret,
+                self.ech.accepted(),
                 &self.config,
             )?;
             if !self.done_retry {
                 emit_fake_ccs(cx.common);
             }
 
-            if full_handshake {
-                cx.common
-                    .handshake_kind
-                    .get_or_insert(HandshakeKind::Full);
-            } else {
-                cx.common.handshake_kind = Some(HandshakeKind::Resumed);
+            match chosen_psk {
+                ChosenPsk::Resumption(..) => {
+                    cx.common.handshake_kind = Some(HandshakeKind::Resumed);
+                }
+                _ => {
+                    cx.common
+                        .handshake_kind
+                        .get_or_insert(HandshakeKind::Full);
+                }
             }
 
-            let mut ocsp_response = server_key.get_ocsp();
+            let mut ocsp_response = server_key
+                .as_ref()
+                .and_then(|key| key.get_ocsp());
+            let mut sct_list = server_key
+                .as_ref()
+                .and_then(|key| key.get_sct_list());
             let mut flight = HandshakeFlightTls13::new(&mut self.transcript);
-            let doing_early_data = emit_encrypted_extensions(
+            let psk_binder = chosen_psk_index
+                .zip(client_hello.psk())
+                .and_then(|(index, offer)| offer.binders.get(index))
+                .map(|binder| binder.as_ref());
+            let (doing_early_data, record_size_limit) = emit_encrypted_extensions(
                 &mut flight,
                 self.suite,
                 cx,
                 &mut ocsp_response,
+                &mut sct_list,
                 client_hello,
-                resumedata.as_ref(),
+                chosen_psk.resumption(),
+                psk_binder,
                 self.extra_exts,
+                &self.ech,
                 &self.config,
             )?;
 
-            let doing_client_auth = if full_handshake {
+            let mut deferred_signature = None;
+            let doing_client_auth = if let Some(server_key) = &server_key {
                 let client_auth = emit_certificate_req_tls13(&mut flight, &self.config)?;
 
+                // RFC 9345 section 4.1.1: only use a delegated credential whose schemes
+                // the client offered for it.
+                let delegated_key = server_key
+                    .get_delegated_key()
+                    .filter(|dk| {
+                        let credential = dk.credential();
+                        client_hello
+                            .delegated_credential_schemes()
+                            .is_some_and(|schemes| {
+                                schemes.contains(&credential.dc_cert_verify_algorithm)
+                            })
+                            && sigschemes_ext.contains(&credential.algorithm)
+                    });
+                let delegated_credential = delegated_key.map(DelegatedKey::credential);
+
                 if let Some(compressor) = cert_compressor {
                     emit_compressed_certificate_tls13(
                         &mut flight,
                         &self.config,
                         server_key.get_cert(),
                         ocsp_response,
+                        sct_list,
+                        delegated_credential,
+                        &custom_replies.certificate,
                         compressor,
                     );
                 } else {
-                    emit_certificate_tls13(&mut flight, server_key.get_cert(), ocsp_response);
+                    emit_certificate_tls13(
+                        &mut flight,
+                        server_key.get_cert(),
+                        ocsp_response,
+                        sct_list,
+                        delegated_credential,
+                        &custom_replies.certificate,
+                    );
                 }
-                emit_certificate_verify_tls13(
-                    &mut flight,
-                    cx.common,
-                    server_key.get_key(),
-                    &sigschemes_ext,
-                )?;
+
+                let (signing_key, schemes) = match delegated_key {
+                    Some(dk) => (
+                        &**dk.key(),
+                        slice::from_ref(&dk.credential().dc_cert_verify_algorithm),
+                    ),
+                    None => (server_key.get_key(), &sigschemes_ext[..]),
+                };
+                deferred_signature =
+                    emit_certificate_verify_tls13(&mut flight, cx.common, signing_key, schemes)?;
                 client_auth
             } else {
                 false
@@ -416,11 +667,126 @@ mod client_hello {
                 }
             }
 
+            // Early data is subject to the limit (if any) of the session it resumes,
+            // so ours only applies from the handshake keys onwards.
+            // See RFC 8449 section 4.
+            let record_size_limit = match (&doing_early_data, record_size_limit) {
+                (EarlyDataDecision::Accepted, limit) => limit,
+                (_, Some(limit)) => {
+                    cx.common
+                        .enforce_record_size_limit(limit);
+                    None
+                }
+                (_, None) => None,
+            };
+
             cx.common.check_aligned_handshake()?;
+
+            let rest = CompleteServerFlight {
+                flight: flight.into_body(),
+                config: self.config,
+                transcript: self.transcript,
+                suite: self.suite,
+                randoms: self.randoms,
+                send_tickets: self.send_tickets,
+                post_handshake_auth: client_hello.post_handshake_auth_offered()
+                    && !cx.common.is_quic()
+                    && !cx.common.is_dtls(),
+                key_schedule,
+                doing_client_auth,
+                early_data_accepted: doing_early_data == EarlyDataDecision::Accepted,
+                record_size_limit,
+            };
+
+            match deferred_signature {
+                Some((scheme, message)) => Ok(Box::new(ExpectSignature {
+                    scheme,
+                    message,
+                    rest,
+                })),
+                None => rest.handle(None, cx),
+            }
+        }
+    }
+
+    /// Waiting for the application to sign our `CertificateVerify` message.
+    ///
+    /// See [`Signer::defers_signing()`].
+    ///
+    /// [`Signer::defers_signing()`]: crate::sign::Signer::defers_signing
+    struct ExpectSignature {
+        scheme: SignatureScheme,
+        message: VerifyMessage,
+        rest: CompleteServerFlight,
+    }
+
+    impl State<ServerConnectionData> for ExpectSignature {
+        fn handle<'m>(
+            self: Box<Self>,
+            _cx: &mut ServerContext<'_>,
+            m: Message<'m>,
+        ) -> hs::NextStateOrError<'m>
+        where
+            Self: 'm,
+        {
+            Err(inappropriate_message(&m.payload, &[]))
+        }
+
+        fn pending_operation(&self) -> Option<PendingOperation<'_>> {
+            Some(PendingOperation::Sign {
+                scheme: self.scheme,
+                message: self.message.as_ref(),
+            })
+        }
+
+        fn complete_operation(
+            self: Box<Self>,
+            cx: &mut ServerContext<'_>,
+            result: OperationResult,
+        ) -> hs::NextStateOrError<'static> {
+            let OperationResult::Signature(sig) = result else {
+                return Err(Error::General("unexpected operation result".into()));
+            };
+
+            self.rest
+                .handle(Some(DigitallySignedStruct::new(self.scheme, sig?)), cx)
+        }
+
+        fn into_owned(self: Box<Self>) -> hs::NextState<'static> {
+            self
+        }
+    }
+
+    /// The remainder of our first flight, following any `CertificateVerify` message.
+    struct CompleteServerFlight {
+        flight: Vec<u8>,
+        config: Arc<ServerConfig>,
+        transcript: HandshakeHash,
+        suite: &'static Tls13CipherSuite,
+        randoms: ConnectionRandoms,
+        send_tickets: usize,
+        post_handshake_auth: bool,
+        key_schedule: KeyScheduleHandshake,
+        doing_client_auth: bool,
+        early_data_accepted: bool,
+        record_size_limit: Option<u16>,
+    }
+
+    impl CompleteServerFlight {
+        fn handle(
+            mut self,
+            certificate_verify: Option<DigitallySignedStruct>,
+            cx: &mut ServerContext<'_>,
+        ) -> hs::NextStateOrError<'static> {
+            let mut flight = HandshakeFlightTls13::resume(&mut self.transcript, self.flight);
+            if let Some(cv) = certificate_verify {
+                emit_signed_certificate_verify_tls13(&mut flight, cv);
+            }
+
             let key_schedule_traffic =
-                emit_finished_tls13(flight, &self.randoms, cx, key_schedule, &self.config);
+                emit_finished_tls13(flight, &self.randoms, cx, self.key_schedule, &self.config);
 
-            if !doing_client_auth && self.config.send_half_rtt_data {
+            if !self.doing_client_auth && self.config.send_half_rtt_data {
                 // Application data can be sent immediately after Finished, in one
                 // flight.  However, if client auth is enabled, we don't want to send
                 // application data to an unauthenticated peer.
@@ -428,7 +794,7 @@ mod client_hello {
                     .start_outgoing_traffic(&mut cx.sendable_plaintext);
             }
 
-            if doing_client_auth {
+            if self.doing_client_auth {
                 if self
                     .config
                     .cert_decompressors
@@ -440,6 +806,7 @@ mod client_hello {
                         suite: self.suite,
                         key_schedule: key_schedule_traffic,
                         send_tickets: self.send_tickets,
+                        post_handshake_auth: self.post_handshake_auth,
                         message_already_in_transcript: false,
                     }))
                 } else {
@@ -449,9 +816,10 @@ mod client_hello {
                         suite: self.suite,
                         key_schedule: key_schedule_traffic,
                         send_tickets: self.send_tickets,
+                        post_handshake_auth: self.post_handshake_auth,
                     }))
                 }
-            } else if doing_early_data == EarlyDataDecision::Accepted && !cx.common.is_quic() {
+            } else if self.early_data_accepted && !cx.common.is_quic() {
                 // Not used for QUIC: RFC 9001 §8.3: Clients MUST NOT send the EndOfEarlyData
                 // message. A server MUST treat receipt of a CRYPTO frame in a 0-RTT packet as a
                 // connection error of type PROTOCOL_VIOLATION.
@@ -461,6 +829,8 @@ mod client_hello {
                     suite: self.suite,
                     key_schedule: key_schedule_traffic,
                     send_tickets: self.send_tickets,
+                    post_handshake_auth: self.post_handshake_auth,
+                    record_size_limit: self.record_size_limit,
                 }))
             } else {
                 Ok(Box::new(ExpectFinished {
@@ -469,6 +839,7 @@ mod client_hello {
                     suite: self.suite,
                     key_schedule: key_schedule_traffic,
                     send_tickets: self.send_tickets,
+                    post_handshake_auth: self.post_handshake_auth,
                 }))
             }
         }
@@ -476,64 +847,55 @@ mod client_hello {
 
     fn emit_server_hello(
         transcript: &mut HandshakeHash,
-        randoms: &ConnectionRandoms,
+        randoms: &mut ConnectionRandoms,
         suite: &'static Tls13CipherSuite,
         cx: &mut ServerContext<'_>,
-        session_id: &SessionId,
-        share_and_kxgroup: (&KeyShareEntry, &'static dyn SupportedKxGroup),
+        client_hello: &ClientHelloPayload,
+        share_and_kxgroup: Option<(&KeyShareEntry, &'static dyn SupportedKxGroup)>,
         chosen_psk_idx: Option<usize>,
-        resuming_psk: Option<&[u8]>,
+        psk: Option<&[u8]>,
+        ech: Option<&EchAccepted>,
         config: &ServerConfig,
     ) -> Result<KeyScheduleHandshake, Error> {
         let mut extensions = Vec::new();
 
-        // Prepare key exchange; the caller already found the matching SupportedKxGroup
-        let (share, kxgroup) = share_and_kxgroup;
-        debug_assert_eq!(kxgroup.name(), share.group);
-        let ckx = kxgroup
-            .start_and_complete(&share.payload.0)
-            .map_err(|err| {
-                cx.common
-                    .send_fatal_alert(AlertDescription::IllegalParameter, err)
-            })?;
-        cx.common.kx_state.complete();
-
-        extensions.push(ServerExtension::KeyShare(KeyShareEntry::new(
-            ckx.group,
-            ckx.pub_key,
-        )));
-        extensions.push(ServerExtension::SupportedVersions(ProtocolVersion::TLSv1_3));
+        // Prepare key exchange; the caller already found the matching SupportedKxGroup.
+        // There is none for the "psk_ke" key exchange mode.
+        let shared_secret = match share_and_kxgroup {
+            Some((share, kxgroup)) => {
+                debug_assert_eq!(kxgroup.name(), share.group);
+                let ckx = kxgroup
+                    .start_and_complete(&share.payload.0)
+                    .map_err(|err| {
+                        cx.common
+                            .send_fatal_alert(AlertDescription::IllegalParameter, err)
+                    })?;
+                cx.common.kx_state.complete();
+
+                extensions.push(ServerExtension::KeyShare(KeyShareEntry::new(
+                    ckx.group,
+                    ckx.pub_key,
+                )));
+                Some(ckx.secret)
+            }
+            None => {
+                cx.common.kx_state = KxState::None;
+                None
+            }
+        };
+        extensions.push(ServerExtension::SupportedVersions(
+            cx.common.protocol.tls13_version(),
+        ));
 
         if let Some(psk_idx) = chosen_psk_idx {
             extensions.push(ServerExtension::PresharedKey(psk_idx as u16));
         }
 
-        let sh = Message {
-            version: ProtocolVersion::TLSv1_2,
-            payload: MessagePayload::handshake(HandshakeMessagePayload {
-                typ: HandshakeType::ServerHello,
-                payload: HandshakePayload::ServerHello(ServerHelloPayload {
-                    legacy_version: ProtocolVersion::TLSv1_2,
-                    random: Random::from(randoms.server),
-                    session_id: *session_id,
-                    cipher_suite: suite.common.suite,
-                    compression_method: Compression::Null,
-                    extensions,
-                }),
-            }),
-        };
-
-        cx.common.check_aligned_handshake()?;
-
         let client_hello_hash = transcript.hash_given(&[]);
 
-        trace!("sending server hello {:?}", sh);
-        transcript.add_message(&sh);
-        cx.common.send_msg(sh, false);
-
         // Start key schedule
-        let key_schedule_pre_handshake = if let Some(psk) = resuming_psk {
-            let early_key_schedule = KeyScheduleEarly::new(suite, psk);
+        let key_schedule_pre_handshake = if let Some(psk) = psk {
+            let early_key_schedule = KeyScheduleEarly::new(suite, psk, cx.common.protocol);
             early_key_schedule.client_early_traffic_secret(
                 &client_hello_hash,
                 &*config.key_log,
@@ -541,13 +903,64 @@ mod client_hello {
                 cx.common,
             );
 
+            // If 0-RTT should be rejected, this will be clobbered by ExtensionProcessing.
+            if client_hello.early_data_extension_offered() {
+                cx.common.early_exporter = Some(early_key_schedule.early_exporter(
+                    &client_hello_hash,
+                    &*config.key_log,
+                    &randoms.client,
+                ));
+            }
+
             KeySchedulePreHandshake::from(early_key_schedule)
         } else {
-            KeySchedulePreHandshake::new(suite)
+            KeySchedulePreHandshake::new(suite, cx.common.protocol)
         };
 
         // Do key exchange
-        let key_schedule = key_schedule_pre_handshake.into_handshake(ckx.secret);
+        let mut key_schedule = match shared_secret {
+            Some(shared_secret) => key_schedule_pre_handshake.into_handshake(shared_secret),
+            None => key_schedule_pre_handshake.into_handshake_without_dhe(),
+        };
+
+        let mut sh = ServerHelloPayload {
+            legacy_version: cx.common.protocol.legacy_version(),
+            random: Random::from(randoms.server),
+            session_id: client_hello.session_id,
+            cipher_suite: suite.common.suite,
+            compression_method: Compression::Null,
+            extensions,
+        };
+
+        if let Some(ech) = ech {
+            // Signal acceptance of ECH in the last 8 bytes of the server random.
+            // See draft-ietf-tls-esni-18 section 7.2.
+            let mut conf = Vec::new();
+            HandshakeMessagePayload {
+                typ: HandshakeType::ServerHello,
+                payload: HandshakePayload::ServerHello(sh.clone()),
+            }
+            .payload_encode(&mut conf, Encoding::EchConfirmation);
+
+            let confirmation = key_schedule
+                .server_ech_confirmation_secret(&ech.inner_random.0, transcript.hash_given(&conf));
+            randoms.server[24..].copy_from_slice(&confirmation);
+            sh.random = Random::from(randoms.server);
+        }
+
+        let sh = Message {
+            version: cx.common.protocol.legacy_version(),
+            payload: MessagePayload::handshake(HandshakeMessagePayload {
+                typ: HandshakeType::ServerHello,
+                payload: HandshakePayload::ServerHello(sh),
+            }),
+        };
+
+        cx.common.check_aligned_handshake()?;
+
+        trace!("sending server hello {:?}", sh);
+        transcript.add_message(&sh);
+        cx.common.send_msg(sh, false);
 
         let handshake_hash = transcript.current_hash();
         let key_schedule = key_schedule.derive_server_handshake_secrets(
@@ -561,7 +974,8 @@ mod client_hello {
     }
 
     fn emit_fake_ccs(common: &mut CommonState) {
-        if common.is_quic() {
+        // DTLS 1.3 has no middlebox compatibility mode: see RFC 9147 section 5.
+        if common.is_quic() || common.is_dtls() {
             return;
         }
         let m = Message {
@@ -571,27 +985,129 @@ mod client_hello {
         common.send_msg(m, false);
     }
 
-    fn emit_hello_retry_request(
-        transcript: &mut HandshakeHash,
+    /// Build a `HelloRetryRequest` asking for a key share for `group` and/or
+    /// carrying `cookie`.
+    fn hello_retry_request(
         suite: &'static Tls13CipherSuite,
         session_id: SessionId,
-        common: &mut CommonState,
-        group: NamedGroup,
-    ) {
+        protocol: Protocol,
+        group: Option<NamedGroup>,
+        cookie: Option<&[u8]>,
+    ) -> HelloRetryRequest {
         let mut req = HelloRetryRequest {
-            legacy_version: ProtocolVersion::TLSv1_2,
+            legacy_version: protocol.legacy_version(),
             session_id,
             cipher_suite: suite.common.suite,
             extensions: Vec::new(),
         };
 
-        req.extensions
-            .push(HelloRetryExtension::KeyShare(group));
+        if let Some(group) = group {
+            req.extensions
+                .push(HelloRetryExtension::KeyShare(group));
+        }
+        if let Some(cookie) = cookie {
+            req.extensions
+                .push(HelloRetryExtension::Cookie(PayloadU16::new(
+                    cookie.to_vec(),
+                )));
+        }
         req.extensions
             .push(HelloRetryExtension::SupportedVersions(
-                ProtocolVersion::TLSv1_3,
+                protocol.tls13_version(),
             ));
 
+        req
+    }
+
+    /// Answer `chm`, a DTLS `ClientHello` without a cookie, with a `HelloRetryRequest`
+    /// carrying one.
+    ///
+    /// The cookie holds everything needed to continue the handshake from the client's
+    /// next `ClientHello`, so the server keeps no state.  See RFC 9147 section 5.1.
+    pub(in crate::server) fn emit_stateless_hello_retry_request(
+        suite: &'static Tls13CipherSuite,
+        selected_kxg: &'static dyn SupportedKxGroup,
+        chm: &Message<'_>,
+        client_hello: &ClientHelloPayload,
+        now: UnixTime,
+        common: &mut CommonState,
+    ) {
+        let Some(cookie_state) = &mut common.dtls.cookie else {
+            return;
+        };
+
+        // Ask for a key share only if the client did not offer one we can use.
+        let has_share = client_hello
+            .keyshare_extension()
+            .is_some_and(|shares| {
+                shares
+                    .iter()
+                    .any(|share| share.group == selected_kxg.name())
+            });
+        let group = (!has_share).then(|| selected_kxg.name());
+
+        let mut transcript = HandshakeHashBuffer::new().start_hash(suite.common.hash_provider);
+        transcript.add_message(chm);
+        let cookie = Cookie {
+            issued: now,
+            suite: suite.common.suite,
+            group,
+            ch1_hash: transcript
+                .current_hash()
+                .as_ref()
+                .to_vec(),
+        };
+        let sealed = cookie_state.seal(&cookie, suite);
+        cookie_state.sent_retry = true;
+
+        emit_hello_retry_request(
+            &mut transcript,
+            suite,
+            client_hello.session_id,
+            common,
+            group,
+            Some(&sealed),
+            None,
+        );
+    }
+
+    fn emit_hello_retry_request(
+        transcript: &mut HandshakeHash,
+        suite: &'static Tls13CipherSuite,
+        session_id: SessionId,
+        common: &mut CommonState,
+        group: Option<NamedGroup>,
+        cookie: Option<&[u8]>,
+        ech: Option<&EchAccepted>,
+    ) {
+        let mut req = hello_retry_request(suite, session_id, common.protocol, group, cookie);
+
+        transcript.rollup_for_hrr();
+
+        if let Some(ech) = ech {
+            // Signal acceptance of ECH in a confirmation extension.
+            // See draft-ietf-tls-esni-18 section 7.2.1.
+            req.extensions
+                .push(HelloRetryExtension::EchHelloRetryRequest(vec![0; 8]));
+
+            let mut conf = Vec::new();
+            HandshakeMessagePayload {
+                typ: HandshakeType::HelloRetryRequest,
+                payload: HandshakePayload::HelloRetryRequest(req.clone()),
+            }
+            .payload_encode(&mut conf, Encoding::EchConfirmation);
+
+            let confirmation = server_ech_hrr_confirmation_secret(
+                suite.hkdf_provider,
+                &ech.inner_random.0,
+                transcript.hash_given(&conf),
+            );
+            if let Some(HelloRetryExtension::EchHelloRetryRequest(ext)) = req.extensions.last_mut()
+            {
+                *ext = confirmation.to_vec();
+            }
+        }
+
         let m = Message {
             version: ProtocolVersion::TLSv1_2,
             payload: MessagePayload::handshake(HandshakeMessagePayload {
@@ -601,7 +1117,6 @@ mod client_hello {
         };
 
         trace!("Requesting retry {:?}", m);
-        transcript.rollup_for_hrr();
         transcript.add_message(&m);
         common.send_msg(m, false);
         common.handshake_kind = Some(HandshakeKind::FullWithHelloRetryRequest);
@@ -611,9 +1126,10 @@ mod client_hello {
         cx: &mut ServerContext<'_>,
         client_hello: &ClientHelloPayload,
         resumedata: Option<&persist::ServerSessionValue>,
+        psk_binder: Option<&[u8]>,
         suite: &'static Tls13CipherSuite,
         config: &ServerConfig,
-    ) -> EarlyDataDecision {
+    ) -> Result<EarlyDataDecision, Error> {
         let early_data_requested = client_hello.early_data_extension_offered();
         let rejected_or_disabled = match early_data_requested {
             true => EarlyDataDecision::RequestedButRejected,
@@ -622,12 +1138,21 @@ mod client_hello {
 
         let Some(resume) = resumedata else {
             // never any early data if not resuming.
-            return rejected_or_disabled;
+            return Ok(rejected_or_disabled);
         };
 
         /* Non-zero max_early_data_size controls whether early_data is allowed at all.
-         * We also require stateful resumption. */
-        let early_data_configured = config.max_early_data_size > 0 && !config.ticketer.enabled();
+         * We also require stateful resumption, or protection against replays of
+         * stateless tickets (RFC8446 section 8). */
+        let stateless = config.ticketer.enabled();
+        let anti_replay = match stateless {
+            true => config.anti_replay.as_deref(),
+            false => None,
+        };
+        let early_data_configured = config.max_early_data_size > 0
+            && (!stateless || anti_replay.is_some())
+            // Our DTLS connections do not accept early data.
+            && !cx.common.is_dtls();
 
         /* "For PSKs provisioned via NewSessionTicket, a server MUST validate
          *  that the ticket age for the selected PSK identity (computed by
@@ -644,22 +1169,48 @@ mod client_hello {
          *  - The selected cipher suite
          *  - The selected ALPN [RFC7301] protocol, if any"
          *
-         * (RFC8446, 4.2.10) */
+         * (RFC8446, 4.2.10)
+         *
+         * With an anti-replay register, the tolerance is its window instead. */
+        let is_fresh = match anti_replay {
+            Some(anti_replay) => resume.is_fresh_within(anti_replay.window()),
+            None => resume.is_fresh(),
+        };
         let early_data_possible = early_data_requested
-            && resume.is_fresh()
+            && is_fresh
             && Some(resume.version) == cx.common.negotiated_version
             && resume.cipher_suite == suite.common.suite
             && resume.alpn.as_ref().map(|x| &x.0) == cx.common.alpn_protocol.as_ref();
 
-        if early_data_configured && early_data_possible && !cx.data.early_data.was_rejected() {
-            EarlyDataDecision::Accepted
+        /* "[...] the server [...] checks to see if it has recorded a matching
+         *  ClientHello.  If one is found, it either aborts the handshake with an
+         *  "illegal_parameter" alert or accepts the PSK but rejects 0-RTT.  If no
+         *  matching ClientHello is found, then it accepts 0-RTT and then stores
+         *  the ClientHello for as long as the expected_arrival_time is inside
+         *  the window." (RFC8446, 8.2)
+         *
+         * We record ClientHellos last, so only those we would otherwise accept
+         * take up space. */
+        let accept = early_data_configured
+            && early_data_possible
+            && !cx.data.early_data.was_rejected()
+            && match (anti_replay, psk_binder) {
+                (None, _) => true,
+                (Some(anti_replay), Some(binder)) => {
+                    anti_replay.record(binder, config.current_time()?)
+                }
+                (Some(_), None) => false,
+            };
+
+        if accept {
+            Ok(EarlyDataDecision::Accepted)
         } else {
             if cx.common.is_quic() {
                 // Clobber value set in tls13::emit_server_hello
                 cx.common.quic.early_secret = None;
             }
 
-            rejected_or_disabled
+            Ok(rejected_or_disabled)
         }
     }
 
@@ -668,17 +1219,47 @@ mod client_hello {
         suite: &'static Tls13CipherSuite,
         cx: &mut ServerContext<'_>,
         ocsp_response: &mut Option<&[u8]>,
+        sct_list: &mut Option<&[u8]>,
         hello: &ClientHelloPayload,
         resumedata: Option<&persist::ServerSessionValue>,
+        psk_binder: Option<&[u8]>,
         extra_exts: Vec<ServerExtension>,
+        ech: &EchState,
         config: &ServerConfig,
-    ) -> Result<EarlyDataDecision, Error> {
+    ) -> Result<(EarlyDataDecision, Option<u16>), Error> {
         let mut ep = hs::ExtensionProcessing::new();
-        ep.process_common(config, cx, ocsp_response, hello, resumedata, extra_exts)?;
+        ep.process_common(
+            config,
+            cx,
+            ocsp_response,
+            sct_list,
+            hello,
+            resumedata,
+            extra_exts,
+        )?;
+
+        if let EchState::Rejected = ech {
+            // Give the client configurations it can retry with.
+            // See draft-ietf-tls-esni-18 section 7.1.
+            ep.exts
+                .push(ServerExtension::EncryptedClientHello(
+                    ServerEncryptedClientHello {
+                        retry_configs: config
+                            .ech_keys
+                            .iter()
+                            .map(|key| key.config().clone())
+                            .collect(),
+                    },
+                ));
+        }
 
-        let early_data = decide_if_early_data_allowed(cx, hello, resumedata, suite, config);
+        let early_data =
+            decide_if_early_data_allowed(cx, hello, resumedata, psk_binder, suite, config)?;
         if early_data == EarlyDataDecision::Accepted {
             ep.exts.push(ServerExtension::EarlyData);
+        } else {
+            // Clobber value set in tls13::emit_server_hello
+            cx.common.early_exporter = None;
         }
 
         let ee = HandshakeMessagePayload {
@@ -688,28 +1269,18 @@ mod client_hello {
 
         trace!("sending encrypted extensions {:?}", ee);
         flight.add(ee);
-        Ok(early_data)
+        Ok((early_data, ep.record_size_limit))
     }
 
     fn emit_certificate_req_tls13(
         flight: &mut HandshakeFlightTls13<'_>,
         config: &ServerConfig,
     ) -> Result<bool, Error> {
-        if !config.verifier.offer_client_auth() {
+        if !config.verifier.offer_client_auth() || config.verifier.defers_client_auth() {
             return Ok(false);
         }
 
-        let mut cr = CertificateRequestPayloadTls13 {
-            context: PayloadU8::empty(),
-            extensions: Vec::new(),
-        };
-
-        let schemes = config
-            .verifier
-            .supported_verify_schemes();
-        cr.extensions
-            .push(CertReqExtension::SignatureAlgorithms(schemes.to_vec()));
-
+        let mut cr = certificate_req_tls13(PayloadU8::empty(), config)?;
         if !config.cert_decompressors.is_empty() {
             cr.extensions
                 .push(CertReqExtension::CertificateCompressionAlgorithms(
@@ -721,12 +1292,6 @@ mod client_hello {
                 ));
         }
 
-        let authorities = config.verifier.root_hint_subjects();
-        if !authorities.is_empty() {
-            cr.extensions
-                .push(CertReqExtension::AuthorityNames(authorities.to_vec()));
-        }
-
         let creq = HandshakeMessagePayload {
             typ: HandshakeType::CertificateRequest,
             payload: HandshakePayload::CertificateRequestTls13(cr),
@@ -741,13 +1306,18 @@ mod client_hello {
         flight: &mut HandshakeFlightTls13<'_>,
         cert_chain: &[CertificateDer<'static>],
         ocsp_response: Option<&[u8]>,
+        sct_list: Option<&[u8]>,
+        delegated_credential: Option<&DelegatedCredential>,
+        custom_exts: &[UnknownExtension],
     ) {
         let cert = HandshakeMessagePayload {
             typ: HandshakeType::Certificate,
-            payload: HandshakePayload::CertificateTls13(CertificatePayloadTls13::new(
-                cert_chain.iter(),
-                ocsp_response,
-            )),
+            payload: HandshakePayload::CertificateTls13(
+                CertificatePayloadTls13::new(cert_chain.iter(), ocsp_response)
+                    .with_end_entity_sct_list(sct_list.and_then(read_sct_list))
+                    .with_end_entity_delegated_credential(delegated_credential.cloned())
+                    .with_end_entity_unknown_extensions(custom_exts),
+            ),
         };
 
         trace!("sending certificate {:?}", cert);
@@ -759,15 +1329,28 @@ mod client_hello {
         config: &ServerConfig,
         cert_chain: &[CertificateDer<'static>],
         ocsp_response: Option<&[u8]>,
+        sct_list: Option<&[u8]>,
+        delegated_credential: Option<&DelegatedCredential>,
+        custom_exts: &[UnknownExtension],
         cert_compressor: &'static dyn CertCompressor,
     ) {
-        let payload = CertificatePayloadTls13::new(cert_chain.iter(), ocsp_response);
+        let payload = CertificatePayloadTls13::new(cert_chain.iter(), ocsp_response)
+            .with_end_entity_sct_list(sct_list.and_then(read_sct_list))
+            .with_end_entity_delegated_credential(delegated_credential.cloned())
+            .with_end_entity_unknown_extensions(custom_exts);
 
         let Ok(entry) = config
             .cert_compression_cache
             .compression_for(cert_compressor, &payload)
         else {
-            return emit_certificate_tls13(flight, cert_chain, ocsp_response);
+            return emit_certificate_tls13(
+                flight,
+                cert_chain,
+                ocsp_response,
+                sct_list,
+                delegated_credential,
+                custom_exts,
+            );
         };
 
         let c = HandshakeMessagePayload {
@@ -779,12 +1362,15 @@ mod client_hello {
         flight.add(c);
     }
 
+    /// Emits our `CertificateVerify` message, unless signing is deferred to the application.
+    ///
+    /// In that case, the chosen scheme and message to sign are returned.
     fn emit_certificate_verify_tls13(
         flight: &mut HandshakeFlightTls13<'_>,
         common: &mut CommonState,
         signing_key: &dyn sign::SigningKey,
         schemes: &[SignatureScheme],
-    ) -> Result<(), Error> {
+    ) -> Result<Option<(SignatureScheme, VerifyMessage)>, Error> {
         let message = construct_server_verify_message(&flight.transcript.current_hash());
 
         let signer = signing_key
@@ -797,10 +1383,20 @@ mod client_hello {
             })?;
 
         let scheme = signer.scheme();
-        let sig = signer.sign(message.as_ref())?;
+        if signer.defers_signing() {
+            trace!("Deferring server signature");
+            return Ok(Some((scheme, message)));
+        }
 
-        let cv = DigitallySignedStruct::new(scheme, sig);
+        let sig = signer.sign(message.as_ref())?;
+        emit_signed_certificate_verify_tls13(flight, DigitallySignedStruct::new(scheme, sig));
+        Ok(None)
+    }
 
+    fn emit_signed_certificate_verify_tls13(
+        flight: &mut HandshakeFlightTls13<'_>,
+        cv: DigitallySignedStruct,
+    ) {
         let cv = HandshakeMessagePayload {
             typ: HandshakeType::CertificateVerify,
             payload: HandshakePayload::CertificateVerify(cv),
@@ -808,7 +1404,6 @@ mod client_hello {
 
         trace!("sending certificate-verify {:?}", cv);
         flight.add(cv);
-        Ok(())
     }
 
     fn emit_finished_tls13(
@@ -882,6 +1477,7 @@ struct ExpectCertificateOrCompressedCertificate {
     suite: &'static Tls13CipherSuite,
     key_schedule: KeyScheduleTrafficWithClientFinishedPending,
     send_tickets: usize,
+    post_handshake_auth: bool,
 }
 
 impl State<ServerConnectionData> for ExpectCertificateOrCompressedCertificate {
@@ -907,6 +1503,7 @@ impl State<ServerConnectionData> for ExpectCertificateOrCompressedCertificate {
                 suite: self.suite,
                 key_schedule: self.key_schedule,
                 send_tickets: self.send_tickets,
+                post_handshake_auth: self.post_handshake_auth,
                 message_already_in_transcript: false,
             })
             .handle(cx, m),
@@ -924,6 +1521,7 @@ impl State<ServerConnectionData> for ExpectCertificateOrCompressedCertificate {
                 suite: self.suite,
                 key_schedule: self.key_schedule,
                 send_tickets: self.send_tickets,
+                post_handshake_auth: self.post_handshake_auth,
             })
             .handle(cx, m),
 
@@ -949,6 +1547,7 @@ struct ExpectCompressedCertificate {
     suite: &'static Tls13CipherSuite,
     key_schedule: KeyScheduleTrafficWithClientFinishedPending,
     send_tickets: usize,
+    post_handshake_auth: bool,
 }
 
 impl State<ServerConnectionData> for ExpectCompressedCertificate {
@@ -1031,6 +1630,7 @@ impl State<ServerConnectionData> for ExpectCompressedCertificate {
             suite: self.suite,
             key_schedule: self.key_schedule,
             send_tickets: self.send_tickets,
+            post_handshake_auth: self.post_handshake_auth,
             message_already_in_transcript: true,
         })
         .handle(cx, m)
@@ -1047,6 +1647,7 @@ struct ExpectCertificate {
     suite: &'static Tls13CipherSuite,
     key_schedule: KeyScheduleTrafficWithClientFinishedPending,
     send_tickets: usize,
+    post_handshake_auth: bool,
     message_already_in_transcript: bool,
 }
 
@@ -1091,6 +1692,7 @@ impl State<ServerConnectionData> for ExpectCertificate {
                     key_schedule: self.key_schedule,
                     transcript: self.transcript,
                     send_tickets: self.send_tickets,
+                    post_handshake_auth: self.post_handshake_auth,
                 }));
             }
 
@@ -1117,6 +1719,7 @@ impl State<ServerConnectionData> for ExpectCertificate {
             key_schedule: self.key_schedule,
             client_cert: client_cert.into_owned(),
             send_tickets: self.send_tickets,
+            post_handshake_auth: self.post_handshake_auth,
         }))
     }
 
@@ -1132,6 +1735,7 @@ struct ExpectCertificateVerify {
     key_schedule: KeyScheduleTrafficWithClientFinishedPending,
     client_cert: CertificateChain<'static>,
     send_tickets: usize,
+    post_handshake_auth: bool,
 }
 
 impl State<ServerConnectionData> for ExpectCertificateVerify {
@@ -1175,6 +1779,7 @@ impl State<ServerConnectionData> for ExpectCertificateVerify {
             key_schedule: self.key_schedule,
             transcript: self.transcript,
             send_tickets: self.send_tickets,
+            post_handshake_auth: self.post_handshake_auth,
         }))
     }
 
@@ -1192,6 +1797,8 @@ struct ExpectEarlyData {
     suite: &'static Tls13CipherSuite,
     key_schedule: KeyScheduleTrafficWithClientFinishedPending,
     send_tickets: usize,
+    post_handshake_auth: bool,
+    record_size_limit: Option<u16>,
 }
 
 impl State<ServerConnectionData> for ExpectEarlyData {
@@ -1227,6 +1834,10 @@ impl State<ServerConnectionData> for ExpectEarlyData {
             } => {
                 self.key_schedule
                     .update_decrypter(cx.common);
+                if let Some(limit) = self.record_size_limit {
+                    cx.common
+                        .enforce_record_size_limit(limit);
+                }
                 self.transcript.add_message(&m);
                 Ok(Box::new(ExpectFinished {
                     config: self.config,
@@ -1234,6 +1845,7 @@ impl State<ServerConnectionData> for ExpectEarlyData {
                     key_schedule: self.key_schedule,
                     transcript: self.transcript,
                     send_tickets: self.send_tickets,
+                    post_handshake_auth: self.post_handshake_auth,
                 }))
             }
             payload => Err(inappropriate_handshake_message(
@@ -1281,6 +1893,7 @@ struct ExpectFinished {
     suite: &'static Tls13CipherSuite,
     key_schedule: KeyScheduleTrafficWithClientFinishedPending,
     send_tickets: usize,
+    post_handshake_auth: bool,
 }
 
 impl ExpectFinished {
@@ -1321,8 +1934,8 @@ impl ExpectFinished {
 
         let mut payload = NewSessionTicketPayloadTls13::new(lifetime, age_add, nonce, ticket);
 
-        if config.max_early_data_size > 0 {
-            if !stateless {
+        if config.max_early_data_size > 0 && !cx.common.is_dtls() {
+            if !stateless || config.anti_replay.is_some() {
                 payload
                     .exts
                     .push(NewSessionTicketExtension::EarlyData(
@@ -1330,11 +1943,19 @@ impl ExpectFinished {
                     ));
             } else {
                 // We implement RFC8446 section 8.1: by enforcing that 0-RTT is
-                // only possible if using stateful resumption
-                warn!("early_data with stateless resumption is not allowed");
+                // only possible if using stateful resumption, unless
+                // `ServerConfig::anti_replay` implements section 8.2
+                warn!("early_data with stateless resumption is not allowed without anti_replay");
             }
         }
 
+        if config.enable_grease {
+            let grease = Grease::new(secure_random)?;
+            payload
+                .exts
+                .push(NewSessionTicketExtension::Unknown(grease_extension(grease)));
+        }
+
         let t = HandshakeMessagePayload {
             typ: HandshakeType::NewSessionTicket,
             payload: HandshakePayload::NewSessionTicketTls13(payload),
@@ -1379,6 +2000,12 @@ impl State<ServerConnectionData> for ExpectFinished {
 
         cx.common.check_aligned_handshake()?;
 
+        // The transcript for any post-handshake authentication ends here,
+        // excluding the tickets we send.
+        let post_handshake_transcript = self
+            .post_handshake_auth
+            .then(|| self.transcript.clone());
+
         let handshake_hash = self.transcript.current_hash();
         let resumption = ResumptionSecret::new(&key_schedule_traffic, &handshake_hash);
 
@@ -1398,7 +2025,10 @@ impl State<ServerConnectionData> for ExpectFinished {
                 _fin_verified: fin,
             }),
             false => Box::new(ExpectTraffic {
+                config: self.config,
                 key_schedule: key_schedule_traffic,
+                post_handshake_transcript,
+                client_auth: PostHandshakeClientAuth::Idle,
                 _fin_verified: fin,
             }),
         })
@@ -1411,11 +2041,164 @@ impl State<ServerConnectionData> for ExpectFinished {
 
 // --- Process traffic ---
 struct ExpectTraffic {
+    config: Arc<ServerConfig>,
     key_schedule: KeyScheduleTraffic,
+    /// The transcript up to the client's `Finished`, if the client offered
+    /// post-handshake authentication.
+    post_handshake_transcript: Option<HandshakeHash>,
+    client_auth: PostHandshakeClientAuth,
     _fin_verified: verify::FinishedMessageVerified,
 }
 
+/// Progress of post-handshake client authentication.
+///
+/// See RFC 8446 section 4.6.2.
+enum PostHandshakeClientAuth {
+    Idle,
+    ExpectCertificate {
+        context: Vec<u8>,
+        transcript: HandshakeHash,
+    },
+    ExpectCertificateVerify {
+        transcript: HandshakeHash,
+        client_cert: CertificateChain<'static>,
+    },
+    ExpectFinished {
+        transcript: HandshakeHash,
+        client_cert: Option<CertificateChain<'static>>,
+    },
+}
+
 impl ExpectTraffic {
+    fn handle_client_auth(
+        &mut self,
+        cx: &mut ServerContext<'_>,
+        m: Message<'_>,
+    ) -> Result<(), Error> {
+        self.client_auth = match mem::replace(&mut self.client_auth, PostHandshakeClientAuth::Idle)
+        {
+            PostHandshakeClientAuth::Idle => {
+                return Err(inappropriate_handshake_message(
+                    &m.payload,
+                    &[ContentType::ApplicationData, ContentType::Handshake],
+                    &[HandshakeType::KeyUpdate],
+                ));
+            }
+            PostHandshakeClientAuth::ExpectCertificate {
+                context,
+                mut transcript,
+            } => {
+                transcript.add_message(&m);
+                let certp = require_handshake_msg_move!(
+                    m,
+                    HandshakeType::Certificate,
+                    HandshakePayload::CertificateTls13
+                )?;
+
+                if certp.context.0 != context {
+                    return Err(cx.common.send_fatal_alert(
+                        AlertDescription::IllegalParameter,
+                        PeerMisbehaved::IncorrectCertificateRequestContext,
+                    ));
+                }
+
+                // We don't send any CertificateRequest extensions, so any extensions
+                // here are illegal.
+                if certp.any_entry_has_extension() {
+                    return Err(PeerMisbehaved::UnsolicitedCertExtension.into());
+                }
+
+                let client_cert = certp.into_certificate_chain();
+                let Some((end_entity, intermediates)) = client_cert.split_first() else {
+                    if self
+                        .config
+                        .verifier
+                        .client_auth_mandatory()
+                    {
+                        return Err(cx.common.send_fatal_alert(
+                            AlertDescription::CertificateRequired,
+                            Error::NoCertificatesPresented,
+                        ));
+                    }
+
+                    debug!("post-handshake client auth requested but no certificate supplied");
+                    self.client_auth = PostHandshakeClientAuth::ExpectFinished {
+                        transcript,
+                        client_cert: None,
+                    };
+                    return Ok(());
+                };
+
+                let now = self.config.current_time()?;
+                self.config
+                    .verifier
+                    .verify_client_cert(end_entity, intermediates, now)
+                    .map_err(|err| {
+                        cx.common
+                            .send_cert_verify_error_alert(err)
+                    })?;
+
+                PostHandshakeClientAuth::ExpectCertificateVerify {
+                    transcript,
+                    client_cert: client_cert.into_owned(),
+                }
+            }
+            PostHandshakeClientAuth::ExpectCertificateVerify {
+                mut transcript,
+                client_cert,
+            } => {
+                let sig = require_handshake_msg!(
+                    m,
+                    HandshakeType::CertificateVerify,
+                    HandshakePayload::CertificateVerify
+                )?;
+                let msg = construct_client_verify_message(&transcript.current_hash());
+                self.config
+                    .verifier
+                    .verify_tls13_signature(msg.as_ref(), &client_cert[0], sig)
+                    .map_err(|err| {
+                        cx.common
+                            .send_cert_verify_error_alert(err)
+                    })?;
+
+                trace!("post-handshake client CertificateVerify OK");
+                transcript.add_message(&m);
+                PostHandshakeClientAuth::ExpectFinished {
+                    transcript,
+                    client_cert: Some(client_cert),
+                }
+            }
+            PostHandshakeClientAuth::ExpectFinished {
+                transcript,
+                client_cert,
+            } => {
+                let finished =
+                    require_handshake_msg!(m, HandshakeType::Finished, HandshakePayload::Finished)?;
+
+                let expect_verify_data = self
+                    .key_schedule
+                    .sign_post_handshake_client_finish(&transcript.current_hash());
+                if !bool::from(ConstantTimeEq::ct_eq(
+                    expect_verify_data.as_ref(),
+                    finished.bytes(),
+                )) {
+                    return Err(cx
+                        .common
+                        .send_fatal_alert(AlertDescription::DecryptError, Error::DecryptError));
+                }
+
+                cx.common.check_aligned_handshake()?;
+
+                if let Some(client_cert) = client_cert {
+                    cx.common.peer_certificates = Some(client_cert);
+                }
+                PostHandshakeClientAuth::Idle
+            }
+        };
+
+        Ok(())
+    }
+
     fn handle_key_update(
         &mut self,
         common: &mut CommonState,
@@ -1463,6 +2246,7 @@ impl State<ServerConnectionData> for ExpectTraffic {
                     },
                 ..
             } => self.handle_key_update(cx.common, &key_update)?,
+            MessagePayload::Handshake { .. } => self.handle_client_auth(cx, m)?,
             payload => {
                 return Err(inappropriate_handshake_message(
                     &payload,
@@ -1490,16 +2274,133 @@ impl State<ServerConnectionData> for ExpectTraffic {
             .extract_secrets(Side::Server)
     }
 
+    fn into_kernel_state(self: Box<Self>) -> Result<Box<dyn KernelState + 'static>, Error> {
+        Ok(self)
+    }
+
     fn send_key_update_request(&mut self, common: &mut CommonState) -> Result<(), Error> {
         self.key_schedule
             .request_key_update_and_update_encrypter(common)
     }
 
+    fn request_client_certificate(&mut self, common: &mut CommonState) -> Result<(), Error> {
+        let Some(transcript) = &self.post_handshake_transcript else {
+            return Err(PeerIncompatible::PostHandshakeAuthNotOffered.into());
+        };
+
+        if !self.config.verifier.offer_client_auth() {
+            return Err(Error::General(
+                "client authentication is not enabled".into(),
+            ));
+        }
+
+        if !matches!(self.client_auth, PostHandshakeClientAuth::Idle) {
+            return Err(Error::General(
+                "client authentication already requested".into(),
+            ));
+        }
+
+        common.check_aligned_handshake()?;
+
+        let context = rand::random_vec(self.config.provider.secure_random, 32)?;
+        let m = Message {
+            version: ProtocolVersion::TLSv1_3,
+            payload: MessagePayload::handshake(HandshakeMessagePayload {
+                typ: HandshakeType::CertificateRequest,
+                payload: HandshakePayload::CertificateRequestTls13(certificate_req_tls13(
+                    PayloadU8::new(context.clone()),
+                    &self.config,
+                )?),
+            }),
+        };
+        trace!("Sending post-handshake CertificateRequest {:?}", m);
+
+        let mut transcript = transcript.clone();
+        transcript.add_message(&m);
+        common.send_msg(m, true);
+
+        self.client_auth = PostHandshakeClientAuth::ExpectCertificate {
+            context,
+            transcript,
+        };
+        Ok(())
+    }
+
+    fn client_certificate_requested(&self) -> bool {
+        !matches!(self.client_auth, PostHandshakeClientAuth::Idle)
+    }
+
     fn into_owned(self: Box<Self>) -> hs::NextState<'static> {
         self
     }
 }
 
+impl KernelState for ExpectTraffic {
+    fn update_secrets(&mut self, direction: Direction) -> Result<ConnectionTrafficSecrets, Error> {
+        self.key_schedule
+            .refresh_traffic_secrets(direction.secret_side(Side::Server))
+    }
+
+    fn handle_new_session_ticket(
+        &mut self,
+        _cx: &KernelContext<'_>,
+        _ticket: &NewSessionTicketPayloadTls13,
+    ) -> Result<(), Error> {
+        Err(Error::InappropriateHandshakeMessage {
+            expect_types: vec![HandshakeType::KeyUpdate],
+            got_type: HandshakeType::NewSessionTicket,
+        })
+    }
+}
+
+/// Make a TLS1.3 `CertificateRequest` with the given `context`.
+///
+/// This does not offer certificate compression.
+fn certificate_req_tls13(
+    context: PayloadU8,
+    config: &ServerConfig,
+) -> Result<CertificateRequestPayloadTls13, Error> {
+    let grease = match config.enable_grease {
+        true => Some(Grease::new(config.provider.secure_random)?),
+        false => None,
+    };
+
+    let mut cr = CertificateRequestPayloadTls13 {
+        context,
+        extensions: Vec::new(),
+    };
+
+    let mut schemes = config
+        .verifier
+        .supported_verify_schemes();
+    if let Some(grease) = grease {
+        schemes.insert(0, grease.signature_scheme());
+    }
+    cr.extensions
+        .push(CertReqExtension::SignatureAlgorithms(schemes));
+
+    let authorities = config.verifier.root_hint_subjects();
+    if !authorities.is_empty() {
+        cr.extensions
+            .push(CertReqExtension::AuthorityNames(authorities.to_vec()));
+    }
+
+    if let Some(grease) = grease {
+        cr.extensions
+            .push(CertReqExtension::Unknown(grease_extension(grease)));
+    }
+
+    Ok(cr)
+}
+
+/// An empty GREASE extension, for server messages.
+fn grease_extension(grease: Grease) -> UnknownExtension {
+    UnknownExtension {
+        typ: grease.extensions()[0],
+        payload: Payload::new(Vec::new()),
+    }
+}
+
 struct ExpectQuicTraffic {
     key_schedule: KeyScheduleTraffic,
     _fin_verified: verify::FinishedMessageVerified,
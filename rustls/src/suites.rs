@@ -124,7 +124,8 @@ impl SupportedCipherSuite {
     /// Return true if this suite is usable for the given [`Protocol`].
     ///
     /// All cipher suites are usable for TCP-TLS.  Only TLS1.3 suites
-    /// with `Tls13CipherSuite::quic` provided are usable for QUIC.
+    /// with `Tls13CipherSuite::quic` provided are usable for QUIC, and
+    /// only those with `Tls13CipherSuite::dtls` provided for DTLS.
     pub(crate) fn usable_for_protocol(&self, proto: Protocol) -> bool {
         match proto {
             Protocol::Tcp => true,
@@ -132,6 +133,10 @@ impl SupportedCipherSuite {
                 .tls13()
                 .and_then(|cs| cs.quic)
                 .is_some(),
+            Protocol::Dtls => self
+                .tls13()
+                .and_then(|cs| cs.dtls)
+                .is_some(),
         }
     }
 
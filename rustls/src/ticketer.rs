@@ -5,8 +5,12 @@ use core::mem;
 use std::sync::{RwLock, RwLockReadGuard};
 
 use pki_types::UnixTime;
+#[cfg(feature = "std")]
+use zeroize::Zeroizing;
 
 use crate::lock::{Mutex, MutexGuard};
+#[cfg(feature = "std")]
+use crate::log::debug;
 use crate::server::ProducesTickets;
 #[cfg(not(feature = "std"))]
 use crate::time_provider::TimeProvider;
@@ -362,3 +366,258 @@ impl core::fmt::Debug for TicketRotator {
             .finish_non_exhaustive()
     }
 }
+
+/// The HKDF-Expand `info` used to derive a ticket encryption key from a
+/// [`TicketKey::secret()`], as described for [`SharedKeyTicketer`].
+#[cfg(feature = "std")]
+pub(crate) const SHARED_TICKET_KEY_LABEL: &[u8] = b"rustls shared ticket key";
+
+/// Identified key material for a [`SharedKeyTicketer`].
+///
+/// This is typically produced by a key distribution system, so that every
+/// server sharing the same keys can decrypt each other's tickets.
+///
+/// `id` prefixes every ticket encrypted using this key, so must be unique
+/// across all keys in use at once.  It is visible to anyone who sees a ticket,
+/// so should be random rather than (for example) a counter.  `secret` must be
+/// uniformly random.
+#[cfg(feature = "std")]
+pub struct TicketKey {
+    id: [u8; 16],
+    secret: Zeroizing<[u8; 32]>,
+}
+
+#[cfg(feature = "std")]
+impl TicketKey {
+    /// Make a new `TicketKey` with the given `id` and `secret`.
+    pub fn new(id: [u8; 16], secret: [u8; 32]) -> Self {
+        Self {
+            id,
+            secret: Zeroizing::new(secret),
+        }
+    }
+
+    /// The identifier which prefixes tickets encrypted with this key.
+    pub fn id(&self) -> [u8; 16] {
+        self.id
+    }
+
+    /// The secret key material.
+    pub fn secret(&self) -> &[u8; 32] {
+        &self.secret
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::fmt::Debug for TicketKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // Note: we deliberately omit the secret from the debug output.
+        f.debug_struct("TicketKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The keys used by a [`SharedKeyTicketer`] at one time.
+///
+/// To rotate keys across a fleet of servers without rejecting valid
+/// tickets, a key should be distributed as `next` before it becomes
+/// `current`, and kept as `previous` afterwards for at least the ticket
+/// lifetime.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct TicketKeys {
+    /// The key used to encrypt new tickets.
+    pub current: TicketKey,
+
+    /// A key accepted for decryption, which is about to become `current`.
+    ///
+    /// This allows a server to accept tickets from servers which
+    /// have already switched to this key.
+    pub next: Option<TicketKey>,
+
+    /// A key accepted for decryption, which used to be `current`.
+    pub previous: Option<TicketKey>,
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug)]
+struct KeyedTicketer {
+    id: [u8; 16],
+    ticketer: Box<dyn ProducesTickets>,
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug)]
+struct SharedKeyTicketerState {
+    current: KeyedTicketer,
+    next: Option<KeyedTicketer>,
+    previous: Option<KeyedTicketer>,
+}
+
+/// A ticketer using externally supplied [`TicketKeys`].
+///
+/// Unlike [`TicketRotator`], this does not generate its own keys: every
+/// server given the same keys can decrypt the others' tickets, which allows
+/// resumption across a fleet of servers behind a load balancer.  The keys
+/// can be replaced at any time using [`SharedKeyTicketer::set_keys()`].
+///
+/// Each ticket starts with the [`TicketKey::id()`] of the key which
+/// encrypted it, as in [RFC 5077 §4].  This is used to choose the key for
+/// decryption.  The id of the key which decrypted a client's ticket is
+/// available from `ServerConnection::resumption_ticket_key_id()`.
+///
+/// The providers' `Ticketer::with_shared_keys()` functions make one of these,
+/// using the same construction so that servers using different providers can
+/// decrypt each other's tickets.  For a [`TicketKey`] with `id` and `secret`:
+///
+/// - the encryption key is HKDF-Expand-SHA256(`secret`, `"rustls shared ticket key"`, 32),
+///   using `secret` as the pseudorandom key, and
+/// - a ticket containing `plaintext` is `id || nonce || ciphertext || tag`, where `nonce`
+///   is 12 random bytes, and `ciphertext || tag` is the AES-256-GCM encryption of
+///   `plaintext` using that key and `nonce`, with `id` as the additional data.
+///
+/// [RFC 5077 §4]: https://www.rfc-editor.org/rfc/rfc5077#section-4
+#[cfg(feature = "std")]
+pub struct SharedKeyTicketer {
+    generator: fn(&TicketKey) -> Result<Box<dyn ProducesTickets>, Error>,
+    lifetime: u32,
+    state: RwLock<SharedKeyTicketerState>,
+}
+
+#[cfg(feature = "std")]
+impl SharedKeyTicketer {
+    /// Creates a new `SharedKeyTicketer`, initially using `keys`.
+    ///
+    /// `lifetime` is in seconds, and is the ticket lifetime hint given
+    /// to clients.  It should be no longer than tickets are accepted for,
+    /// given how keys are rotated.
+    ///
+    /// `generator` produces a `ProducesTickets` implementation encrypting
+    /// using a single key.  The tickets it produces must start with the
+    /// key's [`TicketKey::id()`], and it must accept tickets of any length
+    /// encrypted by another instance using the same key.
+    ///
+    /// This fails if any two of `keys` have the same [`TicketKey::id()`].
+    pub fn new(
+        lifetime: u32,
+        keys: TicketKeys,
+        generator: fn(&TicketKey) -> Result<Box<dyn ProducesTickets>, Error>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            generator,
+            lifetime,
+            state: RwLock::new(Self::make_state(generator, &keys)?),
+        })
+    }
+
+    /// Replace the keys in use.
+    ///
+    /// This fails if any two of `keys` have the same [`TicketKey::id()`],
+    /// or if the lock on the keys is poisoned, in which case the keys in use
+    /// are unchanged.
+    ///
+    /// This takes effect for all following ticket encryptions and
+    /// decryptions, including those for connections already in progress.
+    pub fn set_keys(&self, keys: TicketKeys) -> Result<(), Error> {
+        let state = Self::make_state(self.generator, &keys)?;
+        *self
+            .state
+            .write()
+            .map_err(|_| Error::General("ticket key lock poisoned".into()))? = state;
+        Ok(())
+    }
+
+    fn make_state(
+        generator: fn(&TicketKey) -> Result<Box<dyn ProducesTickets>, Error>,
+        keys: &TicketKeys,
+    ) -> Result<SharedKeyTicketerState, Error> {
+        let ids = [
+            Some(&keys.current),
+            keys.next.as_ref(),
+            keys.previous.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|key| key.id)
+        .collect::<Vec<_>>();
+        if (1..ids.len()).any(|i| ids[i..].contains(&ids[i - 1])) {
+            return Err(Error::General("duplicate ticket key id".into()));
+        }
+
+        let make = |key: &TicketKey| -> Result<KeyedTicketer, Error> {
+            Ok(KeyedTicketer {
+                id: key.id,
+                ticketer: generator(key)?,
+            })
+        };
+
+        Ok(SharedKeyTicketerState {
+            current: make(&keys.current)?,
+            next: keys
+                .next
+                .as_ref()
+                .map(make)
+                .transpose()?,
+            previous: keys
+                .previous
+                .as_ref()
+                .map(make)
+                .transpose()?,
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl ProducesTickets for SharedKeyTicketer {
+    fn lifetime(&self) -> u32 {
+        self.lifetime
+    }
+
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn encrypt(&self, message: &[u8]) -> Option<Vec<u8>> {
+        self.state
+            .read()
+            .ok()?
+            .current
+            .ticketer
+            .encrypt(message)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        self.decrypt_with_key_id(ciphertext)
+            .map(|(plain, _)| plain)
+    }
+
+    fn decrypt_with_key_id(&self, ciphertext: &[u8]) -> Option<(Vec<u8>, Option<[u8; 16]>)> {
+        let alleged_id = ciphertext.get(..16)?;
+        let state = self.state.read().ok()?;
+
+        let Some(keyed) = [
+            Some(&state.current),
+            state.previous.as_ref(),
+            state.next.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|keyed| keyed.id == alleged_id) else {
+            debug!("rejected ticket with unknown key id");
+            return None;
+        };
+
+        let plain = keyed.ticketer.decrypt(ciphertext)?;
+        Some((plain, Some(keyed.id)))
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::fmt::Debug for SharedKeyTicketer {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SharedKeyTicketer")
+            .field("lifetime", &self.lifetime)
+            .finish_non_exhaustive()
+    }
+}
@@ -3,20 +3,23 @@
 use alloc::boxed::Box;
 use alloc::string::ToString;
 
-use crate::common_state::{CommonState, Side};
+use crate::common_state::{CommonState, Protocol, Side};
 use crate::crypto::cipher::{AeadKey, Iv, MessageDecrypter, Tls13AeadAlgorithm};
 use crate::crypto::tls13::{expand, Hkdf, HkdfExpander, OkmBlock, OutputLengthError};
 use crate::crypto::{hash, hmac, SharedSecret};
 use crate::error::Error;
 use crate::msgs::message::Message;
-use crate::suites::PartiallyExtractedSecrets;
-use crate::{quic, KeyLog, Tls13CipherSuite};
+use crate::suites::{ConnectionTrafficSecrets, PartiallyExtractedSecrets};
+use crate::{dtls, quic, KeyLog, Tls13CipherSuite};
 
 /// The kinds of secret we can extract from `KeySchedule`.
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum SecretKind {
     ResumptionPskBinderKey,
+    ExternalPskBinderKey,
+    ImportedPskBinderKey,
     ClientEarlyTrafficSecret,
+    EarlyExporterMasterSecret,
     ClientHandshakeTrafficSecret,
     ServerHandshakeTrafficSecret,
     ClientApplicationTrafficSecret,
@@ -33,7 +36,11 @@ impl SecretKind {
         use self::SecretKind::*;
         match self {
             ResumptionPskBinderKey => b"res binder",
+            ExternalPskBinderKey => b"ext binder",
+            // https://www.rfc-editor.org/rfc/rfc9258#section-5.1
+            ImportedPskBinderKey => b"imp binder",
             ClientEarlyTrafficSecret => b"c e traffic",
+            EarlyExporterMasterSecret => b"e exp master",
             ClientHandshakeTrafficSecret => b"c hs traffic",
             ServerHandshakeTrafficSecret => b"s hs traffic",
             ClientApplicationTrafficSecret => b"c ap traffic",
@@ -52,6 +59,7 @@ impl SecretKind {
         use self::SecretKind::*;
         Some(match self {
             ClientEarlyTrafficSecret => "CLIENT_EARLY_TRAFFIC_SECRET",
+            EarlyExporterMasterSecret => "EARLY_EXPORTER_SECRET",
             ClientHandshakeTrafficSecret => "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
             ServerHandshakeTrafficSecret => "SERVER_HANDSHAKE_TRAFFIC_SECRET",
             ClientApplicationTrafficSecret => "CLIENT_TRAFFIC_SECRET_0",
@@ -70,6 +78,8 @@ impl SecretKind {
 struct KeySchedule {
     current: Box<dyn HkdfExpander>,
     suite: &'static Tls13CipherSuite,
+    /// Chooses the label prefix: DTLS 1.3 uses `"dtls13"` rather than `"tls13 "`.
+    protocol: Protocol,
 }
 
 // We express the state of a contained KeySchedule using these
@@ -84,9 +94,9 @@ pub(crate) struct KeyScheduleEarly {
 }
 
 impl KeyScheduleEarly {
-    pub(crate) fn new(suite: &'static Tls13CipherSuite, secret: &[u8]) -> Self {
+    pub(crate) fn new(suite: &'static Tls13CipherSuite, secret: &[u8], protocol: Protocol) -> Self {
         Self {
-            ks: KeySchedule::new(suite, secret),
+            ks: KeySchedule::new(suite, secret, protocol),
         }
     }
 
@@ -120,6 +130,23 @@ impl KeyScheduleEarly {
         }
     }
 
+    pub(crate) fn early_exporter(
+        &self,
+        hs_hash: &hash::Output,
+        key_log: &dyn KeyLog,
+        client_random: &[u8; 32],
+    ) -> KeyScheduleEarlyExporter {
+        KeyScheduleEarlyExporter {
+            ks: KeySchedule::new_with_empty_secret(self.ks.suite, self.ks.protocol),
+            early_exporter_secret: self.ks.derive_logged_secret(
+                SecretKind::EarlyExporterMasterSecret,
+                hs_hash.as_ref(),
+                key_log,
+                client_random,
+            ),
+        }
+    }
+
     pub(crate) fn resumption_psk_binder_key_and_sign_verify_data(
         &self,
         hs_hash: &hash::Output,
@@ -130,6 +157,22 @@ impl KeyScheduleEarly {
         self.ks
             .sign_verify_data(&resumption_psk_binder_key, hs_hash)
     }
+
+    /// Compute the binder for an external PSK, which may be an imported PSK.
+    pub(crate) fn external_psk_binder_key_and_sign_verify_data(
+        &self,
+        imported: bool,
+        hs_hash: &hash::Output,
+    ) -> hmac::Tag {
+        let external_psk_binder_key = self
+            .ks
+            .derive_for_empty_hash(match imported {
+                true => SecretKind::ImportedPskBinderKey,
+                false => SecretKind::ExternalPskBinderKey,
+            });
+        self.ks
+            .sign_verify_data(&external_psk_binder_key, hs_hash)
+    }
 }
 
 /// Pre-handshake key schedule
@@ -141,9 +184,9 @@ pub(crate) struct KeySchedulePreHandshake {
 }
 
 impl KeySchedulePreHandshake {
-    pub(crate) fn new(suite: &'static Tls13CipherSuite) -> Self {
+    pub(crate) fn new(suite: &'static Tls13CipherSuite, protocol: Protocol) -> Self {
         Self {
-            ks: KeySchedule::new_with_empty_secret(suite),
+            ks: KeySchedule::new_with_empty_secret(suite, protocol),
         }
     }
 
@@ -155,6 +198,12 @@ impl KeySchedulePreHandshake {
             .input_secret(shared_secret.secret_bytes());
         KeyScheduleHandshakeStart { ks: self.ks }
     }
+
+    /// Continue without an (EC)DHE shared secret, for the `psk_ke` key exchange mode.
+    pub(crate) fn into_handshake_without_dhe(mut self) -> KeyScheduleHandshakeStart {
+        self.ks.input_empty();
+        KeyScheduleHandshakeStart { ks: self.ks }
+    }
 }
 
 impl From<KeyScheduleEarly> for KeySchedulePreHandshake {
@@ -271,6 +320,13 @@ impl KeyScheduleHandshakeStart {
             ));
         }
 
+        if common.is_dtls() {
+            common.dtls.handshake_secrets = Some(Box::new(dtls::Secrets {
+                client: client_secret.clone(),
+                server: server_secret.clone(),
+            }));
+        }
+
         KeyScheduleHandshake {
             ks: self.ks,
             client_handshake_traffic_secret: client_secret,
@@ -346,6 +402,13 @@ impl KeyScheduleHandshake {
             ));
         }
 
+        if common.is_dtls() {
+            common.dtls.traffic_secrets = Some(Box::new(dtls::Secrets {
+                client: _client_secret.clone(),
+                server: server_secret.clone(),
+            }));
+        }
+
         KeyScheduleTrafficWithClientFinishedPending {
             handshake_client_traffic_secret: self.client_handshake_traffic_secret,
             traffic,
@@ -401,6 +464,13 @@ impl KeyScheduleClientBeforeFinished {
             ));
         }
 
+        if common.is_dtls() {
+            common.dtls.traffic_secrets = Some(Box::new(dtls::Secrets {
+                client: client_secret.clone(),
+                server: server_secret.clone(),
+            }));
+        }
+
         self.traffic
     }
 }
@@ -444,6 +514,25 @@ impl KeyScheduleTrafficWithClientFinishedPending {
     }
 }
 
+/// The early exporter, available to a client sending early data and to a
+/// server accepting it.
+pub(crate) struct KeyScheduleEarlyExporter {
+    ks: KeySchedule,
+    early_exporter_secret: OkmBlock,
+}
+
+impl KeyScheduleEarlyExporter {
+    pub(crate) fn export_keying_material(
+        &self,
+        out: &mut [u8],
+        label: &[u8],
+        context: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        self.ks
+            .export_keying_material(&self.early_exporter_secret, out, label, context)
+    }
+}
+
 /// KeySchedule during traffic stage.  All traffic & exporter keys are guaranteed
 /// to be available.
 pub(crate) struct KeyScheduleTraffic {
@@ -497,12 +586,23 @@ impl KeyScheduleTraffic {
         self.ks.set_encrypter(&secret, common);
     }
 
+    /// Compute the verify_data of a client Finished message sent after the
+    /// handshake, during post-handshake authentication.
+    ///
+    /// This is keyed with the current client application traffic secret.
+    pub(crate) fn sign_post_handshake_client_finish(&self, hs_hash: &hash::Output) -> hmac::Tag {
+        self.ks
+            .sign_finish(&self.current_client_traffic_secret, hs_hash)
+    }
+
     pub(crate) fn request_key_update_and_update_encrypter(
         &mut self,
         common: &mut CommonState,
     ) -> Result<(), Error> {
         common.check_aligned_handshake()?;
-        common.send_msg_encrypt(Message::build_key_update_request().into());
+        let message = Message::build_key_update_request();
+        common.observe_sent(&message);
+        common.send_msg_encrypt(message.into());
         let secret = self.next_application_traffic_secret(common.side);
         self.ks.set_encrypter(&secret, common);
         Ok(())
@@ -524,6 +624,27 @@ impl KeyScheduleTraffic {
         secret
     }
 
+    /// Move to the next application traffic secret for `side`, returning
+    /// the keys derived from it.
+    ///
+    /// This is for when the record layer is elsewhere (see `KernelConnection`).
+    pub(crate) fn refresh_traffic_secrets(
+        &mut self,
+        side: Side,
+    ) -> Result<ConnectionTrafficSecrets, Error> {
+        let secret = self.next_application_traffic_secret(side);
+        let (key, iv) = expand_secret(
+            &secret,
+            self.ks.suite.hkdf_provider,
+            self.ks.suite.aead_alg.key_len(),
+        );
+        Ok(self
+            .ks
+            .suite
+            .aead_alg
+            .extract_keys(key, iv)?)
+    }
+
     pub(crate) fn export_keying_material(
         &self,
         out: &mut [u8],
@@ -535,25 +656,12 @@ impl KeyScheduleTraffic {
     }
 
     pub(crate) fn extract_secrets(&self, side: Side) -> Result<PartiallyExtractedSecrets, Error> {
-        fn expand(
-            secret: &OkmBlock,
-            hkdf: &'static dyn Hkdf,
-            aead_key_len: usize,
-        ) -> (AeadKey, Iv) {
-            let expander = hkdf.expander_for_okm(secret);
-
-            (
-                hkdf_expand_label_aead_key(expander.as_ref(), aead_key_len, b"key", &[]),
-                hkdf_expand_label(expander.as_ref(), b"iv", &[]),
-            )
-        }
-
-        let (client_key, client_iv) = expand(
+        let (client_key, client_iv) = expand_secret(
             &self.current_client_traffic_secret,
             self.ks.suite.hkdf_provider,
             self.ks.suite.aead_alg.key_len(),
         );
-        let (server_key, server_iv) = expand(
+        let (server_key, server_iv) = expand_secret(
             &self.current_server_traffic_secret,
             self.ks.suite.hkdf_provider,
             self.ks.suite.aead_alg.key_len(),
@@ -577,6 +685,15 @@ impl KeyScheduleTraffic {
     }
 }
 
+fn expand_secret(secret: &OkmBlock, hkdf: &'static dyn Hkdf, aead_key_len: usize) -> (AeadKey, Iv) {
+    let expander = hkdf.expander_for_okm(secret);
+
+    (
+        hkdf_expand_label_aead_key(expander.as_ref(), aead_key_len, b"key", &[]),
+        hkdf_expand_label(expander.as_ref(), b"iv", &[]),
+    )
+}
+
 pub(crate) struct ResumptionSecret<'a> {
     kst: &'a KeyScheduleTraffic,
     resumption_master_secret: OkmBlock,
@@ -600,12 +717,13 @@ impl<'a> ResumptionSecret<'a> {
 }
 
 impl KeySchedule {
-    fn new(suite: &'static Tls13CipherSuite, secret: &[u8]) -> Self {
+    fn new(suite: &'static Tls13CipherSuite, secret: &[u8], protocol: Protocol) -> Self {
         Self {
             current: suite
                 .hkdf_provider
                 .extract_from_secret(None, secret),
             suite,
+            protocol,
         }
     }
 
@@ -623,6 +741,7 @@ impl KeySchedule {
                 self.suite.aead_alg.encrypter(key, iv),
                 self.suite.common.confidentiality_limit,
             );
+        common.record_layer.allow_padding();
     }
 
     fn set_decrypter(&self, secret: &OkmBlock, common: &mut CommonState) {
@@ -641,12 +760,39 @@ impl KeySchedule {
         self.suite.aead_alg.decrypter(key, iv)
     }
 
-    fn new_with_empty_secret(suite: &'static Tls13CipherSuite) -> Self {
+    fn new_with_empty_secret(suite: &'static Tls13CipherSuite, protocol: Protocol) -> Self {
         Self {
             current: suite
                 .hkdf_provider
                 .extract_from_zero_ikm(None),
             suite,
+            protocol,
+        }
+    }
+
+    /// [HKDF-Expand-Label] with this schedule's label prefix, where the output is one block in size.
+    ///
+    /// [HKDF-Expand-Label]: <https://www.rfc-editor.org/rfc/rfc8446#section-7.1>
+    fn expand_label_block(
+        &self,
+        expander: &dyn HkdfExpander,
+        label: &[u8],
+        context: &[u8],
+    ) -> OkmBlock {
+        hkdf_expand_label_inner(
+            expander,
+            self.label_prefix(),
+            label,
+            context,
+            expander.hash_len(),
+            |e, info| e.expand_block(info),
+        )
+    }
+
+    fn label_prefix(&self) -> &'static [u8] {
+        match self.protocol {
+            Protocol::Dtls => DTLS13_LABEL_PREFIX,
+            Protocol::Tcp | Protocol::Quic => TLS13_LABEL_PREFIX,
         }
     }
 
@@ -670,7 +816,7 @@ impl KeySchedule {
 
     /// Derive a secret of given `kind`, using current handshake hash `hs_hash`.
     fn derive(&self, kind: SecretKind, hs_hash: &[u8]) -> OkmBlock {
-        hkdf_expand_label_block(self.current.as_ref(), kind.to_bytes(), hs_hash)
+        self.expand_label_block(self.current.as_ref(), kind.to_bytes(), hs_hash)
     }
 
     fn derive_logged_secret(
@@ -718,7 +864,7 @@ impl KeySchedule {
             .suite
             .hkdf_provider
             .expander_for_okm(base_key);
-        let hmac_key = hkdf_expand_label_block(expander.as_ref(), b"finished", &[]);
+        let hmac_key = self.expand_label_block(expander.as_ref(), b"finished", &[]);
 
         self.suite
             .hkdf_provider
@@ -731,7 +877,7 @@ impl KeySchedule {
             .suite
             .hkdf_provider
             .expander_for_okm(base_key);
-        hkdf_expand_label_block(expander.as_ref(), b"traffic upd", &[])
+        self.expand_label_block(expander.as_ref(), b"traffic upd", &[])
     }
 
     /// Derive the PSK to use given a resumption_master_secret and
@@ -741,7 +887,7 @@ impl KeySchedule {
             .suite
             .hkdf_provider
             .expander_for_okm(rms);
-        hkdf_expand_label_block(expander.as_ref(), b"resumption", nonce)
+        self.expand_label_block(expander.as_ref(), b"resumption", nonce)
     }
 
     fn export_keying_material(
@@ -762,7 +908,7 @@ impl KeySchedule {
                 .suite
                 .hkdf_provider
                 .expander_for_okm(current_exporter_secret);
-            hkdf_expand_label_block(expander.as_ref(), label, h_empty.as_ref())
+            self.expand_label_block(expander.as_ref(), label, h_empty.as_ref())
         };
 
         let h_context = self
@@ -775,8 +921,15 @@ impl KeySchedule {
             .suite
             .hkdf_provider
             .expander_for_okm(&secret);
-        hkdf_expand_label_slice(expander.as_ref(), b"exporter", h_context.as_ref(), out)
-            .map_err(|_| Error::General("exporting too much".to_string()))
+        hkdf_expand_label_inner(
+            expander.as_ref(),
+            self.label_prefix(),
+            b"exporter",
+            h_context.as_ref(),
+            out.len(),
+            |e, info| e.expand_slice(info, out),
+        )
+        .map_err(|_| Error::General("exporting too much".to_string()))
     }
 }
 
@@ -806,7 +959,14 @@ pub(crate) fn hkdf_expand_label<T: From<[u8; N]>, const N: usize>(
     label: &[u8],
     context: &[u8],
 ) -> T {
-    hkdf_expand_label_inner(expander, label, context, N, |e, info| expand(e, info))
+    hkdf_expand_label_inner(
+        expander,
+        TLS13_LABEL_PREFIX,
+        label,
+        context,
+        N,
+        |e, info| expand(e, info),
+    )
 }
 
 /// [HKDF-Expand-Label] where the output is one block in size.
@@ -815,9 +975,14 @@ pub(crate) fn hkdf_expand_label_block(
     label: &[u8],
     context: &[u8],
 ) -> OkmBlock {
-    hkdf_expand_label_inner(expander, label, context, expander.hash_len(), |e, info| {
-        e.expand_block(info)
-    })
+    hkdf_expand_label_inner(
+        expander,
+        TLS13_LABEL_PREFIX,
+        label,
+        context,
+        expander.hash_len(),
+        |e, info| e.expand_block(info),
+    )
 }
 
 /// [HKDF-Expand-Label] where the output is an AEAD key.
@@ -827,24 +992,95 @@ pub(crate) fn hkdf_expand_label_aead_key(
     label: &[u8],
     context: &[u8],
 ) -> AeadKey {
-    hkdf_expand_label_inner(expander, label, context, key_len, |e, info| {
-        let key: AeadKey = expand(e, info);
-        key.with_length(key_len)
-    })
+    hkdf_expand_label_inner(
+        expander,
+        TLS13_LABEL_PREFIX,
+        label,
+        context,
+        key_len,
+        |e, info| {
+            let key: AeadKey = expand(e, info);
+            key.with_length(key_len)
+        },
+    )
 }
 
 /// [HKDF-Expand-Label] where the output is a slice.
 ///
 /// This can fail because HKDF-Expand is limited in its maximum output length.
-fn hkdf_expand_label_slice(
+pub(crate) fn hkdf_expand_label_slice(
     expander: &dyn HkdfExpander,
     label: &[u8],
     context: &[u8],
     output: &mut [u8],
 ) -> Result<(), OutputLengthError> {
-    hkdf_expand_label_inner(expander, label, context, output.len(), |e, info| {
-        e.expand_slice(info, output)
-    })
+    hkdf_expand_label_inner(
+        expander,
+        TLS13_LABEL_PREFIX,
+        label,
+        context,
+        output.len(),
+        |e, info| e.expand_slice(info, output),
+    )
+}
+
+/// [HKDF-Expand-Label] using the DTLS 1.3 label prefix, where the output length is a
+/// compile-time constant.
+///
+/// See [RFC 9147 section 5.9].
+///
+/// [HKDF-Expand-Label]: <https://www.rfc-editor.org/rfc/rfc8446#section-7.1>
+/// [RFC 9147 section 5.9]: <https://www.rfc-editor.org/rfc/rfc9147#section-5.9>
+pub(crate) fn dtls13_expand_label<T: From<[u8; N]>, const N: usize>(
+    expander: &dyn HkdfExpander,
+    label: &[u8],
+    context: &[u8],
+) -> T {
+    hkdf_expand_label_inner(
+        expander,
+        DTLS13_LABEL_PREFIX,
+        label,
+        context,
+        N,
+        |e, info| expand(e, info),
+    )
+}
+
+/// [HKDF-Expand-Label] using the DTLS 1.3 label prefix, where the output is one block in size.
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+pub(crate) fn dtls13_expand_label_block(
+    expander: &dyn HkdfExpander,
+    label: &[u8],
+    context: &[u8],
+) -> OkmBlock {
+    hkdf_expand_label_inner(
+        expander,
+        DTLS13_LABEL_PREFIX,
+        label,
+        context,
+        expander.hash_len(),
+        |e, info| e.expand_block(info),
+    )
+}
+
+/// [HKDF-Expand-Label] using the DTLS 1.3 label prefix, where the output is an AEAD key.
+pub(crate) fn dtls13_expand_label_aead_key(
+    expander: &dyn HkdfExpander,
+    key_len: usize,
+    label: &[u8],
+    context: &[u8],
+) -> AeadKey {
+    hkdf_expand_label_inner(
+        expander,
+        DTLS13_LABEL_PREFIX,
+        label,
+        context,
+        key_len,
+        |e, info| {
+            let key: AeadKey = expand(e, info);
+            key.with_length(key_len)
+        },
+    )
 }
 
 pub(crate) fn server_ech_hrr_confirmation_secret(
@@ -870,8 +1106,12 @@ pub(crate) fn server_ech_hrr_confirmation_secret(
     )
 }
 
+const TLS13_LABEL_PREFIX: &[u8] = b"tls13 ";
+const DTLS13_LABEL_PREFIX: &[u8] = b"dtls13";
+
 fn hkdf_expand_label_inner<F, T>(
     expander: &dyn HkdfExpander,
+    prefix: &[u8],
     label: &[u8],
     context: &[u8],
     n: usize,
@@ -880,16 +1120,14 @@ fn hkdf_expand_label_inner<F, T>(
 where
     F: FnOnce(&dyn HkdfExpander, &[&[u8]]) -> T,
 {
-    const LABEL_PREFIX: &[u8] = b"tls13 ";
-
     let output_len = u16::to_be_bytes(n as u16);
-    let label_len = u8::to_be_bytes((LABEL_PREFIX.len() + label.len()) as u8);
+    let label_len = u8::to_be_bytes((prefix.len() + label.len()) as u8);
     let context_len = u8::to_be_bytes(context.len() as u8);
 
     let info = &[
         &output_len[..],
         &label_len[..],
-        LABEL_PREFIX,
+        prefix,
         label,
         &context_len[..],
         context,
@@ -910,6 +1148,7 @@ mod tests {
         TLS13_AES_128_GCM_SHA256_INTERNAL, TLS13_CHACHA20_POLY1305_SHA256_INTERNAL,
     };
     use super::{derive_traffic_iv, derive_traffic_key, KeySchedule, SecretKind};
+    use crate::common_state::Protocol;
     use crate::KeyLog;
 
     #[test]
@@ -993,7 +1232,10 @@ mod tests {
             0x0d, 0xb2, 0x8f, 0x98, 0x85, 0x86, 0xa1, 0xb7, 0xe4, 0xd5, 0xc6, 0x9c,
         ];
 
-        let mut ks = KeySchedule::new_with_empty_secret(TLS13_CHACHA20_POLY1305_SHA256_INTERNAL);
+        let mut ks = KeySchedule::new_with_empty_secret(
+            TLS13_CHACHA20_POLY1305_SHA256_INTERNAL,
+            Protocol::Tcp,
+        );
         ks.input_secret(&ecdhe_secret);
 
         assert_traffic_secret(
@@ -1118,8 +1360,10 @@ mod benchmarks {
         }
 
         b.iter(|| {
-            let mut ks =
-                KeySchedule::new_with_empty_secret(TLS13_CHACHA20_POLY1305_SHA256_INTERNAL);
+            let mut ks = KeySchedule::new_with_empty_secret(
+                TLS13_CHACHA20_POLY1305_SHA256_INTERNAL,
+                Protocol::Tcp,
+            );
             ks.input_secret(&[0u8; 32]);
 
             extract_traffic_secret(&ks, SecretKind::ClientHandshakeTrafficSecret);
@@ -33,6 +33,13 @@ pub struct Tls13CipherSuite {
     /// Provide `None` to opt out of QUIC support for this suite.  It will
     /// not be offered in QUIC handshakes.
     pub quic: Option<&'static dyn crate::quic::Algorithm>,
+
+    /// How to create DTLS 1.3 record and record number protection
+    /// algorithms for this suite.
+    ///
+    /// Provide `None` to opt out of DTLS support for this suite.  It will
+    /// not be offered in DTLS handshakes.
+    pub dtls: Option<&'static dyn crate::dtls::Algorithm>,
 }
 
 impl Tls13CipherSuite {
@@ -51,11 +58,13 @@ impl Tls13CipherSuite {
             hkdf_provider,
             aead_alg,
             quic,
+            dtls,
         } = self;
         common.fips()
             && hkdf_provider.fips()
             && aead_alg.fips()
             && quic.map(|q| q.fips()).unwrap_or(true)
+            && dtls.map(|d| d.fips()).unwrap_or(true)
     }
 
     /// Returns a `quic::Suite` for the ciphersuite, if supported.
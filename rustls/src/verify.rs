@@ -3,8 +3,9 @@ use core::fmt::Debug;
 
 use pki_types::{CertificateDer, ServerName, UnixTime};
 
+use crate::delegated_credential::DelegatedCredential;
 use crate::enums::SignatureScheme;
-use crate::error::{Error, InvalidMessage};
+use crate::error::{Error, InvalidMessage, PeerMisbehaved};
 use crate::msgs::base::PayloadU16;
 use crate::msgs::codec::{Codec, Reader};
 use crate::msgs::handshake::DistinguishedName;
@@ -152,6 +153,81 @@ pub trait ServerCertVerifier: Debug + Send + Sync {
     fn root_hint_subjects(&self) -> Option<&[DistinguishedName]> {
         None
     }
+
+    /// Returns `true` to request signed certificate timestamps (SCTs) from the server.
+    ///
+    /// If so, the client sends the [`signed_certificate_timestamp`] extension, and any
+    /// SCTs the server provides in response are passed to
+    /// [`ServerCertVerifier::verify_server_cert_with_scts`].
+    ///
+    /// [`signed_certificate_timestamp`]: https://www.rfc-editor.org/rfc/rfc6962#section-3.3.1
+    fn requests_signed_certificate_timestamps(&self) -> bool {
+        false
+    }
+
+    /// Verify the end-entity certificate `end_entity`, taking into account the signed
+    /// certificate timestamps provided by the server during the handshake.
+    ///
+    /// This is called by rustls in place of [`ServerCertVerifier::verify_server_cert`].
+    /// The arguments are the same, plus `sct_list`: a `SignedCertificateTimestampList`
+    /// (see [RFC 6962 section 3.3]) the server sent in its `signed_certificate_timestamp`
+    /// extension, or empty if there was none.  SCTs embedded in `end_entity` itself are not
+    /// included in `sct_list`.
+    ///
+    /// The default implementation ignores `sct_list` and calls
+    /// [`ServerCertVerifier::verify_server_cert`].
+    ///
+    /// [RFC 6962 section 3.3]: https://www.rfc-editor.org/rfc/rfc6962#section-3.3
+    fn verify_server_cert_with_scts(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        sct_list: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let _ = sct_list;
+        self.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    /// Return the signature schemes this verifier accepts for TLS1.3 `CertificateVerify`
+    /// messages signed with a delegated credential, in priority order.
+    ///
+    /// If this is non-empty, the client sends the [`delegated_credential`] extension,
+    /// and a credential provided by the server in response is passed to
+    /// [`ServerCertVerifier::verify_tls13_signature_with_delegated_credential`].
+    ///
+    /// The default implementation returns an empty list, disabling delegated credentials.
+    ///
+    /// [`delegated_credential`]: https://www.rfc-editor.org/rfc/rfc9345#section-4.1
+    fn delegated_credential_schemes(&self) -> Vec<SignatureScheme> {
+        Vec::new()
+    }
+
+    /// Verify a TLS1.3 `CertificateVerify` signature made with a delegated credential.
+    ///
+    /// This is called by rustls in place of [`ServerCertVerifier::verify_tls13_signature`]
+    /// when the server presented `credential` with its end-entity certificate `end_entity`.
+    /// `end_entity` has already been validated by
+    /// [`ServerCertVerifier::verify_server_cert_with_scts`].
+    ///
+    /// Implementations must check that `end_entity` permits delegation, that `credential`
+    /// is valid at `now` and correctly signed by `end_entity`'s key, and that `dss` is a
+    /// valid signature over `message` by the credential's key.
+    ///
+    /// The default implementation rejects all delegated credentials.
+    fn verify_tls13_signature_with_delegated_credential(
+        &self,
+        message: &[u8],
+        end_entity: &CertificateDer<'_>,
+        credential: &DelegatedCredential,
+        dss: &DigitallySignedStruct,
+        now: UnixTime,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        let _ = (message, end_entity, credential, dss, now);
+        Err(PeerMisbehaved::UnsolicitedCertExtension.into())
+    }
 }
 
 /// Something that can verify a client certificate chain
@@ -170,6 +246,20 @@ pub trait ClientCertVerifier: Debug + Send + Sync {
         self.offer_client_auth()
     }
 
+    /// Return `true` to not request a client certificate during the handshake, leaving
+    /// the application to request one afterwards with
+    /// [`ServerConnection::request_client_certificate()`].
+    ///
+    /// Only TLS1.3 clients which offer post-handshake authentication can be
+    /// authenticated this way.  Defaults to `false`.
+    ///
+    /// This is only consulted if [`ClientCertVerifier::offer_client_auth`] is true.
+    ///
+    /// [`ServerConnection::request_client_certificate()`]: crate::server::ServerConnection::request_client_certificate
+    fn defers_client_auth(&self) -> bool {
+        false
+    }
+
     /// Returns the [`DistinguishedName`] [subjects] that the server will hint to clients to
     /// identify acceptable authentication trust anchors.
     ///
@@ -32,6 +32,7 @@ pub struct ClientCertVerifierBuilder {
     unknown_revocation_policy: UnknownStatusPolicy,
     revocation_expiration_policy: ExpirationPolicy,
     anon_policy: AnonymousClientPolicy,
+    defer_client_auth: bool,
     supported_algs: WebPkiSupportedAlgorithms,
 }
 
@@ -45,6 +46,7 @@ impl ClientCertVerifierBuilder {
             roots,
             crls: Vec::new(),
             anon_policy: AnonymousClientPolicy::Deny,
+            defer_client_auth: false,
             revocation_check_depth: RevocationCheckDepth::Chain,
             unknown_revocation_policy: UnknownStatusPolicy::Deny,
             revocation_expiration_policy: ExpirationPolicy::Ignore,
@@ -127,6 +129,21 @@ impl ClientCertVerifierBuilder {
         self
     }
 
+    /// Only authenticate clients after the handshake, when the application asks.
+    ///
+    /// No client certificate is requested during the handshake.  Instead the
+    /// application requests one with [`ServerConnection::request_client_certificate()`],
+    /// which is only possible with TLS1.3 clients which offer post-handshake
+    /// authentication.
+    ///
+    /// See [`ClientCertVerifier::defers_client_auth`] for more information.
+    ///
+    /// [`ServerConnection::request_client_certificate()`]: crate::server::ServerConnection::request_client_certificate
+    pub fn defer_client_auth(mut self) -> Self {
+        self.defer_client_auth = true;
+        self
+    }
+
     /// Allow unknown certificate revocation status when using CRLs.
     ///
     /// If CRLs are provided with [`with_crls`][Self::with_crls] and it isn't possible to
@@ -182,6 +199,7 @@ impl ClientCertVerifierBuilder {
             self.unknown_revocation_policy,
             self.revocation_expiration_policy,
             self.anon_policy,
+            self.defer_client_auth,
             self.supported_algs,
         )))
     }
@@ -257,6 +275,7 @@ pub struct WebPkiClientVerifier {
     unknown_revocation_policy: UnknownStatusPolicy,
     revocation_expiration_policy: ExpirationPolicy,
     anonymous_policy: AnonymousClientPolicy,
+    defer_client_auth: bool,
     supported_algs: WebPkiSupportedAlgorithms,
 }
 
@@ -317,6 +336,8 @@ impl WebPkiClientVerifier {
     ///   are handled when `crls` are provided.
     /// * `anonymous_policy` controls whether client authentication is required, or if anonymous
     ///   clients can connect.
+    /// * `defer_client_auth` controls whether client authentication is left until after the
+    ///   handshake.
     /// * `supported_algs` specifies which signature verification algorithms should be used.
     pub(crate) fn new(
         roots: Arc<RootCertStore>,
@@ -326,6 +347,7 @@ impl WebPkiClientVerifier {
         unknown_revocation_policy: UnknownStatusPolicy,
         revocation_expiration_policy: ExpirationPolicy,
         anonymous_policy: AnonymousClientPolicy,
+        defer_client_auth: bool,
         supported_algs: WebPkiSupportedAlgorithms,
     ) -> Self {
         Self {
@@ -336,6 +358,7 @@ impl WebPkiClientVerifier {
             unknown_revocation_policy,
             revocation_expiration_policy,
             anonymous_policy,
+            defer_client_auth,
             supported_algs,
         }
     }
@@ -353,6 +376,10 @@ impl ClientCertVerifier for WebPkiClientVerifier {
         }
     }
 
+    fn defers_client_auth(&self) -> bool {
+        self.defer_client_auth
+    }
+
     fn root_hint_subjects(&self) -> &[DistinguishedName] {
         &self.root_hint_subjects
     }
@@ -0,0 +1,453 @@
+//! Certificate Transparency, as described in [RFC 6962].
+//!
+//! A server certificate can be accompanied by signed certificate timestamps (SCTs):
+//! promises from CT logs that the certificate (or a precertificate for it) has been
+//! logged.  SCTs may be embedded in the certificate itself, or sent by the server in
+//! the TLS `signed_certificate_timestamp` extension.
+//!
+//! SCTs are verified against a list of known logs, and the ones that verify are
+//! passed to a [`CtPolicy`] to decide whether there are enough of them.  SCTs
+//! delivered in stapled OCSP responses are not supported.
+//!
+//! [RFC 6962]: https://www.rfc-editor.org/rfc/rfc6962
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug};
+use core::time::Duration;
+
+use pki_types::{CertificateDer, SubjectPublicKeyInfoDer, UnixTime};
+
+use crate::crypto::hash::{self, HashAlgorithm};
+use crate::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
+use crate::enums::SignatureScheme;
+use crate::error::{CertificateError, Error};
+use crate::log::trace;
+use crate::msgs::base::PayloadU16;
+use crate::msgs::codec::{u24, Codec, Reader};
+use crate::msgs::handshake::read_sct_list;
+use crate::x509::{asn1_wrap, DerReader};
+
+/// A Certificate Transparency log.
+#[derive(Clone, Debug)]
+pub struct CtLog {
+    /// The log's ID: the SHA-256 hash of its public key.
+    pub id: [u8; 32],
+
+    /// The log's public key, as a DER-encoded `SubjectPublicKeyInfo`.
+    pub key: SubjectPublicKeyInfoDer<'static>,
+
+    /// The name of the organisation operating the log.
+    ///
+    /// Policies such as [`DistinctOperatorsPolicy`] use this to require SCTs from
+    /// independent operators.
+    pub operator: String,
+
+    /// If the log has been retired, the time it was retired.
+    ///
+    /// SCTs from the log with timestamps at or after this time are not accepted.
+    pub not_after: Option<UnixTime>,
+}
+
+/// Decides whether the verified SCTs for a certificate are sufficient.
+pub trait CtPolicy: Debug + Send + Sync {
+    /// Check the SCTs that verified for `end_entity`.
+    ///
+    /// `scts` contains at most one SCT per log; SCTs from unknown logs, with invalid
+    /// signatures, or with timestamps in the future have already been discarded.
+    ///
+    /// Return an error to reject the certificate, typically
+    /// [`CertificateError::InsufficientSignedCertificateTimestamps`].
+    fn check(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        scts: &[VerifiedSct<'_>],
+        now: UnixTime,
+    ) -> Result<(), Error>;
+}
+
+/// A [`CtPolicy`] requiring a minimum number of SCTs, from a minimum number of
+/// distinct log operators.
+#[derive(Clone, Debug)]
+pub struct DistinctOperatorsPolicy {
+    min_scts: usize,
+    min_operators: usize,
+}
+
+impl DistinctOperatorsPolicy {
+    /// Require at least `min_scts` SCTs, from logs run by at least `min_operators`
+    /// different operators.
+    pub fn new(min_scts: usize, min_operators: usize) -> Self {
+        Self {
+            min_scts,
+            min_operators,
+        }
+    }
+}
+
+impl CtPolicy for DistinctOperatorsPolicy {
+    fn check(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        scts: &[VerifiedSct<'_>],
+        _now: UnixTime,
+    ) -> Result<(), Error> {
+        let mut operators = Vec::new();
+        for sct in scts {
+            if !operators.contains(&sct.log.operator.as_str()) {
+                operators.push(sct.log.operator.as_str());
+            }
+        }
+
+        match scts.len() >= self.min_scts && operators.len() >= self.min_operators {
+            true => Ok(()),
+            false => Err(CertificateError::InsufficientSignedCertificateTimestamps.into()),
+        }
+    }
+}
+
+/// An SCT whose signature was verified against a known log.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct VerifiedSct<'a> {
+    /// The log that issued the SCT.
+    pub log: &'a CtLog,
+
+    /// The time at which the log promised to incorporate the certificate.
+    pub timestamp: UnixTime,
+
+    /// Where the SCT came from.
+    pub source: SctSource,
+}
+
+/// Where an SCT was found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SctSource {
+    /// Embedded in the certificate, covering a precertificate.
+    Embedded,
+
+    /// Sent by the server in the TLS `signed_certificate_timestamp` extension.
+    TlsExtension,
+}
+
+/// Certificate Transparency configuration of a `WebPkiServerVerifier`.
+#[derive(Clone, Debug)]
+pub(crate) struct CtVerifier {
+    logs: Vec<CtLog>,
+    policy: Arc<dyn CtPolicy>,
+    sha256: Option<Sha256>,
+}
+
+impl CtVerifier {
+    pub(crate) fn new(logs: Vec<CtLog>, policy: Arc<dyn CtPolicy>, sha256: Option<Sha256>) -> Self {
+        Self {
+            logs,
+            policy,
+            sha256,
+        }
+    }
+
+    /// Verify the SCTs for `end_entity`, and check them against the policy.
+    ///
+    /// `issuer_spki` is the DER-encoded `SubjectPublicKeyInfo` of the issuer of
+    /// `end_entity`, needed to verify embedded SCTs.  `sct_list` is the
+    /// `SignedCertificateTimestampList` sent in the TLS extension, or empty.
+    pub(crate) fn verify(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        issuer_spki: &[u8],
+        sct_list: &[u8],
+        now: UnixTime,
+        supported: &WebPkiSupportedAlgorithms,
+    ) -> Result<(), Error> {
+        let mut verified = Vec::new();
+
+        if !sct_list.is_empty() {
+            let entry = x509_entry(end_entity);
+            for sct in read_sct_list(sct_list).unwrap_or_default() {
+                if let Some(sct) =
+                    self.verify_one(&sct, &entry, SctSource::TlsExtension, now, supported)
+                {
+                    add_verified(&mut verified, sct);
+                }
+            }
+        }
+
+        if let (Some(sha256), Some((embedded, tbs))) = (&self.sha256, split_embedded(end_entity)) {
+            let entry = precert_entry(sha256.hash(issuer_spki).as_ref(), &tbs);
+            for sct in embedded {
+                if let Some(sct) =
+                    self.verify_one(&sct, &entry, SctSource::Embedded, now, supported)
+                {
+                    add_verified(&mut verified, sct);
+                }
+            }
+        }
+
+        self.policy
+            .check(end_entity, &verified, now)
+    }
+
+    /// Verify one `SerializedSCT` covering `entry`.
+    ///
+    /// Returns `None` for SCTs that are malformed, from unknown or retired logs,
+    /// in the future, or incorrectly signed.
+    fn verify_one(
+        &self,
+        sct: &PayloadU16,
+        entry: &[u8],
+        source: SctSource,
+        now: UnixTime,
+        supported: &WebPkiSupportedAlgorithms,
+    ) -> Option<VerifiedSct<'_>> {
+        let sct = Sct::read(&sct.0)?;
+
+        let Some(log) = self
+            .logs
+            .iter()
+            .find(|log| log.id == sct.log_id)
+        else {
+            trace!("SCT from unknown log");
+            return None;
+        };
+
+        let timestamp = UnixTime::since_unix_epoch(Duration::from_millis(sct.timestamp));
+        if timestamp > now || matches!(log.not_after, Some(not_after) if timestamp >= not_after) {
+            trace!("SCT timestamp outside of log's validity");
+            return None;
+        }
+
+        let mut message = Vec::new();
+        0u8.encode(&mut message); // sct_version: v1
+        0u8.encode(&mut message); // signature_type: certificate_timestamp
+        sct.timestamp.encode(&mut message);
+        message.extend_from_slice(entry);
+        PayloadU16::encode_slice(sct.extensions, &mut message);
+
+        let key = webpki::RawPublicKeyEntity::try_from(&log.key).ok()?;
+        let valid = supported
+            .convert_scheme(sct.scheme)
+            .unwrap_or_default()
+            .iter()
+            .any(|alg| {
+                key.verify_signature(*alg, &message, sct.signature)
+                    .is_ok()
+            });
+        if !valid {
+            trace!("SCT signature did not verify");
+            return None;
+        }
+
+        Some(VerifiedSct {
+            log,
+            timestamp,
+            source,
+        })
+    }
+}
+
+/// Add `sct` to `verified`, unless we already have one from the same log.
+fn add_verified<'a>(verified: &mut Vec<VerifiedSct<'a>>, sct: VerifiedSct<'a>) {
+    if !verified
+        .iter()
+        .any(|existing| existing.log.id == sct.log.id)
+    {
+        verified.push(sct);
+    }
+}
+
+/// A SHA-256 implementation, taken from a [`CryptoProvider`].
+#[derive(Clone)]
+pub(crate) struct Sha256(&'static dyn hash::Hash);
+
+impl Sha256 {
+    /// Find SHA-256 among the cipher suites of `provider`.
+    pub(crate) fn from_provider(provider: &CryptoProvider) -> Option<Self> {
+        provider
+            .cipher_suites
+            .iter()
+            .map(|suite| suite.hash_provider())
+            .find(|hash| hash.algorithm() == HashAlgorithm::SHA256)
+            .map(Self)
+    }
+
+    pub(crate) fn hash(&self, data: &[u8]) -> hash::Output {
+        self.0.hash(data)
+    }
+}
+
+impl Debug for Sha256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Sha256")
+    }
+}
+
+/// The parts of a `SignedCertificateTimestamp` we need.
+struct Sct<'a> {
+    log_id: &'a [u8],
+    /// Milliseconds since the Unix epoch.
+    timestamp: u64,
+    extensions: &'a [u8],
+    scheme: SignatureScheme,
+    signature: &'a [u8],
+}
+
+impl<'a> Sct<'a> {
+    fn read(bytes: &'a [u8]) -> Option<Self> {
+        let mut r = Reader::init(bytes);
+        if u8::read(&mut r).ok()? != 0 {
+            trace!("Unsupported SCT version");
+            return None;
+        }
+
+        let log_id = r.take(32)?;
+        let timestamp = u64::read(&mut r).ok()?;
+        let extensions_len = u16::read(&mut r).ok()?;
+        let extensions = r.take(usize::from(extensions_len))?;
+        let scheme = SignatureScheme::read(&mut r).ok()?;
+        let signature_len = u16::read(&mut r).ok()?;
+        let signature = r.take(usize::from(signature_len))?;
+        r.expect_empty("Sct").ok()?;
+
+        Some(Self {
+            log_id,
+            timestamp,
+            extensions,
+            scheme,
+            signature,
+        })
+    }
+}
+
+/// Encode the `signed_entry` of an `x509_entry` covering `end_entity`.
+fn x509_entry(end_entity: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::new();
+    X509_ENTRY.encode(&mut entry);
+    u24(end_entity.len() as u32).encode(&mut entry);
+    entry.extend_from_slice(end_entity);
+    entry
+}
+
+/// Encode the `signed_entry` of a `precert_entry` for a `PreCert` with the given
+/// `issuer_key_hash` and `tbs_certificate`.
+fn precert_entry(issuer_key_hash: &[u8], tbs_certificate: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::new();
+    PRECERT_ENTRY.encode(&mut entry);
+    entry.extend_from_slice(issuer_key_hash);
+    u24(tbs_certificate.len() as u32).encode(&mut entry);
+    entry.extend_from_slice(tbs_certificate);
+    entry
+}
+
+/// Find any SCTs embedded in `end_entity`.
+///
+/// If there are some, return them along with the `TBSCertificate` of the corresponding
+/// precertificate: `end_entity`'s with the embedded SCT extension removed.
+///
+/// Precertificates issued by a dedicated precertificate signing certificate are not
+/// supported: for those the `TBSCertificate` would have a different issuer.
+fn split_embedded(end_entity: &[u8]) -> Option<(Vec<PayloadU16>, Vec<u8>)> {
+    let mut outer = DerReader::new(end_entity);
+    let mut cert = DerReader::new(outer.expect(SEQUENCE)?);
+    let mut tbs = DerReader::new(cert.expect(SEQUENCE)?);
+
+    let mut scts = None;
+    let mut precert_tbs = Vec::new();
+    while !tbs.is_empty() {
+        let (tag, whole, contents) = tbs.read_with_header()?;
+        if tag != CONTEXT_3 {
+            precert_tbs.extend_from_slice(whole);
+            continue;
+        }
+
+        // extensions [3] EXPLICIT Extensions
+        let mut explicit = DerReader::new(contents);
+        let mut extensions = DerReader::new(explicit.expect(SEQUENCE)?);
+        explicit.finish()?;
+
+        let mut kept = Vec::new();
+        while !extensions.is_empty() {
+            let (whole, contents) = extensions.expect_with_header(SEQUENCE)?;
+            match embedded_sct_list(contents) {
+                Some(list) => scts = Some(list),
+                None => kept.extend_from_slice(whole),
+            }
+        }
+
+        if !kept.is_empty() {
+            let kept = asn1_wrap(SEQUENCE, &kept, &[]);
+            precert_tbs.extend(asn1_wrap(CONTEXT_3, &kept, &[]));
+        }
+    }
+
+    let scts = read_sct_list(scts?)?;
+    Some((scts, asn1_wrap(SEQUENCE, &precert_tbs, &[])))
+}
+
+/// If `extension` (the contents of an `Extension` SEQUENCE) is the embedded SCT list
+/// extension, return its `SignedCertificateTimestampList`.
+fn embedded_sct_list(extension: &[u8]) -> Option<&[u8]> {
+    let mut extension = DerReader::new(extension);
+    if extension.expect(OID)? != EMBEDDED_SCT_LIST {
+        return None;
+    }
+    extension.optional(BOOLEAN)?;
+
+    // The extnValue OCTET STRING contains the DER encoding of an OCTET STRING,
+    // which contains the TLS encoding of the list.
+    let mut value = DerReader::new(extension.expect(OCTET_STRING)?);
+    let list = value.expect(OCTET_STRING)?;
+    value.finish()?;
+    extension.finish()?;
+    Some(list)
+}
+
+// LogEntryType
+const X509_ENTRY: u16 = 0;
+const PRECERT_ENTRY: u16 = 1;
+
+const BOOLEAN: u8 = 0x01;
+const OCTET_STRING: u8 = 0x04;
+const OID: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const CONTEXT_3: u8 = 0xa3;
+
+/// Embedded SCT list extension: 1.3.6.1.4.1.11129.2.4.2
+const EMBEDDED_SCT_LIST: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02, 0x04, 0x02];
+
+#[cfg(test)]
+mod tests {
+    use std::vec;
+
+    use super::*;
+
+    #[test]
+    fn sct_read_rejects_bad_encodings() {
+        let mut sct = vec![0u8];
+        sct.extend_from_slice(&[0x11; 32]);
+        sct.extend_from_slice(&1_000u64.to_be_bytes());
+        sct.extend_from_slice(&[0, 0, 0x04, 0x03, 0, 1, 0xaa]);
+        assert!(Sct::read(&sct).is_some());
+
+        let mut trailing = sct.clone();
+        trailing.push(0);
+        assert!(Sct::read(&trailing).is_none());
+
+        let mut v2 = sct.clone();
+        v2[0] = 1;
+        assert!(Sct::read(&v2).is_none());
+
+        assert!(Sct::read(&sct[..sct.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn certificate_without_extensions_has_no_embedded_scts() {
+        // Certificate { TBSCertificate { INTEGER 1 }, AlgorithmIdentifier {}, BIT STRING }
+        let cert = [
+            0x30, 0x09, 0x30, 0x03, 0x02, 0x01, 0x01, 0x30, 0x00, 0x03, 0x00,
+        ];
+        assert!(split_embedded(&cert).is_none());
+    }
+}
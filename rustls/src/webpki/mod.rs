@@ -10,11 +10,15 @@ use crate::error::{CertRevocationListError, CertificateError, Error, OtherError}
 
 mod anchors;
 mod client_verifier;
+mod ct;
+mod ocsp;
 mod server_verifier;
 mod verify;
 
 pub use anchors::RootCertStore;
 pub use client_verifier::{ClientCertVerifierBuilder, WebPkiClientVerifier};
+pub use ct::{CtLog, CtPolicy, DistinctOperatorsPolicy, SctSource, VerifiedSct};
+pub use ocsp::OcspStaplingPolicy;
 pub use server_verifier::{ServerCertVerifierBuilder, WebPkiServerVerifier};
 // Conditionally exported from crate.
 #[allow(unreachable_pub)]
@@ -22,7 +26,8 @@ pub use verify::{
     verify_server_cert_signed_by_trust_anchor, verify_server_name, ParsedCertificate,
 };
 pub use verify::{
-    verify_tls12_signature, verify_tls13_signature, verify_tls13_signature_with_raw_key,
+    verify_tls12_signature, verify_tls13_signature,
+    verify_tls13_signature_with_delegated_credential, verify_tls13_signature_with_raw_key,
     WebPkiSupportedAlgorithms,
 };
 
@@ -0,0 +1,545 @@
+//! Verification of stapled OCSP responses, as described in [RFC 6960].
+//!
+//! Only the `id-pkix-ocsp-basic` response type is supported.  Responses are
+//! "nonce-free" (a TLS client has no way to contribute a nonce to a stapled
+//! response), so freshness is judged from the `thisUpdate` and `nextUpdate`
+//! times in the response against a maximum age.
+//!
+//! [RFC 6960]: https://www.rfc-editor.org/rfc/rfc6960
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use pki_types::{
+    CertificateDer, SignatureVerificationAlgorithm, SubjectPublicKeyInfoDer, TrustAnchor, UnixTime,
+};
+
+use crate::error::{CertificateError, Error};
+use crate::log::trace;
+use crate::webpki::ct::Sha256;
+use crate::x509::{wrap_in_sequence, DerReader};
+
+/// How a [`WebPkiServerVerifier`] treats an OCSP response stapled by the server.
+///
+/// In all cases, only the end-entity certificate's status is checked.
+///
+/// [`WebPkiServerVerifier`]: crate::client::WebPkiServerVerifier
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OcspStaplingPolicy {
+    /// Stapled OCSP responses are not examined.
+    ///
+    /// This is the default.
+    #[default]
+    Ignore,
+
+    /// A stapled OCSP response is verified if the server provides one, but its
+    /// absence is not an error.
+    ///
+    /// A response that is provided but is invalid, stale or reports the certificate
+    /// as revoked (or of unknown status) causes verification to fail.
+    Prefer,
+
+    /// The server must staple a valid, fresh OCSP response reporting the certificate
+    /// as good.
+    Require,
+}
+
+/// The default maximum age of an OCSP response, measured from its `thisUpdate` time.
+///
+/// This is the longest validity interval the CA/Browser Forum Baseline Requirements
+/// allow for OCSP responses for subscriber certificates.
+pub(crate) const DEFAULT_MAX_AGE: Duration = Duration::from_secs(10 * 24 * 60 * 60);
+
+/// Verify the DER-encoded OCSP `response` covers `end_entity` and reports it as good.
+///
+/// `issuer` is the trust anchor or intermediate that issued `end_entity`, as
+/// determined by path building.  The response must be signed either by `issuer`
+/// directly, or by a delegated responder certificate included in the response,
+/// issued by `issuer` and bearing the `id-kp-OCSPSigning` extended key usage.
+///
+/// The response's `CertID` must match the certificate's serial number, and the
+/// hashes of `issuer`'s name and public key.  Only CertIDs using SHA-256 are
+/// supported, and only if `sha256` is available from the `CryptoProvider`.
+pub(crate) fn verify_ocsp_response(
+    response: &[u8],
+    end_entity: &webpki::EndEntityCert<'_>,
+    issuer: &TrustAnchor<'_>,
+    now: UnixTime,
+    max_age: Duration,
+    supported_algs: &[&dyn SignatureVerificationAlgorithm],
+    sha256: Option<&Sha256>,
+) -> Result<(), Error> {
+    let response = BasicResponse::parse(response).ok_or(CertificateError::InvalidOcspResponse)?;
+
+    let issuer_spki =
+        SubjectPublicKeyInfoDer::from(wrap_in_sequence(issuer.subject_public_key_info.as_ref()));
+    let signed_by_issuer = webpki::RawPublicKeyEntity::try_from(&issuer_spki)
+        .map(|issuer| {
+            verify_signature(supported_algs, &response, |alg, msg, sig| {
+                issuer.verify_signature(alg, msg, sig)
+            })
+        })
+        .unwrap_or(false);
+
+    if !signed_by_issuer
+        && !response
+            .certs
+            .iter()
+            .any(|cert| signed_by_delegated_responder(cert, &response, issuer, now, supported_algs))
+    {
+        trace!("OCSP response not signed by issuer or a delegated responder");
+        return Err(CertificateError::InvalidOcspResponse.into());
+    }
+
+    let single = response
+        .responses
+        .iter()
+        .find(|single| {
+            single
+                .cert_id
+                .matches(end_entity.serial(), issuer, sha256)
+        })
+        .ok_or(CertificateError::InvalidOcspResponse)?;
+
+    if single.this_update > now.as_secs() {
+        trace!("OCSP response thisUpdate is in the future");
+        return Err(CertificateError::InvalidOcspResponse.into());
+    }
+
+    let too_old = single
+        .this_update
+        .saturating_add(max_age.as_secs())
+        < now.as_secs();
+    let expired = matches!(single.next_update, Some(next_update) if next_update < now.as_secs());
+    if too_old || expired {
+        return Err(CertificateError::ExpiredOcspResponse.into());
+    }
+
+    match single.status {
+        CertStatus::Good => Ok(()),
+        CertStatus::Revoked => Err(CertificateError::RevokedByOcspResponse.into()),
+        CertStatus::Unknown => Err(CertificateError::UnknownRevocationStatus.into()),
+    }
+}
+
+/// Return true if `cert` is a valid delegated responder for `issuer`, and it signed `response`.
+fn signed_by_delegated_responder(
+    cert: &[u8],
+    response: &BasicResponse<'_>,
+    issuer: &TrustAnchor<'_>,
+    now: UnixTime,
+    supported_algs: &[&dyn SignatureVerificationAlgorithm],
+) -> bool {
+    let der = CertificateDer::from(cert);
+    let Ok(responder) = webpki::EndEntityCert::try_from(&der) else {
+        return false;
+    };
+
+    let anchors = [issuer.clone()];
+    let valid_responder = responder
+        .verify_for_usage(
+            supported_algs,
+            &anchors,
+            &[],
+            now,
+            webpki::KeyUsage::required(EKU_OCSP_SIGNING),
+            None,
+            None,
+        )
+        .is_ok();
+
+    valid_responder
+        && verify_signature(supported_algs, response, |alg, msg, sig| {
+            responder.verify_signature(alg, msg, sig)
+        })
+}
+
+/// Try each of `supported_algs` matching the response's signature algorithm with `verify`.
+fn verify_signature(
+    supported_algs: &[&dyn SignatureVerificationAlgorithm],
+    response: &BasicResponse<'_>,
+    verify: impl Fn(&dyn SignatureVerificationAlgorithm, &[u8], &[u8]) -> Result<(), webpki::Error>,
+) -> bool {
+    supported_algs
+        .iter()
+        .filter(|alg| alg.signature_alg_id().as_ref() == response.signature_alg)
+        .any(|alg| verify(*alg, response.tbs, response.signature).is_ok())
+}
+
+/// The parts of a `BasicOCSPResponse` we need.
+struct BasicResponse<'a> {
+    /// The complete DER encoding of `tbsResponseData`, as signed.
+    tbs: &'a [u8],
+    /// The contents of the `signatureAlgorithm` `AlgorithmIdentifier`.
+    signature_alg: &'a [u8],
+    signature: &'a [u8],
+    responses: Vec<SingleResponse<'a>>,
+    certs: Vec<&'a [u8]>,
+}
+
+impl<'a> BasicResponse<'a> {
+    /// Parse an `OCSPResponse`, which must be successful and of type `id-pkix-ocsp-basic`.
+    fn parse(der: &'a [u8]) -> Option<Self> {
+        let mut outer = DerReader::new(der);
+        let mut ocsp_response = DerReader::new(outer.expect(SEQUENCE)?);
+        outer.finish()?;
+
+        // OCSPResponseStatus ::= ENUMERATED { successful (0), ... }
+        if ocsp_response.expect(ENUMERATED)? != [0] {
+            return None;
+        }
+
+        // responseBytes [0] EXPLICIT ResponseBytes
+        let mut explicit = DerReader::new(ocsp_response.expect(CONTEXT_0)?);
+        ocsp_response.finish()?;
+        let mut response_bytes = DerReader::new(explicit.expect(SEQUENCE)?);
+        explicit.finish()?;
+        if response_bytes.expect(OID)? != ID_PKIX_OCSP_BASIC {
+            return None;
+        }
+        let basic = response_bytes.expect(OCTET_STRING)?;
+        response_bytes.finish()?;
+
+        let mut outer = DerReader::new(basic);
+        let mut basic = DerReader::new(outer.expect(SEQUENCE)?);
+        outer.finish()?;
+
+        let (tbs, tbs_contents) = basic.expect_with_header(SEQUENCE)?;
+        let signature_alg = basic.expect(SEQUENCE)?;
+        let [0, signature @ ..] = basic.expect(BIT_STRING)? else {
+            return None;
+        };
+
+        let mut certs = Vec::new();
+        if let Some(explicit) = basic.optional(CONTEXT_0)? {
+            let mut explicit = DerReader::new(explicit);
+            let mut seq = DerReader::new(explicit.expect(SEQUENCE)?);
+            explicit.finish()?;
+            while !seq.is_empty() {
+                certs.push(seq.expect_with_header(SEQUENCE)?.0);
+            }
+        }
+        basic.finish()?;
+
+        let mut tbs_reader = DerReader::new(tbs_contents);
+        // version [0] EXPLICIT Version DEFAULT v1
+        if let Some(version) = tbs_reader.optional(CONTEXT_0)? {
+            let mut version = DerReader::new(version);
+            if version.expect(INTEGER)? != [0] {
+                return None;
+            }
+            version.finish()?;
+        }
+
+        // responderID: byName [1] or byKey [2].  We identify the responder by
+        // its signature, so this is not needed.
+        match tbs_reader.read()? {
+            (CONTEXT_1 | CONTEXT_2, _) => {}
+            _ => return None,
+        }
+
+        // producedAt
+        tbs_reader.expect(GENERALIZED_TIME)?;
+
+        let mut responses = Vec::new();
+        let mut seq = DerReader::new(tbs_reader.expect(SEQUENCE)?);
+        while !seq.is_empty() {
+            responses.push(SingleResponse::parse(seq.expect(SEQUENCE)?)?);
+        }
+
+        // responseExtensions [1] EXPLICIT Extensions OPTIONAL
+        if let Some(extensions) = tbs_reader.optional(CONTEXT_1)? {
+            check_extensions(extensions)?;
+        }
+        tbs_reader.finish()?;
+
+        Some(Self {
+            tbs,
+            signature_alg,
+            signature,
+            responses,
+            certs,
+        })
+    }
+}
+
+struct SingleResponse<'a> {
+    cert_id: CertId<'a>,
+    status: CertStatus,
+    /// `thisUpdate`, in seconds since the Unix epoch.
+    this_update: u64,
+    /// `nextUpdate`, in seconds since the Unix epoch.
+    next_update: Option<u64>,
+}
+
+impl<'a> SingleResponse<'a> {
+    fn parse(der: &'a [u8]) -> Option<Self> {
+        let mut reader = DerReader::new(der);
+
+        let cert_id = CertId::parse(reader.expect(SEQUENCE)?)?;
+
+        let status = match reader.read()? {
+            (GOOD, []) => CertStatus::Good,
+            (REVOKED, _) => CertStatus::Revoked,
+            (UNKNOWN, []) => CertStatus::Unknown,
+            _ => return None,
+        };
+
+        let this_update = parse_generalized_time(reader.expect(GENERALIZED_TIME)?)?;
+
+        let next_update = match reader.optional(CONTEXT_0)? {
+            Some(explicit) => {
+                let mut explicit = DerReader::new(explicit);
+                let time = parse_generalized_time(explicit.expect(GENERALIZED_TIME)?)?;
+                explicit.finish()?;
+                Some(time)
+            }
+            None => None,
+        };
+
+        // singleExtensions [1] EXPLICIT Extensions OPTIONAL
+        if let Some(extensions) = reader.optional(CONTEXT_1)? {
+            check_extensions(extensions)?;
+        }
+        reader.finish()?;
+
+        Some(Self {
+            cert_id,
+            status,
+            this_update,
+            next_update,
+        })
+    }
+}
+
+/// The contents of a `CertID`, identifying the certificate a `SingleResponse` is about.
+struct CertId<'a> {
+    /// The OID of the `hashAlgorithm` used for the issuer hashes.
+    hash_alg: &'a [u8],
+    issuer_name_hash: &'a [u8],
+    issuer_key_hash: &'a [u8],
+    /// The contents of the `serialNumber` INTEGER.
+    serial: &'a [u8],
+}
+
+impl<'a> CertId<'a> {
+    fn parse(der: &'a [u8]) -> Option<Self> {
+        // CertID ::= SEQUENCE { hashAlgorithm, issuerNameHash, issuerKeyHash, serialNumber }
+        let mut reader = DerReader::new(der);
+        let mut hash_alg = DerReader::new(reader.expect(SEQUENCE)?);
+        let issuer_name_hash = reader.expect(OCTET_STRING)?;
+        let issuer_key_hash = reader.expect(OCTET_STRING)?;
+        let serial = reader.expect(INTEGER)?;
+        reader.finish()?;
+
+        // AlgorithmIdentifier ::= SEQUENCE { algorithm OID, parameters ANY OPTIONAL }
+        // The parameters are absent or NULL for the hash functions we support.
+        let oid = hash_alg.expect(OID)?;
+        if let Some(params) = hash_alg.optional(NULL)? {
+            if !params.is_empty() {
+                return None;
+            }
+        }
+        hash_alg.finish()?;
+
+        Some(Self {
+            hash_alg: oid,
+            issuer_name_hash,
+            issuer_key_hash,
+            serial,
+        })
+    }
+
+    /// Whether this identifies the certificate with `serial`, issued by `issuer`.
+    ///
+    /// RFC 6960 section 4.1.1: `issuerNameHash` is the hash of the DER encoding of
+    /// the issuer's name, and `issuerKeyHash` is the hash of the value (excluding
+    /// the tag, length and number of unused bits) of the BIT STRING
+    /// `subjectPublicKey` in the issuer's certificate.
+    fn matches(&self, serial: &[u8], issuer: &TrustAnchor<'_>, sha256: Option<&Sha256>) -> bool {
+        if self.serial != serial {
+            return false;
+        }
+
+        let name = wrap_in_sequence(issuer.subject.as_ref());
+        let Some(key) = subject_public_key(issuer.subject_public_key_info.as_ref()) else {
+            return false;
+        };
+
+        match (self.hash_alg, sha256) {
+            (ID_SHA256, Some(sha256)) => {
+                self.issuer_name_hash == sha256.hash(&name).as_ref()
+                    && self.issuer_key_hash == sha256.hash(key).as_ref()
+            }
+            _ => {
+                trace!("OCSP response CertID uses an unsupported hash algorithm");
+                false
+            }
+        }
+    }
+}
+
+/// Return the contents of the `subjectPublicKey` BIT STRING from the contents of
+/// a `SubjectPublicKeyInfo`.
+fn subject_public_key(spki: &[u8]) -> Option<&[u8]> {
+    // SubjectPublicKeyInfo ::= SEQUENCE { algorithm AlgorithmIdentifier,
+    //                                     subjectPublicKey BIT STRING }
+    let mut reader = DerReader::new(spki);
+    reader.expect(SEQUENCE)?;
+    let [0, key @ ..] = reader.expect(BIT_STRING)? else {
+        return None;
+    };
+    reader.finish()?;
+    Some(key)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CertStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+/// Check a `[n] EXPLICIT Extensions` element contains no critical extensions.
+///
+/// None of the extensions defined for OCSP responses are needed to process a stapled
+/// response, so any extension marked critical is unsupported.
+fn check_extensions(explicit: &[u8]) -> Option<()> {
+    let mut explicit = DerReader::new(explicit);
+    let mut extensions = DerReader::new(explicit.expect(SEQUENCE)?);
+    explicit.finish()?;
+
+    while !extensions.is_empty() {
+        // Extension ::= SEQUENCE { extnID, critical BOOLEAN DEFAULT FALSE, extnValue }
+        let mut extension = DerReader::new(extensions.expect(SEQUENCE)?);
+        extension.expect(OID)?;
+        if let Some(critical) = extension.optional(BOOLEAN)? {
+            if critical != [0] {
+                return None;
+            }
+        }
+        extension.expect(OCTET_STRING)?;
+        extension.finish()?;
+    }
+
+    Some(())
+}
+
+/// Parse a `GeneralizedTime` of the form `YYYYMMDDHHMMSS[.f*]Z` into seconds since
+/// the Unix epoch.
+fn parse_generalized_time(value: &[u8]) -> Option<u64> {
+    let [value @ .., b'Z'] = value else {
+        return None;
+    };
+    let (value, fraction) = match value.iter().position(|&b| b == b'.') {
+        Some(dot) => value.split_at(dot),
+        None => (value, &[][..]),
+    };
+    if value.len() != 14
+        || !value.iter().all(u8::is_ascii_digit)
+        || fraction.len() == 1
+        || !fraction
+            .iter()
+            .skip(1)
+            .all(u8::is_ascii_digit)
+    {
+        return None;
+    }
+
+    let digits = |range: core::ops::Range<usize>| {
+        value[range]
+            .iter()
+            .fold(0u64, |acc, b| acc * 10 + u64::from(b - b'0'))
+    };
+    let (year, month, day) = (digits(0..4), digits(4..6), digits(6..8));
+    let (hour, minute, second) = (digits(8..10), digits(10..12), digits(12..14));
+
+    if year < 1970 || !(1..=12).contains(&month) || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if day < 1 || day > days_in_month {
+        return None;
+    }
+
+    Some(days_since_epoch(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Days from 1970-01-01 to the given (valid, post-1970) civil date.
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    // Treat March as the first month, so the leap day is at the end of the year.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+const BOOLEAN: u8 = 0x01;
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OID: u8 = 0x06;
+const ENUMERATED: u8 = 0x0a;
+const GENERALIZED_TIME: u8 = 0x18;
+const SEQUENCE: u8 = 0x30;
+const CONTEXT_0: u8 = 0xa0;
+const CONTEXT_1: u8 = 0xa1;
+const CONTEXT_2: u8 = 0xa2;
+
+// CertStatus ::= CHOICE { good [0] IMPLICIT NULL, revoked [1] IMPLICIT RevokedInfo,
+//                         unknown [2] IMPLICIT UnknownInfo }
+const GOOD: u8 = 0x80;
+const REVOKED: u8 = 0xa1;
+const UNKNOWN: u8 = 0x82;
+
+/// id-pkix-ocsp-basic: 1.3.6.1.5.5.7.48.1.1
+const ID_PKIX_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+
+/// id-sha256: 2.16.840.1.101.3.4.2.1
+const ID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+
+/// id-kp-OCSPSigning: 1.3.6.1.5.5.7.3.9
+const EKU_OCSP_SIGNING: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x09];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generalized_time() {
+        assert_eq!(parse_generalized_time(b"19700101000000Z"), Some(0));
+        assert_eq!(
+            parse_generalized_time(b"20240229123456Z"),
+            Some(1_709_210_096)
+        );
+        assert_eq!(
+            parse_generalized_time(b"20240229123456.789Z"),
+            Some(1_709_210_096)
+        );
+        assert_eq!(parse_generalized_time(b"20230229123456Z"), None);
+        assert_eq!(parse_generalized_time(b"20241301000000Z"), None);
+        assert_eq!(parse_generalized_time(b"20240101000000"), None);
+        assert_eq!(parse_generalized_time(b"20240101000000.Z"), None);
+        assert_eq!(parse_generalized_time(b"2024010100000Z"), None);
+        assert_eq!(parse_generalized_time(b"19691231235959Z"), None);
+        assert_eq!(parse_generalized_time(b""), None);
+    }
+
+    #[test]
+    fn unsuccessful_response_is_rejected() {
+        // OCSPResponse { responseStatus: tryLater (3) }
+        assert!(BasicResponse::parse(&[0x30, 0x03, 0x0a, 0x01, 0x03]).is_none());
+    }
+}
@@ -1,19 +1,24 @@
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::time::Duration;
 
-use pki_types::{CertificateDer, CertificateRevocationListDer, ServerName, UnixTime};
+use pki_types::{CertificateDer, CertificateRevocationListDer, ServerName, TrustAnchor, UnixTime};
 use webpki::{CertRevocationList, ExpirationPolicy, RevocationCheckDepth, UnknownStatusPolicy};
 
 use crate::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
-use crate::log::trace;
+use crate::delegated_credential::DelegatedCredential;
+use crate::error::{CertificateError, PeerMisbehaved};
 use crate::verify::{
     DigitallySignedStruct, HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
 };
+use crate::webpki::ct::{CtLog, CtPolicy, CtVerifier, Sha256};
+use crate::webpki::ocsp::{verify_ocsp_response, OcspStaplingPolicy, DEFAULT_MAX_AGE};
 use crate::webpki::verify::{
     verify_server_cert_signed_by_trust_anchor_impl, verify_tls12_signature, verify_tls13_signature,
-    ParsedCertificate,
+    verify_tls13_signature_with_delegated_credential, ParsedCertificate,
 };
-use crate::webpki::{parse_crls, verify_server_name, VerifierBuilderError};
+use crate::webpki::{parse_crls, pki_error, verify_server_name, VerifierBuilderError};
+use crate::x509::wrap_in_sequence;
 #[cfg(doc)]
 use crate::{crypto, ConfigBuilder, ServerConfig};
 use crate::{Error, RootCertStore, SignatureScheme};
@@ -28,6 +33,11 @@ pub struct ServerCertVerifierBuilder {
     revocation_check_depth: RevocationCheckDepth,
     unknown_revocation_policy: UnknownStatusPolicy,
     revocation_expiration_policy: ExpirationPolicy,
+    ocsp_stapling_policy: OcspStaplingPolicy,
+    ocsp_max_age: Duration,
+    ct: Option<CtVerifier>,
+    delegated_credentials: bool,
+    sha256: Option<Sha256>,
     supported_algs: WebPkiSupportedAlgorithms,
 }
 
@@ -35,6 +45,7 @@ impl ServerCertVerifierBuilder {
     pub(crate) fn new(
         roots: Arc<RootCertStore>,
         supported_algs: WebPkiSupportedAlgorithms,
+        sha256: Option<Sha256>,
     ) -> Self {
         Self {
             roots,
@@ -42,6 +53,11 @@ impl ServerCertVerifierBuilder {
             revocation_check_depth: RevocationCheckDepth::Chain,
             unknown_revocation_policy: UnknownStatusPolicy::Deny,
             revocation_expiration_policy: ExpirationPolicy::Ignore,
+            ocsp_stapling_policy: OcspStaplingPolicy::Ignore,
+            ocsp_max_age: DEFAULT_MAX_AGE,
+            ct: None,
+            delegated_credentials: false,
+            sha256,
             supported_algs,
         }
     }
@@ -97,6 +113,74 @@ impl ServerCertVerifierBuilder {
         self
     }
 
+    /// Verify OCSP responses stapled by the server according to `policy`.
+    ///
+    /// When enabled, a stapled response must be signed by the issuer of the server's
+    /// end entity certificate (or by a delegated OCSP responder certificate issued by it,
+    /// included in the response), must be fresh and must report the certificate as good.
+    ///
+    /// Only responses identifying the certificate by SHA-256 hashes are supported, and
+    /// only if the [`CryptoProvider`] has a cipher suite using SHA-256.
+    ///
+    /// The default is [`OcspStaplingPolicy::Ignore`].
+    pub fn with_ocsp_stapling_policy(mut self, policy: OcspStaplingPolicy) -> Self {
+        self.ocsp_stapling_policy = policy;
+        self
+    }
+
+    /// Set the maximum age of an acceptable stapled OCSP response.
+    ///
+    /// Stapled responses carry no nonce, so freshness is judged from the response's
+    /// `thisUpdate` time: a response is treated as stale once `max_age` has passed since
+    /// then, or once its `nextUpdate` time has passed, whichever is sooner.  The current
+    /// time comes from the [`crate::time_provider::TimeProvider`] of the configuration
+    /// using this verifier.
+    ///
+    /// The default is ten days.  This setting has no effect unless a policy other than
+    /// [`OcspStaplingPolicy::Ignore`] is configured with
+    /// [`with_ocsp_stapling_policy`][Self::with_ocsp_stapling_policy].
+    pub fn with_ocsp_max_age(mut self, max_age: Duration) -> Self {
+        self.ocsp_max_age = max_age;
+        self
+    }
+
+    /// Require Certificate Transparency for server certificates.
+    ///
+    /// Signed certificate timestamps (SCTs) embedded in the end entity certificate,
+    /// or sent by the server in the TLS `signed_certificate_timestamp` extension
+    /// (which the client will request), are verified against `logs`.  Those that
+    /// verify are then passed to `policy`, which decides whether they are sufficient.
+    ///
+    /// Verifying embedded SCTs requires SHA-256, which is taken from the cipher suites
+    /// of the [`crypto::CryptoProvider`] given to the builder.  If it has none,
+    /// only SCTs sent in the TLS extension are considered.
+    pub fn with_certificate_transparency(
+        mut self,
+        logs: impl IntoIterator<Item = CtLog>,
+        policy: Arc<dyn CtPolicy>,
+    ) -> Self {
+        self.ct = Some(CtVerifier::new(
+            logs.into_iter().collect(),
+            policy,
+            self.sha256.clone(),
+        ));
+        self
+    }
+
+    /// Accept TLS 1.3 delegated credentials from servers.
+    ///
+    /// The client will offer the `delegated_credential` extension, with the same
+    /// signature schemes used for handshake signatures.  A delegated credential
+    /// sent by the server is accepted only if its certificate carries the
+    /// `DelegationUsage` extension, and the credential is correctly signed and
+    /// currently valid for no more than seven days.
+    ///
+    /// See [`crate::delegated_credential`].
+    pub fn allow_delegated_credentials(mut self) -> Self {
+        self.delegated_credentials = true;
+        self
+    }
+
     /// Build a server certificate verifier, allowing control over the root certificates to use as
     /// trust anchors, and to control how server certificate revocation checking is performed.
     ///
@@ -122,6 +206,11 @@ impl ServerCertVerifierBuilder {
             self.revocation_check_depth,
             self.unknown_revocation_policy,
             self.revocation_expiration_policy,
+            self.ocsp_stapling_policy,
+            self.ocsp_max_age,
+            self.ct,
+            self.delegated_credentials,
+            self.sha256,
             self.supported_algs,
         )
         .into())
@@ -137,6 +226,11 @@ pub struct WebPkiServerVerifier {
     revocation_check_depth: RevocationCheckDepth,
     unknown_revocation_policy: UnknownStatusPolicy,
     revocation_expiration_policy: ExpirationPolicy,
+    ocsp_stapling_policy: OcspStaplingPolicy,
+    ocsp_max_age: Duration,
+    ct: Option<CtVerifier>,
+    delegated_credentials: bool,
+    sha256: Option<Sha256>,
     supported: WebPkiSupportedAlgorithms,
 }
 
@@ -169,7 +263,11 @@ impl WebPkiServerVerifier {
         roots: Arc<RootCertStore>,
         provider: Arc<CryptoProvider>,
     ) -> ServerCertVerifierBuilder {
-        ServerCertVerifierBuilder::new(roots, provider.signature_verification_algorithms)
+        ServerCertVerifierBuilder::new(
+            roots,
+            provider.signature_verification_algorithms,
+            Sha256::from_provider(&provider),
+        )
     }
 
     /// Short-cut for creating a `WebPkiServerVerifier` that does not perform certificate revocation
@@ -184,6 +282,11 @@ impl WebPkiServerVerifier {
             RevocationCheckDepth::Chain,
             UnknownStatusPolicy::Allow,
             ExpirationPolicy::Ignore,
+            OcspStaplingPolicy::Ignore,
+            DEFAULT_MAX_AGE,
+            None,
+            false,
+            None,
             supported_algs,
         )
     }
@@ -197,6 +300,11 @@ impl WebPkiServerVerifier {
     ///   when `crls` are provided.
     /// * `unknown_revocation_policy` controls how certificates with an unknown revocation status
     ///   are handled when `crls` are provided.
+    /// * `ocsp_stapling_policy` controls whether stapled OCSP responses are verified.
+    /// * `ocsp_max_age` is the maximum age of an acceptable stapled OCSP response.
+    /// * `ct` is the Certificate Transparency configuration, if any.
+    /// * `delegated_credentials` controls whether delegated credentials are accepted.
+    /// * `sha256` is used to match OCSP responses that identify certificates by SHA-256.
     /// * `supported` is the set of supported algorithms that will be used for
     ///   certificate verification and TLS handshake signature verification.
     pub(crate) fn new(
@@ -205,6 +313,11 @@ impl WebPkiServerVerifier {
         revocation_check_depth: RevocationCheckDepth,
         unknown_revocation_policy: UnknownStatusPolicy,
         revocation_expiration_policy: ExpirationPolicy,
+        ocsp_stapling_policy: OcspStaplingPolicy,
+        ocsp_max_age: Duration,
+        ct: Option<CtVerifier>,
+        delegated_credentials: bool,
+        sha256: Option<Sha256>,
         supported: WebPkiSupportedAlgorithms,
     ) -> Self {
         Self {
@@ -213,28 +326,22 @@ impl WebPkiServerVerifier {
             revocation_check_depth,
             unknown_revocation_policy,
             revocation_expiration_policy,
+            ocsp_stapling_policy,
+            ocsp_max_age,
+            ct,
+            delegated_credentials,
+            sha256,
             supported,
         }
     }
-}
 
-impl ServerCertVerifier for WebPkiServerVerifier {
-    /// Will verify the certificate is valid in the following ways:
-    /// - Signed by a trusted `RootCertStore` CA
-    /// - Not Expired
-    /// - Valid for DNS entry
-    /// - Valid revocation status (if applicable).
-    ///
-    /// Depending on the verifier's configuration revocation status checking may be performed for
-    /// each certificate in the chain to a root CA (excluding the root itself), or only the
-    /// end entity certificate. Similarly, unknown revocation status may be treated as an error
-    /// or allowed based on configuration.
-    fn verify_server_cert(
+    fn verify_server_cert_impl(
         &self,
         end_entity: &CertificateDer<'_>,
         intermediates: &[CertificateDer<'_>],
         server_name: &ServerName<'_>,
         ocsp_response: &[u8],
+        sct_list: &[u8],
         now: UnixTime,
     ) -> Result<ServerCertVerified, Error> {
         let cert = ParsedCertificate::try_from(end_entity)?;
@@ -260,7 +367,7 @@ impl ServerCertVerifier for WebPkiServerVerifier {
 
         // Note: we use the crate-internal `_impl` fn here in order to provide revocation
         // checking information, if applicable.
-        verify_server_cert_signed_by_trust_anchor_impl(
+        let path = verify_server_cert_signed_by_trust_anchor_impl(
             &cert,
             &self.roots,
             intermediates,
@@ -269,13 +376,109 @@ impl ServerCertVerifier for WebPkiServerVerifier {
             self.supported.all,
         )?;
 
-        if !ocsp_response.is_empty() {
-            trace!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
+        let verify_ocsp = match (self.ocsp_stapling_policy, ocsp_response.is_empty()) {
+            (OcspStaplingPolicy::Ignore, _) | (OcspStaplingPolicy::Prefer, true) => false,
+            (OcspStaplingPolicy::Require, true) => {
+                return Err(CertificateError::MissingOcspResponse.into());
+            }
+            (_, false) => true,
+        };
+
+        if verify_ocsp || self.ct.is_some() {
+            // The issuer of the end entity certificate is the first intermediate
+            // in the path, or the trust anchor if there are none.
+            let issuer_der = path
+                .intermediate_certificates()
+                .next()
+                .map(|issuer| issuer.der());
+            let issuer = match &issuer_der {
+                Some(der) => webpki::anchor_from_trusted_cert(der).map_err(pki_error)?,
+                None => TrustAnchor::clone(path.anchor()),
+            };
+
+            if verify_ocsp {
+                verify_ocsp_response(
+                    ocsp_response,
+                    &cert.0,
+                    &issuer,
+                    now,
+                    self.ocsp_max_age,
+                    self.supported.all,
+                    self.sha256.as_ref(),
+                )?;
+            }
+
+            if let Some(ct) = &self.ct {
+                ct.verify(
+                    end_entity,
+                    &wrap_in_sequence(issuer.subject_public_key_info.as_ref()),
+                    sct_list,
+                    now,
+                    &self.supported,
+                )?;
+            }
         }
 
         verify_server_name(&cert, server_name)?;
         Ok(ServerCertVerified::assertion())
     }
+}
+
+impl ServerCertVerifier for WebPkiServerVerifier {
+    /// Will verify the certificate is valid in the following ways:
+    /// - Signed by a trusted `RootCertStore` CA
+    /// - Not Expired
+    /// - Valid for DNS entry
+    /// - Valid revocation status (if applicable).
+    /// - Valid stapled OCSP response (if applicable).
+    /// - Sufficient signed certificate timestamps (if applicable).
+    ///
+    /// Depending on the verifier's configuration revocation status checking may be performed for
+    /// each certificate in the chain to a root CA (excluding the root itself), or only the
+    /// end entity certificate. Similarly, unknown revocation status may be treated as an error
+    /// or allowed based on configuration.
+    ///
+    /// A stapled OCSP response only ever covers the end entity certificate.
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        self.verify_server_cert_impl(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            &[],
+            now,
+        )
+    }
+
+    fn requests_signed_certificate_timestamps(&self) -> bool {
+        self.ct.is_some()
+    }
+
+    fn verify_server_cert_with_scts(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        sct_list: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        self.verify_server_cert_impl(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            sct_list,
+            now,
+        )
+    }
 
     fn verify_tls12_signature(
         &self,
@@ -298,6 +501,35 @@ impl ServerCertVerifier for WebPkiServerVerifier {
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
         self.supported.supported_schemes()
     }
+
+    fn delegated_credential_schemes(&self) -> Vec<SignatureScheme> {
+        match self.delegated_credentials {
+            true => self.supported.supported_schemes(),
+            false => Vec::new(),
+        }
+    }
+
+    fn verify_tls13_signature_with_delegated_credential(
+        &self,
+        message: &[u8],
+        end_entity: &CertificateDer<'_>,
+        credential: &DelegatedCredential,
+        dss: &DigitallySignedStruct,
+        now: UnixTime,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        if !self.delegated_credentials {
+            return Err(PeerMisbehaved::UnsolicitedCertExtension.into());
+        }
+
+        verify_tls13_signature_with_delegated_credential(
+            message,
+            end_entity,
+            credential,
+            dss,
+            now,
+            &self.supported,
+        )
+    }
 }
 
 #[cfg(test)]
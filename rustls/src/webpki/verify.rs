@@ -7,6 +7,8 @@ use pki_types::{
 
 use super::anchors::RootCertStore;
 use super::pki_error;
+use crate::common_state::Side;
+use crate::delegated_credential::DelegatedCredential;
 use crate::enums::SignatureScheme;
 use crate::error::{Error, PeerMisbehaved};
 use crate::verify::{DigitallySignedStruct, HandshakeSignatureValid};
@@ -38,6 +40,7 @@ pub fn verify_server_cert_signed_by_trust_anchor(
         now,
         supported_algs,
     )
+    .map(|_| ())
 }
 
 /// Verify that the `end_entity` has an alternative name matching the `server_name`.
@@ -92,7 +95,7 @@ impl WebPkiSupportedAlgorithms {
     }
 
     /// Return the first item in `mapping` that matches `scheme`.
-    fn convert_scheme(
+    pub(crate) fn convert_scheme(
         &self,
         scheme: SignatureScheme,
     ) -> Result<&[&'static dyn SignatureVerificationAlgorithm], Error> {
@@ -220,6 +223,42 @@ pub fn verify_tls13_signature_with_raw_key(
         .map(|_| HandshakeSignatureValid::assertion())
 }
 
+/// Verify a TLS 1.3 `CertificateVerify` signature made with a delegated credential.
+///
+/// This checks that `end_entity` permits delegation, that `credential` is valid at `now`
+/// and signed by `end_entity`'s key, and that `dss` is a valid signature over `msg` by
+/// the credential's key using its `dc_cert_verify_algorithm`.
+pub fn verify_tls13_signature_with_delegated_credential(
+    msg: &[u8],
+    end_entity: &CertificateDer<'_>,
+    credential: &DelegatedCredential,
+    dss: &DigitallySignedStruct,
+    now: UnixTime,
+    supported_schemes: &WebPkiSupportedAlgorithms,
+) -> Result<HandshakeSignatureValid, Error> {
+    credential.check_validity(end_entity, now)?;
+
+    let credential_dss =
+        DigitallySignedStruct::new(credential.algorithm, credential.signature.clone());
+    verify_tls13_signature(
+        &credential.signed_message(end_entity, Side::Server),
+        end_entity,
+        &credential_dss,
+        supported_schemes,
+    )?;
+
+    if dss.scheme != credential.dc_cert_verify_algorithm {
+        return Err(PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme.into());
+    }
+
+    verify_tls13_signature_with_raw_key(
+        msg,
+        &credential.subject_public_key_info,
+        dss,
+        supported_schemes,
+    )
+}
+
 /// Verify that the end-entity certificate `end_entity` is a valid server cert
 /// and chains to at least one of the trust anchors in the `roots` [RootCertStore].
 ///
@@ -229,31 +268,31 @@ pub fn verify_tls13_signature_with_raw_key(
 ///
 /// `revocation` controls how revocation checking is performed, if at all.
 ///
+/// On success, the path built to a trust anchor is returned.
+///
 /// This function exists to be used by [`verify_server_cert_signed_by_trust_anchor`],
 /// and differs only in providing a `Option<webpki::RevocationOptions>` argument. We
 /// can't include this argument in `verify_server_cert_signed_by_trust_anchor` because
 /// it will leak the webpki types into Rustls' public API.
-pub(crate) fn verify_server_cert_signed_by_trust_anchor_impl(
-    cert: &ParsedCertificate<'_>,
-    roots: &RootCertStore,
-    intermediates: &[CertificateDer<'_>],
+pub(crate) fn verify_server_cert_signed_by_trust_anchor_impl<'p>(
+    cert: &'p ParsedCertificate<'_>,
+    roots: &'p RootCertStore,
+    intermediates: &'p [CertificateDer<'p>],
     revocation: Option<webpki::RevocationOptions<'_>>,
     now: UnixTime,
     supported_algs: &[&dyn SignatureVerificationAlgorithm],
-) -> Result<(), Error> {
-    let result = cert.0.verify_for_usage(
-        supported_algs,
-        &roots.roots,
-        intermediates,
-        now,
-        webpki::KeyUsage::server_auth(),
-        revocation,
-        None,
-    );
-    match result {
-        Ok(_) => Ok(()),
-        Err(e) => Err(pki_error(e)),
-    }
+) -> Result<webpki::VerifiedPath<'p>, Error> {
+    cert.0
+        .verify_for_usage(
+            supported_algs,
+            &roots.roots,
+            intermediates,
+            now,
+            webpki::KeyUsage::server_auth(),
+            revocation,
+            None,
+        )
+        .map_err(pki_error)
 }
 
 #[cfg(test)]
@@ -2,6 +2,8 @@
 
 use alloc::vec::Vec;
 
+use der::{Decode, Reader};
+
 /// Prepend stuff to `bytes` to put it in a DER SEQUENCE.
 pub(crate) fn wrap_in_sequence(bytes: &[u8]) -> Vec<u8> {
     asn1_wrap(DER_SEQUENCE_TAG, bytes, &[])
@@ -24,7 +26,7 @@ pub(crate) fn wrap_in_octet_string(bytes: &[u8]) -> Vec<u8> {
     asn1_wrap(DER_OCTET_STRING_TAG, bytes, &[])
 }
 
-fn asn1_wrap(tag: u8, bytes_a: &[u8], bytes_b: &[u8]) -> Vec<u8> {
+pub(crate) fn asn1_wrap(tag: u8, bytes_a: &[u8], bytes_b: &[u8]) -> Vec<u8> {
     let len = bytes_a.len() + bytes_b.len();
 
     if len <= 0x7f {
@@ -57,6 +59,68 @@ fn asn1_wrap(tag: u8, bytes_a: &[u8], bytes_b: &[u8]) -> Vec<u8> {
     }
 }
 
+/// A reader for a series of DER-encoded elements, using the `der` crate to
+/// decode each element's tag and length.
+pub(crate) struct DerReader<'a>(&'a [u8]);
+
+impl<'a> DerReader<'a> {
+    pub(crate) fn new(der: &'a [u8]) -> Self {
+        Self(der)
+    }
+
+    /// Read the next element, returning its tag and contents.
+    pub(crate) fn read(&mut self) -> Option<(u8, &'a [u8])> {
+        self.read_with_header()
+            .map(|(tag, _, contents)| (tag, contents))
+    }
+
+    /// Read the next element, which must have the given `tag`, returning its contents.
+    pub(crate) fn expect(&mut self, tag: u8) -> Option<&'a [u8]> {
+        self.expect_with_header(tag)
+            .map(|(_, contents)| contents)
+    }
+
+    /// Read the next element, which must have the given `tag`, returning its complete
+    /// encoding and its contents.
+    pub(crate) fn expect_with_header(&mut self, tag: u8) -> Option<(&'a [u8], &'a [u8])> {
+        match self.read_with_header()? {
+            (actual, whole, contents) if actual == tag => Some((whole, contents)),
+            _ => None,
+        }
+    }
+
+    /// Read the next element if it has the given `tag`.
+    ///
+    /// The outer `Option` is `None` on malformed input.
+    pub(crate) fn optional(&mut self, tag: u8) -> Option<Option<&'a [u8]>> {
+        match self.0.first() {
+            Some(&actual) if actual == tag => self.expect(tag).map(Some),
+            _ => Some(None),
+        }
+    }
+
+    pub(crate) fn read_with_header(&mut self) -> Option<(u8, &'a [u8], &'a [u8])> {
+        let mut reader = der::SliceReader::new(self.0).ok()?;
+        let header = der::Header::decode(&mut reader).ok()?;
+        let contents = reader.read_slice(header.length).ok()?;
+
+        let (whole, rest) = self
+            .0
+            .split_at(usize::try_from(reader.position()).ok()?);
+        self.0 = rest;
+        Some((header.tag.into(), whole, contents))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Require that all input was consumed.
+    pub(crate) fn finish(&self) -> Option<()> {
+        self.is_empty().then_some(())
+    }
+}
+
 const DER_SEQUENCE_TAG: u8 = 0x30;
 const DER_BIT_STRING_TAG: u8 = 0x03;
 const DER_OCTET_STRING_TAG: u8 = 0x04;
@@ -131,6 +195,19 @@ mod tests {
         assert_eq!(result.len(), 0x1000000 + 6);
     }
 
+    #[test]
+    fn reader_rejects_bad_lengths() {
+        assert_eq!(DerReader::new(&[0x30, 0x81, 0x7f]).read(), None);
+        assert_eq!(DerReader::new(&[0x30, 0x82, 0x00, 0x80]).read(), None);
+        assert_eq!(DerReader::new(&[0x30, 0x85, 0, 0, 0, 0, 1]).read(), None);
+        assert_eq!(DerReader::new(&[0x30, 0x02, 0x00]).read(), None);
+        assert_eq!(DerReader::new(&[0x1f, 0x00]).read(), None);
+        assert_eq!(
+            DerReader::new(&[0x04, 0x01, 0xaa]).read(),
+            Some((DER_OCTET_STRING_TAG, &[0xaa][..]))
+        );
+    }
+
     #[test]
     fn test_wrap_in_bit_string() {
         // The BIT STRING encoding starts with a single octet on
@@ -7,10 +7,14 @@ use std::io::{self, IoSlice, Read, Write};
 use std::ops::{Deref, DerefMut};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::{fmt, mem};
 
 use pki_types::{CertificateDer, IpAddr, ServerName, UnixTime};
-use rustls::client::{verify_server_cert_signed_by_trust_anchor, ResolvesClientCert, Resumption};
+use rustls::client::{
+    verify_server_cert_signed_by_trust_anchor, ClientSessionStore, ResolvesClientCert, Resumption,
+};
+use rustls::crypto::hash::HashAlgorithm;
 use rustls::crypto::{ActiveKeyExchange, CryptoProvider, SharedSecret, SupportedKxGroup};
 use rustls::internal::msgs::base::Payload;
 use rustls::internal::msgs::codec::Codec;
@@ -20,23 +24,32 @@ use rustls::internal::msgs::handshake::{
     ServerExtension, ServerName as ServerNameExtensionItem, SessionId,
 };
 use rustls::internal::msgs::message::{Message, MessagePayload, PlainMessage};
-use rustls::server::{ClientHello, ParsedCertificate, ResolvesServerCert};
+use rustls::kernel::KernelAction;
+use rustls::psk::{ExternalPsk, PskKeyExchangeModes};
+use rustls::server::{
+    ClientHello, HandlesCustomExtensions, OperationResult, ParsedCertificate, PendingOperation,
+    ProducesTickets, ResolvesServerCert, StrikeRegister,
+};
 #[cfg(feature = "aws_lc_rs")]
 use rustls::{
-    client::{EchConfig, EchGreaseConfig, EchMode},
+    client::{EchConfig, EchGreaseConfig, EchMode, EchStatus},
     crypto::aws_lc_rs::hpke::ALL_SUPPORTED_SUITES,
+    crypto::hpke::Hpke,
     internal::msgs::base::PayloadU16,
     internal::msgs::handshake::{
         EchConfigContents, EchConfigPayload, HpkeKeyConfig, HpkeSymmetricCipherSuite,
     },
     pki_types::{DnsName, EchConfigListBytes},
+    server::EchServerKey,
 };
 use rustls::{
     sign, AlertDescription, CertificateError, CipherSuite, ClientConfig, ClientConnection,
-    ConnectionCommon, ConnectionTrafficSecrets, ContentType, DistinguishedName, Error,
-    HandshakeKind, HandshakeType, InconsistentKeys, InvalidMessage, KeyLog, NamedGroup,
-    PeerIncompatible, PeerMisbehaved, ProtocolVersion, ServerConfig, ServerConnection, SideData,
-    SignatureScheme, Stream, StreamOwned, SupportedCipherSuite,
+    ConnectionCommon, ConnectionObserver, ConnectionTrafficSecrets, ContentType, CustomExtension,
+    DistinguishedName, ECPointFormat, Error, ExtensionType, HandshakeEvent, HandshakeKind,
+    HandshakeObserver, HandshakeType, InconsistentKeys, InvalidMessage, KeyLog, NamedGroup,
+    PSKKeyExchangeMode, PadsRecords, PeerIncompatible, PeerMisbehaved, ProtocolVersion,
+    RecordPadding, ServerConfig, ServerConnection, Side, SideData, SignatureScheme, Stream,
+    StreamOwned, SupportedCipherSuite, TicketKey, TicketKeys,
 };
 
 use super::*;
@@ -1629,6 +1642,334 @@ fn client_with_sni_disabled_does_not_send_sni() {
     }
 }
 
+#[test]
+fn server_defers_certificate_resolution_and_signing() {
+    for kt in ALL_KEY_TYPES {
+        let mut server_config = make_server_config(*kt);
+        server_config.cert_resolver = Arc::new(DeferredResolver);
+        let server_config = Arc::new(server_config);
+
+        for version in rustls::ALL_VERSIONS {
+            for defer_signing in [false, true] {
+                let client_config = make_client_config_with_versions(*kt, &[version]);
+                let (mut client, mut server) =
+                    make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+
+                transfer(&mut client, &mut server);
+                server.process_new_packets().unwrap();
+
+                match server.pending_operation() {
+                    Some(PendingOperation::ResolveCertificate(client_hello)) => {
+                        assert_eq!(client_hello.server_name(), Some("localhost"));
+                    }
+                    other => panic!("unexpected pending operation {other:?}"),
+                }
+                assert!(!server.wants_write());
+
+                let result =
+                    kt.complete_operation(server.pending_operation().unwrap(), defer_signing);
+                server
+                    .complete_pending_operation(result)
+                    .unwrap();
+
+                if defer_signing {
+                    let Some(operation @ PendingOperation::Sign { .. }) =
+                        server.pending_operation()
+                    else {
+                        panic!("expected signing to be pending");
+                    };
+                    let result = kt.complete_operation(operation, defer_signing);
+                    server
+                        .complete_pending_operation(result)
+                        .unwrap();
+                }
+
+                assert!(server.pending_operation().is_none());
+                do_handshake(&mut client, &mut server);
+                assert_eq!(client.protocol_version(), Some(version.version));
+            }
+        }
+    }
+}
+
+#[test]
+fn server_leaves_data_unprocessed_while_operation_pending() {
+    let kt = KeyType::Rsa2048;
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(DeferredResolver);
+    let (mut client, mut server) = make_pair_for_configs(make_client_config(kt), server_config);
+
+    let mut client_hello = Vec::new();
+    client
+        .write_tls(&mut client_hello)
+        .unwrap();
+
+    // the second copy of the ClientHello is not processed until the operation is complete
+    server
+        .read_tls(&mut &client_hello[..])
+        .unwrap();
+    server
+        .read_tls(&mut &client_hello[..])
+        .unwrap();
+    server.process_new_packets().unwrap();
+    assert!(server.pending_operation().is_some());
+    server.process_new_packets().unwrap();
+    assert!(server.pending_operation().is_some());
+
+    let result = kt.complete_operation(server.pending_operation().unwrap(), false);
+    server
+        .complete_pending_operation(result)
+        .unwrap();
+    // ... and is then rejected, as the server now expects encrypted messages
+    assert_eq!(server.process_new_packets(), Err(Error::DecryptError));
+}
+
+#[test]
+fn server_rejects_mismatched_operation_result() {
+    let kt = KeyType::EcdsaP256;
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(DeferredResolver);
+    let (mut client, mut server) = make_pair_for_configs(make_client_config(kt), server_config);
+
+    assert_eq!(
+        server.complete_pending_operation(OperationResult::Certificate(None)),
+        Err(Error::General("no operation pending".into()))
+    );
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    assert_eq!(
+        server.complete_pending_operation(OperationResult::Signature(Ok(vec![]))),
+        Err(Error::General(
+            "result does not match pending operation".into()
+        ))
+    );
+    assert!(server.pending_operation().is_some());
+
+    assert_eq!(
+        server.complete_pending_operation(OperationResult::Certificate(None)),
+        Err(Error::General(
+            "no server certificate chain resolved".into()
+        ))
+    );
+    assert!(server.pending_operation().is_none());
+
+    transfer(&mut server, &mut client);
+    assert_eq!(
+        client.process_new_packets(),
+        Err(Error::AlertReceived(AlertDescription::AccessDenied))
+    );
+}
+
+fn test_psk(identity: &[u8]) -> ExternalPsk {
+    ExternalPsk::new(identity.to_vec(), vec![0x11; 32]).unwrap()
+}
+
+fn check_psk_data_transfer(client: &mut ClientConnection, server: &mut ServerConnection) {
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(client, server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"hello");
+}
+
+fn make_psk_configs(
+    client_psks: impl IntoIterator<Item = ExternalPsk>,
+    server_psks: impl IntoIterator<Item = ExternalPsk>,
+) -> (ClientConfig, ServerConfig) {
+    let kt = KeyType::EcdsaP256;
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    client_config.psk_resolver = Some(StaticPskResolver::new(client_psks));
+    let mut server_config = make_server_config(kt);
+    server_config.psk_resolver = Some(StaticPskResolver::new(server_psks));
+    (client_config, server_config)
+}
+
+#[test]
+fn external_psk_handshake() {
+    let (client_config, server_config) =
+        make_psk_configs([test_psk(b"other"), test_psk(b"psk")], [test_psk(b"psk")]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(client.external_psk_identity(), Some(&b"psk"[..]));
+    assert_eq!(server.external_psk_identity(), Some(&b"psk"[..]));
+    assert!(client.peer_certificates().is_none());
+    assert!(client
+        .negotiated_key_exchange_group()
+        .is_some());
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Full));
+    check_psk_data_transfer(&mut client, &mut server);
+}
+
+#[test]
+fn external_psk_only_handshake_without_server_certificate() {
+    let psk = || test_psk(b"psk").with_key_exchange_modes(PskKeyExchangeModes::PskOnly);
+    let (client_config, mut server_config) = make_psk_configs([psk()], [psk()]);
+    server_config.cert_resolver = Arc::new(rustls::server::ResolvesServerCertUsingSni::new());
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(client.external_psk_identity(), Some(&b"psk"[..]));
+    assert_eq!(server.external_psk_identity(), Some(&b"psk"[..]));
+    assert!(client
+        .negotiated_key_exchange_group()
+        .is_none());
+    assert!(server
+        .negotiated_key_exchange_group()
+        .is_none());
+    check_psk_data_transfer(&mut client, &mut server);
+}
+
+#[test]
+fn external_psk_prefers_dhe_when_both_modes_allowed() {
+    let psk = || test_psk(b"psk").with_key_exchange_modes(PskKeyExchangeModes::DheOrPskOnly);
+    let (client_config, server_config) = make_psk_configs([psk()], [psk()]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(server.external_psk_identity(), Some(&b"psk"[..]));
+    assert!(server
+        .negotiated_key_exchange_group()
+        .is_some());
+}
+
+#[test]
+fn external_psk_follows_server_mode_preference() {
+    let client_psk = test_psk(b"psk").with_key_exchange_modes(PskKeyExchangeModes::DheOrPskOnly);
+    for (server_modes, expect_dhe) in [
+        (PskKeyExchangeModes::PskOnlyOrDhe, false),
+        (PskKeyExchangeModes::PskOnly, false),
+        (PskKeyExchangeModes::DheOrPskOnly, true),
+        (PskKeyExchangeModes::Dhe, true),
+    ] {
+        let server_psk = test_psk(b"psk").with_key_exchange_modes(server_modes);
+        let (client_config, server_config) = make_psk_configs([client_psk.clone()], [server_psk]);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+
+        assert_eq!(server.external_psk_identity(), Some(&b"psk"[..]));
+        assert_eq!(
+            client
+                .negotiated_key_exchange_group()
+                .is_some(),
+            expect_dhe
+        );
+        assert_eq!(
+            server
+                .negotiated_key_exchange_group()
+                .is_some(),
+            expect_dhe
+        );
+        check_psk_data_transfer(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn imported_psk_handshake() {
+    let provider = provider::default_provider();
+    let imported = test_psk(b"epsk")
+        .import(b"context", HashAlgorithm::SHA384, &provider)
+        .unwrap();
+    let identity = imported.identity().to_vec();
+
+    let (client_config, server_config) = make_psk_configs([imported.clone()], [imported]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(server.external_psk_identity(), Some(&identity[..]));
+    assert_eq!(
+        client
+            .negotiated_cipher_suite()
+            .and_then(|suite| suite.tls13())
+            .map(|suite| suite.common.hash_provider.algorithm()),
+        Some(HashAlgorithm::SHA384)
+    );
+}
+
+#[test]
+fn external_psk_with_wrong_secret_is_rejected() {
+    let (client_config, server_config) = make_psk_configs(
+        [test_psk(b"psk")],
+        [ExternalPsk::new(b"psk".to_vec(), vec![0x22; 32]).unwrap()],
+    );
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerMisbehaved(
+            PeerMisbehaved::IncorrectBinder
+        )))
+    );
+    transfer(&mut server, &mut client);
+    assert_eq!(
+        client.process_new_packets(),
+        Err(Error::AlertReceived(AlertDescription::DecryptError))
+    );
+}
+
+#[test]
+fn unknown_external_psk_falls_back_to_certificate() {
+    let (client_config, server_config) =
+        make_psk_configs([test_psk(b"client")], [test_psk(b"server")]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(client.external_psk_identity(), None);
+    assert_eq!(server.external_psk_identity(), None);
+    assert!(client.peer_certificates().is_some());
+}
+
+#[test]
+fn psk_only_server_rejects_client_without_psk() {
+    let (mut client_config, mut server_config) = make_psk_configs([], [test_psk(b"psk")]);
+    client_config.psk_resolver = None;
+    server_config.cert_resolver = Arc::new(rustls::server::ResolvesServerCertUsingSni::new());
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::General(
+            "no server certificate chain resolved".into()
+        )))
+    );
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn external_psk_is_not_offered_for_tls12() {
+    let (_, server_config) = make_psk_configs([], [test_psk(b"psk")]);
+    let mut client_config =
+        make_client_config_with_versions(KeyType::EcdsaP256, &[&rustls::version::TLS12]);
+    client_config.psk_resolver = Some(StaticPskResolver::new([test_psk(b"psk")]));
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_2));
+    assert_eq!(server.external_psk_identity(), None);
+    assert!(client.peer_certificates().is_some());
+}
+
+#[test]
+fn external_psk_handshake_can_be_resumed() {
+    let (client_config, server_config) = make_psk_configs([test_psk(b"psk")], [test_psk(b"psk")]);
+    let client_config = Arc::new(client_config);
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.external_psk_identity(), Some(&b"psk"[..]));
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Resumed));
+    assert_eq!(client.external_psk_identity(), None);
+    assert_eq!(server.external_psk_identity(), None);
+}
+
 #[test]
 fn client_checks_server_certificate_with_given_name() {
     for kt in ALL_KEY_TYPES {
@@ -2088,61 +2429,278 @@ fn client_auth_works() {
     }
 }
 
-#[test]
-fn client_mandatory_auth_client_revocation_works() {
-    for kt in ALL_KEY_TYPES {
-        // Create a server configuration that includes a CRL that specifies the client certificate
-        // is revoked.
-        let relevant_crls = vec![kt.client_crl()];
-        // Only check the EE certificate status. See client_mandatory_auth_intermediate_revocation_works
-        // for testing revocation status of the whole chain.
-        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
-            .with_crls(relevant_crls)
-            .only_check_end_entity_revocation();
-        let revoked_server_config = Arc::new(make_server_config_with_client_verifier(
-            *kt,
-            ee_verifier_builder,
-        ));
+fn make_server_config_with_deferred_client_auth(kt: KeyType) -> ServerConfig {
+    make_server_config_with_client_verifier(
+        kt,
+        webpki_client_verifier_builder(get_client_root_store(kt)).defer_client_auth(),
+    )
+}
 
-        // Create a server configuration that includes a CRL that doesn't cover the client certificate,
-        // and uses the default behaviour of treating unknown revocation status as an error.
-        let unrelated_crls = vec![kt.intermediate_crl()];
-        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
-            .with_crls(unrelated_crls.clone())
-            .only_check_end_entity_revocation();
-        let missing_client_crl_server_config = Arc::new(make_server_config_with_client_verifier(
-            *kt,
-            ee_verifier_builder,
-        ));
+fn make_client_config_with_post_handshake_auth(kt: KeyType) -> ClientConfig {
+    let mut client_config =
+        make_client_config_with_versions_with_auth(kt, &[&rustls::version::TLS13]);
+    client_config.enable_post_handshake_auth = true;
+    client_config
+}
 
-        // Create a server configuration that includes a CRL that doesn't cover the client certificate,
-        // but change the builder to allow unknown revocation status.
-        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
-            .with_crls(unrelated_crls.clone())
-            .only_check_end_entity_revocation()
-            .allow_unknown_revocation_status();
-        let allow_missing_client_crl_server_config = Arc::new(
-            make_server_config_with_client_verifier(*kt, ee_verifier_builder),
-        );
+fn do_post_handshake_client_auth(
+    client: &mut ClientConnection,
+    server: &mut ServerConnection,
+) -> Result<(), ErrorFromPeer> {
+    server
+        .request_client_certificate()
+        .unwrap();
+    assert!(server.client_certificate_requested());
+    transfer(server, client);
+    client
+        .process_new_packets()
+        .map_err(ErrorFromPeer::Client)?;
+    transfer(client, server);
+    server
+        .process_new_packets()
+        .map_err(ErrorFromPeer::Server)?;
+    Ok(())
+}
 
-        for version in rustls::ALL_VERSIONS {
-            // Connecting to the server with a CRL that indicates the client certificate is revoked
-            // should fail with the expected error.
-            let client_config =
-                Arc::new(make_client_config_with_versions_with_auth(*kt, &[version]));
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &revoked_server_config);
-            let err = do_handshake_until_error(&mut client, &mut server);
+#[test]
+fn post_handshake_client_auth_works() {
+    for kt in ALL_KEY_TYPES {
+        let server_config = Arc::new(make_server_config_with_deferred_client_auth(*kt));
+        let client_config = Arc::new(make_client_config_with_post_handshake_auth(*kt));
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert!(server.peer_certificates().is_none());
+
+        // twice, to check the second exchange uses the right transcript
+        for _ in 0..2 {
+            do_post_handshake_client_auth(&mut client, &mut server).unwrap();
+            assert!(!server.client_certificate_requested());
             assert_eq!(
-                err,
-                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
-                    CertificateError::Revoked
-                )))
+                server
+                    .peer_certificates()
+                    .map(|certs| certs.len()),
+                Some(kt.get_client_chain().len())
             );
-            // Connecting to the server missing CRL information for the client certificate should
-            // fail with the expected unknown revocation status error.
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &missing_client_crl_server_config);
+        }
+
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        check_read(&mut server.reader(), b"hello");
+    }
+}
+
+#[test]
+fn post_handshake_client_auth_after_key_update() {
+    let kt = KeyType::EcdsaP256;
+    let (mut client, mut server) = make_pair_for_configs(
+        make_client_config_with_post_handshake_auth(kt),
+        make_server_config_with_deferred_client_auth(kt),
+    );
+    do_handshake(&mut client, &mut server);
+
+    client.refresh_traffic_keys().unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    do_post_handshake_client_auth(&mut client, &mut server).unwrap();
+    assert!(server.peer_certificates().is_some());
+}
+
+#[test]
+fn post_handshake_client_auth_declined() {
+    let kt = KeyType::Rsa2048;
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    client_config.enable_post_handshake_auth = true;
+    let client_config = Arc::new(client_config);
+
+    let optional = Arc::new(make_server_config_with_client_verifier(
+        kt,
+        webpki_client_verifier_builder(get_client_root_store(kt))
+            .defer_client_auth()
+            .allow_unauthenticated(),
+    ));
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &optional);
+    do_handshake(&mut client, &mut server);
+    do_post_handshake_client_auth(&mut client, &mut server).unwrap();
+    assert!(!server.client_certificate_requested());
+    assert!(server.peer_certificates().is_none());
+
+    let mandatory = Arc::new(make_server_config_with_deferred_client_auth(kt));
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &mandatory);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        do_post_handshake_client_auth(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::NoCertificatesPresented))
+    );
+    transfer(&mut server, &mut client);
+    assert_eq!(
+        client.process_new_packets(),
+        Err(Error::AlertReceived(AlertDescription::CertificateRequired))
+    );
+}
+
+#[test]
+fn post_handshake_client_auth_rejects_invalid_certificate() {
+    let kt = KeyType::Rsa2048;
+    let server_config = make_server_config_with_client_verifier(
+        kt,
+        webpki_client_verifier_builder(get_client_root_store(kt))
+            .with_crls(vec![kt.client_crl()])
+            .only_check_end_entity_revocation()
+            .defer_client_auth(),
+    );
+    let (mut client, mut server) = make_pair_for_configs(
+        make_client_config_with_post_handshake_auth(kt),
+        server_config,
+    );
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        do_post_handshake_client_auth(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+            CertificateError::Revoked
+        )))
+    );
+    assert!(server.peer_certificates().is_none());
+}
+
+#[test]
+fn post_handshake_client_auth_preconditions() {
+    let kt = KeyType::EcdsaP256;
+    let server_config = Arc::new(make_server_config_with_deferred_client_auth(kt));
+
+    // not before the handshake completes
+    let (mut client, mut server) = make_pair_for_arc_configs(
+        &Arc::new(make_client_config_with_post_handshake_auth(kt)),
+        &server_config,
+    );
+    assert_eq!(
+        server.request_client_certificate(),
+        Err(Error::HandshakeNotComplete)
+    );
+
+    // only one request at a time
+    do_handshake(&mut client, &mut server);
+    server
+        .request_client_certificate()
+        .unwrap();
+    assert_eq!(
+        server.request_client_certificate(),
+        Err(Error::General(
+            "client authentication already requested".into()
+        ))
+    );
+
+    // not unless offered by the client
+    let (mut client, mut server) = make_pair_for_arc_configs(
+        &Arc::new(make_client_config_with_versions_with_auth(
+            kt,
+            &[&rustls::version::TLS13],
+        )),
+        &server_config,
+    );
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        server.request_client_certificate(),
+        Err(Error::PeerIncompatible(
+            PeerIncompatible::PostHandshakeAuthNotOffered
+        ))
+    );
+
+    // not unless the server verifier does client auth
+    let (mut client, mut server) = make_pair_for_configs(
+        make_client_config_with_post_handshake_auth(kt),
+        make_server_config(kt),
+    );
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        server.request_client_certificate(),
+        Err(Error::General(
+            "client authentication is not enabled".into()
+        ))
+    );
+    assert!(!server.client_certificate_requested());
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn post_handshake_client_auth_not_available_for_tls12() {
+    let kt = KeyType::EcdsaP256;
+    let mut client_config =
+        make_client_config_with_versions_with_auth(kt, &[&rustls::version::TLS12]);
+    client_config.enable_post_handshake_auth = true;
+    let (mut client, mut server) = make_pair_for_configs(
+        client_config,
+        make_server_config_with_deferred_client_auth(kt),
+    );
+    do_handshake(&mut client, &mut server);
+    assert!(server.peer_certificates().is_none());
+    assert_eq!(
+        server.request_client_certificate(),
+        Err(Error::PeerIncompatible(
+            PeerIncompatible::PostHandshakeAuthNotOffered
+        ))
+    );
+}
+
+#[test]
+fn client_mandatory_auth_client_revocation_works() {
+    for kt in ALL_KEY_TYPES {
+        // Create a server configuration that includes a CRL that specifies the client certificate
+        // is revoked.
+        let relevant_crls = vec![kt.client_crl()];
+        // Only check the EE certificate status. See client_mandatory_auth_intermediate_revocation_works
+        // for testing revocation status of the whole chain.
+        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
+            .with_crls(relevant_crls)
+            .only_check_end_entity_revocation();
+        let revoked_server_config = Arc::new(make_server_config_with_client_verifier(
+            *kt,
+            ee_verifier_builder,
+        ));
+
+        // Create a server configuration that includes a CRL that doesn't cover the client certificate,
+        // and uses the default behaviour of treating unknown revocation status as an error.
+        let unrelated_crls = vec![kt.intermediate_crl()];
+        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
+            .with_crls(unrelated_crls.clone())
+            .only_check_end_entity_revocation();
+        let missing_client_crl_server_config = Arc::new(make_server_config_with_client_verifier(
+            *kt,
+            ee_verifier_builder,
+        ));
+
+        // Create a server configuration that includes a CRL that doesn't cover the client certificate,
+        // but change the builder to allow unknown revocation status.
+        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
+            .with_crls(unrelated_crls.clone())
+            .only_check_end_entity_revocation()
+            .allow_unknown_revocation_status();
+        let allow_missing_client_crl_server_config = Arc::new(
+            make_server_config_with_client_verifier(*kt, ee_verifier_builder),
+        );
+
+        for version in rustls::ALL_VERSIONS {
+            // Connecting to the server with a CRL that indicates the client certificate is revoked
+            // should fail with the expected error.
+            let client_config =
+                Arc::new(make_client_config_with_versions_with_auth(*kt, &[version]));
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &revoked_server_config);
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+                    CertificateError::Revoked
+                )))
+            );
+            // Connecting to the server missing CRL information for the client certificate should
+            // fail with the expected unknown revocation status error.
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &missing_client_crl_server_config);
             let res = do_handshake_until_error(&mut client, &mut server);
             assert!(matches!(
                 res,
@@ -3303,7 +3861,7 @@ fn server_exposes_offered_sni_even_if_resolver_fails() {
 #[test]
 fn sni_resolver_works() {
     let kt = KeyType::Rsa2048;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let resolver = rustls::server::ResolvesServerCertUsingSni::new();
     let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
     let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
     resolver
@@ -3341,7 +3899,7 @@ fn sni_resolver_works() {
 #[test]
 fn sni_resolver_rejects_wrong_names() {
     let kt = KeyType::Rsa2048;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let resolver = rustls::server::ResolvesServerCertUsingSni::new();
     let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
     let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
 
@@ -3371,7 +3929,7 @@ fn sni_resolver_rejects_wrong_names() {
 #[test]
 fn sni_resolver_lower_cases_configured_names() {
     let kt = KeyType::Rsa2048;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let resolver = rustls::server::ResolvesServerCertUsingSni::new();
     let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
     let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
 
@@ -3398,7 +3956,7 @@ fn sni_resolver_lower_cases_configured_names() {
 fn sni_resolver_lower_cases_queried_names() {
     // actually, the handshake parser does this, but the effect is the same.
     let kt = KeyType::Rsa2048;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let resolver = rustls::server::ResolvesServerCertUsingSni::new();
     let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
     let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
 
@@ -3424,7 +3982,7 @@ fn sni_resolver_lower_cases_queried_names() {
 #[test]
 fn sni_resolver_rejects_bad_certs() {
     let kt = KeyType::Rsa2048;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let resolver = rustls::server::ResolvesServerCertUsingSni::new();
     let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
     let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
 
@@ -3446,90 +4004,504 @@ fn sni_resolver_rejects_bad_certs() {
     );
 }
 
-#[test]
-fn test_keys_match() {
-    // Consistent: Both of these should have the same SPKI values
-    let expect_consistent =
-        sign::CertifiedKey::new(KeyType::Rsa2048.get_chain(), Arc::new(SigningKeySomeSpki));
-    assert!(matches!(expect_consistent.keys_match(), Ok(())));
-
-    // Inconsistent: These should not have the same SPKI values
-    let expect_inconsistent =
-        sign::CertifiedKey::new(KeyType::EcdsaP256.get_chain(), Arc::new(SigningKeySomeSpki));
-    assert!(matches!(
-        expect_inconsistent.keys_match(),
-        Err(Error::InconsistentKeys(InconsistentKeys::KeyMismatch))
-    ));
-
-    // Unknown: This signing key returns None for its SPKI, so we can't tell if the certified key is consistent
-    let expect_unknown =
-        sign::CertifiedKey::new(KeyType::Rsa2048.get_chain(), Arc::new(SigningKeyNoneSpki));
-    assert!(matches!(
-        expect_unknown.keys_match(),
-        Err(Error::InconsistentKeys(InconsistentKeys::Unknown))
-    ));
+fn certified_key(kt: KeyType) -> sign::CertifiedKey {
+    let key = provider::default_provider()
+        .key_provider
+        .load_private_key(kt.get_key())
+        .unwrap();
+    sign::CertifiedKey::new(kt.get_chain(), key)
 }
 
-/// Represents a SigningKey that returns None for its SPKI via the default impl.
-#[derive(Debug)]
-struct SigningKeyNoneSpki;
-
-impl sign::SigningKey for SigningKeyNoneSpki {
-    fn choose_scheme(&self, _offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
-        unimplemented!("Not meant to be called during tests")
-    }
-
-    fn algorithm(&self) -> rustls::SignatureAlgorithm {
-        unimplemented!("Not meant to be called during tests")
-    }
+fn server_certificate_for(
+    server_config: &Arc<ServerConfig>,
+    client_config: ClientConfig,
+    name: &'static str,
+) -> Result<CertificateDer<'static>, ErrorFromPeer> {
+    let mut server = ServerConnection::new(Arc::clone(server_config)).unwrap();
+    let mut client = ClientConnection::new(Arc::new(client_config), server_name(name)).unwrap();
+    do_handshake_until_error(&mut client, &mut server)?;
+    Ok(client.peer_certificates().unwrap()[0].clone())
 }
 
-/// Represents a SigningKey that returns Some for its SPKI.
-#[derive(Debug)]
-struct SigningKeySomeSpki;
-
-impl sign::SigningKey for SigningKeySomeSpki {
-    fn public_key(&self) -> Option<pki_types::SubjectPublicKeyInfoDer> {
-        let chain = KeyType::Rsa2048.get_chain();
-        let cert = ParsedCertificate::try_from(chain.first().unwrap()).unwrap();
-        Some(
-            cert.subject_public_key_info()
-                .into_owned(),
-        )
-    }
-
-    fn choose_scheme(&self, _offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
-        unimplemented!("Not meant to be called during tests")
-    }
-
-    fn algorithm(&self) -> rustls::SignatureAlgorithm {
-        unimplemented!("Not meant to be called during tests")
-    }
+fn client_config_accepting_anything(signature_schemes: Vec<SignatureScheme>) -> ClientConfig {
+    ClientConfig::builder_with_provider(provider::default_provider().into())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(MockServerVerifier::offers_signature_schemes(
+            signature_schemes,
+        )))
+        .with_no_client_auth()
 }
 
-fn do_exporter_test(client_config: ClientConfig, server_config: ServerConfig) {
-    let mut client_secret = [0u8; 64];
-    let mut server_secret = [0u8; 64];
+#[test]
+fn sni_resolver_matches_wildcards() {
+    let key = rcgen::KeyPair::generate().unwrap();
+    let wildcard_cert = rcgen::CertificateParams::new(vec!["*.tenant.example".into()])
+        .unwrap()
+        .self_signed(&key)
+        .unwrap();
+    let wildcard_key = sign::CertifiedKey::new(
+        vec![wildcard_cert.der().clone()],
+        provider::default_provider()
+            .key_provider
+            .load_private_key(pki_types::PrivatePkcs8KeyDer::from(key.serialize_der()).into())
+            .unwrap(),
+    );
 
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    let resolver = Arc::new(rustls::server::ResolvesServerCertUsingSni::new());
+    assert_eq!(
+        Err(Error::InvalidCertificate(CertificateError::NotValidForName)),
+        resolver.add("*.other.example", wildcard_key.clone())
+    );
+    resolver
+        .add("*.tenant.example", wildcard_key)
+        .unwrap();
+
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.cert_resolver = resolver.clone();
+    let server_config = Arc::new(server_config);
+    let client_config =
+        || client_config_accepting_anything(vec![SignatureScheme::ECDSA_NISTP256_SHA256]);
 
     assert_eq!(
-        Err(Error::HandshakeNotComplete),
-        client.export_keying_material(&mut client_secret, b"label", Some(b"context"))
+        server_certificate_for(&server_config, client_config(), "a.tenant.example"),
+        Ok(wildcard_cert.der().clone())
     );
     assert_eq!(
-        Err(Error::HandshakeNotComplete),
-        server.export_keying_material(&mut server_secret, b"label", Some(b"context"))
+        server_certificate_for(&server_config, client_config(), "B.Tenant.Example"),
+        Ok(wildcard_cert.der().clone())
     );
-    do_handshake(&mut client, &mut server);
 
-    assert!(client
-        .export_keying_material(&mut client_secret, b"label", Some(b"context"))
-        .is_ok());
-    assert!(server
-        .export_keying_material(&mut server_secret, b"label", Some(b"context"))
-        .is_ok());
-    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
+    // a wildcard only covers one label
+    for name in ["tenant.example", "a.b.tenant.example"] {
+        assert_eq!(
+            server_certificate_for(&server_config, client_config(), name),
+            Err(ErrorFromPeer::Server(Error::General(
+                "no server certificate chain resolved".into()
+            )))
+        );
+    }
+
+    assert!(resolver.remove("*.TENANT.example"));
+    assert!(!resolver.remove("*.tenant.example"));
+    assert!(server_certificate_for(&server_config, client_config(), "a.tenant.example").is_err());
+}
+
+#[test]
+fn sni_resolver_prefers_exact_names_to_wildcards() {
+    let key = rcgen::KeyPair::generate().unwrap();
+    let wildcard_cert = rcgen::CertificateParams::new(vec!["*.testserver.com".into()])
+        .unwrap()
+        .self_signed(&key)
+        .unwrap();
+    let wildcard_key = sign::CertifiedKey::new(
+        vec![wildcard_cert.der().clone()],
+        provider::default_provider()
+            .key_provider
+            .load_private_key(pki_types::PrivatePkcs8KeyDer::from(key.serialize_der()).into())
+            .unwrap(),
+    );
+
+    let kt = KeyType::EcdsaP256;
+    let resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    resolver
+        .add("*.testserver.com", wildcard_key)
+        .unwrap();
+    resolver
+        .add("second.testserver.com", certified_key(kt))
+        .unwrap();
+
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
+
+    assert_eq!(
+        server_certificate_for(
+            &server_config,
+            make_client_config(kt),
+            "second.testserver.com"
+        ),
+        Ok(kt.get_chain()[0].clone())
+    );
+    assert_eq!(
+        server_certificate_for(
+            &server_config,
+            client_config_accepting_anything(vec![SignatureScheme::ECDSA_NISTP256_SHA256]),
+            "first.testserver.com"
+        ),
+        Ok(wildcard_cert.der().clone())
+    );
+}
+
+#[test]
+fn sni_resolver_uses_default_certificate() {
+    let resolver = Arc::new(rustls::server::ResolvesServerCertUsingSni::new());
+    resolver
+        .add("localhost", certified_key(KeyType::Rsa2048))
+        .unwrap();
+    resolver
+        .add_default(certified_key(KeyType::EcdsaP256))
+        .unwrap();
+
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.cert_resolver = resolver.clone();
+    let server_config = Arc::new(server_config);
+
+    assert_eq!(
+        server_certificate_for(
+            &server_config,
+            make_client_config(KeyType::Rsa2048),
+            "localhost"
+        ),
+        Ok(KeyType::Rsa2048.get_chain()[0].clone())
+    );
+    assert_eq!(
+        server_certificate_for(
+            &server_config,
+            make_client_config(KeyType::EcdsaP256),
+            "testserver.com"
+        ),
+        Ok(KeyType::EcdsaP256.get_chain()[0].clone())
+    );
+
+    // without SNI
+    let mut client_config = make_client_config(KeyType::EcdsaP256);
+    client_config.enable_sni = false;
+    assert_eq!(
+        server_certificate_for(&server_config, client_config, "testserver.com"),
+        Ok(KeyType::EcdsaP256.get_chain()[0].clone())
+    );
+
+    assert!(resolver.remove_default());
+    assert!(!resolver.remove_default());
+    assert_eq!(
+        server_certificate_for(
+            &server_config,
+            make_client_config(KeyType::EcdsaP256),
+            "testserver.com"
+        ),
+        Err(ErrorFromPeer::Server(Error::General(
+            "no server certificate chain resolved".into()
+        )))
+    );
+}
+
+#[test]
+fn sni_resolver_chooses_key_by_signature_scheme() {
+    let resolver = Arc::new(rustls::server::ResolvesServerCertUsingSni::new());
+    resolver
+        .add("localhost", certified_key(KeyType::Rsa2048))
+        .unwrap();
+    resolver
+        .add("localhost", certified_key(KeyType::EcdsaP256))
+        .unwrap();
+
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.cert_resolver = resolver.clone();
+    let server_config = Arc::new(server_config);
+
+    for (schemes, kt) in [
+        (
+            vec![SignatureScheme::ECDSA_NISTP256_SHA256],
+            KeyType::EcdsaP256,
+        ),
+        (vec![SignatureScheme::RSA_PSS_SHA256], KeyType::Rsa2048),
+        // earlier keys are preferred
+        (
+            vec![
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+                SignatureScheme::RSA_PSS_SHA256,
+            ],
+            KeyType::Rsa2048,
+        ),
+    ] {
+        assert_eq!(
+            server_certificate_for(
+                &server_config,
+                client_config_accepting_anything(schemes),
+                "localhost"
+            ),
+            Ok(kt.get_chain()[0].clone())
+        );
+    }
+
+    // adding another key of the same algorithm replaces the first
+    resolver
+        .add("localhost", certified_key(KeyType::Rsa4096))
+        .unwrap();
+    assert_eq!(
+        server_certificate_for(
+            &server_config,
+            client_config_accepting_anything(vec![SignatureScheme::RSA_PSS_SHA256]),
+            "localhost"
+        ),
+        Ok(KeyType::Rsa4096.get_chain()[0].clone())
+    );
+
+    assert!(resolver.remove("LOCALHOST"));
+    assert!(server_certificate_for(
+        &server_config,
+        make_client_config(KeyType::Rsa2048),
+        "localhost"
+    )
+    .is_err());
+}
+
+/// Files for a `ResolvesServerCertFromFiles`, removed on drop.
+struct CertFiles {
+    cert: std::path::PathBuf,
+    key: std::path::PathBuf,
+}
+
+impl CertFiles {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir();
+        // tests for each provider run in the same process
+        let prefix = format!(
+            "rustls-{}-{}-{name}",
+            std::process::id(),
+            module_path!().replace("::", "-")
+        );
+        Self {
+            cert: dir.join(format!("{prefix}.fullchain")),
+            key: dir.join(format!("{prefix}.key")),
+        }
+    }
+
+    fn write(&self, cert_from: &str, key_from: &str) {
+        let test_ca = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../test-ca");
+        std::fs::copy(test_ca.join(cert_from), &self.cert).unwrap();
+        std::fs::copy(test_ca.join(key_from), &self.key).unwrap();
+    }
+}
+
+impl Drop for CertFiles {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.cert).ok();
+        std::fs::remove_file(&self.key).ok();
+    }
+}
+
+#[derive(Debug, Default)]
+struct RecordingReporter(Mutex<Vec<rustls::server::ReloadOutcome>>);
+
+impl rustls::server::ReportsCertReloads for RecordingReporter {
+    fn report(&self, outcome: &rustls::server::ReloadOutcome) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(outcome.clone());
+    }
+}
+
+#[test]
+fn file_resolver_reloads_changed_files() {
+    use rustls::server::{ReloadOutcome, ResolvesServerCertFromFiles};
+
+    let files = CertFiles::new("file_resolver_reloads_changed_files");
+    files.write("rsa-2048/end.fullchain", "rsa-2048/end.key");
+    let reporter = Arc::new(RecordingReporter::default());
+    let resolver = Arc::new(
+        ResolvesServerCertFromFiles::new(&files.cert, &files.key, &provider::default_provider())
+            .unwrap()
+            .with_reporter(reporter.clone()),
+    );
+
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.cert_resolver = resolver.clone();
+    let server_config = Arc::new(server_config);
+
+    assert_eq!(
+        server_certificate_for(
+            &server_config,
+            make_client_config(KeyType::Rsa2048),
+            "localhost"
+        ),
+        Ok(KeyType::Rsa2048.get_chain()[0].clone())
+    );
+    assert_eq!(resolver.reload(), ReloadOutcome::Unchanged);
+
+    files.write("ecdsa-p256/end.fullchain", "ecdsa-p256/end.key");
+    assert_eq!(resolver.reload(), ReloadOutcome::Reloaded);
+    assert_eq!(resolver.reload(), ReloadOutcome::Unchanged);
+    assert_eq!(
+        server_certificate_for(
+            &server_config,
+            make_client_config(KeyType::EcdsaP256),
+            "localhost"
+        ),
+        Ok(KeyType::EcdsaP256.get_chain()[0].clone())
+    );
+
+    assert_eq!(
+        *reporter.0.lock().unwrap(),
+        vec![
+            ReloadOutcome::Unchanged,
+            ReloadOutcome::Reloaded,
+            ReloadOutcome::Unchanged
+        ]
+    );
+}
+
+#[test]
+fn file_resolver_keeps_previous_key_on_errors() {
+    use rustls::server::{ReloadOutcome, ResolvesServerCertFromFiles};
+
+    let files = CertFiles::new("file_resolver_keeps_previous_key_on_errors");
+    files.write("rsa-2048/end.fullchain", "rsa-2048/end.key");
+    let resolver = Arc::new(
+        ResolvesServerCertFromFiles::new(&files.cert, &files.key, &provider::default_provider())
+            .unwrap(),
+    );
+
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.cert_resolver = resolver.clone();
+    let server_config = Arc::new(server_config);
+    let still_serves_previous_key = || {
+        assert_eq!(
+            server_certificate_for(
+                &server_config,
+                make_client_config(KeyType::Rsa2048),
+                "localhost"
+            ),
+            Ok(KeyType::Rsa2048.get_chain()[0].clone())
+        );
+    };
+
+    // certificate does not match key
+    files.write("ecdsa-p256/end.fullchain", "rsa-2048/end.key");
+    assert_eq!(
+        resolver.reload(),
+        ReloadOutcome::Failed(Error::InconsistentKeys(InconsistentKeys::KeyMismatch))
+    );
+    // reported once per change
+    assert_eq!(resolver.reload(), ReloadOutcome::Unchanged);
+    still_serves_previous_key();
+
+    // no certificates
+    files.write("rsa-2048/end.key", "rsa-2048/end.key");
+    assert_eq!(
+        resolver.reload(),
+        ReloadOutcome::Failed(Error::NoCertificatesPresented)
+    );
+    still_serves_previous_key();
+
+    // missing files
+    std::fs::remove_file(&files.key).unwrap();
+    assert!(matches!(
+        resolver.reload(),
+        ReloadOutcome::Failed(Error::General(_))
+    ));
+    still_serves_previous_key();
+
+    files.write("rsa-2048/end.fullchain", "rsa-2048/end.key");
+    assert_eq!(resolver.reload(), ReloadOutcome::Reloaded);
+    still_serves_previous_key();
+}
+
+#[test]
+fn file_resolver_requires_valid_files() {
+    use rustls::server::ResolvesServerCertFromFiles;
+
+    let files = CertFiles::new("file_resolver_requires_valid_files");
+    assert!(matches!(
+        ResolvesServerCertFromFiles::new(&files.cert, &files.key, &provider::default_provider()),
+        Err(Error::General(_))
+    ));
+
+    files.write("ecdsa-p256/end.fullchain", "rsa-2048/end.key");
+    assert_eq!(
+        ResolvesServerCertFromFiles::new(&files.cert, &files.key, &provider::default_provider())
+            .unwrap_err(),
+        Error::InconsistentKeys(InconsistentKeys::KeyMismatch)
+    );
+}
+
+#[test]
+fn test_keys_match() {
+    // Consistent: Both of these should have the same SPKI values
+    let expect_consistent =
+        sign::CertifiedKey::new(KeyType::Rsa2048.get_chain(), Arc::new(SigningKeySomeSpki));
+    assert!(matches!(expect_consistent.keys_match(), Ok(())));
+
+    // Inconsistent: These should not have the same SPKI values
+    let expect_inconsistent =
+        sign::CertifiedKey::new(KeyType::EcdsaP256.get_chain(), Arc::new(SigningKeySomeSpki));
+    assert!(matches!(
+        expect_inconsistent.keys_match(),
+        Err(Error::InconsistentKeys(InconsistentKeys::KeyMismatch))
+    ));
+
+    // Unknown: This signing key returns None for its SPKI, so we can't tell if the certified key is consistent
+    let expect_unknown =
+        sign::CertifiedKey::new(KeyType::Rsa2048.get_chain(), Arc::new(SigningKeyNoneSpki));
+    assert!(matches!(
+        expect_unknown.keys_match(),
+        Err(Error::InconsistentKeys(InconsistentKeys::Unknown))
+    ));
+}
+
+/// Represents a SigningKey that returns None for its SPKI via the default impl.
+#[derive(Debug)]
+struct SigningKeyNoneSpki;
+
+impl sign::SigningKey for SigningKeyNoneSpki {
+    fn choose_scheme(&self, _offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
+        unimplemented!("Not meant to be called during tests")
+    }
+
+    fn algorithm(&self) -> rustls::SignatureAlgorithm {
+        unimplemented!("Not meant to be called during tests")
+    }
+}
+
+/// Represents a SigningKey that returns Some for its SPKI.
+#[derive(Debug)]
+struct SigningKeySomeSpki;
+
+impl sign::SigningKey for SigningKeySomeSpki {
+    fn public_key(&self) -> Option<pki_types::SubjectPublicKeyInfoDer> {
+        let chain = KeyType::Rsa2048.get_chain();
+        let cert = ParsedCertificate::try_from(chain.first().unwrap()).unwrap();
+        Some(
+            cert.subject_public_key_info()
+                .into_owned(),
+        )
+    }
+
+    fn choose_scheme(&self, _offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
+        unimplemented!("Not meant to be called during tests")
+    }
+
+    fn algorithm(&self) -> rustls::SignatureAlgorithm {
+        unimplemented!("Not meant to be called during tests")
+    }
+}
+
+fn do_exporter_test(client_config: ClientConfig, server_config: ServerConfig) {
+    let mut client_secret = [0u8; 64];
+    let mut server_secret = [0u8; 64];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    assert_eq!(
+        Err(Error::HandshakeNotComplete),
+        client.export_keying_material(&mut client_secret, b"label", Some(b"context"))
+    );
+    assert_eq!(
+        Err(Error::HandshakeNotComplete),
+        server.export_keying_material(&mut server_secret, b"label", Some(b"context"))
+    );
+    do_handshake(&mut client, &mut server);
+
+    assert!(client
+        .export_keying_material(&mut client_secret, b"label", Some(b"context"))
+        .is_ok());
+    assert!(server
+        .export_keying_material(&mut server_secret, b"label", Some(b"context"))
+        .is_ok());
+    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
 
     let mut empty = vec![];
     assert_eq!(
@@ -4545,16 +5517,188 @@ fn tls13_stateless_resumption() {
 }
 
 #[test]
-fn early_data_not_available() {
-    let (mut client, _) = make_pair(KeyType::Rsa2048);
-    assert!(client.early_data().is_none());
-}
+fn stateless_resumption_across_servers_sharing_ticket_keys() {
+    let keys = |current: u8, previous: Option<u8>| TicketKeys {
+        current: TicketKey::new([current; 16], [current; 32]),
+        next: None,
+        previous: previous.map(|n| TicketKey::new([n; 16], [n; 32])),
+    };
 
-fn early_data_configs() -> (Arc<ClientConfig>, Arc<ServerConfig>) {
-    let kt = KeyType::Rsa2048;
-    let mut client_config = make_client_config(kt);
-    client_config.enable_early_data = true;
-    client_config.resumption = Resumption::store(Arc::new(ClientStorage::new()));
+    for version in rustls::ALL_VERSIONS {
+        let kt = KeyType::Rsa2048;
+        let client_config = Arc::new(make_client_config_with_versions(kt, &[version]));
+        let make_server = || {
+            let ticketer = provider::Ticketer::with_shared_keys(3600, keys(1, None)).unwrap();
+            let mut server_config = make_server_config(kt);
+            server_config.ticketer = ticketer.clone();
+            (ticketer, Arc::new(server_config))
+        };
+        let (_, server_a) = make_server();
+        let (ticketer_b, server_b) = make_server();
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_a);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+        assert_eq!(server.resumption_ticket_key_id(), None);
+
+        // resumed at a different server
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_b);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+        assert_eq!(server.handshake_kind(), Some(HandshakeKind::Resumed));
+        assert_eq!(server.resumption_ticket_key_id(), Some([1; 16]));
+
+        // and again after rotating the keys
+        ticketer_b
+            .set_keys(keys(2, Some(1)))
+            .unwrap();
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_b);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+        assert_eq!(server.resumption_ticket_key_id(), Some([1; 16]));
+
+        // but not with a ticket from a server still using a key
+        // the other has since discarded
+        ticketer_b
+            .set_keys(keys(3, Some(2)))
+            .unwrap();
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_a);
+        do_handshake(&mut client, &mut server);
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_b);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+        assert_eq!(server.resumption_ticket_key_id(), None);
+    }
+}
+
+#[test]
+fn shared_key_ticketers_decrypt_each_others_tickets() {
+    let a = provider::Ticketer::with_shared_keys(3600, shared_ticket_keys(1, None, None)).unwrap();
+    let b = provider::Ticketer::with_shared_keys(3600, shared_ticket_keys(1, None, None)).unwrap();
+    assert!(a.enabled());
+    assert_eq!(a.lifetime(), 3600);
+
+    let cipher = a.encrypt(b"hello world").unwrap();
+    assert_eq!(&cipher[..16], &[1; 16]);
+    assert_eq!(
+        b.decrypt_with_key_id(&cipher),
+        Some((b"hello world".to_vec(), Some([1; 16])))
+    );
+
+    let other =
+        provider::Ticketer::with_shared_keys(3600, shared_ticket_keys(2, None, None)).unwrap();
+    assert_eq!(other.decrypt(&cipher), None);
+}
+
+#[test]
+fn shared_key_ticketer_decrypts_known_ticket() {
+    // This is the documented `SharedKeyTicketer` construction, so every
+    // provider must be able to decrypt it.
+    let keys = TicketKeys {
+        current: TicketKey::new([0x11; 16], [0x22; 32]),
+        next: None,
+        previous: None,
+    };
+    let ticketer = provider::Ticketer::with_shared_keys(3600, keys).unwrap();
+    assert_eq!(
+        ticketer
+            .decrypt(KNOWN_SHARED_KEY_TICKET)
+            .unwrap(),
+        b"shared ticket"
+    );
+}
+
+/// `id = [0x11; 16]`, `secret = [0x22; 32]`, `nonce = 00..0b`, `plaintext = "shared ticket"`
+const KNOWN_SHARED_KEY_TICKET: &[u8] = &[
+    0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11,
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x2d, 0xa5, 0x69, 0xfa,
+    0xfd, 0x4f, 0x66, 0x89, 0x7a, 0x05, 0x44, 0x77, 0x5a, 0x4f, 0x39, 0x1c, 0x5e, 0xc4, 0xb0, 0x79,
+    0x11, 0xe8, 0xb4, 0x7f, 0xb6, 0xff, 0xaa, 0xc9, 0x27,
+];
+
+#[test]
+fn shared_key_ticketer_rotation() {
+    let a =
+        provider::Ticketer::with_shared_keys(3600, shared_ticket_keys(1, Some(2), None)).unwrap();
+    let b =
+        provider::Ticketer::with_shared_keys(3600, shared_ticket_keys(1, Some(2), None)).unwrap();
+    let cipher1 = a.encrypt(b"ticket 1").unwrap();
+
+    // `a` rotates before `b`: each accepts the other's tickets
+    a.set_keys(shared_ticket_keys(2, None, Some(1)))
+        .unwrap();
+    let cipher2 = a.encrypt(b"ticket 2").unwrap();
+    assert_eq!(
+        a.decrypt_with_key_id(&cipher1),
+        Some((b"ticket 1".to_vec(), Some([1; 16])))
+    );
+    assert_eq!(
+        b.decrypt_with_key_id(&cipher2),
+        Some((b"ticket 2".to_vec(), Some([2; 16])))
+    );
+
+    a.set_keys(shared_ticket_keys(3, None, Some(2)))
+        .unwrap();
+    let cipher3 = a.encrypt(b"ticket 3").unwrap();
+    assert_eq!(a.decrypt(&cipher1), None);
+    assert_eq!(a.decrypt(&cipher2).unwrap(), b"ticket 2");
+    assert_eq!(a.decrypt(&cipher3).unwrap(), b"ticket 3");
+}
+
+#[test]
+fn shared_key_ticketer_rejects_duplicate_key_ids() {
+    for keys in [
+        shared_ticket_keys(1, Some(1), None),
+        shared_ticket_keys(1, None, Some(1)),
+        shared_ticket_keys(1, Some(2), Some(2)),
+    ] {
+        assert_eq!(
+            provider::Ticketer::with_shared_keys(3600, keys).err(),
+            Some(Error::General("duplicate ticket key id".into()))
+        );
+    }
+
+    let t = provider::Ticketer::with_shared_keys(3600, shared_ticket_keys(1, None, None)).unwrap();
+    let cipher = t.encrypt(b"hello world").unwrap();
+    assert!(t
+        .set_keys(shared_ticket_keys(2, Some(1), Some(2)))
+        .is_err());
+    assert_eq!(t.decrypt(&cipher).unwrap(), b"hello world");
+}
+
+#[test]
+fn shared_key_ticketer_rejects_modified_tickets() {
+    let t = provider::Ticketer::with_shared_keys(3600, shared_ticket_keys(1, None, None)).unwrap();
+    let cipher = t.encrypt(b"hello world").unwrap();
+
+    assert_eq!(t.decrypt(&cipher[..15]), None);
+    for i in [0, 16, cipher.len() - 1] {
+        let mut modified = cipher.clone();
+        modified[i] ^= 1;
+        assert_eq!(t.decrypt(&modified), None);
+    }
+}
+
+fn shared_ticket_keys(current: u8, next: Option<u8>, previous: Option<u8>) -> TicketKeys {
+    let key = |n: u8| TicketKey::new([n; 16], [n; 32]);
+    TicketKeys {
+        current: key(current),
+        next: next.map(key),
+        previous: previous.map(key),
+    }
+}
+
+#[test]
+fn early_data_not_available() {
+    let (mut client, _) = make_pair(KeyType::Rsa2048);
+    assert!(client.early_data().is_none());
+}
+
+fn early_data_configs() -> (Arc<ClientConfig>, Arc<ServerConfig>) {
+    let kt = KeyType::Rsa2048;
+    let mut client_config = make_client_config(kt);
+    client_config.enable_early_data = true;
+    client_config.resumption = Resumption::store(Arc::new(ClientStorage::new()));
 
     let mut server_config = make_server_config(kt);
     server_config.max_early_data_size = 1234;
@@ -4604,6 +5748,90 @@ fn early_data_is_available_on_resumption() {
     assert_eq!(&received_early_data[..], b"hello");
 }
 
+#[test]
+fn early_exporter_is_available_with_early_data() {
+    let (client_config, server_config) = early_data_configs();
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert!(client
+        .export_early_keying_material([0u8; 32], b"label", None)
+        .is_err());
+    do_handshake(&mut client, &mut server);
+    assert!(client
+        .export_early_keying_material([0u8; 32], b"label", None)
+        .is_err());
+    assert!(server
+        .export_early_keying_material([0u8; 32], b"label", None)
+        .is_err());
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    client
+        .early_data()
+        .unwrap()
+        .write_all(b"hello")
+        .unwrap();
+    let client_early = client
+        .export_early_keying_material([0u8; 32], b"label", Some(b"context"))
+        .unwrap();
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    let server_early = server
+        .export_early_keying_material([0u8; 32], b"label", Some(b"context"))
+        .unwrap();
+    assert_eq!(client_early, server_early);
+
+    do_handshake(&mut client, &mut server);
+    assert!(client.is_early_data_accepted());
+
+    // still available after the handshake, and distinct from the main exporter
+    assert_eq!(
+        client
+            .export_early_keying_material([0u8; 32], b"label", Some(b"context"))
+            .unwrap(),
+        client_early
+    );
+    assert_ne!(
+        client
+            .export_keying_material([0u8; 32], b"label", Some(b"context"))
+            .unwrap(),
+        client_early
+    );
+    assert!(client
+        .export_early_keying_material([0u8; 0], b"label", None)
+        .is_err());
+}
+
+#[test]
+fn early_exporter_is_discarded_when_early_data_rejected() {
+    let (client_config, server_config) = early_data_configs();
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let mut server_config = (*server_config).clone();
+    server_config.max_early_data_size = 0;
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(server_config));
+    client
+        .early_data()
+        .unwrap()
+        .write_all(b"hello")
+        .unwrap();
+    assert!(client
+        .export_early_keying_material([0u8; 32], b"label", None)
+        .is_ok());
+
+    do_handshake(&mut client, &mut server);
+    assert!(!client.is_early_data_accepted());
+    assert!(client
+        .export_early_keying_material([0u8; 32], b"label", None)
+        .is_err());
+    assert!(server
+        .export_early_keying_material([0u8; 32], b"label", None)
+        .is_err());
+}
+
 #[test]
 fn early_data_not_available_on_server_before_client_hello() {
     let mut server = ServerConnection::new(Arc::new(make_server_config(KeyType::Rsa2048))).unwrap();
@@ -4645,6 +5873,106 @@ fn early_data_can_be_rejected_by_server() {
     assert!(!client.is_early_data_accepted());
 }
 
+#[test]
+fn early_data_with_stateless_resumption_requires_anti_replay() {
+    let (client_config, _) = early_data_configs();
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.max_early_data_size = 1234;
+    server_config.ticketer = provider::Ticketer::new().unwrap();
+
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(server_config.clone()));
+    do_handshake(&mut client, &mut server);
+    let (mut client, _) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(server_config.clone()));
+    assert!(client.early_data().is_none());
+
+    server_config.anti_replay = Some(StrikeRegister::new(Duration::from_secs(10), 16));
+    let server_config = Arc::new(server_config);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(
+        client
+            .early_data()
+            .expect("early data not offered")
+            .write(b"hello")
+            .unwrap(),
+        5
+    );
+    let mut first_flight = Vec::new();
+    client
+        .write_tls(&mut first_flight)
+        .unwrap();
+
+    server
+        .read_tls(&mut &first_flight[..])
+        .unwrap();
+    server.process_new_packets().unwrap();
+    let mut received_early_data = [0u8; 5];
+    assert_eq!(
+        server
+            .early_data()
+            .expect("early_data didn't happen")
+            .read(&mut received_early_data)
+            .unwrap(),
+        5
+    );
+    assert_eq!(&received_early_data[..], b"hello");
+    do_handshake(&mut client, &mut server);
+    assert!(client.is_early_data_accepted());
+
+    // The same ClientHello sent again is a replay: the handshake proceeds,
+    // but its early data is rejected.
+    let mut replayed = ServerConnection::new(server_config.clone()).unwrap();
+    replayed
+        .read_tls(&mut &first_flight[..])
+        .unwrap();
+    replayed.process_new_packets().unwrap();
+    assert!(replayed.early_data().is_none());
+}
+
+#[test]
+fn early_data_accepted_within_anti_replay_window_beyond_default_skew() {
+    #[derive(Debug)]
+    struct Later(Duration);
+
+    impl rustls::time_provider::TimeProvider for Later {
+        fn current_time(&self) -> Option<UnixTime> {
+            Some(UnixTime::since_unix_epoch(
+                Duration::from_secs(UnixTime::now().as_secs()) + self.0,
+            ))
+        }
+    }
+
+    let (client_config, _) = early_data_configs();
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.max_early_data_size = 1234;
+    server_config.ticketer = provider::Ticketer::new().unwrap();
+    server_config.anti_replay = Some(StrikeRegister::new(Duration::from_secs(120), 16));
+
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(server_config.clone()));
+    do_handshake(&mut client, &mut server);
+
+    // The client's view of the ticket age now differs from the server's by 90s:
+    // beyond the default tolerance, but inside the anti-replay window.
+    server_config.time_provider = Arc::new(Later(Duration::from_secs(90)));
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(server_config));
+    assert_eq!(
+        client
+            .early_data()
+            .expect("early data not offered")
+            .write(b"hello")
+            .unwrap(),
+        5
+    );
+    do_handshake(&mut client, &mut server);
+    assert!(client.is_early_data_accepted());
+}
+
 #[test]
 fn early_data_is_limited_on_client() {
     let (client_config, server_config) = early_data_configs();
@@ -4946,6 +6274,14 @@ mod test_quic {
                 server_early.packet.as_ref()
             ));
         }
+        assert_eq!(
+            client
+                .export_early_keying_material([0u8; 32], b"label", None)
+                .unwrap(),
+            server
+                .export_early_keying_material([0u8; 32], b"label", None)
+                .unwrap()
+        );
         step(&mut server, &mut client)
             .unwrap()
             .unwrap();
@@ -4980,6 +6316,9 @@ mod test_quic {
             assert_eq!(client.quic_transport_parameters(), Some(server_params));
             assert!(client.zero_rtt_keys().is_some());
             assert!(server.zero_rtt_keys().is_none());
+            assert!(server
+                .export_early_keying_material([0u8; 32], b"label", None)
+                .is_err());
             step(&mut server, &mut client)
                 .unwrap()
                 .unwrap();
@@ -5190,6 +6529,7 @@ mod test_quic {
                 client_version: ProtocolVersion::TLSv1_3,
                 random,
                 session_id: SessionId::random(provider.secure_random).unwrap(),
+                legacy_cookie: None,
                 cipher_suites: vec![CipherSuite::TLS13_AES_128_GCM_SHA256],
                 compression_methods: vec![Compression::Null],
                 extensions: vec![
@@ -5255,6 +6595,7 @@ mod test_quic {
                 client_version: ProtocolVersion::TLSv1_2,
                 random,
                 session_id: SessionId::random(provider.secure_random).unwrap(),
+                legacy_cookie: None,
                 cipher_suites: vec![CipherSuite::TLS13_AES_128_GCM_SHA256],
                 compression_methods: vec![Compression::Null],
                 extensions: vec![
@@ -5959,59 +7300,336 @@ fn test_tls13_client_resumption_does_not_reuse_tickets() {
     assert!(matches!(ops[0], ClientStorageOp::TakeTls13Ticket(_, false)));
 }
 
-#[test]
-fn test_client_mtu_reduction() {
-    struct CollectWrites {
-        writevs: Vec<Vec<usize>>,
-    }
+/// A temporary file for a `ClientSessionFileStore`.
+struct SessionFile(std::path::PathBuf);
 
-    impl io::Write for CollectWrites {
-        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
-            panic!()
-        }
-        fn flush(&mut self) -> io::Result<()> {
-            panic!()
-        }
-        fn write_vectored(&mut self, b: &[io::IoSlice<'_>]) -> io::Result<usize> {
-            let writes = b
-                .iter()
-                .map(|slice| slice.len())
-                .collect::<Vec<usize>>();
-            let len = writes.iter().sum();
-            self.writevs.push(writes);
-            Ok(len)
-        }
+impl SessionFile {
+    fn new(name: &str) -> Self {
+        // tests for each provider run in the same process
+        Self(std::env::temp_dir().join(format!(
+            "rustls-{}-{}-{name}.sessions",
+            std::process::id(),
+            module_path!().replace("::", "-")
+        )))
     }
 
-    fn collect_write_lengths(client: &mut ClientConnection) -> Vec<usize> {
-        let mut collector = CollectWrites { writevs: vec![] };
-
-        client
-            .write_tls(&mut collector)
-            .unwrap();
-        assert_eq!(collector.writevs.len(), 1);
-        collector.writevs[0].clone()
+    fn store(&self) -> Arc<rustls::client::ClientSessionFileStore> {
+        Arc::new(rustls::client::ClientSessionFileStore::new(
+            &self.0,
+            &provider::default_provider(),
+            32,
+        ))
     }
+}
 
-    for kt in ALL_KEY_TYPES {
-        let mut client_config = make_client_config(*kt);
-        client_config.max_fragment_size = Some(64);
-        let mut client =
-            ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-        let writes = collect_write_lengths(&mut client);
-        println!("writes at mtu=64: {:?}", writes);
-        assert!(writes.iter().all(|x| *x <= 64));
-        assert!(writes.len() > 1);
+impl Drop for SessionFile {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.0).ok();
     }
 }
 
+fn client_config_with_store(
+    version: &'static rustls::SupportedProtocolVersion,
+    store: Arc<dyn rustls::client::ClientSessionStore>,
+) -> Arc<ClientConfig> {
+    let mut client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+    client_config.resumption = Resumption::store(store);
+    client_config.enable_early_data = true;
+    Arc::new(client_config)
+}
+
 #[test]
-fn test_server_mtu_reduction() {
-    let mut server_config = make_server_config(KeyType::Rsa2048);
-    server_config.max_fragment_size = Some(64);
-    server_config.send_half_rtt_data = true;
-    let (mut client, mut server) =
-        make_pair_for_configs(make_client_config(KeyType::Rsa2048), server_config);
+fn client_session_file_store_resumes_in_later_store() {
+    let mut versions = vec![&rustls::version::TLS13];
+    #[cfg(feature = "tls12")]
+    versions.push(&rustls::version::TLS12);
+
+    for version in versions {
+        let file = SessionFile::new(&format!("resume-{:?}", version.version));
+        let server_config = Arc::new(make_server_config(KeyType::Rsa2048));
+
+        let client_config = client_config_with_store(version, file.store());
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+
+        // as if in a new process
+        let client_config = client_config_with_store(version, file.store());
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+    }
+}
+
+#[test]
+fn client_session_file_store_allows_early_data_in_later_store() {
+    let file = SessionFile::new("early-data");
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.max_early_data_size = 1234;
+    let server_config = Arc::new(server_config);
+
+    let client_config = client_config_with_store(&rustls::version::TLS13, file.store());
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let client_config = client_config_with_store(&rustls::version::TLS13, file.store());
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .write(b"hello")
+            .unwrap(),
+        5
+    );
+    do_handshake(&mut client, &mut server);
+    assert!(client.is_early_data_accepted());
+
+    let mut received_early_data = [0u8; 5];
+    server
+        .early_data()
+        .unwrap()
+        .read_exact(&mut received_early_data)
+        .unwrap();
+    assert_eq!(&received_early_data, b"hello");
+}
+
+#[test]
+fn client_session_file_store_does_not_reuse_tickets() {
+    let file = SessionFile::new("reuse");
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.send_tls13_tickets = 1;
+    let server_config = Arc::new(server_config);
+
+    let client_config = client_config_with_store(&rustls::version::TLS13, file.store());
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    // take the only ticket, without receiving a new one
+    let store = file.store();
+    assert!(store
+        .take_tls13_ticket(&ServerName::try_from("localhost").unwrap())
+        .is_some());
+
+    let client_config = client_config_with_store(&rustls::version::TLS13, file.store());
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+}
+
+#[test]
+fn client_session_file_store_does_not_reuse_tickets_taken_by_live_stores() {
+    let file = SessionFile::new("reuse-live");
+    let name = ServerName::try_from("localhost").unwrap();
+    file.store()
+        .insert_tls13_ticket(name.clone(), tls13_session_value());
+
+    // as if in two processes running at once
+    let first = file.store();
+    let second = file.store();
+    assert!(first.take_tls13_ticket(&name).is_some());
+    assert!(second
+        .take_tls13_ticket(&name)
+        .is_none());
+
+    // later changes by `second` do not restore the ticket
+    second.set_kx_hint(name.clone(), rustls::NamedGroup::secp384r1);
+    let store = file.store();
+    assert_eq!(store.kx_hint(&name), Some(rustls::NamedGroup::secp384r1));
+    assert!(store.take_tls13_ticket(&name).is_none());
+}
+
+#[test]
+fn client_session_file_store_does_not_use_tickets_without_lock() {
+    let file = SessionFile::new("locked");
+    let name = ServerName::try_from("localhost").unwrap();
+    let store = file.store();
+    store.insert_tls13_ticket(name.clone(), tls13_session_value());
+
+    let mut lock_path = file.0.clone().into_os_string();
+    lock_path.push(".lock");
+    std::fs::write(&lock_path, b"").unwrap();
+    assert!(store.take_tls13_ticket(&name).is_none());
+    std::fs::remove_file(&lock_path).unwrap();
+
+    assert!(file
+        .store()
+        .take_tls13_ticket(&name)
+        .is_some());
+}
+
+#[test]
+fn client_session_file_store_discards_expired_and_corrupt_sessions() {
+    let file = SessionFile::new("expired");
+    let name = ServerName::try_from("localhost").unwrap();
+    let mut value = tls13_session_value();
+    assert!(!value.has_expired_at(UnixTime::now()));
+    value.rewind_epoch(8 * 24 * 60 * 60);
+    assert!(value.has_expired_at(UnixTime::now()));
+
+    let store = file.store();
+    store.set_kx_hint(name.clone(), rustls::NamedGroup::secp384r1);
+    store.insert_tls13_ticket(name.clone(), value);
+    let store = file.store();
+    assert!(store.take_tls13_ticket(&name).is_none());
+    assert_eq!(store.kx_hint(&name), Some(rustls::NamedGroup::secp384r1));
+
+    std::fs::write(&file.0, b"\x01garbage").unwrap();
+    let store = file.store();
+    assert_eq!(store.kx_hint(&name), None);
+
+    // and the store still works
+    let server_config = Arc::new(make_server_config(KeyType::Rsa2048));
+    let client_config = client_config_with_store(&rustls::version::TLS13, store);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    let client_config = client_config_with_store(&rustls::version::TLS13, file.store());
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+}
+
+/// Do a TLS1.3 handshake with ALPN, returning the newest ticket.
+fn tls13_session_value() -> rustls::client::Tls13ClientSessionValue {
+    let store = Arc::new(rustls::client::ClientSessionMemoryCache::new(32));
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]);
+    client_config.resumption = Resumption::store(store.clone());
+    client_config.alpn_protocols = vec![b"h2".to_vec()];
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.alpn_protocols = vec![b"h2".to_vec()];
+    server_config.max_early_data_size = 1234;
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    store
+        .take_tls13_ticket(&ServerName::try_from("localhost").unwrap())
+        .unwrap()
+}
+
+#[test]
+fn tls13_client_session_value_round_trips() {
+    let value = tls13_session_value();
+    assert_eq!(value.alpn_protocol(), Some(&b"h2"[..]));
+
+    let bytes = value.to_bytes();
+    let decoded =
+        rustls::client::Tls13ClientSessionValue::from_bytes(&bytes, &provider::default_provider())
+            .unwrap();
+    assert_eq!(decoded.to_bytes(), bytes);
+    assert_eq!(decoded.alpn_protocol(), Some(&b"h2"[..]));
+    assert_eq!(decoded.max_early_data_size(), 1234);
+    assert_eq!(decoded.suite(), value.suite());
+
+    // truncated
+    assert!(rustls::client::Tls13ClientSessionValue::from_bytes(
+        &bytes[..bytes.len() - 1],
+        &provider::default_provider()
+    )
+    .is_err());
+
+    // unknown format version
+    let mut future = bytes.clone();
+    future[0] = 2;
+    assert!(rustls::client::Tls13ClientSessionValue::from_bytes(
+        &future,
+        &provider::default_provider()
+    )
+    .is_err());
+
+    // suite not supported by the provider
+    let provider = CryptoProvider {
+        cipher_suites: vec![cipher_suite::TLS13_CHACHA20_POLY1305_SHA256],
+        ..provider::default_provider()
+    };
+    assert_ne!(
+        value.suite().common.suite,
+        CipherSuite::TLS13_CHACHA20_POLY1305_SHA256
+    );
+    assert!(rustls::client::Tls13ClientSessionValue::from_bytes(&bytes, &provider).is_err());
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn tls12_client_session_value_round_trips() {
+    let store = Arc::new(rustls::client::ClientSessionMemoryCache::new(32));
+    let client_config = client_config_with_store(&rustls::version::TLS12, store.clone());
+    let (mut client, mut server) = make_pair_for_arc_configs(
+        &client_config,
+        &Arc::new(make_server_config(KeyType::Rsa2048)),
+    );
+    do_handshake(&mut client, &mut server);
+
+    let value = store
+        .tls12_session(&ServerName::try_from("localhost").unwrap())
+        .unwrap();
+    let bytes = value.to_bytes();
+    let decoded =
+        rustls::client::Tls12ClientSessionValue::from_bytes(&bytes, &provider::default_provider())
+            .unwrap();
+    assert_eq!(decoded.to_bytes(), bytes);
+
+    // not a TLS1.3 session
+    assert!(rustls::client::Tls13ClientSessionValue::from_bytes(
+        &bytes,
+        &provider::default_provider()
+    )
+    .is_err());
+}
+
+#[test]
+fn test_client_mtu_reduction() {
+    struct CollectWrites {
+        writevs: Vec<Vec<usize>>,
+    }
+
+    impl io::Write for CollectWrites {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            panic!()
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            panic!()
+        }
+        fn write_vectored(&mut self, b: &[io::IoSlice<'_>]) -> io::Result<usize> {
+            let writes = b
+                .iter()
+                .map(|slice| slice.len())
+                .collect::<Vec<usize>>();
+            let len = writes.iter().sum();
+            self.writevs.push(writes);
+            Ok(len)
+        }
+    }
+
+    fn collect_write_lengths(client: &mut ClientConnection) -> Vec<usize> {
+        let mut collector = CollectWrites { writevs: vec![] };
+
+        client
+            .write_tls(&mut collector)
+            .unwrap();
+        assert_eq!(collector.writevs.len(), 1);
+        collector.writevs[0].clone()
+    }
+
+    for kt in ALL_KEY_TYPES {
+        let mut client_config = make_client_config(*kt);
+        client_config.max_fragment_size = Some(64);
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+        let writes = collect_write_lengths(&mut client);
+        println!("writes at mtu=64: {:?}", writes);
+        assert!(writes.iter().all(|x| *x <= 64));
+        assert!(writes.len() > 1);
+    }
+}
+
+#[test]
+fn test_server_mtu_reduction() {
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.max_fragment_size = Some(64);
+    server_config.send_half_rtt_data = true;
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa2048), server_config);
 
     let big_data = [0u8; 2048];
     server
@@ -6089,23 +7707,708 @@ fn handshakes_complete_and_data_flows_with_gratuitious_max_fragment_sizes() {
                 let mut server_config = make_server_config(*kt);
                 server_config.max_fragment_size = Some(frag_size);
 
-                let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
-                do_handshake(&mut client, &mut server);
+                let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+                do_handshake(&mut client, &mut server);
+
+                // check server -> client data flow
+                let pattern = (0x00..=0xffu8).collect::<Vec<u8>>();
+                assert_eq!(pattern.len(), server.writer().write(&pattern).unwrap());
+                transfer(&mut server, &mut client);
+                client.process_new_packets().unwrap();
+                check_read(&mut client.reader(), &pattern);
+
+                // and client -> server
+                assert_eq!(pattern.len(), client.writer().write(&pattern).unwrap());
+                transfer(&mut client, &mut server);
+                server.process_new_packets().unwrap();
+                check_read(&mut server.reader(), &pattern);
+            }
+        }
+    }
+}
+
+fn check_client_record_size_limit(limit: u16) -> Option<Error> {
+    let mut client_config = make_client_config(KeyType::Ed25519);
+    client_config.record_size_limit = Some(limit);
+    ClientConnection::new(Arc::new(client_config), server_name("localhost")).err()
+}
+
+#[test]
+fn bad_client_record_size_limits() {
+    assert_eq!(
+        check_client_record_size_limit(0),
+        Some(Error::BadRecordSizeLimit)
+    );
+    assert_eq!(
+        check_client_record_size_limit(63),
+        Some(Error::BadRecordSizeLimit)
+    );
+    assert_eq!(check_client_record_size_limit(64), None);
+    assert_eq!(check_client_record_size_limit(4096), None);
+    assert_eq!(check_client_record_size_limit(0x4001), None);
+    assert_eq!(
+        check_client_record_size_limit(0x4002),
+        Some(Error::BadRecordSizeLimit)
+    );
+}
+
+#[test]
+fn record_size_limits_are_respected() {
+    // header, explicit nonce and tag for TLS1.2 suites, or header and tag for TLS1.3
+    let encryption_overhead = 29;
+
+    for version in rustls::ALL_VERSIONS {
+        println!("test version={version:?}");
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        client_config.record_size_limit = Some(256);
+        let mut server_config = make_server_config(KeyType::Rsa2048);
+        server_config.record_size_limit = Some(512);
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+
+        let big_data = [0u8; 2048];
+        server
+            .writer()
+            .write_all(&big_data)
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new(&mut client);
+            server.write_tls(&mut pipe).unwrap();
+            assert_eq!(pipe.writevs.len(), 1);
+            assert!(pipe.writevs[0].len() >= 2048 / 256);
+            assert!(pipe.writevs[0]
+                .iter()
+                .all(|x| *x <= 256 + encryption_overhead));
+        }
+        check_read(&mut client.reader(), &big_data);
+
+        client
+            .writer()
+            .write_all(&big_data)
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new(&mut server);
+            client.write_tls(&mut pipe).unwrap();
+            assert_eq!(pipe.writevs.len(), 1);
+            assert!(pipe.writevs[0].len() >= 2048 / 512);
+            assert!(pipe.writevs[0]
+                .iter()
+                .all(|x| *x <= 512 + encryption_overhead));
+        }
+        check_read(&mut server.reader(), &big_data);
+    }
+}
+
+#[test]
+fn tls13_records_are_padded() {
+    // header and tag
+    let encryption_overhead = 5 + 16;
+
+    // the expected length of each record's `TLSInnerPlaintext`
+    for (padding, expected_len) in [
+        (RecordPadding::BlockSize(64), 64),
+        (RecordPadding::MaxFragment, 513),
+    ] {
+        println!("test padding={padding:?}");
+        let mut client_config =
+            make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]);
+        client_config.record_padding = padding.clone();
+        client_config.record_size_limit = Some(513);
+        let mut server_config = make_server_config(KeyType::Rsa2048);
+        server_config.record_padding = padding;
+        server_config.record_size_limit = Some(513);
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+
+        for data in [&b"hello"[..], &b"hello world"[..]] {
+            client.writer().write_all(data).unwrap();
+            {
+                let mut pipe = OtherSession::new(&mut server);
+                client.write_tls(&mut pipe).unwrap();
+                assert_eq!(pipe.writevs, vec![vec![expected_len + encryption_overhead]]);
+            }
+            check_read(&mut server.reader(), data);
+
+            server.writer().write_all(data).unwrap();
+            {
+                let mut pipe = OtherSession::new(&mut client);
+                server.write_tls(&mut pipe).unwrap();
+                assert_eq!(pipe.writevs, vec![vec![expected_len + encryption_overhead]]);
+            }
+            check_read(&mut client.reader(), data);
+        }
+    }
+}
+
+#[test]
+fn tls13_record_padding_respects_max_fragment_size() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]);
+    client_config.record_padding = RecordPadding::MaxFragment;
+    client_config.max_fragment_size = Some(1024);
+
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa2048));
+    do_handshake(&mut client, &mut server);
+
+    let big_data = [0u8; 1500];
+    client
+        .writer()
+        .write_all(&big_data)
+        .unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut server);
+        client.write_tls(&mut pipe).unwrap();
+        // both records are full size: the second one by virtue of padding
+        assert_eq!(pipe.writevs, vec![vec![1024 + 1 + 16, 1024 + 1 + 16]]);
+    }
+    check_read(&mut server.reader(), &big_data);
+}
+
+#[test]
+fn tls13_record_padding_with_custom_policy() {
+    #[derive(Debug, Default)]
+    struct PadHandshakes(Mutex<Vec<(ContentType, usize)>>);
+
+    impl PadsRecords for PadHandshakes {
+        fn padding_len(&self, typ: ContentType, len: usize, _max_padding: usize) -> usize {
+            self.0.lock().unwrap().push((typ, len));
+            match typ {
+                ContentType::Handshake => 100,
+                _ => 0,
+            }
+        }
+    }
+
+    let padder = Arc::new(PadHandshakes::default());
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.record_padding = RecordPadding::Custom(padder.clone());
+
+    let (mut client, mut server) = make_pair_for_configs(
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]),
+        server_config,
+    );
+    do_handshake(&mut client, &mut server);
+
+    server
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    check_read(&mut client.reader(), b"hello");
+
+    let calls = padder.0.lock().unwrap();
+    assert!(calls
+        .iter()
+        .any(|(typ, _)| *typ == ContentType::Handshake));
+    assert_eq!(calls.last(), Some(&(ContentType::ApplicationData, 5)));
+}
+
+#[test]
+fn tls12_records_are_not_padded() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS12]);
+    client_config.record_padding = RecordPadding::MaxFragment;
+
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa2048));
+    do_handshake(&mut client, &mut server);
+
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    let mut pipe = OtherSession::new(&mut server);
+    client.write_tls(&mut pipe).unwrap();
+    assert!(pipe.writevs[0][0] < 64);
+}
+
+#[derive(Debug)]
+struct EchoCustomExtensions;
+
+impl HandlesCustomExtensions for EchoCustomExtensions {
+    fn respond(&self, client_hello: &ClientHello<'_>) -> Vec<CustomExtension> {
+        let typ = ExtensionType::Unknown(0xfe01);
+        let mut exts =
+            vec![CustomExtension::new(ExtensionType::Unknown(0xfe02), *b"unoffered").unwrap()];
+        if let Some(payload) = client_hello.custom_extension(typ) {
+            let mut reply = payload.to_vec();
+            reply.reverse();
+            exts.push(CustomExtension::new(typ, reply).unwrap());
+        }
+        exts
+    }
+
+    fn respond_in_certificate(&self, client_hello: &ClientHello<'_>) -> Vec<CustomExtension> {
+        let typ = ExtensionType::Unknown(0xfe01);
+        client_hello
+            .custom_extension(typ)
+            .map(|_| CustomExtension::new(typ, *b"cert").unwrap())
+            .into_iter()
+            .collect()
+    }
+}
+
+#[test]
+fn custom_extensions_are_exchanged() {
+    let typ = ExtensionType::Unknown(0xfe01);
+
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        client_config.custom_extensions = vec![CustomExtension::new(typ, *b"hello").unwrap()];
+        let mut server_config = make_server_config(KeyType::Rsa2048);
+        server_config.custom_extensions = Some(Arc::new(EchoCustomExtensions));
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+
+        let mut expected = vec![CustomExtension::new(typ, *b"olleh").unwrap()];
+        if version.version == ProtocolVersion::TLSv1_3 {
+            expected.push(CustomExtension::new(typ, *b"cert").unwrap());
+        }
+        assert_eq!(client.received_custom_extensions(), &expected[..]);
+    }
+}
+
+#[test]
+fn custom_extensions_are_not_sent_unsolicited() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        let mut server_config = make_server_config(KeyType::Rsa2048);
+        server_config.custom_extensions = Some(Arc::new(EchoCustomExtensions));
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert!(client
+            .received_custom_extensions()
+            .is_empty());
+    }
+}
+
+#[test]
+fn custom_extensions_cannot_use_reserved_types() {
+    assert_eq!(
+        CustomExtension::new(ExtensionType::ServerName, []),
+        Err(Error::BadCustomExtension)
+    );
+}
+
+#[derive(Debug, Default)]
+struct RecordingObserver {
+    events: Arc<Mutex<Vec<(Side, HandshakeEvent)>>>,
+}
+
+impl RecordingObserver {
+    fn take(&self, side: Side) -> Vec<HandshakeEvent> {
+        let mut events = self.events.lock().unwrap();
+        let (ours, others) = events
+            .drain(..)
+            .partition(|(s, _)| *s == side);
+        *events = others;
+        ours.into_iter()
+            .map(|(_, event)| event)
+            .collect()
+    }
+}
+
+impl HandshakeObserver for RecordingObserver {
+    fn new_connection(&self, side: Side) -> Box<dyn ConnectionObserver> {
+        Box::new(RecordingConnectionObserver {
+            side,
+            events: self.events.clone(),
+        })
+    }
+}
+
+struct RecordingConnectionObserver {
+    side: Side,
+    events: Arc<Mutex<Vec<(Side, HandshakeEvent)>>>,
+}
+
+impl ConnectionObserver for RecordingConnectionObserver {
+    fn observe(&mut self, event: &HandshakeEvent) {
+        self.events
+            .lock()
+            .unwrap()
+            .push((self.side, *event));
+    }
+}
+
+fn message_types(events: &[HandshakeEvent]) -> Vec<(&'static str, HandshakeType)> {
+    events
+        .iter()
+        .filter_map(|event| match *event {
+            HandshakeEvent::MessageSent { typ, len } => {
+                assert!(len >= 4);
+                Some(("sent", typ))
+            }
+            HandshakeEvent::MessageReceived { typ, len } => {
+                assert!(len >= 4);
+                Some(("received", typ))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn observer_sees_tls13_handshake() {
+    let observer = Arc::new(RecordingObserver::default());
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]);
+    client_config.observer = Some(observer.clone());
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.observer = Some(observer.clone());
+    // otherwise the certificate is compressed if a compression feature is enabled
+    server_config.cert_compressors.clear();
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    client.refresh_traffic_keys().unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    let client_events = observer.take(Side::Client);
+    assert_eq!(
+        message_types(&client_events),
+        vec![
+            ("sent", HandshakeType::ClientHello),
+            ("received", HandshakeType::ServerHello),
+            ("received", HandshakeType::EncryptedExtensions),
+            ("received", HandshakeType::Certificate),
+            ("received", HandshakeType::CertificateVerify),
+            ("received", HandshakeType::Finished),
+            ("sent", HandshakeType::Finished),
+            ("received", HandshakeType::NewSessionTicket),
+            ("received", HandshakeType::NewSessionTicket),
+            ("sent", HandshakeType::KeyUpdate),
+        ]
+    );
+    assert_eq!(
+        client_events[7],
+        HandshakeEvent::HandshakeComplete {
+            kind: HandshakeKind::Full
+        }
+    );
+
+    let server_events = observer.take(Side::Server);
+    assert_eq!(
+        message_types(&server_events),
+        vec![
+            ("received", HandshakeType::ClientHello),
+            ("sent", HandshakeType::ServerHello),
+            ("sent", HandshakeType::EncryptedExtensions),
+            ("sent", HandshakeType::Certificate),
+            ("sent", HandshakeType::CertificateVerify),
+            ("sent", HandshakeType::Finished),
+            ("received", HandshakeType::Finished),
+            ("sent", HandshakeType::NewSessionTicket),
+            ("sent", HandshakeType::NewSessionTicket),
+            ("received", HandshakeType::KeyUpdate),
+            ("sent", HandshakeType::KeyUpdate),
+        ]
+    );
+    assert!(server_events.contains(&HandshakeEvent::HandshakeComplete {
+        kind: HandshakeKind::Full
+    }));
+}
+
+#[test]
+fn observer_sees_resumption() {
+    for version in rustls::ALL_VERSIONS {
+        let observer = Arc::new(RecordingObserver::default());
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        client_config.observer = Some(observer.clone());
+        let client_config = Arc::new(client_config);
+        let mut server_config = make_server_config(KeyType::Rsa2048);
+        server_config.observer = Some(observer.clone());
+        let server_config = Arc::new(server_config);
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert!(!observer
+            .take(Side::Client)
+            .contains(&HandshakeEvent::ResumptionOffered));
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        let client_events = observer.take(Side::Client);
+        assert_eq!(client_events[0], HandshakeEvent::ResumptionOffered);
+        assert!(client_events.contains(&HandshakeEvent::HandshakeComplete {
+            kind: HandshakeKind::Resumed
+        }));
+        assert!(observer
+            .take(Side::Server)
+            .contains(&HandshakeEvent::HandshakeComplete {
+                kind: HandshakeKind::Resumed
+            }));
+    }
+}
+
+#[test]
+fn observer_sees_alerts() {
+    let observer = Arc::new(RecordingObserver::default());
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]);
+    client_config.observer = Some(observer.clone());
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.observer = Some(observer.clone());
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    client.send_close_notify();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    let alerts = |event: &HandshakeEvent| match *event {
+        HandshakeEvent::AlertSent { description, fatal }
+        | HandshakeEvent::AlertReceived { description, fatal } => Some((description, fatal)),
+        _ => None,
+    };
+    assert_eq!(
+        observer
+            .take(Side::Client)
+            .last()
+            .copied(),
+        Some(HandshakeEvent::AlertSent {
+            description: AlertDescription::CloseNotify,
+            fatal: false
+        })
+    );
+    assert_eq!(
+        observer
+            .take(Side::Server)
+            .iter()
+            .filter_map(alerts)
+            .collect::<Vec<_>>(),
+        vec![(AlertDescription::CloseNotify, false)]
+    );
+}
+
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+#[test]
+fn client_sends_grease() {
+    use rustls::server::Acceptor;
+
+    let mut client_config = make_client_config(KeyType::Ed25519);
+    client_config.enable_grease = true;
+    client_config.alpn_protocols = vec![b"h2".to_vec()];
+    let mut client =
+        ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+
+    let mut acceptor = Acceptor::default();
+    acceptor
+        .read_tls(&mut buf.as_slice())
+        .unwrap();
+    let accepted = acceptor.accept().unwrap().unwrap();
+    let ch = accepted.client_hello();
+
+    assert!(is_grease(u16::from(ch.cipher_suites()[0])));
+    assert!(is_grease(u16::from(ch.supported_versions().unwrap()[0])));
+    assert!(is_grease(u16::from(ch.signature_schemes()[0])));
+    let grease_group = ch.named_groups().unwrap()[0];
+    assert!(is_grease(u16::from(grease_group)));
+    assert_eq!(ch.key_share_groups().unwrap().next(), Some(grease_group));
+    let alpn = ch.alpn().unwrap().collect::<Vec<_>>();
+    assert_eq!(alpn.len(), 2);
+    assert!(is_grease(u16::from_be_bytes(alpn[0].try_into().unwrap())));
+    assert_eq!(alpn[1], b"h2");
+    assert_eq!(
+        ch.extensions()
+            .filter(|typ| is_grease(u16::from(*typ)))
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn client_does_not_send_grease_by_default() {
+    use rustls::server::Acceptor;
+
+    let client_config = Arc::new(make_client_config(KeyType::Ed25519));
+    let mut client = ClientConnection::new(client_config, server_name("localhost")).unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+
+    let mut acceptor = Acceptor::default();
+    acceptor
+        .read_tls(&mut buf.as_slice())
+        .unwrap();
+    let accepted = acceptor.accept().unwrap().unwrap();
+    let ch = accepted.client_hello();
+
+    assert!(!ch
+        .cipher_suites()
+        .iter()
+        .any(|cs| is_grease(u16::from(*cs))));
+    assert!(!ch
+        .extensions()
+        .any(|typ| is_grease(u16::from(typ))));
+}
+
+#[test]
+fn handshakes_succeed_with_grease() {
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config =
+            make_client_config_with_versions_with_auth(KeyType::Rsa2048, &[version]);
+        client_config.enable_grease = true;
+        client_config.alpn_protocols = vec![b"h2".to_vec()];
+        let client_config = Arc::new(client_config);
+        let mut server_config = make_server_config_with_mandatory_client_auth(KeyType::Rsa2048);
+        server_config.enable_grease = true;
+        server_config.alpn_protocols = vec![b"h2".to_vec()];
+        let server_config = Arc::new(server_config);
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.alpn_protocol(), Some(&b"h2"[..]));
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+
+        // The client ignored GREASE in any tickets it received.
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+    }
+}
+
+#[test]
+fn handshake_with_grease_survives_hello_retry_request() {
+    let mut client_config = make_client_config_with_kx_groups(
+        KeyType::Rsa2048,
+        vec![provider::kx_group::X25519, provider::kx_group::SECP384R1],
+    );
+    client_config.enable_grease = true;
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa2048, vec![provider::kx_group::SECP384R1]);
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client.handshake_kind(),
+        Some(HandshakeKind::FullWithHelloRetryRequest)
+    );
+}
+
+#[test]
+fn post_handshake_client_auth_works_with_grease() {
+    let mut server_config = make_server_config_with_deferred_client_auth(KeyType::Rsa2048);
+    server_config.enable_grease = true;
+    let client_config = make_client_config_with_post_handshake_auth(KeyType::Rsa2048);
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    do_post_handshake_client_auth(&mut client, &mut server).unwrap();
+    assert!(server.peer_certificates().is_some());
+}
+
+fn client_hello_with_padding(padding: Option<u16>) -> Vec<u8> {
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.client_hello_padding = padding;
+    let mut client =
+        ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn client_hello_is_padded() {
+    use rustls::server::Acceptor;
+
+    let unpadded_len = client_hello_with_padding(None).len() - 5;
+    assert!(unpadded_len < 512);
+
+    for target in [512, unpadded_len as u16 + 1, unpadded_len as u16 + 4] {
+        let buf = client_hello_with_padding(Some(target));
+        let mut acceptor = Acceptor::default();
+        acceptor
+            .read_tls(&mut buf.as_slice())
+            .unwrap();
+        let accepted = acceptor.accept().unwrap().unwrap();
+        let ch = accepted.client_hello();
+
+        // Any padding extension adds at least four bytes.
+        assert_eq!(
+            ch.encoded().len(),
+            usize::from(target).max(unpadded_len + 4)
+        );
+        assert!(ch
+            .extensions()
+            .any(|typ| typ == ExtensionType::Padding));
+    }
+}
 
-                // check server -> client data flow
-                let pattern = (0x00..=0xffu8).collect::<Vec<u8>>();
-                assert_eq!(pattern.len(), server.writer().write(&pattern).unwrap());
-                transfer(&mut server, &mut client);
-                client.process_new_packets().unwrap();
-                check_read(&mut client.reader(), &pattern);
+#[test]
+fn client_hello_is_not_padded_beyond_target() {
+    let unpadded = client_hello_with_padding(None);
+    let padded = client_hello_with_padding(Some(128));
+    assert!(unpadded.len() - 5 > 128);
+    assert_eq!(padded.len(), unpadded.len());
+}
 
-                // and client -> server
-                assert_eq!(pattern.len(), client.writer().write(&pattern).unwrap());
-                transfer(&mut client, &mut server);
-                server.process_new_packets().unwrap();
-                check_read(&mut server.reader(), &pattern);
+#[test]
+fn padded_client_hello_can_resume() {
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        client_config.client_hello_padding = Some(1024);
+        let client_config = Arc::new(client_config);
+        let server_config = Arc::new(make_server_config(KeyType::Rsa2048));
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+
+        // The PSK binder covers the padding.
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        let mut buf = Vec::new();
+        client.write_tls(&mut buf).unwrap();
+        assert_eq!(buf.len(), 5 + 1024);
+        server
+            .read_tls(&mut buf.as_slice())
+            .unwrap();
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+    }
+}
+
+#[test]
+fn server_rejects_too_small_record_size_limit() {
+    fn set_record_size_limit(msg: &mut Message) -> Altered {
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+                for ext in ch.extensions.iter_mut() {
+                    if let ClientExtension::RecordSizeLimit(limit) = ext {
+                        *limit = 63;
+                    }
+                }
             }
+            *encoded = Payload::new(parsed.get_encoding());
         }
+        Altered::InPlace
+    }
+
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        client_config.record_size_limit = Some(64);
+        let client =
+            ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+        let server = ServerConnection::new(Arc::new(make_server_config(KeyType::Rsa2048))).unwrap();
+        let (mut client, mut server) = (client.into(), server.into());
+
+        transfer_altered(&mut client, set_record_size_limit, &mut server);
+        assert_eq!(
+            server.process_new_packets(),
+            Err(Error::PeerMisbehaved(
+                PeerMisbehaved::InvalidRecordSizeLimit
+            ))
+        );
     }
 }
 
@@ -6561,6 +8864,60 @@ fn test_acceptor() {
     assert_eq!(alert_content, expected);
 }
 
+#[test]
+fn test_acceptor_client_hello_details() {
+    use rustls::server::Acceptor;
+
+    let client_config = Arc::new(make_client_config(KeyType::Ed25519));
+    let mut client = ClientConnection::new(client_config, server_name("localhost")).unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+
+    let mut acceptor = Acceptor::default();
+    acceptor
+        .read_tls(&mut buf.as_slice())
+        .unwrap();
+    let accepted = acceptor.accept().unwrap().unwrap();
+    let ch = accepted.client_hello();
+
+    assert_eq!(ch.client_version(), ProtocolVersion::TLSv1_2);
+    assert_eq!(
+        ch.supported_versions(),
+        Some(&[ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2][..])
+    );
+    assert_eq!(ch.compression_methods(), &[Compression::Null]);
+    assert_eq!(
+        ch.ec_point_formats(),
+        Some(&[ECPointFormat::Uncompressed][..])
+    );
+    assert_eq!(
+        ch.psk_key_exchange_modes(),
+        Some(&[PSKKeyExchangeMode::PSK_DHE_KE][..])
+    );
+
+    let kx_groups = provider::default_provider()
+        .kx_groups
+        .iter()
+        .map(|kx| kx.name())
+        .collect::<Vec<_>>();
+    assert_eq!(ch.named_groups(), Some(&kx_groups[..]));
+    assert_eq!(
+        ch.key_share_groups()
+            .unwrap()
+            .collect::<Vec<_>>(),
+        vec![kx_groups[0]]
+    );
+
+    let extensions = ch.extensions().collect::<Vec<_>>();
+    assert!(extensions.contains(&ExtensionType::SupportedVersions));
+    assert!(extensions.contains(&ExtensionType::ServerName));
+    assert!(extensions.contains(&ExtensionType::KeyShare));
+    assert!(!ch.encrypted_client_hello_offered());
+
+    // The ClientHello fits in one record, so its encoding follows the record header.
+    assert_eq!(ch.encoded(), &buf[5..]);
+}
+
 #[test]
 fn test_acceptor_rejected_handshake() {
     use rustls::server::Acceptor;
@@ -6793,64 +9150,398 @@ fn test_secret_extract_produces_correct_variant() {
     );
 }
 
-/// Test that secrets cannot be extracted unless explicitly enabled, and until
-/// the handshake is done.
-#[cfg(feature = "tls12")]
+/// Test that secrets cannot be extracted unless explicitly enabled, and until
+/// the handshake is done.
+#[cfg(feature = "tls12")]
+#[test]
+fn test_secret_extraction_disabled_or_too_early() {
+    let kt = KeyType::Rsa2048;
+    let provider = Arc::new(CryptoProvider {
+        cipher_suites: vec![cipher_suite::TLS13_AES_128_GCM_SHA256],
+        ..provider::default_provider()
+    });
+
+    for (server_enable, client_enable) in [(true, false), (false, true)] {
+        let mut server_config = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(kt.get_chain(), kt.get_key())
+            .unwrap();
+        server_config.enable_secret_extraction = server_enable;
+        let server_config = Arc::new(server_config);
+
+        let mut client_config = make_client_config(kt);
+        client_config.enable_secret_extraction = client_enable;
+
+        let client_config = Arc::new(client_config);
+
+        let (client, server) = make_pair_for_arc_configs(&client_config, &server_config);
+
+        assert!(
+            client
+                .dangerous_extract_secrets()
+                .is_err(),
+            "extraction should fail until handshake completes"
+        );
+        assert!(
+            server
+                .dangerous_extract_secrets()
+                .is_err(),
+            "extraction should fail until handshake completes"
+        );
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+
+        do_handshake(&mut client, &mut server);
+
+        assert_eq!(
+            server_enable,
+            server
+                .dangerous_extract_secrets()
+                .is_ok()
+        );
+        assert_eq!(
+            client_enable,
+            client
+                .dangerous_extract_secrets()
+                .is_ok()
+        );
+    }
+}
+
+fn traffic_secret_bytes(secrets: &ConnectionTrafficSecrets) -> Vec<u8> {
+    match secrets {
+        ConnectionTrafficSecrets::Aes128Gcm { key, iv }
+        | ConnectionTrafficSecrets::Aes256Gcm { key, iv }
+        | ConnectionTrafficSecrets::Chacha20Poly1305 { key, iv } => {
+            [key.as_ref(), iv.as_ref()].concat()
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn make_tls13_kernel_pair(
+    client_config: ClientConfig,
+) -> (RawTls, rustls::kernel::KernelConnection, ServerConnection) {
+    let mut client_config = client_config;
+    client_config.enable_secret_extraction = true;
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.enable_secret_extraction = true;
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    let suite = client
+        .negotiated_cipher_suite()
+        .unwrap();
+    let (secrets, kernel) = client
+        .dangerous_into_kernel_connection()
+        .unwrap();
+    (RawTls::new(suite, secrets), kernel, server)
+}
+
+#[test]
+fn test_kernel_connection_key_update() {
+    let client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]);
+    let (mut raw_client, mut kernel, mut server) = make_tls13_kernel_pair(client_config);
+    assert_eq!(kernel.protocol_version(), ProtocolVersion::TLSv1_3);
+
+    // update our keys, asking the server to update its keys too
+    let KernelAction::SendKeyUpdate {
+        message,
+        secrets: client_tx,
+    } = kernel.update_keys(true).unwrap()
+    else {
+        panic!("expected SendKeyUpdate");
+    };
+    let msg = PlainMessage {
+        typ: ContentType::Handshake,
+        version: ProtocolVersion::TLSv1_3,
+        payload: Payload::new(message),
+    };
+    raw_client.encrypt_and_send(&msg, &mut server);
+    server.process_new_packets().unwrap();
+
+    // the server's reply goes out ahead of its next record
+    server
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    let reply = raw_client.receive_plain(&mut server);
+    assert_eq!(reply.typ, ContentType::Handshake);
+    let actions = kernel
+        .handle_record(reply.typ, reply.payload.bytes())
+        .unwrap();
+    let [KernelAction::UpdateRx(client_rx)] = &actions[..] else {
+        panic!("unexpected actions {actions:?}");
+    };
+
+    // both sides now agree on the new secrets
+    let server_secrets = server
+        .dangerous_extract_secrets()
+        .unwrap();
+    assert_eq!(server_secrets.rx.0, 0);
+    assert_eq!(
+        traffic_secret_bytes(&server_secrets.rx.1),
+        traffic_secret_bytes(&client_tx)
+    );
+    assert_eq!(server_secrets.tx.0, 1); // after "hello"
+    assert_eq!(
+        traffic_secret_bytes(&server_secrets.tx.1),
+        traffic_secret_bytes(client_rx)
+    );
+}
+
+#[test]
+fn test_kernel_connection_replies_to_key_update_request() {
+    let client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]);
+    let (_, mut kernel, _) = make_tls13_kernel_pair(client_config);
+
+    // KeyUpdate(update_requested), as a server would send
+    let actions = kernel
+        .handle_record(ContentType::Handshake, &[0x18, 0x00, 0x00, 0x01, 0x01])
+        .unwrap();
+    match &actions[..] {
+        [KernelAction::UpdateRx(_), KernelAction::SendKeyUpdate { message, .. }] => {
+            assert_eq!(message, &[0x18, 0x00, 0x00, 0x01, 0x00]);
+        }
+        _ => panic!("unexpected actions {actions:?}"),
+    }
+
+    // the same, split across two records
+    let actions = kernel
+        .handle_record(ContentType::Handshake, &[0x18, 0x00])
+        .unwrap();
+    assert!(actions.is_empty());
+    let actions = kernel
+        .handle_record(ContentType::Handshake, &[0x00, 0x01, 0x00])
+        .unwrap();
+    assert!(matches!(actions[..], [KernelAction::UpdateRx(_)]));
+
+    // a KeyUpdate followed by part of another message
+    assert_eq!(
+        kernel
+            .handle_record(
+                ContentType::Handshake,
+                &[0x18, 0x00, 0x00, 0x01, 0x00, 0x04]
+            )
+            .unwrap_err(),
+        PeerMisbehaved::KeyEpochWithPendingFragment.into()
+    );
+}
+
+#[test]
+fn test_kernel_connection_receives_tickets() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]);
+    client_config.enable_secret_extraction = true;
+    let client_config = Arc::new(client_config);
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.send_tls13_tickets = 1;
+    let server_config = Arc::new(server_config);
+
+    // complete the handshake, without the client seeing the server's ticket
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    assert!(!client.is_handshaking());
+    assert!(!server.is_handshaking());
+
+    let suite = client
+        .negotiated_cipher_suite()
+        .unwrap();
+    let (secrets, mut kernel) = client
+        .dangerous_into_kernel_connection()
+        .unwrap();
+    let mut raw_client = RawTls::new(suite, secrets);
+
+    let ticket = raw_client.receive_plain(&mut server);
+    assert_eq!(ticket.typ, ContentType::Handshake);
+    assert!(kernel
+        .handle_record(ticket.typ, ticket.payload.bytes())
+        .unwrap()
+        .is_empty());
+
+    // the ticket is usable for resumption
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+}
+
+#[test]
+fn test_kernel_connection_alerts() {
+    let client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]);
+    let (mut raw_client, mut kernel, mut server) = make_tls13_kernel_pair(client_config);
+
+    // warnings other than user_canceled are fatal in TLS1.3
+    assert!(kernel
+        .handle_record(ContentType::Alert, &[0x01, 0x5a])
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        kernel
+            .handle_record(ContentType::Alert, &[0x01, 0x2a])
+            .unwrap_err(),
+        Error::AlertReceived(AlertDescription::BadCertificate)
+    );
+
+    assert!(!kernel.peer_has_closed());
+    server.send_close_notify();
+    let alert = raw_client.receive_plain(&mut server);
+    assert_eq!(alert.typ, ContentType::Alert);
+    let actions = kernel
+        .handle_record(alert.typ, alert.payload.bytes())
+        .unwrap();
+    assert!(matches!(actions[..], [KernelAction::PeerClosed]));
+    assert!(kernel.peer_has_closed());
+
+    assert_eq!(
+        kernel
+            .handle_record(ContentType::Alert, &[0x02, 0x28])
+            .unwrap_err(),
+        Error::AlertReceived(AlertDescription::HandshakeFailure)
+    );
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn test_kernel_connection_tls12() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS12]);
+    client_config.enable_secret_extraction = true;
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa2048));
+    do_handshake(&mut client, &mut server);
+
+    let (_, mut kernel) = client
+        .dangerous_into_kernel_connection()
+        .unwrap();
+    assert_eq!(kernel.protocol_version(), ProtocolVersion::TLSv1_2);
+    assert!(kernel.update_keys(false).is_err());
+
+    // warnings are ignored, but there are no handshake messages after the handshake
+    assert!(kernel
+        .handle_record(ContentType::Alert, &[0x01, 0x2a])
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        kernel
+            .handle_record(ContentType::Handshake, &[0x00, 0x00, 0x00, 0x00])
+            .unwrap_err(),
+        Error::InappropriateMessage {
+            expect_types: vec![ContentType::Alert],
+            got_type: ContentType::Handshake,
+        }
+    );
+}
+
 #[test]
-fn test_secret_extraction_disabled_or_too_early() {
-    let kt = KeyType::Rsa2048;
-    let provider = Arc::new(CryptoProvider {
-        cipher_suites: vec![cipher_suite::TLS13_AES_128_GCM_SHA256],
-        ..provider::default_provider()
-    });
-
-    for (server_enable, client_enable) in [(true, false), (false, true)] {
-        let mut server_config = ServerConfig::builder_with_provider(provider.clone())
-            .with_safe_default_protocol_versions()
-            .unwrap()
-            .with_no_client_auth()
-            .with_single_cert(kt.get_chain(), kt.get_key())
-            .unwrap();
-        server_config.enable_secret_extraction = server_enable;
-        let server_config = Arc::new(server_config);
+fn test_kernel_connection_requires_received_data_to_be_consumed() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]);
+    client_config.enable_secret_extraction = true;
+    let client_config = Arc::new(client_config);
+    let server_config = Arc::new(make_server_config(KeyType::Rsa2048));
 
-        let mut client_config = make_client_config(kt);
-        client_config.enable_secret_extraction = client_enable;
+    // complete the handshake, then receive a ticket and application data in one read
+    let handshake = || {
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        assert!(!client.is_handshaking());
 
-        let client_config = Arc::new(client_config);
+        server
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut server, &mut client);
+        (client, server)
+    };
 
-        let (client, server) = make_pair_for_arc_configs(&client_config, &server_config);
+    // not processed
+    let (client, _) = handshake();
+    assert_eq!(
+        client
+            .dangerous_into_kernel_connection()
+            .err(),
+        Some(Error::General(
+            "received TLS records have not been processed".into()
+        ))
+    );
 
-        assert!(
-            client
-                .dangerous_extract_secrets()
-                .is_err(),
-            "extraction should fail until handshake completes"
-        );
-        assert!(
-            server
-                .dangerous_extract_secrets()
-                .is_err(),
-            "extraction should fail until handshake completes"
-        );
+    // processed, but not read
+    let (mut client, _) = handshake();
+    client.process_new_packets().unwrap();
+    assert_eq!(
+        client
+            .dangerous_into_kernel_connection()
+            .err(),
+        Some(Error::General(
+            "received plaintext has not been read".into()
+        ))
+    );
 
-        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    // only part of the next record received
+    let (mut client, mut server) = handshake();
+    client.process_new_packets().unwrap();
+    let mut received = [0u8; 5];
+    client
+        .reader()
+        .read_exact(&mut received)
+        .unwrap();
+    server
+        .writer()
+        .write_all(b"world")
+        .unwrap();
+    let mut record = Vec::new();
+    server.write_tls(&mut record).unwrap();
+    client
+        .read_tls(&mut &record[..record.len() - 1])
+        .unwrap();
+    client.process_new_packets().unwrap();
+    assert_eq!(
+        client
+            .dangerous_into_kernel_connection()
+            .err(),
+        Some(Error::General(
+            "received TLS records have not been processed".into()
+        ))
+    );
 
-        do_handshake(&mut client, &mut server);
+    // all consumed
+    let (mut client, _) = handshake();
+    client.process_new_packets().unwrap();
+    client
+        .reader()
+        .read_exact(&mut received)
+        .unwrap();
+    assert_eq!(&received, b"hello");
+    client
+        .dangerous_into_kernel_connection()
+        .unwrap();
+}
 
-        assert_eq!(
-            server_enable,
-            server
-                .dangerous_extract_secrets()
-                .is_ok()
-        );
-        assert_eq!(
-            client_enable,
-            client
-                .dangerous_extract_secrets()
-                .is_ok()
-        );
-    }
+#[test]
+fn test_kernel_connection_requires_secret_extraction() {
+    let client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa2048));
+    do_handshake(&mut client, &mut server);
+    assert!(client
+        .dangerous_into_kernel_connection()
+        .is_err());
 }
 
 #[test]
@@ -7239,6 +9930,236 @@ fn test_client_fips_service_indicator_includes_ech_hpke_suite() {
     }
 }
 
+#[cfg(feature = "aws_lc_rs")]
+fn make_ech_client_config(
+    server_key: &EchServerKey,
+    kx_groups: Vec<&'static dyn SupportedKxGroup>,
+) -> ClientConfig {
+    let ech_config = EchConfig::new(server_key.config_list(), ALL_SUPPORTED_SUITES).unwrap();
+    let config = ClientConfig::builder_with_provider(
+        CryptoProvider {
+            kx_groups,
+            ..provider::default_provider()
+        }
+        .into(),
+    )
+    .with_ech(EchMode::Enable(ech_config))
+    .unwrap();
+    finish_client_config(KeyType::Rsa2048, config)
+}
+
+#[cfg(feature = "aws_lc_rs")]
+fn make_ech_server_key(suite: &'static dyn Hpke) -> Arc<EchServerKey> {
+    let public_name = DnsName::try_from("testserver.com").unwrap();
+    Arc::new(EchServerKey::generate(suite, 1, public_name).unwrap())
+}
+
+#[cfg(feature = "aws_lc_rs")]
+#[test]
+fn test_server_accepts_ech() {
+    for suite in ALL_SUPPORTED_SUITES {
+        let server_key = make_ech_server_key(*suite);
+        let client_config =
+            make_ech_client_config(&server_key, provider::default_provider().kx_groups);
+        let mut server_config = make_server_config(KeyType::Rsa2048);
+        server_config.ech_keys = vec![server_key];
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+
+        assert_eq!(client.ech_status(), EchStatus::Accepted);
+        assert_eq!(server.server_name(), Some("localhost"));
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+    }
+}
+
+#[cfg(feature = "aws_lc_rs")]
+#[test]
+fn test_server_accepts_ech_with_hello_retry_request() {
+    let server_key = make_ech_server_key(ALL_SUPPORTED_SUITES[0]);
+    // client sends a secp384r1 key share, but the server only accepts x25519
+    let client_config = make_ech_client_config(
+        &server_key,
+        vec![provider::kx_group::SECP384R1, provider::kx_group::X25519],
+    );
+    let mut server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa2048, vec![provider::kx_group::X25519]);
+    server_config.ech_keys = vec![server_key];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(client.ech_status(), EchStatus::Accepted);
+    assert_eq!(server.server_name(), Some("localhost"));
+    assert_eq!(
+        client.handshake_kind(),
+        Some(HandshakeKind::FullWithHelloRetryRequest)
+    );
+}
+
+#[cfg(feature = "aws_lc_rs")]
+#[test]
+fn test_server_accepts_ech_with_padded_client_hello() {
+    let server_key = make_ech_server_key(ALL_SUPPORTED_SUITES[0]);
+    let mut client_config =
+        make_ech_client_config(&server_key, provider::default_provider().kx_groups);
+    client_config.client_hello_padding = Some(2048);
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.ech_keys = vec![server_key];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    assert_eq!(buf.len(), 5 + 2048);
+    server
+        .read_tls(&mut buf.as_slice())
+        .unwrap();
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(client.ech_status(), EchStatus::Accepted);
+    assert_eq!(server.server_name(), Some("localhost"));
+}
+
+#[cfg(feature = "aws_lc_rs")]
+#[test]
+fn test_server_accepts_ech_with_acceptor() {
+    use rustls::server::Acceptor;
+
+    let server_key = make_ech_server_key(ALL_SUPPORTED_SUITES[0]);
+    let client_config = make_ech_client_config(&server_key, provider::default_provider().kx_groups);
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.ech_keys = vec![server_key];
+
+    let mut client = ClientConnection::new(client_config.into(), server_name("localhost")).unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+
+    let mut acceptor = Acceptor::default();
+    acceptor
+        .read_tls(&mut buf.as_slice())
+        .unwrap();
+    let accepted = acceptor.accept().unwrap().unwrap();
+    // The outer hello carries the public name.
+    assert_eq!(
+        accepted.client_hello().server_name(),
+        Some("testserver.com")
+    );
+
+    let mut server = accepted
+        .into_connection(server_config.into())
+        .unwrap();
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(client.ech_status(), EchStatus::Accepted);
+    assert_eq!(server.server_name(), Some("localhost"));
+}
+
+#[cfg(feature = "aws_lc_rs")]
+#[test]
+fn test_server_rejects_ech_with_retry_configs() {
+    let suite = ALL_SUPPORTED_SUITES[0];
+    let client_config = make_ech_client_config(
+        &make_ech_server_key(suite),
+        provider::default_provider().kx_groups,
+    );
+    // The server has a different key with the same config ID, so can't decrypt the offer.
+    let server_key = make_ech_server_key(suite);
+    let retry_config_list = server_key.config_list();
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.ech_keys = vec![server_key];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    let err = do_handshake_until_error(&mut client, &mut server).unwrap_err();
+
+    let ErrorFromPeer::Client(Error::PeerIncompatible(
+        PeerIncompatible::ServerRejectedEncryptedClientHello(Some(retry_configs)),
+    )) = err
+    else {
+        panic!("unexpected error {err:?}");
+    };
+    assert_eq!(retry_configs.get_encoding(), retry_config_list.as_ref());
+    assert_eq!(client.ech_retry_configs(), Some(retry_config_list));
+    assert_eq!(client.ech_status(), EchStatus::Rejected);
+    assert_eq!(server.server_name(), Some("testserver.com"));
+}
+
+#[cfg(feature = "aws_lc_rs")]
+#[test]
+fn test_client_retries_ech_with_retry_configs() {
+    let suite = ALL_SUPPORTED_SUITES[0];
+    let client_config = make_ech_client_config(
+        &make_ech_server_key(suite),
+        provider::default_provider().kx_groups,
+    );
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.ech_keys = vec![make_ech_server_key(suite)];
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+    do_handshake_until_error(&mut client, &mut server).unwrap_err();
+    assert_eq!(client.ech_status(), EchStatus::Rejected);
+    assert!(client.ech_retry_configs().is_some());
+    let retry_config = client
+        .ech_retry_client_config()
+        .unwrap();
+
+    // a retry that is rejected again is not retried
+    let mut rotated_server_config = make_server_config(KeyType::Rsa2048);
+    rotated_server_config.ech_keys = vec![make_ech_server_key(suite)];
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&retry_config, &Arc::new(rotated_server_config));
+    do_handshake_until_error(&mut client, &mut server).unwrap_err();
+    assert_eq!(client.ech_status(), EchStatus::Rejected);
+    assert!(client.ech_retry_configs().is_some());
+    assert!(client
+        .ech_retry_client_config()
+        .is_none());
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&retry_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.ech_status(), EchStatus::Accepted);
+    assert_eq!(client.ech_retry_configs(), None);
+    assert!(client
+        .ech_retry_client_config()
+        .is_none());
+    assert_eq!(server.server_name(), Some("localhost"));
+}
+
+#[cfg(feature = "aws_lc_rs")]
+#[test]
+fn test_client_does_not_retry_ech_with_other_public_name() {
+    let suite = ALL_SUPPORTED_SUITES[0];
+    let client_config = make_ech_client_config(
+        &make_ech_server_key(suite),
+        provider::default_provider().kx_groups,
+    );
+    let public_name = DnsName::try_from("otherserver.com").unwrap();
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.ech_keys = vec![Arc::new(
+        EchServerKey::generate(suite, 1, public_name).unwrap(),
+    )];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake_until_error(&mut client, &mut server).unwrap_err();
+    assert_eq!(client.ech_status(), EchStatus::Rejected);
+    assert!(client.ech_retry_configs().is_some());
+    assert!(client
+        .ech_retry_client_config()
+        .is_none());
+}
+
+#[test]
+fn test_no_ech_retry_configs_without_ech() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.ech_status(), EchStatus::NotOffered);
+    assert_eq!(client.ech_retry_configs(), None);
+    assert!(client
+        .ech_retry_client_config()
+        .is_none());
+}
+
 #[test]
 fn test_complete_io_errors_if_close_notify_received_too_early() {
     let mut server = ServerConnection::new(Arc::new(make_server_config(KeyType::Rsa2048))).unwrap();
@@ -7498,6 +10419,40 @@ fn test_server_uses_cached_compressed_certificates() {
     }
 }
 
+#[cfg(feature = "zstd")]
+#[test]
+fn test_zstd_dictionary_compression() {
+    use rustls::compress::ZstdDictionary;
+
+    let kt = KeyType::Rsa2048;
+    let chain = kt.get_chain();
+    let dictionary: &'static ZstdDictionary = Box::leak(Box::new(
+        ZstdDictionary::from_certificates(
+            rustls::CertificateCompressionAlgorithm::from(0xff01),
+            &chain[1..],
+        )
+        .unwrap(),
+    ));
+
+    let mut server_config = make_server_config(kt);
+    server_config.cert_compressors = vec![dictionary, rustls::compress::ZSTD_COMPRESSOR];
+    let mut client_config = make_client_config(kt);
+    client_config.cert_decompressors = vec![dictionary, rustls::compress::ZSTD_DECOMPRESSOR];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    // a client without the dictionary does not offer it, so is not sent certificates
+    // compressed with it
+    let mut server_config = make_server_config(kt);
+    server_config.cert_compressors = vec![dictionary, rustls::compress::ZSTD_COMPRESSOR];
+    let mut client_config = make_client_config(kt);
+    client_config.cert_decompressors = vec![rustls::compress::ZSTD_DECOMPRESSOR];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+}
+
 #[test]
 fn test_server_uses_uncompressed_certificate_if_compression_fails() {
     let mut server_config = make_server_config(KeyType::Rsa2048);
@@ -7881,6 +10836,7 @@ fn test_illegal_client_renegotiation_attempt_after_tls13_handshake() {
                     client_version: ProtocolVersion::TLSv1_2,
                     random: Random::from([0u8; 32]),
                     session_id: SessionId::read_bytes(&[0u8]).unwrap(),
+                    legacy_cookie: None,
                     cipher_suites: vec![],
                     compression_methods: vec![Compression::Null],
                     extensions: vec![ClientExtension::ExtendedMasterSecretRequest],
@@ -12,17 +12,21 @@ use pki_types::{
 };
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
 use rustls::client::{
-    AlwaysResolvesClientRawPublicKeys, ServerCertVerifierBuilder, WebPkiServerVerifier,
+    AlwaysResolvesClientRawPublicKeys, ResolvesClientPsk, ServerCertVerifierBuilder,
+    WebPkiServerVerifier,
 };
 use rustls::crypto::cipher::{InboundOpaqueMessage, MessageDecrypter, MessageEncrypter};
 use rustls::crypto::{verify_tls13_signature_with_raw_key, CryptoProvider};
+use rustls::internal::msgs::base::Payload;
 use rustls::internal::msgs::codec::{Codec, Reader};
 use rustls::internal::msgs::message::{Message, OutboundOpaqueMessage, PlainMessage};
+use rustls::psk::ExternalPsk;
 use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
 use rustls::server::{
-    AlwaysResolvesServerRawPublicKeys, ClientCertVerifierBuilder, WebPkiClientVerifier,
+    AlwaysResolvesServerRawPublicKeys, ClientCertVerifierBuilder, ClientHello, OperationResult,
+    PendingOperation, ResolvesServerCert, ResolvesServerPsk, WebPkiClientVerifier,
 };
-use rustls::sign::CertifiedKey;
+use rustls::sign::{CertifiedKey, Signer, SigningKey};
 use rustls::{
     ClientConfig, ClientConnection, Connection, ConnectionCommon, ContentType,
     DigitallySignedStruct, DistinguishedName, Error, InconsistentKeys, NamedGroup, ProtocolVersion,
@@ -346,6 +350,12 @@ impl KeyType {
             .into()
     }
 
+    pub fn get_intermediate_key(&self) -> PrivateKeyDer<'static> {
+        PrivatePkcs8KeyDer::from_pem_slice(self.bytes_for("inter.key"))
+            .unwrap()
+            .into()
+    }
+
     pub fn get_client_chain(&self) -> Vec<CertificateDer<'static>> {
         CertificateDer::pem_slice_iter(self.bytes_for("client.fullchain"))
             .map(|result| result.unwrap())
@@ -413,6 +423,39 @@ impl KeyType {
         Ok(Arc::new(CertifiedKey::new(self.get_chain(), private_key)))
     }
 
+    /// Completes a deferred `operation` using this key type's server certificate and key.
+    ///
+    /// A resolved certificate's key also defers signing if `defer_signing` is set.
+    pub fn complete_operation(
+        &self,
+        operation: PendingOperation<'_>,
+        defer_signing: bool,
+    ) -> OperationResult {
+        let key = provider::default_provider()
+            .key_provider
+            .load_private_key(self.get_key())
+            .unwrap();
+
+        match operation {
+            PendingOperation::ResolveCertificate(_) => {
+                let key: Arc<dyn SigningKey> = match defer_signing {
+                    true => Arc::new(DeferredSigningKey(key)),
+                    false => key,
+                };
+                OperationResult::Certificate(Some(Arc::new(CertifiedKey::new(
+                    self.get_chain(),
+                    key,
+                ))))
+            }
+            PendingOperation::Sign { scheme, message } => OperationResult::Signature(
+                key.choose_scheme(&[scheme])
+                    .unwrap()
+                    .sign(message),
+            ),
+            _ => unreachable!(),
+        }
+    }
+
     fn get_crl(&self, role: &str, r#type: &str) -> CertificateRevocationListDer<'static> {
         CertificateRevocationListDer::from_pem_slice(
             self.bytes_for(&format!("{role}.{type}.crl.pem")),
@@ -828,6 +871,78 @@ pub fn do_handshake_until_both_error(
     }
 }
 
+/// A certificate resolver which defers resolution to the application.
+#[derive(Debug)]
+pub struct DeferredResolver;
+
+impl ResolvesServerCert for DeferredResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        unreachable!()
+    }
+
+    fn defers_resolution(&self) -> bool {
+        true
+    }
+}
+
+/// A signing key which defers signing to the application.
+#[derive(Debug)]
+pub struct DeferredSigningKey(pub Arc<dyn SigningKey>);
+
+impl SigningKey for DeferredSigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        self.0
+            .choose_scheme(offered)
+            .map(|signer| Box::new(DeferredSigner(signer)) as Box<dyn Signer>)
+    }
+
+    fn algorithm(&self) -> rustls::SignatureAlgorithm {
+        self.0.algorithm()
+    }
+}
+
+#[derive(Debug)]
+struct DeferredSigner(Box<dyn Signer>);
+
+impl Signer for DeferredSigner {
+    fn sign(&self, _message: &[u8]) -> Result<Vec<u8>, Error> {
+        unreachable!()
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        self.0.scheme()
+    }
+
+    fn defers_signing(&self) -> bool {
+        true
+    }
+}
+
+/// A PSK resolver which offers, or accepts, a fixed set of external PSKs.
+#[derive(Debug, Default)]
+pub struct StaticPskResolver(pub Vec<Arc<ExternalPsk>>);
+
+impl StaticPskResolver {
+    pub fn new(psks: impl IntoIterator<Item = ExternalPsk>) -> Arc<Self> {
+        Arc::new(Self(psks.into_iter().map(Arc::new).collect()))
+    }
+}
+
+impl ResolvesClientPsk for StaticPskResolver {
+    fn resolve(&self, _server_name: &ServerName<'_>) -> Vec<Arc<ExternalPsk>> {
+        self.0.clone()
+    }
+}
+
+impl ResolvesServerPsk for StaticPskResolver {
+    fn resolve(&self, identity: &[u8]) -> Option<Arc<ExternalPsk>> {
+        self.0
+            .iter()
+            .find(|psk| psk.identity() == identity)
+            .cloned()
+    }
+}
+
 pub fn server_name(name: &'static str) -> ServerName<'static> {
     name.try_into().unwrap()
 }
@@ -1087,6 +1202,13 @@ impl MockServerVerifier {
         }
     }
 
+    pub fn offers_signature_schemes(signature_schemes: Vec<SignatureScheme>) -> Self {
+        MockServerVerifier {
+            signature_schemes,
+            ..Default::default()
+        }
+    }
+
     pub fn expects_raw_public_keys() -> Self {
         MockServerVerifier {
             requires_raw_public_keys: true,
@@ -1213,6 +1335,7 @@ pub struct RawTls {
     enc_seq: u64,
     decrypter: Box<dyn MessageDecrypter>,
     dec_seq: u64,
+    received: Vec<u8>,
 }
 
 impl RawTls {
@@ -1236,7 +1359,8 @@ impl RawTls {
         )
     }
 
-    fn new(suite: SupportedCipherSuite, secrets: rustls::ExtractedSecrets) -> Self {
+    /// Use secrets extracted from a connection, for example by `dangerous_into_kernel_connection()`
+    pub fn new(suite: SupportedCipherSuite, secrets: rustls::ExtractedSecrets) -> Self {
         let rustls::ExtractedSecrets {
             tx: (tx_seq, tx_keys),
             rx: (rx_seq, rx_keys),
@@ -1279,6 +1403,7 @@ impl RawTls {
             enc_seq: tx_seq,
             decrypter,
             dec_seq: rx_seq,
+            received: Vec::new(),
         }
     }
 
@@ -1302,16 +1427,32 @@ impl RawTls {
         peer: &mut impl DerefMut<Target = ConnectionCommon<impl SideData>>,
         f: impl Fn(Message),
     ) {
-        let mut data = vec![];
-        peer.write_tls(&mut io::Cursor::new(&mut data))
-            .unwrap();
+        let msg = Message::try_from(self.receive_plain(peer)).unwrap();
+        println!("receive_and_decrypt: {msg:?}");
+
+        f(msg);
+    }
+
+    /// Receive and decrypt a single record, without parsing its contents
+    pub fn receive_plain(
+        &mut self,
+        peer: &mut impl DerefMut<Target = ConnectionCommon<impl SideData>>,
+    ) -> PlainMessage {
+        // later records from the same write are kept for the next call
+        if self.received.is_empty() {
+            peer.write_tls(&mut self.received)
+                .unwrap();
+        }
 
-        let mut reader = Reader::init(&data);
+        let mut reader = Reader::init(&self.received);
         let content_type = ContentType::read(&mut reader).unwrap();
         let version = ProtocolVersion::read(&mut reader).unwrap();
         let len = u16::read(&mut reader).unwrap();
+        let mut data = self
+            .received
+            .drain(..5 + len as usize)
+            .collect::<Vec<u8>>();
         let left = &mut data[5..];
-        assert_eq!(len as usize, left.len());
 
         let inbound = InboundOpaqueMessage::new(content_type, version, left);
         let plain = self
@@ -1320,10 +1461,11 @@ impl RawTls {
             .unwrap();
         self.dec_seq += 1;
 
-        let msg = Message::try_from(plain).unwrap();
-        println!("receive_and_decrypt: {msg:?}");
-
-        f(msg);
+        PlainMessage {
+            typ: plain.typ,
+            version: plain.version,
+            payload: Payload::new(plain.payload.to_vec()),
+        }
     }
 }
 
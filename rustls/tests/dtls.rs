@@ -0,0 +1,626 @@
+#![allow(clippy::duplicate_mod)]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rustls::dtls::{CookieKey, DtlsClientConnection, DtlsConnectionCommon, DtlsServerConnection};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{
+    AlertDescription, ClientConfig, Error, HandshakeKind, PeerMisbehaved, ServerConfig, SideData,
+};
+
+use super::*;
+
+mod common;
+use common::*;
+
+const MAX_ROUNDS: usize = 50;
+
+/// Move every datagram `from` has to send to `to`, returning the number moved.
+fn transfer<F: SideData, T: SideData>(
+    from: &mut DtlsConnectionCommon<F>,
+    to: &mut DtlsConnectionCommon<T>,
+    now: Instant,
+) -> Result<usize, Error> {
+    let mut count = 0;
+    while let Some(mut datagram) = from.poll_transmit(now) {
+        to.process_datagram(&mut datagram)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn datagrams<D: SideData>(conn: &mut DtlsConnectionCommon<D>, now: Instant) -> Vec<Vec<u8>> {
+    let mut datagrams = Vec::new();
+    while let Some(datagram) = conn.poll_transmit(now) {
+        datagrams.push(datagram);
+    }
+    datagrams
+}
+
+fn do_handshake(client: &mut DtlsClientConnection, server: &mut DtlsServerConnection) {
+    let now = Instant::now();
+    for _ in 0..MAX_ROUNDS {
+        let sent = transfer(client, server, now).unwrap() + transfer(server, client, now).unwrap();
+        if sent == 0 {
+            break;
+        }
+    }
+    assert!(!client.is_handshaking());
+    assert!(!server.is_handshaking());
+}
+
+fn make_pair(
+    client_config: ClientConfig,
+    server_config: ServerConfig,
+) -> (DtlsClientConnection, DtlsServerConnection) {
+    (
+        DtlsClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap(),
+        DtlsServerConnection::new(Arc::new(server_config)).unwrap(),
+    )
+}
+
+fn send_and_receive<F: SideData, T: SideData>(
+    from: &mut DtlsConnectionCommon<F>,
+    to: &mut DtlsConnectionCommon<T>,
+    data: &[u8],
+) {
+    from.send_application_data(data)
+        .unwrap();
+    transfer(from, to, Instant::now()).unwrap();
+    assert_eq!(to.recv_application_data().as_deref(), Some(data));
+    assert_eq!(to.recv_application_data(), None);
+}
+
+#[test]
+fn dtls_handshake() {
+    for kt in [KeyType::Rsa2048, KeyType::EcdsaP256, KeyType::Ed25519] {
+        let (mut client, mut server) = make_pair(make_client_config(kt), make_server_config(kt));
+        do_handshake(&mut client, &mut server);
+
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+        assert_eq!(
+            client.protocol_version(),
+            Some(rustls::ProtocolVersion::DTLSv1_3)
+        );
+        assert_eq!(server.server_name(), Some("localhost"));
+        assert_eq!(
+            client.negotiated_cipher_suite(),
+            server.negotiated_cipher_suite()
+        );
+
+        send_and_receive(&mut client, &mut server, b"hello from client");
+        send_and_receive(&mut server, &mut client, b"hello from server");
+
+        // nothing is outstanding once everything is acknowledged
+        transfer(&mut client, &mut server, Instant::now()).unwrap();
+        transfer(&mut server, &mut client, Instant::now()).unwrap();
+        assert_eq!(client.poll_timeout(), None);
+        assert_eq!(server.poll_timeout(), None);
+    }
+}
+
+#[test]
+fn dtls_requires_tls13() {
+    let client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS12]);
+    assert!(matches!(
+        DtlsClientConnection::new(Arc::new(client_config), server_name("localhost")),
+        Err(Error::General(_))
+    ));
+
+    let server_config =
+        make_server_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS12]);
+    assert!(matches!(
+        DtlsServerConnection::new(Arc::new(server_config)),
+        Err(Error::General(_))
+    ));
+}
+
+#[test]
+fn dtls_application_data_before_handshake() {
+    let (mut client, _) = make_pair(
+        make_client_config(KeyType::Rsa2048),
+        make_server_config(KeyType::Rsa2048),
+    );
+    assert_eq!(
+        client.send_application_data(b"too early"),
+        Err(Error::HandshakeNotComplete)
+    );
+    assert_eq!(
+        client.refresh_traffic_keys(),
+        Err(Error::HandshakeNotComplete)
+    );
+}
+
+#[test]
+fn dtls_application_data_too_large() {
+    let (mut client, mut server) = make_pair(
+        make_client_config(KeyType::Rsa2048),
+        make_server_config(KeyType::Rsa2048),
+    );
+    do_handshake(&mut client, &mut server);
+
+    let max = client.max_application_data_size();
+    send_and_receive(&mut client, &mut server, &vec![1u8; max]);
+    assert!(matches!(
+        client.send_application_data(&vec![1u8; max + 1]),
+        Err(Error::General(_))
+    ));
+}
+
+#[test]
+fn dtls_fragmented_handshake() {
+    let (mut client, mut server) = make_pair(
+        make_client_config(KeyType::Rsa4096),
+        make_server_config(KeyType::Rsa4096),
+    );
+    client.set_max_datagram_size(300);
+    server.set_max_datagram_size(300);
+
+    let now = Instant::now();
+    let mut largest = 0;
+    for _ in 0..MAX_ROUNDS {
+        let mut sent = 0;
+        for mut datagram in datagrams(&mut client, now) {
+            largest = largest.max(datagram.len());
+            server
+                .process_datagram(&mut datagram)
+                .unwrap();
+            sent += 1;
+        }
+        for mut datagram in datagrams(&mut server, now) {
+            largest = largest.max(datagram.len());
+            client
+                .process_datagram(&mut datagram)
+                .unwrap();
+            sent += 1;
+        }
+        if sent == 0 {
+            break;
+        }
+    }
+
+    assert!(!client.is_handshaking());
+    assert!(!server.is_handshaking());
+    assert!(largest <= 300);
+    send_and_receive(&mut client, &mut server, b"hello");
+}
+
+#[test]
+fn dtls_reordered_handshake() {
+    let (mut client, mut server) = make_pair(
+        make_client_config(KeyType::Rsa4096),
+        make_server_config(KeyType::Rsa4096),
+    );
+    client.set_max_datagram_size(400);
+    server.set_max_datagram_size(400);
+
+    let now = Instant::now();
+    for _ in 0..MAX_ROUNDS {
+        let mut sent = 0;
+        for mut datagram in datagrams(&mut client, now)
+            .into_iter()
+            .rev()
+        {
+            server
+                .process_datagram(&mut datagram)
+                .unwrap();
+            sent += 1;
+        }
+        for mut datagram in datagrams(&mut server, now)
+            .into_iter()
+            .rev()
+        {
+            client
+                .process_datagram(&mut datagram)
+                .unwrap();
+            sent += 1;
+        }
+        if sent == 0 {
+            break;
+        }
+    }
+
+    assert!(!client.is_handshaking());
+    assert!(!server.is_handshaking());
+    send_and_receive(&mut server, &mut client, b"hello");
+}
+
+#[test]
+fn dtls_retransmits_lost_flights() {
+    let (mut client, mut server) = make_pair(
+        make_client_config(KeyType::EcdsaP256),
+        make_server_config(KeyType::EcdsaP256),
+    );
+
+    let mut now = Instant::now();
+    for _ in 0..MAX_ROUNDS {
+        // lose the first attempt at each flight, in each direction
+        let lost = datagrams(&mut client, now);
+        let lost_back = datagrams(&mut server, now);
+        if lost.is_empty() && lost_back.is_empty() && client.poll_timeout().is_none() {
+            break;
+        }
+
+        let deadline = [client.poll_timeout(), server.poll_timeout()]
+            .into_iter()
+            .flatten()
+            .min();
+        if let Some(deadline) = deadline {
+            assert!(deadline > now);
+            now = deadline;
+            client.handle_timeout(now);
+            server.handle_timeout(now);
+        }
+
+        transfer(&mut client, &mut server, now).unwrap();
+        transfer(&mut server, &mut client, now).unwrap();
+        transfer(&mut client, &mut server, now).unwrap();
+        transfer(&mut server, &mut client, now).unwrap();
+    }
+
+    assert!(!client.is_handshaking());
+    assert!(!server.is_handshaking());
+    send_and_receive(&mut client, &mut server, b"hello");
+    send_and_receive(&mut server, &mut client, b"hello");
+}
+
+#[test]
+fn dtls_retransmission_backs_off() {
+    let (mut client, _) = make_pair(
+        make_client_config(KeyType::EcdsaP256),
+        make_server_config(KeyType::EcdsaP256),
+    );
+    client.set_initial_timeout(Duration::from_millis(100));
+
+    let start = Instant::now();
+    let first = datagrams(&mut client, start);
+    assert_eq!(first.len(), 1);
+    assert_eq!(
+        client.poll_timeout(),
+        Some(start + Duration::from_millis(100))
+    );
+
+    // nothing happens before the deadline
+    client.handle_timeout(start + Duration::from_millis(99));
+    assert!(client.poll_transmit(start).is_none());
+
+    let now = start + Duration::from_millis(100);
+    client.handle_timeout(now);
+    let second = datagrams(&mut client, now);
+    assert_eq!(second.len(), 1);
+    assert_eq!(
+        client.poll_timeout(),
+        Some(now + Duration::from_millis(200))
+    );
+
+    // the retransmitted ClientHello is the same message, in a new record
+    assert_eq!(first[0][13..], second[0][13..]);
+    assert_ne!(first[0][..13], second[0][..13]);
+}
+
+#[test]
+fn dtls_duplicate_flight_triggers_retransmission() {
+    let (mut client, mut server) = make_pair(
+        make_client_config(KeyType::EcdsaP256),
+        make_server_config(KeyType::EcdsaP256),
+    );
+
+    let now = Instant::now();
+    let client_hello = datagrams(&mut client, now);
+    for datagram in &client_hello {
+        server
+            .process_datagram(&mut datagram.clone())
+            .unwrap();
+    }
+    let server_flight = datagrams(&mut server, now);
+    assert!(!server_flight.is_empty());
+
+    // the server flight was lost, so the client retransmits its ClientHello
+    // and the server answers immediately, without waiting for its timer
+    for datagram in &client_hello {
+        server
+            .process_datagram(&mut datagram.clone())
+            .unwrap();
+    }
+    let retransmitted = datagrams(&mut server, now);
+    assert_eq!(retransmitted.len(), server_flight.len());
+
+    for mut datagram in retransmitted {
+        client
+            .process_datagram(&mut datagram)
+            .unwrap();
+    }
+    do_handshake(&mut client, &mut server);
+}
+
+#[test]
+fn dtls_discards_corrupt_records() {
+    let (mut client, mut server) = make_pair(
+        make_client_config(KeyType::EcdsaP256),
+        make_server_config(KeyType::EcdsaP256),
+    );
+    do_handshake(&mut client, &mut server);
+
+    client
+        .send_application_data(b"hello")
+        .unwrap();
+    let mut datagram = client
+        .poll_transmit(Instant::now())
+        .unwrap();
+    let mut corrupt = datagram.clone();
+    let last = corrupt.len() - 1;
+    corrupt[last] ^= 1;
+
+    server
+        .process_datagram(&mut corrupt)
+        .unwrap();
+    assert_eq!(server.recv_application_data(), None);
+    server
+        .process_datagram(&mut datagram)
+        .unwrap();
+    assert_eq!(
+        server
+            .recv_application_data()
+            .as_deref(),
+        Some(&b"hello"[..])
+    );
+}
+
+#[test]
+fn dtls_replayed_record_is_discarded() {
+    let (mut client, mut server) = make_pair(
+        make_client_config(KeyType::EcdsaP256),
+        make_server_config(KeyType::EcdsaP256),
+    );
+    do_handshake(&mut client, &mut server);
+
+    client
+        .send_application_data(b"once")
+        .unwrap();
+    let datagram = client
+        .poll_transmit(Instant::now())
+        .unwrap();
+    server
+        .process_datagram(&mut datagram.clone())
+        .unwrap();
+    server
+        .process_datagram(&mut datagram.clone())
+        .unwrap();
+    assert_eq!(
+        server
+            .recv_application_data()
+            .as_deref(),
+        Some(&b"once"[..])
+    );
+    assert_eq!(server.recv_application_data(), None);
+}
+
+#[test]
+fn dtls_stateless_hello_retry_request() {
+    let key = Arc::new(CookieKey::from_secret(&[1; 32]));
+    let server_config = Arc::new(make_server_config(KeyType::EcdsaP256));
+    let mut client = DtlsClientConnection::new(
+        Arc::new(make_client_config(KeyType::EcdsaP256)),
+        server_name("localhost"),
+    )
+    .unwrap();
+
+    let now = Instant::now();
+    let mut first =
+        DtlsServerConnection::with_cookie(server_config.clone(), key.clone(), b"client".to_vec())
+            .unwrap();
+    transfer(&mut client, &mut first, now).unwrap();
+    assert!(first.awaiting_cookie());
+    assert!(first.is_handshaking());
+    assert_eq!(first.poll_timeout(), None);
+    let hello_retry = datagrams(&mut first, now);
+    assert_eq!(hello_retry.len(), 1);
+    drop(first);
+
+    for mut datagram in hello_retry {
+        client
+            .process_datagram(&mut datagram)
+            .unwrap();
+    }
+
+    // a new server, sharing only the key, continues the handshake
+    let mut second =
+        DtlsServerConnection::with_cookie(server_config, key, b"client".to_vec()).unwrap();
+    transfer(&mut client, &mut second, now).unwrap();
+    assert!(!second.awaiting_cookie());
+    do_handshake(&mut client, &mut second);
+
+    assert_eq!(
+        client.handshake_kind(),
+        Some(HandshakeKind::FullWithHelloRetryRequest)
+    );
+    send_and_receive(&mut client, &mut second, b"hello");
+}
+
+#[test]
+fn dtls_cookie_from_another_address_is_rejected() {
+    let key = Arc::new(CookieKey::from_secret(&[1; 32]));
+    let server_config = Arc::new(make_server_config(KeyType::EcdsaP256));
+    let mut client = DtlsClientConnection::new(
+        Arc::new(make_client_config(KeyType::EcdsaP256)),
+        server_name("localhost"),
+    )
+    .unwrap();
+
+    let now = Instant::now();
+    let mut first =
+        DtlsServerConnection::with_cookie(server_config.clone(), key.clone(), b"client".to_vec())
+            .unwrap();
+    transfer(&mut client, &mut first, now).unwrap();
+    transfer(&mut first, &mut client, now).unwrap();
+
+    let mut second =
+        DtlsServerConnection::with_cookie(server_config, key, b"attacker".to_vec()).unwrap();
+    assert_eq!(
+        transfer(&mut client, &mut second, now),
+        Err(PeerMisbehaved::InvalidCookie.into())
+    );
+}
+
+#[test]
+fn dtls_cookie_is_checked_before_certificate_resolution() {
+    #[derive(Debug)]
+    struct CountingResolver(Arc<dyn ResolvesServerCert>, AtomicUsize);
+
+    impl ResolvesServerCert for CountingResolver {
+        fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            self.0.resolve(client_hello)
+        }
+    }
+
+    let key = Arc::new(CookieKey::from_secret(&[1; 32]));
+    let mut server_config = make_server_config(KeyType::EcdsaP256);
+    let resolver = Arc::new(CountingResolver(
+        server_config.cert_resolver.clone(),
+        AtomicUsize::new(0),
+    ));
+    server_config.cert_resolver = resolver.clone();
+    let server_config = Arc::new(server_config);
+    let mut client = DtlsClientConnection::new(
+        Arc::new(make_client_config(KeyType::EcdsaP256)),
+        server_name("localhost"),
+    )
+    .unwrap();
+
+    let now = Instant::now();
+    let mut first =
+        DtlsServerConnection::with_cookie(server_config.clone(), key.clone(), b"client".to_vec())
+            .unwrap();
+    transfer(&mut client, &mut first, now).unwrap();
+    transfer(&mut first, &mut client, now).unwrap();
+    assert_eq!(resolver.1.load(Ordering::SeqCst), 0);
+    let second_hello = datagrams(&mut client, now);
+
+    let mut attacker =
+        DtlsServerConnection::with_cookie(server_config.clone(), key.clone(), b"attacker".to_vec())
+            .unwrap();
+    for mut datagram in second_hello.clone() {
+        assert_eq!(
+            attacker.process_datagram(&mut datagram),
+            Err(PeerMisbehaved::InvalidCookie.into())
+        );
+    }
+    assert_eq!(resolver.1.load(Ordering::SeqCst), 0);
+
+    let mut second =
+        DtlsServerConnection::with_cookie(server_config, key, b"client".to_vec()).unwrap();
+    for mut datagram in second_hello {
+        second
+            .process_datagram(&mut datagram)
+            .unwrap();
+    }
+    do_handshake(&mut client, &mut second);
+    assert_eq!(resolver.1.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn dtls_key_update() {
+    let (mut client, mut server) = make_pair(
+        make_client_config(KeyType::EcdsaP256),
+        make_server_config(KeyType::EcdsaP256),
+    );
+    do_handshake(&mut client, &mut server);
+
+    for _ in 0..5 {
+        client.refresh_traffic_keys().unwrap();
+        let now = Instant::now();
+        transfer(&mut client, &mut server, now).unwrap();
+        transfer(&mut server, &mut client, now).unwrap();
+        assert_eq!(client.poll_timeout(), None);
+
+        send_and_receive(&mut client, &mut server, b"after client update");
+        send_and_receive(&mut server, &mut client, b"to client");
+
+        server.refresh_traffic_keys().unwrap();
+        transfer(&mut server, &mut client, now).unwrap();
+        transfer(&mut client, &mut server, now).unwrap();
+        send_and_receive(&mut server, &mut client, b"after server update");
+        send_and_receive(&mut client, &mut server, b"to server");
+    }
+}
+
+#[test]
+fn dtls_close_notify() {
+    let (mut client, mut server) = make_pair(
+        make_client_config(KeyType::EcdsaP256),
+        make_server_config(KeyType::EcdsaP256),
+    );
+    do_handshake(&mut client, &mut server);
+
+    client.send_close_notify();
+    transfer(&mut client, &mut server, Instant::now()).unwrap();
+    assert!(server.has_received_close_notify());
+    assert_eq!(
+        client.send_application_data(b"too late"),
+        Err(Error::HandshakeNotComplete)
+    );
+}
+
+#[test]
+fn dtls_fatal_alert_is_reported() {
+    let mut client_config = make_client_config(KeyType::EcdsaP256);
+    client_config.alpn_protocols = vec![b"foo".to_vec()];
+    let mut server_config = make_server_config(KeyType::EcdsaP256);
+    server_config.alpn_protocols = vec![b"bar".to_vec()];
+    let (mut client, mut server) = make_pair(client_config, server_config);
+
+    let now = Instant::now();
+    assert_eq!(
+        transfer(&mut client, &mut server, now),
+        Err(Error::NoApplicationProtocol)
+    );
+    assert_eq!(
+        transfer(&mut server, &mut client, now),
+        Err(Error::AlertReceived(
+            AlertDescription::NoApplicationProtocol
+        ))
+    );
+    assert_eq!(
+        client.process_datagram(&mut []),
+        Err(Error::AlertReceived(
+            AlertDescription::NoApplicationProtocol
+        ))
+    );
+}
+
+#[test]
+fn dtls_resumption() {
+    let client_config = Arc::new(make_client_config(KeyType::EcdsaP256));
+    let server_config = Arc::new(make_server_config(KeyType::EcdsaP256));
+
+    for expected in [HandshakeKind::Full, HandshakeKind::Resumed] {
+        let mut client =
+            DtlsClientConnection::new(client_config.clone(), server_name("localhost")).unwrap();
+        let mut server = DtlsServerConnection::new(server_config.clone()).unwrap();
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(expected));
+        assert_eq!(server.handshake_kind(), Some(expected));
+    }
+}
+
+#[test]
+fn dtls_exporter() {
+    let (mut client, mut server) = make_pair(
+        make_client_config(KeyType::EcdsaP256),
+        make_server_config(KeyType::EcdsaP256),
+    );
+    do_handshake(&mut client, &mut server);
+
+    let client_exported = client
+        .export_keying_material([0u8; 32], b"label", Some(b"context"))
+        .unwrap();
+    let server_exported = server
+        .export_keying_material([0u8; 32], b"label", Some(b"context"))
+        .unwrap();
+    assert_eq!(client_exported, server_exported);
+}
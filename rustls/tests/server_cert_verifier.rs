@@ -6,6 +6,7 @@ use super::*;
 
 mod common;
 use std::sync::Arc;
+use std::time::Duration;
 
 use common::{
     client_config_builder, client_config_builder_with_versions, do_handshake,
@@ -13,19 +14,22 @@ use common::{
     make_pair_for_arc_configs, make_server_config, server_config_builder, transfer_altered,
     Altered, ErrorFromPeer, KeyType, MockServerVerifier, ALL_KEY_TYPES,
 };
-use pki_types::{CertificateDer, ServerName};
+use pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::client::ct::{CtLog, DistinctOperatorsPolicy};
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
-use rustls::client::WebPkiServerVerifier;
+use rustls::client::{OcspStaplingPolicy, WebPkiServerVerifier};
+use rustls::delegated_credential::{DelegatedCredential, DelegatedKey};
+use rustls::internal::msgs::codec::Codec;
 use rustls::internal::msgs::handshake::{ClientExtension, HandshakePayload};
 use rustls::internal::msgs::message::{Message, MessagePayload};
 use rustls::server::{ClientHello, ResolvesServerCert};
-use rustls::sign::CertifiedKey;
+use rustls::sign::{CertifiedKey, Signer, SigningKey};
 use rustls::version::{TLS12, TLS13};
 use rustls::{
     AlertDescription, CertificateError, DigitallySignedStruct, DistinguishedName, Error,
-    InvalidMessage, RootCertStore,
+    InvalidMessage, PeerIncompatible, RootCertStore, SignatureAlgorithm, SignatureScheme,
 };
-use x509_parser::prelude::FromDer;
+use x509_parser::prelude::{FromDer, X509Certificate};
 use x509_parser::x509::X509Name;
 
 #[test]
@@ -381,3 +385,1104 @@ impl ServerCertVerifier for ServerCertVerifierWithCasExt {
         Some(&self.ca_names)
     }
 }
+
+#[test]
+fn ocsp_stapling_is_ignored_by_default() {
+    let kt = KeyType::EcdsaP256;
+    let verifier = WebPkiServerVerifier::builder_with_provider(
+        ca_roots(kt),
+        Arc::new(provider::default_provider()),
+    )
+    .build()
+    .unwrap();
+
+    verify_with_ocsp(&verifier, kt, b"not an ocsp response").unwrap();
+}
+
+#[test]
+fn ocsp_stapling_policy_controls_missing_response() {
+    let kt = KeyType::EcdsaP256;
+    verify_with_ocsp(&ocsp_verifier(kt, OcspStaplingPolicy::Prefer), kt, &[]).unwrap();
+    assert_eq!(
+        verify_with_ocsp(&ocsp_verifier(kt, OcspStaplingPolicy::Require), kt, &[]),
+        Err(Error::InvalidCertificate(
+            CertificateError::MissingOcspResponse
+        ))
+    );
+}
+
+#[test]
+fn ocsp_stapled_response_signed_by_issuer() {
+    let kt = KeyType::EcdsaP256;
+    let issuer_key = load_signing_key(kt.get_intermediate_key());
+
+    for policy in [OcspStaplingPolicy::Prefer, OcspStaplingPolicy::Require] {
+        let verifier = ocsp_verifier(kt, policy);
+
+        let good = OcspResponse::new(&kt.get_chain()[0], &kt.get_chain()[1]).sign(&*issuer_key);
+        verify_with_ocsp(&verifier, kt, &good).unwrap();
+
+        let revoked = OcspResponse {
+            status: OcspStatus::Revoked,
+            ..OcspResponse::new(&kt.get_chain()[0], &kt.get_chain()[1])
+        }
+        .sign(&*issuer_key);
+        assert_eq!(
+            verify_with_ocsp(&verifier, kt, &revoked),
+            Err(Error::InvalidCertificate(
+                CertificateError::RevokedByOcspResponse
+            ))
+        );
+
+        let unknown = OcspResponse {
+            status: OcspStatus::Unknown,
+            ..OcspResponse::new(&kt.get_chain()[0], &kt.get_chain()[1])
+        }
+        .sign(&*issuer_key);
+        assert_eq!(
+            verify_with_ocsp(&verifier, kt, &unknown),
+            Err(Error::InvalidCertificate(
+                CertificateError::UnknownRevocationStatus
+            ))
+        );
+    }
+}
+
+#[test]
+fn ocsp_stale_response_is_rejected() {
+    let kt = KeyType::EcdsaP256;
+    let issuer_key = load_signing_key(kt.get_intermediate_key());
+    let now = UnixTime::now().as_secs();
+
+    let past_next_update = OcspResponse {
+        this_update: now - 2 * DAY,
+        next_update: Some(now - DAY),
+        ..OcspResponse::new(&kt.get_chain()[0], &kt.get_chain()[1])
+    }
+    .sign(&*issuer_key);
+    assert_eq!(
+        verify_with_ocsp(
+            &ocsp_verifier(kt, OcspStaplingPolicy::Require),
+            kt,
+            &past_next_update
+        ),
+        Err(Error::InvalidCertificate(
+            CertificateError::ExpiredOcspResponse
+        ))
+    );
+
+    let old = OcspResponse {
+        this_update: now - 2 * DAY,
+        next_update: None,
+        ..OcspResponse::new(&kt.get_chain()[0], &kt.get_chain()[1])
+    }
+    .sign(&*issuer_key);
+    let verifier = ocsp_verifier(kt, OcspStaplingPolicy::Require);
+    verify_with_ocsp(&verifier, kt, &old).unwrap();
+
+    let verifier = WebPkiServerVerifier::builder_with_provider(
+        ca_roots(kt),
+        Arc::new(provider::default_provider()),
+    )
+    .with_ocsp_stapling_policy(OcspStaplingPolicy::Require)
+    .with_ocsp_max_age(Duration::from_secs(DAY))
+    .build()
+    .unwrap();
+    assert_eq!(
+        verify_with_ocsp(&verifier, kt, &old),
+        Err(Error::InvalidCertificate(
+            CertificateError::ExpiredOcspResponse
+        ))
+    );
+
+    let future = OcspResponse {
+        this_update: now + DAY,
+        ..OcspResponse::new(&kt.get_chain()[0], &kt.get_chain()[1])
+    }
+    .sign(&*issuer_key);
+    assert_eq!(
+        verify_with_ocsp(&verifier, kt, &future),
+        Err(Error::InvalidCertificate(
+            CertificateError::InvalidOcspResponse
+        ))
+    );
+}
+
+#[test]
+fn ocsp_invalid_response_is_rejected() {
+    let kt = KeyType::EcdsaP256;
+    let verifier = ocsp_verifier(kt, OcspStaplingPolicy::Prefer);
+    let invalid = Err(Error::InvalidCertificate(
+        CertificateError::InvalidOcspResponse,
+    ));
+
+    assert_eq!(
+        verify_with_ocsp(&verifier, kt, b"not an ocsp response"),
+        invalid
+    );
+
+    // Signed by the end entity, rather than its issuer.
+    let wrong_signer = OcspResponse::new(&kt.get_chain()[0], &kt.get_chain()[1])
+        .sign(&*load_signing_key(kt.get_key()));
+    assert_eq!(verify_with_ocsp(&verifier, kt, &wrong_signer), invalid);
+
+    // Covering a different certificate.
+    let wrong_serial = OcspResponse {
+        serial: vec![0x01, 0x02, 0x03],
+        ..OcspResponse::new(&kt.get_chain()[0], &kt.get_chain()[1])
+    }
+    .sign(&*load_signing_key(kt.get_intermediate_key()));
+    assert_eq!(verify_with_ocsp(&verifier, kt, &wrong_serial), invalid);
+
+    // Covering a certificate with the same serial, but from a different issuer.
+    let wrong_issuer = OcspResponse::new(&kt.get_chain()[0], &KeyType::Rsa2048.get_chain()[1])
+        .sign(&*load_signing_key(kt.get_intermediate_key()));
+    assert_eq!(verify_with_ocsp(&verifier, kt, &wrong_issuer), invalid);
+
+    // Tampered with after signing.
+    let mut tampered = OcspResponse::new(&kt.get_chain()[0], &kt.get_chain()[1])
+        .sign(&*load_signing_key(kt.get_intermediate_key()));
+    let good_status = tampered
+        .windows(2)
+        .position(|w| w == [0x80, 0x00])
+        .unwrap();
+    tampered[good_status] = 0x82;
+    assert_eq!(verify_with_ocsp(&verifier, kt, &tampered), invalid);
+}
+
+#[test]
+fn ocsp_stapled_response_signed_by_delegated_responder() {
+    let ca_key = rcgen::KeyPair::generate().unwrap();
+    let mut ca_params = rcgen::CertificateParams::new(vec![]).unwrap();
+    ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+
+    let ee_key = rcgen::KeyPair::generate().unwrap();
+    let ee = rcgen::CertificateParams::new(vec!["localhost".into()])
+        .unwrap()
+        .signed_by(&ee_key, &ca, &ca_key)
+        .unwrap();
+
+    let responder = |purposes| {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(vec![]).unwrap();
+        params.extended_key_usages = purposes;
+        let cert = params
+            .signed_by(&key, &ca, &ca_key)
+            .unwrap();
+        (
+            cert.der().clone(),
+            load_signing_key(PrivatePkcs8KeyDer::from(key.serialize_der()).into()),
+        )
+    };
+
+    let mut roots = RootCertStore::empty();
+    roots.add(ca.der().clone()).unwrap();
+    let verifier = WebPkiServerVerifier::builder_with_provider(
+        Arc::new(roots),
+        Arc::new(provider::default_provider()),
+    )
+    .with_ocsp_stapling_policy(OcspStaplingPolicy::Require)
+    .build()
+    .unwrap();
+
+    let verify = |response: &[u8]| {
+        verifier.verify_server_cert(
+            ee.der(),
+            &[],
+            &ServerName::try_from("localhost").unwrap(),
+            response,
+            UnixTime::now(),
+        )
+    };
+
+    // Signed by the issuing CA directly.
+    let by_issuer = OcspResponse::new(ee.der(), ca.der()).sign(&*load_signing_key(
+        PrivatePkcs8KeyDer::from(ca_key.serialize_der()).into(),
+    ));
+    verify(&by_issuer).unwrap();
+
+    let (responder_cert, responder_key) =
+        responder(vec![rcgen::ExtendedKeyUsagePurpose::OcspSigning]);
+    let by_responder = OcspResponse {
+        certs: vec![responder_cert],
+        ..OcspResponse::new(ee.der(), ca.der())
+    }
+    .sign(&*responder_key);
+    verify(&by_responder).unwrap();
+
+    // A responder certificate without the OCSP signing extended key usage is not authorized.
+    let (responder_cert, responder_key) =
+        responder(vec![rcgen::ExtendedKeyUsagePurpose::ServerAuth]);
+    let by_unauthorized = OcspResponse {
+        certs: vec![responder_cert],
+        ..OcspResponse::new(ee.der(), ca.der())
+    }
+    .sign(&*responder_key);
+    assert_eq!(
+        verify(&by_unauthorized).map(|_| ()),
+        Err(Error::InvalidCertificate(
+            CertificateError::InvalidOcspResponse
+        ))
+    );
+}
+
+#[test]
+fn client_rejects_revoked_stapled_ocsp_response() {
+    let kt = KeyType::EcdsaP256;
+    let issuer_key = load_signing_key(kt.get_intermediate_key());
+
+    for (status, expected) in [
+        (OcspStatus::Good, None),
+        (
+            OcspStatus::Revoked,
+            Some(CertificateError::RevokedByOcspResponse),
+        ),
+    ] {
+        let ocsp_response = OcspResponse {
+            status,
+            ..OcspResponse::new(&kt.get_chain()[0], &kt.get_chain()[1])
+        }
+        .sign(&*issuer_key);
+
+        let server_config = Arc::new(
+            server_config_builder()
+                .with_no_client_auth()
+                .with_single_cert_with_ocsp(kt.get_chain(), kt.get_key(), ocsp_response)
+                .unwrap(),
+        );
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = client_config_builder_with_versions(&[version])
+                .dangerous()
+                .with_custom_certificate_verifier(ocsp_verifier(kt, OcspStaplingPolicy::Require))
+                .with_no_client_auth();
+
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            match &expected {
+                None => {
+                    do_handshake(&mut client, &mut server);
+                }
+                Some(err) => {
+                    let errs = do_handshake_until_both_error(&mut client, &mut server);
+                    assert_eq!(
+                        errs,
+                        Err(vec![
+                            ErrorFromPeer::Client(Error::InvalidCertificate(err.clone())),
+                            ErrorFromPeer::Server(Error::AlertReceived(
+                                AlertDescription::CertificateRevoked
+                            )),
+                        ]),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn ct_scts_in_tls_extension() {
+    let kt = KeyType::EcdsaP256;
+    let logs = [
+        CtTestLog::new(1, "Operator A"),
+        CtTestLog::new(2, "Operator B"),
+    ];
+
+    let now_ms = UnixTime::now().as_secs() * 1000;
+    let entry = x509_entry(&kt.get_chain()[0]);
+    let sct_list = encode_sct_list(&[
+        logs[0].sct(now_ms - 1000, &entry),
+        logs[1].sct(now_ms - 2000, &entry),
+    ]);
+
+    let mut certified_key = CertifiedKey::new(kt.get_chain(), load_signing_key(kt.get_key()));
+    certified_key.sct_list = Some(sct_list);
+    let server_config = Arc::new(
+        server_config_builder()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(AlwaysResolves(Arc::new(certified_key)))),
+    );
+
+    for version in rustls::ALL_VERSIONS {
+        for (policy, expected) in [
+            (DistinctOperatorsPolicy::new(2, 2), None),
+            (
+                DistinctOperatorsPolicy::new(3, 1),
+                Some(CertificateError::InsufficientSignedCertificateTimestamps),
+            ),
+        ] {
+            let client_config = client_config_builder_with_versions(&[version])
+                .dangerous()
+                .with_custom_certificate_verifier(ct_verifier(kt, &logs, policy))
+                .with_no_client_auth();
+
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            match expected {
+                None => {
+                    do_handshake(&mut client, &mut server);
+                }
+                Some(err) => {
+                    let errs = do_handshake_until_both_error(&mut client, &mut server);
+                    assert_eq!(
+                        errs,
+                        Err(vec![
+                            ErrorFromPeer::Client(Error::InvalidCertificate(err)),
+                            ErrorFromPeer::Server(Error::AlertReceived(
+                                AlertDescription::BadCertificate
+                            )),
+                        ]),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn ct_sct_validation() {
+    let kt = KeyType::EcdsaP256;
+    let logs = [
+        CtTestLog::new(1, "Operator A"),
+        CtTestLog::new(2, "Operator A"),
+    ];
+    let end_entity = &kt.get_chain()[0];
+    let entry = x509_entry(end_entity);
+    let now_ms = UnixTime::now().as_secs() * 1000;
+
+    let verify = |policy, scts: &[Vec<u8>]| {
+        let chain = kt.get_chain();
+        ct_verifier(kt, &logs, policy)
+            .verify_server_cert_with_scts(
+                &chain[0],
+                &chain[1..],
+                &ServerName::try_from("testserver.com").unwrap(),
+                &[],
+                &encode_sct_list(scts),
+                UnixTime::now(),
+            )
+            .map(|_| ())
+    };
+    let insufficient = Err(Error::InvalidCertificate(
+        CertificateError::InsufficientSignedCertificateTimestamps,
+    ));
+
+    let good = logs[0].sct(now_ms, &entry);
+    verify(
+        DistinctOperatorsPolicy::new(1, 1),
+        std::slice::from_ref(&good),
+    )
+    .unwrap();
+
+    // Two SCTs from the same log only count once.
+    let again = logs[0].sct(now_ms - 1000, &entry);
+    assert_eq!(
+        verify(DistinctOperatorsPolicy::new(2, 1), &[good.clone(), again]),
+        insufficient
+    );
+
+    // Two logs with the same operator.
+    let other_log = logs[1].sct(now_ms, &entry);
+    verify(
+        DistinctOperatorsPolicy::new(2, 1),
+        &[good.clone(), other_log.clone()],
+    )
+    .unwrap();
+    assert_eq!(
+        verify(DistinctOperatorsPolicy::new(2, 2), &[good, other_log]),
+        insufficient
+    );
+
+    // SCTs which do not verify are ignored.
+    let policy = || DistinctOperatorsPolicy::new(1, 1);
+    let future = logs[0].sct(now_ms + 60_000, &entry);
+    assert_eq!(verify(policy(), &[future]), insufficient);
+
+    let mut bad_signature = logs[0].sct(now_ms, &entry);
+    *bad_signature.last_mut().unwrap() ^= 1;
+    assert_eq!(verify(policy(), &[bad_signature]), insufficient);
+
+    let other_cert = logs[0].sct(now_ms, &x509_entry(&kt.get_chain()[1]));
+    assert_eq!(verify(policy(), &[other_cert]), insufficient);
+
+    let unknown_log = CtTestLog::new(3, "Operator C").sct(now_ms, &entry);
+    assert_eq!(verify(policy(), &[unknown_log]), insufficient);
+
+    // A retired log's SCTs are accepted only if issued before it was retired.
+    let retired = CtTestLog {
+        not_after: Some(UnixTime::since_unix_epoch(Duration::from_millis(
+            now_ms - 1000,
+        ))),
+        ..CtTestLog::new(1, "Operator A")
+    };
+    let verify_retired = |sct: Vec<u8>| {
+        let chain = kt.get_chain();
+        WebPkiServerVerifier::builder_with_provider(
+            ca_roots(kt),
+            Arc::new(provider::default_provider()),
+        )
+        .with_certificate_transparency([retired.log()], Arc::new(policy()))
+        .build()
+        .unwrap()
+        .verify_server_cert_with_scts(
+            &chain[0],
+            &chain[1..],
+            &ServerName::try_from("testserver.com").unwrap(),
+            &[],
+            &encode_sct_list(&[sct]),
+            UnixTime::now(),
+        )
+        .map(|_| ())
+    };
+    verify_retired(retired.sct(now_ms - 2000, &entry)).unwrap();
+    assert_eq!(
+        verify_retired(retired.sct(now_ms - 1000, &entry)),
+        insufficient
+    );
+}
+
+#[test]
+fn ct_embedded_scts() {
+    let ca_key = rcgen::KeyPair::generate().unwrap();
+    let mut ca_params = rcgen::CertificateParams::new(vec![]).unwrap();
+    ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+
+    let ee_key = rcgen::KeyPair::generate().unwrap();
+    let mut ee_params = rcgen::CertificateParams::new(vec!["localhost".into()]).unwrap();
+    ee_params.serial_number = Some(rcgen::SerialNumber::from(42u64));
+    let precert = ee_params
+        .clone()
+        .signed_by(&ee_key, &ca, &ca_key)
+        .unwrap();
+    let (_, parsed) = X509Certificate::from_der(precert.der()).unwrap();
+    let issuer_key_hash = provider::cipher_suite::TLS13_AES_128_GCM_SHA256
+        .tls13()
+        .unwrap()
+        .common
+        .hash_provider
+        .hash(&ca_key.public_key_der());
+    let entry = precert_entry(issuer_key_hash.as_ref(), parsed.tbs_certificate.as_ref());
+
+    let logs = [
+        CtTestLog::new(1, "Operator A"),
+        CtTestLog::new(2, "Operator B"),
+    ];
+    let now_ms = UnixTime::now().as_secs() * 1000;
+    let sct_list = encode_sct_list(&[logs[0].sct(now_ms, &entry), logs[1].sct(now_ms, &entry)]);
+    ee_params
+        .custom_extensions
+        .push(rcgen::CustomExtension::from_oid_content(
+            &[1, 3, 6, 1, 4, 1, 11129, 2, 4, 2],
+            der(0x04, &sct_list),
+        ));
+    let ee = ee_params
+        .signed_by(&ee_key, &ca, &ca_key)
+        .unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(ca.der().clone()).unwrap();
+    let verify = |end_entity: &CertificateDer<'_>, policy| {
+        WebPkiServerVerifier::builder_with_provider(
+            Arc::new(roots.clone()),
+            Arc::new(provider::default_provider()),
+        )
+        .with_certificate_transparency(logs.iter().map(CtTestLog::log), Arc::new(policy))
+        .build()
+        .unwrap()
+        .verify_server_cert(
+            end_entity,
+            &[],
+            &ServerName::try_from("localhost").unwrap(),
+            &[],
+            UnixTime::now(),
+        )
+        .map(|_| ())
+    };
+
+    verify(ee.der(), DistinctOperatorsPolicy::new(2, 2)).unwrap();
+    assert_eq!(
+        verify(ee.der(), DistinctOperatorsPolicy::new(3, 2)),
+        Err(Error::InvalidCertificate(
+            CertificateError::InsufficientSignedCertificateTimestamps
+        ))
+    );
+    assert_eq!(
+        verify(precert.der(), DistinctOperatorsPolicy::new(1, 1)),
+        Err(Error::InvalidCertificate(
+            CertificateError::InsufficientSignedCertificateTimestamps
+        ))
+    );
+}
+
+#[test]
+fn ct_extension_not_requested_by_default() {
+    let kt = KeyType::EcdsaP256;
+    let mut certified_key = CertifiedKey::new(kt.get_chain(), load_signing_key(kt.get_key()));
+    certified_key.sct_list = Some(encode_sct_list(&[CtTestLog::new(1, "Operator A").sct(
+        UnixTime::now().as_secs() * 1000,
+        &x509_entry(&kt.get_chain()[0]),
+    )]));
+    let server_config = Arc::new(
+        server_config_builder()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(AlwaysResolves(Arc::new(certified_key)))),
+    );
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(kt, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn delegated_credential_is_used_when_offered() {
+    let pki = DelegationTestPki::new(true);
+    let certified_key = pki.certified_key();
+    let dc_key = load_signing_key(PrivatePkcs8KeyDer::from(pki.dc_key.serialize_der()).into());
+    let delegated = DelegatedKey::delegate(
+        &certified_key,
+        dc_key,
+        SignatureScheme::ECDSA_NISTP256_SHA256,
+        Duration::from_secs(DAY),
+        UnixTime::now(),
+    )
+    .unwrap();
+
+    // The certificate's own key cannot sign handshakes, so these only succeed
+    // if the delegated credential is used.
+    let certified_key = CertifiedKey {
+        key: Arc::new(UnusableKey),
+        delegated_credential: Some(delegated),
+        ..certified_key
+    };
+    let server_config = Arc::new(
+        server_config_builder()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(AlwaysResolves(Arc::new(certified_key)))),
+    );
+
+    let client_config = Arc::new(
+        client_config_builder_with_versions(&[&TLS13])
+            .dangerous()
+            .with_custom_certificate_verifier(pki.verifier(true))
+            .with_no_client_auth(),
+    );
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    // Without opting in, the client does not offer the extension.
+    let client_config = Arc::new(
+        client_config_builder_with_versions(&[&TLS13])
+            .dangerous()
+            .with_custom_certificate_verifier(pki.verifier(false))
+            .with_no_client_auth(),
+    );
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerIncompatible(
+            PeerIncompatible::NoSignatureSchemesInCommon
+        )))
+    );
+}
+
+#[test]
+fn delegated_credential_verification() {
+    let pki = DelegationTestPki::new(true);
+    let certified_key = pki.certified_key();
+    let dc_key = load_signing_key(PrivatePkcs8KeyDer::from(pki.dc_key.serialize_der()).into());
+    let now = UnixTime::now();
+    let delegated = DelegatedKey::delegate(
+        &certified_key,
+        dc_key.clone(),
+        SignatureScheme::ECDSA_NISTP256_SHA256,
+        Duration::from_secs(DAY),
+        now,
+    )
+    .unwrap();
+
+    let message = b"hello";
+    let signature = dc_key
+        .choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256])
+        .unwrap()
+        .sign(message)
+        .unwrap();
+    let dss = DigitallySignedStruct::read_bytes(
+        &[
+            &u16::from(SignatureScheme::ECDSA_NISTP256_SHA256).to_be_bytes()[..],
+            &(signature.len() as u16).to_be_bytes(),
+            &signature,
+        ]
+        .concat(),
+    )
+    .unwrap();
+    let end_entity = pki.ee.der();
+    let verify = |verifier: &WebPkiServerVerifier,
+                  end_entity: &CertificateDer<'_>,
+                  credential: &DelegatedCredential,
+                  at: u64| {
+        verifier
+            .verify_tls13_signature_with_delegated_credential(
+                message,
+                end_entity,
+                credential,
+                &dss,
+                UnixTime::since_unix_epoch(Duration::from_secs(at)),
+            )
+            .map(|_| ())
+    };
+
+    let verifier = pki.verifier(true);
+    let credential = delegated.credential();
+    verify(&verifier, end_entity, credential, now.as_secs()).unwrap();
+
+    assert_eq!(
+        verify(&verifier, end_entity, credential, now.as_secs() + DAY),
+        Err(Error::InvalidCertificate(
+            CertificateError::DelegatedCredentialExpired
+        ))
+    );
+    assert_eq!(
+        verify(&verifier, end_entity, credential, now.as_secs() - 7 * DAY),
+        Err(Error::InvalidCertificate(
+            CertificateError::DelegatedCredentialValidityTooLong
+        ))
+    );
+
+    let without_usage = DelegationTestPki::new(false);
+    assert_eq!(
+        verify(&verifier, without_usage.ee.der(), credential, now.as_secs()),
+        Err(Error::InvalidCertificate(
+            CertificateError::DelegationNotPermitted
+        ))
+    );
+
+    let mut tampered = credential.clone();
+    tampered.valid_time += 1;
+    assert_eq!(
+        verify(&verifier, end_entity, &tampered, now.as_secs()),
+        Err(Error::InvalidCertificate(CertificateError::BadSignature))
+    );
+
+    let round_tripped = DelegatedCredential::from_bytes(&credential.to_bytes()).unwrap();
+    verify(&verifier, end_entity, &round_tripped, now.as_secs()).unwrap();
+
+    assert!(verify(&pki.verifier(false), end_entity, credential, now.as_secs()).is_err());
+
+    assert!(matches!(
+        DelegatedKey::delegate(
+            &without_usage.certified_key(),
+            dc_key,
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            Duration::from_secs(DAY),
+            now,
+        ),
+        Err(Error::InvalidCertificate(
+            CertificateError::DelegationNotPermitted
+        ))
+    ));
+}
+
+/// A CA, and an end-entity certificate it issued for `localhost`.
+struct DelegationTestPki {
+    ca: rcgen::Certificate,
+    ee: rcgen::Certificate,
+    ee_key: rcgen::KeyPair,
+    dc_key: rcgen::KeyPair,
+}
+
+impl DelegationTestPki {
+    fn new(delegation_usage: bool) -> Self {
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let mut ca_params = rcgen::CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let ee_key = rcgen::KeyPair::generate().unwrap();
+        let mut ee_params = rcgen::CertificateParams::new(vec!["localhost".into()]).unwrap();
+        if delegation_usage {
+            ee_params
+                .custom_extensions
+                .push(rcgen::CustomExtension::from_oid_content(
+                    &[1, 3, 6, 1, 4, 1, 44363, 44],
+                    vec![0x05, 0x00],
+                ));
+        }
+        let ee = ee_params
+            .signed_by(&ee_key, &ca, &ca_key)
+            .unwrap();
+
+        Self {
+            ca,
+            ee,
+            ee_key,
+            dc_key: rcgen::KeyPair::generate().unwrap(),
+        }
+    }
+
+    fn certified_key(&self) -> CertifiedKey {
+        CertifiedKey::new(
+            vec![self.ee.der().clone()],
+            load_signing_key(PrivatePkcs8KeyDer::from(self.ee_key.serialize_der()).into()),
+        )
+    }
+
+    fn verifier(&self, allow_delegated_credentials: bool) -> Arc<WebPkiServerVerifier> {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(self.ca.der().clone())
+            .unwrap();
+        let builder = WebPkiServerVerifier::builder_with_provider(
+            Arc::new(roots),
+            Arc::new(provider::default_provider()),
+        );
+        match allow_delegated_credentials {
+            true => builder.allow_delegated_credentials(),
+            false => builder,
+        }
+        .build()
+        .unwrap()
+    }
+}
+
+/// A key that cannot sign anything.
+#[derive(Debug)]
+struct UnusableKey;
+
+impl SigningKey for UnusableKey {
+    fn choose_scheme(&self, _offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        None
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::ECDSA
+    }
+}
+
+#[derive(Debug)]
+struct AlwaysResolves(Arc<CertifiedKey>);
+
+impl ResolvesServerCert for AlwaysResolves {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+}
+
+fn ct_verifier(
+    kt: KeyType,
+    logs: &[CtTestLog],
+    policy: DistinctOperatorsPolicy,
+) -> Arc<WebPkiServerVerifier> {
+    WebPkiServerVerifier::builder_with_provider(
+        ca_roots(kt),
+        Arc::new(provider::default_provider()),
+    )
+    .with_certificate_transparency(logs.iter().map(CtTestLog::log), Arc::new(policy))
+    .build()
+    .unwrap()
+}
+
+/// A CT log for testing, able to issue SCTs.
+struct CtTestLog {
+    id: [u8; 32],
+    operator: &'static str,
+    key: Arc<rcgen::KeyPair>,
+    not_after: Option<UnixTime>,
+}
+
+impl CtTestLog {
+    fn new(id: u8, operator: &'static str) -> Self {
+        Self {
+            id: [id; 32],
+            operator,
+            key: Arc::new(rcgen::KeyPair::generate().unwrap()),
+            not_after: None,
+        }
+    }
+
+    fn log(&self) -> CtLog {
+        CtLog {
+            id: self.id,
+            key: self.key.public_key_der().into(),
+            operator: self.operator.into(),
+            not_after: self.not_after,
+        }
+    }
+
+    /// Issue a `SerializedSCT` for `entry` at `timestamp` (in milliseconds).
+    fn sct(&self, timestamp: u64, entry: &[u8]) -> Vec<u8> {
+        let message = [&[0, 0][..], &timestamp.to_be_bytes(), entry, &[0, 0]].concat();
+        let signature = load_signing_key(PrivatePkcs8KeyDer::from(self.key.serialize_der()).into())
+            .choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256])
+            .unwrap()
+            .sign(&message)
+            .unwrap();
+
+        [
+            &[0][..],
+            &self.id,
+            &timestamp.to_be_bytes(),
+            &[0, 0],
+            &u16::from(SignatureScheme::ECDSA_NISTP256_SHA256).to_be_bytes(),
+            &(signature.len() as u16).to_be_bytes(),
+            &signature,
+        ]
+        .concat()
+    }
+}
+
+fn x509_entry(cert: &[u8]) -> Vec<u8> {
+    [&[0, 0][..], &u24_bytes(cert.len()), cert].concat()
+}
+
+fn precert_entry(issuer_key_hash: &[u8], tbs_certificate: &[u8]) -> Vec<u8> {
+    [
+        &[0, 1][..],
+        issuer_key_hash,
+        &u24_bytes(tbs_certificate.len()),
+        tbs_certificate,
+    ]
+    .concat()
+}
+
+fn encode_sct_list(scts: &[Vec<u8>]) -> Vec<u8> {
+    let body = scts
+        .iter()
+        .flat_map(|sct| [&(sct.len() as u16).to_be_bytes()[..], sct].concat())
+        .collect::<Vec<u8>>();
+    [&(body.len() as u16).to_be_bytes()[..], &body].concat()
+}
+
+fn u24_bytes(len: usize) -> [u8; 3] {
+    let [_, a, b, c] = (len as u32).to_be_bytes();
+    [a, b, c]
+}
+
+const DAY: u64 = 24 * 60 * 60;
+
+fn ca_roots(kt: KeyType) -> Arc<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    roots.add(kt.ca_cert()).unwrap();
+    Arc::new(roots)
+}
+
+fn ocsp_verifier(kt: KeyType, policy: OcspStaplingPolicy) -> Arc<WebPkiServerVerifier> {
+    WebPkiServerVerifier::builder_with_provider(
+        ca_roots(kt),
+        Arc::new(provider::default_provider()),
+    )
+    .with_ocsp_stapling_policy(policy)
+    .build()
+    .unwrap()
+}
+
+fn verify_with_ocsp(
+    verifier: &WebPkiServerVerifier,
+    kt: KeyType,
+    ocsp_response: &[u8],
+) -> Result<(), Error> {
+    let chain = kt.get_chain();
+    verifier
+        .verify_server_cert(
+            &chain[0],
+            &chain[1..],
+            &ServerName::try_from("testserver.com").unwrap(),
+            ocsp_response,
+            UnixTime::now(),
+        )
+        .map(|_| ())
+}
+
+fn load_signing_key(key: PrivateKeyDer<'static>) -> Arc<dyn SigningKey> {
+    provider::default_provider()
+        .key_provider
+        .load_private_key(key)
+        .unwrap()
+}
+
+#[derive(Clone, Copy)]
+enum OcspStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+/// Builds a DER-encoded `OCSPResponse`, signed with ECDSA P-256 and SHA-256.
+struct OcspResponse {
+    issuer_name_hash: Vec<u8>,
+    issuer_key_hash: Vec<u8>,
+    serial: Vec<u8>,
+    status: OcspStatus,
+    this_update: u64,
+    next_update: Option<u64>,
+    certs: Vec<CertificateDer<'static>>,
+}
+
+impl OcspResponse {
+    /// A response about `cert`, identified by SHA-256 hashes of `issuer`'s name and key.
+    fn new(cert: &CertificateDer<'_>, issuer: &CertificateDer<'_>) -> Self {
+        let now = UnixTime::now().as_secs();
+        let (_, issuer) = X509Certificate::from_der(issuer).unwrap();
+        let sha256 = provider::cipher_suite::TLS13_AES_128_GCM_SHA256
+            .tls13()
+            .unwrap()
+            .common
+            .hash_provider;
+        Self {
+            issuer_name_hash: sha256
+                .hash(issuer.subject().as_raw())
+                .as_ref()
+                .to_vec(),
+            issuer_key_hash: sha256
+                .hash(
+                    &issuer
+                        .public_key()
+                        .subject_public_key
+                        .data,
+                )
+                .as_ref()
+                .to_vec(),
+            serial: X509Certificate::from_der(cert)
+                .unwrap()
+                .1
+                .raw_serial()
+                .to_vec(),
+            status: OcspStatus::Good,
+            this_update: now - 60 * 60,
+            next_update: Some(now + DAY),
+            certs: vec![],
+        }
+    }
+
+    fn sign(&self, key: &dyn SigningKey) -> Vec<u8> {
+        // id-sha256
+        let sha256 = der(
+            0x30,
+            &[
+                0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05, 0x00,
+            ],
+        );
+        let cert_id = der(
+            0x30,
+            &[
+                sha256,
+                der(0x04, &self.issuer_name_hash),
+                der(0x04, &self.issuer_key_hash),
+                der(0x02, &self.serial),
+            ]
+            .concat(),
+        );
+        let status = match self.status {
+            OcspStatus::Good => vec![0x80, 0x00],
+            OcspStatus::Revoked => der(0xa1, &generalized_time(self.this_update)),
+            OcspStatus::Unknown => vec![0x82, 0x00],
+        };
+        let next_update = self
+            .next_update
+            .map(|t| der(0xa0, &generalized_time(t)))
+            .unwrap_or_default();
+        let single = der(
+            0x30,
+            &[
+                cert_id,
+                status,
+                generalized_time(self.this_update),
+                next_update,
+            ]
+            .concat(),
+        );
+
+        let tbs = der(
+            0x30,
+            &[
+                der(0xa2, &der(0x04, &[0; 20])),
+                generalized_time(self.this_update),
+                der(0x30, &single),
+            ]
+            .concat(),
+        );
+
+        let signature = key
+            .choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256])
+            .unwrap()
+            .sign(&tbs)
+            .unwrap();
+
+        let certs = match self.certs.is_empty() {
+            true => vec![],
+            false => der(
+                0xa0,
+                &der(
+                    0x30,
+                    &self
+                        .certs
+                        .iter()
+                        .flat_map(|c| c.to_vec())
+                        .collect::<Vec<_>>(),
+                ),
+            ),
+        };
+
+        // ecdsa-with-SHA256
+        let signature_alg = der(
+            0x30,
+            &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02],
+        );
+        let basic = der(
+            0x30,
+            &[
+                tbs,
+                signature_alg,
+                der(0x03, &[&[0][..], &signature].concat()),
+                certs,
+            ]
+            .concat(),
+        );
+
+        // id-pkix-ocsp-basic
+        let response_type = der(
+            0x06,
+            &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01],
+        );
+        der(
+            0x30,
+            &[
+                der(0x0a, &[0]),
+                der(
+                    0xa0,
+                    &der(0x30, &[response_type, der(0x04, &basic)].concat()),
+                ),
+            ]
+            .concat(),
+        )
+    }
+}
+
+fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    match contents.len() {
+        len @ 0..=0x7f => out.push(len as u8),
+        len @ 0x80..=0xff => out.extend([0x81, len as u8]),
+        len => out.extend([0x82, (len >> 8) as u8, len as u8]),
+    }
+    out.extend_from_slice(contents);
+    out
+}
+
+fn generalized_time(secs: u64) -> Vec<u8> {
+    let t = time::OffsetDateTime::from_unix_timestamp(secs as i64).unwrap();
+    let value = format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}Z",
+        t.year(),
+        u8::from(t.month()),
+        t.day(),
+        t.hour(),
+        t.minute(),
+        t.second()
+    );
+    der(0x18, value.as_bytes())
+}
@@ -4,14 +4,17 @@ use std::num::NonZeroUsize;
 use std::sync::Arc;
 
 use rustls::client::{ClientConnectionData, EarlyDataError, UnbufferedClientConnection};
-use rustls::server::{ServerConnectionData, UnbufferedServerConnection};
+use rustls::server::{
+    ServerConnectionData, UnbufferedAcceptStatus, UnbufferedAcceptor, UnbufferedServerConnection,
+};
 use rustls::unbuffered::{
     ConnectionState, EncodeError, EncryptError, InsufficientSizeError, UnbufferedConnectionCommon,
     UnbufferedStatus, WriteTraffic,
 };
-use rustls::version::TLS13;
+use rustls::version::{TLS12, TLS13};
 use rustls::{
-    AlertDescription, CertificateError, ClientConfig, Error, InvalidMessage, ServerConfig, SideData,
+    AlertDescription, CertificateError, ClientConfig, Error, InvalidMessage, PeerIncompatible,
+    RecordPadding, ServerConfig, SideData,
 };
 
 use super::*;
@@ -193,6 +196,56 @@ fn tls13_handshake_fragmented() {
     );
 }
 
+#[test]
+fn tls12_handshake_with_deferred_operations() {
+    let outcome = handshake_config(&rustls::version::TLS12, |_, server| {
+        server.cert_resolver = Arc::new(DeferredResolver);
+    });
+    assert_eq!(
+        outcome.server_transcript,
+        vec![
+            "Ok(BlockedHandshake)",
+            "Ok(PerformOperation)",
+            "Ok(PerformOperation)",
+            "Ok(EncodeTlsData)",
+            "Ok(TransmitTlsData)",
+            "Ok(BlockedHandshake)",
+            "Ok(BlockedHandshake)",
+            "Ok(BlockedHandshake)",
+            "Ok(EncodeTlsData)",
+            "Ok(EncodeTlsData)",
+            "Ok(TransmitTlsData)",
+            "Ok(WriteTraffic)"
+        ],
+        "server transcript mismatch"
+    );
+}
+
+#[test]
+fn tls13_handshake_with_deferred_operations() {
+    let outcome = handshake_config(&rustls::version::TLS13, |_, server| {
+        server.cert_resolver = Arc::new(DeferredResolver);
+    });
+    assert_eq!(
+        outcome.server_transcript,
+        vec![
+            "Ok(BlockedHandshake)",
+            "Ok(PerformOperation)",
+            "Ok(EncodeTlsData)",
+            "Ok(EncodeTlsData)",
+            "Ok(TransmitTlsData)",
+            "Ok(PerformOperation)",
+            "Ok(EncodeTlsData)",
+            "Ok(TransmitTlsData)",
+            "Ok(BlockedHandshake)",
+            "Ok(EncodeTlsData)",
+            "Ok(TransmitTlsData)",
+            "Ok(WriteTraffic)"
+        ],
+        "server transcript mismatch"
+    );
+}
+
 fn handshake(version: &'static rustls::SupportedProtocolVersion) -> Outcome {
     handshake_config(version, |_, _| ())
 }
@@ -277,6 +330,50 @@ fn app_data_server_to_client() {
     }
 }
 
+#[test]
+fn app_data_with_record_padding() {
+    let expected: &[_] = b"hello";
+    for padding in [RecordPadding::BlockSize(256), RecordPadding::MaxFragment] {
+        eprintln!("{padding:?}");
+        let mut server_config = make_server_config_with_versions(KeyType::Rsa2048, &[&TLS13]);
+        server_config.record_padding = padding.clone();
+        // keep padded records within the size of the test's buffers
+        server_config.max_fragment_size = Some(4096);
+        let mut client_config = make_client_config(KeyType::Rsa2048);
+        client_config.record_padding = padding;
+        client_config.max_fragment_size = Some(4096);
+
+        let mut client_actions = Actions {
+            app_data_to_send: Some(expected),
+            ..NO_ACTIONS
+        };
+        let mut server_actions = Actions {
+            app_data_to_send: Some(expected),
+            ..NO_ACTIONS
+        };
+
+        let outcome = run(
+            Arc::new(client_config),
+            &mut client_actions,
+            Arc::new(server_config),
+            &mut server_actions,
+        );
+
+        assert_eq!(
+            [expected],
+            outcome
+                .server_received_app_data
+                .as_slice()
+        );
+        assert_eq!(
+            [expected],
+            outcome
+                .client_received_app_data
+                .as_slice()
+        );
+    }
+}
+
 #[test]
 fn early_data() {
     let expected: &[_] = b"hello";
@@ -464,6 +561,7 @@ fn run(
 
                 server_handshake_done = true;
             }
+            State::PerformedOperation => {}
             State::ReceivedEarlyData { records } => {
                 outcome
                     .server_received_early_data
@@ -809,6 +907,64 @@ fn refresh_traffic_keys_automatically() {
     };
 }
 
+#[test]
+fn post_handshake_client_auth() {
+    let kt = KeyType::EcdsaP256;
+    let mut outcome = handshake_config(&TLS13, |client_config, server_config| {
+        *client_config = make_client_config_with_versions_with_auth(kt, &[&TLS13]);
+        client_config.enable_post_handshake_auth = true;
+        *server_config = make_server_config_with_client_verifier(
+            kt,
+            webpki_client_verifier_builder(get_client_root_store(kt)).defer_client_auth(),
+        );
+    });
+    let mut client = outcome.client.take().unwrap();
+    let mut server = outcome.server.take().unwrap();
+    assert!(server.peer_certificates().is_none());
+
+    write_traffic(server.process_tls_records(&mut []), |wt| {
+        wt.request_client_certificate().unwrap()
+    });
+    assert!(server.client_certificate_requested());
+    let (mut request, _) = encode_tls_data(server.process_tls_records(&mut []));
+    confirm_transmit_tls_data(server.process_tls_records(&mut []));
+
+    // the client's Certificate, CertificateVerify and Finished
+    let (mut response, discard) = encode_tls_data(client.process_tls_records(&mut request));
+    assert_eq!(discard, request.len());
+    loop {
+        match client.process_tls_records(&mut []) {
+            UnbufferedStatus {
+                discard: 0,
+                state: Ok(ConnectionState::EncodeTlsData(mut etd)),
+            } => {
+                let mut buf = [0u8; 4096];
+                let len = etd.encode(&mut buf).unwrap();
+                response.extend_from_slice(&buf[..len]);
+            }
+            status => {
+                confirm_transmit_tls_data(status);
+                break;
+            }
+        }
+    }
+
+    match server.process_tls_records(&mut response) {
+        UnbufferedStatus {
+            discard,
+            state: Ok(ConnectionState::WriteTraffic(_)),
+        } => assert_eq!(discard, response.len()),
+        st => panic!("unexpected server state {st:?}"),
+    }
+    assert!(!server.client_certificate_requested());
+    assert_eq!(
+        server
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(kt.get_client_chain().len())
+    );
+}
+
 #[test]
 fn tls12_connection_fails_after_key_reaches_confidentiality_limit() {
     const CONFIDENTIALITY_LIMIT: usize = 1024;
@@ -1015,6 +1171,7 @@ enum State {
         sent_app_data: bool,
         sent_close_notify: bool,
     },
+    PerformedOperation,
 }
 
 const NO_ACTIONS: Actions = Actions {
@@ -1108,6 +1265,12 @@ fn advance_server(
     transcript.push(format!("{:?}", state));
 
     let state = match state.unwrap() {
+        ConnectionState::PerformOperation(state) => {
+            let result = KeyType::Rsa2048.complete_operation(state.operation(), true);
+            state.complete(result).unwrap();
+            State::PerformedOperation
+        }
+
         ConnectionState::ReadEarlyData(mut state) => {
             let mut records = vec![];
             let mut peeked_len = state.peek_len();
@@ -1422,3 +1585,214 @@ fn server_receives_incorrect_first_handshake_message() {
         _ => panic!("unexpected alert sending state"),
     };
 }
+
+#[test]
+fn unbuffered_acceptor_handshake() {
+    let client_config = make_client_config(KeyType::Rsa2048);
+    let mut client =
+        UnbufferedClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let mut buffers = BothBuffers::default();
+    let mut transcript = vec![];
+
+    assert!(matches!(
+        advance_client(
+            &mut client,
+            &mut buffers.client,
+            NO_ACTIONS,
+            &mut transcript
+        ),
+        State::EncodedTlsData
+    ));
+    assert!(matches!(
+        advance_client(
+            &mut client,
+            &mut buffers.client,
+            NO_ACTIONS,
+            &mut transcript
+        ),
+        State::TransmitTlsData { .. }
+    ));
+    buffers.client_send();
+
+    let mut acceptor = UnbufferedAcceptor::default();
+    let client_hello_len = buffers.server.incoming.filled().len();
+    for prefix in 0..client_hello_len {
+        let UnbufferedAcceptStatus { discard, accepted } =
+            acceptor.accept(&mut buffers.server.incoming.filled()[..prefix]);
+        assert_eq!(discard, 0);
+        assert!(accepted.unwrap().is_none());
+    }
+
+    let UnbufferedAcceptStatus { discard, accepted } =
+        acceptor.accept(buffers.server.incoming.filled());
+    assert_eq!(discard, client_hello_len);
+    buffers.server.incoming.discard(discard);
+
+    let accepted = accepted.unwrap().unwrap();
+    assert_eq!(accepted.client_hello().server_name(), Some("localhost"));
+
+    let server_config = make_server_config(KeyType::Rsa2048);
+    let mut server = accepted
+        .into_connection(Arc::new(server_config))
+        .unwrap();
+
+    let mut client_handshake_done = false;
+    let mut server_handshake_done = false;
+    for _ in 0..MAX_ITERATIONS {
+        match advance_client(
+            &mut client,
+            &mut buffers.client,
+            NO_ACTIONS,
+            &mut transcript,
+        ) {
+            State::TransmitTlsData { .. } => buffers.client_send(),
+            State::BlockedHandshake => buffers.server_send(),
+            State::WriteTraffic { .. } => {
+                buffers.client_send();
+                client_handshake_done = true;
+            }
+            _ => {}
+        }
+
+        match advance_server(
+            &mut server,
+            &mut buffers.server,
+            NO_ACTIONS,
+            &mut transcript,
+        ) {
+            State::TransmitTlsData { .. } => buffers.server_send(),
+            State::BlockedHandshake => buffers.client_send(),
+            State::WriteTraffic { .. } => {
+                buffers.server_send();
+                server_handshake_done = true;
+            }
+            _ => {}
+        }
+
+        if client_handshake_done && server_handshake_done {
+            break;
+        }
+    }
+
+    assert!(client_handshake_done && server_handshake_done);
+    assert_eq!(
+        client.negotiated_cipher_suite(),
+        server.negotiated_cipher_suite()
+    );
+}
+
+#[test]
+fn unbuffered_acceptor_rejected_handshake() {
+    let client_config = make_client_config_with_versions(KeyType::Rsa2048, &[&TLS13]);
+    let mut client =
+        UnbufferedClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let (mut client_hello, _) = encode_tls_data(client.process_tls_records(&mut []));
+
+    let mut acceptor = UnbufferedAcceptor::default();
+    let UnbufferedAcceptStatus { discard, accepted } = acceptor.accept(&mut client_hello);
+    assert_eq!(discard, client_hello.len());
+    let accepted = accepted.unwrap().unwrap();
+
+    let server_config = make_server_config_with_versions(KeyType::Rsa2048, &[&TLS12]);
+    let (err, mut alert) = accepted
+        .into_connection(Arc::new(server_config))
+        .err()
+        .unwrap();
+    assert_eq!(
+        err,
+        Error::PeerIncompatible(PeerIncompatible::Tls12NotOfferedOrEnabled)
+    );
+
+    assert!(matches!(
+        alert.encode(&mut []),
+        Err(InsufficientSizeError { required_size: 7 })
+    ));
+    let mut alert_buffer = [0u8; 7];
+    assert_eq!(alert.encode(&mut alert_buffer).unwrap(), 7);
+    assert_eq!(alert_buffer, &[0x15, 0x3, 0x3, 0x0, 0x2, 0x2, 0x46][..]);
+
+    // Reusing an acceptor is not allowed
+    let UnbufferedAcceptStatus { discard, accepted } = acceptor.accept(&mut []);
+    assert_eq!(discard, 0);
+    assert_eq!(
+        accepted.unwrap_err().0,
+        Error::General("Acceptor polled after completion".into())
+    );
+}
+
+#[cfg(feature = "aws_lc_rs")]
+#[test]
+fn ech_rejected_with_retry_configs() {
+    use rustls::client::{EchConfig, EchMode};
+    use rustls::crypto::aws_lc_rs::hpke::ALL_SUPPORTED_SUITES;
+    use rustls::pki_types::DnsName;
+    use rustls::server::EchServerKey;
+
+    let make_ech_key = || {
+        let public_name = DnsName::try_from("testserver.com").unwrap();
+        Arc::new(EchServerKey::generate(ALL_SUPPORTED_SUITES[0], 1, public_name).unwrap())
+    };
+    let ech_config = EchConfig::new(make_ech_key().config_list(), ALL_SUPPORTED_SUITES).unwrap();
+    let client_config = ClientConfig::builder_with_provider(provider::default_provider().into())
+        .with_ech(EchMode::Enable(ech_config))
+        .unwrap();
+    let client_config = finish_client_config(KeyType::Rsa2048, client_config);
+    // The server has a different key with the same config ID, so can't decrypt the offer.
+    let server_key = make_ech_key();
+    let retry_config_list = server_key.config_list();
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.ech_keys = vec![server_key];
+
+    let mut client =
+        UnbufferedClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let mut server = UnbufferedServerConnection::new(Arc::new(server_config)).unwrap();
+
+    let (mut client_hello, _) = encode_tls_data(client.process_tls_records(&mut []));
+    confirm_transmit_tls_data(client.process_tls_records(&mut []));
+
+    let (mut server_flight, discard) =
+        encode_tls_data(server.process_tls_records(&mut client_hello));
+    assert_eq!(discard, client_hello.len());
+    loop {
+        match server.process_tls_records(&mut []) {
+            UnbufferedStatus {
+                discard: 0,
+                state: Ok(ConnectionState::EncodeTlsData(mut etd)),
+            } => {
+                let mut buf = [0u8; 4096];
+                let len = etd.encode(&mut buf).unwrap();
+                server_flight.extend_from_slice(&buf[..len]);
+            }
+            status => {
+                confirm_transmit_tls_data(status);
+                break;
+            }
+        }
+    }
+
+    let mut offset = 0;
+    let err = loop {
+        let UnbufferedStatus { discard, state } =
+            client.process_tls_records(&mut server_flight[offset..]);
+        offset += discard;
+        match state {
+            Ok(ConnectionState::EncodeTlsData(mut etd)) => {
+                etd.encode(&mut [0u8; 4096]).unwrap();
+            }
+            Ok(ConnectionState::TransmitTlsData(ttd)) => ttd.done(),
+            Ok(st) => panic!("unexpected client state {st:?}"),
+            Err(err) => break err,
+        }
+    };
+
+    assert!(matches!(
+        err,
+        Error::PeerIncompatible(PeerIncompatible::ServerRejectedEncryptedClientHello(Some(
+            _
+        )))
+    ));
+    assert_eq!(client.ech_retry_configs(), Some(retry_config_list));
+    assert!(client
+        .ech_retry_client_config()
+        .is_some());
+}